@@ -0,0 +1,446 @@
+//! The reindex job queue. `Reindex` requests are queued here and run one at
+//! a time by `run_jobs`, which holds the state lock only while a job runs.
+
+use crate::state::{DaemonState, HandlerError, ReindexControl};
+use ct_indexer::{IndexStats, ProgressFn, cancel::CancelToken};
+use ct_protocol::data::{JobState, ProgressData, ReindexJob};
+use ct_protocol::{ErrorCode, Response};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+
+/// Finished jobs kept for `Progress` and for clients still waiting on them.
+const FINISHED_JOBS: usize = 16;
+
+/// How often a waiting client checks whether its request was cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// What a `Reindex` asked for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobSpec {
+    pub features: Option<Vec<String>>,
+    pub target: Option<String>,
+    pub module: Option<String>,
+    pub struct_name: Option<String>,
+    pub include_derives: bool,
+    pub package: Option<String>,
+}
+
+impl JobSpec {
+    /// Whether running this job makes `other` redundant: the same options,
+    /// over the whole workspace or the same member.
+    fn covers(&self, other: &JobSpec) -> bool {
+        let options = |spec: &JobSpec| JobSpec { package: None, ..spec.clone() };
+        (self.package.is_none() || self.package == other.package) && options(self) == options(other)
+    }
+}
+
+/// A job taken off the queue by `ReindexQueue::next`.
+pub struct Job {
+    pub id: u64,
+    pub spec: JobSpec,
+    pub cancel: CancelToken,
+}
+
+struct Entry {
+    status: ReindexJob,
+    spec: JobSpec,
+    cancel: CancelToken,
+    /// One token per request that queued this job or was folded into it
+    waiters: Vec<CancelToken>,
+    started: Option<Instant>,
+}
+
+impl Entry {
+    /// Count in another request, returning the token that withdraws it.
+    fn join(&mut self) -> CancelToken {
+        let waiter = CancelToken::new();
+        self.waiters.push(waiter.clone());
+        waiter
+    }
+
+    /// Whether every request that wanted this job has been cancelled.
+    fn abandoned(&self) -> bool {
+        !self.waiters.is_empty() && self.waiters.iter().all(CancelToken::is_cancelled)
+    }
+}
+
+/// A finished job and the answer for clients waiting on it; superseded jobs
+/// have none, their clients wait on the job that replaced them.
+struct Finished {
+    status: ReindexJob,
+    outcome: Option<Result<Response, HandlerError>>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    queued: VecDeque<Entry>,
+    running: Option<Entry>,
+    /// Most recent first
+    finished: VecDeque<Finished>,
+    closed: bool,
+}
+
+impl Jobs {
+    fn finish(&mut self, mut entry: Entry, state: JobState, outcome: Option<Result<Response, HandlerError>>) {
+        entry.status.state = state;
+        entry.status.duration_ms = entry.started.map(|started| started.elapsed().as_millis() as u64);
+        if let Some(Err(e)) = &outcome {
+            entry.status.error = Some(e.message.clone());
+        }
+        self.finished.push_front(Finished { status: entry.status, outcome });
+        self.finished.truncate(FINISHED_JOBS);
+    }
+
+    /// Retire queued jobs whose requests were all cancelled before they
+    /// ran, and cancel the running job once its requests all were.
+    fn sweep_cancelled(&mut self) {
+        if let Some(running) = self.running.as_ref().filter(|running| running.abandoned()) {
+            running.cancel.cancel();
+        }
+        let (cancelled, queued) = std::mem::take(&mut self.queued)
+            .into_iter()
+            .partition(|entry| entry.cancel.is_cancelled() || entry.abandoned());
+        self.queued = queued;
+        for entry in cancelled {
+            let outcome = cancelled_response(entry.status.id, &entry.spec);
+            self.finish(entry, JobState::Cancelled, Some(outcome));
+        }
+    }
+
+    fn is_known(&self, id: u64) -> bool {
+        self.queued.iter().chain(&self.running).any(|entry| entry.status.id == id)
+    }
+}
+
+/// Reindex jobs waiting to run, running and recently finished. Clones share
+/// the same queue.
+#[derive(Clone)]
+pub struct ReindexQueue {
+    jobs: Arc<Mutex<Jobs>>,
+    /// Bumped whenever a job moves on
+    changed: Arc<watch::Sender<()>>,
+}
+
+impl Default for ReindexQueue {
+    fn default() -> Self {
+        Self {
+            jobs: Arc::default(),
+            changed: Arc::new(watch::channel(()).0),
+        }
+    }
+}
+
+impl ReindexQueue {
+    /// Queue a job, returning its id and the token that withdraws this
+    /// request from it; the job is cancelled once every request for it is.
+    /// `None` once the daemon is shutting down. A queued job covering this
+    /// one is returned instead, and jobs this one covers are superseded:
+    /// dropped if queued, cancelled with their changes rolled back if
+    /// running, their requests now waiting on this one.
+    pub fn enqueue(&self, spec: JobSpec) -> Option<(u64, CancelToken)> {
+        let mut jobs = self.lock();
+        if jobs.closed {
+            return None;
+        }
+        if let Some(entry) = jobs.queued.iter_mut().find(|entry| entry.spec.covers(&spec)) {
+            return Some((entry.status.id, entry.join()));
+        }
+
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        let (covered, queued) = std::mem::take(&mut jobs.queued)
+            .into_iter()
+            .partition(|entry| spec.covers(&entry.spec));
+        jobs.queued = queued;
+        let mut waiters = Vec::new();
+        for mut entry in covered {
+            entry.status.superseded_by = Some(id);
+            waiters.append(&mut entry.waiters);
+            jobs.finish(entry, JobState::Superseded, None);
+        }
+        if let Some(running) = jobs.running.as_mut().filter(|running| spec.covers(&running.spec)) {
+            running.status.superseded_by = Some(id);
+            waiters.append(&mut running.waiters);
+            running.cancel.cancel();
+        }
+
+        let mut entry = Entry {
+            status: ReindexJob {
+                id,
+                package: spec.package.clone(),
+                state: JobState::Queued,
+                done: 0,
+                total: 0,
+                duration_ms: None,
+                error: None,
+                superseded_by: None,
+            },
+            spec,
+            cancel: CancelToken::new(),
+            waiters,
+            started: None,
+        };
+        let waiter = entry.join();
+        jobs.queued.push_back(entry);
+        drop(jobs);
+        self.changed.send_replace(());
+        Some((id, waiter))
+    }
+
+    /// Wait for the next job and mark it running; `None` once closed.
+    pub async fn next(&self) -> Option<Job> {
+        let mut changed = self.changed.subscribe();
+        loop {
+            {
+                let mut jobs = self.lock();
+                if jobs.closed {
+                    return None;
+                }
+                jobs.sweep_cancelled();
+                if let Some(mut entry) = jobs.queued.pop_front() {
+                    entry.status.state = JobState::Running;
+                    entry.started = Some(Instant::now());
+                    let job = Job { id: entry.status.id, spec: entry.spec.clone(), cancel: entry.cancel.clone() };
+                    jobs.running = Some(entry);
+                    drop(jobs);
+                    self.changed.send_replace(());
+                    return Some(job);
+                }
+            }
+            // The queue keeps the sender alive
+            let _ = changed.changed().await;
+        }
+    }
+
+    /// Record how the running job `id` ended. `Ok(None)` means it was
+    /// cancelled.
+    pub fn finish(&self, id: u64, result: Result<Option<IndexStats>, HandlerError>) {
+        let mut jobs = self.lock();
+        let Some(entry) = jobs.running.take_if(|running| running.status.id == id) else {
+            return;
+        };
+        let package = entry.spec.package.clone();
+        match result {
+            Ok(Some(stats)) => {
+                let data = json!({
+                    "status": "reindexed",
+                    "job_id": id,
+                    "package": package,
+                    "stats": stats,
+                });
+                jobs.finish(entry, JobState::Done, Some(Ok(Response::success("".to_string(), data))));
+            }
+            Ok(None) if entry.status.superseded_by.is_some() => jobs.finish(entry, JobState::Superseded, None),
+            Ok(None) => {
+                let outcome = cancelled_response(id, &entry.spec);
+                jobs.finish(entry, JobState::Cancelled, Some(outcome));
+            }
+            Err(e) => jobs.finish(entry, JobState::Failed, Some(Err(e))),
+        }
+        drop(jobs);
+        self.changed.send_replace(());
+    }
+
+    /// The answer for job `id` once it has run, or for the job that
+    /// superseded it.
+    pub async fn wait(&self, mut id: u64) -> Result<Response, HandlerError> {
+        let mut changed = self.changed.subscribe();
+        loop {
+            {
+                let mut jobs = self.lock();
+                jobs.sweep_cancelled();
+                if let Some(finished) = jobs.finished.iter().find(|finished| finished.status.id == id) {
+                    match (&finished.outcome, finished.status.superseded_by) {
+                        (Some(outcome), _) => return outcome.clone(),
+                        (None, Some(by)) => {
+                            id = by;
+                            continue;
+                        }
+                        (None, None) => {}
+                    }
+                }
+                if !jobs.is_known(id) {
+                    return Err((format!("Reindex job {} is no longer tracked", id), ErrorCode::InternalError).into());
+                }
+            }
+            // Cancelling the request only flips the job's token
+            let _ = tokio::time::timeout(CANCEL_POLL, changed.changed()).await;
+        }
+    }
+
+    /// Updates the running job `id` as its indexer moves through crates.
+    pub fn progress_fn(&self, id: u64) -> ProgressFn {
+        let jobs = self.jobs.clone();
+        Arc::new(move |progress| {
+            let mut jobs = lock(&jobs);
+            if let Some(running) = jobs.running.as_mut().filter(|running| running.status.id == id) {
+                running.status.done = progress.done;
+                running.status.total = progress.total;
+            }
+        })
+    }
+
+    /// Whether no job is running or waiting to.
+    pub fn is_idle(&self) -> bool {
+        let jobs = self.lock();
+        jobs.running.is_none() && jobs.queued.is_empty()
+    }
+
+    pub fn progress(&self) -> ProgressData {
+        let jobs = self.lock();
+        ProgressData {
+            running: jobs.running.as_ref().map(|entry| entry.status.clone()),
+            queued: jobs.queued.iter().map(|entry| entry.status.clone()).collect(),
+            finished: jobs.finished.iter().map(|finished| finished.status.clone()).collect(),
+        }
+    }
+
+    /// Stop taking jobs for shutdown: cancel the running one and everything
+    /// queued.
+    pub fn close(&self) {
+        let mut jobs = self.lock();
+        jobs.closed = true;
+        if let Some(running) = &jobs.running {
+            running.cancel.cancel();
+        }
+        for entry in &jobs.queued {
+            entry.cancel.cancel();
+        }
+        jobs.sweep_cancelled();
+        drop(jobs);
+        self.changed.send_replace(());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        lock(&self.jobs)
+    }
+}
+
+/// The queue's lock; its state stays consistent even if a holder panicked.
+fn lock(jobs: &Mutex<Jobs>) -> MutexGuard<'_, Jobs> {
+    jobs.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn cancelled_response(id: u64, spec: &JobSpec) -> Result<Response, HandlerError> {
+    let data = json!({
+        "status": "cancelled",
+        "job_id": id,
+        "package": spec.package,
+    });
+    Ok(Response::success("".to_string(), data))
+}
+
+/// Run queued jobs one after another until the queue is closed.
+pub async fn run_jobs(queue: ReindexQueue, state: Arc<RwLock<DaemonState>>, reindex: ReindexControl) {
+    while let Some(job) = queue.next().await {
+        // Stops a watcher batch being applied; shutdown cancels the job here
+        reindex.supersede(job.cancel.clone());
+        let result = state.write().await.run_reindex(&job, queue.progress_fn(job.id)).await;
+        queue.finish(job.id, result);
+        reindex.release(&job.cancel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str) -> JobSpec {
+        JobSpec { package: Some(name.to_string()), ..JobSpec::default() }
+    }
+
+    #[tokio::test]
+    async fn test_queue_dedupes_and_supersedes() {
+        let queue = ReindexQueue::default();
+        let (app, _) = queue.enqueue(package("app")).unwrap();
+        let (core, _) = queue.enqueue(package("core")).unwrap();
+        assert_eq!(queue.enqueue(package("app")).unwrap().0, app);
+        assert_eq!(queue.progress().queued.len(), 2);
+
+        // A workspace-wide job covers both members
+        let (all, _) = queue.enqueue(JobSpec::default()).unwrap();
+        assert_eq!(queue.enqueue(package("core")).unwrap().0, all);
+        let progress = queue.progress();
+        assert_eq!(progress.queued.iter().map(|job| job.id).collect::<Vec<_>>(), [all]);
+        assert!(progress.finished.iter().all(|job| job.state == JobState::Superseded && job.superseded_by == Some(all)));
+        assert_eq!(progress.finished.len(), 2);
+
+        // A running job covered by a new one is cancelled
+        let job = queue.next().await.unwrap();
+        assert_eq!(job.id, all);
+        queue.progress_fn(all)(&ct_indexer::IndexProgress { crate_name: "app".to_string(), done: 1, total: 2 });
+        let running = queue.progress().running.unwrap();
+        assert_eq!((running.state, running.done, running.total), (JobState::Running, 1, 2));
+        let (again, _) = queue.enqueue(JobSpec::default()).unwrap();
+        assert!(job.cancel.is_cancelled());
+        queue.finish(all, Ok(None));
+
+        // Clients of the superseded jobs get the answer of the one that ran
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.wait(core).await }
+        });
+        let job = queue.next().await.unwrap();
+        assert_eq!(job.id, again);
+        queue.finish(again, Ok(Some(IndexStats::default())));
+        let Ok(Response::Success(envelope)) = waiter.await.unwrap() else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["status"], "reindexed");
+        assert_eq!(envelope.data["job_id"], again);
+        assert_eq!(queue.progress().finished[0].state, JobState::Done);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_and_closed_jobs() {
+        let queue = ReindexQueue::default();
+        let (first, cancel) = queue.enqueue(package("app")).unwrap();
+        cancel.cancel();
+        let Ok(Response::Success(envelope)) = queue.wait(first).await else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["status"], "cancelled");
+
+        let (failing, _) = queue.enqueue(package("app")).unwrap();
+        assert_ne!(failing, first);
+        queue.next().await.unwrap();
+        queue.finish(failing, Err(("Reindex of app failed".to_string(), ErrorCode::InternalError).into()));
+        assert_eq!(queue.progress().finished[0].error.as_deref(), Some("Reindex of app failed"));
+        assert!(queue.wait(failing).await.is_err());
+
+        let (queued, _) = queue.enqueue(JobSpec::default()).unwrap();
+        queue.close();
+        let finished = &queue.progress().finished[0];
+        assert_eq!((finished.id, finished.state), (queued, JobState::Cancelled));
+        assert!(queue.enqueue(JobSpec::default()).is_none());
+        assert!(queue.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_job_cancelled_with_its_last_request() {
+        let queue = ReindexQueue::default();
+        let (id, first) = queue.enqueue(package("app")).unwrap();
+        let (shared, second) = queue.enqueue(package("app")).unwrap();
+        assert_eq!(shared, id);
+        let job = queue.next().await.unwrap();
+
+        // A workspace-wide job takes over both requests
+        let (all, third) = queue.enqueue(JobSpec::default()).unwrap();
+        assert!(job.cancel.is_cancelled());
+        queue.finish(id, Ok(None));
+        let job = queue.next().await.unwrap();
+        assert_eq!(job.id, all);
+
+        first.cancel();
+        second.cancel();
+        queue.lock().sweep_cancelled();
+        assert!(!job.cancel.is_cancelled());
+        third.cancel();
+        queue.lock().sweep_cancelled();
+        assert!(job.cancel.is_cancelled());
+    }
+}
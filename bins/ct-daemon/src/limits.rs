@@ -0,0 +1,156 @@
+//! What one client may ask of the daemon: how many connect at once and how
+//! fast each may send requests, so a runaway script cannot starve the rest.
+
+use ct_core::config::Config;
+use ct_protocol::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// `max_connections` and the per-connection rate limit. Clones share the
+/// connection slots.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    connections: Option<Arc<Semaphore>>,
+    max_connections: usize,
+    per_sec: u32,
+    burst: u32,
+}
+
+impl Default for Limits {
+    /// No limits at all.
+    fn default() -> Self {
+        Self { connections: None, max_connections: 0, per_sec: 0, burst: 0 }
+    }
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        let max_connections = config.connection_limit().unwrap_or(0);
+        Self {
+            connections: config.connection_limit().map(|max| Arc::new(Semaphore::new(max))),
+            max_connections,
+            per_sec: config.rate_limit_per_sec,
+            burst: config.rate_limit_burst,
+        }
+    }
+
+    /// A slot for one more connection, held until dropped, or the limit
+    /// when every slot is taken.
+    pub fn admit(&self) -> Result<ConnectionSlot, usize> {
+        match &self.connections {
+            Some(slots) => slots
+                .clone()
+                .try_acquire_owned()
+                .map(|permit| ConnectionSlot { _permit: Some(permit) })
+                .map_err(|_| self.max_connections),
+            None => Ok(ConnectionSlot { _permit: None }),
+        }
+    }
+
+    /// A fresh rate limiter for one connection, or `None` when unlimited.
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        RateLimiter::new(self.per_sec, self.burst)
+    }
+}
+
+/// Tokens a request takes: a batch costs what its commands would, and
+/// cancelling is free so it always gets through.
+pub fn request_cost(cmd: &Command) -> u32 {
+    match cmd {
+        Command::Cancel { .. } => 0,
+        Command::Batch { requests } => u32::try_from(requests.len()).unwrap_or(u32::MAX).max(1),
+        _ => 1,
+    }
+}
+
+/// One of the `max_connections` slots.
+pub struct ConnectionSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// A token bucket: `per_sec` tokens a second, up to `burst` saved up.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// `None` when `per_sec` is 0. The bucket starts full.
+    pub fn new(per_sec: u32, burst: u32) -> Option<Self> {
+        let burst = f64::from(burst.max(1));
+        (per_sec > 0).then(|| Self {
+            per_sec: f64::from(per_sec),
+            burst,
+            tokens: burst,
+            refilled: Instant::now(),
+        })
+    }
+
+    /// Take `cost` tokens, or tell how long until there will be enough.
+    /// A cost above the burst size is charged as the burst size.
+    pub fn acquire(&mut self, cost: u32) -> Result<(), Duration> {
+        self.acquire_at(cost, Instant::now())
+    }
+
+    fn acquire_at(&mut self, cost: u32, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+
+        let cost = f64::from(cost).min(self.burst);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((cost - self.tokens) / self.per_sec))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        assert!(RateLimiter::new(0, 10).is_none());
+
+        let mut limiter = RateLimiter::new(10, 5).unwrap();
+        let start = limiter.refilled;
+        for _ in 0..5 {
+            assert!(limiter.acquire_at(1, start).is_ok());
+        }
+        let wait = limiter.acquire_at(1, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+        // Cancel and the like cost nothing
+        assert!(limiter.acquire_at(0, start).is_ok());
+
+        let later = start + Duration::from_millis(200);
+        assert!(limiter.acquire_at(2, later).is_ok());
+        assert!(limiter.acquire_at(1, later).is_err());
+
+        // Never more than the burst saved up, and a large batch costs the whole of it
+        let much_later = later + Duration::from_secs(60);
+        assert!(limiter.acquire_at(50, much_later).is_ok());
+        assert!(limiter.acquire_at(1, much_later).is_err());
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let config = Config { max_connections: 2, ..Config::default() };
+        let limits = Limits::new(&config);
+        let first = limits.admit().unwrap();
+        let _second = limits.clone().admit().unwrap();
+        assert_eq!(limits.admit().err(), Some(2));
+        drop(first);
+        assert!(limits.admit().is_ok());
+
+        let unlimited = Limits::new(&Config { max_connections: 0, rate_limit_per_sec: 0, ..Config::default() });
+        let _slots: Vec<_> = (0..100).map(|_| unlimited.admit().unwrap()).collect();
+        assert!(unlimited.rate_limiter().is_none());
+    }
+}
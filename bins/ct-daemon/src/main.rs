@@ -1,19 +1,26 @@
+mod jobs;
+mod limits;
+mod mcp;
+mod memory;
+mod reader;
 mod server;
 mod state;
+mod supervisor;
 
+use anyhow::Context;
 use clap::Parser;
-use ct_core::{config::Config, compute_workspace_fingerprint, utils::find_workspace_root};
+use ct_core::{CoreError, config::{Config, Transport}, transport::TransportStream, compute_workspace_fingerprint, compute_workspace_id, pidfile::PidFile, utils::find_workspace_root};
 use ct_db::Database;
-use ct_indexer::{Indexer, watcher::spawn_watcher};
-use std::path::PathBuf;
-use tracing::info;
+use ct_indexer::{IndexError, cancel::CancelToken, watcher::spawn_watcher};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser, Debug)]
 #[command(name = "ct-daemon")]
 #[command(about = "ct indexing daemon", version)]
 struct Args {
-    /// Path to workspace to index
+    /// Path to a cargo workspace, a directory of .rs files, or a single .rs file
     #[arg(long = "idx", value_name = "PATH")]
     workspace: Option<PathBuf>,
 
@@ -29,74 +36,174 @@ struct Args {
     #[arg(long, default_value = "auto")]
     transport: String,
 
+    /// Also serve the commands over HTTP on this address (overrides `http_addr`)
+    #[arg(long, value_name = "ADDR")]
+    http: Option<String>,
+
     /// Run once and exit
     #[arg(long)]
     once: bool,
     
-    /// Clean cache before starting
+    /// Serve MCP tools (find, doc, ls, export) over stdin and stdout, exiting
+    /// when stdin closes; relays to the workspace's daemon if one is running
+    #[arg(long, conflicts_with = "once")]
+    mcp: bool,
+    
+    /// Speak the protocol over stdin and stdout instead of a socket, exiting
+    /// when stdin closes; relays to the workspace's daemon if one is running
+    #[arg(long, conflicts_with_all = ["once", "mcp"])]
+    stdio: bool,
+    
+    /// Wipe the workspace's cache and database, wherever `db_dir` puts it,
+    /// and index from scratch
     #[arg(long)]
     clean: bool,
+    
+    /// Reindex every crate on startup, not only those changed since the last run
+    #[arg(long)]
+    full_reindex: bool,
+    
+    /// Index #[cfg(test)] items too (same as `[index] include_tests = true`)
+    #[arg(long)]
+    include_tests: bool,
+    
+    /// Run the daemon as a child process and start it again, with backoff,
+    /// when it crashes; crashes are recorded for `ct doctor`
+    #[arg(long, conflicts_with_all = ["once", "mcp", "stdio"])]
+    supervise: bool,
+    
+    /// Index the workspace into the database FILE and exit, leaving its
+    /// cache and any daemon serving it alone; `ct diff` diffs against these
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["once", "mcp", "stdio", "supervise", "clean"])]
+    snapshot: Option<PathBuf>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ct_daemon=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     let args = Args::parse();
     
     let workspace_root = if let Some(path) = args.workspace {
         path.canonicalize()?
     } else {
-        find_workspace_root(&std::env::current_dir()?)?
+        // Outside a cargo workspace, index the current directory's sources directly
+        let current_dir = std::env::current_dir()?;
+        find_workspace_root(&current_dir).unwrap_or(current_dir)
     };
     
-    info!("Starting ct-daemon for workspace: {:?}", workspace_root);
-    
-    let config = Config::load()?;
+    let mut config = Config::load()?;
+    config.index.include_tests |= args.include_tests;
+    if args.http.is_some() {
+        config.http_addr = args.http.clone();
+    }
+    let workspace_id = compute_workspace_id(&workspace_root);
     let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
     
+    if let Some(snapshot) = args.snapshot {
+        return write_snapshot(&config, workspace_root, &snapshot).await;
+    }
+    
+    if args.supervise {
+        return supervisor::supervise(config.get_crash_log_path(&workspace_fingerprint)).await;
+    }
+    
+    // Adopt a cache left by versions that keyed it on the path alone
+    let migrated = config.migrate_cache_dir(&workspace_id, &workspace_fingerprint);
+    
     // Create cache directory
     let cache_dir = config.get_cache_dir(&workspace_fingerprint);
+    std::fs::create_dir_all(&cache_dir)?;
     
-    // Clean cache if requested or if auto_clean_on_start is enabled
-    if (args.clean || config.auto_clean_on_start) && cache_dir.exists() {
-        info!("Cleaning cache directory: {:?}", cache_dir);
-        std::fs::remove_dir_all(&cache_dir)?;
+    // One daemon per workspace: claim the PID file before touching anything
+    // a running daemon might be using
+    let pid_file = match PidFile::acquire(config.get_pid_path(&workspace_fingerprint)) {
+        Ok(pid_file) => pid_file,
+        Err(CoreError::AlreadyRunning { .. }) if args.mcp => {
+            let daemon = mcp::Remote::connect(&config, &workspace_id).await?;
+            mcp::serve(tokio::io::stdin(), tokio::io::stdout(), daemon).await?;
+            return Ok(());
+        }
+        Err(CoreError::AlreadyRunning { .. }) if args.stdio => {
+            let daemon = TransportStream::connect(&config, &workspace_id).await?;
+            daemon.relay(&mut tokio::io::join(tokio::io::stdin(), tokio::io::stdout())).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if args.stdio {
+        config.transport = Transport::Stdio;
+    }
+    
+    // Clean cache if requested or if auto_clean_on_start is enabled; earlier
+    // runs' logs and crashes are kept for working out what happened to them
+    let log_dir = config.get_log_dir(&workspace_fingerprint);
+    let clean = args.clean || config.auto_clean_on_start;
+    if clean {
+        let crash_log = config.get_crash_log_path(&workspace_fingerprint);
+        clean_cache_dir(&cache_dir, &[pid_file.path(), &log_dir, &crash_log])?;
+        // A database kept elsewhere with `db_dir` is wiped all the same
+        let db_path = config.get_db_path(&workspace_fingerprint);
+        if !db_path.starts_with(&cache_dir) {
+            remove_database(&db_path)?;
+        }
     }
     
-    // Remove existing socket file if it exists
+    init_logging(&config, &log_dir)?;
+    info!("Starting ct-daemon for workspace: {:?}", workspace_root);
+    match migrated {
+        Ok(true) => info!("Migrated cache directory {} to {}", workspace_id, workspace_fingerprint),
+        Ok(false) => {}
+        Err(e) => warn!("Could not migrate cache directory {}: {}", workspace_id, e),
+    }
+    info!("Wrote PID file {:?}", pid_file.path());
+    if clean {
+        info!("Cleaned cache directory: {:?}", cache_dir);
+    }
+    
+    // Remove the socket file a daemon that died left behind
     #[cfg(unix)]
     {
-        let socket_path = config.get_socket_path(&workspace_fingerprint);
+        let socket_path = config.get_socket_path(&workspace_id);
         if std::path::Path::new(&socket_path).exists() {
             info!("Removing existing socket file: {}", socket_path);
             std::fs::remove_file(&socket_path)?;
         }
     }
     
-    std::fs::create_dir_all(&cache_dir)?;
-    
     // Open database
     let db_path = config.get_db_path(&workspace_fingerprint);
     info!("Opening database at {:?}", db_path);
     let db = Database::open(&db_path)?;
     
-    // Create indexer and perform initial indexing
-    let mut indexer = Indexer::new(workspace_root.clone(), db);
+    // Create indexer and perform initial indexing; Ctrl+C aborts it cleanly
+    let cancel = CancelToken::new();
+    let signal_cancel = cancel.clone();
+    let signal_task = tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            signal_cancel.cancel();
+        }
+    });
+    let mut indexer = state::build_indexer(&config, workspace_root.clone(), db)?
+        .with_skip_unchanged(!args.full_reindex)
+        .with_cancel_token(cancel);
     
     info!("Starting initial indexing...");
-    match indexer.index_workspace().await {
+    let result = indexer.index_workspace().await;
+    signal_task.abort();
+    let mut initial_stats = None;
+    match result {
         Ok(stats) => {
             info!(
-                "Initial indexing complete: {} crates, {} files, {} symbols in {}ms",
-                stats.crates_indexed, stats.files_indexed, stats.symbols_indexed, stats.duration_ms
+                "Initial indexing complete: {} crates ({} unchanged), {} files, {} symbols in {}ms",
+                stats.crates_indexed, stats.crates_unchanged, stats.files_indexed, stats.symbols_indexed, stats.duration_ms
             );
+            for failure in &stats.failures {
+                eprintln!("WARNING: Failed to index crate {}: {}", failure.name, failure.error);
+            }
+            initial_stats = Some(stats);
+        }
+        Err(IndexError::Cancelled) => {
+            info!("Initial indexing cancelled, exiting");
+            return Ok(());
         }
         Err(e) => {
             eprintln!("WARNING: Initial indexing failed: {}. The daemon will start but some features may be limited.", e);
@@ -110,14 +217,43 @@ async fn main() -> anyhow::Result<()> {
     }
     
     // Start file watcher
-    let watcher_handle = spawn_watcher(workspace_root.clone(), config.watcher_debounce_ms).await?;
+    let (changes_tx, changes_rx) = tokio::sync::mpsc::channel(16);
+    let watcher_handle = spawn_watcher(
+        workspace_root.clone(),
+        config.watcher_debounce_ms,
+        &config.watcher.ignore,
+        changes_tx,
+    ).await?;
     
     // Start IPC server
-    let server_handle = server::start_server(config, workspace_fingerprint).await?;
+    let server_handle = server::start_server(
+        config,
+        workspace_root,
+        workspace_fingerprint,
+        initial_stats,
+        changes_rx,
+        &watcher_handle,
+    ).await?;
     
     info!("Daemon started, waiting for shutdown signal...");
     
-    // Wait for shutdown signal
+    // With --mcp or --stdio the daemon lives as long as the process that spawned it
+    let client = server_handle.local_client();
+    let spawner = async move {
+        let result = if args.mcp {
+            mcp::serve(tokio::io::stdin(), tokio::io::stdout(), client).await.map_err(Into::into)
+        } else if args.stdio {
+            client.serve_connection(tokio::io::join(tokio::io::stdin(), tokio::io::stdout())).await
+        } else {
+            std::future::pending().await
+        };
+        if let Err(e) = result {
+            warn!("Connection over stdio failed: {}", e);
+        }
+    };
+    tokio::pin!(spawner);
+    
+    // Wait for a shutdown signal, a client's Shutdown or idle_shutdown_minutes
     #[cfg(unix)]
     {
         use tokio::signal;
@@ -133,23 +269,109 @@ async fn main() -> anyhow::Result<()> {
             } => {
                 info!("Received SIGTERM, shutting down...");
             }
+            _ = server_handle.shutdown_requested() => {
+                info!("Shutting down on request...");
+            }
+            _ = server_handle.idle() => {
+                info!("Idle for too long, shutting down...");
+            }
+            _ = &mut spawner => {
+                info!("Stdin closed, shutting down...");
+            }
         }
     }
     
     #[cfg(not(unix))]
     {
-        tokio::signal::ctrl_c().await?;
-        info!("Received Ctrl+C, shutting down...");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("Received Ctrl+C, shutting down...");
+            }
+            _ = server_handle.shutdown_requested() => {
+                info!("Shutting down on request...");
+            }
+            _ = server_handle.idle() => {
+                info!("Idle for too long, shutting down...");
+            }
+            _ = &mut spawner => {
+                info!("Stdin closed, shutting down...");
+            }
+        }
     }
     
-    // Cleanup
+    // Cleanup; the PID file goes last, once nothing is left running
     watcher_handle.stop().await?;
     server_handle.shutdown().await?;
+    drop(pid_file);
     
     info!("Daemon shutdown complete");
     Ok(())
 }
 
+/// Index `workspace_root` from scratch into `snapshot`, reporting crates
+/// that failed to index on stderr as the daemon does.
+async fn write_snapshot(config: &Config, workspace_root: PathBuf, snapshot: &Path) -> anyhow::Result<()> {
+    let db = Database::open(snapshot)?;
+    let mut indexer = state::build_indexer(config, workspace_root, db)?.with_skip_unchanged(false);
+    let stats = indexer.index_workspace().await?;
+    for failure in &stats.failures {
+        eprintln!("WARNING: Failed to index crate {}: {}", failure.name, failure.error);
+    }
+    Ok(())
+}
+
+/// Log to stderr and, as JSON lines, to a file in `log_dir` started afresh
+/// each day. `RUST_LOG` overrides the `[log]` levels.
+fn init_logging(config: &Config, log_dir: &Path) -> anyhow::Result<()> {
+    let file = tracing_appender::rolling::Builder::new()
+        .rotation(tracing_appender::rolling::Rotation::DAILY)
+        .filename_prefix("ct-daemon.log")
+        .max_log_files(config.log.max_files.max(1))
+        .build(log_dir)?;
+    let filter = match tracing_subscriber::EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => tracing_subscriber::EnvFilter::try_new(config.log.filter())
+            .context("Invalid [log] level in ct.toml")?,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(tracing_subscriber::fmt::layer().json().with_writer(file))
+        .init();
+    Ok(())
+}
+
+/// Empties `cache_dir` except for `keep`: the PID file this daemon holds
+/// and the logs and crash records of earlier runs.
+fn clean_cache_dir(cache_dir: &Path, keep: &[&Path]) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        if keep.contains(&path.as_path()) {
+            continue;
+        }
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete the database at `path` with its WAL and shared-memory files.
+fn remove_database(path: &Path) -> std::io::Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
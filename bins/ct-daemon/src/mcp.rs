@@ -0,0 +1,291 @@
+//! `ct-daemon --mcp`: the symbol index as Model Context Protocol tools,
+//! JSON-RPC over stdin and stdout, for agent runtimes that speak MCP
+//! rather than shelling out to `ct`.
+
+use ct_core::config::Config;
+use ct_core::transport::IpcClient;
+use ct_protocol::{Command, ErrorCode, Request, Response, PROTOCOL_VERSION};
+use serde_json::{json, Map, Value};
+use std::future::Future;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::debug;
+
+/// MCP revisions this server speaks, newest last.
+const MCP_VERSIONS: [&str; 3] = ["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Where tool calls are answered: this daemon, or one already running for
+/// the workspace.
+pub trait Backend {
+    fn send(&mut self, request: Request) -> impl Future<Output = Response> + Send;
+}
+
+impl Backend for crate::server::LocalClient {
+    fn send(&mut self, request: Request) -> impl Future<Output = Response> + Send {
+        crate::server::LocalClient::send(self, request)
+    }
+}
+
+/// A daemon reached over IPC, for when another process holds the workspace.
+pub struct Remote(pub IpcClient);
+
+impl Remote {
+    pub async fn connect(config: &Config, workspace_id: &str) -> ct_core::Result<Self> {
+        Ok(Self(IpcClient::connect(config, workspace_id).await?))
+    }
+}
+
+impl Backend for Remote {
+    async fn send(&mut self, request: Request) -> Response {
+        let request_id = request.request_id.clone();
+        match self.0.send_request(request).await {
+            Ok(response) => response,
+            Err(e) => Response::error(request_id, format!("The daemon did not answer: {}", e), ErrorCode::DaemonUnavailable),
+        }
+    }
+}
+
+/// Answer JSON-RPC messages, one per line on `input`, until it closes.
+pub async fn serve<R, W, B>(input: R, mut output: W, mut backend: B) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    B: Backend,
+{
+    let mut lines = BufReader::new(input).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle_message(message, &mut backend).await,
+            Err(e) => Some(rpc_error(Value::Null, -32700, format!("Parse error: {}", e))),
+        };
+        if let Some(reply) = reply {
+            output.write_all(format!("{}\n", reply).as_bytes()).await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The reply to one message; notifications get none.
+async fn handle_message<B: Backend>(message: Value, backend: &mut B) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let method = message.get("method").and_then(Value::as_str).unwrap_or_default();
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    debug!("MCP {}", method);
+
+    let result = match method {
+        "initialize" => Ok(initialize(&params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => call_tool(&params, backend).await,
+        _ if id.is_none() => return None,
+        _ => Err((-32601, format!("Method not found: {}", method))),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => rpc_error(id, code, message),
+    })
+}
+
+fn rpc_error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn initialize(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = requested
+        .filter(|v| MCP_VERSIONS.contains(v))
+        .unwrap_or(MCP_VERSIONS[MCP_VERSIONS.len() - 1]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "ct", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Look up Rust symbols in this workspace's index: find them by name, \
+            read their docs, list what is under a path, and export bundles of related symbols.",
+    })
+}
+
+fn tools() -> Value {
+    let string = |description: &str| json!({ "type": "string", "description": description });
+    let boolean = |description: &str| json!({ "type": "boolean", "description": description });
+    let path = string("Canonical symbol path, e.g. `my_crate::net::Client`");
+    let expansion = string("Expansion operators: `>` children, `>>` every descendant, `<` parents");
+    json!([
+        {
+            "name": "find",
+            "description": "Find symbols by name, or everything under a module path. \
+                Returns each symbol's path and line span; set `all` for its kind, signature and docs too.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "name": string("Symbol name to look for"),
+                    "path": string("Module path to list symbols under, instead of `name`"),
+                    "kind": string("Only this kind: fn, struct, enum, trait, method, ..."),
+                    "match_mode": {
+                        "type": "string",
+                        "enum": ["exact", "prefix", "glob", "regex", "fuzzy"],
+                        "description": "How `name` is matched; exact by default",
+                    },
+                    "sig": string("Functions whose signature has this shape, e.g. `fn(&str) -> Result<_, _>`"),
+                    "all": boolean("Return every field of each symbol, not just its path and span"),
+                },
+            },
+        },
+        {
+            "name": "doc",
+            "description": "Documentation and signature of the symbol at a path.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": path,
+                    "include_docs": boolean("Include the full doc comment"),
+                    "examples": boolean("Include the code examples from the docs"),
+                },
+                "required": ["path"],
+            },
+        },
+        {
+            "name": "ls",
+            "description": "The symbols at a path and, with expansion operators, their children or parents.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": path,
+                    "expansion": expansion,
+                    "include_docs": boolean("Include docs of expanded items"),
+                    "impl_parents": boolean("Follow impl blocks up to the types they implement"),
+                },
+                "required": ["path"],
+            },
+        },
+        {
+            "name": "export",
+            "description": "A bundle of one or more symbols with what they reference, \
+                to read a piece of the codebase in one go.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "paths": { "type": "array", "items": { "type": "string" }, "description": "Root symbol paths" },
+                    "expansion": expansion,
+                    "bundle": boolean("Merge the roots into one bundle"),
+                    "include_docs": boolean("Include docs of exported items"),
+                    "with_source": boolean("Include source snippets"),
+                },
+                "required": ["paths"],
+            },
+        },
+    ])
+}
+
+const TOOLS: [&str; 4] = ["find", "doc", "ls", "export"];
+
+/// Run a tool as the command of the same name. Failed commands are tool
+/// results with `isError`, so the model sees why.
+async fn call_tool<B: Backend>(params: &Value, backend: &mut B) -> Result<Value, (i64, String)> {
+    let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+    if !TOOLS.contains(&name) {
+        return Err((-32602, format!("Unknown tool: {}", name)));
+    }
+    let mut fields = match params.get("arguments") {
+        Some(Value::Object(arguments)) => arguments.clone(),
+        None | Some(Value::Null) => Map::new(),
+        Some(_) => return Err((-32602, "Tool arguments must be an object".to_string())),
+    };
+    fields.insert("cmd".to_string(), Value::String(name.to_string()));
+    let cmd: Command = match serde_json::from_value(Value::Object(fields)) {
+        Ok(cmd) => cmd,
+        Err(e) => return Ok(tool_result(format!("Invalid arguments for {}: {}", name, e), true)),
+    };
+
+    let request = Request {
+        cmd,
+        request_id: uuid::Uuid::new_v4().to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        chunked: false,
+    };
+    Ok(match backend.send(request).await {
+        Response::Success(envelope) => tool_result(envelope.data.to_string(), false),
+        Response::Decision(envelope) => {
+            let decision = envelope.decision_required;
+            let text = format!(
+                "{} ({} characters). Narrow the request: {}",
+                decision.reason,
+                decision.content_len,
+                decision.options.join(", ")
+            );
+            tool_result(text, true)
+        }
+        Response::Error(error) => {
+            let text = match error.hint {
+                Some(hint) => format!("{}\n{}", error.err, hint),
+                None => error.err,
+            };
+            tool_result(text, true)
+        }
+    })
+}
+
+fn tool_result(text: String, is_error: bool) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }], "isError": is_error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every request with its command's name.
+    struct Echo;
+
+    impl Backend for Echo {
+        async fn send(&mut self, request: Request) -> Response {
+            let cmd = serde_json::to_value(&request.cmd).unwrap();
+            if cmd["cmd"] == "doc" && cmd["path"] == "missing" {
+                return Response::error(request.request_id, "Symbol not found".to_string(), ErrorCode::NotFound);
+            }
+            Response::success(request.request_id, cmd)
+        }
+    }
+
+    async fn exchange(messages: &[Value]) -> Vec<Value> {
+        let input: String = messages.iter().map(|m| format!("{}\n", m)).collect();
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, Echo).await.unwrap();
+        String::from_utf8(output).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_tools_over_json_rpc() {
+        let replies = exchange(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2025-03-26" } }),
+            json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": { "name": "find", "arguments": { "name": "State" } } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": { "name": "doc", "arguments": { "path": "missing" } } }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "tools/call", "params": { "name": "export", "arguments": {} } }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "tools/call", "params": { "name": "reindex" } }),
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "resources/list" }),
+        ])
+        .await;
+        assert_eq!(replies.len(), 7);
+
+        assert_eq!(replies[0]["result"]["protocolVersion"], "2025-03-26");
+        let tools: Vec<&str> = replies[1]["result"]["tools"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(tools, TOOLS);
+
+        let found = &replies[2]["result"];
+        assert_eq!(found["isError"], false);
+        let data: Value = serde_json::from_str(found["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!((data["cmd"].as_str(), data["name"].as_str()), (Some("find"), Some("State")));
+
+        assert_eq!(replies[3]["result"]["isError"], true);
+        assert_eq!(replies[3]["result"]["content"][0]["text"], "Symbol not found");
+        // `paths` is required
+        assert_eq!(replies[4]["result"]["isError"], true);
+
+        assert_eq!(replies[5]["error"]["code"], -32602);
+        assert_eq!((replies[6]["id"].as_i64(), replies[6]["error"]["code"].as_i64()), (Some(7), Some(-32601)));
+    }
+}
@@ -0,0 +1,75 @@
+//! The daemon's memory budget: what it holds, measured approximately, and
+//! whether that is past `max_mem_mb`.
+
+use crate::state::HandlerError;
+use ct_core::memory::MemoryGauge;
+use ct_core::models::MemoryUsage;
+use ct_protocol::ErrorCode;
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    /// Lookup tables of the rustdoc JSON being indexed
+    pub rustdoc_json: MemoryGauge,
+    /// Lines queued for connections' writers
+    pub responses: MemoryGauge,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes; `None` only measures.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit, ..Self::default() }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            sqlite_bytes: ct_db::sqlite_memory_used(),
+            rustdoc_json_bytes: self.rustdoc_json.bytes(),
+            response_bytes: self.responses.bytes(),
+            budget_bytes: self.limit,
+        }
+    }
+
+    /// Whether holding `extra` more bytes would take the daemon past its
+    /// budget.
+    pub fn exceeded_by(&self, extra: usize) -> bool {
+        self.limit.is_some_and(|limit| self.usage().total() + extra > limit)
+    }
+
+    /// The error refusing a response of `bytes` that would take the daemon
+    /// past its budget, or `None` if it fits. Shed caches before asking.
+    pub(crate) fn refusal(&self, bytes: usize) -> Option<HandlerError> {
+        let limit = self.limit.filter(|_| self.exceeded_by(bytes))?;
+        let (code, hint) = if bytes > limit {
+            (ErrorCode::InvalidArg, "Ask for less, such as fewer symbols or no source, or raise max_mem_mb")
+        } else {
+            (ErrorCode::Busy, "Other requests are holding memory; retry shortly, or raise max_mem_mb")
+        };
+        let message = format!("A {} byte response would exceed the daemon's memory budget of {} MB", bytes, limit >> 20);
+        Some(HandlerError::from((message, code)).with_hint(hint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_counts_held_buffers() {
+        assert!(!MemoryBudget::new(None).exceeded_by(usize::MAX / 2));
+
+        let budget = MemoryBudget::new(Some(ct_db::sqlite_memory_used() + (64 << 20)));
+        assert!(!budget.exceeded_by(0));
+        let json = budget.rustdoc_json.hold(1 << 20);
+        let response = budget.responses.hold(2 << 20);
+        let usage = budget.usage();
+        assert_eq!((usage.rustdoc_json_bytes, usage.response_bytes), (1 << 20, 2 << 20));
+        assert!(budget.exceeded_by(64 << 20));
+        drop((json, response));
+        assert_eq!(budget.usage().response_bytes, 0);
+    }
+}
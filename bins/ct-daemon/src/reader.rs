@@ -0,0 +1,2124 @@
+use crate::memory::MemoryBudget;
+use crate::state::{db_error, finish_response, HandlerError};
+use ct_core::compute_file_digest;
+use ct_core::config::Config;
+use ct_core::models::{ChangeKind, Cursor, Page, Symbol, TreeNode};
+use ct_db::queries::{self, CallDirection, DepDirection, MatchMode, SymbolField};
+use ct_db::pool::{PooledConnection, ReadPool, PROGRESS_STEPS};
+use ct_db::{Database, DbError};
+use ct_indexer::cancel::CancelToken;
+use ct_protocol::data::{
+    BenchCommand, BenchData, CallEdge, CallGraphData, DiffData, ExportBundle, ExportData, ExportInvariants, ExportSymbol, FindData, Latency, LsData, LsItem,
+    LocateData, OwnerData, RefCrate, RefFile, RefSite, RefsData, SearchData, SearchItem, SymbolItem, TodoData, TodoItem,
+};
+use ct_protocol::{
+    CacheMetrics, Command, DepsDirection, ErrorCode, GrepField, ImplsDirection, Metrics, PageCursor, PagePosition, RefsGroup, Request,
+    Response, StatusGroup,
+};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Idle read connections kept open for queries.
+const READ_POOL_SIZE: usize = 4;
+
+/// Calls away from the root that Callers/Callees walk unless told otherwise.
+const DEFAULT_CALL_DEPTH: usize = 3;
+
+/// The most steps any graph query (Callers, Callees, Deps) will walk.
+const MAX_GRAPH_DEPTH: usize = 10;
+
+/// Files and functions listed under "largest" by Stats.
+const STATS_LARGEST: usize = 10;
+
+/// Alternative paths offered when a symbol is not found.
+pub(crate) const MAX_SUGGESTIONS: usize = 5;
+
+/// The longest `Bench` run accepted, in seconds.
+const MAX_BENCH_SECS: u32 = 60;
+
+/// Serves the commands that only read the index from a pool of read-only
+/// connections, so they need neither the state lock nor a running reindex
+/// to finish.
+#[derive(Clone)]
+pub struct QueryReader {
+    config: Arc<Config>,
+    pool: Arc<ReadPool>,
+    /// For filling the snippet cache, the one write queries make.
+    db_path: PathBuf,
+    /// Connection those writes go through, opened on the first of them.
+    snippet_writer: Arc<Mutex<Option<Database>>>,
+    workspace_root: PathBuf,
+    workspace_fingerprint: Arc<str>,
+    /// Interrupts this reader's queries once set by a `Cancel`.
+    cancel: CancelToken,
+    cost: Arc<QueryCost>,
+    /// Checked while building large responses
+    memory: MemoryBudget,
+}
+
+/// Work one request's queries did, reported in its response metrics.
+#[derive(Debug, Default)]
+pub(crate) struct QueryCost {
+    /// Whether any query ran on a connection that counts steps
+    measured: AtomicBool,
+    db_steps: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl QueryCost {
+    fn record_cache(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fill in the cost fields of `metrics`, leaving out what was not measured.
+    pub(crate) fn apply(&self, metrics: &mut Metrics) {
+        if self.measured.load(Ordering::Relaxed) {
+            metrics.db_steps = Some(self.db_steps.load(Ordering::Relaxed));
+        }
+        let cache = CacheMetrics {
+            hits: self.cache_hits.load(Ordering::Relaxed),
+            misses: self.cache_misses.load(Ordering::Relaxed),
+        };
+        if cache.hits + cache.misses > 0 {
+            metrics.cache = Some(cache);
+        }
+    }
+}
+
+impl QueryReader {
+    pub fn new(config: Config, db_path: &Path, workspace_root: PathBuf, workspace_fingerprint: &str) -> Self {
+        Self {
+            config: Arc::new(config),
+            pool: Arc::new(ReadPool::new(db_path, READ_POOL_SIZE)),
+            db_path: db_path.to_path_buf(),
+            snippet_writer: Arc::default(),
+            workspace_root,
+            workspace_fingerprint: Arc::from(workspace_fingerprint),
+            cancel: CancelToken::new(),
+            cost: Arc::default(),
+            memory: MemoryBudget::default(),
+        }
+    }
+
+    /// This reader, stopping large responses that would take the daemon
+    /// past `memory`.
+    pub fn with_memory(self, memory: MemoryBudget) -> Self {
+        Self { memory, ..self }
+    }
+
+    /// A reader for one request, sharing this one's pool, whose queries
+    /// stop once `cancel` is cancelled and whose cost is counted afresh.
+    pub fn with_cancel(&self, cancel: CancelToken) -> Self {
+        Self { cancel, cost: Arc::default(), ..self.clone() }
+    }
+
+    /// What this reader's queries have cost so far.
+    pub(crate) fn cost(&self) -> &QueryCost {
+        &self.cost
+    }
+
+    /// Close idle pooled connections, freeing their page caches; returns
+    /// how many were closed.
+    pub fn shed_caches(&self) -> usize {
+        self.pool.shed()
+    }
+
+    /// Fail once a response grown to `bytes` would take the daemon past its
+    /// memory budget, rather than finish building it.
+    fn within_budget(&self, bytes: usize) -> Result<(), HandlerError> {
+        if !self.memory.exceeded_by(bytes) {
+            return Ok(());
+        }
+        self.shed_caches();
+        self.memory.refusal(bytes).map_or(Ok(()), Err)
+    }
+
+    pub(crate) fn workspace_fingerprint(&self) -> &str {
+        &self.workspace_fingerprint
+    }
+
+    /// A pooled connection whose statements are interrupted on cancel and
+    /// counted towards this reader's cost.
+    fn db(&self) -> Result<PooledConnection<'_>, (String, ErrorCode)> {
+        let db = self.pool.get().map_err(db_error("Database error"))?;
+        let (cancel, cost) = (self.cancel.clone(), self.cost.clone());
+        cost.measured.store(true, Ordering::Relaxed);
+        db.interrupt_when(move || {
+            cost.db_steps.fetch_add(PROGRESS_STEPS, Ordering::Relaxed);
+            cancel.is_cancelled()
+        });
+        Ok(db)
+    }
+
+    /// Whether `cmd` is read-only and can be handled here.
+    pub fn serves(cmd: &Command) -> bool {
+        matches!(
+            cmd,
+            Command::Find { .. }
+                | Command::Search { .. }
+                | Command::FuzzyFind { .. }
+                | Command::SearchDocs { .. }
+                | Command::Grep { .. }
+                | Command::Doc { .. }
+                | Command::Ls { .. }
+                | Command::Export { .. }
+                | Command::Stats
+                | Command::Verify
+                | Command::Tree { .. }
+                | Command::Refs { .. }
+                | Command::Owner { .. }
+                | Command::Locate { .. }
+                | Command::Todo { .. }
+                | Command::Impls { .. }
+                | Command::Deps { .. }
+                | Command::Callers { .. }
+                | Command::Callees { .. }
+                | Command::Diff { .. }
+                | Command::Bench { .. }
+                | Command::Status { .. }
+        )
+    }
+
+    /// Handle `request`, failing with `IndexMismatch` rather than answering
+    /// with rows read across a reindex that committed mid-query.
+    pub async fn handle_request(&self, request: Request) -> Response {
+        let start = std::time::Instant::now();
+        let before = self.index_epoch();
+        let mut result = self.handle_command(request.cmd).await;
+        let after = self.index_epoch();
+        if self.cancel.is_cancelled() {
+            // Whatever the interrupted query failed with, this is why
+            result = Err(("Request cancelled".to_string(), ErrorCode::Cancelled).into());
+        } else if result.is_ok() && before != after {
+            result = Err((
+                format!(
+                    "Index changed while the query ran (epoch {} -> {}); retry",
+                    before.unwrap_or(0),
+                    after.unwrap_or(0)
+                ),
+                ErrorCode::IndexMismatch,
+            ).into());
+        }
+        finish_response(request.request_id, start, result, after, self)
+    }
+
+    /// The committed index epoch, if the database can be read.
+    pub fn index_epoch(&self) -> Option<u64> {
+        let db = self.pool.get().ok()?;
+        queries::get_index_epoch(&db).ok()
+    }
+
+    pub async fn handle_command(&self, cmd: Command) -> Result<Response, HandlerError> {
+        let paging = Paging::new(&cmd)?;
+        match cmd {
+            Command::Find { name, path, kind, vis, unimplemented, todo, all, recently_changed, depth, match_mode, tag, sig, cursor: _ } => {
+                self.handle_find(name, path, kind, vis, unimplemented, todo, all, recently_changed, depth, match_mode, tag, sig, &paging).await
+            }
+            Command::Search { query, kind, vis, limit, all, cursor: _ } => {
+                self.handle_search(query, kind, vis, limit, all, &paging).await
+            }
+            Command::FuzzyFind { query, kind, vis, limit, cursor: _ } => {
+                self.handle_fuzzy_find(query, kind, vis, limit, &paging).await
+            }
+            Command::SearchDocs { query, limit } => {
+                self.handle_search_docs(query, limit).await
+            }
+            Command::Grep { pattern, fields, regex, limit } => {
+                self.handle_grep(pattern, fields, regex, limit).await
+            }
+            Command::Doc { path, include_docs, examples, vis, unimplemented, todo } => {
+                self.handle_doc(path, include_docs, examples, vis, unimplemented, todo).await
+            }
+            Command::Ls { path, expansion, impl_parents, include_docs, vis, unimplemented, todo, cursor: _ } => {
+                self.handle_ls(path, expansion, impl_parents, include_docs, vis, unimplemented, todo, &paging).await
+            }
+            Command::Export { paths, bundle, expansion, include_docs, vis, unimplemented, todo, impl_parents, with_source } => {
+                self.handle_export(paths, bundle, expansion, include_docs, vis, unimplemented, todo, impl_parents, with_source).await
+            }
+            Command::Stats => {
+                self.handle_stats().await
+            }
+            Command::Verify => {
+                self.handle_verify().await
+            }
+            Command::Tree { path, depth, kinds } => {
+                self.handle_tree(path, depth, kinds).await
+            }
+            Command::Refs { path, limit, group_by } => {
+                self.handle_refs(path, limit, group_by).await
+            }
+            Command::Owner { file, line } => {
+                self.handle_owner(file, line).await
+            }
+            Command::Locate { path } => {
+                self.handle_locate(path).await
+            }
+            Command::Todo { matching, limit } => {
+                self.handle_todo(matching, limit).await
+            }
+            Command::Impls { path, direction, external } => {
+                self.handle_impls(path, direction, external).await
+            }
+            Command::Deps { crate_name, direction, depth } => {
+                self.handle_deps(crate_name, direction, depth).await
+            }
+            Command::Diff { baseline, breaking_only } => {
+                self.handle_diff(baseline, breaking_only.unwrap_or(false)).await
+            }
+            Command::Callers { path, depth } => {
+                self.handle_call_graph(path, CallDirection::Callers, depth).await
+            }
+            Command::Callees { path, depth } => {
+                self.handle_call_graph(path, CallDirection::Callees, depth).await
+            }
+            Command::Status { vis, unimplemented, todo, hotspots, group_by, cursor: _ } => {
+                self.handle_status(vis, unimplemented, todo, hotspots, group_by, &paging).await
+            }
+            Command::Bench { queries, warmup, duration } => {
+                self.handle_bench(queries, warmup, duration).await
+            }
+            other => Err((
+                format!("{:?} is not a read-only command", other),
+                ErrorCode::InternalError,
+            ).into()),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_find(
+        &self,
+        name: Option<String>,
+        path: Option<String>,
+        kind: Option<String>,
+        vis: Option<String>,
+        unimplemented: Option<bool>,
+        todo: Option<bool>,
+        all: Option<bool>,
+        recently_changed: Option<u32>,
+        depth: Option<usize>,
+        match_mode: Option<String>,
+        tag: Option<String>,
+        sig: Option<String>,
+        paging: &Paging,
+    ) -> Result<Response, HandlerError> {
+        if name.is_none() && path.is_none() && tag.is_none() && sig.is_none() {
+            return Err(("Must provide either name, path, tag or sig".to_string(), ErrorCode::InvalidArg).into());
+        }
+        
+        let db = self.db()?;
+        
+        let status_filter = status_filter(unimplemented, todo);
+        let after = paging.after_key()?;
+        // Names have no `::`, so a glob with one is a path glob
+        let glob_mode = match_mode.as_deref().is_none_or(|mode| mode == "glob");
+        let path_glob = match (&name, &path) {
+            (Some(name), _) if glob_mode && name.contains("::") && queries::is_glob(name) => Some(name.clone()),
+            (None, Some(path)) if queries::is_glob(path) => Some(path.clone()),
+            _ => None,
+        };
+        let changed_since = recently_changed.map(|days| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            now - i64::from(days) * 24 * 60 * 60
+        });
+        let page = if let Some(pattern) = path_glob {
+            // The pattern says how deep to match, and a name glob leaves no
+            // room for a separate path
+            if depth.is_some() {
+                return Err((
+                    "depth doesn't apply to a path glob; use * per level or ** for any depth".to_string(),
+                    ErrorCode::InvalidArg,
+                ).into());
+            }
+            if name.is_some() && path.is_some() {
+                return Err((
+                    "Give the path glob as either name or path, not both".to_string(),
+                    ErrorCode::InvalidArg,
+                ).into());
+            }
+            queries::find_symbols_by_path_glob(
+                &db,
+                &pattern,
+                kind.as_deref(),
+                vis.as_deref(),
+                status_filter,
+                changed_since,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?
+        } else if let Some(name) = name {
+            let mode = match match_mode.as_deref() {
+                Some(mode) => mode.parse().map_err(|e: DbError| (e.to_string(), ErrorCode::InvalidArg))?,
+                None if queries::is_glob(&name) => MatchMode::Glob,
+                None => MatchMode::default(),
+            };
+            
+            queries::find_symbols_by_name(
+                &db,
+                &name,
+                mode,
+                kind.as_deref(),
+                vis.as_deref(),
+                status_filter,
+                changed_since,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?
+        } else if let Some(path) = path {
+            queries::find_symbols_by_path_prefix(
+                &db,
+                &path,
+                depth,
+                kind.as_deref(),
+                vis.as_deref(),
+                status_filter,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(db_error("Query error"))?
+        } else if let Some(sig) = sig {
+            queries::find_by_signature(
+                &db,
+                &sig,
+                kind.as_deref(),
+                vis.as_deref(),
+                status_filter,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?
+        } else if let Some(tag) = tag {
+            queries::find_symbols_by_tag(
+                &db,
+                &tag,
+                kind.as_deref(),
+                vis.as_deref(),
+                status_filter,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(db_error("Query error"))?
+        } else {
+            Page { items: vec![], next_cursor: None }
+        };
+        let next_cursor = paging.next_after(page.next_cursor);
+        let symbols = page.items;
+        
+        // Filter response based on 'all' flag
+        let items = if all.unwrap_or(false) {
+            // Return all fields
+            symbols.into_iter().map(|s| with_all_fields(&s)).collect()
+        } else {
+            // Return only path and span fields
+            symbols.into_iter().map(|s| {
+                SymbolItem {
+                    path: s.path,
+                    span_start: s.span_start,
+                    span_end: s.span_end,
+                    fields: Default::default(),
+                }
+            }).collect()
+        };
+        
+        Ok(Response::success(
+            "".to_string(), // Request ID will be filled by caller
+            json!(FindData { items }),
+        ).with_next_cursor(next_cursor))
+    }
+
+    async fn handle_search(
+        &self,
+        query: String,
+        kind: Option<String>,
+        vis: Option<String>,
+        limit: Option<usize>,
+        all: Option<bool>,
+        paging: &Paging,
+    ) -> Result<Response, HandlerError> {
+        if query.trim().is_empty() {
+            return Err(("Search query is empty".to_string(), ErrorCode::InvalidArg).into());
+        }
+        
+        let db = self.db()?;
+        
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let offset = paging.offset()?;
+        let mut hits = queries::search_fulltext(&db, &query, kind.as_deref(), vis.as_deref(), offset, limit + 1)
+            .map_err(db_error("Query error"))?;
+        let next_cursor = paging.next_cursor((hits.len() > limit).then_some(PagePosition::Offset(offset + limit)));
+        hits.truncate(limit);
+        
+        let items = if all.unwrap_or(false) {
+            hits.iter().map(with_all_fields).collect()
+        } else {
+            hits.into_iter().map(|h| {
+                SearchItem {
+                    path: h.symbol.path,
+                    kind: h.symbol.kind.as_str().to_string(),
+                    score: h.score,
+                    span_start: h.symbol.span_start,
+                    span_end: h.symbol.span_end,
+                    fields: Default::default(),
+                }
+            }).collect()
+        };
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(SearchData { items }),
+        ).with_next_cursor(next_cursor))
+    }
+
+    async fn handle_fuzzy_find(
+        &self,
+        query: String,
+        kind: Option<String>,
+        vis: Option<String>,
+        limit: Option<usize>,
+        paging: &Paging,
+    ) -> Result<Response, HandlerError> {
+        if query.trim().is_empty() {
+            return Err(("Fuzzy query is empty".to_string(), ErrorCode::InvalidArg).into());
+        }
+        
+        let db = self.db()?;
+        
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let offset = paging.offset()?;
+        let mut hits = queries::fuzzy_find_symbols(&db, &query, kind.as_deref(), vis.as_deref(), offset, limit + 1)
+            .map_err(db_error("Query error"))?;
+        let next_cursor = paging.next_cursor((hits.len() > limit).then_some(PagePosition::Offset(offset + limit)));
+        hits.truncate(limit);
+        
+        let items: Vec<SearchItem> = hits.into_iter().map(|h| {
+            SearchItem {
+                path: h.symbol.path,
+                kind: h.symbol.kind.as_str().to_string(),
+                score: h.score as f64,
+                span_start: h.symbol.span_start,
+                span_end: h.symbol.span_end,
+                fields: Default::default(),
+            }
+        }).collect();
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(SearchData { items }),
+        ).with_next_cursor(next_cursor))
+    }
+
+    async fn handle_search_docs(&self, query: String, limit: Option<usize>) -> Result<Response, HandlerError> {
+        if query.trim().is_empty() {
+            return Err(("Search query is empty".to_string(), ErrorCode::InvalidArg).into());
+        }
+        
+        let db = self.db()?;
+        
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let hits = queries::search_docs(&db, &query, limit)
+            .map_err(db_error("Query error"))?;
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "items": hits,
+            }),
+        ))
+    }
+
+    async fn handle_grep(
+        &self,
+        pattern: String,
+        fields: Vec<GrepField>,
+        regex: bool,
+        limit: Option<usize>,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let fields: Vec<SymbolField> = fields
+            .into_iter()
+            .map(|field| match field {
+                GrepField::Name => SymbolField::Name,
+                GrepField::Sig => SymbolField::Signature,
+                GrepField::Docs => SymbolField::Docs,
+            })
+            .collect();
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let mut hits = queries::grep_symbols(&db, &pattern, regex, &fields, limit + 1)
+            .map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?;
+        let truncated = hits.len() > limit;
+        hits.truncate(limit);
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "items": hits,
+                "truncated": truncated,
+            }),
+        ))
+    }
+
+    async fn handle_doc(
+        &self,
+        path: String,
+        include_docs: bool,
+        examples: bool,
+        _vis: Option<String>,
+        _unimplemented: Option<bool>,
+        _todo: Option<bool>,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let Some(symbol) = queries::find_symbol_by_path(&db, &path)
+            .map_err(db_error("Query error"))?
+        else {
+            return Err(symbol_not_found(&path, queries::suggest_paths(&db, &path, MAX_SUGGESTIONS)));
+        };
+        
+        let mut data = json!({
+            "symbol": {
+                "path": symbol.path,
+                "signature": symbol.signature,
+                "docs": if include_docs { symbol.docs.clone() } else { None },
+            },
+        });
+        
+        if examples {
+            let examples = queries::get_examples(&db, &symbol.symbol_id)
+                .map_err(db_error("Query error"))?;
+            data["examples"] = json!(examples);
+        }
+        
+        Ok(Response::success("".to_string(), data))
+    }
+
+    async fn handle_stats(&self) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let summary = queries::get_stats(&db, STATS_LARGEST)
+            .map_err(db_error("Query error"))?;
+        
+        Ok(Response::success("".to_string(), json!(summary)))
+    }
+
+    async fn handle_verify(&self) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let report = queries::verify_files(&db, &self.workspace_root)
+            .map_err(db_error("Query error"))?;
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "fresh": report.is_fresh(),
+                "files_checked": report.files_checked,
+                "stale_crates": report.stale_crates,
+            }),
+        ))
+    }
+
+    async fn handle_tree(
+        &self,
+        path: String,
+        depth: Option<usize>,
+        kinds: Vec<String>,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        // Kinds are filtered in the query, so the limit isn't spent on others
+        let page = queries::find_tree_symbols(&db, &path, depth, &kinds, self.config.max_list)
+            .map_err(db_error("Query error"))?;
+        let truncated = page.next_cursor.is_some();
+        let mut tree = TreeNode::build(page.items);
+        if !kinds.is_empty() {
+            TreeNode::retain_kinds(&mut tree, &kinds);
+        }
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "path": path,
+                "truncated": truncated,
+                "tree": tree,
+            }),
+        ))
+    }
+
+    async fn handle_refs(
+        &self,
+        path: String,
+        limit: Option<usize>,
+        group_by: Option<RefsGroup>,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let mut sites = queries::find_references(&db, &path, limit + 1)
+            .map_err(db_error("Query error"))?;
+        let truncated = sites.len() > limit;
+        sites.truncate(limit);
+        let total = sites.len();
+        
+        // Sites arrive ordered by file, so each file's references are
+        // adjacent; a stable sort by crate keeps them so within each crate
+        let by_crate = group_by == Some(RefsGroup::Crate);
+        if by_crate {
+            sites.sort_by(|a, b| a.crate_name.cmp(&b.crate_name));
+        }
+        let mut crates: Vec<RefCrate> = Vec::new();
+        for site in sites {
+            let reference = RefSite {
+                from: site.from_path,
+                target: site.target_path,
+                line_start: site.span_start,
+                line_end: site.span_end,
+                column: site.span_col,
+            };
+            if crates.last().is_none_or(|krate| krate.name != site.crate_name) {
+                crates.push(RefCrate { name: site.crate_name, files: Vec::new() });
+            }
+            let files = &mut crates.last_mut().expect("pushed above").files;
+            match files.last_mut() {
+                Some(file) if file.file == site.file => file.references.push(reference),
+                _ => files.push(RefFile { file: site.file, references: vec![reference] }),
+            }
+        }
+        let files = if by_crate {
+            Vec::new()
+        } else {
+            std::mem::take(&mut crates).into_iter().flat_map(|krate| krate.files).collect()
+        };
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(RefsData { path, total, truncated, files, crates }),
+        ))
+    }
+
+    async fn handle_status(
+        &self,
+        vis: Option<String>,
+        unimplemented: Option<bool>,
+        todo: Option<bool>,
+        hotspots: bool,
+        group_by: Option<StatusGroup>,
+        paging: &Paging,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let counts = queries::get_status_counts(&db, vis.as_deref())
+            .map_err(db_error("Query error"))?;
+        
+        let page = queries::get_status_items(
+            &db,
+            vis.as_deref(),
+            unimplemented.unwrap_or(false),
+            todo.unwrap_or(false),
+            paging.after_key()?.as_ref(),
+            self.config.max_list,
+        ).map_err(db_error("Query error"))?;
+        
+        let mut data = json!({
+            "counts": counts,
+            "items": page.items,
+        });
+        
+        if let Some(group_by) = group_by {
+            let groups = queries::get_status_groups(&db, vis.as_deref(), group_by)
+                .map_err(db_error("Query error"))?;
+            data["groups"] = json!(groups);
+        }
+        
+        if hotspots {
+            let hotspots = queries::get_hotspots(
+                &db,
+                vis.as_deref(),
+                self.config.hotspots_top_n,
+            ).map_err(db_error("Query error"))?;
+            data["hotspots"] = json!(hotspots);
+        }
+        
+        Ok(Response::success("".to_string(), data).with_next_cursor(paging.next_after(page.next_cursor)))
+    }
+
+    async fn handle_owner(&self, file: String, line: u32) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let file = self.workspace_relative(&file);
+        let Some(symbol) = queries::find_symbol_at(&db, &file, line).map_err(db_error("Query error"))? else {
+            let error = HandlerError::from((format!("No symbol contains {}:{}", file, line), ErrorCode::NotFound));
+            let indexed = queries::find_crate_for_file(&db, &file).map_err(db_error("Query error"))?;
+            return Err(match indexed {
+                Some(_) => error,
+                None => error.with_hint("The file is not indexed; give its path relative to the workspace root"),
+            });
+        };
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(OwnerData {
+                file,
+                line,
+                path: symbol.path,
+                kind: symbol.kind.as_str().to_string(),
+                signature: symbol.signature,
+                span_start: symbol.span_start,
+                span_end: symbol.span_end,
+            }),
+        ))
+    }
+
+    async fn handle_locate(&self, path: String) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let Some(symbol) = queries::find_symbol_by_path(&db, &path)
+            .map_err(db_error("Query error"))?
+        else {
+            return Err(symbol_not_found(&path, queries::suggest_paths(&db, &path, MAX_SUGGESTIONS)));
+        };
+        let Some(file) = queries::get_file(&db, symbol.file_id).map_err(db_error("Query error"))? else {
+            return Err((format!("No file is indexed for {}", symbol.path), ErrorCode::NotFound).into());
+        };
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(LocateData {
+                absolute_file: self.workspace_root.join(&file.path).to_string_lossy().into_owned(),
+                file: file.path,
+                path: symbol.path,
+                kind: symbol.kind.as_str().to_string(),
+                span_start: symbol.span_start,
+                span_end: symbol.span_end,
+            }),
+        ))
+    }
+
+    async fn handle_todo(&self, matching: Option<String>, limit: Option<usize>) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let limit = limit.unwrap_or(self.config.max_list).min(self.config.max_list);
+        let mut markers = queries::get_markers(&db, matching.as_deref(), limit + 1)
+            .map_err(db_error("Query error"))?;
+        let truncated = markers.len() > limit;
+        markers.truncate(limit);
+        
+        let items: Vec<TodoItem> = markers
+            .into_iter()
+            .map(|site| TodoItem {
+                path: site.path,
+                file: site.file,
+                line: site.marker.line,
+                marker: site.marker.marker,
+                message: site.marker.message,
+                introduced: site.marker.introduced,
+            })
+            .collect();
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(TodoData { total: items.len(), truncated, items }),
+        ))
+    }
+
+    /// `file` as the index stores it: relative to the workspace root, with
+    /// forward slashes and no leading `./`. Paths reaching the root through
+    /// a symlink are compared canonically.
+    fn workspace_relative(&self, file: &str) -> String {
+        let path = Path::new(file);
+        let canonical = || -> Option<PathBuf> {
+            let root = self.workspace_root.canonicalize().ok()?;
+            Some(path.canonicalize().ok()?.strip_prefix(root).ok()?.to_path_buf())
+        };
+        let relative = match path.strip_prefix(&self.workspace_root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => canonical().unwrap_or_else(|| path.to_path_buf()),
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        relative.trim_start_matches("./").to_string()
+    }
+
+    /// Replay a mix of lookups on symbols sampled from the index through
+    /// `handle_command`, one at a time, and report their latencies.
+    async fn handle_bench(&self, queries: u32, warmup: u32, duration: u32) -> Result<Response, HandlerError> {
+        if queries == 0 || duration == 0 || duration > MAX_BENCH_SECS {
+            return Err((
+                format!("queries must be at least 1 and duration between 1 and {} seconds", MAX_BENCH_SECS),
+                ErrorCode::InvalidArg,
+            ).into());
+        }
+        
+        let mix: Vec<(&str, Command)> = {
+            let db = self.db()?;
+            let symbols = queries::sample_symbols(&db, (queries as usize).div_ceil(BENCH_MIX_LEN))
+                .map_err(db_error("Query error"))?;
+            symbols.iter().flat_map(bench_mix).take(queries as usize).collect()
+        };
+        if mix.is_empty() {
+            return Err(HandlerError::from(("Nothing is indexed to benchmark".to_string(), ErrorCode::NotFound))
+                .with_hint("Run `ct reindex` first"));
+        }
+        
+        // Seconds of back-to-back queries that never yield; run them on a
+        // blocking thread rather than tie up a runtime worker
+        let (warmup, duration) = (Duration::from_millis(warmup as u64), Duration::from_secs(duration as u64));
+        let (reader, runtime) = (self.clone(), tokio::runtime::Handle::current());
+        let (mix, timings, errors, elapsed) = tokio::task::spawn_blocking(move || {
+            let (timings, errors, elapsed) = runtime.block_on(reader.time_queries(&mix, warmup, duration));
+            (mix, timings, errors, elapsed)
+        })
+        .await
+        .map_err(|e| HandlerError::from((format!("Benchmark failed: {}", e), ErrorCode::InternalError)))?;
+        
+        let mut all: Vec<Duration> = Vec::new();
+        let mut commands: Vec<(&str, Vec<Duration>)> = Vec::new();
+        for ((command, _), samples) in mix.iter().zip(timings) {
+            all.extend(&samples);
+            match commands.iter_mut().find(|(name, _)| name == command) {
+                Some((_, times)) => times.extend(samples),
+                None => commands.push((command, samples)),
+            }
+        }
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(BenchData {
+                queries: mix.len(),
+                warmup_ms: warmup.as_millis() as u64,
+                duration_ms: elapsed.as_millis() as u64,
+                samples: all.len() as u64,
+                errors,
+                throughput_qps: all.len() as f64 / elapsed.as_secs_f64(),
+                latency: latency(&mut all),
+                commands: commands
+                    .into_iter()
+                    .map(|(command, mut samples)| BenchCommand {
+                        command: command.to_string(),
+                        samples: samples.len() as u64,
+                        latency: latency(&mut samples),
+                    })
+                    .collect(),
+            }),
+        ))
+    }
+
+    /// Run `mix` over and over for `warmup`, then for `duration`, returning
+    /// each command's timings, how many failed and how long it took.
+    async fn time_queries(
+        &self,
+        mix: &[(&str, Command)],
+        warmup: Duration,
+        duration: Duration,
+    ) -> (Vec<Vec<Duration>>, u64, Duration) {
+        // Warm the page cache and the pool before timing anything
+        let started = Instant::now();
+        for (_, cmd) in mix.iter().cycle() {
+            if started.elapsed() >= warmup || self.cancel.is_cancelled() {
+                break;
+            }
+            // Boxed, as this future is itself part of `handle_command`'s
+            let _ = Box::pin(self.handle_command(cmd.clone())).await;
+        }
+        
+        let mut timings: Vec<Vec<Duration>> = vec![Vec::new(); mix.len()];
+        let mut errors = 0;
+        let started = Instant::now();
+        for (i, (_, cmd)) in mix.iter().enumerate().cycle() {
+            if started.elapsed() >= duration || self.cancel.is_cancelled() {
+                break;
+            }
+            let query_start = Instant::now();
+            if Box::pin(self.handle_command(cmd.clone())).await.is_err() {
+                errors += 1;
+            }
+            timings[i].push(query_start.elapsed());
+        }
+        (timings, errors, started.elapsed())
+    }
+
+    async fn handle_impls(&self, path: String, direction: ImplsDirection, external: bool) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        let limit = self.config.max_list;
+        let mut impls = match direction {
+            ImplsDirection::Implementors => queries::find_trait_implementors(&db, &path, limit + 1),
+            ImplsDirection::Traits => queries::find_implemented_traits(&db, &path, external, limit + 1),
+        }
+        .map_err(db_error("Query error"))?;
+        let truncated = impls.len() > limit;
+        impls.truncate(limit);
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "path": path,
+                "direction": direction,
+                "truncated": truncated,
+                "impls": impls,
+            }),
+        ))
+    }
+
+    async fn handle_deps(
+        &self,
+        crate_name: Option<String>,
+        direction: DepsDirection,
+        depth: Option<usize>,
+    ) -> Result<Response, HandlerError> {
+        let depth = graph_depth(depth, 1)?;
+        let db = self.db()?;
+        
+        let walk = match direction {
+            DepsDirection::Dependencies => DepDirection::Dependencies,
+            DepsDirection::Dependents => DepDirection::Dependents,
+        };
+        let limit = self.config.max_list;
+        let mut edges = queries::dependency_graph(&db, crate_name.as_deref(), walk, depth, limit + 1)
+            .map_err(db_error("Query error"))?;
+        let truncated = edges.len() > limit;
+        edges.truncate(limit);
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!({
+                "crate": crate_name,
+                "direction": direction,
+                "depth": depth,
+                "truncated": truncated,
+                "edges": edges,
+            }),
+        ))
+    }
+
+    async fn handle_diff(&self, baseline: Option<String>, breaking_only: bool) -> Result<Response, HandlerError> {
+        // Relative snapshot paths are taken from the workspace root, as the
+        // daemon's working directory is not the client's
+        let snapshot = baseline.as_ref().map(|b| self.workspace_root.join(b));
+        if let Some(snapshot) = &snapshot {
+            if !snapshot.is_file() {
+                return Err(HandlerError::from((format!("Snapshot not found: {}", snapshot.display()), ErrorCode::NotFound))
+                    .with_hint("Relative snapshot paths are resolved against the workspace root"));
+            }
+        }
+        
+        let db = self.db()?;
+        
+        let baseline_epoch = match snapshot {
+            Some(_) => None,
+            None => Some(
+                queries::get_baseline_epoch(&db)
+                    .map_err(db_error("Query error"))?
+                    .ok_or_else(|| {
+                        HandlerError::from(("No earlier epoch to diff against".to_string(), ErrorCode::NotFound))
+                            .with_hint("Reindex once to record a baseline, or pass a snapshot to diff against")
+                    })?,
+            ),
+        };
+        let limit = self.config.max_list;
+        let mut changes = queries::diff_symbols(&db, snapshot.as_deref(), breaking_only, limit + 1)
+            .map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?;
+        let truncated = changes.len() > limit;
+        changes.truncate(limit);
+        let count = |kind| changes.iter().filter(|c| c.change == kind).count();
+        
+        let data = DiffData {
+            baseline,
+            baseline_epoch,
+            added: count(ChangeKind::Added),
+            removed: count(ChangeKind::Removed),
+            changed: count(ChangeKind::Changed),
+            truncated,
+            changes,
+        };
+        
+        Ok(Response::success("".to_string(), json!(data)))
+    }
+
+    async fn handle_call_graph(
+        &self,
+        path: String,
+        direction: CallDirection,
+        depth: Option<usize>,
+    ) -> Result<Response, HandlerError> {
+        let depth = graph_depth(depth, DEFAULT_CALL_DEPTH)?;
+        
+        let db = self.db()?;
+        
+        let limit = self.config.max_list;
+        let mut sites = queries::call_graph(&db, &path, direction, depth, limit + 1)
+            .map_err(db_error("Query error"))?;
+        let truncated = sites.len() > limit;
+        sites.truncate(limit);
+        
+        let edges = sites
+            .into_iter()
+            .map(|(hop, site)| CallEdge {
+                caller: site.caller_path,
+                callee: site.callee_path,
+                file: site.file,
+                line_start: site.span_start,
+                line_end: site.span_end,
+                depth: hop,
+            })
+            .collect();
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(CallGraphData { path, depth, truncated, edges }),
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_ls(
+        &self,
+        path: String,
+        expansion: Option<String>,
+        _impl_parents: bool,
+        include_docs: bool,
+        vis: Option<String>,
+        unimplemented: Option<bool>,
+        todo: Option<bool>,
+        paging: &Paging,
+    ) -> Result<Response, HandlerError> {
+        let db = self.db()?;
+        
+        // `>` lists direct children, `>>` every descendant; `<`/`<<` add the
+        // parent or every ancestor
+        let expansion = expansion.unwrap_or_default();
+        let depth = if expansion.contains(">>") { None } else { Some(1) };
+        let after = paging.after_key()?;
+        let glob = queries::is_glob(&path);
+        if glob && !expansion.is_empty() {
+            return Err((
+                "Expansion operators don't apply to a glob; use ** for descendants, e.g. my_crate::api::**".to_string(),
+                ErrorCode::InvalidArg,
+            ).into());
+        }
+        let page = if glob {
+            queries::find_symbols_by_path_glob(
+                &db,
+                &path,
+                None,
+                vis.as_deref(),
+                status_filter(unimplemented, todo),
+                None,
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(|e| match e {
+                DbError::InvalidQuery(msg) => (msg, ErrorCode::InvalidArg),
+                e => db_error("Query error")(e),
+            })?
+        } else {
+            queries::find_symbols_by_path_prefix(
+                &db,
+                &path,
+                depth,
+                None,
+                vis.as_deref(),
+                status_filter(unimplemented, todo),
+                after.as_ref(),
+                self.config.max_list,
+            ).map_err(db_error("Query error"))?
+        };
+        let next_cursor = paging.next_after(page.next_cursor);
+        let mut symbols = page.items;
+        
+        // Ancestors lead the first page only
+        if expansion.contains('<') && after.is_none() {
+            let mut ancestors = Vec::new();
+            let mut current = path.trim_end_matches("::");
+            while let Some((parent, _)) = current.rsplit_once("::") {
+                if let Some(symbol) = queries::find_symbol_by_path(&db, parent)
+                    .map_err(db_error("Query error"))?
+                {
+                    ancestors.push(symbol);
+                }
+                if !expansion.contains("<<") {
+                    break;
+                }
+                current = parent;
+            }
+            ancestors.reverse();
+            symbols.splice(0..0, ancestors);
+        }
+        
+        let items = symbols.into_iter().map(|s| {
+            LsItem {
+                path: s.path,
+                kind: s.kind.as_str().to_string(),
+                signature: s.signature,
+                docs: if include_docs { s.docs } else { None },
+                span_start: s.span_start,
+                span_end: s.span_end,
+            }
+        }).collect();
+        
+        Ok(Response::success(
+            "".to_string(),
+            json!(LsData { items }),
+        ).with_next_cursor(next_cursor))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_export(
+        &self,
+        paths: Vec<String>,
+        _bundle: bool,
+        expansion: Option<String>,
+        include_docs: bool,
+        vis: Option<String>,
+        unimplemented: Option<bool>,
+        todo: Option<bool>,
+        _impl_parents: bool,
+        with_source: bool,
+    ) -> Result<Response, HandlerError> {
+        if paths.is_empty() {
+            return Err(("Export needs at least one path".to_string(), ErrorCode::InvalidArg).into());
+        }
+        
+        let db = self.db()?;
+        
+        // Roots in the order asked for, each once
+        let mut seen = HashSet::new();
+        let mut roots = Vec::new();
+        for path in &paths {
+            let Some(symbol) = queries::find_symbol_by_path(&db, path)
+                .map_err(db_error("Query error"))?
+            else {
+                return Err(symbol_not_found(path, queries::suggest_paths(&db, path, MAX_SUGGESTIONS)));
+            };
+            if seen.insert(symbol.path.clone()) {
+                roots.push(symbol);
+            }
+        }
+        
+        // `>` adds each root's direct children, `>>` every descendant; a
+        // child shared by several roots, or itself a root, appears once
+        let expansion = expansion.unwrap_or_default();
+        let mut children = Vec::new();
+        let mut truncated = false;
+        if expansion.contains('>') {
+            let depth = if expansion.contains(">>") { None } else { Some(1) };
+            for root in &roots {
+                let page = queries::find_symbols_by_path_prefix(
+                    &db,
+                    &root.path,
+                    depth,
+                    None,
+                    vis.as_deref(),
+                    status_filter(unimplemented, todo),
+                    None,
+                    self.config.max_list,
+                ).map_err(db_error("Query error"))?;
+                truncated |= page.next_cursor.is_some();
+                children.extend(page.items.into_iter().filter(|s| seen.insert(s.path.clone())));
+            }
+            children.sort_by(|a, b| (a.path.matches("::").count(), &a.path).cmp(&(b.path.matches("::").count(), &b.path)));
+            truncated |= children.len() > self.config.max_list;
+            children.truncate(self.config.max_list);
+        }
+        
+        // Sources can be large; give up as soon as the bundle outgrows the budget
+        let mut bytes = 0;
+        let mut export = |symbols: Vec<Symbol>| -> Result<Vec<ExportSymbol>, HandlerError> {
+            let mut exported = Vec::with_capacity(symbols.len());
+            for symbol in symbols {
+                let symbol = self.export_symbol(&db, symbol, include_docs, with_source)?;
+                bytes += symbol.path.len()
+                    + symbol.signature.len()
+                    + symbol.docs.as_ref().map_or(0, String::len)
+                    + symbol.source.as_ref().map_or(0, String::len);
+                self.within_budget(bytes)?;
+                exported.push(symbol);
+            }
+            Ok(exported)
+        };
+        let bundle = ExportBundle {
+            symbols: export(roots)?,
+            children: export(children)?,
+            extern_refs: vec![],
+            impl_ranges: vec![],
+            order: "bfs".to_string(),
+            truncated,
+            invariants: ExportInvariants { range_1_based_inclusive: true },
+        };
+        Ok(Response::success("".to_string(), json!(ExportData { bundle })))
+    }
+}
+
+impl QueryReader {
+    /// `symbol` as it appears in an export bundle.
+    fn export_symbol(
+        &self,
+        db: &PooledConnection<'_>,
+        symbol: Symbol,
+        include_docs: bool,
+        with_source: bool,
+    ) -> Result<ExportSymbol, HandlerError> {
+        let source = if with_source { self.symbol_source(db, &symbol)? } else { None };
+        Ok(ExportSymbol {
+            kind: symbol.kind.as_str().to_string(),
+            docs: if include_docs { symbol.docs } else { None },
+            // The file changed since it was indexed; the span may be off
+            source_stale: source.as_ref().is_some_and(|(_, stale)| *stale),
+            source: source.map(|(text, _)| text),
+            path: symbol.path,
+            signature: symbol.signature,
+            span_start: symbol.span_start,
+            span_end: symbol.span_end,
+        })
+    }
+
+    /// Text of `symbol`'s span and whether its file changed since indexing.
+    /// Spans of unchanged files are served from, or added to, the snippet
+    /// cache; `None` if the file is gone.
+    fn symbol_source(
+        &self,
+        db: &PooledConnection<'_>,
+        symbol: &Symbol,
+    ) -> Result<Option<(String, bool)>, (String, ErrorCode)> {
+        let Some(file) = queries::get_file(db, symbol.file_id)
+            .map_err(db_error("Query error"))?
+        else {
+            return Ok(None);
+        };
+        
+        let cached = queries::get_snippet(db, &file.digest, symbol.span_start, symbol.span_end)
+            .map_err(db_error("Query error"))?;
+        self.cost.record_cache(cached.is_some());
+        if let Some(text) = cached {
+            return Ok(Some((text, false)));
+        }
+        
+        let Ok(content) = std::fs::read(self.workspace_root.join(&file.path)) else {
+            return Ok(None);
+        };
+        let text = slice_lines(&String::from_utf8_lossy(&content), symbol.span_start, symbol.span_end);
+        if compute_file_digest(&content) != file.digest {
+            return Ok(Some((text, true)));
+        }
+        
+        // Best effort: a reindex holding the write lock just means a miss next time
+        if let Err(e) = self.cache_snippet(&file.digest, symbol, &text) {
+            debug!("Not caching snippet for {}: {}", symbol.path, e);
+        }
+        Ok(Some((text, false)))
+    }
+
+    /// Store `symbol`'s span of the file with `digest`. Fails at once rather
+    /// than waiting while a reindex holds the write lock.
+    fn cache_snippet(&self, digest: &str, symbol: &Symbol, text: &str) -> ct_db::Result<()> {
+        let mut writer = self.snippet_writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let db = match writer.take() {
+            Some(db) => db,
+            None => Database::open_existing(&self.db_path, Duration::ZERO)?,
+        };
+        let result = db.insert_snippet(digest, symbol.span_start, symbol.span_end, text);
+        *writer = Some(db);
+        result
+    }
+}
+
+/// `NotFound` for `path`, naming the indexed `candidates` it may have meant.
+pub(crate) fn symbol_not_found(path: &str, candidates: ct_db::Result<Vec<String>>) -> HandlerError {
+    // Suggestions are a courtesy; failing to look them up is not an error
+    let candidates = candidates.unwrap_or_default();
+    let error = HandlerError::from((format!("Symbol not found: {}", path), ErrorCode::NotFound));
+    match candidates.first() {
+        Some(best) => error
+            .with_hint(format!("did you mean `{}`?", best))
+            .with_details(json!({ "candidates": candidates })),
+        None => error.with_hint("Check the path with `ct find` or `ct ls`"),
+    }
+}
+
+/// A listing entry carrying every field `value` serializes with.
+fn with_all_fields<T, I>(value: &T) -> I
+where
+    T: serde::Serialize,
+    I: serde::de::DeserializeOwned,
+{
+    serde_json::from_value(json!(value)).expect("indexed symbols serialize with a path and span")
+}
+
+/// Lines `start..=end` (1-based) of `content`, each with its newline.
+fn slice_lines(content: &str, start: u32, end: u32) -> String {
+    content
+        .split_inclusive('\n')
+        .skip(start.saturating_sub(1) as usize)
+        .take((end + 1).saturating_sub(start.max(1)) as usize)
+        .collect()
+}
+
+/// Where a paged query resumes, and the query its `next_cursor` continues.
+pub(crate) struct Paging {
+    cmd: Command,
+    after: Option<PagePosition>,
+}
+
+impl Paging {
+    /// Decode the cursor `cmd` resumes from, refusing one that was issued
+    /// for a different query.
+    pub(crate) fn new(cmd: &Command) -> Result<Self, HandlerError> {
+        let query = cmd.clone().with_cursor(None);
+        let Some(token) = cmd.cursor() else {
+            return Ok(Self { cmd: query, after: None });
+        };
+        
+        let page = PageCursor::decode(token).map_err(|e| (e.to_string(), ErrorCode::InvalidArg))?;
+        if serde_json::to_value(&page.cmd).ok() != serde_json::to_value(&query).ok() {
+            return Err(HandlerError::from(("Cursor was issued for a different query".to_string(), ErrorCode::InvalidArg))
+                .with_hint("Send the cursor with `Continue`, which reruns the query it was issued for"));
+        }
+        Ok(Self { cmd: query, after: Some(page.position) })
+    }
+
+    /// The keyset position of a path-ordered query.
+    pub(crate) fn after_key(&self) -> Result<Option<Cursor>, HandlerError> {
+        match &self.after {
+            None => Ok(None),
+            Some(PagePosition::After { path, rowid }) => Ok(Some(Cursor {
+                after_path: path.clone(),
+                after_rowid: *rowid,
+            })),
+            Some(PagePosition::Offset(_)) => Err(mismatched_cursor()),
+        }
+    }
+
+    /// Results already returned by a ranked query.
+    fn offset(&self) -> Result<usize, HandlerError> {
+        match &self.after {
+            None => Ok(0),
+            Some(PagePosition::Offset(offset)) => Ok(*offset),
+            Some(PagePosition::After { .. }) => Err(mismatched_cursor()),
+        }
+    }
+
+    /// The `next_cursor` token resuming at `position`, if more results follow.
+    fn next_cursor(&self, position: Option<PagePosition>) -> Option<String> {
+        position.map(|position| PageCursor { cmd: self.cmd.clone(), position }.encode())
+    }
+
+    /// The `next_cursor` token for a page that ended at `next`.
+    pub(crate) fn next_after(&self, next: Option<Cursor>) -> Option<String> {
+        self.next_cursor(next.map(|c| PagePosition::After { path: c.after_path, rowid: c.after_rowid }))
+    }
+}
+
+fn mismatched_cursor() -> HandlerError {
+    HandlerError::from(("Cursor does not fit this query".to_string(), ErrorCode::InvalidArg))
+        .with_hint("Send the cursor with `Continue`, which reruns the query it was issued for")
+}
+
+/// Status to filter on for the `--unimplemented`/`--todo` flags; implemented
+/// symbols unless either is set, everything if both are.
+fn status_filter(unimplemented: Option<bool>, todo: Option<bool>) -> Option<&'static str> {
+    match (unimplemented, todo) {
+        (Some(true), Some(true)) => None, // Show both
+        (Some(true), _) => Some("unimplemented"),
+        (_, Some(true)) => Some("todo"),
+        _ => Some("implemented"),
+    }
+}
+
+/// Commands `bench_mix` makes per symbol.
+const BENCH_MIX_LEN: usize = 5;
+
+/// The lookups an editor or agent typically makes about `symbol`, each named
+/// for the report.
+fn bench_mix(symbol: &Symbol) -> [(&'static str, Command); BENCH_MIX_LEN] {
+    [
+        ("find", Command::Find {
+            name: Some(symbol.name.clone()),
+            path: None,
+            kind: None,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            all: None,
+            recently_changed: None,
+            depth: None,
+            match_mode: None,
+            tag: None,
+            sig: None,
+            cursor: None,
+        }),
+        ("search", Command::Search {
+            query: symbol.name.clone(),
+            kind: None,
+            vis: None,
+            limit: None,
+            all: None,
+            cursor: None,
+        }),
+        ("doc", Command::Doc {
+            path: symbol.path.clone(),
+            include_docs: true,
+            examples: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+        }),
+        ("ls", Command::Ls {
+            path: symbol.path.clone(),
+            expansion: Some(">".to_string()),
+            impl_parents: false,
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            cursor: None,
+        }),
+        ("refs", Command::Refs { path: symbol.path.clone(), limit: None, group_by: None }),
+    ]
+}
+
+/// Nearest-rank percentiles of `samples`, which this sorts.
+fn latency(samples: &mut [Duration]) -> Latency {
+    samples.sort_unstable();
+    let Some(max) = samples.last() else {
+        return Latency::default();
+    };
+    let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+    let percentile = |p: usize| ms(&samples[(samples.len() * p).div_ceil(100).max(1) - 1]);
+    Latency {
+        p50_ms: percentile(50),
+        p90_ms: percentile(90),
+        p99_ms: percentile(99),
+        max_ms: ms(max),
+    }
+}
+
+/// `depth` or `default`, checked against `MAX_GRAPH_DEPTH`.
+fn graph_depth(depth: Option<usize>, default: usize) -> Result<usize, (String, ErrorCode)> {
+    let depth = depth.unwrap_or(default);
+    if depth == 0 || depth > MAX_GRAPH_DEPTH {
+        return Err((
+            format!("depth must be between 1 and {}", MAX_GRAPH_DEPTH),
+            ErrorCode::InvalidArg,
+        ));
+    }
+    Ok(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ct_core::models::*;
+    use ct_db::Database;
+
+    #[tokio::test]
+    async fn test_reader_serves_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let db = Database::open(&db_path).unwrap();
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1").unwrap();
+        db.insert_symbol(&Symbol {
+            symbol_id: "s1".to_string(),
+            crate_id,
+            file_id,
+            path: "app::State".to_string(),
+            name: "State".to_string(),
+            kind: SymbolKind::Struct,
+            visibility: Visibility::Public,
+            signature: "pub struct State".to_string(),
+            docs: None,
+            status: ImplementationStatus::Implemented,
+            span_start: 1,
+            span_end: 3,
+            def_hash: "h".to_string(),
+        })
+        .unwrap();
+        let example = DocExample { lang: "rust".to_string(), code: "let s = State::new();".to_string() };
+        db.insert_example("s1", 0, &example).unwrap();
+        for (line, message) in [(2, "TODO(alice): split"), (3, "TODO: document")] {
+            let marker = Marker { marker: "TODO".to_string(), message: message.to_string(), line, introduced: None };
+            db.insert_marker(file_id, &marker).unwrap();
+        }
+
+        // An open write transaction does not hold up readers
+        db.conn().execute_batch("BEGIN IMMEDIATE").unwrap();
+
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let find = Command::Find {
+            name: Some("State".to_string()),
+            path: None,
+            kind: None,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            all: None,
+            recently_changed: None,
+            depth: None,
+            match_mode: None,
+            tag: None,
+            sig: None,
+            cursor: None,
+        };
+        assert!(QueryReader::serves(&find));
+        assert!(!QueryReader::serves(&Command::Diag));
+
+        let request = Request {
+            cmd: find,
+            request_id: "r1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        let Response::Success(envelope) = reader.handle_request(request).await else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["items"][0]["path"], "app::State");
+        assert_eq!(envelope.index_epoch, Some(0));
+        assert_eq!(envelope.workspace_fingerprint.as_deref(), Some("blake3:test"));
+
+        // A mistyped path suggests the symbol it probably meant
+        let request = Request {
+            cmd: Command::Doc {
+                path: "app::state::State".to_string(),
+                include_docs: false,
+                examples: false,
+                vis: None,
+                unimplemented: None,
+                todo: None,
+            },
+            request_id: "r2".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        let Response::Error(envelope) = reader.handle_request(request).await else {
+            panic!("expected an error")
+        };
+        assert_eq!(envelope.err_code, ErrorCode::NotFound);
+        assert!(!envelope.retryable);
+        assert_eq!(envelope.hint.as_deref(), Some("did you mean `app::State`?"));
+        assert_eq!(envelope.details.unwrap()["candidates"], serde_json::json!(["app::State"]));
+
+        // Examples stored under the indexer's id come back through Doc
+        let doc = Command::Doc {
+            path: "app::State".to_string(),
+            include_docs: false,
+            examples: true,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+        };
+        let Response::Success(envelope) = reader.handle_command(doc).await.unwrap() else { panic!("expected success") };
+        assert_eq!(envelope.data["examples"], serde_json::json!([{"lang": "rust", "code": "let s = State::new();"}]));
+
+        // Locations resolve to their symbol, absolute paths included
+        let absolute = dir.path().join("src/lib.rs").to_string_lossy().into_owned();
+        let owner = reader.handle_command(Command::Owner { file: absolute, line: 2 }).await.unwrap();
+        let Response::Success(envelope) = owner else { panic!("expected success") };
+        let owner: OwnerData = envelope.data_as().unwrap();
+        assert_eq!((owner.file.as_str(), owner.path.as_str()), ("src/lib.rs", "app::State"));
+        let owner = reader.handle_command(Command::Owner { file: r"src\lib.rs".to_string(), line: 2 }).await.unwrap();
+        let Response::Success(envelope) = owner else { panic!("expected success") };
+        assert_eq!(envelope.data_as::<OwnerData>().unwrap().file, "src/lib.rs");
+        let Err(error) = reader.handle_command(Command::Owner { file: "./src/lib.rs".to_string(), line: 9 }).await else {
+            panic!("expected an error")
+        };
+        assert_eq!((error.code, error.hint), (ErrorCode::NotFound, None));
+
+        let located = reader.handle_command(Command::Locate { path: "app::State".to_string() }).await.unwrap();
+        let Response::Success(envelope) = located else { panic!("expected success") };
+        let located: LocateData = envelope.data_as().unwrap();
+        assert_eq!((located.file.as_str(), located.span_start), ("src/lib.rs", 1));
+        assert_eq!(Path::new(&located.absolute_file), dir.path().join("src/lib.rs"));
+        let Err(error) = reader.handle_command(Command::Locate { path: "app::Stat".to_string() }).await else {
+            panic!("expected an error")
+        };
+        assert_eq!(error.code, ErrorCode::NotFound);
+
+        let todo = reader.handle_command(Command::Todo { matching: None, limit: Some(1) }).await.unwrap();
+        let Response::Success(envelope) = todo else { panic!("expected success") };
+        let todo: TodoData = envelope.data_as().unwrap();
+        assert!(todo.truncated);
+        assert_eq!((todo.items[0].path.as_str(), todo.items[0].line), ("app::State", 2));
+        let todo = reader.handle_command(Command::Todo { matching: Some("ALICE".to_string()), limit: None }).await.unwrap();
+        let Response::Success(envelope) = todo else { panic!("expected success") };
+        assert_eq!(envelope.data_as::<TodoData>().unwrap().total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bench_replays_sampled_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let bench = |queries| Command::Bench { queries, warmup: 0, duration: 1 };
+
+        let db = Database::open(&db_path).unwrap();
+        let Err(error) = reader.handle_command(bench(3)).await else { panic!("expected an error") };
+        assert_eq!(error.code, ErrorCode::NotFound);
+
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1").unwrap();
+        db.insert_symbol(&Symbol {
+            symbol_id: "s1".to_string(),
+            crate_id,
+            file_id,
+            path: "app::connect".to_string(),
+            name: "connect".to_string(),
+            kind: SymbolKind::Fn,
+            visibility: Visibility::Public,
+            signature: "pub fn connect()".to_string(),
+            docs: None,
+            status: ImplementationStatus::Implemented,
+            span_start: 1,
+            span_end: 1,
+            def_hash: "h".to_string(),
+        })
+        .unwrap();
+
+        let Response::Success(envelope) = reader.handle_command(bench(3)).await.unwrap() else {
+            panic!("expected success")
+        };
+        let report: BenchData = envelope.data_as().unwrap();
+        assert_eq!(report.queries, 3);
+        assert_eq!(report.errors, 0);
+        assert!(report.samples >= 3 && report.throughput_qps > 0.0);
+        let commands: Vec<&str> = report.commands.iter().map(|c| c.command.as_str()).collect();
+        assert_eq!(commands, ["find", "search", "doc"]);
+        assert!(report.latency.p50_ms <= report.latency.p99_ms && report.latency.p99_ms <= report.latency.max_ms);
+
+        let Err(error) = reader.handle_command(Command::Bench { queries: 3, warmup: 0, duration: 0 }).await else {
+            panic!("expected an error")
+        };
+        assert_eq!(error.code, ErrorCode::InvalidArg);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let latency = latency(&mut samples);
+        assert_eq!((latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms), (50.0, 90.0, 99.0, 100.0));
+        assert_eq!(super::latency(&mut []), Latency::default());
+    }
+
+    #[tokio::test]
+    async fn test_refs_group_by_crate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let db = Database::open(&db_path).unwrap();
+        // `zeta` sorts before `core` by file but after it by crate
+        for (crate_name, file, caller) in [("zeta", "a/main.rs", "zeta::main"), ("core", "b/lib.rs", "core::run")] {
+            let crate_id = db.insert_crate(crate_name, None, "fp").unwrap();
+            let file_id = db.insert_file(crate_id, file, "blake3:1").unwrap();
+            db.insert_symbol(&Symbol {
+                symbol_id: caller.to_string(),
+                crate_id,
+                file_id,
+                path: caller.to_string(),
+                name: caller.rsplit("::").next().unwrap().to_string(),
+                kind: SymbolKind::Fn,
+                visibility: Visibility::Public,
+                signature: format!("pub fn {}()", caller),
+                docs: None,
+                status: ImplementationStatus::Implemented,
+                span_start: 1,
+                span_end: 9,
+                def_hash: "h".to_string(),
+            })
+            .unwrap();
+            let symbol_id = db.conn().last_insert_rowid();
+            for (line, column) in [(3, 5), (7, 12)] {
+                db.insert_reference(&Reference {
+                    id: 0,
+                    symbol_id,
+                    target_path: "core::Config".to_string(),
+                    file_id,
+                    span_start: line,
+                    span_end: line,
+                    span_col: column,
+                })
+                .unwrap();
+            }
+        }
+
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let refs = |group_by| {
+            let request = Request {
+                cmd: Command::Refs { path: "core::Config".to_string(), limit: None, group_by },
+                request_id: "r1".to_string(),
+                protocol_version: ct_protocol::PROTOCOL_VERSION,
+                chunked: false,
+            };
+            let reader = &reader;
+            async move {
+                let Response::Success(envelope) = reader.handle_request(request).await else {
+                    panic!("expected success")
+                };
+                envelope.data_as::<RefsData>().unwrap()
+            }
+        };
+
+        let by_file = refs(None).await;
+        assert_eq!(by_file.total, 4);
+        assert!(by_file.crates.is_empty());
+        let files: Vec<_> = by_file.files.iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(files, vec!["a/main.rs", "b/lib.rs"]);
+        let sites: Vec<_> = by_file.files[0].references.iter().map(|r| (r.line_start, r.column)).collect();
+        assert_eq!(sites, vec![(3, Some(5)), (7, Some(12))]);
+        assert_eq!(refs(Some(RefsGroup::File)).await, by_file);
+
+        let by_crate = refs(Some(RefsGroup::Crate)).await;
+        assert_eq!(by_crate.total, 4);
+        assert!(by_crate.files.is_empty());
+        let crates: Vec<_> = by_crate.crates.iter().map(|c| (c.name.as_str(), c.files[0].file.as_str())).collect();
+        assert_eq!(crates, vec![("core", "b/lib.rs"), ("zeta", "a/main.rs")]);
+        assert_eq!(by_crate.crates[1].files[0].references, by_file.files[0].references);
+    }
+
+    #[tokio::test]
+    async fn test_ls_pages_with_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let db = Database::open(&db_path).unwrap();
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1").unwrap();
+        for name in ["a", "b", "c"] {
+            db.insert_symbol(&Symbol {
+                symbol_id: name.to_string(),
+                crate_id,
+                file_id,
+                path: format!("app::{}", name),
+                name: name.to_string(),
+                kind: SymbolKind::Fn,
+                visibility: Visibility::Public,
+                signature: format!("pub fn {}()", name),
+                docs: None,
+                status: ImplementationStatus::Implemented,
+                span_start: 1,
+                span_end: 1,
+                def_hash: "h".to_string(),
+            })
+            .unwrap();
+        }
+
+        let config = Config { max_list: 2, ..Config::default() };
+        let reader = QueryReader::new(config, &db_path, dir.path().to_path_buf(), "blake3:test");
+        let ls = |cursor: Option<String>| Command::Ls {
+            path: "app".to_string(),
+            expansion: None,
+            impl_parents: false,
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            cursor,
+        };
+        let paths = |env: &ct_protocol::SuccessEnvelope| {
+            env.data["items"].as_array().unwrap().iter().map(|i| i["path"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        let Response::Success(first) = reader.handle_command(ls(None)).await.unwrap() else {
+            panic!("expected success")
+        };
+        assert_eq!(paths(&first), vec!["app::a", "app::b"]);
+        assert!(first.truncated);
+        let cursor = first.next_cursor.unwrap();
+
+        let Response::Success(second) = reader.handle_command(ls(Some(cursor.clone()))).await.unwrap() else {
+            panic!("expected success")
+        };
+        assert_eq!(paths(&second), vec!["app::c"]);
+        assert!(!second.truncated && second.next_cursor.is_none());
+
+        // A cursor only continues the query it came from
+        let other = Command::Search {
+            query: "a".to_string(),
+            kind: None,
+            vis: None,
+            limit: None,
+            all: None,
+            cursor: Some(cursor),
+        };
+        let error = reader.handle_command(other).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArg);
+    }
+
+    #[tokio::test]
+    async fn test_find_and_ls_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let db = Database::open(&db_path).unwrap();
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1").unwrap();
+        for (path, kind) in [
+            ("app::api::Handler", SymbolKind::Struct),
+            ("app::api::handler_get", SymbolKind::Fn),
+            ("app::api::handler_post", SymbolKind::Fn),
+            ("app::api::v1::handler_old", SymbolKind::Fn),
+        ] {
+            db.insert_symbol(&Symbol {
+                symbol_id: path.to_string(),
+                crate_id,
+                file_id,
+                path: path.to_string(),
+                name: path.rsplit("::").next().unwrap().to_string(),
+                kind,
+                visibility: Visibility::Public,
+                signature: String::new(),
+                docs: None,
+                status: ImplementationStatus::Implemented,
+                span_start: 1,
+                span_end: 1,
+                def_hash: "h".to_string(),
+            })
+            .unwrap();
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let history = SymbolHistory { last_commit: "c0ffee".to_string(), last_modified: now, churn: 1 };
+        db.insert_symbol_history("app::api::handler_get", &history).unwrap();
+
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let paths = |command| {
+            let reader = &reader;
+            async move {
+                let Response::Success(env) = reader.handle_command(command).await.unwrap() else {
+                    panic!("expected success")
+                };
+                env.data["items"].as_array().unwrap().iter().map(|i| i["path"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+        let find = |name: &str, kind: Option<&str>| Command::Find {
+            name: Some(name.to_string()),
+            path: None,
+            kind: kind.map(str::to_string),
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            all: None,
+            recently_changed: None,
+            depth: None,
+            match_mode: None,
+            tag: None,
+            sig: None,
+            cursor: None,
+        };
+        let ls = |path: &str, expansion: Option<&str>| Command::Ls {
+            path: path.to_string(),
+            expansion: expansion.map(str::to_string),
+            impl_parents: false,
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            cursor: None,
+        };
+
+        // A name with wildcards is a glob without --match glob
+        assert_eq!(
+            paths(find("handler_*", Some("fn"))).await,
+            vec!["app::api::handler_get", "app::api::handler_post", "app::api::v1::handler_old"]
+        );
+        // and one with `::` a path glob, whose `*` stays in its segment
+        assert_eq!(
+            paths(find("app::api::*", None)).await,
+            vec!["app::api::Handler", "app::api::handler_get", "app::api::handler_post"]
+        );
+        assert_eq!(paths(ls("app::**::handler_{get,old}", None)).await, vec!["app::api::handler_get", "app::api::v1::handler_old"]);
+        
+        let error = reader.handle_command(ls("app::api::*", Some(">"))).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArg);
+        let error = reader.handle_command(find("app::[", None)).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArg);
+        
+        // Path globs take the other filters too, or refuse them
+        let mut recent = find("app::api::*", None);
+        if let Command::Find { recently_changed, .. } = &mut recent {
+            *recently_changed = Some(7);
+        }
+        assert_eq!(paths(recent).await, vec!["app::api::handler_get"]);
+        let mut deep = find("app::**", None);
+        if let Command::Find { depth, .. } = &mut deep {
+            *depth = Some(1);
+        }
+        assert_eq!(reader.handle_command(deep).await.unwrap_err().code, ErrorCode::InvalidArg);
+        
+        let fuzzy = |query: &str, limit| Command::FuzzyFind {
+            query: query.to_string(),
+            kind: None,
+            vis: None,
+            limit,
+            cursor: None,
+        };
+        assert_eq!(paths(fuzzy("hpost", None)).await, vec!["app::api::handler_post"]);
+        assert_eq!(paths(fuzzy("apihget", Some(1))).await, vec!["app::api::handler_get"]);
+        let Response::Success(env) = reader.handle_command(fuzzy("handler", Some(2))).await.unwrap() else {
+            panic!("expected success")
+        };
+        let scores: Vec<f64> = env.data["items"].as_array().unwrap().iter().map(|i| i["score"].as_f64().unwrap()).collect();
+        assert!(scores[0] >= scores[1]);
+        assert!(env.next_cursor.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_export_source_uses_snippet_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let content = "// state\npub struct State {\n    id: u32,\n}\n";
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), content).unwrap();
+        
+        let db = Database::open(&db_path).unwrap();
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let digest = compute_file_digest(content.as_bytes());
+        let file_id = db.insert_file(crate_id, "src/lib.rs", &digest).unwrap();
+        let symbol = Symbol {
+            symbol_id: "s1".to_string(),
+            crate_id,
+            file_id,
+            path: "app::State".to_string(),
+            name: "State".to_string(),
+            kind: SymbolKind::Struct,
+            visibility: Visibility::Public,
+            signature: "pub struct State".to_string(),
+            docs: None,
+            status: ImplementationStatus::Implemented,
+            span_start: 2,
+            span_end: 4,
+            def_hash: "h".to_string(),
+        };
+        db.insert_symbol(&symbol).unwrap();
+        
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let export = || Command::Export {
+            paths: vec!["app::State".to_string()],
+            bundle: true,
+            expansion: None,
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            impl_parents: false,
+            with_source: true,
+        };
+        let source = |response: Response| {
+            let Response::Success(envelope) = response else { panic!("expected success") };
+            envelope.data["bundle"]["symbols"][0].clone()
+        };
+        
+        let root = source(reader.handle_command(export()).await.unwrap());
+        assert_eq!(root["source"], "pub struct State {\n    id: u32,\n}\n");
+        assert!(root.get("source_stale").is_none());
+        assert!(queries::get_snippet(db.conn(), &digest, 2, 4).unwrap().is_some());
+        
+        // Served from the cache once sliced, even if the file is gone
+        std::fs::remove_file(dir.path().join("src/lib.rs")).unwrap();
+        let root = source(reader.handle_command(export()).await.unwrap());
+        assert_eq!(root["source"], "pub struct State {\n    id: u32,\n}\n");
+        
+        // Metrics count the cache hit and the bytes and steps of the request alone
+        let request = Request {
+            cmd: export(),
+            request_id: "r1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        let Response::Success(envelope) = reader.with_cancel(CancelToken::new()).handle_request(request).await else {
+            panic!("expected success")
+        };
+        let metrics = envelope.metrics.unwrap();
+        assert_eq!(metrics.cache, Some(CacheMetrics { hits: 1, misses: 0 }));
+        assert_eq!(metrics.bytes, serde_json::to_string(&envelope.data).unwrap().len());
+        assert!(metrics.db_steps.is_some());
+        
+        // A file edited since indexing is flagged and not cached
+        std::fs::write(dir.path().join("src/lib.rs"), format!("\n{}", content)).unwrap();
+        db.conn().execute("DELETE FROM source_snippets", []).unwrap();
+        let root = source(reader.handle_command(export()).await.unwrap());
+        assert_eq!(root["source_stale"], true);
+        assert!(queries::get_snippet(db.conn(), &digest, 2, 4).unwrap().is_none());
+        
+        // A reindex holding the write lock leaves the span uncached without
+        // holding up the answer
+        std::fs::write(dir.path().join("src/lib.rs"), content).unwrap();
+        let mut indexer = Database::open(&db_path).unwrap();
+        indexer.begin_transaction().unwrap();
+        let start = Instant::now();
+        let root = source(reader.handle_command(export()).await.unwrap());
+        assert!(start.elapsed() < ct_db::BUSY_TIMEOUT);
+        assert!(root.get("source_stale").is_none());
+        indexer.commit_transaction().unwrap();
+        assert!(queries::get_snippet(db.conn(), &digest, 2, 4).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_merges_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("symbols.sqlite");
+        let db = Database::open(&db_path).unwrap();
+        let crate_id = db.insert_crate("app", None, "fp").unwrap();
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1").unwrap();
+        for path in ["app::net", "app::net::Client", "app::net::Client::connect", "app::net::serve"] {
+            db.insert_symbol(&Symbol {
+                symbol_id: path.to_string(),
+                crate_id,
+                file_id,
+                path: path.to_string(),
+                name: path.rsplit("::").next().unwrap().to_string(),
+                kind: SymbolKind::Fn,
+                visibility: Visibility::Public,
+                signature: String::new(),
+                docs: None,
+                status: ImplementationStatus::Implemented,
+                span_start: 1,
+                span_end: 1,
+                def_hash: "h".to_string(),
+            })
+            .unwrap();
+        }
+        
+        let reader = QueryReader::new(Config::default(), &db_path, dir.path().to_path_buf(), "blake3:test");
+        let export = |paths: &[&str]| Command::Export {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            bundle: true,
+            expansion: Some(">>".to_string()),
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            impl_parents: false,
+            with_source: false,
+        };
+        
+        let Response::Success(envelope) = reader.handle_command(export(&["app::net::Client", "app::net", "app::net"])).await.unwrap() else {
+            panic!("expected success")
+        };
+        let bundle: ExportBundle = serde_json::from_value(envelope.data["bundle"].clone()).unwrap();
+        let paths = |symbols: &[ExportSymbol]| symbols.iter().map(|s| s.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&bundle.symbols), ["app::net::Client", "app::net"]);
+        // Shared descendants once, and never a root again; shallowest first
+        assert_eq!(paths(&bundle.children), ["app::net::serve", "app::net::Client::connect"]);
+        
+        let error = reader.handle_command(export(&["app::net", "app::nett"])).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::NotFound);
+        
+        // Building stops as soon as the bundle outgrows the memory budget
+        let reader = reader.with_memory(MemoryBudget::new(Some(1)));
+        let error = reader.handle_command(export(&["app::net"])).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArg);
+        assert!(error.hint.unwrap().contains("max_mem_mb"));
+    }
+}
@@ -1,43 +1,237 @@
+use ct_core::compute_workspace_id;
 use ct_core::config::{Config, Transport};
-use ct_protocol::{Request, Response, ErrorCode, deserialize_message, serialize_message};
+use ct_core::memory::{MemoryGauge, MemoryHold};
+use ct_protocol::{Command, Event, Frame, PageCursor, Request, Response, ErrorCode, deserialize_message, serialize_message, chunk_lines, RESPONSE_CHUNK_BYTES};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, UnixListener};
-use tokio::sync::{mpsc, Mutex};
-use tracing::{debug, error, info};
-use crate::state::DaemonState;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{broadcast, mpsc, Mutex, Notify, RwLock, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, error, info, warn};
+use crate::jobs::{run_jobs, JobSpec, ReindexQueue};
+use crate::limits::{request_cost, Limits};
+use crate::memory::MemoryBudget;
+use crate::reader::QueryReader;
+use crate::state::{finish_response, DaemonState, HandlerError, ReindexControl};
+use ct_indexer::IndexStats;
+use ct_indexer::cancel::CancelToken;
+use ct_indexer::watcher::WatcherHandle;
+
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::NamedPipeServer;
 
+mod http;
 #[cfg(windows)]
-use tokio::net::windows::named_pipe::{ServerOptions, NamedPipeServer};
+mod pipe;
+
+/// Most commands one Batch may carry.
+const MAX_BATCH: usize = 100;
+
+/// Most requests one connection may have running at once; past this the
+/// daemon reads no further requests from it until one finishes.
+const MAX_PIPELINED: usize = 32;
+
+/// A line queued for a connection's writer; responses count against the
+/// memory budget until written.
+type Outgoing = (String, Option<MemoryHold>);
+
+/// Lines a connection's writer may have queued; past this, responses and
+/// events wait for the client to read, and no further requests are read.
+const OUTGOING_LINES: usize = 64;
+
+/// How long shutdown waits for in-flight requests before cancelling them.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
 
 pub struct ServerHandle {
     shutdown_tx: mpsc::Sender<()>,
+    reindex: ReindexControl,
+    jobs: ReindexQueue,
+    inflight: Inflight,
+    state: Arc<RwLock<DaemonState>>,
+    /// Notified when a client sends `Shutdown`
+    shutdown_requested: Arc<Notify>,
+    socket_path: Option<PathBuf>,
+    activity: Activity,
+    idle_shutdown: Option<Duration>,
+    /// Stops the HTTP server, if one runs
+    http_stop: Arc<Notify>,
+    dispatch: Dispatch,
+}
+
+/// Sends requests to this daemon without a connection, for front ends on
+/// other channels such as MCP over stdio.
+#[derive(Clone)]
+pub struct LocalClient {
+    dispatch: Dispatch,
+}
+
+impl LocalClient {
+    pub async fn send(&self, request: Request) -> Response {
+        self.dispatch.handle(request).await
+    }
+
+    /// Serve `stream` as if a client had connected over it.
+    pub async fn serve_connection<S>(&self, stream: S) -> anyhow::Result<()>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        handle_connection(stream, self.dispatch.clone()).await
+    }
 }
 
 impl ServerHandle {
+    pub fn local_client(&self) -> LocalClient {
+        LocalClient { dispatch: self.dispatch.clone() }
+    }
+
+    /// Resolves once a client asks the daemon to stop.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown_requested.notified().await
+    }
+
+    /// Resolves once the daemon has been idle for `idle_shutdown_minutes`:
+    /// no client connected, no file changed and no reindex queued. Never,
+    /// if that is disabled.
+    pub async fn idle(&self) {
+        let Some(limit) = self.idle_shutdown else {
+            return std::future::pending().await;
+        };
+        loop {
+            let idle_for = self.activity.idle_for().filter(|_| self.jobs.is_idle());
+            match idle_for {
+                Some(idle_for) if idle_for >= limit => return,
+                Some(idle_for) => tokio::time::sleep(limit - idle_for).await,
+                None => tokio::time::sleep(limit).await,
+            }
+        }
+    }
+
+    /// Stop accepting connections, abort any reindex and drop queued ones, give in-flight
+    /// requests `SHUTDOWN_GRACE` to finish before cancelling the rest, then
+    /// checkpoint the WAL and remove the socket file.
     pub async fn shutdown(self) -> anyhow::Result<()> {
-        self.shutdown_tx.send(()).await?;
+        self.jobs.close();
+        self.reindex.cancel();
+        // The accept loop may have stopped on its own already
+        let _ = self.shutdown_tx.send(()).await;
+        self.http_stop.notify_one();
+        
+        let waited = std::time::Instant::now();
+        while !self.inflight.is_empty() && waited.elapsed() < SHUTDOWN_GRACE {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        if !self.inflight.is_empty() {
+            info!("Cancelling requests still running after {:?}", SHUTDOWN_GRACE);
+            self.inflight.cancel_all();
+        }
+        
+        // Once the lock is ours no reindex is writing
+        let state = self.state.write().await;
+        match state.checkpoint_wal() {
+            Ok((_, true)) => warn!("WAL checkpoint incomplete: the database is still being read"),
+            Ok(_) => {}
+            Err(e) => warn!("Could not checkpoint the WAL: {}", e),
+        }
+        
+        if let Some(socket_path) = &self.socket_path {
+            match std::fs::remove_file(socket_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Could not remove socket {}: {}", socket_path.display(), e),
+            }
+        }
         Ok(())
     }
 }
 
 pub async fn start_server(
     config: Config,
+    workspace_root: PathBuf,
     workspace_fingerprint: String,
+    initial_stats: Option<IndexStats>,
+    mut file_changes: mpsc::Receiver<Vec<PathBuf>>,
+    watcher: &WatcherHandle,
 ) -> anyhow::Result<ServerHandle> {
     let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let workspace_id = compute_workspace_id(&workspace_root);
     
-    let state = Arc::new(Mutex::new(DaemonState::new(
+    let state = DaemonState::new(
         config.clone(),
+        workspace_root,
         workspace_fingerprint.clone(),
-    )));
+        initial_stats,
+    )
+    .with_watcher_stats(watcher.stats())
+    .with_tracked_inputs(watcher.inputs());
+    let reader = state.reader();
+    let events = state.events();
+    let jobs = state.jobs();
+    let memory = state.memory();
+    // SQLite recycles its page cache sooner rather than outgrow the budget
+    ct_db::set_soft_heap_limit(memory.limit().unwrap_or(0));
+    let state = Arc::new(RwLock::new(state));
+    
+    let reindex = ReindexControl::default();
+    tokio::spawn(run_jobs(jobs.clone(), state.clone(), reindex.clone()));
+    let inflight = Inflight::default();
+    let activity = Activity::default();
+    let shutdown_requested = Arc::new(Notify::new());
+    let dispatch = Dispatch {
+        state: state.clone(),
+        reader,
+        reindex: reindex.clone(),
+        jobs: jobs.clone(),
+        memory,
+        events,
+        inflight: inflight.clone(),
+        activity: activity.clone(),
+        limits: Limits::new(&config),
+        request_timeout: config.request_timeout(),
+        shutdown_requested: shutdown_requested.clone(),
+    };
+    
+    // Apply watcher batches; queued requests and reindexes take turns on the lock
+    let watch_state = state.clone();
+    let watch_reindex = reindex.clone();
+    let watch_activity = activity.clone();
+    tokio::spawn(async move {
+        while let Some(mut files) = file_changes.recv().await {
+            watch_activity.touch();
+            // A checkout spans several debounce windows; judge it as one batch
+            while let Ok(more) = file_changes.try_recv() {
+                files.extend(more);
+            }
+            files.sort();
+            files.dedup();
+            let cancel = watch_reindex.current();
+            watch_state.write().await.handle_file_changes(files, cancel).await;
+        }
+    });
+    
+    let local_dispatch = dispatch.clone();
+    let http_stop = Arc::new(Notify::new());
+    if let Some(addr) = &config.http_addr {
+        let listener = TcpListener::bind(addr).await?;
+        info!("HTTP API listening on http://{}", addr);
+        let token = std::env::var(http::TOKEN_ENV).ok();
+        tokio::spawn(http::serve(listener, dispatch.clone(), token, http_stop.clone()));
+    }
     
     let transport = config.get_effective_transport();
+    // Removed again on shutdown
+    let socket_path = (cfg!(unix) && transport == Transport::Unix)
+        .then(|| PathBuf::from(config.get_socket_path(&workspace_id)));
     
     match transport {
         #[cfg(unix)]
         Transport::Unix => {
-            let socket_path = config.get_socket_path(&workspace_fingerprint);
+            let socket_path = config.get_socket_path(&workspace_id);
             
             // Remove existing socket if it exists
             if std::path::Path::new(&socket_path).exists() {
@@ -48,17 +242,20 @@ pub async fn start_server(
             info!("IPC server listening on Unix socket: {}", socket_path);
             
             tokio::spawn(async move {
-                unix_server_loop(listener, state, shutdown_rx).await;
+                unix_server_loop(listener, dispatch, shutdown_rx).await;
             });
         }
         
         #[cfg(windows)]
         Transport::Pipe => {
-            let pipe_name = config.get_pipe_name(&workspace_fingerprint);
+            let pipe_name = config.get_pipe_name(&workspace_id);
+            let security = pipe::PipeSecurity::new()?;
+            let server = security.create(&pipe_name, true)
+                .map_err(|e| anyhow::anyhow!("Could not create named pipe {} (held by another process?): {}", pipe_name, e))?;
             info!("IPC server listening on named pipe: {}", pipe_name);
             
             tokio::spawn(async move {
-                pipe_server_loop(pipe_name, state, shutdown_rx).await;
+                pipe_server_loop(server, pipe_name, security, dispatch, shutdown_rx).await;
             });
         }
         
@@ -67,30 +264,47 @@ pub async fn start_server(
             info!("IPC server listening on TCP: {}", config.tcp_addr);
             
             tokio::spawn(async move {
-                tcp_server_loop(listener, state, shutdown_rx).await;
+                tcp_server_loop(listener, dispatch, shutdown_rx).await;
             });
         }
         
+        Transport::Stdio => {
+            info!("Serving requests over stdio only");
+        }
+        
         _ => {
             return Err(anyhow::anyhow!("Unsupported transport: {:?}", transport));
         }
     }
     
-    Ok(ServerHandle { shutdown_tx })
+    let idle_shutdown = config.idle_shutdown();
+    Ok(ServerHandle {
+        shutdown_tx,
+        reindex,
+        jobs,
+        inflight,
+        state,
+        shutdown_requested,
+        socket_path,
+        activity,
+        idle_shutdown,
+        http_stop,
+        dispatch: local_dispatch,
+    })
 }
 
 #[cfg(unix)]
 async fn unix_server_loop(
     listener: UnixListener,
-    state: Arc<Mutex<DaemonState>>,
+    dispatch: Dispatch,
     mut shutdown_rx: mpsc::Receiver<()>,
 ) {
     loop {
         tokio::select! {
             Ok((stream, _)) = listener.accept() => {
-                let state = state.clone();
+                let dispatch = dispatch.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, state).await {
+                    if let Err(e) = handle_connection(stream, dispatch).await {
                         error!("Error handling connection: {}", e);
                     }
                 });
@@ -103,54 +317,59 @@ async fn unix_server_loop(
     }
 }
 
+/// Hand each connected instance of the pipe to a connection task, having
+/// created the next one first so clients always find an instance to open.
 #[cfg(windows)]
 async fn pipe_server_loop(
+    mut server: NamedPipeServer,
     pipe_name: String,
-    state: Arc<Mutex<DaemonState>>,
+    security: pipe::PipeSecurity,
+    dispatch: Dispatch,
     mut shutdown_rx: mpsc::Receiver<()>,
 ) {
     loop {
-        let server = match ServerOptions::new()
-            .first_pipe_instance(false)
-            .create(&pipe_name)
-        {
-            Ok(s) => s,
-            Err(e) => {
-                error!("Failed to create named pipe: {}", e);
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                continue;
-            }
-        };
-        
-        tokio::select! {
-            _ = server.connect() => {
-                let state = state.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(server, state).await {
-                        error!("Error handling connection: {}", e);
-                    }
-                });
-            }
+        let connected = tokio::select! {
+            connected = server.connect() => connected,
             _ = shutdown_rx.recv() => {
                 info!("Pipe server shutting down");
                 break;
             }
+        };
+        let next = loop {
+            match security.create(&pipe_name, false) {
+                Ok(next) => break next,
+                Err(e) => {
+                    error!("Failed to create named pipe: {}", e);
+                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                }
+            }
+        };
+        let client = std::mem::replace(&mut server, next);
+        if let Err(e) = connected {
+            error!("Failed to accept a pipe client: {}", e);
+            continue;
         }
+        let dispatch = dispatch.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(client, dispatch).await {
+                error!("Error handling connection: {}", e);
+            }
+        });
     }
 }
 
 async fn tcp_server_loop(
     listener: TcpListener,
-    state: Arc<Mutex<DaemonState>>,
+    dispatch: Dispatch,
     mut shutdown_rx: mpsc::Receiver<()>,
 ) {
     loop {
         tokio::select! {
             Ok((stream, addr)) = listener.accept() => {
                 debug!("New TCP connection from: {}", addr);
-                let state = state.clone();
+                let dispatch = dispatch.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, state).await {
+                    if let Err(e) = handle_connection(stream, dispatch).await {
                         error!("Error handling connection: {}", e);
                     }
                 });
@@ -163,23 +382,472 @@ async fn tcp_server_loop(
     }
 }
 
+/// Routes each request to the read pool or the daemon state. Only commands
+/// changing the state take its lock exclusively.
+#[derive(Clone)]
+struct Dispatch {
+    state: Arc<RwLock<DaemonState>>,
+    reader: QueryReader,
+    reindex: ReindexControl,
+    jobs: ReindexQueue,
+    memory: MemoryBudget,
+    events: broadcast::Sender<Event>,
+    inflight: Inflight,
+    activity: Activity,
+    limits: Limits,
+    /// How long a query may run or wait for the state lock, if limited
+    request_timeout: Option<Duration>,
+    shutdown_requested: Arc<Notify>,
+}
+
+impl Dispatch {
+    async fn handle(&self, request: Request) -> Response {
+        let response = match request.cmd {
+            Command::Batch { requests } => self.handle_batch(request.request_id, requests).await,
+            _ => self.handle_one(request).await,
+        };
+        self.within_budget(response)
+    }
+    
+    /// Answer each command in turn, as if sent alone; a failing command
+    /// gets an error envelope without stopping the rest.
+    async fn handle_batch(&self, request_id: String, requests: Vec<Command>) -> Response {
+        let start = std::time::Instant::now();
+        if requests.len() > MAX_BATCH {
+            return Response::error(
+                request_id,
+                format!("Batch of {} commands exceeds the limit of {}", requests.len(), MAX_BATCH),
+                ErrorCode::InvalidArg,
+            );
+        }
+        
+        let mut responses = Vec::with_capacity(requests.len());
+        for (i, cmd) in requests.into_iter().enumerate() {
+            let request = Request {
+                cmd,
+                request_id: format!("{}.{}", request_id, i),
+                protocol_version: ct_protocol::PROTOCOL_VERSION,
+                chunked: false,
+            };
+            responses.push(self.handle_one(request).await);
+        }
+        
+        let data = serde_json::json!({ "responses": responses });
+        let response = Ok(Response::success(request_id.clone(), data));
+        finish_response(request_id, start, response, self.reader.index_epoch(), &self.reader)
+    }
+    
+    async fn handle_one(&self, request: Request) -> Response {
+        match request.cmd {
+            Command::Batch { .. } => {
+                return Response::error(request.request_id, "Batches cannot be nested".to_string(), ErrorCode::InvalidArg);
+            }
+            Command::Events { .. } | Command::Subscribe { .. } => {
+                return Response::error(request.request_id, "Events and Subscribe must be sent on their own".to_string(), ErrorCode::InvalidArg);
+            }
+            _ => {}
+        }
+        
+        // A cursor carries the query it continues; run that query from it
+        let request = match request.cmd {
+            Command::Continue { cursor } => match PageCursor::decode(&cursor) {
+                Ok(page) => Request { cmd: page.cmd.with_cursor(Some(cursor)), ..request },
+                Err(e) => return Response::error(request.request_id, e.to_string(), ErrorCode::InvalidArg),
+            },
+            _ => request,
+        };
+        
+        if let Command::Shutdown = request.cmd {
+            info!("Shutdown requested by a client");
+            self.shutdown_requested.notify_one();
+            return Response::success(request.request_id, serde_json::json!({ "shutting_down": true }));
+        }
+        
+        if let Command::Cancel { request_id } = &request.cmd {
+            let cancelled = self.inflight.cancel(request_id);
+            let data = serde_json::json!({ "request_id": request_id, "cancelled": cancelled });
+            return Response::success(request.request_id, data);
+        }
+        
+        if let Command::Progress = request.cmd {
+            let data = serde_json::json!(self.jobs.progress());
+            return Response::success(request.request_id, data);
+        }
+        
+        if let Command::Reindex { .. } = request.cmd {
+            return self.handle_reindex(request).await;
+        }
+        
+        // Queries run alongside a reindex holding the state lock
+        if QueryReader::serves(&request.cmd) {
+            // Over budget, give back cached pages before taking on more
+            if self.memory.exceeded_by(0) {
+                let closed = self.reader.shed_caches();
+                debug!("Over the memory budget, closed {} idle connections", closed);
+            }
+            let cancel = CancelToken::new();
+            let _inflight = self.inflight.register(&request.request_id, cancel.clone());
+            // A benchmark runs for as long as it was asked to
+            let timeout = self.request_timeout.filter(|_| !matches!(request.cmd, Command::Bench { .. }));
+            let (start, deadline) = (std::time::Instant::now(), Deadline::start(timeout, cancel.clone()));
+            let request_id = request.request_id.clone();
+            let response = self.reader.with_cancel(cancel).handle_request(request).await;
+            return match response {
+                Response::Error(e) if e.err_code == ErrorCode::Cancelled && deadline.expired() => {
+                    self.timed_out(request_id, start, "Query")
+                }
+                response => response,
+            };
+        }
+        
+        let start = std::time::Instant::now();
+        if DaemonState::reads_only(&request.cmd) {
+            return match self.within_timeout(self.state.read()).await {
+                Some(state) => state.handle_query(request).await,
+                None => self.timed_out(request.request_id, start, "Waiting for a reindex"),
+            };
+        }
+        
+        // Cancelling the request stops its catch-up without touching the
+        // token later watcher batches share
+        let cancel = match request.cmd {
+            Command::WatcherControl { .. } => self.reindex.current().child(),
+            _ => CancelToken::new(),
+        };
+        
+        let _inflight = self.inflight.register(&request.request_id, cancel.clone());
+        let mut state = match request.cmd {
+            // Long-running by design and cancellable, so wait as long as it takes
+            Command::WatcherControl { .. } => self.state.write().await,
+            _ => match self.within_timeout(self.state.write()).await {
+                Some(state) => state,
+                None => return self.timed_out(request.request_id, start, "Waiting for a reindex"),
+            },
+        };
+        state.handle_request(request, cancel).await
+    }
+    
+    /// Queue a reindex, answering at once or, with `wait`, once it has run.
+    /// A job is cancelled once every request waiting on it is.
+    async fn handle_reindex(&self, request: Request) -> Response {
+        let start = std::time::Instant::now();
+        let Command::Reindex { features, target, module, struct_name, include_derives, package, wait } = request.cmd else {
+            unreachable!("only called for Reindex")
+        };
+        let spec = JobSpec { features, target, module, struct_name, include_derives, package };
+        let result = match self.jobs.enqueue(spec) {
+            None => Err(("The daemon is shutting down".to_string(), ErrorCode::Cancelled).into()),
+            Some((id, cancel)) if wait => {
+                let _inflight = self.inflight.register(&request.request_id, cancel);
+                self.jobs.wait(id).await
+            }
+            Some((id, _)) => Ok(Response::success(
+                "".to_string(),
+                serde_json::json!({ "status": "queued", "job_id": id }),
+            )),
+        };
+        finish_response(request.request_id, start, result, self.reader.index_epoch(), &self.reader)
+    }
+    
+    /// Refuse a response that would take the daemon past its memory budget
+    /// even after shedding caches. Handlers building large responses, such
+    /// as exports, check the budget as they go and stop early.
+    fn within_budget(&self, response: Response) -> Response {
+        let Response::Success(envelope) = &response else {
+            return response;
+        };
+        let bytes = envelope.metrics.as_ref().map_or(0, |metrics| metrics.bytes);
+        if !self.memory.exceeded_by(bytes) {
+            return response;
+        }
+        self.reader.shed_caches();
+        match self.memory.refusal(bytes) {
+            Some(e) => Response::error(envelope.request_id.clone(), e.message, e.code).with_hint(e.hint),
+            None => response,
+        }
+    }
+    
+    /// `future`'s output, unless the request timeout passes first.
+    async fn within_timeout<F: std::future::Future>(&self, future: F) -> Option<F::Output> {
+        match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+    
+    /// The `Timeout` error for a request that spent the request timeout on `what`.
+    fn timed_out(&self, request_id: String, start: std::time::Instant, what: &str) -> Response {
+        let timeout = self.request_timeout.unwrap_or_default();
+        let error = HandlerError::from((
+            format!("{} took longer than the {} ms request timeout", what, timeout.as_millis()),
+            ErrorCode::Timeout,
+        ))
+        .with_hint("Retry later, narrow the query, or raise request_timeout_ms in ct.toml");
+        finish_response(request_id, start, Err(error), self.reader.index_epoch(), &self.reader)
+    }
+}
+
+/// Cancels a request's token once its time is up, unless dropped first.
+struct Deadline {
+    timer: Option<JoinHandle<()>>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    fn start(timeout: Option<Duration>, cancel: CancelToken) -> Self {
+        let expired = Arc::new(AtomicBool::new(false));
+        let timer = timeout.map(|timeout| {
+            let expired = expired.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                expired.store(true, Ordering::Relaxed);
+                cancel.cancel();
+            })
+        });
+        Self { timer, expired }
+    }
+    
+    fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        if let Some(timer) = &self.timer {
+            timer.abort();
+        }
+    }
+}
+
+/// Cancel tokens of the requests being handled, so a `Cancel` from any
+/// connection can stop one and shutdown can wait for or stop them all.
+#[derive(Clone, Default)]
+struct Inflight(Arc<std::sync::Mutex<InflightRequests>>);
+
+#[derive(Default)]
+struct InflightRequests {
+    next_key: u64,
+    /// Request id and cancel token, by registration
+    requests: HashMap<u64, (String, CancelToken)>,
+}
+
+impl Inflight {
+    /// Track the request until the returned guard drops.
+    fn register(&self, request_id: &str, cancel: CancelToken) -> InflightGuard {
+        let mut inflight = self.lock();
+        let key = inflight.next_key;
+        inflight.next_key += 1;
+        inflight.requests.insert(key, (request_id.to_string(), cancel));
+        InflightGuard { inflight: self.clone(), key }
+    }
+
+    /// Whether `request_id` was still running and is now cancelled; requests
+    /// without an id cannot be cancelled this way.
+    fn cancel(&self, request_id: &str) -> bool {
+        if request_id.is_empty() {
+            return false;
+        }
+        let inflight = self.lock();
+        let mut cancelled = false;
+        for (id, cancel) in inflight.requests.values() {
+            if id == request_id {
+                cancel.cancel();
+                cancelled = true;
+            }
+        }
+        cancelled
+    }
+
+    fn cancel_all(&self) {
+        for (_, cancel) in self.lock().requests.values() {
+            cancel.cancel();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lock().requests.is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InflightRequests> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct InflightGuard {
+    inflight: Inflight,
+    key: u64,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.inflight.lock().requests.remove(&self.key);
+    }
+}
+
+/// When the daemon last had something to do, for `idle_shutdown_minutes`.
+/// Clones share the same record.
+#[derive(Clone)]
+struct Activity {
+    since: std::time::Instant,
+    /// Milliseconds after `since` of the last connection or file change
+    last: Arc<AtomicU64>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            since: std::time::Instant::now(),
+            last: Arc::default(),
+            connections: Arc::default(),
+        }
+    }
+}
+
+impl Activity {
+    fn touch(&self) {
+        self.last.store(self.since.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Count a connection as activity until the guard is dropped.
+    fn connect(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+        ConnectionGuard { activity: self.clone() }
+    }
+
+    /// How long nothing has happened; `None` while a client is connected.
+    fn idle_for(&self) -> Option<Duration> {
+        if self.connections.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        Some(self.since.elapsed().saturating_sub(last))
+    }
+}
+
+struct ConnectionGuard {
+    activity: Activity,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.activity.touch();
+        self.activity.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 async fn handle_connection<S>(
     stream: S,
-    state: Arc<Mutex<DaemonState>>,
+    dispatch: Dispatch,
 ) -> anyhow::Result<()>
 where
-    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
+    let _slot = match dispatch.limits.admit() {
+        Ok(slot) => slot,
+        Err(max) => return turn_away(stream, max).await,
+    };
+    let _connected = dispatch.activity.connect();
     let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader);
+    
+    // Responses and pushed events share the connection through one writer
+    let (out, mut lines) = mpsc::channel::<Outgoing>(OUTGOING_LINES);
+    let writer_task = tokio::spawn(async move {
+        while let Some((line, _held)) = lines.recv().await {
+            writer.write_all(format!("{}\n", line).as_bytes()).await?;
+            if lines.is_empty() {
+                writer.flush().await?;
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    });
+    
+    let mut forwarders = Forwarders::default();
+    let result = serve_requests(&mut reader, &dispatch, &out, &mut forwarders).await;
+    forwarders.abort();
+    drop(out);
+    writer_task.await??;
+    result
+}
+
+/// Answer a connection past `max_connections` with `Busy` and close it.
+/// The error answers the client's first request, so it reads it as the
+/// response it is waiting for.
+async fn turn_away<S>(stream: S, max: usize) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    warn!("Turning a client away, {} already connected", max);
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), BufReader::new(reader).read_line(&mut line)).await;
+    let request_id = deserialize_message::<Request>(line.trim())
+        .map(|request| request.request_id)
+        .unwrap_or_else(|_| "unknown".to_string());
+    let response = too_many_clients(request_id, max);
+    writer.write_all(format!("{}\n", serialize_message(&response)?).as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// The `Busy` error for a client past `max_connections`.
+fn too_many_clients(request_id: String, max: usize) -> Response {
+    Response::error(
+        request_id,
+        format!("The daemon is already serving {} clients", max),
+        ErrorCode::Busy,
+    )
+    .with_hint(Some("Retry shortly, or raise max_connections in ct.toml".to_string()))
+}
+
+/// The `Busy` error for a request over the connection's rate limit.
+fn rate_limited(request_id: String, retry_after: Duration) -> Response {
+    let retry_after_ms = retry_after.as_millis().max(1) as u64;
+    Response::error(
+        request_id,
+        format!("Too many requests; retry in {} ms", retry_after_ms),
+        ErrorCode::Busy,
+    )
+    .with_hint(Some("Send fewer requests, batch them, or raise rate_limit_per_sec in ct.toml".to_string()))
+    .with_details(Some(serde_json::json!({ "retry_after_ms": retry_after_ms })))
+}
+
+async fn serve_requests<R>(
+    reader: &mut BufReader<R>,
+    dispatch: &Dispatch,
+    out: &mpsc::Sender<Outgoing>,
+    forwarders: &mut Forwarders,
+) -> anyhow::Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let send = |line: String| send_line(dispatch, out, line);
+    // Dropped on return, aborting whatever is still running
+    let mut responding = JoinSet::new();
+    // Held while a response is written, so the frames of a chunked one
+    // are not interleaved with other responses
+    let replying = Arc::new(Mutex::new(()));
+    let mut limiter = dispatch.limits.rate_limiter();
+    let pipelined = Arc::new(Semaphore::new(MAX_PIPELINED));
     let mut line = String::new();
     
     loop {
+        // Wait for a free slot before reading on, so the client backs off
+        let slot = pipelined.clone().acquire_owned().await?;
+        while responding.try_join_next().is_some() {}
         line.clear();
         let n = reader.read_line(&mut line).await?;
         
         if n == 0 {
-            // Client disconnected
+            // The client has sent its last request; answer those still
+            // running unless it stops reading first
+            tokio::select! {
+                _ = async { while responding.join_next().await.is_some() {} } => {}
+                _ = out.closed() => {}
+            }
             break;
         }
         
@@ -192,24 +860,592 @@ where
                     format!("Invalid request: {}", e),
                     ErrorCode::ProtocolError,
                 );
-                let msg = serialize_message(&response)?;
-                writer.write_all(format!("{}\n", msg).as_bytes()).await?;
-                writer.flush().await?;
+                send(serialize_message(&response)?).await?;
                 continue;
             }
         };
         
         debug!("Received request: {:?}", request.cmd);
         
-        let response = {
-            let mut state = state.lock().await;
-            state.handle_request(request).await
-        };
+        let cost = request_cost(&request.cmd);
+        if let Some(Err(retry_after)) = limiter.as_mut().map(|limiter| limiter.acquire(cost)) {
+            send(serialize_message(&rate_limited(request.request_id, retry_after))?).await?;
+            continue;
+        }
+        
+        match request.cmd {
+            Command::Events { enabled } => {
+                // Symbol changes only go to subscriptions, filtered by prefix
+                let forwarder = enabled.then(|| {
+                    forward_events(dispatch.events.subscribe(), out.clone(), dispatch.memory.responses.clone(), |event| {
+                        (!matches!(event, Event::SymbolsChanged { .. })).then_some(event)
+                    })
+                });
+                Forwarders::replace(&mut forwarders.events, forwarder);
+                let response = Response::success(request.request_id, serde_json::json!({ "events": enabled }));
+                send(serialize_message(&response)?).await?;
+                continue;
+            }
+            Command::Subscribe { path_prefix } => {
+                let prefix = path_prefix.clone();
+                let forwarder = forward_events(dispatch.events.subscribe(), out.clone(), dispatch.memory.responses.clone(), move |event| {
+                    let Event::SymbolsChanged { epoch, mut changes, truncated } = event else {
+                        return None;
+                    };
+                    changes.retain(|change| under_prefix(&change.path, &prefix));
+                    (!changes.is_empty()).then_some(Event::SymbolsChanged { epoch, changes, truncated })
+                });
+                Forwarders::replace(&mut forwarders.subscription, Some(forwarder));
+                let response = Response::success(request.request_id, serde_json::json!({ "subscribed": path_prefix }));
+                send(serialize_message(&response)?).await?;
+                continue;
+            }
+            _ => {}
+        }
         
-        let msg = serialize_message(&response)?;
-        writer.write_all(format!("{}\n", msg).as_bytes()).await?;
-        writer.flush().await?;
+        // Answered as it finishes, so a slow query holds up no others
+        let (dispatch, out, replying) = (dispatch.clone(), out.clone(), replying.clone());
+        responding.spawn(async move {
+            let _slot = slot;
+            let (chunked, request_id) = (request.chunked, request.request_id.clone());
+            let response = dispatch.handle(request).await;
+            let lines = if chunked {
+                chunk_lines(&request_id, &response, RESPONSE_CHUNK_BYTES)
+            } else {
+                serialize_message(&response).map(|line| vec![line])
+            };
+            drop(response);
+            match lines {
+                Ok(lines) => {
+                    let _replying = replying.lock().await;
+                    for line in lines {
+                        if send_line(&dispatch, &out, line).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to serialize the response to {}: {}", request_id, e),
+            }
+        });
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Queue a response line for the connection's writer, waiting for room.
+async fn send_line(dispatch: &Dispatch, out: &mpsc::Sender<Outgoing>, line: String) -> anyhow::Result<()> {
+    let held = dispatch.memory.responses.hold(line.len());
+    out.send((line, Some(held))).await.map_err(|_| anyhow::anyhow!("Connection writer closed"))
+}
+
+/// Tasks pushing events to one connection: everything it enabled with
+/// `Events`, and the symbol changes it asked for with `Subscribe`.
+#[derive(Default)]
+struct Forwarders {
+    events: Option<JoinHandle<()>>,
+    subscription: Option<JoinHandle<()>>,
+}
+
+impl Forwarders {
+    fn replace(slot: &mut Option<JoinHandle<()>>, forwarder: Option<JoinHandle<()>>) {
+        if let Some(previous) = std::mem::replace(slot, forwarder) {
+            previous.abort();
+        }
+    }
+
+    fn abort(self) {
+        for forwarder in [self.events, self.subscription].into_iter().flatten() {
+            forwarder.abort();
+        }
+    }
+}
+
+/// Whether `path` is `prefix` itself or lies under it; an empty prefix
+/// matches every path.
+fn under_prefix(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Push daemon events to a connection as `event` frames until it closes,
+/// skipping those `filter` drops. Queued frames are counted in `held`.
+fn forward_events<F>(
+    mut events: broadcast::Receiver<Event>,
+    out: mpsc::Sender<Outgoing>,
+    held: MemoryGauge,
+    filter: F,
+) -> JoinHandle<()>
+where
+    F: Fn(Event) -> Option<Event> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("Connection fell behind, {} events dropped", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(event) = filter(event) else {
+                continue;
+            };
+            let Ok(line) = serialize_message(&Frame::Event(event)) else {
+                continue;
+            };
+            let hold = held.hold(line.len());
+            if out.send((line, Some(hold))).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ct_db::Database;
+    use ct_protocol::{ChangeKind, ServerMessage, SymbolChange};
+
+    pub(super) fn test_dispatch(dir: &std::path::Path) -> Dispatch {
+        let config = Config { db_dir: Some(dir.to_path_buf()), ..Config::default() };
+        Database::open(&config.get_db_path("blake3:test")).unwrap();
+        let state = DaemonState::new(config, dir.to_path_buf(), "blake3:test".to_string(), None);
+        Dispatch {
+            reader: state.reader(),
+            events: state.events(),
+            jobs: state.jobs(),
+            memory: state.memory(),
+            state: Arc::new(RwLock::new(state)),
+            reindex: ReindexControl::default(),
+            inflight: Inflight::default(),
+            activity: Activity::default(),
+            limits: Limits::default(),
+            request_timeout: None,
+            shutdown_requested: Arc::new(Notify::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_answered_as_they_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let state = dispatch.state.clone();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server, dispatch));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut client_read = BufReader::new(client_read);
+        let mut read_response = async || {
+            let mut line = String::new();
+            client_read.read_line(&mut line).await.unwrap();
+            deserialize_message::<Response>(&line).unwrap()
+        };
+
+        // Diag waits for the state lock, as during a reindex; Progress does not
+        let locked = state.write().await;
+        for (cmd, request_id) in [(Command::Diag, "d1"), (Command::Progress, "p1")] {
+            let request = Request { cmd, request_id: request_id.to_string(), protocol_version: ct_protocol::PROTOCOL_VERSION, chunked: false };
+            client_write.write_all(format!("{}\n", serialize_message(&request).unwrap()).as_bytes()).await.unwrap();
+        }
+        assert_eq!(read_response().await.request_id(), "p1");
+        drop(locked);
+        let diag = read_response().await;
+        assert!(matches!(diag, Response::Success(_)));
+        assert_eq!(diag.request_id(), "d1");
+    }
+
+    #[tokio::test]
+    async fn test_requests_answered_after_client_stops_sending() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let state = dispatch.state.clone();
+        let (client, server) = tokio::io::duplex(4096);
+        let connection = tokio::spawn(handle_connection(server, dispatch));
+        let (client_read, mut client_write) = tokio::io::split(client);
+
+        // Still running when the client closes its half, as when stdin ends
+        let locked = state.write().await;
+        let request = Request { cmd: Command::Diag, request_id: "d1".to_string(), protocol_version: ct_protocol::PROTOCOL_VERSION, chunked: false };
+        client_write.write_all(format!("{}\n", serialize_message(&request).unwrap()).as_bytes()).await.unwrap();
+        client_write.shutdown().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(locked);
+
+        let mut line = String::new();
+        BufReader::new(client_read).read_line(&mut line).await.unwrap();
+        assert_eq!(deserialize_message::<Response>(&line).unwrap().request_id(), "d1");
+        connection.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_pushed_after_opt_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let events = dispatch.events.clone();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server, dispatch));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut client_read = BufReader::new(client_read);
+        let mut line = String::new();
+
+        let request = Request {
+            cmd: Command::Events { enabled: true },
+            request_id: "e1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        client_write.write_all(format!("{}\n", serialize_message(&request).unwrap()).as_bytes()).await.unwrap();
+        client_read.read_line(&mut line).await.unwrap();
+        assert!(matches!(deserialize_message(&line).unwrap(), ServerMessage::Response(Response::Success(_))));
+
+        let started = Event::ReindexStarted { package: None };
+        events.send(started.clone()).unwrap();
+        line.clear();
+        client_read.read_line(&mut line).await.unwrap();
+        assert!(matches!(deserialize_message(&line).unwrap(), ServerMessage::Frame(Frame::Event(e)) if e == started));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_pushes_changes_under_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let events = dispatch.events.clone();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server, dispatch));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut client_read = BufReader::new(client_read);
+        let mut line = String::new();
+
+        let request = Request {
+            cmd: Command::Subscribe { path_prefix: "app::net".to_string() },
+            request_id: "s1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        client_write.write_all(format!("{}\n", serialize_message(&request).unwrap()).as_bytes()).await.unwrap();
+        client_read.read_line(&mut line).await.unwrap();
+        assert!(matches!(deserialize_message(&line).unwrap(), ServerMessage::Response(Response::Success(_))));
+
+        let change = |path: &str| SymbolChange {
+            change: ChangeKind::Added,
+            path: path.to_string(),
+            kind: "fn".to_string(),
+            visibility: "pub".to_string(),
+            signature: "fn f()".to_string(),
+            previous_signature: None,
+        };
+        // Neither reaches a connection that only subscribed
+        events.send(Event::ReindexStarted { package: None }).unwrap();
+        events
+            .send(Event::SymbolsChanged { epoch: 1, changes: vec![change("app::network::f")], truncated: false })
+            .unwrap();
+        events
+            .send(Event::SymbolsChanged {
+                epoch: 2,
+                changes: vec![change("app::net::connect"), change("app::networking"), change("app::net")],
+                truncated: false,
+            })
+            .unwrap();
+
+        line.clear();
+        client_read.read_line(&mut line).await.unwrap();
+        let ServerMessage::Frame(Frame::Event(Event::SymbolsChanged { epoch, changes, .. })) = deserialize_message(&line).unwrap() else {
+            panic!("expected symbols_changed, got {}", line)
+        };
+        assert_eq!(epoch, 2);
+        let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["app::net::connect", "app::net"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_inflight_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let request = |cmd, request_id: &str| Request {
+            cmd,
+            request_id: request_id.to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+
+        let cancel = CancelToken::new();
+        let guard = dispatch.inflight.register("q1", cancel.clone());
+        let Response::Success(envelope) = dispatch.handle(request(Command::Cancel { request_id: "q1".to_string() }, "c1")).await else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["cancelled"], true);
+        assert!(cancel.is_cancelled());
+
+        // Finished requests are forgotten
+        drop(guard);
+        let Response::Success(envelope) = dispatch.handle(request(Command::Cancel { request_id: "q1".to_string() }, "c2")).await else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["cancelled"], false);
+
+        // A cancelled query fails with Cancelled rather than its own error
+        let response = dispatch.reader.with_cancel(cancel).handle_request(request(Command::Stats, "q2")).await;
+        assert!(matches!(response, Response::Error(e) if e.err_code == ErrorCode::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reindexes_after_cancelled_reindex() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"lib\"\nversion = \"0.1.0\"\n").unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn run() {}\n").unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let mut events = dispatch.events.subscribe();
+        tokio::spawn(run_jobs(dispatch.jobs.clone(), dispatch.state.clone(), dispatch.reindex.clone()));
+        let request = |cmd, request_id: &str| Request {
+            cmd,
+            request_id: request_id.to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+
+        // The job waits for the state lock until its only waiter is cancelled
+        let locked = dispatch.state.write().await;
+        let reindex = Command::Reindex {
+            features: None,
+            target: None,
+            module: None,
+            struct_name: None,
+            include_derives: false,
+            package: None,
+            wait: true,
+        };
+        let waiter = tokio::spawn({
+            let dispatch = dispatch.clone();
+            async move { dispatch.handle(request(reindex, "r1")).await }
+        });
+        loop {
+            let cancel = request(Command::Cancel { request_id: "r1".to_string() }, "c1");
+            let Response::Success(envelope) = dispatch.handle(cancel).await else {
+                panic!("expected success")
+            };
+            if envelope.data["cancelled"] == true && dispatch.jobs.progress().running.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(locked);
+        let Response::Success(envelope) = waiter.await.unwrap() else {
+            panic!("expected success")
+        };
+        assert_eq!(envelope.data["status"], "cancelled");
+
+        // Once the job is done, watcher batches get a live token again
+        let cancel = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let cancel = dispatch.reindex.current();
+                if !cancel.is_cancelled() {
+                    break cancel;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the cancelled job's token is released");
+        dispatch.state.write().await.handle_file_changes(vec![PathBuf::from("src/lib.rs")], cancel).await;
+        let applied = std::iter::from_fn(|| events.try_recv().ok())
+            .any(|event| matches!(event, Event::WatcherBatchApplied { files: 1, .. }));
+        assert!(applied);
+    }
+
+    #[tokio::test]
+    async fn test_state_lock_waits_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = Dispatch { request_timeout: Some(Duration::from_millis(20)), ..test_dispatch(dir.path()) };
+        let request = |cmd| Request {
+            cmd,
+            request_id: "r1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+
+        // Readers of the state share its lock
+        let shared = dispatch.state.read().await;
+        assert!(matches!(dispatch.handle(request(Command::Diag)).await, Response::Success(_)));
+        drop(shared);
+
+        // Behind a reindex, queries still run but state commands give up
+        let exclusive = dispatch.state.write().await;
+        assert!(matches!(dispatch.handle(request(Command::Stats)).await, Response::Success(_)));
+        let Response::Error(error) = dispatch.handle(request(Command::Diag)).await else {
+            panic!("expected a timeout")
+        };
+        assert_eq!(error.err_code, ErrorCode::Timeout);
+        assert!(error.hint.is_some());
+        drop(exclusive);
+        assert!(matches!(dispatch.handle(request(Command::Diag)).await, Response::Success(_)));
+    }
+
+    #[tokio::test]
+    async fn test_responses_over_memory_budget_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let response = |bytes| {
+            let mut response = Response::success("e1".to_string(), serde_json::json!({ "bundle": {} }));
+            if let Response::Success(envelope) = &mut response {
+                envelope.metrics = Some(ct_protocol::Metrics { elapsed_ms: 0, bytes, db_steps: None, cache: None });
+            }
+            response
+        };
+        assert!(matches!(dispatch.within_budget(response(1 << 20)), Response::Success(_)));
+
+        let dispatch = Dispatch { memory: MemoryBudget::new(Some(1 << 20)), ..dispatch };
+        let Response::Error(error) = dispatch.within_budget(response(2 << 20)) else {
+            panic!("expected a refusal")
+        };
+        assert_eq!(error.err_code, ErrorCode::InvalidArg);
+        assert!(error.hint.unwrap().contains("max_mem_mb"));
+    }
+
+    #[tokio::test]
+    async fn test_activity_tracks_connections() {
+        let activity = Activity::default();
+        let connected = activity.connect();
+        assert_eq!(activity.idle_for(), None);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        drop(connected);
+        assert!(activity.idle_for().unwrap() < Duration::from_millis(30));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(activity.idle_for().unwrap() >= Duration::from_millis(30));
+        activity.touch();
+        assert!(activity.idle_for().unwrap() < Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_connections_over_limits_are_busy() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config { max_connections: 1, rate_limit_per_sec: 1, rate_limit_burst: 2, ..Config::default() };
+        let dispatch = Dispatch { limits: Limits::new(&config), ..test_dispatch(dir.path()) };
+        let line = |cmd: Command, id: &str| {
+            let request = Request { cmd, request_id: id.to_string(), protocol_version: ct_protocol::PROTOCOL_VERSION, chunked: false };
+            format!("{}\n", serialize_message(&request).unwrap())
+        };
+        async fn response<R: tokio::io::AsyncBufRead + Unpin>(lines: &mut tokio::io::Lines<R>) -> Response {
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+        }
+
+        let (mut client, server) = tokio::io::duplex(1 << 16);
+        tokio::spawn(handle_connection(server, dispatch.clone()));
+        for id in ["r1", "r2", "r3"] {
+            client.write_all(line(Command::Stats, id).as_bytes()).await.unwrap();
+        }
+        client.write_all(line(Command::Cancel { request_id: "x".to_string() }, "c1").as_bytes()).await.unwrap();
+        let (reader, mut client) = tokio::io::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        // Answered as they finish, so matched up by id
+        let mut responses = HashMap::new();
+        for _ in 0..4 {
+            let response = response(&mut lines).await;
+            responses.insert(response.request_id().to_string(), response);
+        }
+        assert!(matches!(responses["r1"], Response::Success(_)));
+        assert!(matches!(responses["r2"], Response::Success(_)));
+        let Response::Error(error) = &responses["r3"] else { panic!("expected rate limiting") };
+        assert_eq!(error.err_code, ErrorCode::Busy);
+        assert!(error.details.as_ref().unwrap()["retry_after_ms"].as_u64().unwrap() > 0);
+        assert!(matches!(responses["c1"], Response::Success(_)));
+
+        // The first connection is still open, so a second one is turned away
+        let (mut second, server) = tokio::io::duplex(1 << 16);
+        tokio::spawn(handle_connection(server, dispatch.clone()));
+        second.write_all(line(Command::Stats, "s1").as_bytes()).await.unwrap();
+        let mut second = BufReader::new(second).lines();
+        assert!(matches!(&response(&mut second).await, Response::Error(e) if e.err_code == ErrorCode::Busy && e.request_id == "s1"));
+        assert!(second.next_line().await.unwrap().is_none());
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deadline_cancels_when_expired() {
+        let cancel = CancelToken::new();
+        let deadline = Deadline::start(Some(Duration::from_millis(5)), cancel.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(deadline.expired() && cancel.is_cancelled());
+
+        // Finishing in time stops the timer
+        let cancel = CancelToken::new();
+        drop(Deadline::start(Some(Duration::from_millis(5)), cancel.clone()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_requests_and_removes_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+        let request = Request {
+            cmd: Command::Shutdown,
+            request_id: "s1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        assert!(matches!(dispatch.handle(request).await, Response::Success(_)));
+        tokio::time::timeout(Duration::from_secs(1), dispatch.shutdown_requested.notified()).await.unwrap();
+
+        let socket_path = dir.path().join("ctd.sock");
+        std::fs::write(&socket_path, "").unwrap();
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let handle = ServerHandle {
+            shutdown_tx,
+            reindex: dispatch.reindex.clone(),
+            jobs: dispatch.jobs.clone(),
+            inflight: dispatch.inflight.clone(),
+            state: dispatch.state.clone(),
+            shutdown_requested: dispatch.shutdown_requested.clone(),
+            socket_path: Some(socket_path.clone()),
+            activity: dispatch.activity.clone(),
+            idle_shutdown: None,
+            http_stop: Arc::new(Notify::new()),
+            dispatch: dispatch.clone(),
+        };
+        // A request without an id, finishing within the grace period
+        let cancel = CancelToken::new();
+        let guard = dispatch.inflight.register("", cancel.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        });
+
+        handle.shutdown().await.unwrap();
+        assert!(dispatch.inflight.is_empty());
+        assert!(!cancel.is_cancelled());
+        assert!(!socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_batch_answers_each_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatch = test_dispatch(dir.path());
+
+        let request = Request {
+            cmd: Command::Batch {
+                requests: vec![
+                    Command::Stats,
+                    Command::Diag,
+                    Command::Continue { cursor: "bogus".to_string() },
+                    Command::Batch { requests: vec![] },
+                ],
+            },
+            request_id: "b1".to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        let Response::Success(envelope) = dispatch.handle(request).await else {
+            panic!("expected success")
+        };
+        let responses: Vec<Response> = serde_json::from_value(envelope.data["responses"].clone()).unwrap();
+        assert_eq!(responses.len(), 4);
+        assert!(matches!(responses[0], Response::Success(_)));
+        assert!(matches!(responses[1], Response::Success(_)));
+        assert!(matches!(&responses[2], Response::Error(e) if e.err_code == ErrorCode::InvalidArg && e.request_id == "b1.2"));
+        assert!(matches!(&responses[3], Response::Error(e) if e.err_code == ErrorCode::InvalidArg));
+    }
+}
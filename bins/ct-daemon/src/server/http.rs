@@ -0,0 +1,446 @@
+//! The daemon's commands as REST endpoints, for dashboards and tools that
+//! would rather not speak the NDJSON protocol. `GET /{cmd}?field=value`
+//! runs a read-only command, `POST /{cmd}` with a JSON object body any
+//! command; either answers with the same envelope an IPC client gets.
+//!
+//! There are no cookies to ride on, but a web page can still send requests
+//! to a local port, or reach it through DNS rebinding. So requests must name
+//! a loopback `Host`, carry no `Origin` (browsers add one to cross-origin
+//! requests, other clients don't), POST a JSON `Content-Type` (which a page
+//! cannot send without a preflight), and, when `CT_HTTP_TOKEN` is set for
+//! the daemon, bring it as a bearer token.
+
+use super::{rate_limited, too_many_clients, Dispatch};
+use crate::limits::{request_cost, RateLimiter};
+use crate::reader::QueryReader;
+use crate::state::DaemonState;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::get;
+use axum::{Json, Router};
+use ct_protocol::{Command, ErrorCode, ErrorEnvelope, Request, Response};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tracing::error;
+
+/// Query parameters that are lists even when given once.
+const LIST_FIELDS: [&str; 5] = ["paths", "kinds", "features", "add", "remove"];
+
+/// Rate limiters kept before the idle ones are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Environment variable holding the bearer token HTTP clients must send.
+pub const TOKEN_ENV: &str = "CT_HTTP_TOKEN";
+
+/// `Host` names a request may be addressed to.
+const LOOPBACK_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+
+/// Serve HTTP on `listener` until `stop` is notified, requiring `token`
+/// when there is one.
+pub(super) async fn serve(listener: TcpListener, dispatch: Dispatch, token: Option<String>, stop: Arc<Notify>) {
+    let app = router(dispatch, token).into_make_service_with_connect_info::<SocketAddr>();
+    let result = axum::serve(listener, app)
+        .with_graceful_shutdown(async move { stop.notified().await })
+        .await;
+    if let Err(e) = result {
+        error!("HTTP server failed: {}", e);
+    }
+}
+
+fn router(dispatch: Dispatch, token: Option<String>) -> Router {
+    let http = Http { dispatch, limiters: Arc::default(), token: token.filter(|token| !token.is_empty()) };
+    Router::new()
+        .route("/{cmd}", get(get_command).post(post_command))
+        .with_state(http)
+}
+
+#[derive(Clone)]
+struct Http {
+    dispatch: Dispatch,
+    /// The rate limit applies per client address; HTTP has no lasting connection
+    limiters: Arc<Mutex<HashMap<IpAddr, RateLimiter>>>,
+    token: Option<String>,
+}
+
+impl Http {
+    /// Why the request must be refused, as the response to send, if it
+    /// could come from a web page or lacks the token.
+    fn refuse(&self, headers: &HeaderMap, request_id: &str) -> Option<HttpResponse> {
+        let refused = |status, message: &str| {
+            let mut response = reply(Response::error(request_id.to_string(), message.to_string(), ErrorCode::InvalidArg));
+            *response.status_mut() = status;
+            Some(response)
+        };
+        let host = headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or_default();
+        if !is_loopback_host(host) {
+            return refused(StatusCode::FORBIDDEN, "The Host header must be localhost, 127.0.0.1 or [::1]");
+        }
+        if headers.contains_key(header::ORIGIN) {
+            return refused(StatusCode::FORBIDDEN, "Requests from web pages (with an Origin header) are not served");
+        }
+        if let Some(token) = &self.token {
+            let sent = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+            if sent.and_then(|value| value.strip_prefix("Bearer ")) != Some(token.as_str()) {
+                let mut response = refused(StatusCode::UNAUTHORIZED, &format!("Send the daemon's {} as `Authorization: Bearer <token>`", TOKEN_ENV))?;
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    async fn run(&self, peer: IpAddr, request_id: String, cmd: Command) -> HttpResponse {
+        if matches!(cmd, Command::Events { .. } | Command::Subscribe { .. }) {
+            let message = "Events and Subscribe push to a connection; use the IPC protocol for them";
+            return reply(Response::error(request_id, message.to_string(), ErrorCode::InvalidArg));
+        }
+        if let Err(retry_after) = self.acquire(peer, request_cost(&cmd)) {
+            return reply(rate_limited(request_id, retry_after));
+        }
+        let _slot = match self.dispatch.limits.admit() {
+            Ok(slot) => slot,
+            Err(max) => return reply(too_many_clients(request_id, max)),
+        };
+        let _active = self.dispatch.activity.connect();
+
+        let request = Request { cmd, request_id, protocol_version: ct_protocol::PROTOCOL_VERSION, chunked: false };
+        reply(self.dispatch.handle(request).await)
+    }
+
+    fn acquire(&self, peer: IpAddr, cost: u32) -> Result<(), std::time::Duration> {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        if limiters.len() >= MAX_TRACKED_CLIENTS && !limiters.contains_key(&peer) {
+            limiters.clear();
+        }
+        match limiters.get_mut(&peer) {
+            Some(limiter) => limiter.acquire(cost),
+            None => match self.dispatch.limits.rate_limiter() {
+                Some(mut limiter) => {
+                    let acquired = limiter.acquire(cost);
+                    limiters.insert(peer, limiter);
+                    acquired
+                }
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+async fn get_command(
+    State(http): State<Http>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    let request_id = request_id(&headers);
+    if let Some(refused) = http.refuse(&headers, &request_id) {
+        return refused;
+    }
+    let cmd = match command_from_query(&name, &params) {
+        Ok(cmd) => cmd,
+        Err(e) => return reply(Response::error(request_id, e, ErrorCode::InvalidArg)),
+    };
+    if !is_read_only(&cmd) {
+        let message = format!("{} changes the daemon's state; send it with POST", name);
+        let mut response = reply(Response::error(request_id, message, ErrorCode::InvalidArg));
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return response;
+    }
+    http.run(peer.ip(), request_id, cmd).await
+}
+
+async fn post_command(
+    State(http): State<Http>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HttpResponse {
+    let request_id = request_id(&headers);
+    if let Some(refused) = http.refuse(&headers, &request_id) {
+        return refused;
+    }
+    if !is_json(&headers) {
+        let message = "POST bodies must be sent as Content-Type: application/json";
+        let mut response = reply(Response::error(request_id, message.to_string(), ErrorCode::InvalidArg));
+        *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+        return response;
+    }
+    let fields = if body.iter().all(u8::is_ascii_whitespace) {
+        Ok(Map::new())
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(Value::Object(fields)) => Ok(fields),
+            Ok(_) => Err("The request body must be a JSON object of the command's fields".to_string()),
+            Err(e) => Err(format!("Invalid JSON body: {}", e)),
+        }
+    };
+    match fields.and_then(|fields| command_from_fields(&name, fields)) {
+        Ok(cmd) => http.run(peer.ip(), request_id, cmd).await,
+        Err(e) => reply(Response::error(request_id, e, ErrorCode::InvalidArg)),
+    }
+}
+
+/// Whether `host`, without its port, is a loopback name.
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    LOOPBACK_HOSTS.iter().any(|loopback| name.eq_ignore_ascii_case(loopback))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    media_type.eq_ignore_ascii_case("application/json")
+}
+
+/// The client's `X-Request-Id`, or a new one.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Commands `GET` may run: queries, and nothing that changes the daemon.
+fn is_read_only(cmd: &Command) -> bool {
+    QueryReader::serves(cmd)
+        || DaemonState::reads_only(cmd)
+        || matches!(cmd, Command::Progress | Command::Continue { .. })
+}
+
+/// The command `name` with fields from query parameters. Values read as
+/// booleans or numbers where they look like one, a bare `flag` is `true`,
+/// and a repeated parameter is a list. Should that not fit the command,
+/// each such value in turn, then all of them, are tried as strings, for
+/// the likes of `search?query=42`.
+fn command_from_query(name: &str, params: &[(String, String)]) -> Result<Command, String> {
+    let (typed, raw) = (query_fields(params, true), query_fields(params, false));
+    let error = match command_from_fields(name, typed.clone()) {
+        Ok(cmd) => return Ok(cmd),
+        Err(e) => e,
+    };
+    for (key, value) in &raw {
+        if typed.get(key) == Some(value) {
+            continue;
+        }
+        let mut fields = typed.clone();
+        fields.insert(key.clone(), value.clone());
+        if let Ok(cmd) = command_from_fields(name, fields) {
+            return Ok(cmd);
+        }
+    }
+    command_from_fields(name, raw).map_err(|_| error)
+}
+
+fn query_fields(params: &[(String, String)], infer: bool) -> Map<String, Value> {
+    let mut fields = Map::new();
+    for (key, value) in params {
+        let value = if infer { scalar(value) } else { Value::String(value.clone()) };
+        match fields.get_mut(key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = Value::Array(vec![first.take(), value]),
+            None if LIST_FIELDS.contains(&key.as_str()) => {
+                fields.insert(key.clone(), Value::Array(vec![value]));
+            }
+            None => {
+                fields.insert(key.clone(), value);
+            }
+        }
+    }
+    fields
+}
+
+fn scalar(value: &str) -> Value {
+    match value {
+        "" | "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => match value.parse::<u64>() {
+            Ok(n) => Value::from(n),
+            Err(_) => Value::String(value.to_string()),
+        },
+    }
+}
+
+/// `/symbols` is a friendlier name for `find`; dashes may stand for underscores.
+fn command_from_fields(name: &str, mut fields: Map<String, Value>) -> Result<Command, String> {
+    let cmd = match name {
+        "symbols" => "find".to_string(),
+        _ => name.replace('-', "_"),
+    };
+    fields.insert("cmd".to_string(), Value::String(cmd));
+    serde_json::from_value(Value::Object(fields)).map_err(|e| format!("Invalid {} request: {}", name, e))
+}
+
+/// The envelope as JSON, with the HTTP status matching its error code.
+fn reply(response: Response) -> HttpResponse {
+    let (status, retry_after) = match &response {
+        Response::Error(error) => (error_status(error), retry_after_secs(error)),
+        _ => (StatusCode::OK, None),
+    };
+    let mut reply = (status, Json(response)).into_response();
+    if let Some(secs) = retry_after {
+        reply.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    reply
+}
+
+fn error_status(error: &ErrorEnvelope) -> StatusCode {
+    match error.err_code {
+        ErrorCode::InvalidArg | ErrorCode::ProtocolError => StatusCode::BAD_REQUEST,
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::IndexMismatch | ErrorCode::Cancelled => StatusCode::CONFLICT,
+        ErrorCode::Busy if retry_after_secs(error).is_some() => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::Busy | ErrorCode::DaemonUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Whole seconds until a rate-limited client may retry.
+fn retry_after_secs(error: &ErrorEnvelope) -> Option<u64> {
+    let ms = error.details.as_ref()?.get("retry_after_ms")?.as_u64()?;
+    Some(ms.div_ceil(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::server::tests::test_dispatch;
+    use ct_core::config::Config;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Send a raw HTTP/1.1 request, returning the status and body.
+    async fn send(addr: SocketAddr, method: &str, target: &str, body: &str) -> (u16, Value) {
+        send_with(addr, method, target, "Host: localhost\r\nContent-Type: application/json", body).await
+    }
+
+    /// `send` with `headers`, `\r\n`-separated, in place of the defaults.
+    async fn send_with(addr: SocketAddr, method: &str, target: &str, headers: &str, body: &str) -> (u16, Value) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\n{}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            headers,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).await.unwrap();
+        let status = raw[9..12].parse().unwrap();
+        let (_, body) = raw.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_commands_over_http() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config { rate_limit_per_sec: 1, rate_limit_burst: 5, ..Config::default() };
+        let dispatch = Dispatch { limits: Limits::new(&config), ..test_dispatch(dir.path()) };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(Notify::new());
+        let server = tokio::spawn(serve(listener, dispatch, None, stop.clone()));
+
+        let (status, body) = send(addr, "GET", "/stats", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["ok"], true);
+
+        let (status, body) = send(addr, "GET", "/symbols?name=State&all", "").await;
+        assert_eq!((status, body["ok"].clone()), (200, Value::Bool(true)));
+
+        let (status, body) = send(addr, "POST", "/export", r#"{"paths": ["missing::Thing"]}"#).await;
+        assert_ne!(status, 400, "{}", body);
+        let (status, body) = send(addr, "GET", "/reindex", "").await;
+        assert_eq!(status, 405);
+        assert_eq!(body["err_code"], "INVALID_ARG");
+        let (status, _) = send(addr, "GET", "/no-such-command", "").await;
+        assert_eq!(status, 400);
+
+        // Refused requests cost nothing; the rest of the burst of 5 is spent
+        for _ in 0..2 {
+            assert_eq!(send(addr, "GET", "/stats", "").await.0, 200);
+        }
+        let (status, body) = send(addr, "GET", "/stats", "").await;
+        assert_eq!(status, 429);
+        assert!(body["details"]["retry_after_ms"].as_u64().is_some());
+
+        stop.notify_one();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refuses_requests_from_web_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(Notify::new());
+        let server = tokio::spawn(serve(listener, test_dispatch(dir.path()), Some("s3cret".to_string()), stop.clone()));
+        let authorized = "Authorization: Bearer s3cret";
+
+        let (status, _) = send_with(addr, "GET", "/stats", &format!("Host: 127.0.0.1:7878\r\n{}", authorized), "").await;
+        assert_eq!(status, 200);
+        let (status, _) = send_with(addr, "GET", "/stats", "Host: 127.0.0.1:7878", "").await;
+        assert_eq!(status, 401);
+        let (status, _) = send_with(addr, "GET", "/stats", "Host: 127.0.0.1:7878\r\nAuthorization: Bearer wrong", "").await;
+        assert_eq!(status, 401);
+
+        // DNS rebinding: the page's own name arrives as the Host
+        let (status, _) = send_with(addr, "GET", "/stats", &format!("Host: evil.example:7878\r\n{}", authorized), "").await;
+        assert_eq!(status, 403);
+        let headers = format!("Host: localhost\r\nOrigin: https://evil.example\r\n{}", authorized);
+        let (status, _) = send_with(addr, "GET", "/stats", &headers, "").await;
+        assert_eq!(status, 403);
+
+        // A form or no-cors fetch cannot send JSON
+        let headers = format!("Host: [::1]:7878\r\nContent-Type: text/plain\r\n{}", authorized);
+        let (status, body) = send_with(addr, "POST", "/shutdown", &headers, "{}").await;
+        assert_eq!(status, 415);
+        assert_eq!(body["err_code"], "INVALID_ARG");
+        let headers = format!("Host: localhost\r\nContent-Type: application/json; charset=utf-8\r\n{}", authorized);
+        assert_eq!(send_with(addr, "POST", "/stats", &headers, "").await.0, 200);
+
+        stop.notify_one();
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_loopback_hosts() {
+        for host in ["localhost", "LOCALHOST:80", "127.0.0.1:7878", "[::1]", "[::1]:7878"] {
+            assert!(is_loopback_host(host), "{}", host);
+        }
+        for host in ["", "evil.example", "localhost.evil.example", "127.0.0.2:7878", "0.0.0.0"] {
+            assert!(!is_loopback_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_query_parameters_become_fields() {
+        let params = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let cmd = command_from_query("tree", &params(&[("path", "app"), ("depth", "2"), ("kinds", "fn")])).unwrap();
+        assert!(matches!(cmd, Command::Tree { path, depth: Some(2), kinds } if path == "app" && kinds == ["fn"]));
+
+        let cmd = command_from_query("search", &params(&[("query", "42"), ("limit", "5")])).unwrap();
+        assert!(matches!(cmd, Command::Search { query, .. } if query == "42"));
+
+        let cmd = command_from_query("export", &params(&[("paths", "a::B"), ("paths", "a::C"), ("with_source", "")])).unwrap();
+        assert!(matches!(cmd, Command::Export { paths, with_source: true, .. } if paths.len() == 2));
+
+        assert!(command_from_query("find", &params(&[("depth", "deep")])).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Named pipe instances for the daemon's clients. Only the user the daemon
+//! runs as (and SYSTEM) may open them, and only from this machine.
+
+use std::ffi::c_void;
+use std::io;
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use windows_sys::Win32::Foundation::LocalFree;
+use windows_sys::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
+use windows_sys::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+
+/// Full access for the pipe's owner and SYSTEM, none for anyone else; `P`
+/// keeps entries inherited from elsewhere out.
+const PIPE_SDDL: &str = "D:P(A;;GA;;;OW)(A;;GA;;;SY)";
+
+/// The security descriptor every instance of the pipe is created with.
+pub(super) struct PipeSecurity {
+    descriptor: PSECURITY_DESCRIPTOR,
+}
+
+// The descriptor is only read after it is built, and freed once in `drop`
+unsafe impl Send for PipeSecurity {}
+unsafe impl Sync for PipeSecurity {}
+
+impl PipeSecurity {
+    pub(super) fn new() -> io::Result<Self> {
+        let sddl: Vec<u16> = PIPE_SDDL.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut descriptor: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+        // SAFETY: `sddl` is NUL-terminated and outlives the call; the
+        // descriptor it allocates is released in `drop`
+        let ok = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { descriptor })
+    }
+
+    /// A new instance of the pipe `name`. The first must be created with
+    /// `first` set, which fails if another process already holds the name
+    /// rather than letting it pose as the daemon.
+    pub(super) fn create(&self, name: &str, first: bool) -> io::Result<NamedPipeServer> {
+        let mut attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: self.descriptor,
+            bInheritHandle: 0,
+        };
+        // SAFETY: `attributes` and the descriptor it points to are valid
+        // for the duration of the call, which copies what it needs
+        unsafe {
+            ServerOptions::new()
+                .first_pipe_instance(first)
+                .reject_remote_clients(true)
+                .create_with_security_attributes_raw(name, &mut attributes as *mut SECURITY_ATTRIBUTES as *mut c_void)
+        }
+    }
+}
+
+impl Drop for PipeSecurity {
+    fn drop(&mut self) {
+        // SAFETY: allocated by ConvertStringSecurityDescriptorToSecurityDescriptorW
+        unsafe {
+            LocalFree(self.descriptor);
+        }
+    }
+}
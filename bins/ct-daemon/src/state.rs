@@ -1,285 +1,498 @@
 use ct_core::config::Config;
 use ct_core::models::*;
-use ct_protocol::{Request, Response, Command, ErrorCode, PROTOCOL_VERSION};
-use ct_db::{Database, queries};
+use ct_protocol::{Request, Response, Command, ErrorCode, Event, Metrics, WatcherAction, PROTOCOL_VERSION};
+use ct_db::{Database, DbError, queries};
+use ct_indexer::{Indexer, CHANGE_LOG_LIMIT, IndexError, IndexStats, ProgressFn, cancel::CancelToken, filter::PathFilter, watcher::{TrackedInputs, WatcherStats}};
+use std::collections::BTreeSet;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::info;
+use tokio::sync::broadcast;
+use crate::jobs::{Job, ReindexQueue};
+use crate::memory::MemoryBudget;
+use crate::reader::{symbol_not_found, QueryReader, MAX_SUGGESTIONS};
+use tracing::{info, warn};
 use serde_json::json;
 
 pub struct DaemonState {
     config: Config,
+    workspace_root: PathBuf,
     workspace_fingerprint: String,
     db_path: PathBuf,
     index_timestamp: SystemTime,
     last_index_duration_ms: u64,
+    index_failures: Vec<CrateFailure>,
+    crate_stats: Vec<CrateIndexStats>,
+    watcher_paused: bool,
+    /// Files changed while the watcher was paused, reindexed on resume.
+    pending_changes: BTreeSet<PathBuf>,
+    watcher_stats: Option<Arc<WatcherStats>>,
+    tracked_inputs: Option<TrackedInputs>,
+    reader: QueryReader,
+    /// Reindex and watcher activity for connections that enabled events.
+    events: broadcast::Sender<Event>,
+    jobs: ReindexQueue,
+    memory: MemoryBudget,
+}
+
+/// Events held for a connection that has fallen behind; older ones are dropped.
+const EVENT_BACKLOG: usize = 256;
+
+/// Tracks the token of the reindex currently allowed to run so a newer
+/// request, or shutdown, can cancel it without waiting for the state lock.
+#[derive(Clone, Default)]
+pub struct ReindexControl {
+    current: Arc<Mutex<Option<CancelToken>>>,
+}
+
+impl ReindexControl {
+    /// Cancel any in-flight reindex and make `token` the next one's.
+    pub fn supersede(&self, token: CancelToken) {
+        let mut current = self.current.lock().unwrap();
+        if let Some(previous) = current.replace(token) {
+            previous.cancel();
+        }
+    }
+
+    /// Token for background work that should stop along with the running
+    /// reindex, or on shutdown, without superseding it.
+    pub fn current(&self) -> CancelToken {
+        self.current.lock().unwrap().get_or_insert_with(CancelToken::new).clone()
+    }
+
+    /// Forget `token` once its reindex has finished, so work started after
+    /// it is not handed a token that was cancelled along with it.
+    pub fn release(&self, token: &CancelToken) {
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|current| current.same_as(token)) {
+            *current = None;
+        }
+    }
+
+    pub fn cancel(&self) {
+        if let Some(token) = self.current.lock().unwrap().as_ref() {
+            token.cancel();
+        }
+    }
 }
 
 impl DaemonState {
-    pub fn new(config: Config, workspace_fingerprint: String) -> Self {
+    pub fn new(
+        config: Config,
+        workspace_root: PathBuf,
+        workspace_fingerprint: String,
+        initial_stats: Option<IndexStats>,
+    ) -> Self {
         let db_path = config.get_db_path(&workspace_fingerprint);
+        let stats = initial_stats.unwrap_or_default();
+        let memory = MemoryBudget::new(config.memory_budget());
         
         Self {
+            reader: QueryReader::new(config.clone(), &db_path, workspace_root.clone(), &workspace_fingerprint)
+                .with_memory(memory.clone()),
+            memory,
             config,
+            workspace_root,
             workspace_fingerprint,
             db_path,
             index_timestamp: SystemTime::now(),
-            last_index_duration_ms: 0,
+            last_index_duration_ms: stats.duration_ms,
+            index_failures: stats.failures,
+            crate_stats: stats.crates,
+            watcher_paused: false,
+            pending_changes: BTreeSet::new(),
+            watcher_stats: None,
+            tracked_inputs: None,
+            events: broadcast::channel(EVENT_BACKLOG).0,
+            jobs: ReindexQueue::default(),
         }
     }
 
-    pub async fn handle_request(&mut self, request: Request) -> Response {
+    /// Fold the WAL back into the database before exiting; see
+    /// `Database::checkpoint_wal`.
+    pub fn checkpoint_wal(&self) -> ct_db::Result<(i64, bool)> {
+        Database::open(&self.db_path)?.checkpoint_wal()
+    }
+
+    /// Handle for serving read-only commands without this state's lock.
+    pub fn reader(&self) -> QueryReader {
+        self.reader.clone()
+    }
+
+    /// Where connections subscribe to this daemon's events.
+    pub fn events(&self) -> broadcast::Sender<Event> {
+        self.events.clone()
+    }
+
+    /// What this daemon holds, shared with the connections it serves.
+    pub fn memory(&self) -> MemoryBudget {
+        self.memory.clone()
+    }
+
+    /// Where `Reindex` requests queue up to run under this state's lock.
+    pub fn jobs(&self) -> ReindexQueue {
+        self.jobs.clone()
+    }
+
+    /// Forward an indexer's progress as `ReindexProgress` events, and to
+    /// `also` if given.
+    fn progress_events(&self, also: Option<ProgressFn>) -> ProgressFn {
+        let events = self.events.clone();
+        Arc::new(move |progress| {
+            if let Some(also) = &also {
+                also(progress);
+            }
+            // Nobody listening is not an error
+            let _ = events.send(Event::ReindexProgress {
+                crate_name: progress.crate_name.clone(),
+                done: progress.done,
+                total: progress.total,
+            });
+        })
+    }
+
+    /// Report this watcher's activity in `Diag`.
+    pub fn with_watcher_stats(mut self, stats: Arc<WatcherStats>) -> Self {
+        self.watcher_stats = Some(stats);
+        self
+    }
+
+    /// Keep the watcher's set of non-Rust inputs in step with the index.
+    pub fn with_tracked_inputs(mut self, inputs: TrackedInputs) -> Self {
+        self.tracked_inputs = Some(inputs);
+        self.refresh_tracked_inputs();
+        self
+    }
+
+    fn refresh_tracked_inputs(&self) {
+        let Some(inputs) = &self.tracked_inputs else {
+            return;
+        };
+        let paths = Database::open(&self.db_path)
+            .map_err(|e| e.to_string())
+            .and_then(|db| queries::get_input_paths(db.conn()).map_err(|e| e.to_string()));
+        match paths {
+            Ok(paths) => inputs.replace(paths.into_iter().map(|p| self.workspace_root.join(p))),
+            Err(e) => warn!("Could not load tracked inputs: {}", e),
+        }
+    }
+
+    /// Whether `cmd` only reads this state, so `handle_query` can serve it
+    /// under a shared lock.
+    pub fn reads_only(cmd: &Command) -> bool {
+        matches!(cmd, Command::Diag | Command::WatcherControl { action: WatcherAction::Status })
+    }
+
+    /// Handle a command `reads_only` accepts.
+    pub async fn handle_query(&self, request: Request) -> Response {
         let start = std::time::Instant::now();
-        
         let result = match request.cmd {
-            Command::Find { name, path, kind, vis, unimplemented, todo, all } => {
-                self.handle_find(name, path, kind, vis, unimplemented, todo, all).await
-            }
-            Command::Doc { path, include_docs, vis, unimplemented, todo } => {
-                self.handle_doc(path, include_docs, vis, unimplemented, todo).await
-            }
-            Command::Ls { path, expansion, impl_parents, include_docs, vis, unimplemented, todo } => {
-                self.handle_ls(path, expansion, impl_parents, include_docs, vis, unimplemented, todo).await
-            }
-            Command::Export { path, bundle, expansion, include_docs, vis, unimplemented, todo, impl_parents, with_source } => {
-                self.handle_export(path, bundle, expansion, include_docs, vis, unimplemented, todo, impl_parents, with_source).await
-            }
-            Command::Reindex { features, target, module, struct_name, include_derives } => {
-                self.handle_reindex(features, target, module, struct_name, include_derives).await
-            }
-            Command::Status { vis, unimplemented, todo } => {
-                self.handle_status(vis, unimplemented, todo).await
+            Command::Diag => self.handle_diag().await,
+            Command::WatcherControl { action: WatcherAction::Status } => {
+                Ok(Response::success("".to_string(), self.watcher_control_status()))
             }
+            other => Err((format!("{:?} changes daemon state", other), ErrorCode::InternalError).into()),
+        };
+        
+        finish_response(request.request_id, start, result, self.reader.index_epoch(), &self.reader)
+    }
+
+    /// `cancel` aborts long-running commands (the catch-up reindex of
+    /// `WatcherControl` resume). `Reindex` goes through `jobs` instead.
+    pub async fn handle_request(&mut self, request: Request, cancel: CancelToken) -> Response {
+        let start = std::time::Instant::now();
+        
+        // Measures the cost of the read-only commands handled below
+        let reader = self.reader.with_cancel(cancel.clone());
+        let result = match request.cmd {
             Command::Diag => {
                 self.handle_diag().await
             }
-            Command::Bench { queries, warmup, duration } => {
-                self.handle_bench(queries, warmup, duration).await
+            Command::WatcherControl { action } => {
+                self.handle_watcher_control(action, cancel).await
+            }
+            Command::Tag { path, add, remove, note } => {
+                self.handle_tag(path, add, remove, note).await
             }
+            // Read-only queries
+            cmd => reader.handle_command(cmd).await,
         };
         
-        let elapsed_ms = start.elapsed().as_millis() as u64;
+        finish_response(request.request_id, start, result, self.reader.index_epoch(), &reader)
+    }
+
+    /// Run a queued reindex job; `Ok(None)` if it was cancelled.
+    pub async fn run_reindex(
+        &mut self,
+        job: &Job,
+        progress: ProgressFn,
+    ) -> Result<Option<IndexStats>, HandlerError> {
+        let spec = job.spec.clone();
+        info!("Running reindex job {} with features: {:?}, target: {:?}, module: {:?}, struct: {:?}, include_derives: {}, package: {:?}", 
+              job.id, spec.features, spec.target, spec.module, spec.struct_name, spec.include_derives, spec.package);
+        let package = spec.package;
         
-        match result {
-            Ok(mut response) => {
-                if let Response::Success(ref mut envelope) = response {
-                    envelope.metrics = Some(ct_protocol::Metrics {
-                        elapsed_ms,
-                        bytes: 0, // TODO: Calculate actual response size
+        let db = Database::open(&self.db_path)
+            .map_err(db_error("Database error"))?;
+        let mut indexer = build_indexer(&self.config, self.workspace_root.clone(), db)
+            .map_err(|e| (format!("Indexer error: {}", e), ErrorCode::InvalidArg))?
+            .with_filters(spec.module, spec.struct_name, spec.include_derives)
+            .with_cancel_token(job.cancel.clone())
+            .with_progress(self.progress_events(Some(progress)))
+            .with_memory_gauge(self.memory.rustdoc_json.clone())
+            .with_change_log(self.events.receiver_count() > 0);
+        
+        let start = std::time::Instant::now();
+        let _ = self.events.send(Event::ReindexStarted { package: package.clone() });
+        let result = match &package {
+            Some(package) => indexer.index_package(package).await,
+            None => indexer.index_workspace().await,
+        };
+        let _ = self.events.send(Event::ReindexFinished {
+            package: package.clone(),
+            ok: result.is_ok(),
+            duration_ms: start.elapsed().as_millis() as u64,
+        });
+        
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(IndexError::Cancelled) => {
+                info!("Reindex superseded or daemon shutting down, changes rolled back");
+                return Ok(None);
+            }
+            Err(e) => {
+                let target = package.as_deref().unwrap_or("workspace");
+                if let Some(package) = &package {
+                    self.index_failures.retain(|f| &f.name != package);
+                    self.index_failures.push(CrateFailure {
+                        name: package.clone(),
+                        error: e.to_string(),
                     });
                 }
-                response
-            }
-            Err((err_msg, err_code)) => {
-                Response::error(request.request_id, err_msg, err_code)
+                return Err((format!("Reindex of {} failed: {}", target, e), index_error_code(&e)).into());
             }
-        }
+        };
+        
+        self.record_index_run(&stats, package.is_none());
+        self.publish_changes(indexer.take_changes());
+        Ok(Some(stats))
     }
 
-    async fn handle_find(
-        &self,
-        name: Option<String>,
-        path: Option<String>,
-        kind: Option<String>,
-        vis: Option<String>,
-        unimplemented: Option<bool>,
-        todo: Option<bool>,
-        all: Option<bool>,
-    ) -> Result<Response, (String, ErrorCode)> {
-        if name.is_none() && path.is_none() {
-            return Err(("Must provide either name or path".to_string(), ErrorCode::InvalidArg));
+    /// Incrementally reindex the crates owning a batch of watcher changes.
+    /// While paused, changes are queued for the catch-up reindex on resume.
+    pub async fn handle_file_changes(&mut self, files: Vec<PathBuf>, cancel: CancelToken) {
+        if self.watcher_paused {
+            self.pending_changes.extend(files);
+            return;
         }
         
-        let db = Database::open(&self.db_path)
-            .map_err(|e| (format!("Database error: {}", e), ErrorCode::InternalError))?;
+        match self.reindex_changed_files(files, cancel).await {
+            Ok(_) => {}
+            Err(IndexError::Cancelled) => info!("Incremental reindex cancelled"),
+            Err(e) => warn!("Incremental reindex failed: {}", e),
+        }
+    }
+
+    async fn reindex_changed_files(
+        &mut self,
+        files: Vec<PathBuf>,
+        cancel: CancelToken,
+    ) -> ct_indexer::Result<IndexStats> {
+        let db = Database::open(&self.db_path)?;
+        let mut indexer = build_indexer(&self.config, self.workspace_root.clone(), db)?
+            .with_cancel_token(cancel)
+            .with_progress(self.progress_events(None))
+            .with_memory_gauge(self.memory.rustdoc_json.clone())
+            .with_change_log(self.events.receiver_count() > 0);
         
-        let symbols = if let Some(name) = name {
-            let status_filter = match (unimplemented, todo) {
-                (Some(true), Some(true)) => None, // Show both
-                (Some(true), _) => Some("unimplemented"),
-                (_, Some(true)) => Some("todo"),
-                _ => Some("implemented"),
-            };
-            
-            queries::find_symbols_by_name(
-                db.conn(),
-                &name,
-                kind.as_deref(),
-                vis.as_deref(),
-                status_filter,
-                self.config.max_list,
-            ).map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?
-        } else if let Some(_path) = path {
-            vec![]  // TODO: Implement path search
+        let count = files.len();
+        let threshold = self.config.watcher.full_reindex_threshold;
+        let stats = if count >= threshold {
+            info!("{} files changed (threshold {}), running a full reindex", count, threshold);
+            let stats = indexer.index_workspace().await?;
+            self.record_index_run(&stats, true);
+            stats
         } else {
-            vec![]
+            let stats = indexer.reindex_files(files).await?;
+            if !stats.crates.is_empty() || !stats.failures.is_empty() {
+                self.record_index_run(&stats, false);
+            }
+            stats
         };
         
-        // Filter response based on 'all' flag
-        let items: Vec<serde_json::Value> = if all.unwrap_or(false) {
-            // Return all fields
-            symbols.into_iter().map(|s| serde_json::to_value(s).unwrap()).collect()
-        } else {
-            // Return only path and span fields
-            symbols.into_iter().map(|s| {
-                json!({
-                    "path": s.path,
-                    "span_start": s.span_start,
-                    "span_end": s.span_end,
-                })
-            }).collect()
-        };
+        let _ = self.events.send(Event::WatcherBatchApplied {
+            files: count,
+            crates: stats.crates.iter().map(|c| c.name.clone()).collect(),
+        });
+        self.publish_changes(indexer.take_changes());
+        Ok(stats)
+    }
+
+    /// Tell subscribers which symbols a finished reindex changed.
+    fn publish_changes(&self, mut changes: Vec<SymbolChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        let truncated = changes.len() > CHANGE_LOG_LIMIT;
+        changes.truncate(CHANGE_LOG_LIMIT);
+        let _ = self.events.send(Event::SymbolsChanged {
+            epoch: self.reader.index_epoch().unwrap_or_default(),
+            changes,
+            truncated,
+        });
+    }
+
+    fn watcher_status(&self) -> Option<WatcherStatus> {
+        let stats = self.watcher_stats.as_ref()?;
+        Some(WatcherStatus {
+            running: stats.is_running(),
+            paused: self.watcher_paused,
+            watched_paths: stats.watched_files() + self.tracked_inputs.as_ref().map_or(0, TrackedInputs::len),
+            events_received: stats.events_received(),
+            last_event: stats
+                .last_event_secs()
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map(|dt| dt.to_rfc3339()),
+            pending_batch: stats.pending_batch(),
+            queued_while_paused: self.pending_changes.len(),
+        })
+    }
+
+    async fn handle_watcher_control(
+        &mut self,
+        action: WatcherAction,
+        cancel: CancelToken,
+    ) -> Result<Response, HandlerError> {
+        let mut data = json!({});
+        match action {
+            WatcherAction::Pause => {
+                info!("Watcher paused, queueing changes");
+                self.watcher_paused = true;
+            }
+            WatcherAction::Resume => {
+                self.watcher_paused = false;
+                let files: Vec<PathBuf> = std::mem::take(&mut self.pending_changes).into_iter().collect();
+                info!("Watcher resumed, reindexing {} queued files", files.len());
+                if !files.is_empty() {
+                    data["reindexed_files"] = json!(files.len());
+                    match self.reindex_changed_files(files.clone(), cancel).await {
+                        Ok(stats) => data["stats"] = json!(stats),
+                        Err(IndexError::Cancelled) => {
+                            // Keep the batch so a later resume or reindex picks it up
+                            self.pending_changes.extend(files);
+                            data["status"] = json!("cancelled");
+                        }
+                        Err(e) => {
+                            return Err((format!("Catch-up reindex failed: {}", e), index_error_code(&e)).into());
+                        }
+                    }
+                }
+            }
+            WatcherAction::Status => {}
+        }
         
-        Ok(Response::success(
-            "".to_string(), // Request ID will be filled by caller
-            json!({
-                "items": items,
-            }),
-        ))
+        data["paused"] = json!(self.watcher_paused);
+        data["pending_files"] = json!(self.pending_changes.len());
+        Ok(Response::success("".to_string(), data))
     }
 
-    async fn handle_doc(
-        &self,
-        path: String,
-        include_docs: bool,
-        _vis: Option<String>,
-        _unimplemented: Option<bool>,
-        _todo: Option<bool>,
-    ) -> Result<Response, (String, ErrorCode)> {
-        // Stub implementation
-        Ok(Response::success(
-            "".to_string(),
-            json!({
-                "symbol": {
-                    "path": path,
-                    "signature": "pub struct Example",
-                    "docs": if include_docs { Some("Example documentation") } else { None },
-                },
-            }),
-        ))
+    fn watcher_control_status(&self) -> serde_json::Value {
+        json!({
+            "paused": self.watcher_paused,
+            "pending_files": self.pending_changes.len(),
+        })
     }
 
-    async fn handle_ls(
-        &self,
-        _path: String,
-        _expansion: Option<String>,
-        _impl_parents: bool,
-        _include_docs: bool,
-        _vis: Option<String>,
-        _unimplemented: Option<bool>,
-        _todo: Option<bool>,
-    ) -> Result<Response, (String, ErrorCode)> {
-        // Stub implementation
-        Ok(Response::success(
-            "".to_string(),
-            json!({
-                "items": [],
-            }),
-        ))
+    /// Remember a finished run for `Diag`. A full run replaces all per-crate
+    /// results; a partial one only those of the crates it covered.
+    fn record_index_run(&mut self, stats: &IndexStats, full: bool) {
+        self.index_timestamp = SystemTime::now();
+        self.last_index_duration_ms = stats.duration_ms;
+        if full {
+            self.index_failures = stats.failures.clone();
+            self.crate_stats = stats.crates.clone();
+        } else {
+            self.record_crate_stats(stats);
+        }
+        self.refresh_tracked_inputs();
     }
 
-    async fn handle_export(
-        &self,
-        path: String,
-        _bundle: bool,
-        _expansion: Option<String>,
-        _include_docs: bool,
-        _vis: Option<String>,
-        _unimplemented: Option<bool>,
-        _todo: Option<bool>,
-        _impl_parents: bool,
-        _with_source: bool,
-    ) -> Result<Response, (String, ErrorCode)> {
-        // Stub implementation
-        Ok(Response::success(
-            "".to_string(),
-            json!({
-                "bundle": {
-                    "symbol": {
-                        "path": path,
-                        "kind": "struct",
-                        "signature": "pub struct Example",
-                    },
-                    "children": [],
-                    "extern_refs": [],
-                    "impl_ranges": [],
-                    "order": "bfs",
-                    "invariants": {
-                        "range_1_based_inclusive": true,
-                    },
-                },
-            }),
-        ))
+    /// Replace the per-crate entries covered by a partial reindex.
+    fn record_crate_stats(&mut self, stats: &IndexStats) {
+        for crate_stats in &stats.crates {
+            self.index_failures.retain(|f| f.name != crate_stats.name);
+            self.crate_stats.retain(|c| c.name != crate_stats.name);
+            self.crate_stats.push(crate_stats.clone());
+        }
+        for failure in &stats.failures {
+            self.index_failures.retain(|f| f.name != failure.name);
+            self.index_failures.push(failure.clone());
+        }
     }
 
-    async fn handle_reindex(
+    async fn handle_tag(
         &self,
-        features: Option<Vec<String>>,
-        target: Option<String>,
-        module: Option<String>,
-        struct_name: Option<String>,
-        include_derives: bool,
-    ) -> Result<Response, (String, ErrorCode)> {
-        // Stub implementation
-        info!("Reindexing requested with features: {:?}, target: {:?}, module: {:?}, struct: {:?}, include_derives: {}", 
-              features, target, module, struct_name, include_derives);
+        path: String,
+        add: Vec<String>,
+        remove: Vec<String>,
+        note: Option<String>,
+    ) -> Result<Response, HandlerError> {
+        if add.iter().chain(&remove).any(|tag| tag.trim().is_empty()) {
+            return Err(("Tags must not be empty".to_string(), ErrorCode::InvalidArg).into());
+        }
         
-        // TODO: Pass filtering options to the indexer when reindexing
-        // let mut indexer = Indexer::new(workspace_root, db)
-        //     .with_filters(module, struct_name, include_derives);
+        let mut db = Database::open(&self.db_path)
+            .map_err(db_error("Database error"))?;
         
-        Ok(Response::success(
-            "".to_string(),
-            json!({
-                "status": "reindex_started",
-                "filters": {
-                    "module": module,
-                    "struct_name": struct_name,
-                    "include_derives": include_derives
-                }
-            }),
-        ))
-    }
-
-    async fn handle_status(
-        &self,
-        vis: Option<String>,
-        unimplemented: Option<bool>,
-        todo: Option<bool>,
-    ) -> Result<Response, (String, ErrorCode)> {
-        let db = Database::open(&self.db_path)
-            .map_err(|e| (format!("Database error: {}", e), ErrorCode::InternalError))?;
+        if queries::find_symbol_by_path(db.conn(), &path)
+            .map_err(db_error("Query error"))?
+            .is_none()
+        {
+            return Err(symbol_not_found(&path, queries::suggest_paths(db.conn(), &path, MAX_SUGGESTIONS)));
+        }
         
-        let counts = queries::get_status_counts(db.conn(), vis.as_deref())
-            .map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?;
+        // Tags are annotations, not index content, so the epoch stays put
+        let write = |db: &mut Database| -> ct_db::Result<()> {
+            db.begin_transaction()?;
+            for tag in &remove {
+                db.remove_tag(&path, tag)?;
+            }
+            for tag in &add {
+                db.add_tag(&path, tag, note.as_deref())?;
+            }
+            db.commit_transaction()
+        };
+        if let Err(e) = write(&mut db) {
+            let _ = db.rollback_transaction();
+            return Err(db_error("Database error")(e).into());
+        }
         
-        let items = queries::get_status_items(
-            db.conn(),
-            vis.as_deref(),
-            unimplemented.unwrap_or(false),
-            todo.unwrap_or(false),
-            self.config.max_list,
-        ).map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?;
+        let tags = queries::get_tags(db.conn(), &path)
+            .map_err(db_error("Query error"))?;
         
         Ok(Response::success(
             "".to_string(),
             json!({
-                "counts": counts,
-                "items": items,
+                "path": path,
+                "tags": tags,
             }),
         ))
     }
 
-    async fn handle_diag(&self) -> Result<Response, (String, ErrorCode)> {
+    async fn handle_diag(&self) -> Result<Response, HandlerError> {
         let db = Database::open(&self.db_path)
-            .map_err(|e| (format!("Database error: {}", e), ErrorCode::InternalError))?;
+            .map_err(db_error("Database error"))?;
         
         let symbol_count = db.get_symbol_count()
-            .map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?;
+            .map_err(db_error("Query error"))?;
         let crate_count = db.get_crate_count()
-            .map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?;
+            .map_err(db_error("Query error"))?;
         let file_count = db.get_file_count()
-            .map_err(|e| (format!("Query error: {}", e), ErrorCode::InternalError))?;
+            .map_err(db_error("Query error"))?;
+        
+        let memory = self.memory.usage();
+        let mut crate_stats = self.crate_stats.clone();
+        crate_stats.sort_by_key(|c| std::cmp::Reverse(c.duration_ms));
         
         let timestamp = self.index_timestamp
             .duration_since(UNIX_EPOCH)
@@ -288,18 +501,15 @@ impl DaemonState {
         
         let diag = DiagResponse {
             db_path: self.db_path.to_string_lossy().to_string(),
-            schema_version: "1".to_string(),
+            schema_version: ct_db::migrations::CURRENT_VERSION.to_string(),
             tool_version: "0.1.0".to_string(),
             protocol_versions_supported: vec![PROTOCOL_VERSION],
-            workspace_root: std::env::current_dir()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string(),
+            workspace_root: self.workspace_root.to_string_lossy().to_string(),
             workspace_fingerprint: self.workspace_fingerprint.clone(),
             crate_count,
             file_count,
             symbol_count,
-            mem_footprint_bytes: 0, // TODO: Implement memory tracking
+            mem_footprint_bytes: memory.total(),
             last_index_duration_ms: self.last_index_duration_ms,
             index_timestamp: chrono::DateTime::from_timestamp(timestamp as i64, 0)
                 .map(|dt| dt.to_rfc3339())
@@ -309,6 +519,11 @@ impl DaemonState {
             target: "x86_64-unknown-linux-gnu".to_string(), // TODO: Get actual target
             daemon_hot: true,
             transport: format!("{:?}", self.config.get_effective_transport()).to_lowercase(),
+            failed_crates: self.index_failures.clone(),
+            crate_stats,
+            watcher: self.watcher_status(),
+            reindex: self.jobs.progress(),
+            memory,
         };
         
         Ok(Response::success(
@@ -316,28 +531,151 @@ impl DaemonState {
             serde_json::to_value(diag).unwrap(),
         ))
     }
+}
 
-    async fn handle_bench(
-        &self,
-        queries: u32,
-        warmup: u32,
-        duration: u32,
-    ) -> Result<Response, (String, ErrorCode)> {
-        // Stub implementation
-        info!("Benchmarking with {} queries, {}ms warmup, {}s duration", queries, warmup, duration);
-        Ok(Response::success(
-            "".to_string(),
-            json!({
-                "query_latency_p50_ms": 5,
-                "query_latency_p90_ms": 10,
-                "query_latency_p99_ms": 20,
-                "throughput_qps": 200,
-                "configuration": {
-                    "queries": queries,
-                    "warmup_ms": warmup,
-                    "duration_s": duration,
-                },
-            }),
+/// Why a handler failed, sent back as an error envelope. Plain
+/// `(message, code)` pairs convert into one with the code's usual hint.
+#[derive(Debug, Clone)]
+pub(crate) struct HandlerError {
+    pub(crate) message: String,
+    pub(crate) code: ErrorCode,
+    pub(crate) hint: Option<String>,
+    pub(crate) details: Option<serde_json::Value>,
+}
+
+impl HandlerError {
+    pub(crate) fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub(crate) fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl From<(String, ErrorCode)> for HandlerError {
+    fn from((message, code): (String, ErrorCode)) -> Self {
+        let hint = match code {
+            ErrorCode::Busy => Some("Another process is writing the index; retry shortly"),
+            ErrorCode::IndexMismatch => Some("The index changed underneath the request; send it again"),
+            _ => None,
+        };
+        Self { message, code, hint: hint.map(str::to_string), details: None }
+    }
+}
+
+/// Turn a database failure into a handler error, reporting a lock held by
+/// another process as `Busy` so clients know to retry.
+pub(crate) fn db_error(context: &'static str) -> impl Fn(DbError) -> (String, ErrorCode) {
+    move |e| {
+        let code = match e {
+            DbError::Busy(_) => ErrorCode::Busy,
+            _ => ErrorCode::InternalError,
+        };
+        (format!("{}: {}", context, e), code)
+    }
+}
+
+/// Error code for a failed (re)index: `Busy` if it could not get the write lock.
+fn index_error_code(e: &IndexError) -> ErrorCode {
+    match e {
+        IndexError::Database(DbError::Busy(_)) => ErrorCode::Busy,
+        _ => ErrorCode::InternalError,
+    }
+}
+
+/// Attach metrics, including the cost `reader` measured, the index epoch
+/// and the workspace fingerprint to a handler's response, or turn its error
+/// into one.
+pub(crate) fn finish_response(
+    request_id: String,
+    start: std::time::Instant,
+    result: Result<Response, HandlerError>,
+    index_epoch: Option<u64>,
+    reader: &QueryReader,
+) -> Response {
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    
+    match result {
+        Ok(response) => {
+            // Handlers build their responses without the id
+            let mut response = response.with_request_id(request_id);
+            if let Response::Success(ref mut envelope) = response {
+                let mut metrics = Metrics {
+                    elapsed_ms,
+                    // Serialized once, here, and written as is when sent
+                    bytes: envelope.measure_data(),
+                    db_steps: None,
+                    cache: None,
+                };
+                reader.cost().apply(&mut metrics);
+                envelope.metrics = Some(metrics);
+                envelope.index_epoch = index_epoch;
+                envelope.workspace_fingerprint = Some(reader.workspace_fingerprint().to_string());
+            }
+            response
+        }
+        Err(e) => {
+            Response::error(request_id, e.message, e.code)
+                .with_hint(e.hint)
+                .with_details(e.details)
+        }
+    }
+}
+
+/// Create an indexer honoring the member selection and file globs from `config`.
+pub fn build_indexer(
+    config: &Config,
+    workspace_root: PathBuf,
+    db: Database,
+) -> ct_indexer::Result<Indexer> {
+    let path_filter = PathFilter::new(&config.index.include, &config.index.exclude)?;
+    Ok(Indexer::new(workspace_root, db)
+        .with_git_metadata(config.git_metadata)
+        .with_path_filter(path_filter)
+        .with_tests(config.index.include_tests)
+        .with_members(
+            config.index_members.clone(),
+            config.index_exclude_members.clone(),
         ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindex_supersede() {
+        let control = ReindexControl::default();
+        let (first, second) = (CancelToken::new(), CancelToken::new());
+        control.supersede(first.clone());
+        control.supersede(second.clone());
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+
+        control.cancel();
+        assert!(second.is_cancelled());
+
+        // Only the finished reindex's own token is released
+        control.release(&first);
+        assert!(control.current().same_as(&second));
+        control.release(&second);
+        assert!(!control.current().is_cancelled());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_paused_watcher_queues_changes() {
+        let mut state = DaemonState::new(Config::default(), PathBuf::from("."), "blake3:test".to_string(), None);
+        state.handle_watcher_control(WatcherAction::Pause, CancelToken::new()).await.unwrap();
+
+        state.handle_file_changes(vec![PathBuf::from("src/a.rs")], CancelToken::new()).await;
+        state.handle_file_changes(vec![PathBuf::from("src/a.rs"), PathBuf::from("src/b.rs")], CancelToken::new()).await;
+
+        let response = state.handle_watcher_control(WatcherAction::Status, CancelToken::new()).await.unwrap();
+        let Response::Success(envelope) = response else { panic!("expected success") };
+        assert_eq!(envelope.data["paused"], true);
+        assert_eq!(envelope.data["pending_files"], 2);
+    }
+}
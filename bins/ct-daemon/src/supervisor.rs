@@ -0,0 +1,200 @@
+//! `ct-daemon --supervise`: run the daemon as a child process and start it
+//! again when it crashes, waiting longer after each crash in a row. Every
+//! crash is recorded in the workspace cache for `ct doctor`. A daemon that
+//! exits with an error instead, say over a bad ct.toml or because another
+//! is already running, is not started again: it would only fail the same way.
+
+use ct_core::crash::{self, CrashRecord};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStderr, Command};
+
+/// Wait before the first restart, doubled after each crash in a row.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A daemon that ran this long before crashing starts over from `FIRST_BACKOFF`.
+const STABLE_RUN: Duration = Duration::from_secs(5 * 60);
+/// Crashes in a row, each before `STABLE_RUN`, after which the supervisor gives up.
+const MAX_CRASHES: u32 = 5;
+/// Lines of the daemon's stderr kept to find a panic message in.
+const STDERR_TAIL: usize = 20;
+/// Lines of it that explain a crash other than a panic.
+const REASON_LINES: usize = 5;
+/// What a Rust program exits with when it panics.
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// Run the daemon with this process's arguments until it exits other than
+/// by crashing. The daemon's own PID file keeps a second one from starting.
+pub async fn supervise(crash_log: PathBuf) -> anyhow::Result<()> {
+    let exe = std::env::current_exe()?;
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).filter(|arg| arg != "--supervise").collect();
+    let mut crashes = 0;
+    let mut backoff = FIRST_BACKOFF;
+
+    loop {
+        let started = Instant::now();
+        let mut child = Command::new(&exe).args(&args).stderr(Stdio::piped()).spawn()?;
+        let pid = child.id().unwrap_or_default();
+        let tail = child.stderr.take().map(|stderr| tokio::spawn(relay_stderr(stderr)));
+        let status = child.wait().await?;
+        let tail = match tail {
+            Some(tail) => tail.await.unwrap_or_default(),
+            None => VecDeque::new(),
+        };
+        if status.success() {
+            return Ok(());
+        }
+        if !crashed(status) {
+            anyhow::bail!("ct-daemon exited with {}", describe(status));
+        }
+
+        if started.elapsed() >= STABLE_RUN {
+            crashes = 0;
+            backoff = FIRST_BACKOFF;
+        }
+        crashes += 1;
+        let restart = (crashes < MAX_CRASHES).then_some(backoff);
+        let record = CrashRecord {
+            at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            pid,
+            exit: describe(status),
+            reason: crash_reason(&tail),
+            restart_in_secs: restart.map(|wait| wait.as_secs()),
+        };
+        if let Err(e) = crash::record(&crash_log, &record) {
+            eprintln!("Could not record the crash in {}: {}", crash_log.display(), e);
+        }
+        let Some(wait) = restart else {
+            anyhow::bail!("ct-daemon crashed {} times in a row, last with {}; giving up", crashes, record.exit);
+        };
+        eprintln!("ct-daemon (PID {}) crashed with {}; starting it again in {:?}", pid, record.exit, wait);
+        tokio::time::sleep(wait).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        // Whatever the crash left half-written is reindexed, not wiped again
+        args.retain(|arg| arg != "--clean");
+    }
+}
+
+/// Pass the daemon's stderr through to ours, returning its last lines.
+async fn relay_stderr(stderr: ChildStderr) -> VecDeque<String> {
+    let mut lines = BufReader::new(stderr).split(b'\n');
+    let mut out = tokio::io::stderr();
+    let mut tail = VecDeque::with_capacity(STDERR_TAIL);
+    while let Ok(Some(line)) = lines.next_segment().await {
+        let _ = out.write_all(&line).await;
+        let _ = out.write_all(b"\n").await;
+        if tail.len() == STDERR_TAIL {
+            tail.pop_front();
+        }
+        tail.push_back(strip_ansi(&String::from_utf8_lossy(&line)));
+    }
+    tail
+}
+
+/// Whether `status` is a crash worth starting the daemon again after: a
+/// panic or a fatal signal, rather than an error the daemon reported.
+fn crashed(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if status.signal().is_some() {
+            return true;
+        }
+    }
+    match status.code() {
+        Some(PANIC_EXIT_CODE) | None => true,
+        // Windows exits a crashed process with its NTSTATUS, e.g. 0xC0000005
+        Some(code) => cfg!(windows) && code as u32 >= 0xC000_0000,
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("signal {}", signal);
+        }
+    }
+    match status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => status.to_string(),
+    }
+}
+
+/// The panic message at the end of `tail` if the daemon panicked, else the
+/// last few lines it wrote.
+fn crash_reason(tail: &VecDeque<String>) -> String {
+    let start = tail
+        .iter()
+        .rposition(|line| line.contains("panicked at"))
+        .unwrap_or(tail.len().saturating_sub(REASON_LINES));
+    tail.iter()
+        .skip(start)
+        .take_while(|line| !line.starts_with("note: ") && !line.starts_with("stack backtrace:"))
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `line` without the color escape sequences the console log uses.
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Control sequences end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_crashed() {
+        use std::os::unix::process::ExitStatusExt;
+        // Wait statuses: the exit code in the high byte, a signal in the low
+        assert!(crashed(ExitStatus::from_raw(PANIC_EXIT_CODE << 8)));
+        assert!(crashed(ExitStatus::from_raw(9)));
+        assert!(!crashed(ExitStatus::from_raw(1 << 8)));
+        assert!(!crashed(ExitStatus::from_raw(7 << 8)));
+    }
+
+    #[test]
+    fn test_crash_reason() {
+        let tail: VecDeque<String> = [
+            "\u{1b}[2m2026-10-18T04:45:02Z\u{1b}[0m \u{1b}[32m INFO\u{1b}[0m Indexing crate: ct-db",
+            "thread 'main' panicked at libs/ct-indexer/src/lib.rs:12:5:",
+            "index out of bounds",
+            "note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace",
+        ]
+        .iter()
+        .map(|line| strip_ansi(line))
+        .collect();
+        assert_eq!(tail[0], "2026-10-18T04:45:02Z  INFO Indexing crate: ct-db");
+        assert_eq!(
+            crash_reason(&tail),
+            "thread 'main' panicked at libs/ct-indexer/src/lib.rs:12:5:\nindex out of bounds"
+        );
+
+        let killed: VecDeque<String> = (0..8).map(|i| format!("Indexing crate: {}", i)).collect();
+        assert_eq!(crash_reason(&killed).lines().collect::<Vec<_>>(), (3..8).map(|i| format!("Indexing crate: {}", i)).collect::<Vec<_>>());
+    }
+}
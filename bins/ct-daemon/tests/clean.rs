@@ -0,0 +1,56 @@
+//! `ct-daemon --clean`, as `ct daemon start --clean` runs it: the cache and
+//! database are wiped and the workspace indexed again from scratch.
+
+use ct_db::Database;
+use std::path::Path;
+use std::process::Command;
+
+fn run_daemon(workspace: &Path, cache: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ct-daemon"))
+        .args(args)
+        .current_dir(workspace)
+        .env("XDG_CACHE_HOME", cache)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "ct-daemon {:?} failed:\n{}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_start_clean_reindex() {
+    let workspace = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    let db_dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        workspace.path().join("Cargo.toml"),
+        "[package]\nname = \"cleaned\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    std::fs::create_dir(workspace.path().join("src")).unwrap();
+    std::fs::write(workspace.path().join("src/lib.rs"), "/// Kept across a clean\npub fn kept() {}\n").unwrap();
+    // The lockfile is part of the workspace fingerprint, so have it before the first run
+    let locked = Command::new("cargo").args(["generate-lockfile", "--offline"]).current_dir(workspace.path()).status().unwrap();
+    assert!(locked.success());
+    // The database outside the cache directory, so both get cleaned
+    std::fs::write(workspace.path().join("ct.toml"), format!("db_dir = {:?}\n", db_dir.path())).unwrap();
+    let db_path = db_dir.path().join("symbols.sqlite");
+
+    run_daemon(workspace.path(), cache.path(), &["--once"]);
+    let symbols = Database::open(&db_path).unwrap().get_symbol_count().unwrap();
+    assert!(symbols > 0);
+
+    // Leftovers a clean start must not see
+    let db = Database::open(&db_path).unwrap();
+    db.insert_crate("stale", None, "blake3:stale").unwrap();
+    drop(db);
+    let cache_dir = std::fs::read_dir(cache.path().join("ct")).unwrap().next().unwrap().unwrap().path();
+    std::fs::write(cache_dir.join("leftover"), "").unwrap();
+
+    run_daemon(workspace.path(), cache.path(), &["--once", "--clean"]);
+    assert!(!cache_dir.join("leftover").exists());
+    assert!(cache_dir.join("logs").is_dir());
+    let db = Database::open(&db_path).unwrap();
+    let crates = db.crate_fingerprints().unwrap();
+    assert!(crates.contains_key("cleaned") && !crates.contains_key("stale"), "{:?}", crates);
+    assert_eq!(db.get_symbol_count().unwrap(), symbols);
+}
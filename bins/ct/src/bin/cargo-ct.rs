@@ -0,0 +1,33 @@
+//! `cargo ct find State`: cargo runs `cargo-ct ct find State`, and this
+//! runs `ct find State` with the same terminal and exit code.
+
+use ct_core::utils::EXIT_INTERNAL_ERROR;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let mut args = std::env::args_os().skip(1).peekable();
+    // Run as `cargo-ct find State` directly there is no `ct` to drop
+    if args.peek().is_some_and(|arg| arg == "ct") {
+        args.next();
+    }
+
+    let code = match Command::new(ct_path()).args(args).status() {
+        Ok(status) => status.code().unwrap_or(EXIT_INTERNAL_ERROR as i32),
+        Err(e) => {
+            eprintln!("Error: could not run ct: {}", e);
+            EXIT_INTERNAL_ERROR as i32
+        }
+    };
+    std::process::exit(code);
+}
+
+/// `ct` in the same directory as `cargo-ct`, or else the one on PATH.
+fn ct_path() -> PathBuf {
+    let name = format!("ct{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(&name)))
+        .filter(|ct| ct.exists())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
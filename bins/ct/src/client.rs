@@ -1,78 +1,146 @@
-use ct_core::{config::Config, compute_workspace_fingerprint, utils::find_workspace_root};
+use ct_core::{config::Config, compute_workspace_id, utils::{find_workspace_root, workspace_search_start}};
 use ct_core::transport::IpcClient;
-use ct_protocol::{Request, Response, Command};
-use std::path::Path;
+use ct_protocol::{Request, Response, Command, ErrorCode, Event};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 use anyhow::{Context, Result};
 
 pub struct CtClient {
     client: IpcClient,
+    /// Epoch of the first response; later ones must match it.
+    index_epoch: Option<u64>,
+    /// Workspace fingerprint of the first response, held to the same rule.
+    workspace_fingerprint: Option<String>,
 }
 
 impl CtClient {
     pub async fn connect() -> Result<Self> {
+        Self::connect_with(true).await
+    }
+
+    /// Connect only if a daemon is already running, never starting one.
+    pub async fn connect_running() -> Result<Self> {
+        Self::connect_with(false).await
+    }
+
+    async fn connect_with(autostart: bool) -> Result<Self> {
         let config = Config::load()?;
-        let workspace_root = find_workspace_root(&std::env::current_dir()?)?;
-        let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
+        let workspace_root = find_workspace_root(&workspace_search_start()?)?;
+        let workspace_id = compute_workspace_id(&workspace_root);
         
         // Try to connect to daemon
-        match IpcClient::connect(&config, &workspace_fingerprint).await {
-            Ok(client) => Ok(Self { client }),
-            Err(_) if config.autostart => {
+        match IpcClient::connect(&config, &workspace_id).await {
+            Ok(client) => Ok(Self { client, index_epoch: None, workspace_fingerprint: None }),
+            Err(_) if autostart && config.autostart => {
                 // Try to start daemon
-                Self::start_daemon(&workspace_root).await?;
+                Self::start_daemon(&config, &workspace_root).await?;
                 
                 // Wait a bit for daemon to start
                 tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
                 
                 // Try connecting again
-                let client = IpcClient::connect(&config, &workspace_fingerprint).await
+                let client = IpcClient::connect(&config, &workspace_id).await
                     .context("Failed to connect to daemon after autostart")?;
                 
-                Ok(Self { client })
+                Ok(Self { client, index_epoch: None, workspace_fingerprint: None })
             }
             Err(e) => Err(e.into()),
         }
     }
 
-    async fn start_daemon(workspace_root: &Path) -> Result<()> {
-        use std::process::Command;
-        
-        // Find ct-daemon in PATH or same directory as ct
-        let daemon_path = if let Ok(exe) = std::env::current_exe() {
-            let dir = exe.parent().unwrap();
-            let daemon = dir.join("ct-daemon");
-            if daemon.exists() {
-                daemon
-            } else {
-                // Fall back to PATH
-                std::path::PathBuf::from("ct-daemon")
-            }
-        } else {
-            std::path::PathBuf::from("ct-daemon")
-        };
-        
-        Command::new(daemon_path)
-            .arg("--idx")
-            .arg(workspace_root)
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn()
-            .context("Failed to start ct-daemon")?;
-        
+    async fn start_daemon(config: &Config, workspace_root: &Path) -> Result<()> {
+        let mut cmd = std::process::Command::new(daemon_path());
+        cmd.arg("--idx").arg(workspace_root);
+        if config.supervise {
+            cmd.arg("--supervise");
+        }
+        detach(&mut cmd).spawn().context("Failed to start ct-daemon")?;
         Ok(())
     }
 
     pub async fn send_command(&mut self, cmd: Command) -> Result<Response> {
+        self.send_command_with_events(cmd, &mut |_| {}).await
+    }
+
+    /// Send `cmd`, handing events pushed before its response to `on_event`;
+    /// the connection must have opted in with `Command::Events`.
+    pub async fn send_command_with_events(
+        &mut self,
+        cmd: Command,
+        on_event: &mut (dyn FnMut(Event) + Send),
+    ) -> Result<Response> {
         let request = Request {
             cmd,
             request_id: Uuid::new_v4().to_string(),
             protocol_version: ct_protocol::PROTOCOL_VERSION,
+            // Large exports arrive in frames; IpcClient reassembles them
+            chunked: true,
         };
         
-        self.client.send_request(request).await
-            .context("Failed to send request to daemon")
+        let response = self.client.send_request_with_events(request, on_event).await
+            .context("Failed to send request to daemon")?;
+        Ok(self.check_index(response))
     }
+
+    /// Wait for the next event the daemon pushes, e.g. after
+    /// `Command::Subscribe`.
+    pub async fn next_event(&mut self) -> Result<Event> {
+        self.client.next_event().await.context("Lost the connection to the daemon")
+    }
+
+    /// Turn a response from a different index epoch or workspace fingerprint
+    /// than the first into an `IndexMismatch` error, so output assembled from
+    /// several requests never mixes results from before and after a reindex.
+    fn check_index(&mut self, response: Response) -> Response {
+        let Response::Success(envelope) = &response else {
+            return response;
+        };
+        let mismatch = match (&self.workspace_fingerprint, &envelope.workspace_fingerprint) {
+            (Some(first), Some(current)) if first != current => Some(format!(
+                "Workspace changed between requests (fingerprint {} -> {}); rerun the command",
+                first, current
+            )),
+            _ => match (self.index_epoch, envelope.index_epoch) {
+                (Some(first), Some(epoch)) if first != epoch => Some(format!(
+                    "Index changed between requests (epoch {} -> {}); rerun the command",
+                    first, epoch
+                )),
+                _ => None,
+            },
+        };
+        if let Some(message) = mismatch {
+            return Response::error(envelope.request_id.clone(), message, ErrorCode::IndexMismatch);
+        }
+        self.index_epoch = self.index_epoch.or(envelope.index_epoch);
+        if self.workspace_fingerprint.is_none() {
+            self.workspace_fingerprint = envelope.workspace_fingerprint.clone();
+        }
+        response
+    }
+}
+
+/// `ct-daemon` in the same directory as `ct`, or else the one on PATH.
+pub fn daemon_path() -> PathBuf {
+    let name = format!("ct-daemon{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(&name)))
+        .filter(|daemon| daemon.exists())
+        .unwrap_or_else(|| PathBuf::from(name))
 }
 
+/// Set `cmd` up to outlive this terminal: no stdio and, on Windows, no
+/// console or process group shared with `ct`.
+pub fn detach(cmd: &mut std::process::Command) -> &mut std::process::Command {
+    cmd.stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+    cmd
+}
@@ -1,21 +1,48 @@
-use crate::client::CtClient;
+use crate::client::{daemon_path, detach, CtClient};
+use crate::graph::Graph;
+use crate::editor;
+use crate::pager;
+use crate::table;
+use crate::service::{resolve_program, Platform, Service};
+use crate::style::Style;
 use crate::OutputFormat;
-use crate::DaemonCommand;
+use crate::{DaemonCommand, DbCommand, GrepIn, RefsGroupBy, WatcherCommand};
 use ct_core::utils::*;
-use ct_protocol::{Command, Response, ErrorCode};
-use anyhow::Result;
+use ct_core::models::{DiagResponse, DocExample, GrepHit, IndexSummary, TraitImpl, TreeNode};
+use ct_protocol::data::{BenchData, CallEdge, CallGraphData, ExportBundle, DiffData, ExportData, ExportSymbol, FindData, Latency, LocateData, SearchData, SymbolItem, RefFile, RefsData, TodoData, TodoItem};
+use ct_protocol::{
+    Command, DepsDirection, Response, ErrorCode, Event, GrepField, ImplsDirection, RefsGroup, StatusGroup, SuccessEnvelope,
+    SymbolChange, WatcherAction,
+};
+use anyhow::{bail, Context, Result};
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::Shells;
+use clap_complete::Shell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process::Command as ProcessCommand;
 use ct_core::config::Config;
-use ct_core::compute_workspace_fingerprint;
-use serde_json::json;
+use ct_core::pidfile;
+use ct_core::transport::IpcClient;
+use ct_core::{compute_workspace_fingerprint, compute_workspace_id};
+use serde_json::{json, Value};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn find(
-    query: String,
+    query: Option<String>,
     kind: Option<String>,
     vis: Option<String>,
     unimplemented: bool,
     todo: bool,
     all: bool,
+    recently_changed: Option<u32>,
+    by_path: bool,
+    depth: Option<usize>,
+    match_mode: Option<String>,
+    tag: Option<String>,
+    sig: Option<String>,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -24,26 +51,177 @@ pub async fn find(
         Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
     };
     
+    let (name, path) = if by_path { (None, query) } else { (query, None) };
     let cmd = Command::Find {
-        name: Some(query.clone()),
-        path: None,
+        name,
+        path,
         kind,
         vis,
         unimplemented: if unimplemented { Some(true) } else { None },
         todo: if todo { Some(true) } else { None },
-        all: if all { Some(true) } else { None },
+        // Pretty output shows kinds and visibility, which only come with all fields
+        all: if all || matches!(format, OutputFormat::Pretty) { Some(true) } else { None },
+        recently_changed,
+        depth,
+        match_mode,
+        tag,
+        sig,
+        cursor: None,
     };
     
     let response = client.send_command(cmd).await?;
     print_find_response(response, format, pretty, all)
 }
 
+pub async fn fuzzy_find(
+    query: String,
+    kind: Option<String>,
+    vis: Option<String>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let cmd = Command::FuzzyFind { query, kind, vis, limit: None, cursor: None };
+    let response = client.send_command(cmd).await?;
+    print_search_response(response, format, pretty)
+}
+
+pub async fn pick(query: String, kind: Option<String>) -> Result<u8> {
+    if !std::io::stderr().is_terminal() {
+        eprintln!("Error: ct pick needs a terminal to draw on (stderr is redirected)");
+        return Ok(EXIT_INVALID_ARGS);
+    }
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    match crate::pick::pick(&mut client, query, kind).await? {
+        Some(path) => {
+            println!("{}", path);
+            Ok(EXIT_OK)
+        }
+        None => Ok(EXIT_CANCELLED),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn search(
+    query: String,
+    kind: Option<String>,
+    vis: Option<String>,
+    limit: Option<usize>,
+    all: bool,
+    docs: bool,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let cmd = if docs {
+        Command::SearchDocs { query, limit }
+    } else {
+        Command::Search {
+            query,
+            kind,
+            vis,
+            limit,
+            all: if all { Some(true) } else { None },
+            cursor: None,
+        }
+    };
+    
+    let response = client.send_command(cmd).await?;
+    if docs {
+        return print_response(response, format, pretty);
+    }
+    print_search_response(response, format, pretty)
+}
+
+/// Ranked hits of `search` and `find --fuzzy`, kept in rank order.
+fn print_search_response(response: Response, format: OutputFormat, pretty: bool) -> Result<u8> {
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let found: SearchData = env.data_as()?;
+            let symbols: Vec<SymbolItem> = found
+                .items
+                .into_iter()
+                .map(|item| {
+                    let mut fields = item.fields;
+                    fields.insert("kind".to_string(), json!(item.kind));
+                    SymbolItem { path: item.path, span_start: item.span_start, span_end: item.span_end, fields }
+                })
+                .collect();
+            print!("{}", render_symbols(&symbols, false, Style::detect()));
+            note_next_cursor(env);
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+pub async fn grep(
+    pattern: String,
+    fields: Vec<GrepIn>,
+    regex: bool,
+    limit: Option<usize>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let fields = fields
+        .into_iter()
+        .map(|field| match field {
+            GrepIn::Name => GrepField::Name,
+            GrepIn::Sig => GrepField::Sig,
+            GrepIn::Docs => GrepField::Docs,
+        })
+        .collect();
+    let response = client.send_command(Command::Grep { pattern, fields, regex, limit }).await?;
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let hits: Vec<GrepHit> = serde_json::from_value(env.data["items"].clone())?;
+            print!("{}", render_grep(&hits, Style::detect()));
+            if env.data["truncated"].as_bool().unwrap_or(false) {
+                eprintln!("Showing the first {} hits; raise --limit or narrow the pattern", hits.len());
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// One `path  field: snippet` line per hit, paths padded to line up.
+fn render_grep(hits: &[GrepHit], style: Style) -> String {
+    let width = hits.iter().map(|hit| hit.path.len()).max().unwrap_or(0);
+    hits.iter()
+        .map(|hit| {
+            let field = style.dim(&format!("{}:", hit.field));
+            format!("{:<width$}  {} {}\n", hit.path, field, hit.snippet, width = width)
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn doc(
     path: String,
     include_docs: bool,
+    examples: bool,
     vis: Option<String>,
     unimplemented: bool,
     todo: bool,
+    page: bool,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -55,15 +233,43 @@ pub async fn doc(
     let cmd = Command::Doc {
         path,
         include_docs,
+        examples,
         vis,
         unimplemented: if unimplemented { Some(true) } else { None },
         todo: if todo { Some(true) } else { None },
     };
     
     let response = client.send_command(cmd).await?;
-    print_response(response, format, pretty)
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            pager::show(&render_doc(&env.data, Style::detect())?, page)?;
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// The symbol's path and highlighted signature, then its docs and examples
+/// when they were asked for.
+fn render_doc(data: &Value, style: Style) -> Result<String> {
+    let symbol = &data["symbol"];
+    let text = |key: &str| symbol[key].as_str().unwrap_or_default();
+    let mut out = format!("{}\n{}", style.heading(text("path")), style.code(&format!("{}\n", text("signature")), None));
+    if !text("docs").is_empty() {
+        out.push_str(&format!("\n{}\n", text("docs").trim_end()));
+    }
+    let examples: Vec<DocExample> = match data.get("examples") {
+        Some(examples) => serde_json::from_value(examples.clone())?,
+        None => vec![],
+    };
+    for (number, example) in examples.iter().enumerate() {
+        let code = format!("{}\n", example.code.trim_end());
+        out.push_str(&format!("\n{}\n{}", style.heading(&format!("Example {}", number + 1)), style.code(&code, Some(&example.lang))));
+    }
+    Ok(out)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn ls(
     path: String,
     expansion: String,
@@ -73,6 +279,7 @@ pub async fn ls(
     unimplemented: bool,
     todo: bool,
     _max_size: Option<usize>,
+    page: bool,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -89,14 +296,24 @@ pub async fn ls(
         vis,
         unimplemented: if unimplemented { Some(true) } else { None },
         todo: if todo { Some(true) } else { None },
+        cursor: None,
     };
     
     let response = client.send_command(cmd).await?;
-    print_response(response, format, pretty)
+    show_response(response, format, pretty, page && matches!(format, OutputFormat::Pretty))
+}
+
+/// Where `ct export` writes the bundle.
+pub enum ExportTarget {
+    Stdout,
+    File(PathBuf),
+    /// One file per symbol and an `index.json` manifest
+    Dir(PathBuf),
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn export(
-    path: Vec<String>,
+    paths: Vec<String>,
     bundle: bool,
     expansion: String,
     include_docs: bool,
@@ -106,6 +323,8 @@ pub async fn export(
     todo: bool,
     with_source: bool,
     _max_size: Option<usize>,
+    target: ExportTarget,
+    page: bool,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -114,11 +333,8 @@ pub async fn export(
         Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
     };
     
-    // For now, just use the first path - we may need to update the protocol to support multiple paths
-    let single_path = path.into_iter().next().unwrap_or_default();
-    
     let cmd = Command::Export {
-        path: single_path,
+        paths,
         bundle,
         expansion: if expansion.is_empty() { None } else { Some(expansion) },
         include_docs,
@@ -130,15 +346,133 @@ pub async fn export(
     };
     
     let response = client.send_command(cmd).await?;
-    print_response(response, format, pretty)
+    let Response::Success(env) = &response else {
+        return print_response(response, format, pretty);
+    };
+    let to_json = |value: &Value| -> Result<String> {
+        Ok(if pretty { serde_json::to_string_pretty(value)? } else { serde_json::to_string(value)? })
+    };
+    match target {
+        ExportTarget::Stdout if matches!(format, OutputFormat::Pretty) => {
+            let found: ExportData = env.data_as()?;
+            pager::show(&render_export(&found.bundle, Style::detect()), page)?;
+            if found.bundle.truncated {
+                eprintln!("Some children were left out to stay within max_list");
+            }
+            Ok(EXIT_OK)
+        }
+        ExportTarget::Stdout => print_response(response, format, pretty),
+        ExportTarget::File(file) => {
+            std::fs::write(&file, to_json(&env.data)? + "\n")?;
+            let found: ExportData = env.data_as()?;
+            let count = found.bundle.symbols.len() + found.bundle.children.len();
+            eprintln!("Wrote {} symbols to {}", count, file.display());
+            Ok(EXIT_OK)
+        }
+        ExportTarget::Dir(dir) => {
+            let found: ExportData = env.data_as()?;
+            let files = export_files(&found.bundle);
+            for (file, value) in &files {
+                let path = dir.join(file);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, to_json(value)? + "\n")?;
+            }
+            eprintln!(
+                "Wrote {} symbols to {}, listed in {}",
+                files.len() - 1,
+                dir.display(),
+                dir.join(EXPORT_MANIFEST).display()
+            );
+            Ok(EXIT_OK)
+        }
+    }
+}
+
+/// Each symbol of `bundle` as a `kind path  lines a-b` line over its
+/// highlighted source, or its docs and signature when exported without
+/// source, roots first.
+fn render_export(bundle: &ExportBundle, style: Style) -> String {
+    let symbols: Vec<&ExportSymbol> = bundle.symbols.iter().chain(&bundle.children).collect();
+    let width = symbols.iter().map(|symbol| symbol.kind.len()).max().unwrap_or(0);
+    let blocks: Vec<String> = symbols
+        .iter()
+        .map(|symbol| {
+            let mut location = format!("lines {}-{}", symbol.span_start, symbol.span_end);
+            if symbol.source_stale {
+                location.push_str(", changed since indexing");
+            }
+            let code = match &symbol.source {
+                Some(source) => format!("{}\n", source.trim_end()),
+                None => {
+                    let docs = symbol.docs.iter().flat_map(|docs| docs.lines());
+                    let mut code: String = docs.map(|line| format!("/// {}\n", line).replace("/// \n", "///\n")).collect();
+                    code.push_str(&format!("{}\n", symbol.signature));
+                    code
+                }
+            };
+            format!("{} {}  {}\n{}", style.kind(&symbol.kind, width), symbol.path, style.dim(&location), style.code(&code, None))
+        })
+        .collect();
+    blocks.join("\n")
+}
+
+/// The manifest `ct export --out-dir` writes next to the symbol files.
+const EXPORT_MANIFEST: &str = "index.json";
+
+/// The files `ct export --out-dir` writes for `bundle`, relative to the
+/// directory: each symbol at its path with one directory per segment, and
+/// `EXPORT_MANIFEST`, the bundle with each symbol replaced by its path,
+/// kind and file. Symbols sharing a path, like a function and a module of
+/// the same name, are told apart by their kind.
+fn export_files(bundle: &ExportBundle) -> Vec<(String, Value)> {
+    // Lowercased, as two names differing only in case are one file on
+    // case-insensitive file systems
+    let mut used: HashSet<String> = HashSet::from([EXPORT_MANIFEST.to_lowercase()]);
+    let mut files = Vec::new();
+    let mut entries = |symbols: &[ExportSymbol]| -> Vec<Value> {
+        symbols
+            .iter()
+            .map(|symbol| {
+                // Segments can hold `<`, spaces and the like, e.g. in impl paths
+                let stem: Vec<String> = symbol
+                    .path
+                    .split("::")
+                    .map(|segment| {
+                        segment
+                            .chars()
+                            .map(|c| if c.is_alphanumeric() || matches!(c, '_' | '-') { c } else { '_' })
+                            .collect()
+                    })
+                    .collect();
+                let stem = stem.join("/");
+                let file = [format!("{}.json", stem), format!("{}.{}.json", stem, symbol.kind)]
+                    .into_iter()
+                    .chain((2..).map(|n| format!("{}.{}.{}.json", stem, symbol.kind, n)))
+                    .find(|file| used.insert(file.to_lowercase()))
+                    .expect("unbounded candidates");
+                files.push((file.clone(), json!(symbol)));
+                json!({"path": symbol.path, "kind": symbol.kind, "file": file})
+            })
+            .collect()
+    };
+    let mut manifest = json!(bundle);
+    manifest["symbols"] = json!(entries(&bundle.symbols));
+    manifest["children"] = json!(entries(&bundle.children));
+    files.push((EXPORT_MANIFEST.to_string(), manifest));
+    files
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn reindex(
     features: Vec<String>,
     target: Option<String>,
     module: Option<String>,
     struct_name: Option<String>,
     include_derives: bool,
+    package: Option<String>,
+    wait: bool,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -153,16 +487,50 @@ pub async fn reindex(
         module,
         struct_name,
         include_derives,
+        package,
+        wait,
     };
     
-    let response = client.send_command(cmd).await?;
+    let response = if wait {
+        client.send_command(Command::Events { enabled: true }).await?;
+        client.send_command_with_events(cmd, &mut draw_progress).await?
+    } else {
+        client.send_command(cmd).await?
+    };
     print_response(response, format, pretty)
 }
 
+/// Width of the `ct reindex --wait` progress bar, in cells.
+const PROGRESS_WIDTH: usize = 30;
+
+/// Redraw the reindex progress line on stderr for each event.
+fn draw_progress(event: Event) {
+    match event {
+        Event::ReindexProgress { crate_name, done, total } => {
+            let filled = (done * PROGRESS_WIDTH).checked_div(total).unwrap_or(PROGRESS_WIDTH);
+            eprint!(
+                "\r\x1b[K[{}{}] {}/{} {}",
+                "#".repeat(filled),
+                " ".repeat(PROGRESS_WIDTH - filled),
+                done,
+                total,
+                crate_name
+            );
+        }
+        Event::ReindexFinished { ok, duration_ms, .. } => {
+            let outcome = if ok { "Reindexed" } else { "Reindex stopped" };
+            eprintln!("\r\x1b[K{} in {}ms", outcome, duration_ms);
+        }
+        _ => {}
+    }
+}
+
 pub async fn status(
     vis: Option<String>,
     unimplemented: bool,
     todo: bool,
+    hotspots: bool,
+    group_by: Option<StatusGroup>,
     format: OutputFormat,
     pretty: bool,
 ) -> Result<u8> {
@@ -175,225 +543,1547 @@ pub async fn status(
         vis,
         unimplemented: if unimplemented { Some(true) } else { None },
         todo: if todo { Some(true) } else { None },
+        hotspots,
+        group_by,
+        cursor: None,
     };
     
     let response = client.send_command(cmd).await?;
     print_response(response, format, pretty)
 }
 
-pub async fn diag(format: OutputFormat, pretty: bool) -> Result<u8> {
+pub async fn continue_listing(cursor: String, format: OutputFormat, pretty: bool) -> Result<u8> {
     let mut client = match CtClient::connect().await {
         Ok(c) => c,
         Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
     };
     
-    let response = client.send_command(Command::Diag).await?;
+    let response = client.send_command(Command::Continue { cursor }).await?;
     print_response(response, format, pretty)
 }
 
-pub async fn bench(
-    _queries: u32,
-    _warmup: u32,
-    _duration: u32,
-    _format: OutputFormat,
-    _pretty: bool,
-) -> Result<u8> {
-    let _client = match CtClient::connect().await {
+pub async fn stats(format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
         Ok(c) => c,
         Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
     };
     
-    // TODO: Implement benchmarking
-    eprintln!("Benchmarking not yet implemented");
-    Ok(EXIT_OK)
-}
-
-fn print_find_response(
-    response: Response,
-    format: OutputFormat,
-    pretty: bool,
-    all: bool,
-) -> Result<u8> {
-    match response {
-        Response::Success(env) => {
-            match format {
-                OutputFormat::Json => {
-                    if all {
-                        // Show full symbol data
-                        let output = if pretty {
-                            serde_json::to_string_pretty(&env.data)?
-                        } else {
-                            serde_json::to_string(&env.data)?
-                        };
-                        println!("{}", output);
-                    } else {
-                        // Show only paths and spans
-                        if let Some(symbols) = env.data.get("symbols").and_then(|s| s.as_array()) {
-                            let simplified: Vec<_> = symbols.iter()
-                                .map(|s| json!({
-                                    "path": s.get("path"),
-                                    "span": s.get("span"),
-                                }))
-                                .collect();
-                            
-                            let output = if pretty {
-                                serde_json::to_string_pretty(&simplified)?
-                            } else {
-                                serde_json::to_string(&simplified)?
-                            };
-                            println!("{}", output);
-                        } else {
-                            println!("{}", if pretty {
-                                serde_json::to_string_pretty(&env.data)?
-                            } else {
-                                serde_json::to_string(&env.data)?
-                            });
-                        }
-                    }
-                }
-                OutputFormat::Pretty => {
-                    if let Some(symbols) = env.data.get("symbols").and_then(|s| s.as_array()) {
-                        for symbol in symbols {
-                            if let Some(path) = symbol.get("path").and_then(|p| p.as_str()) {
-                                println!("{}", path);
-                                if let Some(span) = symbol.get("span").and_then(|s| s.as_object()) {
-                                    if let (Some(file), Some(line), Some(col)) = (
-                                        span.get("file").and_then(|f| f.as_str()),
-                                        span.get("line").and_then(|l| l.as_u64()),
-                                        span.get("col").and_then(|c| c.as_u64())
-                                    ) {
-                                        println!("  at {}:{}:{}", file, line, col);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    let response = client.send_command(Command::Stats).await?;
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            print!("{}", render_stats(&env.data_as()?, Style::detect()));
             Ok(EXIT_OK)
         }
         _ => print_response(response, format, pretty),
     }
 }
 
-fn print_response(response: Response, _format: OutputFormat, pretty: bool) -> Result<u8> {
-    match response {
-        Response::Success(env) => {
-            let output = if pretty {
-                serde_json::to_string_pretty(&env.data)?
-            } else {
-                serde_json::to_string(&env.data)?
-            };
-            println!("{}", output);
-            Ok(EXIT_OK)
-        }
-        Response::Decision(env) => {
-            eprintln!("Decision required: {}", env.decision_required.reason);
-            eprintln!("Content length: {} bytes", env.decision_required.content_len);
-            eprintln!("Options: {:?}", env.decision_required.options);
-            Ok(EXIT_OVER_MAX)
+/// One screen: workspace totals, a row per crate, then the largest files
+/// and functions.
+fn render_stats(summary: &IndexSummary, style: Style) -> String {
+    let count = |counts: &BTreeMap<String, usize>, key: &str| counts.get(key).copied().unwrap_or(0);
+    let totals = &summary.totals;
+    let public = count(&totals.by_visibility, "public");
+    let mut out = format!(
+        "{} symbols in {} {}: {} public, {} internal, {:.0}% documented, {} unimplemented, {} todo\n\n",
+        totals.total,
+        summary.crates.len(),
+        if summary.crates.len() == 1 { "crate" } else { "crates" },
+        public,
+        totals.total - public,
+        totals.doc_coverage * 100.0,
+        count(&totals.by_status, "unimplemented"),
+        count(&totals.by_status, "todo")
+    );
+
+    let name_width = summary.crates.iter().map(|c| c.name.len()).chain([5]).max().unwrap_or(5);
+    out.push_str(&style.heading(&format!(
+        "{:<width$} {:>8} {:>7} {:>8} {:>5} {:>7} {:>5}  {}",
+        "crate", "symbols", "public", "internal", "docs", "unimpl", "todo", "most common kinds",
+        width = name_width
+    )));
+    out.push('\n');
+    for krate in &summary.crates {
+        let stats = &krate.stats;
+        let public = count(&stats.by_visibility, "public");
+        let mut kinds: Vec<_> = stats.by_kind.iter().collect();
+        kinds.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let kinds: Vec<String> = kinds.iter().take(3).map(|(kind, n)| format!("{} {}", n, kind)).collect();
+        out.push_str(&format!(
+            "{:<width$} {:>8} {:>7} {:>8} {:>4.0}% {:>7} {:>5}  {}\n",
+            krate.name,
+            stats.total,
+            public,
+            stats.total - public,
+            stats.doc_coverage * 100.0,
+            count(&stats.by_status, "unimplemented"),
+            count(&stats.by_status, "todo"),
+            style.dim(&kinds.join(", ")),
+            width = name_width
+        ));
+    }
+
+    if !summary.largest_files.is_empty() {
+        out.push_str(&format!("\n{}\n", style.heading("Largest files")));
+        for file in &summary.largest_files {
+            out.push_str(&format!(
+                "{:>6} lines  {}  {}\n",
+                file.lines,
+                file.path,
+                style.dim(&format!("{}, {} symbols", file.crate_name, file.symbols))
+            ));
         }
-        Response::Error(env) => {
-            eprintln!("Error: {}", env.err);
-            match env.err_code {
-                ErrorCode::InvalidArg => Ok(EXIT_INVALID_ARGS),
-                ErrorCode::DaemonUnavailable => Ok(EXIT_DAEMON_UNAVAILABLE),
-                ErrorCode::IndexMismatch => Ok(EXIT_INDEX_MISMATCH),
-                _ => Ok(EXIT_INTERNAL_ERROR),
-            }
+    }
+    if !summary.largest_fns.is_empty() {
+        out.push_str(&format!("\n{}\n", style.heading("Largest functions")));
+        for function in &summary.largest_fns {
+            out.push_str(&format!(
+                "{:>6} lines  {}  {}\n",
+                function.lines,
+                style.path(&function.path, None),
+                style.dim(&format!("{}:{}", function.file, function.span_start))
+            ));
         }
     }
+    out
 }
 
-pub async fn daemon(command: DaemonCommand) -> Result<u8> {
-    match command {
-        DaemonCommand::Start { idx, clean, transport } => {
-            daemon_start(idx, clean, transport).await
-        }
-        DaemonCommand::Stop => {
-            daemon_stop().await
-        }
-        DaemonCommand::Restart { idx, transport } => {
-            daemon_restart(idx, transport).await
+pub async fn verify(format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Verify).await?;
+    let stale = matches!(&response, Response::Success(env) if env.data["fresh"] == false);
+    let code = print_response(response, format, pretty)?;
+    Ok(if stale { EXIT_INDEX_MISMATCH } else { code })
+}
+
+pub async fn impls(path: String, traits: bool, external: bool, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let direction = if traits { ImplsDirection::Traits } else { ImplsDirection::Implementors };
+    let response = client.send_command(Command::Impls { path, direction, external }).await?;
+    match (&response, format.graph()) {
+        (Response::Success(env), Some(graph_format)) => {
+            // Types point at the traits they implement, as in a UML diagram
+            let impls: Vec<TraitImpl> = serde_json::from_value(env.data["impls"].clone())?;
+            let mut graph = Graph::new("impls");
+            for imp in &impls {
+                graph.edge(&imp.for_path, &imp.trait_path, None);
+            }
+            print!("{}", graph.render(graph_format));
+            Ok(EXIT_OK)
         }
-        DaemonCommand::Status => {
-            daemon_status().await
+        (Response::Success(env), None) if matches!(format, OutputFormat::Pretty) => {
+            let impls: Vec<TraitImpl> = serde_json::from_value(env.data["impls"].clone())?;
+            if impls.is_empty() && traits && !external {
+                eprintln!("No workspace traits implemented; --external includes std and dependency traits");
+            }
+            print!("{}", render_impls(&impls, Style::detect()));
+            if env.data["truncated"].as_bool().unwrap_or(false) {
+                eprintln!("Showing the first {} impls", impls.len());
+            }
+            Ok(EXIT_OK)
         }
+        _ => print_response(response, format, pretty),
     }
 }
 
-async fn daemon_start(idx: String, clean: bool, transport: String) -> Result<u8> {
-    let config = Config::load()?;
-    
-    // Get workspace fingerprint
-    let workspace_root = std::path::Path::new(&idx).canonicalize()
-        .unwrap_or_else(|_| std::path::PathBuf::from(&idx));
-    let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
-    
-    println!("Starting daemon for workspace: {}", workspace_root.display());
+/// One `file:start-end  impl Trait for Type` line per impl block, locations
+/// padded to line up.
+fn render_impls(impls: &[TraitImpl], style: Style) -> String {
+    let locations: Vec<String> = impls
+        .iter()
+        .map(|imp| format!("{}:{}-{}", imp.file, imp.line_start, imp.line_end))
+        .collect();
+    let width = locations.iter().map(String::len).max().unwrap_or(0);
+    impls
+        .iter()
+        .zip(&locations)
+        .map(|(imp, location)| {
+            let location = style.dim(&format!("{:<width$}", location, width = width));
+            format!("{}  impl {} for {}\n", location, imp.trait_path, imp.for_path)
+        })
+        .collect()
+}
+
+/// `ct callers` when `callers`, else `ct callees`.
+pub async fn call_graph(
+    path: String,
+    callers: bool,
+    depth: Option<usize>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
     
-    // Clean cache if requested
-    if clean {
-        let cache_dir = config.get_cache_dir(&workspace_fingerprint);
-        if cache_dir.exists() {
-            println!("Cleaning cache directory: {}", cache_dir.display());
-            std::fs::remove_dir_all(&cache_dir)?;
+    let command = if callers { Command::Callers { path, depth } } else { Command::Callees { path, depth } };
+    let response = client.send_command(command).await?;
+    match (&response, format.graph()) {
+        (Response::Success(env), Some(graph_format)) => {
+            let data: CallGraphData = env.data_as()?;
+            let mut graph = Graph::new(if callers { "callers" } else { "callees" });
+            graph.node(&data.path);
+            for edge in &data.edges {
+                graph.edge(&edge.caller, &edge.callee, None);
+            }
+            print!("{}", graph.render(graph_format));
+            Ok(EXIT_OK)
+        }
+        (Response::Success(env), None) if matches!(format, OutputFormat::Pretty) => {
+            let graph: CallGraphData = env.data_as()?;
+            if graph.edges.is_empty() {
+                eprintln!("No {} of {} found", if callers { "callers" } else { "callees" }, graph.path);
+            }
+            print!("{}", render_call_tree(&graph, callers, Style::detect()));
+            if graph.truncated {
+                eprintln!("Tree stops at max_list call sites; lower --depth to see whole levels");
+            }
+            Ok(EXIT_OK)
         }
+        _ => print_response(response, format, pretty),
     }
-    
-    // Remove existing socket file if it exists
-    #[cfg(unix)]
-    {
-        let socket_path = config.get_socket_path(&workspace_fingerprint);
-        if std::path::Path::new(&socket_path).exists() {
-            std::fs::remove_file(&socket_path)?;
+}
+
+/// `graph` as a tree under its root: each symbol on its own line below the
+/// one whose expansion found it, with its call sites. The walk expands each
+/// symbol once, where it was first reached; like `cargo tree`, other
+/// appearances of a symbol with calls of its own are marked `(*)`.
+fn render_call_tree(graph: &CallGraphData, callers: bool, style: Style) -> String {
+    let mut tree = CallTree { graph, callers, style, expanded: HashSet::new(), out: format!("{}\n", graph.path) };
+    tree.visit(&graph.path, 0);
+    tree.out
+}
+
+struct CallTree<'g> {
+    graph: &'g CallGraphData,
+    callers: bool,
+    style: Style,
+    expanded: HashSet<String>,
+    out: String,
+}
+
+impl<'g> CallTree<'g> {
+    fn visit(&mut self, node: &str, hop: usize) {
+        for (path, sites) in self.children(node, hop) {
+            let sites: Vec<String> = sites.iter().map(|site| format!("{}:{}", site.file, site.line_start)).collect();
+            let expand = !self.children(&path, hop + 1).is_empty() && self.expanded.insert(path.clone());
+            let shown_elsewhere = !expand && self.graph.edges.iter().any(|edge| self.found_from(edge, &path));
+            let marker = if shown_elsewhere { " (*)" } else { "" };
+            let sites = self.style.dim(&sites.join(", "));
+            self.out.push_str(&format!("{}{}  {}{}\n", "  ".repeat(hop + 1), path, sites, marker));
+            if expand {
+                self.visit(&path, hop + 1);
+            }
         }
     }
     
-    // Check if daemon is already running
-    if let Ok(mut client) = CtClient::connect().await {
-        match client.send_command(Command::Diag).await {
-            Ok(Response::Success(_)) => {
-                eprintln!("Daemon is already running");
-                return Ok(EXIT_DAEMON_ALREADY_RUNNING);
+    /// The symbols found from `node` at `hop`, each with its call sites.
+    fn children(&self, node: &str, hop: usize) -> Vec<(String, Vec<&'g CallEdge>)> {
+        let mut found: Vec<(String, Vec<&CallEdge>)> = Vec::new();
+        for edge in self.graph.edges.iter().filter(|edge| edge.depth == hop + 1 && self.found_from(edge, node)) {
+            let next = if self.callers { &edge.caller } else { &edge.callee };
+            match found.iter_mut().find(|(path, _)| path == next) {
+                Some((_, sites)) => sites.push(edge),
+                None => found.push((next.clone(), vec![edge])),
             }
-            _ => {}
         }
+        found
     }
     
-    // Start the daemon
-    // Find ct-daemon in PATH or same directory as ct
-    let daemon_path = if let Ok(exe) = std::env::current_exe() {
-        let dir = exe.parent().unwrap();
-        let daemon = dir.join("ct-daemon");
-        if daemon.exists() {
-            daemon
+    /// Whether expanding `node` found `edge`: callees are looked up by exact
+    /// caller path, callers by any spelling of the callee, as `call_graph` does.
+    fn found_from(&self, edge: &CallEdge, node: &str) -> bool {
+        if self.callers {
+            edge.callee == node
+                || edge.callee.ends_with(&format!("::{}", node))
+                || node.ends_with(&format!("::{}", edge.callee))
         } else {
-            // Fall back to PATH
-            std::path::PathBuf::from("ct-daemon")
+            edge.caller == node
         }
-    } else {
-        std::path::PathBuf::from("ct-daemon")
+    }
+}
+
+pub async fn deps(
+    crate_name: Option<String>,
+    dependents: bool,
+    depth: Option<usize>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
     };
     
-    let mut cmd = ProcessCommand::new(daemon_path);
-    cmd.arg("--idx").arg(&workspace_root);
-    cmd.arg("--transport").arg(&transport);
-    
-    if clean {
-        cmd.arg("--clean");
+    let direction = if dependents { DepsDirection::Dependents } else { DepsDirection::Dependencies };
+    let response = client.send_command(Command::Deps { crate_name, direction, depth }).await?;
+    match (&response, format.graph()) {
+        (Response::Success(env), Some(graph_format)) => {
+            let mut graph = Graph::new("deps");
+            for edge in env.data["edges"].as_array().into_iter().flatten() {
+                let text = |key: &str| edge[key].as_str().unwrap_or_default().to_string();
+                let kind = text("kind");
+                graph.edge(&text("from"), &text("to"), (kind != "normal").then_some(kind));
+            }
+            print!("{}", graph.render(graph_format));
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
     }
-    
-    // Run in background
-    cmd.stdout(std::process::Stdio::null());
-    cmd.stderr(std::process::Stdio::null());
-    cmd.stdin(std::process::Stdio::null());
-    
-    let child = cmd.spawn()?;
-    println!("Started ct-daemon with PID: {}", child.id());
-    
-    // Wait a bit for the daemon to start
-    tokio::time::sleep(tokio::time::Duration::from_millis(2000)).await;
+}
+
+pub async fn tree(
+    path: String,
+    depth: Option<usize>,
+    kinds: Vec<String>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Tree { path, depth, kinds }).await?;
+    if let (Response::Success(env), Some(table_format)) = (&response, format.table()) {
+        let tree: Vec<TreeNode> = serde_json::from_value(env.data["tree"].clone())?;
+        print!("{}", table::render(&tree_records(&tree), table_format));
+        return Ok(EXIT_OK);
+    }
+    match (&response, format.graph()) {
+        (Response::Success(env), Some(graph_format)) => {
+            let tree: Vec<TreeNode> = serde_json::from_value(env.data["tree"].clone())?;
+            print!("{}", tree_graph(&tree).render(graph_format));
+            Ok(EXIT_OK)
+        }
+        (Response::Success(env), None) if matches!(format, OutputFormat::Pretty) => {
+            let tree: Vec<TreeNode> = serde_json::from_value(env.data["tree"].clone())?;
+            if tree.is_empty() {
+                eprintln!("No symbols under {}", env.data["path"].as_str().unwrap_or_default());
+            }
+            print!("{}", render_tree(&tree, Style::detect()));
+            if env.data["truncated"].as_bool().unwrap_or(false) {
+                eprintln!("Tree stops at max_list symbols; narrow the path or lower --depth");
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// Edges from each node of `nodes` to its children.
+fn tree_graph(nodes: &[TreeNode]) -> Graph {
+    fn add(node: &TreeNode, graph: &mut Graph) {
+        graph.node(&node.path);
+        for child in &node.children {
+            graph.edge(&node.path, &child.path, None);
+            add(child, graph);
+        }
+    }
+    
+    let mut graph = Graph::new("tree");
+    for node in nodes {
+        add(node, &mut graph);
+    }
+    graph
+}
+
+/// One record per node, with its depth below its root.
+fn tree_records(nodes: &[TreeNode]) -> Vec<Value> {
+    fn walk(node: &TreeNode, depth: usize, out: &mut Vec<Value>) {
+        out.push(json!({"path": node.path, "kind": node.kind.as_str(), "depth": depth}));
+        for child in &node.children {
+            walk(child, depth + 1, out);
+        }
+    }
+    
+    let mut out = Vec::new();
+    for node in nodes {
+        walk(node, 0, &mut out);
+    }
+    out
+}
+
+/// One `kind name` line per node, indented two spaces per level; roots
+/// show their full path so it is clear where the tree hangs.
+fn render_tree(nodes: &[TreeNode], style: Style) -> String {
+    fn walk(node: &TreeNode, depth: usize, style: Style, out: &mut String) {
+        let name = if depth == 0 { &node.path } else { &node.name };
+        out.push_str(&format!("{}{} {}\n", "  ".repeat(depth), style.kind(node.kind.as_str(), 0), name));
+        for child in &node.children {
+            walk(child, depth + 1, style, out);
+        }
+    }
+    
+    let mut out = String::new();
+    for node in nodes {
+        walk(node, 0, style, &mut out);
+    }
+    out
+}
+
+pub async fn refs(
+    path: String,
+    group_by: Option<RefsGroupBy>,
+    limit: Option<usize>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let group_by = group_by.map(|group_by| match group_by {
+        RefsGroupBy::File => RefsGroup::File,
+        RefsGroupBy::Crate => RefsGroup::Crate,
+    });
+    let response = client.send_command(Command::Refs { path, limit, group_by }).await?;
+    if let (Response::Success(env), Some(table_format)) = (&response, format.table()) {
+        let refs: RefsData = env.data_as()?;
+        print!("{}", table::render(&ref_records(&refs), table_format));
+        return Ok(EXIT_OK);
+    }
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let refs: RefsData = env.data_as()?;
+            print!("{}", render_refs(&refs, Style::detect()));
+            if refs.truncated {
+                eprintln!("Showing the first {} references; raise --limit for more", refs.total);
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// One record per reference site, with its file and, when grouped by crate,
+/// its crate.
+fn ref_records(refs: &RefsData) -> Vec<Value> {
+    let records = |krate: Option<&str>, files: &[RefFile]| -> Vec<Value> {
+        files
+            .iter()
+            .flat_map(|file| {
+                file.references.iter().map(move |site| {
+                    let mut record = json!({
+                        "file": file.file,
+                        "line_start": site.line_start,
+                        "line_end": site.line_end,
+                        "column": site.column,
+                        "from": site.from,
+                        "target": site.target,
+                    });
+                    if let Some(krate) = krate {
+                        record["crate"] = json!(krate);
+                    }
+                    record
+                })
+            })
+            .collect()
+    };
+    
+    let mut out = records(None, &refs.files);
+    for krate in &refs.crates {
+        out.extend(records(Some(&krate.name), &krate.files));
+    }
+    out
+}
+
+/// One `file:line:col  from` line per reference, so editors and terminals
+/// can jump to each site; files are separated by a blank line, and crates,
+/// when grouped by, head their files.
+fn render_refs(refs: &RefsData, style: Style) -> String {
+    let render_files = |files: &[RefFile], indent: &str| {
+        let blocks: Vec<String> = files
+            .iter()
+            .map(|file| {
+                file.references
+                    .iter()
+                    .map(|site| {
+                        let location = match site.column {
+                            Some(column) => format!("{}:{}:{}", file.file, site.line_start, column),
+                            None => format!("{}:{}", file.file, site.line_start),
+                        };
+                        format!("{}{}  {}\n", indent, style.dim(&location), site.from)
+                    })
+                    .collect()
+            })
+            .collect();
+        blocks.join("\n")
+    };
+    
+    if refs.total == 0 {
+        return format!("No references to {}\n", refs.path);
+    }
+    if refs.crates.is_empty() {
+        return render_files(&refs.files, "");
+    }
+    let blocks: Vec<String> = refs
+        .crates
+        .iter()
+        .map(|krate| {
+            let count: usize = krate.files.iter().map(|file| file.references.len()).sum();
+            format!("{} ({})\n{}", krate.name, count, render_files(&krate.files, "  "))
+        })
+        .collect();
+    blocks.join("\n")
+}
+
+pub async fn owner(file: String, line: u32, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    // The daemon makes paths relative to the workspace root, which need
+    // not be where we run; paths that don't exist here are sent as written
+    let located = std::env::current_dir()?.join(&file);
+    let file = if located.exists() {
+        located.to_string_lossy().into_owned()
+    } else {
+        file
+    };
+    
+    let response = client.send_command(Command::Owner { file, line }).await?;
+    print_response(response, format, pretty)
+}
+
+pub async fn open(path: String, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Locate { path }).await?;
+    let Response::Success(env) = &response else {
+        return print_response(response, format, pretty);
+    };
+    let located: LocateData = env.data_as()?;
+    let Some(command) = editor::command(Path::new(&located.absolute_file), located.span_start) else {
+        eprintln!("No editor to open {} with; set CT_EDITOR (e.g. \"code -g {{file}}:{{line}}\"), $VISUAL or $EDITOR", located.file);
+        return Ok(EXIT_INVALID_ARGS);
+    };
+    editor::run(&command)?;
+    Ok(EXIT_OK)
+}
+
+pub async fn todo(matching: Option<String>, limit: Option<usize>, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Todo { matching, limit }).await?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    if let (Response::Success(env), Some(table_format)) = (&response, format.table()) {
+        let todo: TodoData = env.data_as()?;
+        print!("{}", table::render(&todo_records(&todo, now), table_format));
+        return Ok(EXIT_OK);
+    }
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let todo: TodoData = env.data_as()?;
+            print!("{}", render_todo(&todo, now, Style::detect()));
+            if todo.truncated {
+                eprintln!("Showing the first {} markers; raise --limit for more", todo.total);
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// One record per marker, with its age in whole days when it is known.
+fn todo_records(todo: &TodoData, now: i64) -> Vec<Value> {
+    todo.items
+        .iter()
+        .map(|item| {
+            json!({
+                "file": item.file,
+                "line": item.line,
+                "marker": item.marker,
+                "message": item.message,
+                "path": item.path,
+                "age_days": item.introduced.map(|introduced| (now - introduced).max(0) / 86_400),
+            })
+        })
+        .collect()
+}
+
+/// One `file:line  marker  path  message  age` line per marker, aligned up
+/// to the message.
+fn render_todo(todo: &TodoData, now: i64, style: Style) -> String {
+    if todo.items.is_empty() {
+        return "No todo!, unimplemented! or TODO/FIXME markers\n".to_string();
+    }
+    let location = |item: &TodoItem| format!("{}:{}", item.file, item.line);
+    let location_width = todo.items.iter().map(|item| location(item).len()).max().unwrap_or(0);
+    let marker_width = todo.items.iter().map(|item| item.marker.len()).max().unwrap_or(0);
+    let path_width = todo.items.iter().map(|item| item.path.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for item in &todo.items {
+        let mut line = format!(
+            "{}  {:<marker_width$}  {}",
+            style.dim(&format!("{:<width$}", location(item), width = location_width)),
+            item.marker,
+            style.path(&format!("{:<width$}", item.path, width = path_width), None),
+            marker_width = marker_width
+        );
+        if !item.message.is_empty() {
+            line.push_str(&format!("  {}", item.message));
+        }
+        if let Some(introduced) = item.introduced {
+            line.push_str(&format!("  {}", style.dim(&format!("({})", format_age(now - introduced)))));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// `seconds` as the largest whole unit that fits, e.g. "3 weeks".
+fn format_age(seconds: i64) -> String {
+    let days = seconds.max(0) / 86_400;
+    let (count, unit) = match days {
+        0 => return "today".to_string(),
+        1..=13 => (days, "day"),
+        14..=59 => (days / 7, "week"),
+        60..=729 => (days / 30, "month"),
+        _ => (days / 365, "year"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+pub async fn tag(
+    path: String,
+    add: Vec<String>,
+    remove: Vec<String>,
+    note: Option<String>,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Tag { path, add, remove, note }).await?;
+    print_response(response, format, pretty)
+}
+
+pub async fn diag(format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let response = client.send_command(Command::Diag).await?;
+    print_response(response, format, pretty)
+}
+
+pub async fn diff(baseline: Option<String>, breaking_only: bool, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    // The daemon resolves relative snapshot paths against the workspace
+    // root, so files are sent absolute; anything else must be a git ref
+    let mut checkout = None;
+    let snapshot = match &baseline {
+        None => None,
+        Some(file) if Path::new(file).is_file() => Some(std::path::absolute(file)?),
+        Some(git_ref) => {
+            let root = find_workspace_root(&workspace_search_start()?)?;
+            match BaselineCheckout::index(&root, git_ref) {
+                Ok(indexed) => Some(checkout.insert(indexed).snapshot()),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    return Ok(EXIT_INVALID_ARGS);
+                }
+            }
+        }
+    };
+    
+    let cmd = Command::Diff {
+        baseline: snapshot.map(|snapshot| snapshot.to_string_lossy().into_owned()),
+        breaking_only: breaking_only.then_some(true),
+    };
+    let mut response = client.send_command(cmd).await?;
+    drop(checkout);
+    // Report the baseline as given rather than where it was indexed to
+    if let (Response::Success(env), Some(baseline)) = (&mut response, &baseline) {
+        env.data["baseline"] = json!(baseline);
+    }
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let diff: DiffData = env.data_as()?;
+            let since = match (&diff.baseline, diff.baseline_epoch) {
+                (Some(baseline), _) => baseline.clone(),
+                (None, Some(epoch)) => format!("epoch {}", epoch),
+                (None, None) => "the baseline".to_string(),
+            };
+            let heading = format!(
+                "Since {}: {} added, {} removed, {} changed",
+                since, diff.added, diff.removed, diff.changed
+            );
+            print!("{}", render_changes(&heading, &diff.changes, Style::detect()));
+            if diff.truncated {
+                eprintln!("Showing the first {} changes (max_list)", diff.changes.len());
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// A git ref checked out to a temporary worktree and indexed into a
+/// snapshot next to it, both removed on drop.
+struct BaselineCheckout {
+    repo: PathBuf,
+    dir: PathBuf,
+}
+
+impl BaselineCheckout {
+    /// Check out `git_ref` of the repository holding `workspace_root` and
+    /// index the same workspace in it with `ct-daemon --snapshot`.
+    fn index(workspace_root: &Path, git_ref: &str) -> Result<Self> {
+        let git = |args: &[&str]| -> Result<String> {
+            let output = ProcessCommand::new("git").arg("-C").arg(workspace_root).args(args).output().context("Failed to run git")?;
+            if !output.status.success() {
+                bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        let commit = git(&["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", git_ref)])
+            .map_err(|_| anyhow::anyhow!("{} is neither a file nor a git ref", git_ref))?;
+        eprintln!("Indexing {} as the baseline...", git_ref);
+        let repo = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?);
+        // The workspace may sit below the repository root
+        let within = workspace_root.canonicalize()?.strip_prefix(repo.canonicalize()?)?.to_path_buf();
+        
+        let dir = std::env::temp_dir().join(format!("ct-diff-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let checkout = Self { repo, dir };
+        let tree = checkout.dir.join("tree");
+        let tree_arg = tree.to_string_lossy();
+        // A run stopped by Ctrl-C never removes its worktree; once the temp
+        // directory is cleared, prune forgets it rather than listing it forever
+        git(&["worktree", "prune"])?;
+        git(&["worktree", "add", "--detach", "--quiet", &tree_arg, &commit])?;
+        
+        let status = ProcessCommand::new(daemon_path())
+            .arg("--idx")
+            .arg(tree.join(within))
+            .arg("--snapshot")
+            .arg(checkout.snapshot())
+            .status()
+            .context("Failed to run ct-daemon")?;
+        if !status.success() {
+            bail!("Indexing {} failed", git_ref);
+        }
+        Ok(checkout)
+    }
+    
+    fn snapshot(&self) -> PathBuf {
+        self.dir.join("baseline.db")
+    }
+}
+
+impl Drop for BaselineCheckout {
+    fn drop(&mut self) {
+        let _ = ProcessCommand::new("git")
+            .arg("-C")
+            .arg(&self.repo)
+            .args(["worktree", "remove", "--force"])
+            .arg(self.dir.join("tree"))
+            .output();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub async fn watcher(command: WatcherCommand, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let action = match command {
+        WatcherCommand::Pause => WatcherAction::Pause,
+        WatcherCommand::Resume => WatcherAction::Resume,
+        WatcherCommand::Status => WatcherAction::Status,
+    };
+    
+    let response = client.send_command(Command::WatcherControl { action }).await?;
+    print_response(response, format, pretty)
+}
+
+/// `ct watch`: print each batch of symbol changes under `path_prefix` as
+/// reindexes make them. JSON output is one event per line.
+pub async fn watch(path_prefix: Option<String>, vis: Option<String>, format: OutputFormat, pretty: bool) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    let path_prefix = path_prefix.unwrap_or_default();
+    let response = client.send_command(Command::Subscribe { path_prefix: path_prefix.clone() }).await?;
+    if !matches!(response, Response::Success(_)) {
+        return print_response(response, format, pretty);
+    }
+    let watched = if path_prefix.is_empty() { "the workspace" } else { &path_prefix };
+    eprintln!("Watching {} for symbol changes; Ctrl-C to stop", watched);
+    
+    let style = Style::detect();
+    let mut first = true;
+    loop {
+        let Ok(mut event) = client.next_event().await else {
+            eprintln!("The daemon stopped; no more changes will arrive");
+            return Ok(EXIT_DAEMON_UNAVAILABLE);
+        };
+        let Event::SymbolsChanged { epoch, changes, truncated } = &mut event else {
+            continue;
+        };
+        if let Some(vis) = &vis {
+            changes.retain(|change| &change.visibility == vis);
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        let (epoch, truncated) = (*epoch, *truncated);
+        match (format, format.table()) {
+            (_, Some(table_format)) => {
+                let rendered = table::render(&change_records(epoch, changes), table_format);
+                // One header for the whole stream
+                let rows = match table_format {
+                    table::TableFormat::Csv | table::TableFormat::Tsv if !first => {
+                        rendered.split_once('\n').map_or("", |(_, rows)| rows)
+                    }
+                    _ => &rendered,
+                };
+                print!("{}", rows);
+            }
+            (OutputFormat::Pretty, None) => print!("{}", render_changes(&format!("Reindex {}", epoch), changes, style)),
+            _ => {
+                let output = if pretty { serde_json::to_string_pretty(&event)? } else { serde_json::to_string(&event)? };
+                println!("{}", output);
+            }
+        }
+        std::io::Write::flush(&mut std::io::stdout())?;
+        if truncated {
+            eprintln!("Reindex {} changed more symbols than one event carries; some are left out", epoch);
+        }
+        first = false;
+    }
+}
+
+/// One record per change, every one with the same columns so a stream of
+/// batches shares one header.
+fn change_records(epoch: u64, changes: &[SymbolChange]) -> Vec<Value> {
+    changes
+        .iter()
+        .map(|change| {
+            json!({
+                "epoch": epoch,
+                "change": change.change,
+                "path": change.path,
+                "kind": change.kind,
+                "visibility": change.visibility,
+                "signature": change.signature,
+                "previous_signature": change.previous_signature.as_deref().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// `heading`, then one `+|-|~ kind path  signature` line per change, kinds
+/// and paths padded to line up and symbols that aren't public marked so;
+/// changed symbols show their previous signature under them when it
+/// differs.
+fn render_changes(heading: &str, changes: &[SymbolChange], style: Style) -> String {
+    let kind_width = changes.iter().map(|change| change.kind.len()).max().unwrap_or(0);
+    let path_width = changes.iter().map(|change| change.path.len()).max().unwrap_or(0);
+    let mut out = format!("{}\n", style.dim(heading));
+    for change in changes {
+        let path = format!("{:<width$}", change.path, width = path_width);
+        let marker = match change.visibility.as_str() {
+            "public" => String::new(),
+            visibility => format!("  {}", style.dim(&format!("({})", visibility))),
+        };
+        out.push_str(&format!(
+            "{} {} {}  {}{}\n",
+            style.change(change.change),
+            style.kind(&change.kind, kind_width),
+            style.path(&path, Some(&change.visibility)),
+            style.code(&change.signature, None),
+            marker
+        ));
+        if let Some(previous) = change.previous_signature.as_ref().filter(|previous| **previous != change.signature) {
+            out.push_str(&format!("{}{}\n", " ".repeat(kind_width + 3), style.dim(&format!("was {}", previous))));
+        }
+    }
+    out
+}
+
+pub async fn bench(
+    queries: u32,
+    warmup: u32,
+    duration: u32,
+    format: OutputFormat,
+    pretty: bool,
+) -> Result<u8> {
+    let mut client = match CtClient::connect().await {
+        Ok(c) => c,
+        Err(_) => return Ok(EXIT_DAEMON_UNAVAILABLE),
+    };
+    
+    eprintln!("Benchmarking {} queries for {}s after a {}ms warmup...", queries, duration, warmup);
+    let response = client.send_command(Command::Bench { queries, warmup, duration }).await?;
+    match (&response, format) {
+        (Response::Success(env), OutputFormat::Pretty) => {
+            let report: BenchData = env.data_as()?;
+            print!("{}", render_bench(&report));
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// A table of `report`'s latencies, overall and per command.
+fn render_bench(report: &BenchData) -> String {
+    let row = |name: &str, samples: u64, latency: &Latency| {
+        format!(
+            "{:<8} {:>8} {:>9.2} {:>9.2} {:>9.2} {:>9.2}\n",
+            name, samples, latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms
+        )
+    };
+    let mut out = format!(
+        "{} queries in {:.1}s: {:.0} qps, {} errors\n\n",
+        report.samples,
+        report.duration_ms as f64 / 1000.0,
+        report.throughput_qps,
+        report.errors
+    );
+    out.push_str(&format!(
+        "{:<8} {:>8} {:>9} {:>9} {:>9} {:>9}\n",
+        "command", "samples", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    ));
+    for command in &report.commands {
+        out.push_str(&row(&command.command, command.samples, &command.latency));
+    }
+    out.push_str(&row("all", report.samples, &report.latency));
+    out
+}
+
+fn print_find_response(
+    response: Response,
+    format: OutputFormat,
+    pretty: bool,
+    all: bool,
+) -> Result<u8> {
+    match response {
+        Response::Success(env) => {
+            match (format, format.table()) {
+                (_, Some(table_format)) => {
+                    let records: Vec<Value> = if all {
+                        table::records(&env.data)
+                    } else {
+                        let found: FindData = env.data_as()?;
+                        found.items.iter().map(find_summary).collect()
+                    };
+                    print!("{}", table::render(&records, table_format));
+                }
+                (OutputFormat::Pretty, None) => {
+                    let found: FindData = env.data_as()?;
+                    print!("{}", render_symbols(&found.items, all, Style::detect()));
+                }
+                // Symbols are not a graph; graph formats fall back to JSON
+                _ => {
+                    if all {
+                        // Show full symbol data
+                        let output = if pretty {
+                            serde_json::to_string_pretty(&env.data)?
+                        } else {
+                            serde_json::to_string(&env.data)?
+                        };
+                        println!("{}", output);
+                    } else {
+                        // Show only paths and spans
+                        let found: FindData = env.data_as()?;
+                        let simplified: Vec<_> = found.items.iter()
+                            .map(find_summary)
+                            .collect();
+                        
+                        let output = if pretty {
+                            serde_json::to_string_pretty(&simplified)?
+                        } else {
+                            serde_json::to_string(&simplified)?
+                        };
+                        println!("{}", output);
+                    }
+                }
+            }
+            note_next_cursor(&env);
+            if pretty {
+                note_metrics(&env);
+            }
+            Ok(EXIT_OK)
+        }
+        _ => print_response(response, format, pretty),
+    }
+}
+
+/// One `kind path  lines a-b` line per symbol, kinds and paths padded to
+/// line up and paths styled by visibility; with `signatures`, each
+/// symbol's signature is highlighted under it.
+fn render_symbols(symbols: &[SymbolItem], signatures: bool, style: Style) -> String {
+    let text = |symbol: &SymbolItem, key: &str| symbol.fields.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+    let kind_width = symbols.iter().map(|symbol| text(symbol, "kind").len()).max().unwrap_or(0);
+    let path_width = symbols.iter().map(|symbol| symbol.path.len()).max().unwrap_or(0);
+    symbols
+        .iter()
+        .map(|symbol| {
+            let kind = match kind_width {
+                0 => String::new(),
+                width => format!("{} ", style.kind(&text(symbol, "kind"), width)),
+            };
+            let visibility = text(symbol, "visibility");
+            let path = format!("{:<width$}", symbol.path, width = path_width);
+            let lines = format!("lines {}-{}", symbol.span_start, symbol.span_end);
+            let mut out = format!("{}{}  {}\n", kind, style.path(&path, Some(&visibility)), style.dim(&lines));
+            let signature = text(symbol, "signature");
+            if signatures && !signature.is_empty() {
+                out.push_str(&style.code(&format!("    {}\n", signature), None));
+            }
+            out
+        })
+        .collect()
+}
+
+/// What `find` shows of a symbol without `--all`.
+fn find_summary(symbol: &SymbolItem) -> Value {
+    json!({
+        "path": symbol.path,
+        "span_start": symbol.span_start,
+        "span_end": symbol.span_end,
+    })
+}
+
+/// Write the script registering `shell`'s completions.
+pub fn completions(shell: Shell) -> Result<u8> {
+    print!("{}", completion_script(shell)?);
+    Ok(EXIT_OK)
+}
+
+/// clap's script registering `shell`'s completions, which asks `ct` itself
+/// for candidates through `COMPLETE=<shell> ct -- <args>` as the user types.
+fn completion_script(shell: Shell) -> Result<String> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .with_context(|| format!("No completions for {}", shell))?;
+    let mut script = Vec::new();
+    completer.write_registration("COMPLETE", "ct", "ct", "ct", &mut script)?;
+    Ok(String::from_utf8(script)?)
+}
+
+/// Complete a symbol path from the daemon: the children of the path before
+/// its last `::` whose path starts with `current`, or the crates whose name
+/// does. Completes nothing if no daemon is running or it cannot answer, as
+/// a shell completing should neither start one nor show errors.
+pub fn complete_path(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(prefix) = current.to_str() else {
+        return Vec::new();
+    };
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread().enable_all().build() else {
+        return Vec::new();
+    };
+    let paths = runtime.block_on(symbol_paths(prefix)).unwrap_or_default();
+    paths.into_iter().map(CompletionCandidate::new).collect()
+}
+
+async fn symbol_paths(prefix: &str) -> Result<BTreeSet<String>> {
+    let mut client = CtClient::connect_running().await?;
+    
+    let parent = prefix.rsplit_once("::").map(|(parent, _)| parent.to_string());
+    let crates_only = parent.is_none();
+    let cmd = Command::Find {
+        name: crates_only.then(|| prefix.to_string()),
+        path: parent.clone(),
+        kind: crates_only.then(|| "module".to_string()),
+        vis: None,
+        unimplemented: None,
+        todo: None,
+        all: None,
+        recently_changed: None,
+        depth: parent.is_some().then_some(1),
+        match_mode: crates_only.then(|| "prefix".to_string()),
+        tag: None,
+        sig: None,
+        cursor: None,
+    };
+    let Response::Success(env) = client.send_command(cmd).await? else {
+        return Ok(Default::default());
+    };
+    let found: FindData = env.data_as()?;
+    Ok(found
+        .items
+        .into_iter()
+        .map(|symbol| symbol.path)
+        .filter(|path| match &parent {
+            Some(parent) => path != parent && path.starts_with(prefix),
+            None => !path.contains("::"),
+        })
+        .collect())
+}
+
+/// Tell the user how to get the next page, on stderr so stdout stays parseable.
+fn note_next_cursor(env: &SuccessEnvelope) {
+    if let Some(cursor) = &env.next_cursor {
+        eprintln!("More results: ct continue {}", cursor);
+    }
+}
+
+/// Show what answering took, on stderr like `note_next_cursor`.
+fn note_metrics(env: &SuccessEnvelope) {
+    let Some(metrics) = &env.metrics else {
+        return;
+    };
+    let mut parts = vec![format!("{} ms", metrics.elapsed_ms), format!("{} bytes", metrics.bytes)];
+    if let Some(steps) = metrics.db_steps {
+        parts.push(format!("~{} db steps", steps));
+    }
+    if let Some(cache) = metrics.cache {
+        parts.push(format!("snippet cache {}/{} hits", cache.hits, cache.hits + cache.misses));
+    }
+    eprintln!("Took {}", parts.join(", "));
+}
+
+fn print_response(response: Response, format: OutputFormat, pretty: bool) -> Result<u8> {
+    show_response(response, format, pretty, false)
+}
+
+/// `print_response`, paging a successful response's data when `page` is set.
+fn show_response(response: Response, format: OutputFormat, pretty: bool, page: bool) -> Result<u8> {
+    match response {
+        Response::Success(env) => {
+            let output = if let Some(table_format) = format.table() {
+                table::render(&table::records(&env.data), table_format)
+            } else if pretty {
+                serde_json::to_string_pretty(&env.data)? + "\n"
+            } else {
+                serde_json::to_string(&env.data)? + "\n"
+            };
+            pager::show(&output, page)?;
+            note_next_cursor(&env);
+            if pretty {
+                note_metrics(&env);
+            }
+            Ok(EXIT_OK)
+        }
+        Response::Decision(env) => {
+            eprintln!("Decision required: {}", env.decision_required.reason);
+            eprintln!("Content length: {} bytes", env.decision_required.content_len);
+            eprintln!("Options: {:?}", env.decision_required.options);
+            Ok(EXIT_OVER_MAX)
+        }
+        Response::Error(env) => {
+            eprintln!("Error: {}", env.err);
+            if let Some(hint) = &env.hint {
+                eprintln!("Hint: {}", hint);
+            }
+            match env.err_code {
+                ErrorCode::InvalidArg => Ok(EXIT_INVALID_ARGS),
+                ErrorCode::DaemonUnavailable => Ok(EXIT_DAEMON_UNAVAILABLE),
+                ErrorCode::IndexMismatch => Ok(EXIT_INDEX_MISMATCH),
+                ErrorCode::Busy => Ok(EXIT_BUSY),
+                _ => Ok(EXIT_INTERNAL_ERROR),
+            }
+        }
+    }
+}
+
+pub fn db(command: DbCommand, pretty: bool) -> Result<u8> {
+    match command {
+        DbCommand::Migrate => db_migrate(pretty),
+        DbCommand::Maintain => db_maintain(pretty),
+    }
+}
+
+/// The current workspace's database file. Like every `ct db` subcommand this
+/// works on the file directly, so it runs with or without a daemon.
+fn workspace_db_path() -> Result<std::path::PathBuf> {
+    let config = Config::load()?;
+    let start = workspace_search_start()?;
+    let workspace_root = find_workspace_root(&start).unwrap_or(start);
+    let workspace_id = compute_workspace_id(&workspace_root);
+    let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
+    
+    if config.migrate_cache_dir(&workspace_id, &workspace_fingerprint)? {
+        eprintln!("Moved cache directory {} to {}", workspace_id, workspace_fingerprint);
+    }
+    
+    Ok(config.get_db_path(&workspace_fingerprint))
+}
+
+/// Print a failure of a `ct db` subcommand and pick its exit code.
+fn report_db_error(e: ct_db::DbError) -> u8 {
+    eprintln!("Error: {}", e);
+    match e {
+        ct_db::DbError::Busy(_) => {
+            eprintln!("The daemon may be writing to the index; try again once it is done");
+            EXIT_BUSY
+        }
+        _ => EXIT_INTERNAL_ERROR,
+    }
+}
+
+fn db_migrate(pretty: bool) -> Result<u8> {
+    let db_path = workspace_db_path()?;
+    if !db_path.exists() {
+        eprintln!("No index database at {}; nothing to migrate", db_path.display());
+        return Ok(EXIT_OK);
+    }
+    
+    let report = match ct_db::Database::open_migrated(&db_path) {
+        Ok((_, report)) => report,
+        Err(e) => return Ok(report_db_error(e)),
+    };
+    
+    let data = json!({
+        "db_path": db_path,
+        "from_version": report.from_version,
+        "to_version": report.to_version,
+        "applied": report.applied.iter().map(|m| json!({
+            "version": m.version,
+            "description": m.description,
+        })).collect::<Vec<_>>(),
+    });
+    println!("{}", if pretty {
+        serde_json::to_string_pretty(&data)?
+    } else {
+        serde_json::to_string(&data)?
+    });
+    Ok(EXIT_OK)
+}
+
+fn db_maintain(pretty: bool) -> Result<u8> {
+    let db_path = workspace_db_path()?;
+    if !db_path.exists() {
+        eprintln!("No index database at {}; nothing to maintain", db_path.display());
+        return Ok(EXIT_OK);
+    }
+    
+    let file_size = |path: &std::path::Path| std::fs::metadata(path).map_or(0, |m| m.len());
+    let mut wal_path = db_path.clone().into_os_string();
+    wal_path.push("-wal");
+    let wal_path = std::path::PathBuf::from(wal_path);
+    let bytes_before = file_size(&db_path) + file_size(&wal_path);
+    
+    let report = match ct_db::Database::open(&db_path).and_then(|db| db.maintain()) {
+        Ok(report) => report,
+        Err(e) => return Ok(report_db_error(e)),
+    };
+    if report.wal_busy {
+        eprintln!("WAL is in use by another connection (is the daemon reindexing?); it was not truncated");
+    }
+    
+    let data = json!({
+        "db_path": db_path,
+        "freed_pages": report.freed_pages,
+        "full_vacuum": report.full_vacuum,
+        "checkpointed_frames": report.checkpointed_frames,
+        "wal_busy": report.wal_busy,
+        "bytes_before": bytes_before,
+        "bytes_after": file_size(&db_path) + file_size(&wal_path),
+    });
+    println!("{}", if pretty {
+        serde_json::to_string_pretty(&data)?
+    } else {
+        serde_json::to_string(&data)?
+    });
+    Ok(EXIT_OK)
+}
+
+/// Delete cache directories last used before `older_than` ago (or all of
+/// them), keeping the current workspace's while its daemon is running, and
+/// remove sockets no daemon listens on anymore.
+pub async fn clean(all: bool, older_than: std::time::Duration, dry_run: bool, pretty: bool) -> Result<u8> {
+    let config = Config::load()?;
+    let start = workspace_search_start()?;
+    let workspace_root = find_workspace_root(&start).unwrap_or(start);
+    let workspace_id = compute_workspace_id(&workspace_root);
+    let current_fingerprint = compute_workspace_fingerprint(&workspace_root);
+    let current_live = IpcClient::connect(&config, &workspace_id).await.is_ok();
+    
+    let cutoff = std::time::SystemTime::now()
+        .checked_sub(older_than)
+        .unwrap_or(std::time::UNIX_EPOCH);
+    
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+    let mut kept = 0;
+    for dir in config.list_cache_dirs()? {
+        // A daemon of any workspace holds its pidfile; when that can't be
+        // read, the directory is kept rather than pulled from under one
+        let in_use = (current_live && dir.fingerprint == current_fingerprint)
+            || pidfile::is_running(&config.get_pid_path(&dir.fingerprint)).unwrap_or(true);
+        if in_use || !(all || dir.last_used < cutoff) {
+            kept += 1;
+            continue;
+        }
+        if !dry_run {
+            if let Err(e) = std::fs::remove_dir_all(&dir.path) {
+                failed.push(json!({"path": dir.path, "error": e.to_string()}));
+                continue;
+            }
+        }
+        removed.push(dir);
+    }
+    
+    let sockets_removed = if dry_run { vec![] } else { remove_dead_sockets()? };
+    
+    let data = json!({
+        "dry_run": dry_run,
+        "removed": removed.iter().map(|d| json!({
+            "fingerprint": d.fingerprint,
+            "path": d.path,
+            "size_bytes": d.size_bytes,
+        })).collect::<Vec<_>>(),
+        "freed_bytes": removed.iter().map(|d| d.size_bytes).sum::<u64>(),
+        "kept": kept,
+        "failed": failed,
+        "sockets_removed": sockets_removed,
+    });
+    println!("{}", if pretty {
+        serde_json::to_string_pretty(&data)?
+    } else {
+        serde_json::to_string(&data)?
+    });
+    Ok(if failed.is_empty() { EXIT_OK } else { EXIT_INTERNAL_ERROR })
+}
+
+/// Crashes `ct doctor` shows.
+const DOCTOR_CRASHES: usize = 5;
+
+/// Report on the daemon, toolchain and index for the current workspace,
+/// exiting with an error if anything stands in the way of using ct.
+pub async fn doctor(pretty: bool) -> Result<u8> {
+    let config = Config::load()?;
+    let start = workspace_search_start()?;
+    let workspace_root = find_workspace_root(&start).unwrap_or(start);
+    let workspace_id = compute_workspace_id(&workspace_root);
+    let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
+    let mut problems = Vec::new();
+    
+    let pid_path = config.get_pid_path(&workspace_fingerprint);
+    let running = pidfile::is_running(&pid_path)?;
+    let pid = running.then(|| pidfile::read_pid(&pid_path)).flatten();
+    let reachable = running && IpcClient::connect(&config, &workspace_id).await.is_ok();
+    if running && !reachable {
+        problems.push("ct-daemon is running but not accepting connections; it listens once its first index is built".to_string());
+    }
+    
+    let nightly = ProcessCommand::new("rustc")
+        .args(["+nightly", "--version"])
+        .current_dir(&workspace_root)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if nightly.is_none() {
+        problems.push("No nightly toolchain, which rustdoc JSON needs: rustup toolchain install nightly".to_string());
+    }
+    
+    let db_path = config.get_db_path(&workspace_fingerprint);
+    let schema_version = if db_path.exists() {
+        match ct_db::Database::open(&db_path).and_then(|db| db.schema_version()) {
+            Ok(version) => Some(version),
+            Err(e) => {
+                problems.push(format!("The index at {} cannot be read: {}", db_path.display(), e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    
+    let crashes = ct_core::crash::recent(&config.get_crash_log_path(&workspace_fingerprint), DOCTOR_CRASHES);
+    if let Some(last) = crashes.last().filter(|crash| crash.restart_in_secs.is_none() && !running) {
+        problems.push(format!("ct-daemon kept crashing and its supervisor gave up; last with {}: {}", last.exit, last.reason));
+    }
+    
+    let data = json!({
+        "workspace_root": workspace_root,
+        "workspace_fingerprint": workspace_fingerprint,
+        "daemon": {
+            "running": running,
+            "pid": pid,
+            "reachable": reachable,
+            "supervise": config.supervise,
+        },
+        "nightly": nightly,
+        "index": {
+            "db_path": db_path,
+            "exists": db_path.exists(),
+            "schema_version": schema_version,
+        },
+        "crashes": crashes,
+        "problems": problems,
+    });
+    println!("{}", if pretty {
+        serde_json::to_string_pretty(&data)?
+    } else {
+        serde_json::to_string(&data)?
+    });
+    Ok(if problems.is_empty() { EXIT_OK } else { EXIT_INTERNAL_ERROR })
+}
+
+/// Remove daemon sockets left behind by daemons that exited without cleaning up.
+#[cfg(unix)]
+fn remove_dead_sockets() -> Result<Vec<std::path::PathBuf>> {
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir("/tmp")? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if !(name.starts_with("ctd-") && name.ends_with(".sock")) {
+            continue;
+        }
+        if std::os::unix::net::UnixStream::connect(&path).is_err() {
+            std::fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Named pipes go away with the daemon that created them.
+#[cfg(not(unix))]
+fn remove_dead_sockets() -> Result<Vec<std::path::PathBuf>> {
+    Ok(vec![])
+}
+
+pub async fn daemon(command: DaemonCommand) -> Result<u8> {
+    match command {
+        DaemonCommand::Start { idx, clean, transport, supervise } => {
+            daemon_start(idx, clean, transport, supervise).await
+        }
+        DaemonCommand::Stop => {
+            daemon_stop().await
+        }
+        DaemonCommand::Restart { idx, transport } => {
+            daemon_restart(idx, transport).await
+        }
+        DaemonCommand::Status => {
+            daemon_status().await
+        }
+        DaemonCommand::Logs { follow, lines } => {
+            daemon_logs(follow, lines).await
+        }
+        DaemonCommand::Install { idx, print } => {
+            daemon_install(idx, print)
+        }
+        DaemonCommand::Uninstall { idx } => {
+            daemon_uninstall(idx)
+        }
+    }
+}
+
+/// The daemon service for the workspace at `idx`, or `None` where there is
+/// no per-user service manager to register with.
+fn daemon_service(idx: &str) -> Result<Option<Service>> {
+    let workspace_root = std::path::Path::new(idx).canonicalize()?;
+    let Some(platform) = Platform::current() else {
+        eprintln!("No per-user service manager is supported on this platform");
+        return Ok(None);
+    };
+    let Some(daemon) = resolve_program(&daemon_path()) else {
+        eprintln!("ct-daemon was not found next to ct or on PATH");
+        return Ok(None);
+    };
+    Ok(Some(Service::new(platform, &workspace_root, &daemon)))
+}
+
+fn daemon_install(idx: String, print: bool) -> Result<u8> {
+    let Some(service) = daemon_service(&idx)? else {
+        return Ok(EXIT_INVALID_ARGS);
+    };
+    if print {
+        if let Some(path) = service.definition_path()? {
+            eprintln!("# {}", path.display());
+        }
+        print!("{}", service.definition()?);
+        return Ok(EXIT_OK);
+    }
+    service.install()?;
+    println!("Installed {} ({:?}); it starts the daemon now and at every login", service.name, service.platform);
+    Ok(EXIT_OK)
+}
+
+fn daemon_uninstall(idx: String) -> Result<u8> {
+    let Some(service) = daemon_service(&idx)? else {
+        return Ok(EXIT_INVALID_ARGS);
+    };
+    if service.uninstall()? {
+        println!("Uninstalled {}", service.name);
+    } else {
+        println!("{} is not installed", service.name);
+    }
+    Ok(EXIT_OK)
+}
+
+async fn daemon_start(idx: String, clean: bool, transport: String, supervise: bool) -> Result<u8> {
+    let config = Config::load()?;
+    
+    let workspace_root = std::path::Path::new(&idx).canonicalize()
+        .unwrap_or_else(|_| std::path::PathBuf::from(&idx));
+    
+    // Check if daemon is already running; the daemon itself cleans the cache
+    // and removes a stale socket once it holds the PID file
+    let pid_path = config.get_pid_path(&compute_workspace_fingerprint(&workspace_root));
+    if pidfile::is_running(&pid_path)? {
+        match pidfile::read_pid(&pid_path) {
+            Some(pid) => eprintln!("Daemon is already running (PID {})", pid),
+            None => eprintln!("Daemon is already running"),
+        }
+        return Ok(EXIT_DAEMON_ALREADY_RUNNING);
+    }
+    
+    println!("Starting daemon for workspace: {}", workspace_root.display());
+    
+    let mut cmd = ProcessCommand::new(daemon_path());
+    cmd.arg("--idx").arg(&workspace_root);
+    cmd.arg("--transport").arg(&transport);
+    
+    if clean {
+        cmd.arg("--clean");
+    }
+    let supervise = supervise || config.supervise;
+    if supervise {
+        cmd.arg("--supervise");
+    }
+    
+    // Run in background
+    let child = detach(&mut cmd).spawn()?;
+    if supervise {
+        println!("Started ct-daemon under a supervisor with PID: {}", child.id());
+    } else {
+        println!("Started ct-daemon with PID: {}", child.id());
+    }
+    
+    // Wait a bit for the daemon to start
+    tokio::time::sleep(tokio::time::Duration::from_millis(2000)).await;
     
     // Verify it started - try multiple times
     for attempt in 0..5 {
@@ -428,54 +2118,71 @@ async fn daemon_start(idx: String, clean: bool, transport: String) -> Result<u8>
 }
 
 async fn daemon_stop() -> Result<u8> {
-    let _config = Config::load()?;
+    let config = Config::load()?;
+    let workspace_root = find_workspace_root(&workspace_search_start()?)?;
+    let pid_path = config.get_pid_path(&compute_workspace_fingerprint(&workspace_root));
     
-    // Try to connect to daemon
-    let mut client = match CtClient::connect().await {
-        Ok(c) => c,
-        Err(_) => {
-            println!("Daemon is not running");
-            return Ok(EXIT_OK);
-        }
-    };
-    
-    // Send shutdown signal (we'll use a diagnostic command and then kill the process)
-    let _response = client.send_command(Command::Diag).await?;
-    
-    // Get PID from process list
-    #[cfg(unix)]
-    {
-        let output = ProcessCommand::new("pgrep")
-            .arg("-f")
-            .arg("ct-daemon")
-            .output()?;
-            
-        if output.status.success() {
-            let pids = String::from_utf8_lossy(&output.stdout);
-            for pid in pids.lines() {
-                if let Ok(pid_num) = pid.trim().parse::<u32>() {
-                    ProcessCommand::new("kill")
-                        .arg(pid.trim())
-                        .output()?;
-                    println!("Stopped ct-daemon (PID: {})", pid_num);
-                }
+    if !pidfile::is_running(&pid_path)? {
+        println!("Daemon is not running");
+        return Ok(EXIT_OK);
+    }
+    let pid = pidfile::read_pid(&pid_path);
+    
+    // Ask it to shut down, so it finishes in-flight requests and cleans up
+    let asked = match CtClient::connect_running().await {
+        Ok(mut client) => matches!(client.send_command(Command::Shutdown).await, Ok(Response::Success(_))),
+        Err(_) => false,
+    };
+    
+    // On unix, a daemon that cannot be reached (still indexing, say) or does
+    // not exit in time is stopped by its PID. Windows has no signal it could
+    // shut down cleanly on, so there the protocol is the only way
+    if !asked || !wait_for_exit(&pid_path).await? {
+        let Some(pid) = pid else {
+            eprintln!("Daemon did not stop and its PID file {} has no PID", pid_path.display());
+            return Ok(EXIT_INTERNAL_ERROR);
+        };
+        #[cfg(unix)]
+        let stopped = {
+            ProcessCommand::new("kill").arg(pid.to_string()).output()?;
+            wait_for_exit(&pid_path).await?
+        };
+        #[cfg(not(unix))]
+        let stopped = false;
+        if !stopped {
+            eprintln!("ct-daemon (PID {}) did not stop", pid);
+            #[cfg(not(unix))]
+            if !asked {
+                eprintln!("It answers shutdown requests once its first index is built; try again then");
             }
+            return Ok(EXIT_INTERNAL_ERROR);
         }
     }
     
-    #[cfg(windows)]
-    {
-        ProcessCommand::new("taskkill")
-            .arg("/F")
-            .arg("/IM")
-            .arg("ct-daemon.exe")
-            .output()?;
-        println!("Stopped ct-daemon");
+    match pid {
+        Some(pid) => println!("Stopped ct-daemon (PID {})", pid),
+        None => println!("Stopped ct-daemon"),
     }
-    
     Ok(EXIT_OK)
 }
 
+/// How long `ct daemon stop` waits for the daemon to exit; longer than the
+/// daemon gives in-flight requests to finish.
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Waits for the daemon holding the PID file at `pid_path` to exit, giving
+/// up after `STOP_TIMEOUT`.
+async fn wait_for_exit(pid_path: &std::path::Path) -> Result<bool> {
+    let deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+    while pidfile::is_running(pid_path)? {
+        if tokio::time::Instant::now() >= deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    }
+    Ok(true)
+}
+
 async fn daemon_restart(idx: String, transport: String) -> Result<u8> {
     println!("Stopping daemon...");
     daemon_stop().await?;
@@ -484,7 +2191,7 @@ async fn daemon_restart(idx: String, transport: String) -> Result<u8> {
     tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     
     println!("Starting daemon with clean cache...");
-    daemon_start(idx, true, transport).await
+    daemon_start(idx, true, transport, false).await
 }
 
 async fn daemon_status() -> Result<u8> {
@@ -501,20 +2208,18 @@ async fn daemon_status() -> Result<u8> {
     match response {
         Response::Success(env) => {
             println!("Daemon is running");
-            if let Some(version) = env.data.get("version").and_then(|v| v.as_str()) {
-                println!("Version: {}", version);
+            let diag: DiagResponse = env.data_as()?;
+            println!("Version: {}", diag.tool_version);
+            println!("Workspace: {}", diag.workspace_root);
+            println!("Index timestamp: {}", diag.index_timestamp);
+            println!("Symbols: {}", diag.symbol_count);
+            println!("Crates: {}", diag.crate_count);
+            if let Some(job) = &diag.reindex.running {
+                let scope = job.package.as_deref().unwrap_or("workspace");
+                println!("Reindexing: {} (job {}, {}/{} crates)", scope, job.id, job.done, job.total);
             }
-            if let Some(workspace) = env.data.get("workspace_root").and_then(|w| w.as_str()) {
-                println!("Workspace: {}", workspace);
-            }
-            if let Some(timestamp) = env.data.get("index_timestamp").and_then(|t| t.as_str()) {
-                println!("Index timestamp: {}", timestamp);
-            }
-            if let Some(symbols) = env.data.get("num_symbols").and_then(|s| s.as_u64()) {
-                println!("Symbols: {}", symbols);
-            }
-            if let Some(crates) = env.data.get("num_crates").and_then(|c| c.as_u64()) {
-                println!("Crates: {}", crates);
+            if !diag.reindex.queued.is_empty() {
+                println!("Queued reindexes: {}", diag.reindex.queued.len());
             }
             Ok(EXIT_OK)
         }
@@ -523,4 +2228,545 @@ async fn daemon_status() -> Result<u8> {
             Ok(EXIT_INTERNAL_ERROR)
         }
     }
-}
\ No newline at end of file
+}
+
+async fn daemon_logs(follow: bool, lines: usize) -> Result<u8> {
+    let config = Config::load()?;
+    let workspace_root = find_workspace_root(&workspace_search_start()?)?;
+    let log_dir = config.get_log_dir(&compute_workspace_fingerprint(&workspace_root));
+    
+    let Some(mut path) = latest_log_file(&log_dir) else {
+        println!("No daemon logs in {}", log_dir.display());
+        return Ok(EXIT_OK);
+    };
+    let (last, mut offset) = tail_lines(&path, lines)?;
+    for line in last {
+        println!("{}", render_log_line(&line));
+    }
+    if !follow {
+        return Ok(EXIT_OK);
+    }
+    
+    // Poll for appended lines, moving on to the next file when the log rotates
+    let mut partial = String::new();
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        let mut appended = read_from_offset(&path, &mut offset)?;
+        if appended.is_empty() {
+            match latest_log_file(&log_dir) {
+                Some(latest) if latest != path => {
+                    path = latest;
+                    offset = 0;
+                    partial.clear();
+                    appended = read_from_offset(&path, &mut offset)?;
+                }
+                _ => continue,
+            }
+        }
+        partial.push_str(&String::from_utf8_lossy(&appended));
+        // Only print complete lines; the rest waits for the next read
+        while let Some(end) = partial.find('\n') {
+            println!("{}", render_log_line(partial[..end].trim_end_matches('\r')));
+            partial.drain(..=end);
+        }
+    }
+}
+
+/// The daemon's current log file in `log_dir`. Rotated files are suffixed
+/// with their date, so the newest sorts last.
+fn latest_log_file(log_dir: &std::path::Path) -> Option<std::path::PathBuf> {
+    std::fs::read_dir(log_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("ct-daemon.log"))
+        })
+        .max()
+}
+
+/// The last `lines` lines of the file at `path` and its length, reading
+/// blocks back from its end until they hold enough lines.
+fn tail_lines(path: &std::path::Path, lines: usize) -> Result<(Vec<String>, u64)> {
+    use std::io::{Read, Seek, SeekFrom};
+    const BLOCK: u64 = 8 * 1024;
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    
+    // One newline more than `lines` marks where the first of them starts;
+    // a newline ending the file does not start a line
+    let newlines = |bytes: &[u8]| bytes.iter().filter(|&&b| b == b'\n').count();
+    let mut start = len;
+    let mut tail = Vec::new();
+    while start > 0 && newlines(&tail) <= lines + usize::from(tail.ends_with(b"\n")) {
+        let read = BLOCK.min(start);
+        start -= read;
+        let mut block = vec![0; read as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&tail);
+        tail = block;
+    }
+    
+    let text = String::from_utf8_lossy(&tail);
+    let all: Vec<&str> = text.lines().collect();
+    // Unless the file was read from its start, the first line is partial
+    let whole = if start > 0 { &all[1..] } else { &all[..] };
+    let last = whole[whole.len().saturating_sub(lines)..].iter().map(|line| line.to_string()).collect();
+    Ok((last, len))
+}
+
+fn read_from_offset(path: &std::path::Path, offset: &mut u64) -> Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = std::fs::File::open(path)?;
+    if file.metadata()?.len() < *offset {
+        // Truncated: start over
+        *offset = 0;
+    }
+    file.seek(SeekFrom::Start(*offset))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    *offset += buf.len() as u64;
+    Ok(buf)
+}
+
+/// Renders a JSON log line as `timestamp LEVEL target: message key=value`.
+/// Lines that are not the daemon's JSON are printed as they are.
+fn render_log_line(line: &str) -> String {
+    let Ok(serde_json::Value::Object(entry)) = serde_json::from_str::<serde_json::Value>(line) else {
+        return line.to_string();
+    };
+    let text = |key: &str| entry.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+    let mut rendered = format!("{} {:>5} {}:", text("timestamp"), text("level"), text("target"));
+    if let Some(serde_json::Value::Object(fields)) = entry.get("fields") {
+        if let Some(message) = fields.get("message").and_then(|v| v.as_str()) {
+            rendered.push(' ');
+            rendered.push_str(message);
+        }
+        for (key, value) in fields.iter().filter(|(key, _)| *key != "message") {
+            match value.as_str() {
+                Some(s) => rendered.push_str(&format!(" {}={}", key, s)),
+                None => rendered.push_str(&format!(" {}={}", key, value)),
+            }
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ct_core::models::{CrateSymbolStats, FileSize, FnSize, SymbolKind, SymbolStats};
+    use ct_protocol::data::{ExportInvariants, RefCrate, RefSite};
+    use ct_protocol::ChangeKind;
+
+    #[test]
+    fn test_render_call_tree() {
+        let edge = |caller: &str, callee: &str, line, depth| CallEdge {
+            caller: caller.to_string(),
+            callee: callee.to_string(),
+            file: "src/lib.rs".to_string(),
+            line_start: line,
+            line_end: line,
+            depth,
+        };
+        // Callers of `connect`, which `open` calls twice and `retry` by a
+        // shorter path; `main` calls both of them
+        let graph = CallGraphData {
+            path: "app::net::connect".to_string(),
+            depth: 3,
+            truncated: false,
+            edges: vec![
+                edge("app::open", "app::net::connect", 5, 1),
+                edge("app::open", "app::net::connect", 9, 1),
+                edge("app::net::retry", "connect", 40, 1),
+                edge("app::main", "app::open", 2, 2),
+                edge("app::main", "app::net::retry", 3, 2),
+            ],
+        };
+        assert_eq!(
+            render_call_tree(&graph, true, Style::PLAIN),
+            "app::net::connect\n\
+             \x20 app::open  src/lib.rs:5, src/lib.rs:9\n\
+             \x20   app::main  src/lib.rs:2\n\
+             \x20 app::net::retry  src/lib.rs:40\n\
+             \x20   app::main  src/lib.rs:3\n"
+        );
+        
+        // Callees, where `load` is expanded where it was first reached
+        let graph = CallGraphData {
+            path: "app::main".to_string(),
+            depth: 3,
+            truncated: false,
+            edges: vec![
+                edge("app::main", "app::open", 2, 1),
+                edge("app::main", "app::load", 3, 1),
+                edge("app::open", "app::load", 7, 2),
+                edge("app::load", "app::parse", 12, 2),
+            ],
+        };
+        assert_eq!(
+            render_call_tree(&graph, false, Style::PLAIN),
+            "app::main\n\
+             \x20 app::open  src/lib.rs:2\n\
+             \x20   app::load  src/lib.rs:7 (*)\n\
+             \x20 app::load  src/lib.rs:3\n\
+             \x20   app::parse  src/lib.rs:12\n"
+        );
+    }
+
+    #[test]
+    fn test_render_symbols() {
+        let symbol = |path: &str, kind: &str, visibility: &str, signature: &str| SymbolItem {
+            path: path.to_string(),
+            span_start: 3,
+            span_end: 9,
+            fields: json!({"kind": kind, "visibility": visibility, "signature": signature}).as_object().unwrap().clone(),
+        };
+        let symbols = vec![
+            symbol("app::Config", "struct", "public", "pub struct Config"),
+            symbol("app::net::connect", "fn", "private", "fn connect(addr: &str) -> Conn"),
+        ];
+        assert_eq!(
+            render_symbols(&symbols, false, Style::PLAIN),
+            "struct app::Config        lines 3-9\n\
+             fn     app::net::connect  lines 3-9\n"
+        );
+        assert_eq!(
+            render_symbols(&symbols[1..], true, Style::PLAIN),
+            "fn app::net::connect  lines 3-9\n    fn connect(addr: &str) -> Conn\n"
+        );
+        // Without kinds there is no badge column
+        let bare = SymbolItem { path: "app::main".to_string(), span_start: 1, span_end: 4, fields: Default::default() };
+        assert_eq!(render_symbols(&[bare], false, Style::PLAIN), "app::main  lines 1-4\n");
+    }
+
+    #[test]
+    fn test_render_doc() {
+        let data = json!({
+            "symbol": {"path": "app::parse", "signature": "pub fn parse(s: &str) -> u32", "docs": "Parses `s`.\n"},
+            "examples": [{"lang": "rust", "code": "assert_eq!(parse(\"1\"), 1);\n"}],
+        });
+        assert_eq!(
+            render_doc(&data, Style::PLAIN).unwrap(),
+            "app::parse\npub fn parse(s: &str) -> u32\n\nParses `s`.\n\nExample 1\nassert_eq!(parse(\"1\"), 1);\n"
+        );
+        let data = json!({"symbol": {"path": "app::run", "signature": "fn run()", "docs": null}});
+        assert_eq!(render_doc(&data, Style::PLAIN).unwrap(), "app::run\nfn run()\n");
+    }
+
+    #[test]
+    fn test_render_changes() {
+        let change = |change, path: &str, signature: &str, previous: Option<&str>| SymbolChange {
+            change,
+            path: path.to_string(),
+            kind: if path.ends_with("Config") { "struct" } else { "fn" }.to_string(),
+            visibility: "public".to_string(),
+            signature: signature.to_string(),
+            previous_signature: previous.map(str::to_string),
+        };
+        let changes = vec![
+            change(ChangeKind::Added, "app::net::connect", "pub fn connect(addr: &str)", None),
+            change(ChangeKind::Changed, "app::Config", "pub struct Config<T>", Some("pub struct Config")),
+            change(ChangeKind::Removed, "app::retry", "pub fn retry()", None),
+            SymbolChange {
+                visibility: "private".to_string(),
+                ..change(ChangeKind::Changed, "app::run", "fn run()", Some("fn run()"))
+            },
+        ];
+        assert_eq!(
+            render_changes("Reindex 7", &changes, Style::PLAIN),
+            "Reindex 7\n\
+             + fn     app::net::connect  pub fn connect(addr: &str)\n\
+             ~ struct app::Config        pub struct Config<T>\n\
+             \x20        was pub struct Config\n\
+             - fn     app::retry         pub fn retry()\n\
+             ~ fn     app::run           fn run()  (private)\n"
+        );
+        assert_eq!(
+            table::render(&change_records(7, &changes[2..3]), table::TableFormat::Csv),
+            "change,epoch,kind,path,previous_signature,signature,visibility\n\
+             removed,7,fn,app::retry,,pub fn retry(),public\n"
+        );
+    }
+
+    #[test]
+    fn test_render_stats() {
+        let counts = |pairs: &[(&str, usize)]| pairs.iter().map(|(k, n)| (k.to_string(), *n)).collect::<BTreeMap<_, _>>();
+        let core = SymbolStats {
+            total: 4,
+            documented: 1,
+            doc_coverage: 0.25,
+            by_kind: counts(&[("fn", 2), ("struct", 1), ("enum", 1)]),
+            by_visibility: counts(&[("public", 3), ("crate", 1)]),
+            by_status: counts(&[("implemented", 3), ("todo", 1)]),
+        };
+        let summary = IndexSummary {
+            totals: core.clone(),
+            crates: vec![CrateSymbolStats { name: "core".to_string(), stats: core }],
+            largest_files: vec![FileSize { path: "src/lib.rs".to_string(), crate_name: "core".to_string(), symbols: 4, lines: 120 }],
+            largest_fns: vec![FnSize { path: "core::load".to_string(), file: "src/lib.rs".to_string(), span_start: 10, lines: 31 }],
+        };
+        assert_eq!(
+            render_stats(&summary, Style::PLAIN),
+            "4 symbols in 1 crate: 3 public, 1 internal, 25% documented, 0 unimplemented, 1 todo\n\n\
+             crate  symbols  public internal  docs  unimpl  todo  most common kinds\n\
+             core         4       3        1   25%       0     1  2 fn, 1 enum, 1 struct\n\
+             \n\
+             Largest files\n   \
+                120 lines  src/lib.rs  core, 4 symbols\n\
+             \n\
+             Largest functions\n    \
+                 31 lines  core::load  src/lib.rs:10\n"
+        );
+    }
+
+    #[test]
+    fn test_render_todo() {
+        let now = 1_700_000_000;
+        let item = |file: &str, line, marker: &str, message: &str, introduced: Option<i64>| TodoItem {
+            path: "app::net::connect".to_string(),
+            file: file.to_string(),
+            line,
+            marker: marker.to_string(),
+            message: message.to_string(),
+            introduced,
+        };
+        let todo = TodoData {
+            total: 2,
+            truncated: false,
+            items: vec![
+                item("src/net.rs", 3, "TODO", "TODO(alice): retry", Some(now - 45 * 86_400)),
+                item("src/net.rs", 12, "unimplemented", "", None),
+            ],
+        };
+        assert_eq!(
+            render_todo(&todo, now, Style::PLAIN),
+            "src/net.rs:3   TODO           app::net::connect  TODO(alice): retry  (6 weeks)\n\
+             src/net.rs:12  unimplemented  app::net::connect\n"
+        );
+        assert_eq!(
+            table::render(&todo_records(&todo, now), table::TableFormat::Csv),
+            "age_days,file,line,marker,message,path\n\
+             45,src/net.rs,3,TODO,TODO(alice): retry,app::net::connect\n\
+             ,src/net.rs,12,unimplemented,,app::net::connect\n"
+        );
+
+        assert_eq!(format_age(3600), "today");
+        assert_eq!(format_age(86_400), "1 day");
+        assert_eq!(format_age(90 * 86_400), "3 months");
+        assert_eq!(format_age(800 * 86_400), "2 years");
+    }
+
+    #[test]
+    fn test_render_impls() {
+        let imp = |trait_path: &str, file: &str, line_start| TraitImpl {
+            trait_path: trait_path.to_string(),
+            for_path: "app::State".to_string(),
+            file: file.to_string(),
+            line_start,
+            line_end: line_start + 4,
+        };
+        let impls = vec![imp("Clone", "src/lib.rs", 8), imp("app::fmt::Render", "src/fmt.rs", 120)];
+        assert_eq!(
+            render_impls(&impls, Style::PLAIN),
+            "src/lib.rs:8-12     impl Clone for app::State\n\
+             src/fmt.rs:120-124  impl app::fmt::Render for app::State\n"
+        );
+    }
+
+    #[test]
+    fn test_render_grep() {
+        let hit = |path: &str, field: &str, snippet: &str| GrepHit {
+            path: path.to_string(),
+            kind: SymbolKind::Fn,
+            field: field.to_string(),
+            snippet: snippet.to_string(),
+            span_start: 1,
+            span_end: 2,
+        };
+        let hits = vec![
+            hit("app::net::connect", "sig", "pub fn connect(addr: &str) -> **Result<**Conn>"),
+            hit("app::open", "docs", "Opens the store; see **Result<**T> for errors."),
+        ];
+        assert_eq!(
+            render_grep(&hits, Style::PLAIN),
+            "app::net::connect  sig: pub fn connect(addr: &str) -> **Result<**Conn>\n\
+             app::open          docs: Opens the store; see **Result<**T> for errors.\n"
+        );
+        assert_eq!(render_grep(&[], Style::PLAIN), "");
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let node = |path: &str, kind, children| TreeNode {
+            path: path.to_string(),
+            name: path.rsplit("::").next().unwrap().to_string(),
+            kind,
+            children,
+        };
+        let tree = vec![node("app::net", SymbolKind::Module, vec![
+            node("app::net::Client", SymbolKind::Struct, vec![node("app::net::Client::connect", SymbolKind::Method, vec![])]),
+            node("app::net::retry", SymbolKind::Fn, vec![]),
+        ])];
+        assert_eq!(
+            render_tree(&tree, Style::PLAIN),
+            "module app::net\n  struct Client\n    method connect\n  fn retry\n"
+        );
+        assert_eq!(render_tree(&[], Style::PLAIN), "");
+        assert_eq!(
+            table::render(&tree_records(&tree), table::TableFormat::Csv),
+            "depth,kind,path\n0,module,app::net\n1,struct,app::net::Client\n2,method,app::net::Client::connect\n1,fn,app::net::retry\n"
+        );
+    }
+
+    #[test]
+    fn test_render_refs() {
+        let site = |from: &str, line, column| RefSite {
+            from: from.to_string(),
+            target: "app::Config".to_string(),
+            line_start: line,
+            line_end: line,
+            column,
+        };
+        let files = vec![
+            RefFile { file: "src/main.rs".to_string(), references: vec![site("app::main", 4, Some(9)), site("app::main", 6, None)] },
+            RefFile { file: "src/net.rs".to_string(), references: vec![site("app::net::connect", 12, Some(17))] },
+        ];
+        let mut refs = RefsData {
+            path: "app::Config".to_string(),
+            total: 3,
+            truncated: false,
+            files: files.clone(),
+            crates: vec![],
+        };
+        assert_eq!(
+            render_refs(&refs, Style::PLAIN),
+            "src/main.rs:4:9  app::main\nsrc/main.rs:6  app::main\n\nsrc/net.rs:12:17  app::net::connect\n"
+        );
+        assert_eq!(
+            table::render(&ref_records(&refs), table::TableFormat::Tsv),
+            "column\tfile\tfrom\tline_end\tline_start\ttarget\n\
+             9\tsrc/main.rs\tapp::main\t4\t4\tapp::Config\n\
+             \tsrc/main.rs\tapp::main\t6\t6\tapp::Config\n\
+             17\tsrc/net.rs\tapp::net::connect\t12\t12\tapp::Config\n"
+        );
+
+        refs.files = vec![];
+        refs.crates = vec![RefCrate { name: "app".to_string(), files }];
+        assert_eq!(
+            render_refs(&refs, Style::PLAIN),
+            "app (3)\n  src/main.rs:4:9  app::main\n  src/main.rs:6  app::main\n\n  src/net.rs:12:17  app::net::connect\n"
+        );
+        assert!(ref_records(&refs).iter().all(|record| record["crate"] == "app"));
+
+        refs.crates = vec![];
+        refs.total = 0;
+        assert_eq!(render_refs(&refs, Style::PLAIN), "No references to app::Config\n");
+    }
+
+    #[test]
+    fn test_completion_script() {
+        for shell in [Shell::Bash, Shell::Elvish, Shell::Fish, Shell::PowerShell, Shell::Zsh] {
+            let script = completion_script(shell).unwrap();
+            assert!(script.contains("COMPLETE"), "{} registration does not call back into ct", shell);
+        }
+    }
+
+    #[test]
+    fn test_render_export() {
+        let symbol = |path: &str, kind: &str, docs: Option<&str>, source: Option<&str>| ExportSymbol {
+            path: path.to_string(),
+            kind: kind.to_string(),
+            signature: format!("pub {} {}", kind, path.rsplit("::").next().unwrap()),
+            span_start: 4,
+            span_end: 6,
+            docs: docs.map(str::to_string),
+            source: source.map(str::to_string),
+            source_stale: source.is_some(),
+        };
+        let bundle = ExportBundle {
+            symbols: vec![symbol("app::Config", "struct", Some("Settings.\n\nLoaded once."), None)],
+            children: vec![symbol("app::Config::load", "fn", None, Some("pub fn load() {\n}\n"))],
+            extern_refs: vec![],
+            impl_ranges: vec![],
+            order: "bfs".to_string(),
+            truncated: false,
+            invariants: ExportInvariants { range_1_based_inclusive: true },
+        };
+        assert_eq!(
+            render_export(&bundle, Style::PLAIN),
+            "struct app::Config  lines 4-6\n\
+             /// Settings.\n///\n/// Loaded once.\npub struct Config\n\
+             \n\
+             fn     app::Config::load  lines 4-6, changed since indexing\n\
+             pub fn load() {\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_tail_lines() {
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let text: String = (0..5000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(log.path(), &text).unwrap();
+        
+        let (last, len) = tail_lines(log.path(), 3).unwrap();
+        assert_eq!(last, vec!["line 4997", "line 4998", "line 4999"]);
+        assert_eq!(len, text.len() as u64);
+        assert_eq!(tail_lines(log.path(), 10_000).unwrap().0.len(), 5000);
+        
+        std::fs::write(log.path(), "first\nsecond").unwrap();
+        assert_eq!(tail_lines(log.path(), 1).unwrap().0, vec!["second"]);
+    }
+
+    #[test]
+    fn test_export_files() {
+        let symbol = |path: &str, kind: &str| ExportSymbol {
+            path: path.to_string(),
+            kind: kind.to_string(),
+            signature: String::new(),
+            span_start: 1,
+            span_end: 2,
+            docs: None,
+            source: None,
+            source_stale: false,
+        };
+        let bundle = ExportBundle {
+            symbols: vec![symbol("index", "module")],
+            children: vec![
+                symbol("index::net", "module"),
+                symbol("index::net", "fn"),
+                symbol("index::<impl Display for Id>", "impl"),
+                symbol("index::<impl Display for Id>", "impl"),
+                symbol("index::Net", "fn"),
+            ],
+            extern_refs: vec!["std::fmt::Display".to_string()],
+            impl_ranges: vec![],
+            order: "bfs".to_string(),
+            truncated: false,
+            invariants: ExportInvariants { range_1_based_inclusive: true },
+        };
+        let files = export_files(&bundle);
+        let names: Vec<&str> = files.iter().map(|(file, _)| file.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "index.module.json",
+                "index/net.json",
+                "index/net.fn.json",
+                "index/_impl_Display_for_Id_.json",
+                "index/_impl_Display_for_Id_.impl.json",
+                "index/Net.fn.2.json",
+                "index.json"
+            ]
+        );
+        assert_eq!(files[2].1["kind"], "fn");
+        
+        let manifest = &files[6].1;
+        assert_eq!(manifest["symbols"], json!([{"path": "index", "kind": "module", "file": "index.module.json"}]));
+        assert_eq!(manifest["children"][1], json!({"path": "index::net", "kind": "fn", "file": "index/net.fn.json"}));
+        assert_eq!(manifest["extern_refs"], json!(["std::fmt::Display"]));
+        assert_eq!(manifest["order"], "bfs");
+    }
+}
@@ -0,0 +1,68 @@
+//! Launching an editor at a file and line for `ct open`.
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// The command opening `file` at `line` with the `$CT_EDITOR` template or,
+/// failing that, `$VISUAL` or `$EDITOR`; `None` if none is set. Never read
+/// from ct.toml, which comes with the workspace and so could make `ct open`
+/// run whatever a cloned repository names.
+pub fn command(file: &Path, line: u32) -> Option<Vec<String>> {
+    let template = std::env::var("CT_EDITOR").ok();
+    let visual = std::env::var("VISUAL").ok();
+    let editor = std::env::var("EDITOR").ok();
+    editor_command(template.as_deref(), [visual.as_deref(), editor.as_deref()], &file.to_string_lossy(), line)
+}
+
+/// Run `command`, waiting for the editor to exit.
+pub fn run(command: &[String]) -> Result<()> {
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .with_context(|| format!("Failed to start `{}`", command[0]))?;
+    if !status.success() {
+        bail!("`{}` exited with {}", command[0], status);
+    }
+    Ok(())
+}
+
+/// The program and arguments to open `file` at `line`: `template` split on
+/// whitespace with `{file}` and `{line}` filled in, or the first of
+/// `editors` that is set, given the line the way that editor takes it.
+fn editor_command(template: Option<&str>, editors: [Option<&str>; 2], file: &str, line: u32) -> Option<Vec<String>> {
+    let fill = |word: &str| word.replace("{file}", file).replace("{line}", &line.to_string());
+    if let Some(template) = template.filter(|template| !template.trim().is_empty()) {
+        return Some(template.split_whitespace().map(fill).collect());
+    }
+
+    let editor = editors.into_iter().flatten().find(|editor| !editor.trim().is_empty())?;
+    let mut command: Vec<String> = editor.split_whitespace().map(str::to_string).collect();
+    let program = Path::new(&command[0]).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    match program.as_str() {
+        "code" | "code-insiders" | "codium" | "cursor" => command.extend(["-g".to_string(), format!("{}:{}", file, line)]),
+        "subl" | "zed" | "hx" | "helix" => command.push(format!("{}:{}", file, line)),
+        // vi, vim, nvim, emacs, nano, micro, kak and most others
+        _ => command.extend([format!("+{}", line), file.to_string()]),
+    }
+    Some(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_command() {
+        let words = |words: &[&str]| Some(words.iter().map(|word| word.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(
+            editor_command(Some("idea --line {line} {file}"), [Some("vim"), None], "src/lib.rs", 42),
+            words(&["idea", "--line", "42", "src/lib.rs"])
+        );
+        assert_eq!(editor_command(Some(" "), [None, Some("nvim")], "src/lib.rs", 42), words(&["nvim", "+42", "src/lib.rs"]));
+        assert_eq!(editor_command(None, [Some("code --wait"), Some("vim")], "a.rs", 7), words(&["code", "--wait", "-g", "a.rs:7"]));
+        assert_eq!(editor_command(None, [Some(""), Some("/usr/bin/hx")], "a.rs", 7), words(&["/usr/bin/hx", "a.rs:7"]));
+        assert_eq!(editor_command(None, [None, None], "a.rs", 7), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! Directed graphs for `--format dot|graphml|mermaid`, built from what the
+//! graph-producing commands (deps, callers, callees, impls, tree) return.
+
+/// A text format a `Graph` can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz, for `dot -Tsvg`
+    Dot,
+    /// GraphML, for yEd, Gephi and networkx
+    Graphml,
+    /// A Mermaid flowchart, for Markdown that renders it (GitHub, GitLab)
+    Mermaid,
+}
+
+/// Nodes in the order they were first added, and edges between them, each
+/// with an optional label. Nodes are named by path.
+#[derive(Debug, Default)]
+pub struct Graph {
+    name: String,
+    nodes: Vec<String>,
+    edges: Vec<(usize, usize, Option<String>)>,
+}
+
+impl Graph {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), ..Default::default() }
+    }
+
+    /// Add `node` unless it is already there, returning its index.
+    pub fn node(&mut self, node: &str) -> usize {
+        match self.nodes.iter().position(|n| n == node) {
+            Some(index) => index,
+            None => {
+                self.nodes.push(node.to_string());
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Add an edge, and its ends; an edge already there is not repeated.
+    pub fn edge(&mut self, from: &str, to: &str, label: Option<String>) {
+        let edge = (self.node(from), self.node(to), label);
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.dot(),
+            GraphFormat::Graphml => self.graphml(),
+            GraphFormat::Mermaid => self.mermaid(),
+        }
+    }
+
+    /// A Graphviz digraph; nodes are only listed on their own when no edge
+    /// names them.
+    fn dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = format!("digraph {} {{\n", quote(&self.name));
+        for (index, node) in self.nodes.iter().enumerate() {
+            if !self.edges.iter().any(|(from, to, _)| *from == index || *to == index) {
+                out.push_str(&format!("  {};\n", quote(node)));
+            }
+        }
+        for (from, to, label) in &self.edges {
+            let (from, to) = (quote(&self.nodes[*from]), quote(&self.nodes[*to]));
+            match label {
+                Some(label) => out.push_str(&format!("  {} -> {} [label={}];\n", from, to, quote(label))),
+                None => out.push_str(&format!("  {} -> {};\n", from, to)),
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// GraphML with each node's path as its `name` and edge labels as `label`.
+    fn graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n",
+            "  <key id=\"label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        ));
+        out.push_str(&format!("  <graph id=\"{}\" edgedefault=\"directed\">\n", xml_escape(&self.name)));
+        for (index, node) in self.nodes.iter().enumerate() {
+            out.push_str(&format!("    <node id=\"n{}\"><data key=\"name\">{}</data></node>\n", index, xml_escape(node)));
+        }
+        for (index, (from, to, label)) in self.edges.iter().enumerate() {
+            match label {
+                Some(label) => out.push_str(&format!(
+                    "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"><data key=\"label\">{}</data></edge>\n",
+                    index,
+                    from,
+                    to,
+                    xml_escape(label)
+                )),
+                None => out.push_str(&format!("    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"/>\n", index, from, to)),
+            }
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    /// A left-to-right Mermaid flowchart. Paths go in quoted labels, with
+    /// the characters Mermaid would read as markup written as entity codes.
+    fn mermaid(&self) -> String {
+        let escape = |s: &str| {
+            s.chars()
+                .map(|c| match c {
+                    '"' | '<' | '>' | '|' | '#' => format!("#{};", c as u32),
+                    c => c.to_string(),
+                })
+                .collect::<String>()
+        };
+        let mut out = String::from("flowchart LR\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            out.push_str(&format!("  n{}[\"{}\"]\n", index, escape(node)));
+        }
+        for (from, to, label) in &self.edges {
+            match label {
+                Some(label) => out.push_str(&format!("  n{} -->|\"{}\"| n{}\n", from, escape(label), to)),
+                None => out.push_str(&format!("  n{} --> n{}\n", from, to)),
+            }
+        }
+        out
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_formats() {
+        let mut graph = Graph::new("deps");
+        graph.edge("app", "serde", None);
+        graph.edge("app", "tempfile", Some("dev".to_string()));
+        graph.edge("app", "serde", None);
+        graph.node("Vec<T>");
+
+        assert_eq!(
+            graph.render(GraphFormat::Dot),
+            "digraph \"deps\" {\n  \"Vec<T>\";\n  \"app\" -> \"serde\";\n  \"app\" -> \"tempfile\" [label=\"dev\"];\n}\n"
+        );
+        assert_eq!(
+            graph.render(GraphFormat::Mermaid),
+            "flowchart LR\n  n0[\"app\"]\n  n1[\"serde\"]\n  n2[\"tempfile\"]\n  n3[\"Vec#60;T#62;\"]\n  \
+             n0 --> n1\n  n0 -->|\"dev\"| n2\n"
+        );
+        let graphml = graph.render(GraphFormat::Graphml);
+        assert!(graphml.contains("<graph id=\"deps\" edgedefault=\"directed\">"));
+        assert!(graphml.contains("<node id=\"n3\"><data key=\"name\">Vec&lt;T&gt;</data></node>"));
+        assert!(graphml.contains("<edge id=\"e0\" source=\"n0\" target=\"n1\"/>"));
+        assert!(graphml.contains("<edge id=\"e1\" source=\"n0\" target=\"n2\"><data key=\"label\">dev</data></edge>"));
+        assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+    }
+}
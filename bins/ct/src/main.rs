@@ -1,7 +1,16 @@
 mod client;
 mod commands;
+mod editor;
+mod graph;
+mod pager;
+mod pick;
+mod service;
+mod style;
+mod table;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::ArgValueCompleter;
+use clap_complete::CompleteEnv;
 use ct_core::utils::EXIT_INVALID_ARGS;
 
 #[derive(Parser)]
@@ -15,23 +24,80 @@ struct Cli {
     #[arg(long, global = true, value_enum, default_value = "json")]
     format: OutputFormat,
     
-    /// Pretty-print output
+    /// Pretty-print output and report what the query cost on stderr
     #[arg(long, global = true)]
     pretty: bool,
+    
+    /// Don't page long pretty output of doc, ls and export through $PAGER
+    #[arg(long, global = true)]
+    no_pager: bool,
 }
 
 #[derive(Clone, Copy, Debug, clap::ValueEnum)]
 enum OutputFormat {
     Json,
     Pretty,
+    /// Graphviz, for graph-producing commands (deps, callers, callees, impls,
+    /// tree); others print JSON
+    Dot,
+    /// GraphML, for graph-producing commands like dot
+    Graphml,
+    /// A Mermaid flowchart, for graph-producing commands like dot
+    Mermaid,
+    /// One row per result, with a header, for list-producing commands
+    Csv,
+    /// Like csv, separated by tabs
+    Tsv,
+    /// One JSON object per result and line
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// The graph format this is, for commands that can draw their result.
+    fn graph(self) -> Option<graph::GraphFormat> {
+        match self {
+            OutputFormat::Dot => Some(graph::GraphFormat::Dot),
+            OutputFormat::Graphml => Some(graph::GraphFormat::Graphml),
+            OutputFormat::Mermaid => Some(graph::GraphFormat::Mermaid),
+            _ => None,
+        }
+    }
+    
+    /// The record format this is, for commands that list results.
+    fn table(self) -> Option<table::TableFormat> {
+        match self {
+            OutputFormat::Csv => Some(table::TableFormat::Csv),
+            OutputFormat::Tsv => Some(table::TableFormat::Tsv),
+            OutputFormat::Jsonl => Some(table::TableFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// What of a symbol `grep --in` looks in.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum GrepIn {
+    Name,
+    /// The signature
+    Sig,
+    Docs,
+}
+
+/// What `refs --group-by` groups reference sites by.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum RefsGroupBy {
+    File,
+    Crate,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Find symbols by name or path
     Find {
-        /// Name or path to search for
-        query: String,
+        /// Name or path to search for; with *, ?, [...] or {a,b} a glob, and
+        /// with :: too a path glob (e.g. 'my_crate::api::*')
+        #[arg(required_unless_present_any = ["tag", "sig"])]
+        query: Option<String>,
         
         /// Symbol kind filter
         #[arg(long)]
@@ -52,11 +118,105 @@ enum Commands {
         /// Show all fields (by default only path and span are shown)
         #[arg(short = 'a', long)]
         all: bool,
+        
+        /// Only symbols changed in the last DAYS days (requires git_metadata)
+        #[arg(long, value_name = "DAYS", num_args = 0..=1, default_missing_value = "14")]
+        recently_changed: Option<u32>,
+        
+        /// Treat the query as a path and return it and everything under it
+        #[arg(long)]
+        path: bool,
+        
+        /// With --path, only symbols at most N segments below the query
+        #[arg(long, value_name = "N", requires = "path")]
+        depth: Option<usize>,
+        
+        /// How to match the name: exact, prefix, glob (e.g. 'Hash*'), regex or fuzzy
+        #[arg(
+            long = "match",
+            value_name = "MODE",
+            value_parser = ["exact", "prefix", "glob", "regex", "fuzzy"],
+            conflicts_with = "path",
+        )]
+        match_mode: Option<String>,
+        
+        /// Find the symbols carrying TAG instead of matching a query
+        #[arg(long, value_name = "TAG", conflicts_with_all = ["query", "path", "match_mode", "recently_changed"])]
+        tag: Option<String>,
+        
+        /// Find functions by signature shape, e.g. "fn(&str) -> Result<_, _>" (`_` is any type, `..` any further arguments)
+        #[arg(long, value_name = "PATTERN", conflicts_with_all = ["query", "path", "match_mode", "recently_changed", "tag"])]
+        sig: Option<String>,
+        
+        /// Rank paths against the query like a fuzzy finder, best match
+        /// first (`--match fuzzy` instead tolerates typos in the name)
+        #[arg(
+            long,
+            requires = "query",
+            conflicts_with_all = ["unimplemented", "todo", "all", "recently_changed", "path", "match_mode", "tag", "sig"],
+        )]
+        fuzzy: bool,
+    },
+    
+    /// Pick a symbol interactively by fuzzy path match and print its path
+    Pick {
+        /// Initial query
+        #[arg(default_value = "")]
+        query: String,
+        
+        /// Symbol kind filter
+        #[arg(long)]
+        kind: Option<String>,
+    },
+    
+    /// Full-text search over symbol docs and signatures, best match first
+    Search {
+        /// Words to search for, e.g. "backpressure strategy"
+        query: String,
+        
+        /// Symbol kind filter
+        #[arg(long)]
+        kind: Option<String>,
+        
+        /// Visibility filter (public, private, all)
+        #[arg(long, value_name = "VIS")]
+        vis: Option<String>,
+        
+        /// Maximum number of hits (capped by max_list)
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+        
+        /// Show all fields (by default path, kind, score and span are shown)
+        #[arg(short = 'a', long)]
+        all: bool,
+        
+        /// Search docs only, showing an excerpt with the matches highlighted
+        #[arg(long, conflicts_with_all = ["kind", "vis", "all"])]
+        docs: bool,
+    },
+    
+    /// Grep the index: symbols whose name, signature or docs contain a pattern
+    Grep {
+        /// Text to look for, case-sensitive (a regex with --regex)
+        pattern: String,
+        
+        /// Only look in these (default all of them)
+        #[arg(long = "in", value_enum, value_delimiter = ',', value_name = "WHERE")]
+        fields: Vec<GrepIn>,
+        
+        /// Treat the pattern as a regular expression
+        #[arg(short = 'E', long)]
+        regex: bool,
+        
+        /// Maximum number of hits (capped by max_list)
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
     },
     
     /// Show documentation for a symbol
     Doc {
         /// Path to the symbol
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
         path: String,
         
         /// Include documentation
@@ -67,6 +227,10 @@ enum Commands {
         #[arg(long, value_name = "all")]
         docs_all: bool,
         
+        /// Include code examples from the symbol's docs
+        #[arg(short = 'e', long)]
+        examples: bool,
+        
         /// Visibility filter
         #[arg(long)]
         vis: Option<String>,
@@ -82,7 +246,9 @@ enum Commands {
     
     /// List symbols with expansion
     Ls {
-        /// Path to list
+        /// Path to list, or a path glob listing what it matches: * and ? stay
+        /// within a segment, ** spans any number (e.g. 'my_crate::**::Config')
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
         path: String,
         
         /// Expansion operators (e.g., ">", ">>", "<", "<<")
@@ -116,8 +282,9 @@ enum Commands {
     
     /// Export symbol bundle
     Export {
-        /// Path to export
-        path: String,
+        /// Paths to export, then any expansion operators (">", ">>")
+        #[arg(value_name = "PATH", required = true, add = ArgValueCompleter::new(commands::complete_path))]
+        paths: Vec<String>,
         
         /// Export as bundle
         #[arg(long)]
@@ -131,10 +298,6 @@ enum Commands {
         #[arg(long, value_name = "all")]
         docs_all: bool,
         
-        /// Expansion operators
-        #[arg(value_name = "EXPANSION")]
-        expansion: Vec<String>,
-        
         /// Enable impl-parents traversal
         #[arg(long)]
         impl_parents: bool,
@@ -158,6 +321,15 @@ enum Commands {
         /// Maximum context size override
         #[arg(long)]
         max_size: Option<usize>,
+        
+        /// Write the bundle to FILE instead of stdout
+        #[arg(long, value_name = "FILE", conflicts_with = "out_dir")]
+        out: Option<std::path::PathBuf>,
+        
+        /// Write each symbol to its own JSON file under DIR, laid out by
+        /// path (a::b::C in a/b/C.json), with an index.json manifest
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<std::path::PathBuf>,
     },
     
     /// Trigger reindexing
@@ -181,6 +353,14 @@ enum Commands {
         /// Include derive trait implementations (clone, serialize, etc.)
         #[arg(long)]
         include_derives: bool,
+        
+        /// Reindex only this workspace member
+        #[arg(short = 'p', long, value_name = "CRATE")]
+        package: Option<String>,
+        
+        /// Wait for the reindex to run, with a live progress bar on stderr, instead of returning once it is queued
+        #[arg(long)]
+        wait: bool,
     },
     
     /// Show implementation status
@@ -196,14 +376,193 @@ enum Commands {
         /// Show only todo symbols
         #[arg(short = 't', long)]
         todo: bool,
+        
+        /// Include the most frequently changed symbols (requires git_metadata)
+        #[arg(long)]
+        hotspots: bool,
+        
+        /// Also show the counts per crate, module or kind
+        #[arg(long, value_enum)]
+        group_by: Option<ct_protocol::StatusGroup>,
+    },
+    
+    /// Fetch the next page of a find, ls, search or status listing
+    Continue {
+        /// The cursor printed after the previous page
+        cursor: String,
+    },
+    
+    /// Summarize the index: symbol counts per crate, kind, visibility and status, doc coverage, and the largest files and functions
+    Stats,
+    
+    /// Check indexed files against the workspace; exits 5 if any crate is stale
+    Verify,
+    
+    /// List the types implementing a trait, or with --traits the traits a type implements
+    Impls {
+        /// Path of the trait, or of the type with --traits
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Treat PATH as a type and list the traits it implements
+        #[arg(long)]
+        traits: bool,
+        
+        /// With --traits, also list traits from outside the workspace (std, dependencies)
+        #[arg(long, requires = "traits")]
+        external: bool,
+    },
+    
+    /// Show what calls a function, transitively, as a call tree (try --format pretty)
+    Callers {
+        /// Path of the function (e.g. my_crate::net::connect)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Calls to follow back from PATH (default 3, at most 10)
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+    },
+    
+    /// Show what a function calls, transitively, as a call tree (try --format pretty)
+    Callees {
+        /// Path of the function (e.g. my_crate::net::connect)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Calls to follow from PATH (default 3, at most 10)
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+    },
+    
+    /// Show the crate dependency graph from cargo metadata (try --format dot)
+    Deps {
+        /// Crate to start from; every indexed crate's dependencies if omitted
+        #[arg(value_name = "CRATE")]
+        crate_name: Option<String>,
+        
+        /// List the indexed crates depending on CRATE instead
+        #[arg(long, requires = "crate_name")]
+        dependents: bool,
+        
+        /// Steps to follow from CRATE (default 1)
+        #[arg(long, requires = "crate_name")]
+        depth: Option<usize>,
+    },
+    
+    /// Show the module hierarchy under a path as a tree (try --format pretty)
+    Tree {
+        /// Crate or module to start from (e.g. my_crate::net)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Only N levels below PATH
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+        
+        /// Only these kinds and what contains them (e.g. struct,enum,fn)
+        #[arg(long, value_delimiter = ',')]
+        kinds: Vec<String>,
+    },
+    
+    /// Show where a symbol is referenced as file:line:col (try --format pretty)
+    Refs {
+        /// Path of the symbol (e.g. my_crate::Config)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Group the references by file (default) or by crate
+        #[arg(long, value_enum)]
+        group_by: Option<RefsGroupBy>,
+        
+        /// Maximum number of references (capped by max_list)
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    
+    /// Show the innermost symbol containing a source location, e.g. src/lib.rs:142
+    Owner {
+        /// FILE:LINE as in compiler messages and backtraces; a trailing :COLUMN is ignored
+        #[arg(value_parser = parse_location)]
+        location: (String, u32),
+    },
+    
+    /// Open a symbol's definition in your editor: $CT_EDITOR (e.g.
+    /// "code -g {file}:{line}"), or else $VISUAL or $EDITOR
+    Open {
+        /// Path of the symbol (e.g. my_crate::Config)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+    },
+    
+    /// List todo!(), unimplemented!() and TODO/FIXME markers with their messages, and
+    /// how old they are when git_metadata is enabled (try --format pretty)
+    Todo {
+        /// Only markers whose text contains TEXT, ignoring case, e.g. an assignee as in TODO(alice)
+        #[arg(long, value_name = "TEXT")]
+        matching: Option<String>,
+        
+        /// Maximum number of markers (capped by max_list)
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    
+    /// Tag a symbol (e.g. needs-refactor) or show its tags; find tagged symbols with find --tag
+    Tag {
+        /// Path of the symbol (e.g. my_crate::State)
+        #[arg(add = ArgValueCompleter::new(commands::complete_path))]
+        path: String,
+        
+        /// Tag to add (repeatable)
+        #[arg(long, value_name = "TAG")]
+        add: Vec<String>,
+        
+        /// Tag to remove (repeatable)
+        #[arg(long, value_name = "TAG")]
+        remove: Vec<String>,
+        
+        /// Note to store with the added tags
+        #[arg(long, requires = "add")]
+        note: Option<String>,
     },
     
     /// Show diagnostics
     Diag,
     
-    /// Run benchmarks
+    /// Symbols added, removed or changed since a git ref, a snapshot or the
+    /// previous reindex (try --format pretty)
+    Diff {
+        /// A git ref such as main, v1.2.0 or HEAD~3, which is checked out and
+        /// indexed on the side, or an index file written by `ct-daemon
+        /// --snapshot`; the index before the last reindex when omitted
+        #[arg(long, value_name = "REF|FILE")]
+        baseline: Option<String>,
+        
+        /// Only changes that can break users of the public API: public
+        /// symbols removed, re-signed or made private
+        #[arg(long)]
+        breaking_only: bool,
+    },
+    
+    /// Print symbols as reindexes add, change or remove them, until interrupted
+    Watch {
+        /// Only symbols at or under this path
+        path_prefix: Option<String>,
+        
+        /// Visibility filter (public, private), e.g. public to follow the API
+        #[arg(long, value_name = "VIS")]
+        vis: Option<String>,
+    },
+    
+    /// Pause or resume reindexing on file changes
+    Watcher {
+        #[command(subcommand)]
+        command: WatcherCommand,
+    },
+    
+    /// Time a mix of queries sampled from the index (try --format pretty)
     Bench {
-        /// Number of queries
+        /// Size of the query mix, replayed until the duration is up
         #[arg(long, default_value = "200")]
         queries: u32,
         
@@ -221,6 +580,57 @@ enum Commands {
         #[command(subcommand)]
         command: DaemonCommand,
     },
+    
+    /// Check what ct needs to work here: the daemon, the nightly toolchain,
+    /// the index, and crashes of the supervised daemon
+    Doctor,
+    
+    /// Remove cache directories of workspaces not used recently, and dead daemon sockets
+    Clean {
+        /// Remove every cache directory, except those a running daemon uses
+        #[arg(long)]
+        all: bool,
+        
+        /// Remove caches unused for this long, e.g. 30d, 12h, 2w
+        #[arg(long, value_name = "AGE", value_parser = parse_age, default_value = "30d", conflicts_with = "all")]
+        older_than: std::time::Duration,
+        
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Maintain the index database
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    
+    /// Print a completion script to source from the shell's startup file,
+    /// e.g. `source <(ct completions zsh)`; symbol paths complete from the daemon
+    Completions {
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Upgrade the workspace's index database to the current schema, keeping its data
+    Migrate,
+    /// Checkpoint and truncate the WAL, refresh planner statistics and reclaim free pages
+    Maintain,
+}
+
+#[derive(Subcommand)]
+enum WatcherCommand {
+    /// Queue file changes instead of reindexing them (e.g. during a large checkout)
+    Pause,
+    
+    /// Reindex everything changed while paused in one pass and resume watching
+    Resume,
+    
+    /// Show whether the watcher is paused and how many files are queued
+    Status,
 }
 
 #[derive(Subcommand)]
@@ -238,6 +648,11 @@ enum DaemonCommand {
         /// Transport type (auto, unix, pipe, tcp)
         #[arg(long, default_value = "auto")]
         transport: String,
+        
+        /// Start the daemon again when it crashes, even with `supervise = false`
+        /// in ct.toml
+        #[arg(long)]
+        supervise: bool,
     },
     
     /// Stop the daemon
@@ -256,10 +671,46 @@ enum DaemonCommand {
     
     /// Show daemon status
     Status,
+    
+    /// Print the daemon's log for this workspace
+    Logs {
+        /// Keep printing lines as they are written
+        #[arg(long, short = 'f')]
+        follow: bool,
+        
+        /// Number of lines to print from the end of the log
+        #[arg(long, short = 'n', default_value_t = 100)]
+        lines: usize,
+    },
+    
+    /// Start the daemon at login as a per-user service: a systemd user
+    /// unit, a launchd agent or a Task Scheduler logon task
+    Install {
+        /// Index directory (defaults to current directory)
+        #[arg(long, short = 'i', default_value = ".")]
+        idx: String,
+        
+        /// Print the service definition instead of installing it
+        #[arg(long)]
+        print: bool,
+    },
+    
+    /// Remove the service `ct daemon install` registered
+    Uninstall {
+        /// Index directory (defaults to current directory)
+        #[arg(long, short = 'i', default_value = ".")]
+        idx: String,
+    },
+}
+
+fn main() {
+    // Answers the shell when its completion script runs `COMPLETE=<shell> ct -- <args>`
+    CompleteEnv::with_factory(Cli::command).complete();
+    cli_main();
 }
 
 #[tokio::main]
-async fn main() {
+async fn cli_main() {
     let cli = Cli::parse();
     
     let exit_code = match run(cli).await {
@@ -275,38 +726,154 @@ async fn main() {
 
 async fn run(cli: Cli) -> anyhow::Result<u8> {
     match cli.command {
-        Commands::Find { query, kind, vis, unimplemented, todo, all } => {
-            commands::find(query, kind, vis, unimplemented, todo, all, cli.format, cli.pretty).await
+        Commands::Find { query: Some(query), kind, vis, fuzzy: true, .. } => {
+            commands::fuzzy_find(query, kind, vis, cli.format, cli.pretty).await
+        }
+        Commands::Find { query, kind, vis, unimplemented, todo, all, recently_changed, path, depth, match_mode, tag, sig, .. } => {
+            commands::find(query, kind, vis, unimplemented, todo, all, recently_changed, path, depth, match_mode, tag, sig, cli.format, cli.pretty).await
+        }
+        Commands::Pick { query, kind } => {
+            commands::pick(query, kind).await
         }
-        Commands::Doc { path, docs, docs_all, vis, unimplemented, todo } => {
+        Commands::Search { query, kind, vis, limit, all, docs } => {
+            commands::search(query, kind, vis, limit, all, docs, cli.format, cli.pretty).await
+        }
+        Commands::Grep { pattern, fields, regex, limit } => {
+            commands::grep(pattern, fields, regex, limit, cli.format, cli.pretty).await
+        }
+        Commands::Doc { path, docs, docs_all, examples, vis, unimplemented, todo } => {
             let include_docs = docs || docs_all;
-            commands::doc(path, include_docs, vis, unimplemented, todo, cli.format, cli.pretty).await
+            commands::doc(path, include_docs, examples, vis, unimplemented, todo, !cli.no_pager, cli.format, cli.pretty).await
         }
         Commands::Ls { path, expansion, impl_parents, docs, vis, unimplemented, todo, max_size } => {
             let expansion_str = expansion.join("");
-            commands::ls(path, expansion_str, impl_parents, docs, vis, unimplemented, todo, max_size, cli.format, cli.pretty).await
+            commands::ls(path, expansion_str, impl_parents, docs, vis, unimplemented, todo, max_size, !cli.no_pager, cli.format, cli.pretty).await
         }
-        Commands::Export { path, bundle, docs, docs_all, expansion, impl_parents, vis, unimplemented, todo, with_source, max_size } => {
+        Commands::Export { paths, bundle, docs, docs_all, impl_parents, vis, unimplemented, todo, with_source, max_size, out, out_dir } => {
             let include_docs = docs || docs_all;
-            let expansion_str = expansion.join("");
-            commands::export(vec![path], bundle, expansion_str, include_docs, impl_parents, vis, unimplemented, todo, with_source, max_size, cli.format, cli.pretty).await
+            // Operators follow the paths, as with `ls`
+            let (expansion, paths): (Vec<String>, Vec<String>) = paths
+                .into_iter()
+                .partition(|arg| arg.chars().all(|c| c == '<' || c == '>'));
+            let target = match (out, out_dir) {
+                (Some(file), _) => commands::ExportTarget::File(file),
+                (None, Some(dir)) => commands::ExportTarget::Dir(dir),
+                (None, None) => commands::ExportTarget::Stdout,
+            };
+            commands::export(paths, bundle, expansion.join(""), include_docs, impl_parents, vis, unimplemented, todo, with_source, max_size, target, !cli.no_pager, cli.format, cli.pretty).await
+        }
+        Commands::Reindex { features, target, module, struct_name, include_derives, package, wait } => {
+            commands::reindex(features, target, module, struct_name, include_derives, package, wait, cli.format, cli.pretty).await
+        }
+        Commands::Status { vis, unimplemented, todo, hotspots, group_by } => {
+            commands::status(vis, unimplemented, todo, hotspots, group_by, cli.format, cli.pretty).await
+        }
+        Commands::Continue { cursor } => {
+            commands::continue_listing(cursor, cli.format, cli.pretty).await
+        }
+        Commands::Stats => {
+            commands::stats(cli.format, cli.pretty).await
+        }
+        Commands::Verify => {
+            commands::verify(cli.format, cli.pretty).await
+        }
+        Commands::Impls { path, traits, external } => {
+            commands::impls(path, traits, external, cli.format, cli.pretty).await
+        }
+        Commands::Callers { path, depth } => {
+            commands::call_graph(path, true, depth, cli.format, cli.pretty).await
+        }
+        Commands::Callees { path, depth } => {
+            commands::call_graph(path, false, depth, cli.format, cli.pretty).await
+        }
+        Commands::Deps { crate_name, dependents, depth } => {
+            commands::deps(crate_name, dependents, depth, cli.format, cli.pretty).await
+        }
+        Commands::Tree { path, depth, kinds } => {
+            commands::tree(path, depth, kinds, cli.format, cli.pretty).await
+        }
+        Commands::Refs { path, group_by, limit } => {
+            commands::refs(path, group_by, limit, cli.format, cli.pretty).await
+        }
+        Commands::Owner { location: (file, line) } => {
+            commands::owner(file, line, cli.format, cli.pretty).await
         }
-        Commands::Reindex { features, target, module, struct_name, include_derives } => {
-            commands::reindex(features, target, module, struct_name, include_derives, cli.format, cli.pretty).await
+        Commands::Open { path } => {
+            commands::open(path, cli.format, cli.pretty).await
         }
-        Commands::Status { vis, unimplemented, todo } => {
-            commands::status(vis, unimplemented, todo, cli.format, cli.pretty).await
+        Commands::Todo { matching, limit } => {
+            commands::todo(matching, limit, cli.format, cli.pretty).await
+        }
+        Commands::Tag { path, add, remove, note } => {
+            commands::tag(path, add, remove, note, cli.format, cli.pretty).await
         }
         Commands::Diag => {
             commands::diag(cli.format, cli.pretty).await
         }
+        Commands::Diff { baseline, breaking_only } => {
+            commands::diff(baseline, breaking_only, cli.format, cli.pretty).await
+        }
+        Commands::Watch { path_prefix, vis } => {
+            commands::watch(path_prefix, vis, cli.format, cli.pretty).await
+        }
+        Commands::Watcher { command } => {
+            commands::watcher(command, cli.format, cli.pretty).await
+        }
         Commands::Bench { queries, warmup, duration } => {
             commands::bench(queries, warmup, duration, cli.format, cli.pretty).await
         }
         Commands::Daemon { command } => {
             commands::daemon(command).await
         }
+        Commands::Db { command } => {
+            commands::db(command, cli.pretty)
+        }
+        Commands::Clean { all, older_than, dry_run } => {
+            commands::clean(all, older_than, dry_run, cli.pretty).await
+        }
+        Commands::Doctor => {
+            commands::doctor(cli.pretty).await
+        }
+        Commands::Completions { shell } => {
+            commands::completions(shell)
+        }
+    }
+}
+
+/// Parse a location like `src/lib.rs:142` or `src/lib.rs:142:5` into the
+/// file and line.
+fn parse_location(location: &str) -> Result<(String, u32), String> {
+    let expected = || format!("expected FILE:LINE, got {:?}", location);
+    let (rest, last) = location.rsplit_once(':').ok_or_else(expected)?;
+    let last: u32 = last.parse().map_err(|_| expected())?;
+    // With a column, the line is the number before it
+    let (file, line) = match rest.rsplit_once(':') {
+        Some((file, line)) => match line.parse() {
+            Ok(line) => (file, line),
+            Err(_) => (rest, last),
+        },
+        None => (rest, last),
+    };
+    if file.is_empty() || line == 0 {
+        return Err(expected());
     }
+    Ok((file.to_string(), line))
+}
+
+/// Parse an age like `30d`: a whole number followed by s, m, h, d or w.
+fn parse_age(age: &str) -> Result<std::time::Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (count, unit) = age.split_at(split);
+    let count: u64 = count.parse().map_err(|_| format!("expected a number followed by a unit, got {:?}", age))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {:?}; use s, m, h, d or w", unit)),
+    };
+    Ok(std::time::Duration::from_secs(count * unit_secs))
 }
 
 #[cfg(test)]
@@ -318,4 +885,34 @@ mod tests {
     fn test_cli_args() {
         Cli::command().debug_assert();
     }
+
+    #[test]
+    fn test_path_completion() {
+        let cli = Cli::command();
+        let completes = |name: &str, id: &str| {
+            let subcommand = cli.find_subcommand(name).unwrap();
+            subcommand.get_positionals().any(|arg| arg.get_id() == id && arg.get::<ArgValueCompleter>().is_some())
+        };
+        assert!(completes("doc", "path") && completes("ls", "path") && completes("export", "paths"));
+        assert!(!completes("ls", "expansion"));
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(parse_location("src/lib.rs:142"), Ok(("src/lib.rs".to_string(), 142)));
+        assert_eq!(parse_location("src/lib.rs:142:5"), Ok(("src/lib.rs".to_string(), 142)));
+        assert_eq!(parse_location("C:/app/src/lib.rs:7"), Ok(("C:/app/src/lib.rs".to_string(), 7)));
+        assert!(parse_location("src/lib.rs").is_err());
+        assert!(parse_location(":3").is_err());
+        assert!(parse_location("src/lib.rs:0").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d"), Ok(std::time::Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_age("90m"), Ok(std::time::Duration::from_secs(90 * 60)));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+//! Paging long pretty output through `$PAGER`, as git does: only when
+//! stdout is a terminal and the output would not fit on it.
+
+use anyhow::Result;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Write `text` to stdout, through the pager when `page` is set, stdout is
+/// a terminal and `text` is taller than it. Falls back to printing when the
+/// pager cannot be started.
+pub fn show(text: &str, page: bool) -> Result<()> {
+    if page && std::io::stdout().is_terminal() {
+        let (columns, rows) = crossterm::terminal::size().unwrap_or((u16::MAX, u16::MAX));
+        if let Some(pager) = pager_command(std::env::var_os("PAGER")).filter(|_| too_tall(text, columns, rows)) {
+            if run(&pager, text).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    print!("{}", text);
+    Ok(())
+}
+
+/// The program and arguments `$PAGER` names, `less` when it is unset, and
+/// `None` when it is empty or `cat`, which mean "don't page".
+fn pager_command(pager: Option<OsString>) -> Option<Vec<String>> {
+    let pager = pager.map(|pager| pager.to_string_lossy().into_owned()).unwrap_or_else(|| "less".to_string());
+    let words: Vec<String> = pager.split_whitespace().map(str::to_string).collect();
+    match words.first().map(String::as_str) {
+        None | Some("cat") => None,
+        Some(_) => Some(words),
+    }
+}
+
+/// Whether `text` takes at least `rows` rows of a terminal `columns` wide,
+/// counting lines longer than that as the rows they wrap onto.
+fn too_tall(text: &str, columns: u16, rows: u16) -> bool {
+    let columns = usize::from(columns.max(1));
+    let needed: usize = text.lines().map(|line| visible_width(line).div_ceil(columns).max(1)).sum();
+    needed >= usize::from(rows)
+}
+
+/// The characters of `line` a terminal shows, leaving out the escape
+/// sequences styling it.
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A CSI sequence runs to its final byte, @ through ~
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+fn run(pager: &[String], text: &str) -> Result<()> {
+    let mut command = Command::new(&pager[0]);
+    command.args(&pager[1..]).stdin(Stdio::piped());
+    // Like git: pass colors through, and quit at once if it fits after all
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager before reading everything closes the pipe
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(pager_command(None), Some(vec!["less".to_string()]));
+        assert_eq!(pager_command(Some("less -R".into())), Some(vec!["less".to_string(), "-R".to_string()]));
+        assert_eq!(pager_command(Some("".into())), None);
+        assert_eq!(pager_command(Some("cat".into())), None);
+
+        assert!(!too_tall("a\nb\n", 80, 24));
+        assert!(too_tall(&"line\n".repeat(24), 80, 24));
+        // Long lines count the rows they wrap onto, styling aside
+        let long = format!("\x1b[1m{}\x1b[0m\n", "x".repeat(100));
+        assert_eq!(visible_width(long.trim_end()), 100);
+        assert!(!too_tall(&long.repeat(11), 80, 24));
+        assert!(too_tall(&long.repeat(12), 80, 24));
+    }
+}
@@ -0,0 +1,220 @@
+//! `ct pick`: an interactive fuzzy selector over symbol paths. It draws on
+//! stderr, so only the chosen path reaches stdout, as in `ct doc $(ct pick)`.
+
+use crate::client::CtClient;
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, queue, terminal};
+use ct_protocol::data::{SearchData, SearchItem};
+use ct_protocol::{Command, Response};
+use std::io::{Stderr, Write};
+use std::time::Duration;
+
+/// What a key does to the picker.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    /// The query changed, so the matches must be fetched again
+    Edit,
+    Move,
+    Accept,
+    Cancel,
+    Ignore,
+}
+
+#[derive(Default)]
+struct Picker {
+    query: String,
+    items: Vec<SearchItem>,
+    selected: usize,
+    /// Why there are no matches, when the daemon refused the query
+    error: Option<String>,
+}
+
+impl Picker {
+    /// Apply `key`: type to edit the query, Up/Down (or Ctrl-P/Ctrl-N) to
+    /// move, Enter to pick and Esc (or Ctrl-C) to back out.
+    fn handle(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => Action::Cancel,
+            KeyCode::Char('c' | 'g') if ctrl => Action::Cancel,
+            KeyCode::Enter => Action::Accept,
+            KeyCode::Up => self.step(-1),
+            KeyCode::Char('p' | 'k') if ctrl => self.step(-1),
+            KeyCode::Down => self.step(1),
+            KeyCode::Char('n' | 'j') if ctrl => self.step(1),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                Action::Edit
+            }
+            KeyCode::Char(_) if ctrl => Action::Ignore,
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                Action::Edit
+            }
+            KeyCode::Backspace => match self.query.pop() {
+                Some(_) => Action::Edit,
+                None => Action::Ignore,
+            },
+            _ => Action::Ignore,
+        }
+    }
+
+    fn step(&mut self, by: isize) -> Action {
+        let last = self.items.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(by).min(last);
+        Action::Move
+    }
+
+    /// Fetch the best `limit` matches of the query.
+    async fn refresh(&mut self, client: &mut CtClient, kind: Option<String>, limit: usize) -> Result<()> {
+        self.items.clear();
+        self.selected = 0;
+        self.error = None;
+        if self.query.trim().is_empty() {
+            return Ok(());
+        }
+        let cmd = Command::FuzzyFind { query: self.query.clone(), kind, vis: None, limit: Some(limit), cursor: None };
+        match client.send_command(cmd).await? {
+            Response::Success(env) => self.items = env.data_as::<SearchData>()?.items,
+            Response::Error(env) => self.error = Some(env.err),
+            Response::Decision(env) => self.error = Some(env.decision_required.reason),
+        }
+        Ok(())
+    }
+
+    /// The query on the first line, up to `visible` matches below it with
+    /// the selected one reversed, and a status line at the bottom.
+    fn draw(&self, out: &mut impl Write, visible: usize) -> Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let fit = |s: &str| s.chars().take(usize::from(columns).saturating_sub(1)).collect::<String>();
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0), Print(fit(&format!("> {}", self.query))))?;
+        for (row, item) in self.items.iter().take(visible).enumerate() {
+            queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+            if row == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(fit(&item.path)), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(fit(&item.path)))?;
+            }
+        }
+        let status = match &self.error {
+            Some(error) => error.clone(),
+            None if self.query.trim().is_empty() => "Type part of a symbol path; Enter picks, Esc cancels".to_string(),
+            None => format!("{} matches", self.items.len()),
+        };
+        let query_width = 2 + self.query.chars().count() as u16;
+        queue!(
+            out,
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            SetAttribute(Attribute::Dim),
+            Print(fit(&status)),
+            SetAttribute(Attribute::Reset),
+            cursor::MoveTo(query_width.min(columns.saturating_sub(1)), 0)
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// The alternate screen on stderr in raw mode, left however the picker ends.
+struct Screen(Stderr);
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut err = std::io::stderr();
+        crossterm::execute!(err, terminal::EnterAlternateScreen)?;
+        Ok(Self(err))
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(self.0, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Let the user pick a symbol by fuzzy path match, starting from `query`;
+/// the chosen path, or `None` if they backed out.
+pub async fn pick(client: &mut CtClient, query: String, kind: Option<String>) -> Result<Option<String>> {
+    let mut screen = Screen::enter()?;
+    let mut picker = Picker { query, ..Default::default() };
+    let mut stale = true;
+    loop {
+        let visible = usize::from(terminal::size()?.1).saturating_sub(2).max(1);
+        if stale {
+            picker.refresh(client, kind.clone(), visible).await?;
+            stale = false;
+        }
+        picker.draw(&mut screen.0, visible)?;
+
+        // Apply every key already typed before querying again
+        let events = tokio::task::spawn_blocking(read_events).await??;
+        for event in events {
+            // Windows reports releases too
+            let Event::Key(key) = event else { continue };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match picker.handle(key) {
+                Action::Accept => return Ok(picker.items.get(picker.selected).map(|item| item.path.clone())),
+                Action::Cancel => return Ok(None),
+                Action::Edit => stale = true,
+                Action::Move | Action::Ignore => {}
+            }
+        }
+    }
+}
+
+/// Wait for the next terminal event, then take those already queued behind
+/// it. It blocks, so it runs on a blocking thread rather than the runtime.
+fn read_events() -> std::io::Result<Vec<Event>> {
+    let mut events = vec![event::read()?];
+    while event::poll(Duration::ZERO)? {
+        events.push(event::read()?);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_picker_keys() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        let item = |path: &str| SearchItem {
+            path: path.to_string(),
+            kind: "fn".to_string(),
+            score: 1.0,
+            span_start: 1,
+            span_end: 1,
+            fields: Default::default(),
+        };
+
+        let mut picker = Picker::default();
+        assert_eq!(picker.handle(key(KeyCode::Char('a'))), Action::Edit);
+        assert_eq!(picker.handle(key(KeyCode::Char('p'))), Action::Edit);
+        assert_eq!(picker.query, "ap");
+        assert_eq!(picker.handle(key(KeyCode::Backspace)), Action::Edit);
+        assert_eq!(picker.query, "a");
+        assert_eq!(picker.handle(ctrl('u')), Action::Edit);
+        assert_eq!(picker.handle(key(KeyCode::Backspace)), Action::Ignore);
+
+        picker.items = vec![item("app::a"), item("app::b")];
+        picker.handle(key(KeyCode::Down));
+        picker.handle(ctrl('n'));
+        assert_eq!(picker.selected, 1);
+        picker.handle(key(KeyCode::Up));
+        picker.handle(ctrl('p'));
+        assert_eq!(picker.selected, 0);
+
+        assert_eq!(picker.handle(key(KeyCode::Enter)), Action::Accept);
+        assert_eq!(picker.handle(key(KeyCode::Esc)), Action::Cancel);
+        assert_eq!(picker.handle(ctrl('c')), Action::Cancel);
+        assert_eq!(picker.handle(ctrl('x')), Action::Ignore);
+    }
+}
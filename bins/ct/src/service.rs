@@ -0,0 +1,332 @@
+//! `ct daemon install`: start a workspace's daemon at login as a per-user
+//! service, in whatever form the platform has for one.
+
+use anyhow::{bail, Context, Result};
+use ct_core::compute_workspace_id;
+use ct_core::config::short_id;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The per-user service managers `install` knows how to register with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// A systemd user unit, on Linux
+    Systemd,
+    /// A launchd agent, on macOS
+    Launchd,
+    /// A Task Scheduler task run at logon, on Windows, which keeps real
+    /// services machine-wide and for administrators
+    TaskScheduler,
+}
+
+impl Platform {
+    pub fn current() -> Option<Self> {
+        if cfg!(windows) {
+            Some(Platform::TaskScheduler)
+        } else if cfg!(target_os = "macos") {
+            Some(Platform::Launchd)
+        } else if cfg!(target_os = "linux") {
+            Some(Platform::Systemd)
+        } else {
+            None
+        }
+    }
+}
+
+/// One workspace's daemon, run from `workspace_root` so it finds the
+/// workspace's ct.toml.
+pub struct Service {
+    /// Unit, agent label or task name, `ct-daemon-<workspace id>`
+    pub name: String,
+    pub platform: Platform,
+    daemon: PathBuf,
+    workspace_root: PathBuf,
+}
+
+impl Service {
+    pub fn new(platform: Platform, workspace_root: &Path, daemon: &Path) -> Self {
+        Self {
+            name: format!("ct-daemon-{}", short_id(&compute_workspace_id(workspace_root))),
+            platform,
+            daemon: plain_path(daemon),
+            workspace_root: plain_path(workspace_root),
+        }
+    }
+
+    /// What gets registered: the unit file, agent plist or task XML.
+    pub fn definition(&self) -> Result<String> {
+        let daemon = self.daemon.to_string_lossy();
+        let root = self.workspace_root.to_string_lossy();
+        Ok(match self.platform {
+            Platform::Systemd => format!(
+                "[Unit]\n\
+                 Description=ct symbol index daemon for {root}\n\
+                 \n\
+                 [Service]\n\
+                 ExecStart={} --idx {}\n\
+                 WorkingDirectory={root}\n\
+                 Restart=on-failure\n\
+                 RestartSec=10\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=default.target\n",
+                systemd_quote(&daemon),
+                systemd_quote(&root),
+                root = root.replace('%', "%%"),
+            ),
+            Platform::Launchd => format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+        <string>--idx</string>
+        <string>{}</string>
+    </array>
+    <key>WorkingDirectory</key>
+    <string>{}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+</dict>
+</plist>
+"#,
+                xml_escape(&self.name),
+                xml_escape(&daemon),
+                xml_escape(&root),
+                xml_escape(&root),
+            ),
+            Platform::TaskScheduler => self.task_xml(&logon_user()?),
+        })
+    }
+
+    /// The Task Scheduler task, run at the logon of `user` and as them.
+    fn task_xml(&self, user: &str) -> String {
+        let daemon = self.daemon.to_string_lossy();
+        let root = self.workspace_root.to_string_lossy();
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>ct symbol index daemon for {}</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <LogonTrigger>
+      <UserId>{}</UserId>
+    </LogonTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{}</UserId>
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <RestartOnFailure>
+      <Interval>PT1M</Interval>
+      <Count>3</Count>
+    </RestartOnFailure>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{}</Command>
+      <Arguments>--idx {}</Arguments>
+      <WorkingDirectory>{}</WorkingDirectory>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+            xml_escape(&root),
+            xml_escape(user),
+            xml_escape(user),
+            xml_escape(&daemon),
+            xml_escape(&windows_quote(&root)),
+            xml_escape(&root),
+        )
+    }
+
+    /// The file the definition is kept in, or `None` under Task Scheduler,
+    /// which keeps its own copy.
+    pub fn definition_path(&self) -> Result<Option<PathBuf>> {
+        let home = || std::env::var_os("HOME").map(PathBuf::from).context("HOME is not set");
+        Ok(match self.platform {
+            Platform::Systemd => {
+                let config = match std::env::var_os("XDG_CONFIG_HOME") {
+                    Some(dir) => PathBuf::from(dir),
+                    None => home()?.join(".config"),
+                };
+                Some(config.join("systemd/user").join(format!("{}.service", self.name)))
+            }
+            Platform::Launchd => Some(home()?.join("Library/LaunchAgents").join(format!("{}.plist", self.name))),
+            Platform::TaskScheduler => None,
+        })
+    }
+
+    /// Register the service and start it now.
+    pub fn install(&self) -> Result<()> {
+        let definition = self.definition()?;
+        if let Some(path) = self.definition_path()? {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&path, &definition).with_context(|| format!("Failed to write {}", path.display()))?;
+            if self.platform == Platform::Launchd {
+                return run("launchctl", &["load", "-w", &path.to_string_lossy()]);
+            }
+        }
+        match self.platform {
+            Platform::Systemd => {
+                run("systemctl", &["--user", "daemon-reload"])?;
+                run("systemctl", &["--user", "enable", "--now", &format!("{}.service", self.name)])
+            }
+            Platform::Launchd => Ok(()),
+            Platform::TaskScheduler => {
+                // schtasks reads task XML as UTF-16
+                let xml = std::env::temp_dir().join(format!("{}.xml", self.name));
+                let utf16: Vec<u8> = std::iter::once(0xFEFF)
+                    .chain(definition.encode_utf16())
+                    .flat_map(u16::to_le_bytes)
+                    .collect();
+                std::fs::write(&xml, utf16)?;
+                let created = run("schtasks", &["/Create", "/F", "/TN", &self.name, "/XML", &xml.to_string_lossy()]);
+                let _ = std::fs::remove_file(&xml);
+                created?;
+                run("schtasks", &["/Run", "/TN", &self.name])
+            }
+        }
+    }
+
+    /// Stop the service and unregister it; `false` if it was not installed.
+    pub fn uninstall(&self) -> Result<bool> {
+        let path = self.definition_path()?;
+        match self.platform {
+            Platform::Systemd | Platform::Launchd => {
+                let Some(path) = path.filter(|path| path.exists()) else {
+                    return Ok(false);
+                };
+                if self.platform == Platform::Systemd {
+                    run("systemctl", &["--user", "disable", "--now", &format!("{}.service", self.name)])?;
+                    std::fs::remove_file(&path)?;
+                    run("systemctl", &["--user", "daemon-reload"])?;
+                } else {
+                    run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+                    std::fs::remove_file(&path)?;
+                }
+            }
+            Platform::TaskScheduler => {
+                if run("schtasks", &["/Query", "/TN", &self.name]).is_err() {
+                    return Ok(false);
+                }
+                // Ending the task kills the daemon; `ct daemon stop` has stopped it cleanly first
+                let _ = run("schtasks", &["/End", "/TN", &self.name]);
+                run("schtasks", &["/Delete", "/F", "/TN", &self.name])?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The signed-in Windows user, as `DOMAIN\\user` when the domain is known.
+fn logon_user() -> Result<String> {
+    Ok(match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+        (Err(_), Ok(user)) => user,
+        _ => bail!("USERNAME is not set, so the logon task has no user to run as"),
+    })
+}
+
+/// Run a service manager command, failing with its output if it fails.
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// `path` without the `\\?\` prefix canonicalizing adds on Windows, which
+/// service managers pass on to programs that may not accept it.
+fn plain_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if !rest.starts_with("UNC\\") => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A word for a systemd command line, quoted.
+fn systemd_quote(word: &str) -> String {
+    let escaped = word.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+/// An argument for a Windows command line, quoted so a trailing `\` does
+/// not escape the closing quote.
+fn windows_quote(arg: &str) -> String {
+    let trailing = arg.len() - arg.trim_end_matches('\\').len();
+    format!("\"{}{}\"", arg, "\\".repeat(trailing))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// `program` as a path a service manager can run without our PATH: as
+/// given if it has a directory, else the first match on PATH.
+pub fn resolve_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.canonicalize().ok();
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definitions() {
+        let root = Path::new("/home/me/my 100% project");
+        let daemon = Path::new("/opt/ct/ct-daemon");
+
+        let unit = Service::new(Platform::Systemd, root, daemon);
+        assert!(unit.name.starts_with("ct-daemon-") && unit.name.len() == "ct-daemon-".len() + 8);
+        let text = unit.definition().unwrap();
+        assert!(text.contains(r#"ExecStart="/opt/ct/ct-daemon" --idx "/home/me/my 100%% project""#), "{}", text);
+        assert!(text.contains("WorkingDirectory=/home/me/my 100%% project\n"));
+
+        let agent = Service::new(Platform::Launchd, Path::new("/Users/me/a&b"), daemon).definition().unwrap();
+        assert!(agent.contains("<string>/Users/me/a&amp;b</string>"));
+
+        let task = Service::new(Platform::TaskScheduler, Path::new(r"\\?\C:\code\app\"), Path::new(r"C:\ct\ct-daemon.exe"));
+        let xml = task.task_xml(r"HOST\me");
+        assert!(xml.contains(r"<UserId>HOST\me</UserId>"), "{}", xml);
+        assert!(xml.contains(r"<Command>C:\ct\ct-daemon.exe</Command>"), "{}", xml);
+        assert!(xml.contains(r"<Arguments>--idx &quot;C:\code\app\\&quot;</Arguments>"), "{}", xml);
+        assert!(xml.contains(r"<WorkingDirectory>C:\code\app\</WorkingDirectory>"));
+        assert!(task.definition_path().unwrap().is_none());
+    }
+}
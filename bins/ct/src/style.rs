@@ -0,0 +1,134 @@
+//! Colors for `--format pretty`: kind badges, public paths, dim locations
+//! and highlighted Rust. Off when stdout is not a terminal or `NO_COLOR` is
+//! set, so pretty output piped to a file stays plain text.
+
+use crossterm::style::{Color, Stylize};
+use ct_protocol::ChangeKind;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    color: bool,
+}
+
+impl Style {
+    /// No escape sequences at all.
+    pub const PLAIN: Self = Self { color: false };
+
+    /// Colored when stdout is a terminal, unless `NO_COLOR` is set to
+    /// anything but the empty string (https://no-color.org).
+    pub fn detect() -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        if no_color || !std::io::stdout().is_terminal() {
+            return Self::PLAIN;
+        }
+        Self { color: true }
+    }
+
+    /// `kind` padded to `width`, colored by what sort of item it is.
+    pub fn kind(self, kind: &str, width: usize) -> String {
+        let padded = format!("{:<width$}", kind, width = width);
+        if !self.color {
+            return padded;
+        }
+        let color = match kind {
+            "module" => Color::Blue,
+            "struct" | "enum" | "union" | "type_alias" | "extern_type" => Color::Cyan,
+            "trait" | "impl" => Color::Magenta,
+            "fn" | "method" | "foreign_fn" => Color::Yellow,
+            "const" | "static" => Color::Red,
+            "macro" => Color::Green,
+            _ => return padded.dim().to_string(),
+        };
+        padded.with(color).to_string()
+    }
+
+    /// `path`, bold when `visibility` is public and dim when private.
+    pub fn path(self, path: &str, visibility: Option<&str>) -> String {
+        match visibility {
+            _ if !self.color => path.to_string(),
+            Some("public") => path.bold().to_string(),
+            Some("private") => path.dim().to_string(),
+            _ => path.to_string(),
+        }
+    }
+
+    /// `+`, `-` or `~` for an added, removed or changed symbol, in green,
+    /// red or yellow.
+    pub fn change(self, change: ChangeKind) -> String {
+        let (sign, color) = match change {
+            ChangeKind::Added => ("+", Color::Green),
+            ChangeKind::Removed => ("-", Color::Red),
+            ChangeKind::Changed => ("~", Color::Yellow),
+        };
+        if self.color { sign.with(color).to_string() } else { sign.to_string() }
+    }
+
+    /// File locations, line ranges and other secondary detail.
+    pub fn dim(self, text: &str) -> String {
+        if self.color { text.dim().to_string() } else { text.to_string() }
+    }
+
+    /// Section headings.
+    pub fn heading(self, text: &str) -> String {
+        if self.color { text.bold().underlined().to_string() } else { text.to_string() }
+    }
+
+    /// `code` highlighted as Rust, or `lang` when syntect knows it by that
+    /// name or extension.
+    pub fn code(self, code: &str, lang: Option<&str>) -> String {
+        if !self.color {
+            return code.to_string();
+        }
+        let (syntaxes, theme) = highlighting();
+        let syntax = lang
+            .and_then(|lang| syntaxes.find_syntax_by_token(lang))
+            .unwrap_or_else(|| syntaxes.find_syntax_by_extension("rs").expect("Rust is a default syntax"));
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut out = String::new();
+        for line in LinesWithEndings::from(code) {
+            match highlighter.highlight_line(line, syntaxes) {
+                Ok(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+                Err(_) => out.push_str(line),
+            }
+        }
+        out.push_str("\x1b[0m");
+        out
+    }
+}
+
+/// The syntaxes and theme, loaded the first time code is highlighted.
+fn highlighting() -> &'static (SyntaxSet, Theme) {
+    static HIGHLIGHTING: OnceLock<(SyntaxSet, Theme)> = OnceLock::new();
+    HIGHLIGHTING.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        let theme = themes.remove("base16-ocean.dark").expect("base16-ocean.dark is a default theme");
+        (SyntaxSet::load_defaults_newlines(), theme)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style() {
+        let plain = Style::PLAIN;
+        assert_eq!(plain.kind("fn", 6), "fn    ");
+        assert_eq!(plain.path("app::run", Some("public")), "app::run");
+        assert_eq!(plain.code("fn run() {}", None), "fn run() {}");
+
+        let color = Style { color: true };
+        assert!(color.kind("fn", 6).contains("fn    "));
+        assert_ne!(color.kind("fn", 6), "fn    ");
+        assert_ne!(color.path("app::run", Some("public")), "app::run");
+        assert_eq!(color.path("app::run", None), "app::run");
+        let code = color.code("pub fn run() -> u32 {}\n", None);
+        assert!(code.contains("\x1b[38;2;") && code.contains("run"));
+    }
+}
@@ -0,0 +1,117 @@
+//! Flat records for `--format csv|tsv|jsonl`, one per item of what the
+//! list-producing commands return, for `awk`, `xsv` and data notebooks.
+
+use serde_json::Value;
+
+/// A line-oriented format records can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    /// RFC 4180, with a header row
+    Csv,
+    /// Tab-separated, with a header row; tabs, newlines and backslashes in
+    /// values are written `\t`, `\n` and `\\`
+    Tsv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Keys under which responses carry their list of results.
+const LIST_KEYS: &[&str] = &["items", "impls", "edges", "changes"];
+
+/// The records in `data`: its list of results, or `data` itself when it is
+/// a single result.
+pub fn records(data: &Value) -> Vec<Value> {
+    match data {
+        Value::Array(items) => items.clone(),
+        Value::Object(map) => LIST_KEYS
+            .iter()
+            .find_map(|key| map.get(*key).and_then(Value::as_array))
+            .cloned()
+            .unwrap_or_else(|| vec![data.clone()]),
+        _ => vec![data.clone()],
+    }
+}
+
+/// `records` as `format`. CSV and TSV have a column for every key any
+/// record has, sorted; nested lists and objects are written as JSON.
+pub fn render(records: &[Value], format: TableFormat) -> String {
+    let separator = match format {
+        TableFormat::Csv => ",",
+        TableFormat::Tsv => "\t",
+        TableFormat::Jsonl => {
+            return records.iter().map(|record| format!("{}\n", record)).collect();
+        }
+    };
+    let mut columns: Vec<&str> = records
+        .iter()
+        .filter_map(Value::as_object)
+        .flat_map(|record| record.keys().map(String::as_str))
+        .collect();
+    columns.sort_unstable();
+    columns.dedup();
+
+    let escape = |field: &str| match format {
+        TableFormat::Csv if field.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        }
+        TableFormat::Tsv => field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"),
+        _ => field.to_string(),
+    };
+    let mut out = columns.iter().map(|column| escape(column)).collect::<Vec<_>>().join(separator);
+    out.push('\n');
+    for record in records {
+        let row: Vec<String> = columns.iter().map(|column| escape(&cell(&record[*column]))).collect();
+        out.push_str(&row.join(separator));
+        out.push('\n');
+    }
+    out
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_formats() {
+        let data = json!({
+            "truncated": false,
+            "items": [
+                {"path": "app::Config", "kind": "struct", "span_start": 3},
+                {"path": "app::parse", "kind": "fn", "docs": "Reads \"a, b\"\n\tand more", "tags": ["x"]},
+            ],
+        });
+        let records = records(&data);
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(
+            render(&records, TableFormat::Csv),
+            "docs,kind,path,span_start,tags\n\
+             ,struct,app::Config,3,\n\
+             \"Reads \"\"a, b\"\"\n\tand more\",fn,app::parse,,\"[\"\"x\"\"]\"\n"
+        );
+        assert_eq!(
+            render(&records, TableFormat::Tsv),
+            "docs\tkind\tpath\tspan_start\ttags\n\
+             \tstruct\tapp::Config\t3\t\n\
+             Reads \"a, b\"\\n\\tand more\tfn\tapp::parse\t\t[\"x\"]\n"
+        );
+        assert_eq!(
+            render(&records, TableFormat::Jsonl),
+            "{\"kind\":\"struct\",\"path\":\"app::Config\",\"span_start\":3}\n\
+             {\"docs\":\"Reads \\\"a, b\\\"\\n\\tand more\",\"kind\":\"fn\",\"path\":\"app::parse\",\"tags\":[\"x\"]}\n"
+        );
+
+        // A single result is one record
+        let owner = json!({"path": "app::main", "line": 7});
+        assert_eq!(render(&super::records(&owner), TableFormat::Csv), "line,path\n7,app::main\n");
+    }
+}
@@ -1,7 +1,7 @@
 mod repl;
 
 use clap::Parser;
-use ct_core::{config::Config, compute_workspace_fingerprint, utils::find_workspace_root};
+use ct_core::{config::Config, compute_workspace_id, utils::find_workspace_root};
 use std::path::PathBuf;
 use anyhow::Result;
 
@@ -25,12 +25,12 @@ async fn main() -> Result<()> {
     };
     
     let config = Config::load()?;
-    let workspace_fingerprint = compute_workspace_fingerprint(&workspace_root);
+    let workspace_id = compute_workspace_id(&workspace_root);
     
     println!("ct REPL - Interactive symbol explorer");
     println!("Type 'help' for commands, 'quit' to exit\n");
     
-    let mut repl = repl::Repl::new(config, workspace_fingerprint, workspace_root)?;
+    let mut repl = repl::Repl::new(config, workspace_id, workspace_root)?;
     repl.run().await?;
     
     println!("\nGoodbye!");
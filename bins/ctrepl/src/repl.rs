@@ -1,5 +1,6 @@
 use ct_core::config::Config;
 use ct_core::transport::IpcClient;
+use ct_protocol::data::{FindData, LsData};
 use ct_protocol::{Request, Response, Command};
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
@@ -9,7 +10,7 @@ use anyhow::{Context, Result};
 
 pub struct Repl {
     config: Config,
-    workspace_fingerprint: String,
+    workspace_id: String,
     _workspace_root: PathBuf,
     current_path: String,
     client: Option<IpcClient>,
@@ -18,12 +19,12 @@ pub struct Repl {
 impl Repl {
     pub fn new(
         config: Config,
-        workspace_fingerprint: String,
+        workspace_id: String,
         workspace_root: PathBuf,
     ) -> Result<Self> {
         Ok(Self {
             config,
-            workspace_fingerprint,
+            workspace_id,
             _workspace_root: workspace_root,
             current_path: "crate".to_string(),
             client: None,
@@ -73,14 +74,14 @@ impl Repl {
     }
 
     async fn connect_daemon(&mut self) -> Result<()> {
-        let client = IpcClient::connect(&self.config, &self.workspace_fingerprint).await
+        let client = IpcClient::connect(&self.config, &self.workspace_id).await
             .context("Failed to connect to daemon")?;
         self.client = Some(client);
         Ok(())
     }
 
     async fn handle_command(&mut self, input: &str) -> Result<()> {
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        let parts: Vec<&str> = input.split_whitespace().collect();
         
         if parts.is_empty() {
             return Ok(());
@@ -181,6 +182,7 @@ impl Repl {
                 cmd,
                 request_id: Uuid::new_v4().to_string(),
                 protocol_version: ct_protocol::PROTOCOL_VERSION,
+                chunked: true,
             };
             
             client.send_request(request).await
@@ -199,10 +201,18 @@ impl Repl {
             vis: None,
             unimplemented: None,
             todo: None,
+            cursor: None,
         };
         
-        let response = self.send_command(cmd).await?;
-        self.print_response(response);
+        match self.send_command(cmd).await? {
+            Response::Success(env) => {
+                let listing: LsData = env.data_as()?;
+                for item in listing.items {
+                    println!("{:<10} {}", item.kind, item.path);
+                }
+            }
+            response => self.print_response(response),
+        }
         Ok(())
     }
 
@@ -210,6 +220,7 @@ impl Repl {
         let cmd = Command::Doc {
             path,
             include_docs: true,
+            examples: false,
             vis: None,
             unimplemented: None,
             todo: None,
@@ -229,16 +240,29 @@ impl Repl {
             unimplemented: None,
             todo: None,
             all: None,
+            recently_changed: None,
+            depth: None,
+            match_mode: None,
+            tag: None,
+            sig: None,
+            cursor: None,
         };
         
-        let response = self.send_command(cmd).await?;
-        self.print_response(response);
+        match self.send_command(cmd).await? {
+            Response::Success(env) => {
+                let found: FindData = env.data_as()?;
+                for item in found.items {
+                    println!("{} (lines {}-{})", item.path, item.span_start, item.span_end);
+                }
+            }
+            response => self.print_response(response),
+        }
         Ok(())
     }
 
     async fn send_export_command(&mut self, path: String, expansion: Option<String>) -> Result<()> {
         let cmd = Command::Export {
-            path,
+            paths: vec![path],
             bundle: true,
             expansion,
             include_docs: true,
@@ -266,6 +290,9 @@ impl Repl {
             }
             Response::Error(env) => {
                 eprintln!("Error: {}", env.err);
+                if let Some(hint) = &env.hint {
+                    eprintln!("Hint: {}", hint);
+                }
             }
         }
     }
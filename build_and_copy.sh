@@ -9,9 +9,10 @@ cargo build --release --all
 
 echo "Copying binaries to project root..."
 cp target/release/ct .
+cp target/release/cargo-ct .
 cp target/release/ct-daemon .
 cp target/release/ctrepl .
 
 echo "Build and copy complete!"
 echo "Binaries available in project root:"
-ls -la ct ct-daemon ctrepl
\ No newline at end of file
+ls -la ct cargo-ct ct-daemon ctrepl
\ No newline at end of file
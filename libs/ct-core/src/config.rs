@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use directories::ProjectDirs;
 use crate::{CoreError, Result};
 
@@ -11,6 +13,11 @@ pub struct Config {
     #[serde(default = "default_autostart")]
     pub autostart: bool,
     
+    /// Run daemons `ct` starts under `ct-daemon --supervise`, which starts
+    /// them again when they crash
+    #[serde(default = "default_supervise")]
+    pub supervise: bool,
+    
     #[serde(default = "default_socket_path")]
     pub socket_path: String,
     
@@ -20,6 +27,13 @@ pub struct Config {
     #[serde(default = "default_tcp_addr")]
     pub tcp_addr: String,
     
+    /// Also serve every command over HTTP on this address, e.g.
+    /// `127.0.0.1:7878`. Only requests addressed to a loopback host name are
+    /// served, and `CT_HTTP_TOKEN` in the daemon's environment makes them
+    /// bring it as a bearer token.
+    #[serde(default)]
+    pub http_addr: Option<String>,
+    
     #[serde(default = "default_allow_full_context")]
     pub allow_full_context: bool,
     
@@ -44,6 +58,8 @@ pub struct Config {
     #[serde(default = "default_references_top_n")]
     pub references_top_n: usize,
     
+    /// Rough memory budget of the daemon: past it, caches are shed and
+    /// exports that would not fit are refused. 0 disables it.
     #[serde(default = "default_max_mem_mb")]
     pub max_mem_mb: usize,
     
@@ -61,6 +77,134 @@ pub struct Config {
     
     #[serde(default = "default_cache_ttl_hours")]
     pub cache_ttl_hours: u32,
+    
+    #[serde(default)]
+    pub git_metadata: bool,
+    
+    #[serde(default = "default_hotspots_top_n")]
+    pub hotspots_top_n: usize,
+    
+    /// How long a query may run, or wait for a reindex to release the
+    /// daemon, before it fails; 0 waits indefinitely. Reindexes and
+    /// benchmarks are not limited.
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    
+    /// Exit once no client has been connected and no file has changed for
+    /// this many minutes; 0 keeps the daemon running.
+    #[serde(default)]
+    pub idle_shutdown_minutes: u64,
+    
+    /// Clients served at once; further connections are turned away with
+    /// `Busy`. 0 allows any number.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    
+    /// Sustained requests per second each connection may send, with bursts
+    /// of up to `rate_limit_burst`; past it requests fail with `Busy`.
+    /// 0 disables the limit.
+    #[serde(default = "default_rate_limit_per_sec")]
+    pub rate_limit_per_sec: u32,
+    
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    
+    #[serde(default)]
+    pub index: IndexConfig,
+    
+    #[serde(default)]
+    pub watcher: WatcherConfig,
+    
+    #[serde(default)]
+    pub log: LogConfig,
+    
+    /// Workspace members to index; empty means every member.
+    #[serde(default)]
+    pub index_members: Vec<String>,
+    
+    #[serde(default)]
+    pub index_exclude_members: Vec<String>,
+}
+
+/// `[index]` table: which source files contribute symbols.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexConfig {
+    /// Globs relative to the workspace root; when non-empty only matching files are indexed.
+    #[serde(default)]
+    pub include: Vec<String>,
+    
+    /// Globs relative to the workspace root that are never indexed, e.g. `benches/**`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    
+    /// Also index `#[cfg(test)]` modules and `#[test]` functions.
+    #[serde(default)]
+    pub include_tests: bool,
+}
+
+/// `[watcher]` table: which file changes trigger an incremental reindex.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatcherConfig {
+    /// Globs relative to the workspace root whose changes are ignored, e.g.
+    /// `**/*.pb.rs` for generated code rewritten on every build.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    
+    /// A batch of at least this many changed files (a branch switch, say)
+    /// triggers one full reindex instead of per-crate incremental ones.
+    #[serde(default = "default_full_reindex_threshold")]
+    pub full_reindex_threshold: usize,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            ignore: vec![],
+            full_reindex_threshold: default_full_reindex_threshold(),
+        }
+    }
+}
+
+/// `[log]` table: the daemon's rolling log file in the cache directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Level for ct's own crates: `error`, `warn`, `info`, `debug` or `trace`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    
+    /// Levels for particular modules, e.g. `"ct_indexer::stream" = "debug"`.
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    
+    /// Daily log files kept; older ones are deleted.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            modules: BTreeMap::new(),
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+/// Crates whose logs `[log] level` applies to.
+const LOGGED_CRATES: [&str; 4] = ["ct_daemon", "ct_indexer", "ct_db", "ct_core"];
+
+impl LogConfig {
+    /// `tracing` filter directives: `level` for ct's crates, then the
+    /// per-module overrides.
+    pub fn filter(&self) -> String {
+        LOGGED_CRATES
+            .iter()
+            .map(|krate| format!("{}={}", krate, self.level))
+            .chain(self.modules.iter().map(|(module, level)| format!("{}={}", module, level)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -70,6 +214,9 @@ pub enum Transport {
     Unix,
     Pipe,
     Tcp,
+    /// Only the process that spawned the daemon, over its stdin and stdout
+    /// (`ct-daemon --stdio`); nothing else can connect
+    Stdio,
 }
 
 impl Default for Config {
@@ -77,9 +224,11 @@ impl Default for Config {
         Self {
             transport: default_transport(),
             autostart: default_autostart(),
+            supervise: default_supervise(),
             socket_path: default_socket_path(),
             pipe_name: default_pipe_name(),
             tcp_addr: default_tcp_addr(),
+            http_addr: None,
             allow_full_context: default_allow_full_context(),
             workspace_allow: vec![],
             max_context_size: default_max_context_size(),
@@ -94,6 +243,18 @@ impl Default for Config {
             watcher_debounce_ms: default_watcher_debounce_ms(),
             auto_clean_on_start: default_auto_clean_on_start(),
             cache_ttl_hours: default_cache_ttl_hours(),
+            git_metadata: false,
+            hotspots_top_n: default_hotspots_top_n(),
+            request_timeout_ms: default_request_timeout_ms(),
+            idle_shutdown_minutes: 0,
+            max_connections: default_max_connections(),
+            rate_limit_per_sec: default_rate_limit_per_sec(),
+            rate_limit_burst: default_rate_limit_burst(),
+            index: IndexConfig::default(),
+            watcher: WatcherConfig::default(),
+            log: LogConfig::default(),
+            index_members: vec![],
+            index_exclude_members: vec![],
         }
     }
 }
@@ -106,6 +267,70 @@ fn default_autostart() -> bool {
     true
 }
 
+fn default_supervise() -> bool {
+    false
+}
+
+/// One workspace's cache directory, as found by `Config::list_cache_dirs`.
+#[derive(Debug, Clone)]
+pub struct CacheDir {
+    pub fingerprint: String,
+    pub path: PathBuf,
+    /// Newest modification time of anything inside; a daemon writes to its
+    /// database (and the WAL beside it) whenever it opens or indexes it.
+    pub last_used: SystemTime,
+    pub size_bytes: u64,
+}
+
+fn list_cache_dirs_in(root: &Path) -> std::io::Result<Vec<CacheDir>> {
+    if !root.is_dir() {
+        return Ok(vec![]);
+    }
+    
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let (last_used, size_bytes) = dir_usage(&path)?;
+        dirs.push(CacheDir {
+            fingerprint: entry.file_name().to_string_lossy().to_string(),
+            path,
+            last_used,
+            size_bytes,
+        });
+    }
+    dirs.sort_by_key(|d| d.last_used);
+    Ok(dirs)
+}
+
+/// Newest modification time and total size of the files under `dir`.
+fn dir_usage(dir: &Path) -> std::io::Result<(SystemTime, u64)> {
+    let mut newest = std::fs::metadata(dir)?.modified()?;
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let (modified, bytes) = if entry.file_type()?.is_dir() {
+            dir_usage(&entry.path())?
+        } else {
+            let meta = entry.metadata()?;
+            (meta.modified()?, meta.len())
+        };
+        newest = newest.max(modified);
+        size += bytes;
+    }
+    Ok((newest, size))
+}
+
+/// The first 8 hex digits of a workspace id, for socket, pipe and service
+/// names that have to stay short.
+pub fn short_id(workspace_id: &str) -> &str {
+    let hex = workspace_id.strip_prefix("blake3:").unwrap_or(workspace_id);
+    &hex[..hex.len().min(8)]
+}
+
 fn default_socket_path() -> String {
     "/tmp/ctd.sock".to_string()
 }
@@ -142,6 +367,14 @@ fn default_references_top_n() -> usize {
     16
 }
 
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
 fn default_max_mem_mb() -> usize {
     512
 }
@@ -158,6 +391,10 @@ fn default_watcher_debounce_ms() -> u64 {
     300
 }
 
+fn default_full_reindex_threshold() -> usize {
+    200
+}
+
 fn default_auto_clean_on_start() -> bool {
     false
 }
@@ -166,6 +403,26 @@ fn default_cache_ttl_hours() -> u32 {
     24
 }
 
+fn default_hotspots_top_n() -> usize {
+    20
+}
+
+fn default_request_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_max_connections() -> usize {
+    64
+}
+
+fn default_rate_limit_per_sec() -> u32 {
+    100
+}
+
+fn default_rate_limit_burst() -> u32 {
+    200
+}
+
 impl Config {
     pub fn load() -> Result<Self> {
         if let Ok(content) = std::fs::read_to_string("ct.toml") {
@@ -185,29 +442,94 @@ impl Config {
     }
 
     pub fn get_cache_dir(&self, workspace_fingerprint: &str) -> PathBuf {
+        self.cache_root().join(workspace_fingerprint)
+    }
+
+    /// File a running daemon for the workspace records its process id in.
+    pub fn get_pid_path(&self, workspace_fingerprint: &str) -> PathBuf {
+        self.get_cache_dir(workspace_fingerprint).join("ct-daemon.pid")
+    }
+
+    /// Crashes of the supervised daemon, one JSON object per line.
+    pub fn get_crash_log_path(&self, workspace_fingerprint: &str) -> PathBuf {
+        self.get_cache_dir(workspace_fingerprint).join("crashes.jsonl")
+    }
+
+    /// Directory of the daemon's log files, one per day, named
+    /// `ct-daemon.log.YYYY-MM-DD`.
+    pub fn get_log_dir(&self, workspace_fingerprint: &str) -> PathBuf {
+        self.get_cache_dir(workspace_fingerprint).join("logs")
+    }
+
+    /// Directory holding one cache directory per workspace fingerprint.
+    pub fn cache_root(&self) -> PathBuf {
         if let Some(proj_dirs) = ProjectDirs::from("", "", "ct") {
-            proj_dirs.cache_dir().join(workspace_fingerprint)
+            proj_dirs.cache_dir().to_path_buf()
         } else {
-            PathBuf::from(".ct").join(workspace_fingerprint)
+            PathBuf::from(".ct")
+        }
+    }
+
+    /// Every workspace cache directory under `cache_root`, least recently
+    /// used first. Databases kept elsewhere via `db_dir` are not included.
+    pub fn list_cache_dirs(&self) -> std::io::Result<Vec<CacheDir>> {
+        list_cache_dirs_in(&self.cache_root())
+    }
+
+    /// Move the cache directory older versions created under the path-only
+    /// `workspace_id` to the one for `workspace_fingerprint`, keeping its
+    /// database. Returns whether anything was moved.
+    pub fn migrate_cache_dir(&self, workspace_id: &str, workspace_fingerprint: &str) -> std::io::Result<bool> {
+        let legacy = self.get_cache_dir(workspace_id);
+        let current = self.get_cache_dir(workspace_fingerprint);
+        if legacy == current || current.exists() || !legacy.is_dir() {
+            return Ok(false);
+        }
+        if let Some(parent) = current.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        std::fs::rename(&legacy, &current)?;
+        Ok(true)
     }
 
-    pub fn get_socket_path(&self, workspace_fingerprint: &str) -> String {
+    /// Sockets and pipes are keyed by the workspace id, not the fingerprint,
+    /// so clients can find a running daemon after `Cargo.lock` changes.
+    pub fn get_socket_path(&self, workspace_id: &str) -> String {
         if cfg!(unix) {
-            format!("/tmp/ctd-{}.sock", &workspace_fingerprint[..8])
+            format!("/tmp/ctd-{}.sock", short_id(workspace_id))
         } else {
             self.socket_path.clone()
         }
     }
 
-    pub fn get_pipe_name(&self, workspace_fingerprint: &str) -> String {
+    pub fn get_pipe_name(&self, workspace_id: &str) -> String {
         if cfg!(windows) {
-            format!(r"\\.\pipe\ctd-{}", &workspace_fingerprint[..8])
+            format!(r"\\.\pipe\ctd-{}", short_id(workspace_id))
         } else {
             self.pipe_name.clone()
         }
     }
 
+    /// `request_timeout_ms` as a duration, or `None` when disabled.
+    pub fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_ms > 0).then(|| Duration::from_millis(self.request_timeout_ms))
+    }
+
+    /// `idle_shutdown_minutes` as a duration, or `None` when disabled.
+    pub fn idle_shutdown(&self) -> Option<Duration> {
+        (self.idle_shutdown_minutes > 0).then(|| Duration::from_secs(self.idle_shutdown_minutes * 60))
+    }
+
+    /// `max_connections`, or `None` when unlimited.
+    pub fn connection_limit(&self) -> Option<usize> {
+        (self.max_connections > 0).then_some(self.max_connections)
+    }
+
+    /// `max_mem_mb` in bytes, or `None` when disabled.
+    pub fn memory_budget(&self) -> Option<usize> {
+        (self.max_mem_mb > 0).then(|| self.max_mem_mb * 1024 * 1024)
+    }
+
     pub fn get_effective_transport(&self) -> Transport {
         match self.transport {
             Transport::Auto => {
@@ -232,7 +554,51 @@ mod tests {
     fn test_default_config() {
         let config = Config::default();
         assert_eq!(config.max_context_size, 16000);
-        assert_eq!(config.autostart, true);
+        assert!(config.autostart);
+    }
+
+    #[test]
+    fn test_index_globs() {
+        let config: Config = toml::from_str(
+            "[index]\ninclude = [\"src/**\"]\nexclude = [\"benches/**\", \"src/generated/**\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.index.include, vec!["src/**"]);
+        assert_eq!(config.index.exclude.len(), 2);
+        assert!(!config.index.include_tests);
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let config: Config = toml::from_str(
+            "[log]\nlevel = \"warn\"\nmodules = { \"ct_indexer::stream\" = \"debug\" }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.log.filter(),
+            "ct_daemon=warn,ct_indexer=warn,ct_db=warn,ct_core=warn,ct_indexer::stream=debug"
+        );
+        assert_eq!(config.log.max_files, 7);
+    }
+
+    #[test]
+    fn test_socket_path_uses_id_digits() {
+        let config = Config::default();
+        #[cfg(unix)]
+        assert_eq!(config.get_socket_path("blake3:0123456789abcdef"), "/tmp/ctd-01234567.sock");
+        #[cfg(windows)]
+        assert_eq!(config.get_pipe_name("blake3:0123456789abcdef"), r"\\.\pipe\ctd-01234567");
+    }
+
+    #[test]
+    fn test_watcher_ignore() {
+        let config: Config = toml::from_str(
+            "[watcher]\nignore = [\"**/generated/**\", \"**/*.pb.rs\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.watcher.ignore, vec!["**/generated/**", "**/*.pb.rs"]);
+        assert_eq!(config.watcher.full_reindex_threshold, 200);
+        assert!(Config::default().watcher.ignore.is_empty());
     }
 
     #[test]
@@ -244,4 +610,26 @@ mod tests {
         #[cfg(windows)]
         assert_eq!(transport, Transport::Pipe);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_list_cache_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(list_cache_dirs_in(&root.path().join("missing")).unwrap().is_empty());
+        
+        for (name, age_secs) in [("blake3:new", 0), ("blake3:old", 3600)] {
+            let dir = root.path().join(name);
+            std::fs::create_dir(&dir).unwrap();
+            let db = dir.join("symbols.sqlite");
+            std::fs::write(&db, [0u8; 100]).unwrap();
+            let mtime = SystemTime::now() - std::time::Duration::from_secs(age_secs);
+            std::fs::File::options().write(true).open(&db).unwrap().set_modified(mtime).unwrap();
+            std::fs::File::open(&dir).unwrap().set_modified(mtime).unwrap();
+        }
+        std::fs::write(root.path().join("stray.txt"), "").unwrap();
+        
+        let dirs = list_cache_dirs_in(root.path()).unwrap();
+        let names: Vec<_> = dirs.iter().map(|d| d.fingerprint.as_str()).collect();
+        assert_eq!(names, vec!["blake3:old", "blake3:new"]);
+        assert_eq!(dirs[0].size_bytes, 100);
+    }
+}
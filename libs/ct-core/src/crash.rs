@@ -0,0 +1,75 @@
+//! Crashes of supervised daemons (`ct-daemon --supervise`), kept in the
+//! workspace cache for `ct doctor`.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+/// Records kept; older ones are dropped as new ones come in.
+const MAX_RECORDS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashRecord {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub pid: u32,
+    /// How the process ended, e.g. `exit code 101` or `signal 11`
+    pub exit: String,
+    /// The panic message if it panicked, else the last lines of its stderr
+    pub reason: String,
+    /// Seconds until the supervisor starts it again, or `None` if it gave up
+    pub restart_in_secs: Option<u64>,
+}
+
+/// Append `record` to the crash log at `path`, one JSON object per line.
+pub fn record(path: &Path, record: &CrashRecord) -> std::io::Result<()> {
+    let mut lines: Vec<String> = std::fs::read_to_string(path)
+        .map(|text| text.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    lines.push(serde_json::to_string(record)?);
+    let keep = &lines[lines.len().saturating_sub(MAX_RECORDS)..];
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::File::create(path)?;
+    for line in keep {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// The last `n` records in the crash log at `path`, oldest first; none if
+/// it does not exist. Lines that do not parse are skipped.
+pub fn recent(path: &Path, n: usize) -> Vec<CrashRecord> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let records: Vec<CrashRecord> = text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    records[records.len().saturating_sub(n)..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_roll_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache/crashes.jsonl");
+        assert!(recent(&path, 5).is_empty());
+
+        for at in 0..25 {
+            let crash = CrashRecord {
+                at,
+                pid: 1,
+                exit: "exit code 101".to_string(),
+                reason: "panicked".to_string(),
+                restart_in_secs: Some(1),
+            };
+            record(&path, &crash).unwrap();
+        }
+        let all = recent(&path, 100);
+        assert_eq!(all.len(), MAX_RECORDS);
+        assert_eq!((all[0].at, all[MAX_RECORDS - 1].at), (5, 24));
+        assert_eq!(recent(&path, 2).iter().map(|r| r.at).collect::<Vec<_>>(), [23, 24]);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod config;
+pub mod crash;
+pub mod memory;
 pub mod models;
+pub mod pidfile;
 pub mod transport;
 pub mod utils;
 
@@ -22,6 +25,9 @@ pub enum CoreError {
     
     #[error("Workspace not found")]
     WorkspaceNotFound,
+    
+    #[error("ct-daemon is already running for this workspace (PID {})", .pid.map_or("unknown".to_string(), |pid| pid.to_string()))]
+    AlreadyRunning { pid: Option<u32> },
 }
 
 pub type Result<T> = std::result::Result<T, CoreError>;
@@ -51,13 +57,56 @@ pub fn compute_file_digest(content: &[u8]) -> String {
     format!("blake3:{}", hash.to_hex())
 }
 
+/// Identifies a workspace by location only. Clients use it to find the
+/// daemon's socket without inspecting the workspace; it is also the name
+/// cache directories had before fingerprints covered content.
+pub fn compute_workspace_id(workspace_path: &Path) -> String {
+    let mut hasher = Hasher::new();
+    hasher.update(workspace_path.to_string_lossy().as_bytes());
+    let hash = hasher.finalize();
+    format!("blake3:{}", &hash.to_hex()[..16])
+}
+
+/// Identifies the index a workspace needs: its location, the resolved
+/// dependencies in `Cargo.lock` and the toolchain that produces rustdoc JSON.
+/// A copied workspace or a container with different contents at the same
+/// path gets its own cache.
 pub fn compute_workspace_fingerprint(workspace_path: &Path) -> String {
     let mut hasher = Hasher::new();
     hasher.update(workspace_path.to_string_lossy().as_bytes());
+    hasher.update(b"\0");
+    match std::fs::read(workspace_path.join("Cargo.lock")) {
+        Ok(lock) => hasher.update(blake3::hash(&lock).as_bytes()),
+        Err(_) => hasher.update(b"no-lockfile"),
+    };
+    hasher.update(b"\0");
+    hasher.update(toolchain_version(workspace_path).as_bytes());
     let hash = hasher.finalize();
     format!("blake3:{}", &hash.to_hex()[..16])
 }
 
+/// `rustc --version` of the nightly toolchain rustdoc JSON is generated with,
+/// as resolved from `workspace_path` (so `rust-toolchain` files apply).
+fn toolchain_version(workspace_path: &Path) -> String {
+    let dir = if workspace_path.is_dir() {
+        workspace_path
+    } else {
+        workspace_path.parent().unwrap_or(Path::new("."))
+    };
+    for args in [&["+nightly", "--version"][..], &["--version"][..]] {
+        let output = std::process::Command::new("rustc")
+            .args(args)
+            .current_dir(dir)
+            .output();
+        if let Ok(output) = output {
+            if output.status.success() {
+                return String::from_utf8_lossy(&output.stdout).trim().to_string();
+            }
+        }
+    }
+    "unknown".to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -84,6 +133,19 @@ mod tests {
         assert_eq!(id1.len(), 32); // 16 bytes as hex
     }
 
+    #[test]
+    fn test_workspace_fingerprint_tracks_lockfile() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let id = compute_workspace_id(dir.path());
+        let before = compute_workspace_fingerprint(dir.path());
+        assert_eq!(before, compute_workspace_fingerprint(dir.path()));
+        
+        std::fs::write(dir.path().join("Cargo.lock"), "version = 4\n").unwrap();
+        let after = compute_workspace_fingerprint(dir.path());
+        assert_ne!(before, after);
+        assert_eq!(id, compute_workspace_id(dir.path()));
+    }
+
     #[test]
     fn test_file_digest() {
         let content = b"hello world";
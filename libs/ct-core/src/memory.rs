@@ -0,0 +1,52 @@
+//! Approximate accounting of the daemon's larger buffers, so it can report
+//! its footprint and stay within `max_mem_mb`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bytes currently held by one kind of buffer. Clones share the count.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryGauge(Arc<AtomicUsize>);
+
+impl MemoryGauge {
+    /// Count `bytes` as held until the returned guard is dropped.
+    pub fn hold(&self, bytes: usize) -> MemoryHold {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+        MemoryHold { gauge: self.clone(), bytes }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Bytes counted by a `MemoryGauge`, released when dropped.
+#[derive(Debug)]
+#[must_use = "the bytes are released as soon as the hold is dropped"]
+pub struct MemoryHold {
+    gauge: MemoryGauge,
+    bytes: usize,
+}
+
+impl Drop for MemoryHold {
+    fn drop(&mut self) {
+        self.gauge.0.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_release_on_drop() {
+        let gauge = MemoryGauge::default();
+        let first = gauge.hold(100);
+        let second = gauge.clone().hold(20);
+        assert_eq!(gauge.bytes(), 120);
+        drop(first);
+        assert_eq!(gauge.bytes(), 20);
+        drop(second);
+        assert_eq!(gauge.bytes(), 0);
+    }
+}
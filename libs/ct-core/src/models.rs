@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Symbol {
@@ -23,6 +24,7 @@ pub struct Symbol {
 pub enum SymbolKind {
     Module,
     Struct,
+    Union,
     Enum,
     Trait,
     Fn,
@@ -33,6 +35,11 @@ pub enum SymbolKind {
     Const,
     Static,
     Impl,
+    Macro,
+    /// An opaque `type T;` declared in an `extern` block.
+    ExternType,
+    /// A function declared in an `extern` block, implemented elsewhere.
+    ForeignFn,
 }
 
 impl SymbolKind {
@@ -40,6 +47,7 @@ impl SymbolKind {
         match self {
             Self::Module => "module",
             Self::Struct => "struct",
+            Self::Union => "union",
             Self::Enum => "enum",
             Self::Trait => "trait",
             Self::Fn => "fn",
@@ -50,6 +58,9 @@ impl SymbolKind {
             Self::Const => "const",
             Self::Static => "static",
             Self::Impl => "impl",
+            Self::Macro => "macro",
+            Self::ExternType => "extern_type",
+            Self::ForeignFn => "foreign_fn",
         }
     }
 }
@@ -114,6 +125,16 @@ pub struct ImplBlock {
     pub line_end: u32,
 }
 
+/// A trait implemented for a type, and where the impl block is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraitImpl {
+    pub trait_path: String,
+    pub for_path: String,
+    pub file: String,
+    pub line_start: u32,
+    pub line_end: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reference {
     pub id: i64,
@@ -122,6 +143,101 @@ pub struct Reference {
     pub file_id: i64,
     pub span_start: u32,
     pub span_end: u32,
+    /// 1-based column of `span_start` the reference starts at
+    pub span_col: u32,
+}
+
+/// A reference to `target_path` from the symbol at `from_path`, with the
+/// crate, file and lines it was made on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceSite {
+    pub from_path: String,
+    pub target_path: String,
+    pub crate_name: String,
+    pub file: String,
+    pub span_start: u32,
+    pub span_end: u32,
+    /// Unknown for references indexed before columns were recorded
+    pub span_col: Option<u32>,
+}
+
+/// A call from the body of one symbol to a path, as written at the call site.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Call {
+    pub caller_symbol_id: String,
+    pub callee_path: String,
+    pub file_id: i64,
+    pub span_start: u32,
+    pub span_end: u32,
+}
+
+/// One edge of the call graph with the caller resolved to its path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallSite {
+    pub caller_path: String,
+    pub callee_path: String,
+    pub file: String,
+    pub span_start: u32,
+    pub span_end: u32,
+}
+
+/// Git history for a symbol's span, recorded when `git_metadata` is enabled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolHistory {
+    pub last_commit: String,
+    /// Commit time of `last_commit` as unix seconds.
+    pub last_modified: i64,
+    /// Number of commits that touched the span.
+    pub churn: u32,
+}
+
+/// A `todo!()`, `unimplemented!()` or `TODO`/`FIXME` comment in a source
+/// file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    /// `todo`, `unimplemented`, `TODO` or `FIXME`.
+    pub marker: String,
+    /// The macro's message, or the comment from the marker on.
+    pub message: String,
+    pub line: u32,
+    /// Author time of the line as unix seconds, recorded when `git_metadata`
+    /// is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introduced: Option<i64>,
+}
+
+/// A marker with the innermost symbol it is in, or its crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarkerSite {
+    pub path: String,
+    pub file: String,
+    #[serde(flatten)]
+    pub marker: Marker,
+}
+
+/// A fenced code block taken from an item's doc comment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocExample {
+    /// Language from the fence info string; `rust` when none is given.
+    pub lang: String,
+    pub code: String,
+}
+
+/// A user-supplied label on a symbol, kept across reindexes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tag {
+    pub tag: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hotspot {
+    pub path: String,
+    pub kind: SymbolKind,
+    pub churn: u32,
+    pub last_commit: String,
+    pub last_modified: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -158,7 +274,33 @@ impl Default for BundleInvariants {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Indexed files compared against the workspace on disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    pub files_checked: usize,
+    /// Crates with at least one changed or missing file, by name.
+    pub stale_crates: Vec<StaleCrate>,
+}
+
+impl VerifyReport {
+    /// Every indexed file still has the content it was indexed from.
+    pub fn is_fresh(&self) -> bool {
+        self.stale_crates.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaleCrate {
+    pub name: String,
+    /// Files whose content no longer matches the stored digest.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<String>,
+    /// Files that were indexed but are gone or unreadable.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct StatusCounts {
     pub total: usize,
     pub implemented: usize,
@@ -166,6 +308,66 @@ pub struct StatusCounts {
     pub todo: usize,
 }
 
+/// Status counts of one crate, module or kind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusGroupCounts {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: StatusCounts,
+}
+
+/// Symbol counts over the whole index or one crate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SymbolStats {
+    pub total: usize,
+    /// Symbols with non-empty docs.
+    pub documented: usize,
+    /// `documented / total`, 0.0 when there are no symbols.
+    pub doc_coverage: f64,
+    pub by_kind: BTreeMap<String, usize>,
+    pub by_visibility: BTreeMap<String, usize>,
+    pub by_status: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrateSymbolStats {
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: SymbolStats,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexSummary {
+    pub totals: SymbolStats,
+    /// Ordered by crate name.
+    pub crates: Vec<CrateSymbolStats>,
+    /// Longest first.
+    #[serde(default)]
+    pub largest_files: Vec<FileSize>,
+    /// Functions and methods, longest first.
+    #[serde(default)]
+    pub largest_fns: Vec<FnSize>,
+}
+
+/// An indexed file, sized by its symbols. `lines` runs to the end of its
+/// last symbol, as the index does not keep file lengths.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSize {
+    pub path: String,
+    pub crate_name: String,
+    pub symbols: usize,
+    pub lines: u32,
+}
+
+/// A function or method and the lines its span covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FnSize {
+    pub path: String,
+    pub file: String,
+    pub span_start: u32,
+    pub lines: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatusItem {
     pub path: String,
@@ -176,8 +378,13 @@ pub struct StatusItem {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatusResponse {
     pub counts: StatusCounts,
+    /// `counts` split up when grouping was requested, ordered by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<StatusGroupCounts>,
     #[serde(default)]
     pub items: Vec<StatusItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hotspots: Vec<Hotspot>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -199,6 +406,221 @@ pub struct DiagResponse {
     pub target: String,
     pub daemon_hot: bool,
     pub transport: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_crates: Vec<CrateFailure>,
+    /// Per-crate breakdown of the last indexing run, slowest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub crate_stats: Vec<CrateIndexStats>,
+    /// Absent when the daemon runs without a file watcher.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watcher: Option<WatcherStatus>,
+    /// The reindex job queue, as `Command::Progress` reports it.
+    #[serde(default)]
+    pub reindex: ct_protocol::data::ProgressData,
+    /// What `mem_footprint_bytes` is made of.
+    #[serde(default)]
+    pub memory: MemoryUsage,
+}
+
+/// The daemon's approximate memory use, by what holds it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// Page caches and statements of every SQLite connection
+    pub sqlite_bytes: usize,
+    /// Lookup tables kept while a crate's rustdoc JSON is read
+    pub rustdoc_json_bytes: usize,
+    /// Responses and events not yet written to their connections
+    pub response_bytes: usize,
+    /// `max_mem_mb` in bytes, if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_bytes: Option<usize>,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.sqlite_bytes + self.rustdoc_json_bytes + self.response_bytes
+    }
+}
+
+/// What the file watcher has seen, for working out why edits are not indexed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WatcherStatus {
+    pub running: bool,
+    /// Paused via `WatcherControl`; changes queue until resumed.
+    pub paused: bool,
+    /// Files whose changes are reported: the Rust sources found when
+    /// watching started, and the non-Rust inputs tracked now.
+    pub watched_paths: usize,
+    /// Raw filesystem events, before filtering and debouncing.
+    pub events_received: u64,
+    /// RFC 3339 time of the most recent event, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_event: Option<String>,
+    /// Paths in the debounce window currently being collected.
+    pub pending_batch: usize,
+    /// Changed files held back while paused.
+    pub queued_while_paused: usize,
+}
+
+/// Where the time went while indexing one crate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrateIndexStats {
+    pub name: String,
+    pub symbols: usize,
+    pub files: usize,
+    pub duration_ms: u64,
+    /// Running `cargo rustdoc`; zero for crates parsed from source.
+    pub rustdoc_ms: u64,
+    /// Reading and deserializing rustdoc JSON or source files.
+    pub parse_ms: u64,
+    /// Writing rows to the database.
+    pub db_ms: u64,
+}
+
+/// A dependency declared in a crate's manifest, by package name. `kind` is
+/// `normal`, `dev` or `build`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrateDependency {
+    pub name: String,
+    pub kind: String,
+    pub req: String,
+    pub optional: bool,
+}
+
+/// One edge of the crate dependency graph, found `depth` steps from where
+/// the walk started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: String,
+    pub req: String,
+    pub optional: bool,
+    pub depth: usize,
+}
+
+/// Symbol changes travel in `Event::SymbolsChanged`, so the protocol owns them.
+pub use ct_protocol::{ChangeKind, SymbolChange};
+
+/// A workspace member whose indexing failed while the rest of the run continued.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrateFailure {
+    pub name: String,
+    pub error: String,
+}
+
+/// Where the next page of a path-ordered query starts: strictly after the
+/// row with this path and rowid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub after_path: String,
+    pub after_rowid: i64,
+}
+
+/// One page of query results; `next_cursor` is set when more rows follow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+/// A full-text search match; lower `score` ranks higher (SQLite bm25).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub symbol: Symbol,
+    pub score: f64,
+}
+
+/// A fuzzy match of a symbol's path; higher `score` ranks higher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuzzyHit {
+    #[serde(flatten)]
+    pub symbol: Symbol,
+    pub score: i64,
+}
+
+/// A symbol with the symbols nested under it, for outlines of a module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeNode {
+    pub path: String,
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    /// Nest `symbols` by path. A symbol whose parent is missing hangs off
+    /// its nearest ancestor that is present, or becomes a root.
+    pub fn build(mut symbols: Vec<Symbol>) -> Vec<TreeNode> {
+        // By segment, so `a::b::c` directly follows `a::b` even if `a::b0` exists
+        symbols.sort_by(|a, b| a.path.split("::").cmp(b.path.split("::")));
+        let mut roots = Vec::new();
+        let mut open: Vec<TreeNode> = Vec::new();
+        for symbol in symbols {
+            while open.last().is_some_and(|top| !is_nested(&symbol.path, &top.path)) {
+                Self::close(&mut open, &mut roots);
+            }
+            open.push(TreeNode {
+                path: symbol.path,
+                name: symbol.name,
+                kind: symbol.kind,
+                children: Vec::new(),
+            });
+        }
+        while !open.is_empty() {
+            Self::close(&mut open, &mut roots);
+        }
+        roots
+    }
+
+    /// Drop the nodes whose kind is not one of `kinds`, keeping those with
+    /// a kept node under them so the tree stays connected.
+    pub fn retain_kinds(nodes: &mut Vec<TreeNode>, kinds: &[String]) {
+        nodes.retain_mut(|node| {
+            Self::retain_kinds(&mut node.children, kinds);
+            !node.children.is_empty() || kinds.iter().any(|kind| kind == node.kind.as_str())
+        });
+    }
+
+    fn close(open: &mut Vec<TreeNode>, roots: &mut Vec<TreeNode>) {
+        let Some(node) = open.pop() else {
+            return;
+        };
+        match open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+}
+
+fn is_nested(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent).is_some_and(|rest| rest.starts_with("::"))
+}
+
+/// A symbol whose docs match a search, with an excerpt of the docs in
+/// which the matched words are highlighted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocHit {
+    pub path: String,
+    pub kind: SymbolKind,
+    pub snippet: String,
+    pub score: f64,
+}
+
+/// A symbol whose name, signature or docs matched a grep: the line of
+/// `field` that matched, with the match highlighted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrepHit {
+    pub path: String,
+    pub kind: SymbolKind,
+    /// `name`, `sig` or `docs`
+    pub field: String,
+    pub snippet: String,
+    pub span_start: u32,
+    pub span_end: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -221,4 +643,47 @@ mod tests {
         assert_eq!(Visibility::Public.as_str(), "public");
         assert_eq!(Visibility::Private.as_str(), "private");
     }
+
+    #[test]
+    fn test_tree_build() {
+        let symbol = |path: &str, kind: SymbolKind| Symbol {
+            symbol_id: path.to_string(),
+            crate_id: 1,
+            file_id: 1,
+            path: path.to_string(),
+            name: path.rsplit("::").next().unwrap().to_string(),
+            kind,
+            visibility: Visibility::Public,
+            signature: String::new(),
+            docs: None,
+            status: ImplementationStatus::Implemented,
+            span_start: 1,
+            span_end: 1,
+            def_hash: String::new(),
+        };
+        let mut tree = TreeNode::build(vec![
+            symbol("app::net0", SymbolKind::Module),
+            symbol("app::net::Client", SymbolKind::Struct),
+            symbol("app", SymbolKind::Module),
+            symbol("app::net", SymbolKind::Module),
+            // No `app::net::Client::Read` symbol: hangs off Client
+            symbol("app::net::Client::Read::read", SymbolKind::Method),
+            symbol("app::net::connect", SymbolKind::Fn),
+        ]);
+        let outline = |nodes: &[TreeNode]| -> Vec<String> {
+            fn walk(nodes: &[TreeNode], indent: usize, out: &mut Vec<String>) {
+                for node in nodes {
+                    out.push(format!("{}{}", " ".repeat(indent), node.name));
+                    walk(&node.children, indent + 1, out);
+                }
+            }
+            let mut out = Vec::new();
+            walk(nodes, 0, &mut out);
+            out
+        };
+        assert_eq!(outline(&tree), vec!["app", " net", "  Client", "   read", "  connect", " net0"]);
+        
+        TreeNode::retain_kinds(&mut tree, &["struct".to_string()]);
+        assert_eq!(outline(&tree), vec!["app", " net", "  Client"]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+//! The daemon's PID file. It stays locked for as long as the daemon runs,
+//! which is how a second daemon for the same workspace, or `ct daemon stop`,
+//! tells a live daemon from a file left behind by one that died.
+
+use crate::{CoreError, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A locked file holding this process's id, removed when dropped.
+pub struct PidFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl PidFile {
+    /// Locks the file at `path` and writes this process's id into it.
+    /// Fails with `CoreError::AlreadyRunning` while another process holds it.
+    pub fn acquire(path: PathBuf) -> Result<Self> {
+        loop {
+            let mut file = open(&path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    return Err(CoreError::AlreadyRunning { pid: read_from(&mut file) });
+                }
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+            // An exiting daemon removed the file between our open and lock;
+            // the next daemon would lock a new one, so start over with that
+            if !is_current(&file, &path)? {
+                continue;
+            }
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            return Ok(Self { path, file: Some(file) });
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            remove_locked(&self.path, file);
+        }
+    }
+}
+
+/// Removes the file at `path`, unlocking it through `file`. Windows will not
+/// delete a file that is still open; elsewhere the lock is kept until the
+/// file is gone. A daemon that opened it before then locks the removed file
+/// once it is unlocked, which `acquire` notices and retries.
+fn remove_locked(path: &Path, file: File) {
+    if cfg!(windows) {
+        drop(file);
+        let _ = std::fs::remove_file(path);
+    } else {
+        let _ = std::fs::remove_file(path);
+        drop(file);
+    }
+}
+
+/// Whether a process holds the PID file at `path`. A file nobody holds was
+/// left by a daemon that died; it is left for the next daemon to take over,
+/// as removing it here could race with one starting.
+pub fn is_running(path: &Path) -> Result<bool> {
+    let file = match open_existing(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    match file.try_lock() {
+        // Dropping the file releases the lock again
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// The process id written to the PID file at `path`, if it has one.
+pub fn read_pid(path: &Path) -> Option<u32> {
+    read_from(&mut File::open(path).ok()?)
+}
+
+fn read_from(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    // Not truncated: a running daemon's id must survive a failed attempt
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+}
+
+fn open_existing(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).open(path)
+}
+
+/// Whether `file` is still the one at `path` rather than one removed since
+/// it was opened.
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let current = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let held = file.metadata()?;
+    Ok((held.dev(), held.ino()) == (current.dev(), current.ino()))
+}
+
+/// Windows removes the file only once its holder closed it, see
+/// `remove_locked`, so a locked file is the current one while it exists.
+#[cfg(not(unix))]
+fn is_current(_file: &File, path: &Path) -> std::io::Result<bool> {
+    Ok(path.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_excludes_second_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ct-daemon.pid");
+        assert!(!is_running(&path).unwrap());
+
+        let pid_file = PidFile::acquire(path.clone()).unwrap();
+        assert_eq!(pid_file.path(), path);
+        assert!(is_running(&path).unwrap());
+        assert_eq!(read_pid(&path), Some(std::process::id()));
+
+        let err = PidFile::acquire(path.clone()).err().unwrap();
+        assert!(matches!(err, CoreError::AlreadyRunning { pid: Some(pid) } if pid == std::process::id()));
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        assert_eq!(read_pid(&path), Some(std::process::id()));
+
+        drop(pid_file);
+        assert!(!path.exists());
+        assert!(!is_running(&path).unwrap());
+    }
+
+    #[test]
+    fn test_stale_pid_file_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ct-daemon.pid");
+        std::fs::write(&path, "4242\n").unwrap();
+        assert!(!is_running(&path).unwrap());
+        // Probing leaves the file to whichever daemon starts next
+        assert_eq!(read_pid(&path), Some(4242));
+
+        let pid_file = PidFile::acquire(path.clone()).unwrap();
+        assert_eq!(read_pid(pid_file.path()), Some(std::process::id()));
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_removed_pid_file_is_not_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ct-daemon.pid");
+
+        // Opened by a starting daemon just before the running one exits
+        let pid_file = PidFile::acquire(path.clone()).unwrap();
+        let early = open(&path).unwrap();
+        drop(pid_file);
+        early.try_lock().unwrap();
+        assert!(!is_current(&early, &path).unwrap());
+
+        // The file a later daemon creates is the one that counts
+        let pid_file = PidFile::acquire(path.clone()).unwrap();
+        assert!(!is_current(&early, &path).unwrap());
+        assert!(is_running(&path).unwrap());
+        drop(pid_file);
+    }
+}
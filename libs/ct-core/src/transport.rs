@@ -1,51 +1,86 @@
 use crate::config::{Config, Transport as TransportType};
 use crate::{CoreError, Result};
-use ct_protocol::{deserialize_message, serialize_message, Request, Response};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::collections::HashMap;
+use ct_protocol::{deserialize_message, serialize_message, Event, Frame, Request, Response, ServerMessage, ChunkAssembler};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 #[cfg(windows)]
 use tokio::net::windows::named_pipe::ClientOptions;
 #[cfg(unix)]
 use tokio::net::UnixStream;
 
-/// Represents a transport stream for IPC communication
+/// The error opening a named pipe gives when no instance is free.
+#[cfg(windows)]
+const ERROR_PIPE_BUSY: i32 = 231;
+/// Attempts, 50 ms apart, to open a busy pipe before giving up.
+#[cfg(windows)]
+const PIPE_BUSY_RETRIES: u32 = 40;
+
+/// Represents a transport stream for IPC communication. Reads are buffered
+/// for the life of the connection, as a chunked response spans several lines.
 pub enum TransportStream {
     #[cfg(unix)]
-    Unix(UnixStream),
+    Unix(BufReader<UnixStream>),
     #[cfg(windows)]
-    Pipe(tokio::net::windows::named_pipe::NamedPipeClient),
-    Tcp(tokio::net::TcpStream),
+    Pipe(BufReader<tokio::net::windows::named_pipe::NamedPipeClient>),
+    Tcp(BufReader<tokio::net::TcpStream>),
 }
 
 impl TransportStream {
-    pub async fn connect(config: &Config, workspace_fingerprint: &str) -> Result<Self> {
+    pub async fn connect(config: &Config, workspace_id: &str) -> Result<Self> {
         match config.get_effective_transport() {
             #[cfg(unix)]
             TransportType::Unix => {
-                let path = config.get_socket_path(workspace_fingerprint);
+                let path = config.get_socket_path(workspace_id);
                 let stream = UnixStream::connect(&path)
                     .await
-                    .map_err(|e| CoreError::Io(e))?;
-                Ok(TransportStream::Unix(stream))
+                    .map_err(CoreError::Io)?;
+                Ok(TransportStream::Unix(BufReader::new(stream)))
             }
             #[cfg(windows)]
             TransportType::Pipe => {
-                let pipe_name = config.get_pipe_name(workspace_fingerprint);
-                let client = ClientOptions::new()
-                    .open(&pipe_name)
-                    .map_err(|e| CoreError::Io(e))?;
-                Ok(TransportStream::Pipe(client))
+                let pipe_name = config.get_pipe_name(workspace_id);
+                // Every instance can be taken for a moment while the daemon
+                // hands one to a client and creates the next
+                let mut attempts = 0;
+                let client = loop {
+                    match ClientOptions::new().open(&pipe_name) {
+                        Ok(client) => break client,
+                        Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) && attempts < PIPE_BUSY_RETRIES => {
+                            attempts += 1;
+                            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        }
+                        Err(e) => return Err(CoreError::Io(e)),
+                    }
+                };
+                Ok(TransportStream::Pipe(BufReader::new(client)))
             }
             TransportType::Tcp => {
                 let stream = tokio::net::TcpStream::connect(&config.tcp_addr)
                     .await
-                    .map_err(|e| CoreError::Io(e))?;
-                Ok(TransportStream::Tcp(stream))
+                    .map_err(CoreError::Io)?;
+                Ok(TransportStream::Tcp(BufReader::new(stream)))
             }
             _ => Err(CoreError::Config("Unsupported transport".to_string())),
         }
     }
 
+    /// Pass bytes both ways between this connection and `other` until
+    /// both sides are done, e.g. to put a running daemon on a child's stdio.
+    pub async fn relay<S>(self, other: &mut S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self {
+            #[cfg(unix)]
+            TransportStream::Unix(mut stream) => tokio::io::copy_bidirectional(&mut stream, other).await?,
+            #[cfg(windows)]
+            TransportStream::Pipe(mut stream) => tokio::io::copy_bidirectional(&mut stream, other).await?,
+            TransportStream::Tcp(mut stream) => tokio::io::copy_bidirectional(&mut stream, other).await?,
+        };
+        Ok(())
+    }
+
     pub async fn send_request(&mut self, request: &Request) -> Result<()> {
         let msg = serialize_message(request)
             .map_err(|e| CoreError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
@@ -70,28 +105,48 @@ impl TransportStream {
         Ok(())
     }
 
+    /// Read one response, reassembling it if it was split into frames.
     pub async fn read_response(&mut self) -> Result<Response> {
-        let line = match self {
-            #[cfg(unix)]
-            TransportStream::Unix(stream) => {
-                let mut reader = BufReader::new(stream);
-                let mut line = String::new();
-                reader.read_line(&mut line).await?;
-                line
-            }
-            #[cfg(windows)]
-            TransportStream::Pipe(client) => {
-                let mut reader = BufReader::new(client);
-                let mut line = String::new();
-                reader.read_line(&mut line).await?;
-                line
+        self.read_response_with_events(&mut |_| {}).await
+    }
+
+    /// Read one response, passing any events that arrive first to `on_event`.
+    pub async fn read_response_with_events(&mut self, on_event: &mut (dyn FnMut(Event) + Send)) -> Result<Response> {
+        let mut assembler = ChunkAssembler::default();
+        loop {
+            let message = self.read_message().await?;
+            let frame = match message {
+                ServerMessage::Response(response) => return Ok(response),
+                ServerMessage::Frame(Frame::Event(event)) => {
+                    on_event(event);
+                    continue;
+                }
+                ServerMessage::Frame(frame) => frame,
+            };
+            if let Some(response) = assembler.push(frame).map_err(invalid_data)? {
+                return Ok(response);
             }
-            TransportStream::Tcp(stream) => {
-                let mut reader = BufReader::new(stream);
-                let mut line = String::new();
-                reader.read_line(&mut line).await?;
-                line
+        }
+    }
+
+    /// Wait for the next event pushed to this connection, e.g. after
+    /// `Command::Subscribe`, skipping anything else that arrives.
+    pub async fn read_event(&mut self) -> Result<Event> {
+        loop {
+            if let ServerMessage::Frame(Frame::Event(event)) = self.read_message().await? {
+                return Ok(event);
             }
+        }
+    }
+
+    async fn read_message(&mut self) -> Result<ServerMessage> {
+        let mut line = String::new();
+        match self {
+            #[cfg(unix)]
+            TransportStream::Unix(reader) => reader.read_line(&mut line).await?,
+            #[cfg(windows)]
+            TransportStream::Pipe(reader) => reader.read_line(&mut line).await?,
+            TransportStream::Tcp(reader) => reader.read_line(&mut line).await?,
         };
 
         let line = line.trim();
@@ -102,18 +157,21 @@ impl TransportStream {
             )));
         }
 
-        deserialize_message(line)
-            .map_err(|e| CoreError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+        deserialize_message(line).map_err(invalid_data)
     }
 }
 
+fn invalid_data(e: ct_protocol::ProtocolError) -> CoreError {
+    CoreError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
 pub struct IpcClient {
     stream: TransportStream,
 }
 
 impl IpcClient {
-    pub async fn connect(config: &Config, workspace_fingerprint: &str) -> Result<Self> {
-        let stream = TransportStream::connect(config, workspace_fingerprint).await?;
+    pub async fn connect(config: &Config, workspace_id: &str) -> Result<Self> {
+        let stream = TransportStream::connect(config, workspace_id).await?;
         Ok(Self { stream })
     }
 
@@ -121,6 +179,58 @@ impl IpcClient {
         self.stream.send_request(&request).await?;
         self.stream.read_response().await
     }
+
+    /// Like `send_request`, handing events pushed while waiting to `on_event`.
+    pub async fn send_request_with_events(
+        &mut self,
+        request: Request,
+        on_event: &mut (dyn FnMut(Event) + Send),
+    ) -> Result<Response> {
+        self.stream.send_request(&request).await?;
+        self.stream.read_response_with_events(on_event).await
+    }
+
+    /// The next event pushed to this connection, once no request is waiting
+    /// on a response.
+    pub async fn next_event(&mut self) -> Result<Event> {
+        self.stream.read_event().await
+    }
+
+    /// Send every request before reading any response. The daemon answers
+    /// them as they finish; they come back here in the order sent. Request
+    /// ids must be distinct.
+    pub async fn send_pipelined(&mut self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        let mut pending: HashMap<String, Option<Response>> = HashMap::with_capacity(requests.len());
+        for request in &requests {
+            if pending.insert(request.request_id.clone(), None).is_some() {
+                return Err(CoreError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Request id {:?} is used twice", request.request_id),
+                )));
+            }
+        }
+        for request in &requests {
+            self.stream.send_request(request).await?;
+        }
+
+        let mut unanswered = requests.len();
+        while unanswered > 0 {
+            let response = self.stream.read_response().await?;
+            match pending.get_mut(response.request_id()) {
+                Some(slot @ None) => {
+                    *slot = Some(response);
+                    unanswered -= 1;
+                }
+                _ => {
+                    return Err(CoreError::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Unexpected response to request {:?}", response.request_id()),
+                    )));
+                }
+            }
+        }
+        Ok(requests.iter().filter_map(|request| pending.remove(&request.request_id).flatten()).collect())
+    }
 }
 
 #[cfg(test)]
@@ -137,4 +247,73 @@ mod tests {
         #[cfg(windows)]
         assert_eq!(transport, TransportType::Pipe);
     }
+
+    #[tokio::test]
+    async fn test_relay_passes_both_ways() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            transport: TransportType::Tcp,
+            tcp_addr: listener.local_addr().unwrap().to_string(),
+            ..Config::default()
+        };
+        // A daemon answering each line in upper case
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut lines = BufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                writer.write_all(format!("{}\n", line.to_uppercase()).as_bytes()).await.unwrap();
+            }
+        });
+
+        let stream = TransportStream::connect(&config, "test").await.unwrap();
+        let (mut client, mut relayed) = tokio::io::duplex(1024);
+        let relay = tokio::spawn(async move { stream.relay(&mut relayed).await });
+        client.write_all(b"ping\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).await.unwrap();
+        assert_eq!(answer, "PING\n");
+        relay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_responses_matched_by_id() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            transport: TransportType::Tcp,
+            tcp_addr: listener.local_addr().unwrap().to_string(),
+            ..Config::default()
+        };
+        // A daemon answering three requests last to first
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut lines = BufReader::new(reader).lines();
+            let mut ids = Vec::new();
+            while ids.len() < 3 {
+                let line = lines.next_line().await.unwrap().unwrap();
+                ids.push(deserialize_message::<Request>(&line).unwrap().request_id);
+            }
+            for id in ids.into_iter().rev() {
+                let response = Response::success(id.clone(), serde_json::json!(id));
+                writer.write_all(format!("{}\n", serialize_message(&response).unwrap()).as_bytes()).await.unwrap();
+            }
+        });
+
+        let request = |id: &str| Request {
+            cmd: ct_protocol::Command::Diag,
+            request_id: id.to_string(),
+            protocol_version: ct_protocol::PROTOCOL_VERSION,
+            chunked: false,
+        };
+        let mut client = IpcClient::connect(&config, "test").await.unwrap();
+        let responses = client.send_pipelined(vec![request("a"), request("b"), request("c")]).await.unwrap();
+        let ids: Vec<&str> = responses.iter().map(Response::request_id).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+
+        assert!(client.send_pipelined(vec![request("a"), request("a")]).await.is_err());
+    }
 }
@@ -1,7 +1,15 @@
 use std::path::{Path, PathBuf};
+use crate::models::{DocExample, Marker};
 use crate::{CoreError, Result};
 use std::process::Command;
 
+/// Where to look for the workspace from: the current directory, for `ct`
+/// and `cargo ct` alike. `CARGO_MANIFEST_DIR` is not consulted, as cargo
+/// leaves it set for whatever a build script or test runs.
+pub fn workspace_search_start() -> Result<PathBuf> {
+    std::env::current_dir().map_err(CoreError::Io)
+}
+
 pub fn find_workspace_root(start_path: &Path) -> Result<PathBuf> {
     let mut current = start_path;
     
@@ -14,7 +22,7 @@ pub fn find_workspace_root(start_path: &Path) -> Result<PathBuf> {
                 .arg("1")
                 .current_dir(current)
                 .output()
-                .map_err(|e| CoreError::Io(e))?;
+                .map_err(CoreError::Io)?;
                 
             if output.status.success() {
                 let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)
@@ -37,10 +45,11 @@ pub fn find_workspace_root(start_path: &Path) -> Result<PathBuf> {
 }
 
 pub fn normalize_path(path: &str, current_crate: Option<&str>) -> String {
-    if path.starts_with("crate::") && current_crate.is_some() {
-        path.replace("crate::", &format!("{}::", current_crate.unwrap()))
-    } else {
-        path.to_string()
+    match current_crate {
+        Some(krate) if path.starts_with("crate::") => {
+            path.replace("crate::", &format!("{}::", krate))
+        }
+        _ => path.to_string(),
     }
 }
 
@@ -57,6 +66,166 @@ pub fn validate_visibility_filter(vis: Option<&str>) -> Result<Option<&str>> {
     }
 }
 
+// Fence attributes rustdoc understands; they mark a Rust block, not a language.
+const RUSTDOC_FENCE_ATTRS: &[&str] = &[
+    "rust",
+    "ignore",
+    "should_panic",
+    "no_run",
+    "compile_fail",
+    "test_harness",
+    "standalone_crate",
+];
+
+/// Extract fenced (``` or ~~~) code blocks from a doc comment. Hidden `# `
+/// lines in Rust blocks are dropped, as rustdoc does when rendering.
+pub fn extract_doc_examples(docs: &str) -> Vec<DocExample> {
+    let mut examples = Vec::new();
+    // (fence marker, lang, lines) of the block being read
+    let mut open: Option<(String, String, Vec<&str>)> = None;
+
+    for line in docs.lines() {
+        let trimmed = line.trim_start();
+
+        if let Some((fence, lang, lines)) = &mut open {
+            let closes = trimmed.trim_end().len() >= fence.len()
+                && trimmed.trim_end().chars().all(|c| fence.starts_with(c));
+            if closes {
+                examples.push(finish_example(std::mem::take(lang), lines));
+                open = None;
+            } else {
+                lines.push(line);
+            }
+            continue;
+        }
+
+        let marker = if trimmed.starts_with("```") {
+            '`'
+        } else if trimmed.starts_with("~~~") {
+            '~'
+        } else {
+            continue;
+        };
+        let fence_len = trimmed.chars().take_while(|&c| c == marker).count();
+        let info = trimmed[fence_len..].trim();
+        open = Some((marker.to_string().repeat(fence_len), fence_lang(info), Vec::new()));
+    }
+
+    // Like rustdoc, an unterminated fence runs to the end of the docs
+    if let Some((_, lang, lines)) = open {
+        examples.push(finish_example(lang, &lines));
+    }
+
+    examples
+}
+
+fn finish_example(lang: String, lines: &[&str]) -> DocExample {
+    let code = if lang == "rust" {
+        lines.iter().filter_map(|l| unhide_rust_line(l)).collect::<Vec<_>>().join("\n")
+    } else {
+        lines.join("\n")
+    };
+    DocExample { lang, code }
+}
+
+fn fence_lang(info: &str) -> String {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .find(|token| {
+            !RUSTDOC_FENCE_ATTRS.contains(token)
+                && !token.starts_with("edition")
+                && !token.starts_with("ignore-")
+        })
+        .unwrap_or("rust")
+        .to_string()
+}
+
+fn unhide_rust_line(line: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    if trimmed == "#" || trimmed.starts_with("# ") {
+        None
+    } else if let Some(rest) = trimmed.strip_prefix("##") {
+        Some(format!("{}#{}", &line[..line.len() - trimmed.len()], rest))
+    } else {
+        Some(line.to_string())
+    }
+}
+
+/// The markers in `lines`, which start at line `first_line`: `todo!` and
+/// `unimplemented!` with their message, and `TODO`/`FIXME` comments. One per
+/// line at most, the macro when a line has both.
+pub fn extract_markers(lines: &[&str], first_line: u32) -> Vec<Marker> {
+    lines
+        .iter()
+        .zip(first_line..)
+        .filter_map(|(line, number)| {
+            let (marker, message) = macro_marker(line).or_else(|| comment_marker(line))?;
+            Some(Marker { marker: marker.to_string(), message, line: number, introduced: None })
+        })
+        .collect()
+}
+
+fn macro_marker(line: &str) -> Option<(&'static str, String)> {
+    let (marker, at) = ["todo", "unimplemented"]
+        .into_iter()
+        .filter_map(|marker| Some((marker, line.find(&format!("{}!", marker))?)))
+        .min_by_key(|(_, at)| *at)?;
+    // Part of a longer name, such as `my_todo!`
+    if line[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let args = line[at + marker.len() + 1..].trim_start().strip_prefix('(')?;
+    Some((marker, macro_message(args)))
+}
+
+/// The string literal `args` starts with, or the arguments up to the
+/// closing parenthesis; the rest of the line when it does not close.
+fn macro_message(args: &str) -> String {
+    let args = args.trim_start();
+    if let Some(literal) = args.strip_prefix('"') {
+        let mut message = String::new();
+        let mut chars = literal.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => message.extend(chars.next()),
+                c => message.push(c),
+            }
+        }
+        return message;
+    }
+    let mut depth = 0;
+    let end = args
+        .char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return true,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            false
+        })
+        .map_or(args.len(), |(at, _)| at);
+    args[..end].trim().to_string()
+}
+
+fn comment_marker(line: &str) -> Option<(&'static str, String)> {
+    let trimmed = line.trim_start();
+    let comment = match line.find("//").or_else(|| line.find("/*")) {
+        Some(at) => &line[at..],
+        // Inside a block comment
+        None if trimmed.starts_with('*') => trimmed,
+        None => return None,
+    };
+    let (marker, at) = ["TODO", "FIXME"]
+        .into_iter()
+        .filter_map(|marker| Some((marker, comment.find(marker)?)))
+        .min_by_key(|(_, at)| *at)?;
+    let message = comment[at..].trim_end().trim_end_matches("*/").trim_end();
+    Some((marker, message.to_string()))
+}
+
 pub fn format_exit_code(code: u8) -> String {
     match code {
         0 => "ok".to_string(),
@@ -76,6 +245,9 @@ pub const EXIT_DAEMON_UNAVAILABLE: u8 = 4;
 pub const EXIT_INDEX_MISMATCH: u8 = 5;
 pub const EXIT_INTERNAL_ERROR: u8 = 6;
 pub const EXIT_DAEMON_ALREADY_RUNNING: u8 = 7;
+pub const EXIT_BUSY: u8 = 8;
+/// The user backed out of an interactive prompt, e.g. `ct pick`
+pub const EXIT_CANCELLED: u8 = 9;
 
 #[cfg(test)]
 mod tests {
@@ -109,4 +281,54 @@ mod tests {
         assert!(validate_visibility_filter(None).is_ok());
         assert!(validate_visibility_filter(Some("invalid")).is_err());
     }
+
+    #[test]
+    fn test_extract_doc_examples() {
+        let docs = "Builds a state.\n\n```\n# use demo::State;\nlet s = State::new();\n```\n\n\
+                    ```toml,ignore\nkey = 1\n```\n\n````no_run\n```nested\n##![allow(x)]\n````\n";
+        let examples = extract_doc_examples(docs);
+
+        assert_eq!(examples.len(), 3);
+        assert_eq!(examples[0], DocExample {
+            lang: "rust".to_string(),
+            code: "let s = State::new();".to_string(),
+        });
+        assert_eq!(examples[1].lang, "toml");
+        assert_eq!(examples[1].code, "key = 1");
+        assert_eq!(examples[2].code, "```nested\n#![allow(x)]");
+
+        assert!(extract_doc_examples("no code here").is_empty());
+    }
+
+    #[test]
+    fn test_extract_markers() {
+        let lines = [
+            "fn connect() {",
+            "    // TODO(alice): retry with backoff",
+            "    todo!(\"connect to {}\", \"the \\\"server\\\"\")",
+            "    if x { unimplemented!() }",
+            "    my_todo!(\"not a marker\");",
+            "    /* FIXME: leaks */ let y = todo!(format!(\"{}\", y));",
+            "     * FIXME: in a block comment",
+            "    let todo = \"TODO outside a comment\";",
+            "}",
+        ];
+        let markers: Vec<_> = extract_markers(&lines, 10)
+            .into_iter()
+            .map(|m| (m.line, m.marker, m.message))
+            .collect();
+        let expected = [
+            (11, "TODO", "TODO(alice): retry with backoff"),
+            (12, "todo", "connect to {}"),
+            (13, "unimplemented", ""),
+            (15, "todo", "format!(\"{}\", y)"),
+            (16, "FIXME", "FIXME: in a block comment"),
+        ];
+        assert_eq!(
+            markers,
+            expected.map(|(line, marker, message)| (line, marker.to_string(), message.to_string()))
+        );
+
+        assert_eq!(macro_message("\"say \\\"hi\\\"\")"), "say \"hi\"");
+    }
 }
\ No newline at end of file
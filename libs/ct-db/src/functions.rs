@@ -0,0 +1,140 @@
+//! SQL functions ct registers on every connection, for name and signature
+//! matching that SQLite has no built-in for.
+
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, Result};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Minimum `trigram_similarity` for a fuzzy name match (the pg_trgm default).
+pub const FUZZY_THRESHOLD: f64 = 0.3;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Register `regexp` (which backs `X REGEXP Y`), `path_glob`,
+/// `fuzzy_score`, `trigram_similarity` and `signature_shape`.
+pub(crate) fn register(conn: &Connection) -> Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    
+    // `text REGEXP pattern` calls regexp(pattern, text); the compiled pattern
+    // is cached for the rest of the statement
+    conn.create_scalar_function("regexp", 2, flags, |ctx| {
+        let re: Arc<Regex> = ctx.get_or_create_aux(0, |pattern| -> std::result::Result<_, BoxError> {
+            Ok(Regex::new(pattern.as_str()?)?)
+        })?;
+        let text = ctx.get::<String>(1)?;
+        Ok(re.is_match(&text))
+    })?;
+    
+    // path_glob(pattern, path), cached like regexp
+    conn.create_scalar_function("path_glob", 2, flags, |ctx| {
+        let glob: Arc<GlobMatcher> = ctx.get_or_create_aux(0, |pattern| -> std::result::Result<_, BoxError> {
+            Ok(path_glob(pattern.as_str()?)?)
+        })?;
+        let path = ctx.get::<String>(1)?;
+        Ok(glob.is_match(path.replace("::", "/")))
+    })?;
+    
+    // fuzzy_score(query, text): skim's score, higher is better, or NULL
+    // when `text` lacks some character of `query` in order
+    conn.create_scalar_function("fuzzy_score", 2, flags, |ctx| {
+        let matcher: Arc<SkimMatcherV2> = ctx.get_or_create_aux(0, |_| -> std::result::Result<_, BoxError> {
+            Ok(SkimMatcherV2::default())
+        })?;
+        let query = ctx.get::<String>(0)?;
+        let text = ctx.get::<String>(1)?;
+        Ok(matcher.fuzzy_match(&text, &query))
+    })?;
+    
+    conn.create_scalar_function("trigram_similarity", 2, flags, |ctx| {
+        let a = ctx.get::<String>(0)?;
+        let b = ctx.get::<String>(1)?;
+        Ok(trigram_similarity(&a, &b))
+    })?;
+    
+    conn.create_scalar_function("signature_shape", 1, flags, |ctx| {
+        let signature = ctx.get::<String>(0)?;
+        Ok(crate::signature::signature_shape(&signature))
+    })?;
+    
+    Ok(())
+}
+
+/// A matcher for symbol paths `pattern` describes: `*` and `?` stay within
+/// a `::` segment, `**` spans any number of segments, and `[...]` and
+/// `{a,b}` work as in shells. Paths must have their `::` written as `/`.
+pub fn path_glob(pattern: &str) -> std::result::Result<GlobMatcher, globset::Error> {
+    Ok(GlobBuilder::new(&pattern.replace("::", "/"))
+        .literal_separator(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Shared trigrams over all trigrams of the two strings, case-insensitive,
+/// from 0.0 (nothing in common) to 1.0 (same trigrams).
+pub fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// Trigrams of `s` padded with two leading spaces and one trailing space,
+/// so word starts weigh more than word ends.
+fn trigrams(s: &str) -> HashSet<[char; 3]> {
+    let padded: Vec<char> = "  "
+        .chars()
+        .chain(s.to_lowercase().chars())
+        .chain(" ".chars())
+        .collect();
+    padded.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sql_functions() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register(&conn)?;
+        
+        let matches: bool = conn.query_row("SELECT 'HashMap' REGEXP '^Hash(Map|Set)$'", [], |row| row.get(0))?;
+        assert!(matches);
+        assert!(conn.query_row::<bool, _, _>("SELECT 'x' REGEXP '('", [], |row| row.get(0)).is_err());
+        
+        let glob = |pattern: &str, path: &str| -> Result<bool> {
+            conn.query_row("SELECT path_glob(?, ?)", [pattern, path], |row| row.get(0))
+        };
+        assert!(glob("app::api::*", "app::api::Handler")?);
+        assert!(!glob("app::api::*", "app::api::v1::Handler")?);
+        assert!(glob("app::**::Handler", "app::api::v1::Handler")?);
+        assert!(glob("app::**::Handler", "app::Handler")?);
+        assert!(glob("app::{api,net}::[A-Z]*", "app::net::Client")?);
+        assert!(!glob("app::api::handle?", "app::api::handler_v1")?);
+        assert!(glob("app::[", "app::x").is_err());
+        
+        let score = |query: &str, text: &str| -> Result<Option<i64>> {
+            conn.query_row("SELECT fuzzy_score(?, ?)", [query, text], |row| row.get(0))
+        };
+        assert!(score("apihand", "app::api::Handler")?.is_some());
+        assert_eq!(score("hnadler", "app::api::Handler")?, None);
+        // Matches at word starts beat scattered ones
+        assert!(score("hand", "app::api::Handler")? > score("hand", "app::shared::Command")?);
+        
+        assert_eq!(trigram_similarity("State", "state"), 1.0);
+        assert!(trigram_similarity("HashMap", "HahsMap") > FUZZY_THRESHOLD);
+        assert!(trigram_similarity("HashMap", "Vec") < FUZZY_THRESHOLD);
+        
+        let shape: Option<String> = conn.query_row("SELECT signature_shape('fn f(x: u8) -> bool')", [], |row| row.get(0))?;
+        assert_eq!(shape.as_deref(), Some("fn(u8) -> bool"));
+        
+        Ok(())
+    }
+}
@@ -1,33 +1,174 @@
+pub mod functions;
 pub mod migrations;
+pub mod pool;
 pub mod queries;
+pub mod signature;
 
 use ct_core::models::*;
 use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
 use thiserror::Error;
 use tracing::info;
 
 #[derive(Error, Debug)]
 pub enum DbError {
     #[error("SQLite error: {0}")]
-    Sqlite(#[from] rusqlite::Error),
+    Sqlite(rusqlite::Error),
+    
+    /// Another connection held a lock for longer than `BUSY_TIMEOUT` and
+    /// the retries in `retry_busy`.
+    #[error("Database is locked by another process: {0}")]
+    Busy(String),
     
     #[error("Migration error: {0}")]
     Migration(String),
     
     #[error("Schema mismatch: expected {expected}, found {found}")]
     SchemaMismatch { expected: String, found: String },
+    
+    #[error("Invalid index data: {0}")]
+    InvalidData(String),
+    
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
 }
 
 pub type Result<T> = std::result::Result<T, DbError>;
 
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        if is_busy(&e) {
+            DbError::Busy(e.to_string())
+        } else {
+            DbError::Sqlite(e)
+        }
+    }
+}
+
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Bytes SQLite has allocated across every connection in this process.
+pub fn sqlite_memory_used() -> usize {
+    // SAFETY: reads SQLite's global allocation counter, which it guards itself
+    let used = unsafe { rusqlite::ffi::sqlite3_memory_used() };
+    used.max(0) as usize
+}
+
+/// Ask SQLite to keep its allocations under `bytes` by recycling page cache
+/// sooner; 0 removes the limit. Applies to every connection in this process.
+pub fn set_soft_heap_limit(bytes: usize) {
+    // SAFETY: sets a global threshold SQLite reads under its own mutex
+    unsafe { rusqlite::ffi::sqlite3_soft_heap_limit64(bytes as i64) };
+}
+
+/// How long a connection waits for another one's lock before giving up.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const BUSY_RETRIES: u32 = 4;
+const BUSY_BACKOFF: Duration = Duration::from_millis(25);
+
+/// Run `op`, retrying with exponential backoff while it fails with
+/// `DbError::Busy`. The busy timeout covers ordinary lock waits; this covers
+/// the ones SQLite fails at once instead of waiting, such as a read
+/// transaction that can no longer be upgraded to a write.
+pub fn retry_busy<T>(mut op: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut delay = BUSY_BACKOFF;
+    for _ in 0..BUSY_RETRIES {
+        match op() {
+            Err(DbError::Busy(e)) => {
+                tracing::debug!("Database busy ({}), retrying in {:?}", e, delay);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    op()
+}
+
+const INSERT_SYMBOL: &str = "INSERT INTO symbols (
+    symbol_id, crate_id, file_id, path, name, kind, visibility,
+    signature, docs, status, span_start, span_end, def_hash, signature_shape
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+fn execute_insert_symbol(stmt: &mut rusqlite::CachedStatement<'_>, symbol: &Symbol) -> Result<()> {
+    stmt.execute(params![
+        symbol.symbol_id.as_bytes(),
+        symbol.crate_id,
+        symbol.file_id,
+        &symbol.path,
+        &symbol.name,
+        symbol.kind.as_str(),
+        symbol.visibility.as_str(),
+        &symbol.signature,
+        &symbol.docs,
+        symbol.status.as_str(),
+        symbol.span_start,
+        symbol.span_end,
+        &symbol.def_hash,
+        symbol_shape(symbol),
+    ])?;
+    Ok(())
+}
+
+/// The searchable signature shape of function-like symbols.
+fn symbol_shape(symbol: &Symbol) -> Option<String> {
+    match symbol.kind {
+        SymbolKind::Fn | SymbolKind::Method | SymbolKind::ForeignFn => signature::signature_shape(&symbol.signature),
+        _ => None,
+    }
+}
+
+/// What opening a database did to its schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub applied: Vec<&'static migrations::Migration>,
+}
+
+/// What `Database::maintain` reclaimed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Free pages before the vacuum ran.
+    pub freed_pages: i64,
+    /// The file predated incremental vacuum and was rebuilt with a full VACUUM.
+    pub full_vacuum: bool,
+    /// WAL frames copied back into the database by the checkpoint.
+    pub checkpointed_frames: i64,
+    /// Another connection held the WAL, so it could not be truncated.
+    pub wal_busy: bool,
+}
+
 pub struct Database {
     pub(crate) conn: Connection,
 }
 
 impl Database {
     pub fn open(path: &Path) -> Result<Self> {
+        Self::open_migrated(path).map(|(db, _)| db)
+    }
+
+    /// Open `path`, bringing its schema up to date, and report which
+    /// migrations ran.
+    pub fn open_migrated(path: &Path) -> Result<(Self, MigrationReport)> {
+        retry_busy(|| Self::try_open_migrated(path))
+    }
+
+    fn try_open_migrated(path: &Path) -> Result<(Self, MigrationReport)> {
         let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        
+        // Must precede anything that writes the header, so it only takes
+        // effect on a new file; `maintain` converts older ones
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
         
         // Enable WAL mode and set pragmas
         conn.pragma_update(None, "journal_mode", "WAL")?;
@@ -36,41 +177,136 @@ impl Database {
         conn.pragma_update(None, "mmap_size", 30000000)?;
         conn.pragma_update(None, "page_size", 4096)?;
         conn.pragma_update(None, "foreign_keys", "ON")?;
+        functions::register(&conn)?;
         
         let mut db = Self { conn };
-        db.ensure_schema()?;
-        Ok(db)
+        let report = db.migrate(migrations::MIGRATIONS)?;
+        Ok((db, report))
+    }
+
+    /// Open a database already brought up to date by `open`, leaving its
+    /// schema alone and waiting at most `busy_timeout` for another
+    /// connection's lock. For writes that would rather fail than wait out a
+    /// reindex.
+    pub fn open_existing(path: &Path, busy_timeout: Duration) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(busy_timeout)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        functions::register(&conn)?;
+        Ok(Self { conn })
     }
 
     pub fn open_temp(path: &Path) -> Result<Self> {
         let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
         
         // Same pragmas for temp DB
         conn.pragma_update(None, "journal_mode", "WAL")?;
         conn.pragma_update(None, "synchronous", "NORMAL")?;
         conn.pragma_update(None, "temp_store", "MEMORY")?;
         conn.pragma_update(None, "foreign_keys", "ON")?;
+        functions::register(&conn)?;
         
         let mut db = Self { conn };
-        db.ensure_schema()?;
+        db.migrate(migrations::MIGRATIONS)?;
         Ok(db)
     }
 
-    fn ensure_schema(&mut self) -> Result<()> {
-        let version = self.get_schema_version()?;
-        
-        if version == 0 {
-            info!("Creating initial schema");
-            self.apply_migration(&migrations::V1_SCHEMA)?;
-            self.set_schema_version(1)?;
-        } else if version < migrations::CURRENT_VERSION {
+    /// Apply every step of `steps` newer than the stored schema version, in
+    /// order. A failing step is rolled back and leaves the database at the
+    /// previous version, so a later open can retry it.
+    fn migrate(&mut self, steps: &'static [migrations::Migration]) -> Result<MigrationReport> {
+        let from_version = self.get_schema_version()?;
+        let latest = steps.last().map_or(0, |m| m.version);
+        if from_version > latest {
             return Err(DbError::SchemaMismatch {
-                expected: migrations::CURRENT_VERSION.to_string(),
-                found: version.to_string(),
+                expected: latest.to_string(),
+                found: from_version.to_string(),
             });
         }
         
-        Ok(())
+        let mut applied = Vec::new();
+        for step in steps.iter().filter(|m| m.version > from_version) {
+            info!("Migrating schema to v{}: {}", step.version, step.description);
+            // Take the write lock up front so a concurrent opener waits for
+            // it rather than failing to upgrade a read lock
+            let tx = self.conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            let result = tx
+                .execute_batch(step.sql)
+                .and_then(|_| {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO meta (key, val) VALUES ('schema_version', ?)",
+                        params![step.version.to_string()],
+                    )
+                })
+                .and_then(|_| tx.commit());
+            if let Err(e) = result {
+                if is_busy(&e) {
+                    return Err(DbError::Busy(e.to_string()));
+                }
+                return Err(DbError::Migration(format!("v{} ({}): {}", step.version, step.description, e)));
+            }
+            applied.push(step);
+        }
+        
+        Ok(MigrationReport {
+            from_version,
+            to_version: applied.last().map_or(from_version, |m| m.version),
+            applied,
+        })
+    }
+
+    /// Refresh planner statistics, return free pages to the filesystem and
+    /// truncate the WAL, which otherwise grows for as long as a daemon keeps
+    /// the database open.
+    pub fn maintain(&self) -> Result<MaintenanceReport> {
+        self.conn.execute_batch("ANALYZE")?;
+        
+        let freed_pages: i64 = self.conn.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+        let auto_vacuum: i64 = self.conn.pragma_query_value(None, "auto_vacuum", |row| row.get(0))?;
+        // 2 = INCREMENTAL; anything else needs one full rebuild to switch modes
+        let full_vacuum = auto_vacuum != 2;
+        if full_vacuum {
+            self.conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+            self.conn.execute_batch("VACUUM")?;
+        } else {
+            // Frees one page per step, so drain it rather than execute once
+            let mut stmt = self.conn.prepare("PRAGMA incremental_vacuum")?;
+            let mut rows = stmt.query([])?;
+            while rows.next()?.is_some() {}
+        }
+        
+        let (checkpointed_frames, wal_busy) = self.checkpoint_wal()?;
+        
+        Ok(MaintenanceReport {
+            freed_pages,
+            full_vacuum,
+            checkpointed_frames,
+            wal_busy,
+        })
+    }
+
+    /// Copy the WAL into the database file and truncate it. Returns the
+    /// frames checkpointed and whether another connection kept the WAL from
+    /// being fully copied.
+    pub fn checkpoint_wal(&self) -> Result<(i64, bool)> {
+        let (busy, frames) = self.conn.query_row(
+            "PRAGMA wal_checkpoint(TRUNCATE)",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(2)?)),
+        )?;
+        
+        Ok((frames, busy != 0))
+    }
+
+    /// Compare the indexed files against their current content under
+    /// `workspace_root`; see `queries::verify_files`.
+    pub fn verify(&self, workspace_root: &Path) -> Result<VerifyReport> {
+        queries::verify_files(&self.conn, workspace_root)
+    }
+
+    pub fn schema_version(&self) -> Result<u32> {
+        self.get_schema_version()
     }
 
     fn get_schema_version(&self) -> Result<u32> {
@@ -97,90 +333,407 @@ impl Database {
         Ok(version.and_then(|v| v.parse().ok()).unwrap_or(0))
     }
 
-    fn set_schema_version(&self, version: u32) -> Result<()> {
+    pub fn insert_crate(&self, name: &str, version: Option<&str>, fingerprint: &str) -> Result<i64> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO meta (key, val) VALUES ('schema_version', ?)",
-            params![version.to_string()],
+            "INSERT INTO crates (name, version, fingerprint) VALUES (?, ?, ?)",
+            params![name, version, fingerprint],
         )?;
-        Ok(())
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Remove a crate and everything indexed under it (symbols, files, impls,
+    /// references, calls, history, examples, markers, inputs). Returns the number of crate rows deleted.
+    pub fn delete_crate(&self, name: &str) -> Result<usize> {
+        self.clear_crate_rows("SELECT id FROM crates WHERE name = ?1", &name)?;
+        let deleted = self.conn.execute("DELETE FROM crates WHERE name = ?1", params![name])?;
+        self.prune_snippets()?;
+        Ok(deleted)
     }
 
-    fn apply_migration(&self, migration: &str) -> Result<()> {
-        self.conn.execute_batch(migration)?;
+    /// Swap everything indexed under `crate_id` for `files`, `symbols` and
+    /// `impls` inside one savepoint, so a crash or failed insert leaves the
+    /// previous index intact rather than a mix of old and new rows.
+    /// `File::id` values are placeholders that `symbols` and `impls` refer
+    /// to; they are remapped to the ids of the inserted rows.
+    pub fn replace_crate_index(
+        &mut self,
+        crate_id: i64,
+        files: &[File],
+        symbols: &[Symbol],
+        impls: &[ImplBlock],
+    ) -> Result<()> {
+        const SAVEPOINT: &str = "replace_crate_index";
+        self.savepoint(SAVEPOINT)?;
+        match self.write_crate_index(crate_id, files, symbols, impls) {
+            Ok(()) => self.release_savepoint(SAVEPOINT),
+            Err(e) => {
+                self.rollback_to_savepoint(SAVEPOINT)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn write_crate_index(
+        &self,
+        crate_id: i64,
+        files: &[File],
+        symbols: &[Symbol],
+        impls: &[ImplBlock],
+    ) -> Result<()> {
+        self.clear_crate_rows("SELECT ?1", &crate_id)?;
+        
+        let mut file_ids = HashMap::new();
+        for file in files {
+            file_ids.insert(file.id, self.insert_file(crate_id, &file.path, &file.digest)?);
+        }
+        let file_id = |placeholder: i64| {
+            file_ids.get(&placeholder).copied().ok_or_else(|| {
+                DbError::InvalidData(format!("no file with placeholder id {}", placeholder))
+            })
+        };
+        
+        let symbols = symbols
+            .iter()
+            .map(|symbol| {
+                Ok(Symbol {
+                    crate_id,
+                    file_id: file_id(symbol.file_id)?,
+                    ..symbol.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.insert_symbols_batch(&symbols)?;
+        
+        for imp in impls {
+            self.insert_impl(&ImplBlock {
+                file_id: file_id(imp.file_id)?,
+                ..imp.clone()
+            })?;
+        }
+        self.prune_snippets()
+    }
+
+    /// Delete the rows of every crate whose id `crate_ids` selects, keeping
+    /// the `crates` rows themselves. `key` binds `?1` in `crate_ids`.
+    fn clear_crate_rows(&self, crate_ids: &str, key: &dyn rusqlite::ToSql) -> Result<()> {
+        let file_ids = format!("SELECT id FROM files WHERE crate_id IN ({})", crate_ids);
+        let symbol_ids = format!("SELECT id FROM symbols WHERE crate_id IN ({})", crate_ids);
+        
+        self.conn.execute(
+            &format!(
+                "DELETE FROM symbol_references WHERE symbol_id IN ({}) OR file_id IN ({})",
+                symbol_ids, file_ids
+            ),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM calls WHERE file_id IN ({})", file_ids),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!(
+                "DELETE FROM symbol_history WHERE symbol_id IN
+                 (SELECT symbol_id FROM symbols WHERE crate_id IN ({}))",
+                crate_ids
+            ),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!(
+                "DELETE FROM examples WHERE symbol_id IN
+                 (SELECT symbol_id FROM symbols WHERE crate_id IN ({}))",
+                crate_ids
+            ),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!(
+                "DELETE FROM markers WHERE file_id IN ({})",
+                file_ids
+            ),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!(
+                "DELETE FROM trait_impls WHERE impl_id IN (SELECT id FROM impls WHERE file_id IN ({}))",
+                file_ids
+            ),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM impls WHERE file_id IN ({})", file_ids),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM symbols WHERE crate_id IN ({})", crate_ids),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM files WHERE crate_id IN ({})", crate_ids),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM crate_inputs WHERE crate_id IN ({})", crate_ids),
+            [key],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM crate_deps WHERE crate_id IN ({})", crate_ids),
+            [key],
+        )?;
         Ok(())
     }
 
-    pub fn insert_crate(&self, name: &str, version: Option<&str>, fingerprint: &str) -> Result<i64> {
+    /// Remove a source file and the symbols, impls, trait impls, references,
+    /// calls, history, examples and markers recorded from it. Returns the number of file rows deleted.
+    pub fn delete_file(&self, path: &str) -> Result<usize> {
+        let file_ids = "SELECT id FROM files WHERE path = ?1";
+        let symbol_ids = format!("SELECT id FROM symbols WHERE file_id IN ({})", file_ids);
+        let symbol_keys = format!("SELECT symbol_id FROM symbols WHERE file_id IN ({})", file_ids);
+        
         self.conn.execute(
-            "INSERT INTO crates (name, version, fingerprint) VALUES (?, ?, ?)",
-            params![name, version, fingerprint],
+            &format!(
+                "DELETE FROM symbol_references WHERE symbol_id IN ({}) OR file_id IN ({})",
+                symbol_ids, file_ids
+            ),
+            params![path],
         )?;
-        Ok(self.conn.last_insert_rowid())
+        self.conn.execute(
+            &format!("DELETE FROM calls WHERE file_id IN ({})", file_ids),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM symbol_history WHERE symbol_id IN ({})", symbol_keys),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM examples WHERE symbol_id IN ({})", symbol_keys),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM markers WHERE file_id IN ({})", file_ids),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!(
+                "DELETE FROM trait_impls WHERE impl_id IN (SELECT id FROM impls WHERE file_id IN ({}))",
+                file_ids
+            ),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM impls WHERE file_id IN ({})", file_ids),
+            params![path],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM symbols WHERE file_id IN ({})", file_ids),
+            params![path],
+        )?;
+        let deleted = self.conn.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+        self.prune_snippets()?;
+        Ok(deleted)
     }
 
-    pub fn insert_file(&self, crate_id: i64, path: &str, digest: &str) -> Result<i64> {
+    /// Drop cached snippets of file contents no file is indexed at anymore.
+    fn prune_snippets(&self) -> Result<()> {
         self.conn.execute(
-            "INSERT INTO files (crate_id, path, digest) VALUES (?, ?, ?)",
-            params![crate_id, path, digest],
+            "DELETE FROM source_snippets WHERE file_digest NOT IN (SELECT digest FROM files)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_file(&self, crate_id: i64, path: &str, digest: &str) -> Result<i64> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO files (crate_id, path, digest) VALUES (?, ?, ?)"
         )?;
+        stmt.execute(params![crate_id, path, digest])?;
         Ok(self.conn.last_insert_rowid())
     }
 
-    pub fn insert_symbol(&self, symbol: &Symbol) -> Result<()> {
+    /// Record a non-Rust file the crate's index depends on, e.g. an
+    /// `include_str!` target.
+    pub fn insert_input(&self, crate_id: i64, path: &str) -> Result<()> {
         self.conn.execute(
-            "INSERT INTO symbols (
-                symbol_id, crate_id, file_id, path, name, kind, visibility,
-                signature, docs, status, span_start, span_end, def_hash
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-            params![
-                &symbol.symbol_id.as_bytes()[..],
-                symbol.crate_id,
-                symbol.file_id,
-                &symbol.path,
-                &symbol.name,
-                symbol.kind.as_str(),
-                symbol.visibility.as_str(),
-                &symbol.signature,
-                &symbol.docs,
-                symbol.status.as_str(),
-                symbol.span_start,
-                symbol.span_end,
-                &symbol.def_hash,
-            ],
+            "INSERT INTO crate_inputs (crate_id, path) VALUES (?, ?)",
+            params![crate_id, path],
         )?;
         Ok(())
     }
 
-    pub fn insert_impl(&self, imp: &ImplBlock) -> Result<()> {
+    /// Record a dependency from the crate's manifest; a repeat of the same
+    /// name and kind (e.g. per-target tables) replaces the earlier one.
+    pub fn insert_dependency(&self, crate_id: i64, dependency: &CrateDependency) -> Result<()> {
         self.conn.execute(
+            "INSERT OR REPLACE INTO crate_deps (crate_id, dep_name, kind, req, optional) VALUES (?, ?, ?, ?, ?)",
+            params![crate_id, &dependency.name, &dependency.kind, &dependency.req, dependency.optional],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_symbol(&self, symbol: &Symbol) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(INSERT_SYMBOL)?;
+        execute_insert_symbol(&mut stmt, symbol)
+    }
+
+    /// Insert many symbols through one cached statement. Runs inside the
+    /// caller's transaction if one is open, otherwise in its own.
+    pub fn insert_symbols_batch(&self, symbols: &[Symbol]) -> Result<()> {
+        let tx = if self.conn.is_autocommit() {
+            Some(self.conn.unchecked_transaction()?)
+        } else {
+            None
+        };
+        {
+            let mut stmt = self.conn.prepare_cached(INSERT_SYMBOL)?;
+            for symbol in symbols {
+                execute_insert_symbol(&mut stmt, symbol)?;
+            }
+        }
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn insert_impl(&self, imp: &ImplBlock) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
             "INSERT INTO impls (for_path, trait_path, file_id, line_start, line_end)
-             VALUES (?, ?, ?, ?, ?)",
-            params![
-                &imp.for_path,
-                &imp.trait_path,
-                imp.file_id,
-                imp.line_start,
-                imp.line_end,
-            ],
+             VALUES (?, ?, ?, ?, ?)"
         )?;
+        stmt.execute(params![
+            &imp.for_path,
+            &imp.trait_path,
+            imp.file_id,
+            imp.line_start,
+            imp.line_end,
+        ])?;
+        
+        if let Some(trait_path) = &imp.trait_path {
+            let impl_id = self.conn.last_insert_rowid();
+            let mut stmt = self.conn.prepare_cached(
+                "INSERT OR IGNORE INTO trait_impls (trait_path, for_path, impl_id) VALUES (?, ?, ?)"
+            )?;
+            stmt.execute(params![trait_path, &imp.for_path, impl_id])?;
+        }
         Ok(())
     }
 
     pub fn insert_reference(&self, reference: &Reference) -> Result<()> {
-        self.conn.execute(
-            "INSERT INTO symbol_references (symbol_id, target_path, file_id, span_start, span_end)
-             VALUES (?, ?, ?, ?, ?)",
-            params![
-                reference.symbol_id,
-                &reference.target_path,
-                reference.file_id,
-                reference.span_start,
-                reference.span_end,
-            ],
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO symbol_references (symbol_id, target_path, file_id, span_start, span_end, span_col)
+             VALUES (?, ?, ?, ?, ?, ?)"
+        )?;
+        stmt.execute(params![
+            reference.symbol_id,
+            &reference.target_path,
+            reference.file_id,
+            reference.span_start,
+            reference.span_end,
+            reference.span_col,
+        ])?;
+        Ok(())
+    }
+
+    pub fn insert_call(&self, call: &Call) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO calls (caller_symbol_id, callee_path, file_id, span_start, span_end)
+             VALUES (?, ?, ?, ?, ?)"
+        )?;
+        stmt.execute(params![
+            call.caller_symbol_id.as_bytes(),
+            &call.callee_path,
+            call.file_id,
+            call.span_start,
+            call.span_end,
+        ])?;
+        Ok(())
+    }
+
+    /// Record a call made by the symbol whose row id is `symbol_rowid`.
+    pub fn insert_symbol_call(
+        &self,
+        symbol_rowid: i64,
+        callee_path: &str,
+        file_id: i64,
+        span_start: u32,
+        span_end: u32,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO calls (caller_symbol_id, callee_path, file_id, span_start, span_end)
+             SELECT symbol_id, ?, ?, ?, ? FROM symbols WHERE id = ?"
+        )?;
+        stmt.execute(params![callee_path, file_id, span_start, span_end, symbol_rowid])?;
+        Ok(())
+    }
+
+    pub fn insert_symbol_history(&self, symbol_id: &str, history: &SymbolHistory) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO symbol_history (symbol_id, last_commit, last_modified, churn)
+             VALUES (?, ?, ?, ?)"
+        )?;
+        stmt.execute(params![
+            symbol_id.as_bytes(),
+            &history.last_commit,
+            history.last_modified,
+            history.churn,
+        ])?;
+        Ok(())
+    }
+
+    /// Store the `idx`-th code block of a symbol's docs.
+    pub fn insert_example(&self, symbol_id: &str, idx: usize, example: &DocExample) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO examples (symbol_id, idx, lang, code) VALUES (?, ?, ?, ?)"
+        )?;
+        stmt.execute(params![symbol_id.as_bytes(), idx, &example.lang, &example.code])?;
+        Ok(())
+    }
+
+    /// Store a marker found in the file `file_id`.
+    pub fn insert_marker(&self, file_id: i64, marker: &Marker) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO markers (file_id, line, marker, message, introduced)
+             VALUES (?, ?, ?, ?, ?)"
+        )?;
+        stmt.execute(params![
+            file_id,
+            marker.line,
+            &marker.marker,
+            &marker.message,
+            marker.introduced,
+        ])?;
+        Ok(())
+    }
+
+    /// Cache the text of lines `span_start..=span_end` of the file content
+    /// with digest `file_digest`.
+    pub fn insert_snippet(&self, file_digest: &str, span_start: u32, span_end: u32, text: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO source_snippets (file_digest, span_start, span_end, text)
+             VALUES (?, ?, ?, ?)",
         )?;
+        stmt.execute(params![file_digest, span_start, span_end, text])?;
         Ok(())
     }
 
+    /// Tag every symbol at `path`, replacing the note of an existing tag.
+    /// Returns how many symbols were tagged; 0 if there is no such path.
+    pub fn add_tag(&self, path: &str, tag: &str, note: Option<&str>) -> Result<usize> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO tags (path, kind, tag, note)
+             SELECT DISTINCT path, kind, ?, ? FROM symbols WHERE path = ?",
+        )?;
+        Ok(stmt.execute(params![tag, note, path])?)
+    }
+
+    /// Remove `tag` from the symbols at `path`, returning how many had it.
+    pub fn remove_tag(&self, path: &str, tag: &str) -> Result<usize> {
+        let mut stmt = self.conn.prepare_cached(
+            "DELETE FROM tags WHERE tag = ? AND path = ?",
+        )?;
+        Ok(stmt.execute(params![tag, path])?)
+    }
+
     pub fn get_meta(&self, key: &str) -> Result<Option<String>> {
         let val: Option<String> = self.conn
             .query_row(
@@ -200,6 +753,66 @@ impl Database {
         Ok(())
     }
 
+    /// Advance `index_epoch` and return the new value. Call it in the same
+    /// transaction as the index writes it stands for, so readers never see
+    /// new rows under the old epoch.
+    pub fn bump_index_epoch(&self) -> Result<u64> {
+        let epoch = queries::get_index_epoch(&self.conn)? + 1;
+        self.set_meta("index_epoch", &epoch.to_string())?;
+        Ok(epoch)
+    }
+
+    /// Start a new `epoch_baseline`, empty, and remember which epoch it is
+    /// saved from. Call it at the start of an index transaction so it rolls
+    /// back with it, then [`Database::save_crate_baseline`] or
+    /// [`Database::save_file_baseline`] before replacing rows.
+    pub fn save_epoch_baseline(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM epoch_baseline", [])?;
+        self.conn.execute("DELETE FROM epoch_scope", [])?;
+        let epoch = queries::get_index_epoch(&self.conn)?;
+        self.set_meta("baseline_epoch", &epoch.to_string())
+    }
+
+    /// Copy the symbols of crate `name` into `epoch_baseline` before they
+    /// are replaced.
+    pub fn save_crate_baseline(&self, name: &str) -> Result<()> {
+        self.save_baseline(name, None)
+    }
+
+    /// Copy the symbols of the file at `path` into `epoch_baseline` before
+    /// they are replaced.
+    pub fn save_file_baseline(&self, path: &str) -> Result<()> {
+        let crate_name: Option<String> = self.conn
+            .query_row(
+                "SELECT c.name FROM files f JOIN crates c ON c.id = f.crate_id WHERE f.path = ?1 LIMIT 1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match crate_name {
+            Some(crate_name) => self.save_baseline(&crate_name, Some(path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record `crate_name`, or just its `file`, in the baseline's scope and
+    /// copy those of its symbols not saved yet.
+    fn save_baseline(&self, crate_name: &str, file: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO epoch_scope (crate_name, file) VALUES (?1, ?2)",
+            params![crate_name, file],
+        )?;
+        self.conn.execute(
+            "INSERT INTO epoch_baseline (path, kind, visibility, signature, def_hash)
+             SELECT s.path, s.kind, s.visibility, s.signature, s.def_hash FROM symbols s
+             WHERE s.crate_id IN (SELECT id FROM crates WHERE name = ?1)
+               AND (?2 IS NULL OR s.file_id IN (SELECT id FROM files WHERE path = ?2))
+               AND NOT EXISTS (SELECT 1 FROM epoch_baseline b WHERE b.path = s.path AND b.kind = s.kind)",
+            params![crate_name, file],
+        )?;
+        Ok(())
+    }
+
     pub fn get_symbol_count(&self) -> Result<usize> {
         let count: usize = self.conn
             .query_row("SELECT COUNT(*) FROM symbols", [], |row| row.get(0))?;
@@ -212,6 +825,15 @@ impl Database {
         Ok(count)
     }
 
+    /// The fingerprint each indexed crate was stored with, by crate name.
+    pub fn crate_fingerprints(&self) -> Result<HashMap<String, String>> {
+        let mut stmt = self.conn.prepare("SELECT name, fingerprint FROM crates")?;
+        let fingerprints = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(fingerprints)
+    }
+
     pub fn get_file_count(&self) -> Result<usize> {
         let count: usize = self.conn
             .query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
@@ -219,8 +841,10 @@ impl Database {
     }
 
     pub fn begin_transaction(&mut self) -> Result<()> {
-        self.conn.execute("BEGIN IMMEDIATE", [])?;
-        Ok(())
+        retry_busy(|| {
+            self.conn.execute("BEGIN IMMEDIATE", [])?;
+            Ok(())
+        })
     }
 
     pub fn commit_transaction(&mut self) -> Result<()> {
@@ -232,6 +856,25 @@ impl Database {
         self.conn.execute("ROLLBACK", [])?;
         Ok(())
     }
+
+    /// Open a nested savepoint inside the current transaction.
+    pub fn savepoint(&mut self, name: &str) -> Result<()> {
+        self.conn.execute(&format!("SAVEPOINT {}", name), [])?;
+        Ok(())
+    }
+
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        self.conn.execute(&format!("RELEASE {}", name), [])?;
+        Ok(())
+    }
+
+    /// Undo everything since `savepoint(name)` and discard the savepoint,
+    /// leaving the enclosing transaction open.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<()> {
+        self.conn.execute(&format!("ROLLBACK TO {}", name), [])?;
+        self.conn.execute(&format!("RELEASE {}", name), [])?;
+        Ok(())
+    }
     
     pub fn conn(&self) -> &Connection {
         &self.conn
@@ -241,19 +884,193 @@ impl Database {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ct_protocol::StatusGroup;
+    use queries::{MatchMode, SymbolField};
     use tempfile::NamedTempFile;
 
+    /// A public, implemented symbol at `path` in the first crate a test
+    /// inserts, its id the path; tests override the fields they care about.
+    fn test_symbol(path: &str, kind: SymbolKind, file_id: i64) -> Symbol {
+        Symbol {
+            symbol_id: path.to_string(),
+            crate_id: 1,
+            file_id,
+            path: path.to_string(),
+            name: path.rsplit("::").next().unwrap_or(path).to_string(),
+            kind,
+            visibility: Visibility::Public,
+            signature: String::new(),
+            docs: None,
+            status: ImplementationStatus::Implemented,
+            span_start: 1,
+            span_end: 1,
+            def_hash: "hash".to_string(),
+        }
+    }
+
     #[test]
     fn test_database_creation() -> Result<()> {
         let temp = NamedTempFile::new().unwrap();
         let db = Database::open(temp.path())?;
         
-        assert_eq!(db.get_schema_version()?, 1);
+        assert_eq!(db.get_schema_version()?, migrations::CURRENT_VERSION);
         assert_eq!(db.get_symbol_count()?, 0);
         
         Ok(())
     }
 
+    #[test]
+    fn test_upgrade_from_older_schema() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(temp.path())?;
+            for step in &migrations::MIGRATIONS[..3] {
+                conn.execute_batch(step.sql)?;
+            }
+            conn.execute("INSERT INTO meta (key, val) VALUES ('schema_version', '3')", [])?;
+            conn.execute("INSERT INTO crates (id, name, fingerprint) VALUES (1, 'old', 'fp')", [])?;
+            conn.execute("INSERT INTO files (id, crate_id, path, digest) VALUES (1, 1, 'src/lib.rs', 'd')", [])?;
+            conn.execute(
+                "INSERT INTO symbols (symbol_id, crate_id, file_id, path, name, kind, visibility,
+                 signature, docs, status, span_start, span_end, def_hash)
+                 VALUES (x'01', 1, 1, 'old::Pool', 'Pool', 'struct', 'public', 'struct Pool',
+                 'Connection pool with backpressure', 'implemented', 1, 2, 'h'),
+                 (x'02', 1, 1, 'old::parse', 'parse', 'fn', 'public', 'pub fn parse(s: &str) -> Option<Pool>',
+                 NULL, 'implemented', 3, 4, 'h')",
+                [],
+            )?;
+        }
+        
+        let (db, report) = Database::open_migrated(temp.path())?;
+        assert_eq!(report.from_version, 3);
+        assert_eq!(report.to_version, migrations::CURRENT_VERSION);
+        assert_eq!(
+            report.applied.iter().map(|m| m.version).collect::<Vec<_>>(),
+            (4..=migrations::CURRENT_VERSION).collect::<Vec<_>>()
+        );
+        // Rows from before the upgrade are kept and backfilled into new indexes
+        assert_eq!(db.get_symbol_count()?, 2);
+        assert_eq!(queries::search_fulltext(db.conn(), "backpressure", None, None, 0, 10)?.len(), 1);
+        let found = queries::find_by_signature(db.conn(), "fn(&str) -> Option<_>", None, None, None, None, 10)?;
+        assert_eq!(found.items.len(), 1);
+        // Fields added since are empty on old rows, so every crate is indexed again
+        assert_eq!(db.crate_fingerprints()?.get("old").map(String::as_str), Some(""));
+        
+        let (_, report) = Database::open_migrated(temp.path())?;
+        assert!(report.applied.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_keys_tags_by_path() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(temp.path())?;
+            functions::register(&conn)?;
+            for step in &migrations::MIGRATIONS[..15] {
+                conn.execute_batch(step.sql)?;
+            }
+            conn.execute("INSERT INTO meta (key, val) VALUES ('schema_version', '15')", [])?;
+            conn.execute("INSERT INTO crates (id, name, fingerprint) VALUES (1, 'old', 'fp')", [])?;
+            conn.execute("INSERT INTO files (id, crate_id, path, digest) VALUES (1, 1, 'src/lib.rs', 'd')", [])?;
+            conn.execute(
+                "INSERT INTO symbols (symbol_id, crate_id, file_id, path, name, kind, visibility,
+                 signature, docs, status, span_start, span_end, def_hash)
+                 VALUES (x'01', 1, 1, 'old::Pool', 'Pool', 'struct', 'public', 'struct Pool',
+                 NULL, 'implemented', 1, 2, 'h')",
+                [],
+            )?;
+            // The second tag's symbol is gone, so it has no path to move to
+            conn.execute("INSERT INTO tags (symbol_id, tag, note) VALUES (x'01', 'hot', 'profile'), (x'09', 'stale', NULL)", [])?;
+        }
+        
+        let db = Database::open(temp.path())?;
+        assert_eq!(queries::get_tags(db.conn(), "old::Pool")?, vec![
+            Tag { tag: "hot".to_string(), note: Some("profile".to_string()) },
+        ]);
+        let tags: i64 = db.conn().query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))?;
+        assert_eq!(tags, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() -> Result<()> {
+        const STEPS: &[migrations::Migration] = &[
+            migrations::MIGRATIONS[0],
+            migrations::Migration {
+                version: 2,
+                description: "broken",
+                sql: "CREATE TABLE half_done (x INTEGER); INSERT INTO missing VALUES (1);",
+            },
+        ];
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database { conn: Connection::open(temp.path())? };
+        
+        assert!(matches!(db.migrate(STEPS), Err(DbError::Migration(_))));
+        assert_eq!(db.schema_version()?, 1);
+        let half_done: i64 = db.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'half_done'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(half_done, 0);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintain() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        for i in 0..50 {
+            db.insert_crate(&format!("crate_{}", i), None, &"x".repeat(2000))?;
+        }
+        db.delete_crate("crate_0")?;
+        db.conn().execute("DELETE FROM crates", [])?;
+        
+        let report = db.maintain()?;
+        assert!(!report.full_vacuum);
+        assert!(report.freed_pages > 0);
+        let remaining: i64 = db.conn().pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+        assert_eq!(remaining, 0);
+        assert!(!report.wal_busy);
+        
+        // Files created before incremental vacuum get rebuilt once
+        let legacy = NamedTempFile::new().unwrap();
+        Connection::open(legacy.path())?.execute_batch("CREATE TABLE legacy (id INTEGER)")?;
+        let db = Database::open(legacy.path())?;
+        assert!(db.maintain()?.full_vacuum);
+        assert!(!db.maintain()?.full_vacuum);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy_retry() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        // Leave the waiting to `retry_busy`
+        db.conn().busy_timeout(Duration::from_millis(1))?;
+        
+        let other = Connection::open(temp.path())?;
+        other.execute_batch("BEGIN EXCLUSIVE")?;
+        assert!(matches!(db.begin_transaction(), Err(DbError::Busy(_))));
+        
+        // A lock released while backing off is picked up by a retry
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(60));
+            other.execute_batch("COMMIT").unwrap();
+        });
+        db.begin_transaction()?;
+        db.commit_transaction()?;
+        holder.join().unwrap();
+        
+        Ok(())
+    }
+
     #[test]
     fn test_insert_crate() -> Result<()> {
         let temp = NamedTempFile::new().unwrap();
@@ -265,4 +1082,1096 @@ mod tests {
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_savepoint_rollback() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        
+        db.begin_transaction()?;
+        db.insert_crate("ok", None, "fp1")?;
+        db.savepoint("crate_index")?;
+        db.insert_crate("broken", None, "fp2")?;
+        db.rollback_to_savepoint("crate_index")?;
+        db.commit_transaction()?;
+        
+        assert_eq!(db.get_crate_count()?, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_crate() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let keep = db.insert_crate("keep", None, "fp1")?;
+        let drop = db.insert_crate("drop", None, "fp2")?;
+        db.insert_file(keep, "keep/src/lib.rs", "blake3:1")?;
+        let file_id = db.insert_file(drop, "drop/src/lib.rs", "blake3:2")?;
+        db.insert_impl(&ImplBlock {
+            id: 0,
+            for_path: "drop::State".to_string(),
+            trait_path: None,
+            file_id,
+            line_start: 1,
+            line_end: 2,
+        })?;
+        
+        assert_eq!(db.delete_crate("drop")?, 1);
+        assert_eq!(db.get_crate_count()?, 1);
+        assert_eq!(db.get_file_count()?, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_file() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("test_crate", None, "fp")?;
+        let kept = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let removed = db.insert_file(crate_id, "src/old.rs", "blake3:2")?;
+        for (file_id, name) in [(kept, "run"), (removed, "legacy")] {
+            db.insert_symbol(&test_symbol(&format!("test_crate::{}", name), SymbolKind::Fn, file_id))?;
+        }
+        db.insert_impl(&ImplBlock {
+            id: 0,
+            for_path: "test_crate::Legacy".to_string(),
+            trait_path: None,
+            file_id: removed,
+            line_start: 1,
+            line_end: 2,
+        })?;
+        
+        assert_eq!(db.delete_file("src/old.rs")?, 1);
+        assert_eq!(db.delete_file("src/old.rs")?, 0);
+        assert_eq!(db.get_file_count()?, 1);
+        assert_eq!(db.get_symbol_count()?, 1);
+        assert!(queries::find_symbols_by_name(db.conn(), "legacy", MatchMode::Exact, None, None, None, None, None, 10)?.items.is_empty());
+        let impls: i64 = db.conn().query_row("SELECT COUNT(*) FROM impls", [], |row| row.get(0))?;
+        assert_eq!(impls, 0);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_crate_index() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file = |id: i64, path: &str| File { id, crate_id, path: path.to_string(), digest: "d".to_string() };
+        let symbol = |name: &str, file_id: i64| test_symbol(&format!("app::{}", name), SymbolKind::Fn, file_id);
+        
+        db.replace_crate_index(crate_id, &[file(1, "src/lib.rs"), file(2, "src/old.rs")], &[symbol("old", 2)], &[])?;
+        db.replace_crate_index(crate_id, &[file(7, "src/lib.rs")], &[symbol("new", 7)], &[ImplBlock {
+            id: 0,
+            for_path: "app::State".to_string(),
+            trait_path: None,
+            file_id: 7,
+            line_start: 1,
+            line_end: 2,
+        }])?;
+        assert_eq!(db.get_file_count()?, 1);
+        assert_eq!(db.get_symbol_count()?, 1);
+        assert!(queries::find_symbol_by_path(db.conn(), "app::new")?.is_some());
+        
+        // A failed swap rolls back to the previous contents
+        let err = db.replace_crate_index(crate_id, &[file(1, "src/lib.rs")], &[symbol("broken", 99)], &[]);
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+        assert!(queries::find_symbol_by_path(db.conn(), "app::new")?.is_some());
+        assert!(queries::find_symbol_by_path(db.conn(), "app::broken")?.is_none());
+        assert_eq!(db.get_file_count()?, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_trait_impls() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let lib = db.insert_file(crate_id, "src/lib.rs", "d1")?;
+        let fmt = db.insert_file(crate_id, "src/fmt.rs", "d2")?;
+        let imp = |for_path: &str, trait_path: Option<&str>, file_id: i64, line_start: u32| ImplBlock {
+            id: 0,
+            for_path: for_path.to_string(),
+            trait_path: trait_path.map(str::to_string),
+            file_id,
+            line_start,
+            line_end: line_start + 5,
+        };
+        db.insert_symbol(&Symbol { span_start: 40, span_end: 42, ..test_symbol("app::fmt::Render", SymbolKind::Trait, fmt) })?;
+        for imp in [
+            imp("app::State", Some("Clone"), lib, 10),
+            imp("app::State", Some("fmt::Display"), fmt, 1),
+            imp("app::Config", Some("std::fmt::Display"), fmt, 20),
+            imp("app::State", None, lib, 30),
+            imp("app::State", Some("fmt::Render"), fmt, 50),
+        ] {
+            db.insert_impl(&imp)?;
+        }
+        
+        let types = |rows: Vec<TraitImpl>| rows.into_iter().map(|r| r.for_path).collect::<Vec<_>>();
+        // However the trait was written, any spelling of it finds the impl
+        for query in ["Display", "fmt::Display", "std::fmt::Display", "core::fmt::Display"] {
+            let found = queries::find_trait_implementors(db.conn(), query, 10)?;
+            let expected: Vec<&str> = match query {
+                "core::fmt::Display" => vec!["app::State"],
+                _ => vec!["app::Config", "app::State"],
+            };
+            assert_eq!(types(found), expected, "{}", query);
+        }
+        assert!(queries::find_trait_implementors(db.conn(), "play", 10)?.is_empty());
+        
+        let traits = queries::find_implemented_traits(db.conn(), "app::State", true, 10)?;
+        assert_eq!(traits.len(), 3);
+        assert_eq!(traits[..2], vec![
+            TraitImpl {
+                trait_path: "Clone".to_string(),
+                for_path: "app::State".to_string(),
+                file: "src/lib.rs".to_string(),
+                line_start: 10,
+                line_end: 15,
+            },
+            TraitImpl {
+                trait_path: "fmt::Display".to_string(),
+                for_path: "app::State".to_string(),
+                file: "src/fmt.rs".to_string(),
+                line_start: 1,
+                line_end: 6,
+            },
+        ]);
+        assert_eq!(queries::find_implemented_traits(db.conn(), "State", true, 1)?.len(), 1);
+        // Without external traits only the workspace's own remain, even when
+        // the impl names one by a shorter path
+        let traits = queries::find_implemented_traits(db.conn(), "app::State", false, 10)?;
+        assert_eq!(traits.iter().map(|t| t.trait_path.as_str()).collect::<Vec<_>>(), vec!["fmt::Render"]);
+        
+        db.delete_file("src/fmt.rs")?;
+        assert_eq!(types(queries::find_trait_implementors(db.conn(), "Display", 10)?), Vec::<String>::new());
+        db.delete_crate("app")?;
+        let rows: i64 = db.conn().query_row("SELECT COUNT(*) FROM trait_impls", [], |row| row.get(0))?;
+        assert_eq!(rows, 0);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_crate_symbols() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let index_once = |db: &Database| -> Result<()> {
+            db.delete_crate("test_crate")?;
+            let crate_id = db.insert_crate("test_crate", None, "fp")?;
+            let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:abc")?;
+            db.insert_symbol(&Symbol { crate_id, ..test_symbol("test_crate::run", SymbolKind::Fn, file_id) })
+        };
+        
+        index_once(&db)?;
+        index_once(&db)?;
+        assert_eq!(db.get_crate_count()?, 1);
+        assert_eq!(db.get_file_count()?, 1);
+        assert_eq!(db.get_symbol_count()?, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_symbols_batch() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("test_crate", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let symbols: Vec<Symbol> = (0..3)
+            .map(|i| Symbol { span_start: i, span_end: i, ..test_symbol(&format!("test_crate::f{}", i), SymbolKind::Fn, file_id) })
+            .collect();
+        
+        // Standalone: commits its own transaction
+        db.insert_symbols_batch(&symbols[..2])?;
+        assert_eq!(db.get_symbol_count()?, 2);
+        
+        // Nested: joins the caller's transaction and rolls back with it
+        db.begin_transaction()?;
+        db.insert_symbols_batch(&symbols[2..])?;
+        db.rollback_transaction()?;
+        assert_eq!(db.get_symbol_count()?, 2);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_symbol_history_hotspots() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("test_crate", Some("0.1.0"), "fingerprint123")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:abc")?;
+        let symbol = Symbol { span_end: 3, ..test_symbol("test_crate::State", SymbolKind::Struct, file_id) };
+        db.insert_symbol(&symbol)?;
+        db.insert_symbol_history(&symbol.symbol_id, &SymbolHistory {
+            last_commit: "deadbeef".to_string(),
+            last_modified: 1_700_000_000,
+            churn: 4,
+        })?;
+        
+        let hotspots = queries::get_hotspots(db.conn(), None, 10)?;
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!(hotspots[0].path, "test_crate::State");
+        assert_eq!(hotspots[0].churn, 4);
+        
+        let recent = queries::find_symbols_by_name(
+            db.conn(), "State", MatchMode::Exact, None, None, None, Some(1_700_000_001), None, 10,
+        )?;
+        assert!(recent.items.is_empty());
+        
+        Ok(())
+    }
+    #[test]
+    fn test_symbol_examples() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("test_crate", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let symbol = Symbol {
+            docs: Some("```\nState::new()\n```".to_string()),
+            span_end: 3,
+            ..test_symbol("test_crate::State", SymbolKind::Struct, file_id)
+        };
+        db.insert_symbol(&symbol)?;
+        for (idx, example) in ct_core::utils::extract_doc_examples(symbol.docs.as_deref().unwrap()).iter().enumerate() {
+            db.insert_example(&symbol.symbol_id, idx, example)?;
+        }
+        
+        // Looked up by the id of the symbol as read back, not as inserted
+        let found = queries::find_symbol_by_path(db.conn(), "test_crate::State")?.unwrap();
+        let examples = queries::get_examples(db.conn(), &found.symbol_id)?;
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].code, "State::new()");
+        
+        db.delete_crate("test_crate")?;
+        assert!(queries::get_examples(db.conn(), &found.symbol_id)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_fulltext() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("net", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let symbols = [
+            ("Throttle", "struct Throttle", Some("Applies a backpressure strategy to slow producers.")),
+            ("Queue", "struct Queue", Some("Bounded queue; see the backpressure notes.")),
+            ("connect", "fn connect(addr: &str) -> Conn", None),
+            ("Pool", "struct Pool", Some("Pooled sockets.\n\nKeeps a fixed number of idle connections so callers never wait on a handshake again.")),
+        ];
+        for (name, signature, docs) in symbols {
+            db.insert_symbol(&Symbol {
+                signature: signature.to_string(),
+                docs: docs.map(str::to_string),
+                ..test_symbol(&format!("net::{}", name), SymbolKind::Struct, file_id)
+            })?;
+        }
+        
+        let hits = queries::search_fulltext(db.conn(), "backpressure strategy", None, None, 0, 10)?;
+        let paths: Vec<_> = hits.iter().map(|h| h.symbol.path.as_str()).collect();
+        assert_eq!(paths, vec!["net::Throttle"]);
+        
+        let hits = queries::search_fulltext(db.conn(), "backpressure", None, None, 0, 10)?;
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0].score <= hits[1].score);
+        let rest = queries::search_fulltext(db.conn(), "backpressure", None, None, 1, 10)?;
+        assert_eq!(rest.iter().map(|h| &h.symbol.path).collect::<Vec<_>>(), vec![&hits[1].symbol.path]);
+        
+        // Signatures are searchable and FTS syntax is taken literally
+        assert_eq!(queries::search_fulltext(db.conn(), "addr: &str", None, None, 0, 10)?.len(), 1);
+        assert!(queries::search_fulltext(db.conn(), "NOT \"(", None, None, 0, 10)?.is_empty());
+        
+        // Doc search only looks at docs, and highlights what matched
+        let hits = queries::search_docs(db.conn(), "Backpressure", 10)?;
+        assert_eq!(hits.len(), 2);
+        let queue = hits.iter().find(|h| h.path == "net::Queue").unwrap();
+        assert_eq!(queue.snippet, "Bounded queue; see the **backpressure** notes.");
+        assert!(queries::search_docs(db.conn(), "Throttle", 10)?.is_empty());
+        assert!(queries::search_docs(db.conn(), "addr", 10)?.is_empty());
+        
+        // Grep matches substrings the tokenizer would not, case-sensitively
+        let hits = queries::grep_symbols(db.conn(), "pressure", false, &[], 10)?;
+        let found: Vec<_> = hits.iter().map(|h| (h.path.as_str(), h.field.as_str(), h.snippet.as_str())).collect();
+        assert_eq!(found, vec![
+            ("net::Queue", "docs", "Bounded queue; see the back**pressure** notes."),
+            ("net::Throttle", "docs", "Applies a back**pressure** strategy to slow producers."),
+        ]);
+        assert!(queries::grep_symbols(db.conn(), "Pressure", false, &[], 10)?.is_empty());
+        // The first field that matches is the one reported
+        let hits = queries::grep_symbols(db.conn(), "Queue", false, &[], 10)?;
+        assert_eq!((hits[0].field.as_str(), hits[0].snippet.as_str()), ("name", "**Queue**"));
+        let hits = queries::grep_symbols(db.conn(), "Queue", false, &[SymbolField::Signature], 10)?;
+        assert_eq!(hits[0].snippet, "struct **Queue**");
+        assert!(queries::grep_symbols(db.conn(), "Queue", false, &[SymbolField::Docs], 10)?.is_empty());
+        // Regex metacharacters are literal unless asked for
+        assert!(queries::grep_symbols(db.conn(), "&str) ->", false, &[], 10)?.len() == 1);
+        let hits = queries::grep_symbols(db.conn(), r"fn \w+\(addr", true, &[SymbolField::Signature], 10)?;
+        assert_eq!(hits[0].snippet, "**fn connect(addr**: &str) -> Conn");
+        assert!(matches!(
+            queries::grep_symbols(db.conn(), "(", true, &[], 10),
+            Err(DbError::InvalidQuery(_))
+        ));
+        // Only the matching line is shown, cut to the context around the match
+        let hits = queries::grep_symbols(db.conn(), "handshake", false, &[], 10)?;
+        assert_eq!(hits[0].snippet, "...connections so callers never wait on a **handshake** again.");
+        
+        db.delete_crate("net")?;
+        assert!(queries::search_fulltext(db.conn(), "backpressure", None, None, 0, 10)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_symbols_by_path_prefix() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        for (path, kind) in [
+            ("app::api", SymbolKind::Module),
+            ("app::api::Client", SymbolKind::Struct),
+            ("app::api::Client::send", SymbolKind::Method),
+            ("app::api::Error", SymbolKind::Enum),
+            ("app::apis", SymbolKind::Module),
+            ("app::util", SymbolKind::Module),
+        ] {
+            db.insert_symbol(&test_symbol(path, kind, file_id))?;
+        }
+        
+        let paths = |depth| -> Result<Vec<String>> {
+            Ok(queries::find_symbols_by_path_prefix(db.conn(), "app::api", depth, None, None, None, None, 10)?
+                .items
+                .into_iter()
+                .map(|s| s.path)
+                .collect())
+        };
+        // `app::apis` shares the text prefix but is not nested under it
+        assert_eq!(paths(None)?, vec!["app::api", "app::api::Client", "app::api::Client::send", "app::api::Error"]);
+        assert_eq!(paths(Some(1))?, vec!["app::api", "app::api::Client", "app::api::Error"]);
+        
+        // Only the kinds asked for and what holds them count toward the limit
+        let tree = |kinds: &[&str], depth, limit| -> Result<Vec<String>> {
+            let kinds: Vec<String> = kinds.iter().map(|kind| kind.to_string()).collect();
+            Ok(queries::find_tree_symbols(db.conn(), "app", depth, &kinds, limit)?
+                .items
+                .into_iter()
+                .map(|s| s.path)
+                .collect())
+        };
+        assert_eq!(tree(&["method"], None, 3)?, vec!["app::api", "app::api::Client", "app::api::Client::send"]);
+        assert!(tree(&["method"], Some(2), 10)?.is_empty());
+        assert_eq!(tree(&["enum"], None, 10)?, vec!["app::api", "app::api::Error"]);
+        assert_eq!(tree(&[], Some(1), 10)?, vec!["app::api", "app::apis", "app::util"]);
+        
+        let glob = |pattern| -> Result<Vec<String>> {
+            Ok(queries::find_symbols_by_path_glob(db.conn(), pattern, None, None, None, None, None, 10)?
+                .items
+                .into_iter()
+                .map(|s| s.path)
+                .collect())
+        };
+        // `*` stays within a segment, `**` spans any number of them
+        assert_eq!(glob("app::api::*")?, vec!["app::api::Client", "app::api::Error"]);
+        assert_eq!(glob("app::api::**")?, vec!["app::api::Client", "app::api::Client::send", "app::api::Error"]);
+        assert_eq!(glob("app::api*")?, vec!["app::api", "app::apis"]);
+        assert_eq!(glob("*::**::send")?, vec!["app::api::Client::send"]);
+        assert!(matches!(
+            queries::find_symbols_by_path_glob(db.conn(), "app::[", None, None, None, None, None, 10),
+            Err(DbError::InvalidQuery(_))
+        ));
+        
+        let plan: String = db.conn().query_row(
+            "EXPLAIN QUERY PLAN SELECT id FROM symbols WHERE path >= 'a::' AND path < 'a:;'",
+            [],
+            |row| row.get(3),
+        )?;
+        assert!(plan.contains("idx_symbols_path"), "{}", plan);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyset_pagination() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        // Two symbols share a path, so the rowid has to break the tie
+        for (i, path) in ["app::c::State", "app::a::State", "app::b::State", "app::b::State", "app::d::State"].iter().enumerate() {
+            db.insert_symbol(&Symbol {
+                symbol_id: format!("s{}", i),
+                span_start: i as u32,
+                span_end: i as u32,
+                ..test_symbol(path, SymbolKind::Struct, file_id)
+            })?;
+        }
+        
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = queries::find_symbols_by_name(db.conn(), "State", MatchMode::Exact, None, None, None, None, cursor.as_ref(), 2)?;
+            assert!(page.items.len() <= 2);
+            seen.extend(page.items.into_iter().map(|s| (s.path, s.span_start)));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![
+            ("app::a::State".to_string(), 1),
+            ("app::b::State".to_string(), 2),
+            ("app::b::State".to_string(), 3),
+            ("app::c::State".to_string(), 0),
+            ("app::d::State".to_string(), 4),
+        ]);
+        
+        let page = queries::get_status_items(db.conn(), None, false, false, None, 5)?;
+        assert_eq!(page.items.len(), 5);
+        assert!(page.next_cursor.is_none());
+        let page = queries::get_status_items(db.conn(), None, false, false, None, 4)?;
+        let rest = queries::get_status_items(db.conn(), None, false, false, page.next_cursor.as_ref(), 4)?;
+        assert_eq!(rest.items.len(), 1);
+        assert_eq!(rest.items[0].path, "app::d::State");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_match_modes() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        for name in ["HashMap", "HashSet", "hash_key", "Hash_Ring", "BTreeMap"] {
+            db.insert_symbol(&test_symbol(&format!("app::{}", name), SymbolKind::Struct, file_id))?;
+        }
+        
+        let names = |query: &str, mode| -> Result<Vec<String>> {
+            Ok(queries::find_symbols_by_name(db.conn(), query, mode, None, None, None, None, None, 10)?
+                .items
+                .into_iter()
+                .map(|s| s.name)
+                .collect())
+        };
+        assert_eq!(names("hashmap", MatchMode::Exact)?, Vec::<String>::new());
+        assert_eq!(names("hash", MatchMode::Prefix)?, vec!["HashMap", "HashSet", "Hash_Ring", "hash_key"]);
+        // `_` is literal in a prefix, not a LIKE wildcard
+        assert_eq!(names("Hash_", MatchMode::Prefix)?, vec!["Hash_Ring", "hash_key"]);
+        assert_eq!(names("*Map", MatchMode::Glob)?, vec!["BTreeMap", "HashMap"]);
+        // Case-sensitive, though the literal start is looked up without case
+        assert_eq!(names("Hash_*", MatchMode::Glob)?, vec!["Hash_Ring"]);
+        assert_eq!(names("{HashSet,BTree*}", MatchMode::Glob)?, vec!["BTreeMap", "HashSet"]);
+        assert_eq!(names("^Hash(Map|Set)$", MatchMode::Regex)?, vec!["HashMap", "HashSet"]);
+        assert_eq!(names("HahsMap", MatchMode::Fuzzy)?, vec!["HashMap"]);
+        
+        let ranked = |query: &str, offset| -> Result<Vec<String>> {
+            Ok(queries::fuzzy_find_symbols(db.conn(), query, None, None, offset, 3)?
+                .into_iter()
+                .map(|hit| hit.symbol.name)
+                .collect())
+        };
+        assert_eq!(ranked("hmap", 0)?, vec!["HashMap"]);
+        assert_eq!(ranked("app::hs", 0)?[0], "HashSet");
+        assert_eq!(ranked("ap", 0)?.len() + ranked("ap", 3)?.len(), 5);
+        assert_eq!(ranked("zz", 0)?, Vec::<String>::new());
+        
+        let err = queries::find_symbols_by_name(db.conn(), "(", MatchMode::Regex, None, None, None, None, None, 10);
+        assert!(matches!(err, Err(DbError::InvalidQuery(_))));
+        assert!("soundex".parse::<MatchMode>().is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_filters_are_bound_not_interpolated() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        for (name, visibility, status) in [
+            ("open", Visibility::Public, ImplementationStatus::Implemented),
+            ("close", Visibility::Private, ImplementationStatus::Todo),
+        ] {
+            db.insert_symbol(&Symbol { visibility, status, ..test_symbol(&format!("app::{}", name), SymbolKind::Fn, file_id) })?;
+        }
+        
+        let counts = queries::get_status_counts(db.conn(), Some("all"))?;
+        assert_eq!((counts.total, counts.implemented, counts.todo), (2, 1, 1));
+        let counts = queries::get_status_counts(db.conn(), Some("private"))?;
+        assert_eq!((counts.total, counts.todo), (1, 1));
+        
+        // A quote in a filter value is data, not SQL
+        let hostile = "public' OR '1'='1";
+        assert_eq!(queries::get_status_counts(db.conn(), Some(hostile))?.total, 0);
+        assert!(queries::get_status_items(db.conn(), Some(hostile), false, false, None, 10)?.items.is_empty());
+        assert!(queries::get_hotspots(db.conn(), Some(hostile), 10)?.is_empty());
+        let page = queries::find_symbols_by_name(db.conn(), "open", MatchMode::Exact, Some(hostile), None, None, None, None, 10)?;
+        assert!(page.items.is_empty());
+        
+        let todo = queries::get_status_items(db.conn(), None, true, true, None, 10)?;
+        assert_eq!(todo.items.iter().map(|i| i.path.as_str()).collect::<Vec<_>>(), vec!["app::close"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_stats() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let symbols = [
+            ("core", "core::Config", SymbolKind::Struct, Visibility::Public, ImplementationStatus::Implemented, Some("Settings.")),
+            ("core", "core::load", SymbolKind::Fn, Visibility::Public, ImplementationStatus::Todo, None),
+            ("core", "core::parse", SymbolKind::Fn, Visibility::Private, ImplementationStatus::Implemented, Some("")),
+            ("app", "app::main", SymbolKind::Fn, Visibility::Private, ImplementationStatus::Unimplemented, Some("Entry point.")),
+        ];
+        for (crate_name, path, kind, visibility, status, docs) in symbols {
+            let crate_id = match db.conn().query_row("SELECT id FROM crates WHERE name = ?", [crate_name], |row| row.get(0)).optional()? {
+                Some(id) => id,
+                None => db.insert_crate(crate_name, None, "fp")?,
+            };
+            let file_id = db.insert_file(crate_id, &format!("{}/src/lib.rs", path), "blake3:1")?;
+            db.insert_symbol(&Symbol {
+                crate_id,
+                visibility,
+                docs: docs.map(str::to_string),
+                status,
+                ..test_symbol(path, kind, file_id)
+            })?;
+        }
+        
+        db.conn().execute("UPDATE symbols SET span_start = 10, span_end = 40 WHERE path = 'core::load'", [])?;
+        db.conn().execute("UPDATE symbols SET span_end = 60 WHERE path = 'core::Config'", [])?;
+        
+        let summary = queries::get_stats(db.conn(), 2)?;
+        assert_eq!(summary.totals.total, 4);
+        assert_eq!(summary.totals.documented, 2);
+        assert_eq!(summary.totals.doc_coverage, 0.5);
+        assert_eq!(summary.totals.by_kind["fn"], 3);
+        assert_eq!(summary.totals.by_visibility["private"], 2);
+        assert_eq!(summary.totals.by_status["todo"], 1);
+        
+        let names: Vec<_> = summary.crates.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["app", "core"]);
+        assert_eq!(summary.crates[1].stats.total, 3);
+        assert_eq!(summary.crates[1].stats.by_status["implemented"], 2);
+        assert_eq!(summary.crates[0].stats.doc_coverage, 1.0);
+        
+        let files: Vec<_> = summary.largest_files.iter().map(|f| (f.path.as_str(), f.lines)).collect();
+        assert_eq!(files, vec![("core::Config/src/lib.rs", 60), ("core::load/src/lib.rs", 40)]);
+        assert_eq!(summary.largest_files[0].crate_name, "core");
+        // Only functions and methods, by span length
+        let fns: Vec<_> = summary.largest_fns.iter().map(|f| (f.path.as_str(), f.lines)).collect();
+        assert_eq!(fns, vec![("core::load", 31), ("app::main", 1)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_markers() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let net = db.insert_file(crate_id, "src/net.rs", "blake3:1")?;
+        let lib = db.insert_file(crate_id, "src/lib.rs", "blake3:2")?;
+        for (file_id, path, span_start, span_end) in [
+            (net, "app::net", 1, 40),
+            (net, "app::net::connect", 2, 20),
+            (lib, "app::run", 5, 20),
+        ] {
+            db.insert_symbol(&Symbol {
+                status: ImplementationStatus::Todo,
+                span_start,
+                span_end,
+                ..test_symbol(path, SymbolKind::Fn, file_id)
+            })?;
+        }
+        let marker = |marker: &str, message: &str, line, introduced| Marker {
+            marker: marker.to_string(),
+            message: message.to_string(),
+            line,
+            introduced,
+        };
+        db.insert_marker(net, &marker("TODO", "TODO(alice): retry", 3, Some(1_700_000_000)))?;
+        db.insert_marker(net, &marker("todo", "", 2, None))?;
+        db.insert_marker(net, &marker("TODO", "TODO: split the module", 30, None))?;
+        db.insert_marker(lib, &marker("FIXME", "FIXME(Bob): leaks", 9, None))?;
+        db.insert_marker(lib, &marker("TODO", "TODO: docs", 1, None))?;
+        
+        // By file, then line, in the innermost symbol or else the crate
+        let all = queries::get_markers(db.conn(), None, 10)?;
+        let found: Vec<_> = all.iter().map(|m| (m.file.as_str(), m.marker.line, m.path.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("src/lib.rs", 1, "app"),
+                ("src/lib.rs", 9, "app::run"),
+                ("src/net.rs", 2, "app::net::connect"),
+                ("src/net.rs", 3, "app::net::connect"),
+                ("src/net.rs", 30, "app::net"),
+            ]
+        );
+        assert_eq!(all[3].marker.introduced, Some(1_700_000_000));
+        
+        let bob = queries::get_markers(db.conn(), Some("bob"), 10)?;
+        assert_eq!(bob.iter().map(|m| m.path.as_str()).collect::<Vec<_>>(), vec!["app::run"]);
+        // The marker itself matches too
+        assert_eq!(queries::get_markers(db.conn(), Some("todo"), 10)?.len(), 4);
+        assert_eq!(queries::get_markers(db.conn(), None, 1)?.len(), 1);
+        
+        db.delete_file("src/net.rs")?;
+        assert_eq!(queries::get_markers(db.conn(), None, 10)?.len(), 2);
+        db.delete_crate("app")?;
+        assert!(queries::get_markers(db.conn(), None, 10)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_epoch() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        assert_eq!(queries::get_index_epoch(db.conn())?, 0);
+        
+        assert_eq!(db.bump_index_epoch()?, 1);
+        // A rolled-back run leaves the epoch where it was
+        db.begin_transaction()?;
+        db.bump_index_epoch()?;
+        db.rollback_transaction()?;
+        assert_eq!(queries::get_index_epoch(db.conn())?, 1);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_by_signature() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        for (name, kind, signature) in [
+            ("parse_config", SymbolKind::Fn, "pub fn parse_config(s: &str) -> Result<Config, Error>"),
+            ("parse_port", SymbolKind::Fn, "fn parse_port<'a>(s: &'a str) -> std::result::Result<u16, ParseIntError>"),
+            ("from_str", SymbolKind::Method, "fn from_str(&self, s: &str) -> Result<Self, Self::Err>"),
+            ("count", SymbolKind::Fn, "fn count(s: &str) -> usize"),
+            ("Callback", SymbolKind::TypeAlias, "type Callback = fn(&str) -> Result<(), Error>"),
+        ] {
+            db.insert_symbol(&Symbol { signature: signature.to_string(), ..test_symbol(&format!("app::{}", name), kind, file_id) })?;
+        }
+        
+        let names = |pattern: &str| -> Result<Vec<String>> {
+            Ok(queries::find_by_signature(db.conn(), pattern, None, None, None, None, 10)?
+                .items
+                .into_iter()
+                .map(|s| s.name)
+                .collect())
+        };
+        assert_eq!(names("fn(&str) -> Result<_, _>")?, vec!["parse_config", "parse_port"]);
+        assert_eq!(names("fn(&self, ..) -> Result<_, _>")?, vec!["from_str"]);
+        assert_eq!(names("(&str) -> _")?, vec!["count", "parse_config", "parse_port"]);
+        assert!(names("fn(&str)")?.is_empty());
+        
+        let err = queries::find_by_signature(db.conn(), "Config", None, None, None, None, 10);
+        assert!(matches!(err, Err(DbError::InvalidQuery(_))));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file = File { id: 1, crate_id, path: "src/lib.rs".to_string(), digest: "d".to_string() };
+        let symbol = |name: &str| test_symbol(&format!("app::{}", name), SymbolKind::Struct, file.id);
+        db.replace_crate_index(crate_id, std::slice::from_ref(&file), &[symbol("State"), symbol("Config")], &[])?;
+        
+        assert_eq!(db.add_tag("app::State", "needs-refactor", None)?, 1);
+        assert_eq!(db.add_tag("app::State", "hot", Some("profile first"))?, 1);
+        assert_eq!(db.add_tag("app::Missing", "hot", None)?, 0);
+        // Re-adding a tag updates its note rather than duplicating it
+        db.add_tag("app::State", "hot", Some("see flamegraph"))?;
+        assert_eq!(queries::get_tags(db.conn(), "app::State")?, vec![
+            Tag { tag: "hot".to_string(), note: Some("see flamegraph".to_string()) },
+            Tag { tag: "needs-refactor".to_string(), note: None },
+        ]);
+        
+        // Tags follow the symbol through a reindex, even one that moves its
+        // span and so changes its id
+        let moved = Symbol { symbol_id: "State@2".to_string(), span_start: 2, span_end: 2, ..symbol("State") };
+        db.replace_crate_index(crate_id, std::slice::from_ref(&file), &[symbol("Config"), moved], &[])?;
+        assert_eq!(queries::get_tags(db.conn(), "app::State")?.len(), 2);
+        let tagged = queries::find_symbols_by_tag(db.conn(), "needs-refactor", None, None, None, None, 10)?;
+        assert_eq!(tagged.items.iter().map(|s| s.path.as_str()).collect::<Vec<_>>(), vec!["app::State"]);
+        assert!(queries::find_symbols_by_tag(db.conn(), "needs-refactor", Some("fn"), None, None, None, 10)?.items.is_empty());
+        
+        assert_eq!(db.remove_tag("app::State", "needs-refactor")?, 1);
+        assert_eq!(db.remove_tag("app::State", "needs-refactor")?, 0);
+        assert!(queries::find_symbols_by_tag(db.conn(), "needs-refactor", None, None, None, None, 10)?.items.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_snippets() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        db.insert_snippet("blake3:1", 2, 4, "struct State {\n}\n")?;
+        
+        assert_eq!(queries::get_file(db.conn(), file_id)?.unwrap().digest, "blake3:1");
+        assert_eq!(queries::get_snippet(db.conn(), "blake3:1", 2, 4)?.as_deref(), Some("struct State {\n}\n"));
+        assert!(queries::get_snippet(db.conn(), "blake3:2", 2, 4)?.is_none());
+        
+        // Snippets go with the last file holding their content
+        db.delete_file("src/lib.rs")?;
+        assert!(queries::get_snippet(db.conn(), "blake3:1", 2, 4)?.is_none());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_callers_and_callees() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("app", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/main.rs", "blake3:1")?;
+        for (id, name) in [("main_id", "main"), ("run_id", "run")] {
+            db.insert_symbol(&Symbol {
+                symbol_id: id.to_string(),
+                visibility: Visibility::Private,
+                span_end: 10,
+                ..test_symbol(&format!("app::{}", name), SymbolKind::Fn, file_id)
+            })?;
+        }
+        let call = |caller: &str, callee: &str, line: u32| Call {
+            caller_symbol_id: caller.to_string(),
+            callee_path: callee.to_string(),
+            file_id,
+            span_start: line,
+            span_end: line,
+        };
+        db.insert_call(&call("main_id", "app::run", 3))?;
+        db.insert_call(&call("main_id", "std::process::exit", 4))?;
+        db.insert_call(&call("run_id", "std::process::exit", 8))?;
+        
+        let callees = queries::find_callees(db.conn(), "app::main", 10)?;
+        assert_eq!(
+            callees.iter().map(|c| c.callee_path.as_str()).collect::<Vec<_>>(),
+            vec!["app::run", "std::process::exit"]
+        );
+        assert_eq!(callees[0].file, "src/main.rs");
+        
+        let callers = queries::find_callers(db.conn(), "std::process::exit", 10)?;
+        assert_eq!(
+            callers.iter().map(|c| c.caller_path.as_str()).collect::<Vec<_>>(),
+            vec!["app::main", "app::run"]
+        );
+        
+        // Callers of exit, then the caller of those: main calls run
+        let graph = queries::call_graph(db.conn(), "std::process::exit", queries::CallDirection::Callers, 2, 10)?;
+        assert_eq!(
+            graph.iter().map(|(hop, c)| (*hop, c.caller_path.as_str(), c.span_start)).collect::<Vec<_>>(),
+            vec![(1, "app::main", 4), (1, "app::run", 8), (2, "app::main", 3)]
+        );
+        let graph = queries::call_graph(db.conn(), "app::main", queries::CallDirection::Callees, 5, 10)?;
+        assert_eq!(
+            graph.iter().map(|(hop, c)| (*hop, c.callee_path.as_str())).collect::<Vec<_>>(),
+            vec![(1, "app::run"), (1, "std::process::exit"), (2, "std::process::exit")]
+        );
+        assert_eq!(queries::call_graph(db.conn(), "app::main", queries::CallDirection::Callees, 5, 1)?.len(), 1);
+        
+        db.delete_file("src/main.rs")?;
+        assert!(queries::find_callers(db.conn(), "app::run", 10)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_dependency_graph() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let dep = |name: &str, kind: &str| CrateDependency {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            req: "^1".to_string(),
+            optional: false,
+        };
+        let cli = db.insert_crate("app-cli", None, "fp")?;
+        let core = db.insert_crate("app-core", None, "fp")?;
+        db.insert_dependency(cli, &dep("app-core", "normal"))?;
+        db.insert_dependency(cli, &dep("tempfile", "dev"))?;
+        db.insert_dependency(core, &dep("serde", "normal"))?;
+        
+        let edges = |rows: Vec<DepEdge>| rows.into_iter().map(|e| (e.from, e.to, e.depth)).collect::<Vec<_>>();
+        let owned = |from: &str, to: &str, depth| (from.to_string(), to.to_string(), depth);
+        assert_eq!(
+            edges(queries::dependency_graph(db.conn(), Some("app_cli"), queries::DepDirection::Dependencies, 2, 10)?),
+            vec![owned("app-cli", "app-core", 1), owned("app-cli", "tempfile", 1), owned("app-core", "serde", 2)]
+        );
+        assert_eq!(
+            edges(queries::dependency_graph(db.conn(), Some("serde"), queries::DepDirection::Dependents, 5, 10)?),
+            vec![owned("app-core", "serde", 1), owned("app-cli", "app-core", 2)]
+        );
+        assert_eq!(queries::dependency_graph(db.conn(), None, queries::DepDirection::Dependencies, 1, 10)?.len(), 3);
+        
+        db.delete_crate("app-core")?;
+        assert_eq!(queries::dependency_graph(db.conn(), None, queries::DepDirection::Dependencies, 1, 10)?.len(), 2);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_paths() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("my_crate", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let symbol = |path: &str| test_symbol(path, SymbolKind::Struct, file_id);
+        db.insert_symbols_batch(&[
+            symbol("my_crate::app::State"),
+            symbol("my_crate::utils::State"),
+            symbol("my_crate::utils::Config"),
+        ])?;
+        
+        // Same name, closest module first
+        assert_eq!(
+            queries::suggest_paths(db.conn(), "my_crate::util::State", 5)?,
+            vec!["my_crate::utils::State", "my_crate::app::State"]
+        );
+        // No such name: fall back to similar ones
+        assert_eq!(queries::suggest_paths(db.conn(), "my_crate::utils::Confg", 5)?, vec!["my_crate::utils::Config"]);
+        assert!(queries::suggest_paths(db.conn(), "my_crate::Nothing", 5)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_symbol_at() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("my_crate", None, "fp")?;
+        let lib = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let main = db.insert_file(crate_id, "src/main.rs", "blake3:2")?;
+        let symbol = |path: &str, file_id: i64, span_start: u32, span_end: u32| Symbol {
+            symbol_id: format!("{}@{}", path, file_id),
+            span_start,
+            span_end,
+            ..test_symbol(path, SymbolKind::Fn, file_id)
+        };
+        db.insert_symbols_batch(&[
+            symbol("my_crate", lib, 1, 100),
+            symbol("my_crate::State", lib, 10, 40),
+            symbol("my_crate::State::new", lib, 20, 30),
+            symbol("my_crate::main", main, 1, 50),
+        ])?;
+        
+        let owner = |file: &str, line: u32| {
+            queries::find_symbol_at(db.conn(), file, line).map(|s| s.map(|s| s.path))
+        };
+        assert_eq!(owner("src/lib.rs", 25)?.as_deref(), Some("my_crate::State::new"));
+        assert_eq!(owner("src/lib.rs", 40)?.as_deref(), Some("my_crate::State"));
+        assert_eq!(owner("src/lib.rs", 60)?.as_deref(), Some("my_crate"));
+        assert_eq!(owner("src/main.rs", 25)?.as_deref(), Some("my_crate::main"));
+        assert_eq!(owner("src/lib.rs", 101)?, None);
+        assert_eq!(owner("src/other.rs", 1)?, None);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_status_groups() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let app = db.insert_crate("app", None, "fp")?;
+        let util = db.insert_crate("util", None, "fp")?;
+        let file_id = db.insert_file(app, "src/lib.rs", "blake3:1")?;
+        let symbol = |crate_id: i64, path: &str, kind: SymbolKind, status: ImplementationStatus| Symbol {
+            crate_id,
+            status,
+            ..test_symbol(path, kind, file_id)
+        };
+        use ImplementationStatus::*;
+        db.insert_symbols_batch(&[
+            symbol(app, "app", SymbolKind::Module, Implemented),
+            symbol(app, "app::net", SymbolKind::Module, Implemented),
+            symbol(app, "app::net::connect", SymbolKind::Fn, Unimplemented),
+            symbol(app, "app::net::Conn", SymbolKind::Struct, Implemented),
+            symbol(app, "app::net::Conn::close", SymbolKind::Method, Todo),
+            symbol(util, "util::helper", SymbolKind::Fn, Unimplemented),
+        ])?;
+        
+        let groups = |group_by| -> Result<Vec<(String, usize, usize)>> {
+            Ok(queries::get_status_groups(db.conn(), None, group_by)?
+                .into_iter()
+                .map(|g| (g.name, g.counts.total, g.counts.unimplemented))
+                .collect())
+        };
+        let owned = |rows: &[(&str, usize, usize)]| -> Vec<(String, usize, usize)> {
+            rows.iter().map(|(name, total, unimplemented)| (name.to_string(), *total, *unimplemented)).collect()
+        };
+        assert_eq!(groups(StatusGroup::Crate)?, owned(&[("app", 5, 1), ("util", 1, 1)]));
+        assert_eq!(groups(StatusGroup::Kind)?, owned(&[("fn", 2, 2), ("method", 1, 0), ("module", 2, 0), ("struct", 1, 0)]));
+        // Methods count under the module of their type; crates without
+        // module symbols fall back to the crate name
+        assert_eq!(groups(StatusGroup::Module)?, owned(&[("app", 2, 0), ("app::net", 3, 1), ("util", 1, 1)]));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_symbols() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("test_crate", None, "fp")?;
+        let file_id = db.insert_file(crate_id, "src/lib.rs", "blake3:1")?;
+        let symbol = |i: usize| Symbol {
+            signature: format!("fn f{}()", i),
+            span_start: i as u32,
+            span_end: i as u32,
+            ..test_symbol(&format!("test_crate::f{}", i), SymbolKind::Fn, file_id)
+        };
+        db.insert_symbols_batch(&[symbol(0), symbol(1), symbol(2)])?;
+        db.save_epoch_baseline()?;
+        db.save_crate_baseline("test_crate")?;
+        db.bump_index_epoch()?;
+        let baseline: i64 = db.conn().query_row("SELECT COUNT(*) FROM epoch_baseline", [], |row| row.get(0))?;
+        assert_eq!(baseline, 3);
+        assert_eq!(queries::get_baseline_epoch(db.conn())?, Some(0));
+        assert!(queries::diff_symbols(db.conn(), None, false, 10)?.is_empty());
+        
+        db.conn().execute("DELETE FROM symbols WHERE path = 'test_crate::f0'", [])?;
+        db.conn().execute(
+            "UPDATE symbols SET def_hash = 'new', signature = 'fn f1(x: u8)' WHERE path = 'test_crate::f1'",
+            [],
+        )?;
+        db.insert_symbol(&symbol(3))?;
+        
+        let changes = queries::diff_symbols(db.conn(), None, false, 10)?;
+        let summary = changes.iter().map(|c| (c.change, c.path.as_str())).collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (ChangeKind::Removed, "test_crate::f0"),
+            (ChangeKind::Changed, "test_crate::f1"),
+            (ChangeKind::Added, "test_crate::f3"),
+        ]);
+        assert_eq!(changes[1].previous_signature.as_deref(), Some("fn f1()"));
+        assert_eq!(queries::diff_symbols(db.conn(), None, false, 2)?.len(), 2);
+        
+        // Breaking changes: not the addition, nor a body edit or a private
+        // symbol's removal, but a public symbol going private
+        db.conn().execute("UPDATE symbols SET def_hash = 'body' WHERE path = 'test_crate::f2'", [])?;
+        db.conn().execute("UPDATE epoch_baseline SET visibility = 'private' WHERE path = 'test_crate::f0'", [])?;
+        let breaking = |db: &Database| -> Result<Vec<(ChangeKind, String)>> {
+            let changes = queries::diff_symbols(db.conn(), None, true, 10)?;
+            Ok(changes.into_iter().map(|c| (c.change, c.path)).collect())
+        };
+        assert_eq!(breaking(&db)?, vec![(ChangeKind::Changed, "test_crate::f1".to_string())]);
+        // The hash leaves out visibility, which is compared on its own
+        db.conn().execute("UPDATE symbols SET def_hash = 'hash', visibility = 'private' WHERE path = 'test_crate::f2'", [])?;
+        assert_eq!(breaking(&db)?.len(), 2);
+        
+        // Only what the run replaced is compared: another crate left alone
+        // is not added, and a replaced file leaves its crate's other files out
+        let other = db.insert_crate("other", None, "fp")?;
+        let other_file = db.insert_file(other, "other/lib.rs", "blake3:2")?;
+        db.insert_symbol(&Symbol { crate_id: other, file_id: other_file, path: "other::g".to_string(), ..symbol(4) })?;
+        assert!(!queries::diff_symbols(db.conn(), None, false, 10)?.iter().any(|c| c.path == "other::g"));
+        db.save_epoch_baseline()?;
+        db.save_file_baseline("other/lib.rs")?;
+        db.delete_file("other/lib.rs")?;
+        let changes = queries::diff_symbols(db.conn(), None, false, 10)?;
+        assert_eq!(changes.iter().map(|c| (c.change, c.path.as_str())).collect::<Vec<_>>(), vec![
+            (ChangeKind::Removed, "other::g"),
+        ]);
+        
+        // A snapshot is another index file; the current index diffs clean against itself
+        assert!(queries::diff_symbols(db.conn(), Some(temp.path()), false, 10)?.is_empty());
+        let empty = NamedTempFile::new().unwrap();
+        Database::open(empty.path())?;
+        assert_eq!(queries::diff_symbols(db.conn(), Some(empty.path()), false, 10)?.len(), 3);
+        assert!(queries::diff_symbols(db.conn(), Some(Path::new("/nonexistent/index.db")), false, 10).is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        let root = tempfile::TempDir::new().unwrap();
+        std::fs::write(root.path().join("a.rs"), "fn a() {}").unwrap();
+        std::fs::write(root.path().join("b.rs"), "fn b() {}").unwrap();
+        
+        let a = db.insert_crate("a", None, "fp")?;
+        let b = db.insert_crate("b", None, "fp")?;
+        db.insert_file(a, "a.rs", &ct_core::compute_file_digest(b"fn a() {}"))?;
+        db.insert_file(b, "b.rs", &ct_core::compute_file_digest(b"fn b() {}"))?;
+        db.insert_file(b, "gone.rs", "blake3:0")?;
+        db.insert_file(b, "never_existed.rs", "missing")?;
+        
+        let report = db.verify(root.path())?;
+        assert_eq!(report.files_checked, 4);
+        assert_eq!(report.stale_crates, vec![StaleCrate {
+            name: "b".to_string(),
+            changed: vec![],
+            missing: vec!["gone.rs".to_string()],
+        }]);
+        
+        std::fs::write(root.path().join("a.rs"), "fn a() { todo!() }").unwrap();
+        std::fs::write(root.path().join("gone.rs"), "").unwrap();
+        let report = db.verify(root.path())?;
+        assert_eq!(report.stale_crates.iter().map(|c| (c.name.as_str(), c.changed.len())).collect::<Vec<_>>(), vec![("a", 1), ("b", 1)]);
+        assert!(!report.is_fresh());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_crate_for_file() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        
+        let crate_id = db.insert_crate("crate_a", None, "fp")?;
+        db.insert_file(crate_id, "generated/api.rs", "blake3:1")?;
+        
+        assert_eq!(queries::find_crate_for_file(db.conn(), "generated/api.rs")?.as_deref(), Some("crate_a"));
+        assert!(queries::find_crate_for_file(db.conn(), "crate_a/src/lib.rs")?.is_none());
+        
+        db.insert_input(crate_id, "crate_a/schema.sql")?;
+        assert_eq!(queries::find_crate_for_file(db.conn(), "crate_a/schema.sql")?.as_deref(), Some("crate_a"));
+        assert_eq!(queries::get_input_paths(db.conn())?, vec!["crate_a/schema.sql"]);
+        db.delete_crate("crate_a")?;
+        assert!(queries::get_input_paths(db.conn())?.is_empty());
+        
+        Ok(())
+    }
+}
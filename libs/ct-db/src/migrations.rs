@@ -1,4 +1,34 @@
-pub const CURRENT_VERSION: u32 = 1;
+/// One schema step. Steps run in `version` order, each in its own
+/// transaction together with the `schema_version` bump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "initial schema", sql: V1_SCHEMA },
+    Migration { version: 2, description: "symbol history table", sql: V2_SCHEMA },
+    Migration { version: 3, description: "doc examples table", sql: V3_SCHEMA },
+    Migration { version: 4, description: "crate inputs table", sql: V4_SCHEMA },
+    Migration { version: 5, description: "full-text search index", sql: V5_SCHEMA },
+    Migration { version: 6, description: "calls table", sql: V6_SCHEMA },
+    Migration { version: 7, description: "source snippet cache", sql: V7_SCHEMA },
+    Migration { version: 8, description: "symbol tags table", sql: V8_SCHEMA },
+    Migration { version: 9, description: "signature shape column", sql: V9_SCHEMA },
+    Migration { version: 10, description: "trait impls table", sql: V10_SCHEMA },
+    Migration { version: 11, description: "crate dependencies table", sql: V11_SCHEMA },
+    Migration { version: 12, description: "epoch baseline table", sql: V12_SCHEMA },
+    Migration { version: 13, description: "symbol location indexes", sql: V13_SCHEMA },
+    Migration { version: 14, description: "reference column", sql: V14_SCHEMA },
+    Migration { version: 15, description: "markers table", sql: V15_SCHEMA },
+    Migration { version: 16, description: "tags keyed by path", sql: V16_SCHEMA },
+    Migration { version: 17, description: "epoch baseline scope", sql: V17_SCHEMA },
+    Migration { version: 18, description: "markers keyed by file", sql: V18_SCHEMA },
+];
+
+pub const CURRENT_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
 
 pub const V1_SCHEMA: &str = r#"
 PRAGMA foreign_keys=ON;
@@ -65,4 +95,243 @@ CREATE TABLE IF NOT EXISTS symbol_references (
   span_start INTEGER NOT NULL,
   span_end INTEGER NOT NULL
 );
-"#;
\ No newline at end of file
+"#;
+
+pub const V2_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS symbol_history (
+  symbol_id BLOB PRIMARY KEY,
+  last_commit TEXT NOT NULL,
+  last_modified INTEGER NOT NULL,
+  churn INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_symbol_history_modified ON symbol_history(last_modified);
+CREATE INDEX IF NOT EXISTS idx_symbol_history_churn ON symbol_history(churn);
+"#;
+
+pub const V3_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS examples (
+  id INTEGER PRIMARY KEY,
+  symbol_id BLOB NOT NULL,
+  idx INTEGER NOT NULL,
+  lang TEXT NOT NULL,
+  code TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_examples_symbol ON examples(symbol_id);
+"#;
+
+pub const V4_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS crate_inputs (
+  id INTEGER PRIMARY KEY,
+  crate_id INTEGER NOT NULL REFERENCES crates(id),
+  path TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_crate_inputs_path ON crate_inputs(path);
+"#;
+
+/// Full-text index over symbol names, signatures and docs, kept in sync with
+/// `symbols` by triggers and backfilled from existing rows.
+pub const V5_SCHEMA: &str = r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS symbols_fts USING fts5(
+  name, signature, docs,
+  content='symbols', content_rowid='id'
+);
+
+CREATE TRIGGER IF NOT EXISTS symbols_fts_insert AFTER INSERT ON symbols BEGIN
+  INSERT INTO symbols_fts(rowid, name, signature, docs)
+  VALUES (new.id, new.name, new.signature, new.docs);
+END;
+
+CREATE TRIGGER IF NOT EXISTS symbols_fts_delete AFTER DELETE ON symbols BEGIN
+  INSERT INTO symbols_fts(symbols_fts, rowid, name, signature, docs)
+  VALUES ('delete', old.id, old.name, old.signature, old.docs);
+END;
+
+CREATE TRIGGER IF NOT EXISTS symbols_fts_update AFTER UPDATE ON symbols BEGIN
+  INSERT INTO symbols_fts(symbols_fts, rowid, name, signature, docs)
+  VALUES ('delete', old.id, old.name, old.signature, old.docs);
+  INSERT INTO symbols_fts(rowid, name, signature, docs)
+  VALUES (new.id, new.name, new.signature, new.docs);
+END;
+
+INSERT INTO symbols_fts(symbols_fts) VALUES ('rebuild');
+"#;
+
+pub const V6_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS calls (
+  id INTEGER PRIMARY KEY,
+  caller_symbol_id BLOB NOT NULL,
+  callee_path TEXT NOT NULL,
+  file_id INTEGER NOT NULL REFERENCES files(id),
+  span_start INTEGER NOT NULL,
+  span_end INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_calls_caller ON calls(caller_symbol_id);
+CREATE INDEX IF NOT EXISTS idx_calls_callee ON calls(callee_path);
+"#;
+
+/// Span text keyed by the digest of the file it was sliced from, so a cached
+/// snippet can never outlive the content it came from.
+pub const V7_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS source_snippets (
+  file_digest TEXT NOT NULL,
+  span_start INTEGER NOT NULL,
+  span_end INTEGER NOT NULL,
+  text TEXT NOT NULL,
+  PRIMARY KEY (file_digest, span_start, span_end)
+) WITHOUT ROWID;
+"#;
+
+/// User annotations. Unlike the index tables these are never cleared on
+/// reindex; keyed by path from V16.
+pub const V8_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS tags (
+  symbol_id BLOB NOT NULL,
+  tag TEXT NOT NULL,
+  note TEXT,
+  PRIMARY KEY (symbol_id, tag)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS idx_tags_tag ON tags(tag);
+"#;
+
+/// Normalized argument and return types of functions, for `find --sig`;
+/// backfilled with the `signature_shape` SQL function.
+pub const V9_SCHEMA: &str = r#"
+ALTER TABLE symbols ADD COLUMN signature_shape TEXT;
+
+UPDATE symbols SET signature_shape = signature_shape(signature)
+WHERE kind IN ('fn', 'method', 'foreign_fn');
+
+CREATE INDEX IF NOT EXISTS idx_symbols_signature_shape ON symbols(signature_shape);
+"#;
+
+/// Which types implement which traits, one row per trait impl block in
+/// `impls`; backfilled from existing impls.
+pub const V10_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS trait_impls (
+  trait_path TEXT NOT NULL,
+  for_path TEXT NOT NULL,
+  impl_id INTEGER NOT NULL REFERENCES impls(id),
+  PRIMARY KEY (trait_path, for_path, impl_id)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS idx_trait_impls_for ON trait_impls(for_path);
+CREATE INDEX IF NOT EXISTS idx_trait_impls_impl ON trait_impls(impl_id);
+
+INSERT OR IGNORE INTO trait_impls (trait_path, for_path, impl_id)
+SELECT trait_path, for_path, id FROM impls WHERE trait_path IS NOT NULL;
+"#;
+
+/// Dependencies each indexed crate declares in its manifest, by package
+/// name; filled on the next reindex.
+pub const V11_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS crate_deps (
+  crate_id INTEGER NOT NULL REFERENCES crates(id),
+  dep_name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  req TEXT NOT NULL,
+  optional INTEGER NOT NULL,
+  PRIMARY KEY (crate_id, dep_name, kind)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS idx_crate_deps_dep ON crate_deps(dep_name);
+"#;
+
+/// The symbols as they stood before the latest index run, so `diff` can
+/// compare against the previous epoch without a snapshot file.
+pub const V12_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS epoch_baseline (
+  path TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  visibility TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  def_hash TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_epoch_baseline_path ON epoch_baseline(path);
+"#;
+
+/// Lets `owner` find the symbols of a file without scanning them all.
+pub const V13_SCHEMA: &str = r#"
+CREATE INDEX IF NOT EXISTS idx_files_path ON files(path);
+CREATE INDEX IF NOT EXISTS idx_symbols_file_span ON symbols(file_id, span_start);
+"#;
+
+/// 1-based column each reference starts at, for `file:line:col` output.
+/// Rows indexed before it was recorded have none, so clearing the crate
+/// fingerprints makes the next run index every crate again.
+pub const V14_SCHEMA: &str = r#"
+ALTER TABLE symbol_references ADD COLUMN span_col INTEGER;
+
+UPDATE crates SET fingerprint = '';
+"#;
+
+/// `todo!`, `unimplemented!` and `TODO`/`FIXME` comments in function
+/// bodies; empty for crates indexed before it, until they are indexed again.
+pub const V15_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS markers (
+  symbol_id BLOB NOT NULL,
+  line INTEGER NOT NULL,
+  marker TEXT NOT NULL,
+  message TEXT NOT NULL,
+  introduced INTEGER,
+  PRIMARY KEY (symbol_id, line)
+) WITHOUT ROWID;
+"#;
+
+/// Tags keyed by path and kind rather than symbol id, which hashes the span
+/// and so changes whenever the code above a symbol is edited. Tags whose
+/// symbol no longer exists are dropped.
+pub const V16_SCHEMA: &str = r#"
+CREATE TABLE tags_by_path (
+  path TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  note TEXT,
+  PRIMARY KEY (path, kind, tag)
+) WITHOUT ROWID;
+
+INSERT OR IGNORE INTO tags_by_path (path, kind, tag, note)
+SELECT s.path, s.kind, t.tag, t.note FROM tags t JOIN symbols s ON s.symbol_id = t.symbol_id;
+
+DROP TABLE tags;
+ALTER TABLE tags_by_path RENAME TO tags;
+CREATE INDEX IF NOT EXISTS idx_tags_tag ON tags(tag);
+"#;
+
+/// The crates and files `epoch_baseline` holds the rows of, so `diff`
+/// compares only what the last index run replaced; `file` is NULL for a
+/// whole crate. A baseline saved before this covered every crate and has
+/// no scope, so it is dropped.
+pub const V17_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS epoch_scope (
+  crate_name TEXT NOT NULL,
+  file TEXT
+);
+
+DELETE FROM epoch_baseline;
+DELETE FROM meta WHERE key = 'baseline_epoch';
+"#;
+
+/// Markers keyed by file and line, taken from the whole file rather than
+/// the bodies of unfinished functions, so module, impl and type-level
+/// `TODO`s are kept; the symbol a marker is in is looked up from its line.
+/// Clearing the crate fingerprints makes the next run index every crate
+/// again, filling the table.
+pub const V18_SCHEMA: &str = r#"
+DROP TABLE IF EXISTS markers;
+CREATE TABLE markers (
+  file_id INTEGER NOT NULL,
+  line INTEGER NOT NULL,
+  marker TEXT NOT NULL,
+  message TEXT NOT NULL,
+  introduced INTEGER,
+  PRIMARY KEY (file_id, line)
+) WITHOUT ROWID;
+
+UPDATE crates SET fingerprint = '';
+"#;
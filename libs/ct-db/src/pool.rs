@@ -0,0 +1,157 @@
+//! Read-only connections for serving queries while the indexer writes.
+//! In WAL mode readers see the last committed state and never block on,
+//! or block, the single writer.
+
+use crate::Result;
+use rusqlite::{Connection, OpenFlags};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// SQLite virtual machine steps between calls of an `interrupt_when` check.
+pub const PROGRESS_STEPS: u64 = 1000;
+
+pub struct ReadPool {
+    path: PathBuf,
+    /// Connections kept open between queries; extra ones are closed on return.
+    max_idle: usize,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl ReadPool {
+    /// Connections are opened on demand; the database must already exist.
+    pub fn new(path: impl Into<PathBuf>, max_idle: usize) -> Self {
+        Self {
+            path: path.into(),
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// An idle connection, or a new one when all are in use.
+    pub fn get(&self) -> Result<PooledConnection<'_>> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self.open()?,
+        };
+        Ok(PooledConnection { pool: self, conn: Some(conn) })
+    }
+
+    /// Close the idle connections, freeing their page caches. Returns how
+    /// many were closed.
+    pub fn shed(&self) -> usize {
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap_or_else(|e| e.into_inner()));
+        idle.len()
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn open(&self) -> Result<Connection> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(crate::BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "mmap_size", 30000000)?;
+        crate::functions::register(&conn)?;
+        Ok(conn)
+    }
+
+    fn put_back(&self, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.max_idle {
+            idle.push(conn);
+        }
+    }
+}
+
+/// Returns its connection to the pool when dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a ReadPool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is only taken on drop")
+    }
+}
+
+impl PooledConnection<'_> {
+    /// Fail the statement running on this connection with `SQLITE_INTERRUPT`
+    /// once `cancelled` returns true. It is called every `PROGRESS_STEPS`
+    /// steps, so it can also tally the work done. Cleared when the
+    /// connection goes back to the pool.
+    pub fn interrupt_when<F>(&self, cancelled: F)
+    where
+        F: FnMut() -> bool + Send + std::panic::RefUnwindSafe + 'static,
+    {
+        self.progress_handler(PROGRESS_STEPS as i32, Some(cancelled));
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            conn.progress_handler(0, None::<fn() -> bool>);
+            self.pool.put_back(conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_pool() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let db = Database::open(temp.path())?;
+        let pool = ReadPool::new(temp.path(), 1);
+
+        {
+            let first = pool.get()?;
+            let second = pool.get()?;
+            let count: i64 = first.query_row("SELECT COUNT(*) FROM crates", [], |row| row.get(0))?;
+            assert_eq!(count, 0);
+            assert!(second.execute("INSERT INTO crates (name, fingerprint) VALUES ('x', 'fp')", []).is_err());
+        }
+        // Only `max_idle` connections are kept
+        assert_eq!(pool.idle_count(), 1);
+        assert_eq!(pool.shed(), 1);
+        assert_eq!(pool.idle_count(), 0);
+
+        // Readers see what the writer committed
+        db.insert_crate("written", None, "fp")?;
+        let count: i64 = pool.get()?.query_row("SELECT COUNT(*) FROM crates", [], |row| row.get(0))?;
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupt_when() -> Result<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let _db = Database::open(temp.path())?;
+        let pool = ReadPool::new(temp.path(), 1);
+        let count = "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100000) SELECT COUNT(*) FROM n";
+
+        {
+            let conn = pool.get()?;
+            conn.interrupt_when(|| true);
+            assert!(conn.query_row(count, [], |row| row.get::<_, i64>(0)).is_err());
+        }
+        // The returned connection no longer interrupts
+        let total: i64 = pool.get()?.query_row(count, [], |row| row.get(0))?;
+        assert_eq!(total, 100000);
+
+        Ok(())
+    }
+}
@@ -1,139 +1,511 @@
 use ct_core::models::*;
-use rusqlite::{params, Connection, Result as SqliteResult, OptionalExtension};
-use crate::Result;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result as SqliteResult, Row};
+use crate::functions::FUZZY_THRESHOLD;
+use crate::signature;
+use crate::{DbError, Result};
+use ct_core::compute_file_digest;
+use ct_protocol::StatusGroup;
+use std::path::Path;
+use std::str::FromStr;
 
+/// How `find_symbols_by_name` compares symbol names with the query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// The whole name, case-sensitive.
+    #[default]
+    Exact,
+    /// Names starting with the query, case-insensitive.
+    Prefix,
+    /// A shell-style pattern (`*`, `?`, `[...]`, `{a,b}`), case-sensitive.
+    Glob,
+    /// A regular expression found anywhere in the name.
+    Regex,
+    /// Names sharing enough trigrams with the query to survive typos.
+    Fuzzy,
+}
+
+impl FromStr for MatchMode {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "prefix" => Ok(Self::Prefix),
+            "glob" => Ok(Self::Glob),
+            "regex" => Ok(Self::Regex),
+            "fuzzy" => Ok(Self::Fuzzy),
+            other => Err(DbError::InvalidQuery(format!("unknown match mode: {}", other))),
+        }
+    }
+}
+
+/// Columns `symbol_from_row` reads, after a leading rowid.
+const SYMBOL_COLUMNS: &str = "id, symbol_id, crate_id, file_id, path, name, kind, visibility,
+                signature, docs, status, span_start, span_end, def_hash";
+
+/// Candidates `suggest_paths` ranks before keeping the best few.
+const SUGGESTION_POOL: usize = 50;
+
+#[allow(clippy::too_many_arguments)]
 pub fn find_symbols_by_name(
     conn: &Connection,
     name: &str,
+    mode: MatchMode,
     kind: Option<&str>,
     vis: Option<&str>,
     status: Option<&str>,
+    changed_since: Option<i64>,
+    after: Option<&Cursor>,
     limit: usize,
-) -> Result<Vec<Symbol>> {
-    let mut query = String::from(
-        "SELECT id, symbol_id, crate_id, file_id, path, name, kind, visibility,
-                signature, docs, status, span_start, span_end, def_hash
-         FROM symbols WHERE name = ?"
-    );
+) -> Result<Page<Symbol>> {
+    let mut filter = QueryFilter::new();
+    match mode {
+        MatchMode::Exact => filter.and("name = ?", [name.to_string()]),
+        MatchMode::Prefix => filter.and("name LIKE ? ESCAPE '\\'", [like_prefix(name)]),
+        MatchMode::Glob => {
+            crate::functions::path_glob(name).map_err(|e| DbError::InvalidQuery(e.to_string()))?;
+            // path_glob can't use the case-insensitive name index, but a
+            // LIKE on the pattern's literal start can
+            let literal = &name[..name.find(GLOB_CHARS).unwrap_or(name.len())];
+            if !literal.is_empty() {
+                filter.and("name LIKE ? ESCAPE '\\'", [like_prefix(literal)]);
+            }
+            filter.and("path_glob(?, name)", [name.to_string()])
+        }
+        MatchMode::Regex => {
+            // Fail up front rather than as an error from inside the query
+            regex::Regex::new(name).map_err(|e| DbError::InvalidQuery(e.to_string()))?;
+            filter.and("name REGEXP ?", [name.to_string()])
+        }
+        MatchMode::Fuzzy => filter
+            .and("trigram_similarity(name, ?) >= ?", [Value::from(name.to_string()), Value::from(FUZZY_THRESHOLD)]),
+    };
+    filter
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .eq("status", status)
+        .changed_since(changed_since)
+        .page(after, limit);
     
-    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(name.to_string())];
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
+}
+
+/// A `LIKE ... ESCAPE '\'` pattern for values starting with `prefix`.
+fn like_prefix(prefix: &str) -> String {
+    let escaped = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("{}%", escaped)
+}
+
+/// Characters that make a name or path a glob pattern.
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+
+/// Whether `pattern` is a glob rather than a plain name or path.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(GLOB_CHARS)
+}
+
+/// Symbols whose path matches the glob `pattern`, ordered by path; see
+/// `functions::path_glob` for the syntax. The segments before the first
+/// one with a wildcard narrow the scan to a range of `idx_symbols_path`.
+#[allow(clippy::too_many_arguments)]
+pub fn find_symbols_by_path_glob(
+    conn: &Connection,
+    pattern: &str,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    status: Option<&str>,
+    changed_since: Option<i64>,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<Symbol>> {
+    crate::functions::path_glob(pattern).map_err(|e| DbError::InvalidQuery(e.to_string()))?;
     
-    if let Some(k) = kind {
-        query.push_str(" AND kind = ?");
-        params.push(Box::new(k.to_string()));
+    let mut filter = QueryFilter::new();
+    let literal: Vec<&str> = pattern.split("::").take_while(|segment| !is_glob(segment)).collect();
+    if !literal.is_empty() {
+        let prefix = literal.join("::");
+        filter.and("(path >= ? AND path < ?)", [format!("{}::", prefix), format!("{}:;", prefix)]);
     }
+    filter
+        .and("path_glob(?, path)", [pattern.to_string()])
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .eq("status", status)
+        .changed_since(changed_since)
+        .page(after, limit);
     
-    if let Some(v) = vis {
-        if v != "all" {
-            query.push_str(" AND visibility = ?");
-            params.push(Box::new(v.to_string()));
-        }
-    }
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
+}
+
+pub fn find_symbol_by_path(
+    conn: &Connection,
+    path: &str,
+) -> Result<Option<Symbol>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM symbols WHERE path = ?", SYMBOL_COLUMNS))?;
+    let symbol = stmt.query_row(params![path], symbol_from_row).optional()?;
     
-    if let Some(s) = status {
-        query.push_str(" AND status = ?");
-        params.push(Box::new(s.to_string()));
+    Ok(symbol)
+}
+
+/// Row id of the symbol at `path`, preferring the one defined in `file_id`
+/// when several share the path.
+pub fn find_symbol_rowid(conn: &Connection, path: &str, file_id: i64) -> Result<Option<i64>> {
+    let id = conn
+        .query_row(
+            "SELECT id FROM symbols WHERE path = ? ORDER BY file_id = ? DESC, id LIMIT 1",
+            params![path, file_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    
+    Ok(id)
+}
+
+/// Up to `limit` symbols picked at random, for replaying realistic queries.
+pub fn sample_symbols(conn: &Connection, limit: usize) -> Result<Vec<Symbol>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM symbols ORDER BY random() LIMIT ?", SYMBOL_COLUMNS))?;
+    let symbols = stmt
+        .query_map(params![limit as i64], symbol_from_row)?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    
+    Ok(symbols)
+}
+
+/// The innermost symbol whose span contains `line` of `file`, a path
+/// relative to the workspace root as the indexer stores it. Spans nest, so
+/// the narrowest one wins; of equally narrow ones the one starting last.
+pub fn find_symbol_at(conn: &Connection, file: &str, line: u32) -> Result<Option<Symbol>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM symbols
+         WHERE file_id IN (SELECT id FROM files WHERE path = ?1)
+           AND span_start <= ?2 AND span_end >= ?2
+         ORDER BY span_end - span_start, span_start DESC, id
+         LIMIT 1",
+        SYMBOL_COLUMNS
+    ))?;
+    let symbol = stmt.query_row(params![file, line], symbol_from_row).optional()?;
+    
+    Ok(symbol)
+}
+
+/// `prefix` itself and the symbols nested under it (`prefix::*`), at most
+/// `max_depth` segments below `prefix` when given, ordered by path. The
+/// prefix match is a range scan on `idx_symbols_path` rather than a `LIKE`,
+/// which the case-insensitive default would keep off the index.
+#[allow(clippy::too_many_arguments)]
+pub fn find_symbols_by_path_prefix(
+    conn: &Connection,
+    prefix: &str,
+    max_depth: Option<usize>,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    status: Option<&str>,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<Symbol>> {
+    let prefix = prefix.trim_end_matches("::");
+    let mut filter = QueryFilter::new();
+    // ';' sorts right after ':', so [prefix::, prefix:;) holds every descendant
+    filter.and(
+        "(path = ? OR (path >= ? AND path < ?))",
+        [prefix.to_string(), format!("{}::", prefix), format!("{}:;", prefix)],
+    );
+    if let Some(depth) = max_depth {
+        filter.and(
+            "(LENGTH(path) - LENGTH(REPLACE(path, '::', ''))) / 2 <= ?",
+            [(prefix.matches("::").count() + depth) as i64],
+        );
     }
+    filter
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .eq("status", status)
+        .page(after, limit);
     
-    query.push_str(" ORDER BY name, path, span_start LIMIT ?");
-    params.push(Box::new(limit as i64));
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
+}
+
+/// Symbols at or under `prefix`, as `find_symbols_by_path_prefix`, that
+/// are of one of `kinds` (any when empty) or hold one that is, so the limit
+/// counts only what a tree of those kinds shows.
+pub fn find_tree_symbols(
+    conn: &Connection,
+    prefix: &str,
+    max_depth: Option<usize>,
+    kinds: &[String],
+    limit: usize,
+) -> Result<Page<Symbol>> {
+    let prefix = prefix.trim_end_matches("::");
+    let max_segments = max_depth.map(|depth| (prefix.matches("::").count() + depth) as i64);
+    let segments = |column: &str| format!("(LENGTH({0}) - LENGTH(REPLACE({0}, '::', ''))) / 2 <= ?", column);
+    let mut filter = QueryFilter::new();
+    filter.and(
+        "(path = ? OR (path >= ? AND path < ?))",
+        [prefix.to_string(), format!("{}::", prefix), format!("{}:;", prefix)],
+    );
+    if let Some(max) = max_segments {
+        filter.and(&segments("path"), [max]);
+    }
+    if !kinds.is_empty() {
+        let listed = vec!["?"; kinds.len()].join(", ");
+        let mut values: Vec<Value> = kinds.iter().chain(kinds).map(|kind| Value::from(kind.clone())).collect();
+        let nested_depth = match max_segments {
+            Some(max) => {
+                values.push(Value::from(max));
+                format!(" AND {}", segments("d.path"))
+            }
+            None => String::new(),
+        };
+        filter.and(
+            &format!(
+                "(kind IN ({0}) OR EXISTS (SELECT 1 FROM symbols d
+                   WHERE d.path >= symbols.path || '::' AND d.path < symbols.path || ':;'
+                   AND d.kind IN ({0}){1}))",
+                listed, nested_depth
+            ),
+            values,
+        );
+    }
+    filter.page(None, limit);
     
-    let mut stmt = conn.prepare(&query)?;
-    let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
+}
+
+/// Functions whose normalized signature matches `pattern`, e.g.
+/// `fn(&str) -> Result<_, _>`; see `signature::shape_glob` for the syntax.
+pub fn find_by_signature(
+    conn: &Connection,
+    pattern: &str,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    status: Option<&str>,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<Symbol>> {
+    let glob = signature::shape_glob(pattern).ok_or_else(|| {
+        DbError::InvalidQuery(format!("not a function signature: {} (expected e.g. fn(&str) -> bool)", pattern))
+    })?;
     
-    let symbols = stmt.query_map(&param_refs[..], |row| {
-        Ok(Symbol {
-            symbol_id: hex::encode(row.get::<_, Vec<u8>>(1)?),
-            crate_id: row.get(2)?,
-            file_id: row.get(3)?,
-            path: row.get(4)?,
-            name: row.get(5)?,
-            kind: parse_symbol_kind(&row.get::<_, String>(6)?),
-            visibility: parse_visibility(&row.get::<_, String>(7)?),
-            signature: row.get(8)?,
-            docs: row.get(9)?,
-            status: parse_status(&row.get::<_, String>(10)?),
-            span_start: row.get(11)?,
-            span_end: row.get(12)?,
-            def_hash: row.get(13)?,
-        })
-    })?
-    .collect::<SqliteResult<Vec<_>>>()?;
+    let mut filter = QueryFilter::new();
+    filter
+        .and("signature_shape GLOB ?", [glob])
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .eq("status", status)
+        .page(after, limit);
     
-    Ok(symbols)
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
 }
 
-pub fn find_symbol_by_path(
+/// Symbols carrying `tag`, ordered by path.
+pub fn find_symbols_by_tag(
     conn: &Connection,
-    path: &str,
-) -> Result<Option<Symbol>> {
+    tag: &str,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    status: Option<&str>,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<Symbol>> {
+    let mut filter = QueryFilter::new();
+    filter
+        .and(
+            "EXISTS (SELECT 1 FROM tags t WHERE t.tag = ? AND t.path = symbols.path AND t.kind = symbols.kind)",
+            [tag.to_string()],
+        )
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .eq("status", status)
+        .page(after, limit);
+    
+    let rows = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), |row| {
+        Ok((row.get(0)?, symbol_from_row(row)?))
+    })?;
+    Ok(into_page(rows, limit, |symbol| &symbol.path))
+}
+
+/// Tags on the symbol at `path`, by name.
+pub fn get_tags(conn: &Connection, path: &str) -> Result<Vec<Tag>> {
     let mut stmt = conn.prepare(
-        "SELECT id, symbol_id, crate_id, file_id, path, name, kind, visibility,
-                signature, docs, status, span_start, span_end, def_hash
-         FROM symbols WHERE path = ?"
+        "SELECT DISTINCT t.tag, t.note FROM tags t JOIN symbols s ON s.path = t.path AND s.kind = t.kind
+         WHERE t.path = ? ORDER BY t.tag"
     )?;
     
-    let symbol = stmt.query_row(params![path], |row| {
-        Ok(Symbol {
-            symbol_id: hex::encode(row.get::<_, Vec<u8>>(1)?),
-            crate_id: row.get(2)?,
-            file_id: row.get(3)?,
-            path: row.get(4)?,
-            name: row.get(5)?,
-            kind: parse_symbol_kind(&row.get::<_, String>(6)?),
-            visibility: parse_visibility(&row.get::<_, String>(7)?),
-            signature: row.get(8)?,
-            docs: row.get(9)?,
-            status: parse_status(&row.get::<_, String>(10)?),
-            span_start: row.get(11)?,
-            span_end: row.get(12)?,
-            def_hash: row.get(13)?,
+    let tags = stmt.query_map(params![path], |row| {
+        Ok(Tag {
+            tag: row.get(0)?,
+            note: row.get(1)?,
         })
-    })
-    .optional()?;
+    })?
+    .collect::<SqliteResult<Vec<_>>>()?;
     
-    Ok(symbol)
+    Ok(tags)
 }
 
 pub fn get_status_counts(
     conn: &Connection,
     vis: Option<&str>,
 ) -> Result<StatusCounts> {
-    let where_clause = match vis {
-        Some(v) if v != "all" => format!("WHERE visibility = '{}'", v),
-        _ => String::from("WHERE 1=1"),
-    };
+    let mut filter = QueryFilter::new();
+    filter.visibility("visibility", vis);
     
-    let total: usize = conn.query_row(
-        &format!("SELECT COUNT(*) FROM symbols {}", where_clause),
-        [],
-        |row| row.get(0),
+    let counts = filter.query(
+        conn,
+        "SELECT COUNT(*),
+                COALESCE(SUM(status = 'implemented'), 0),
+                COALESCE(SUM(status = 'unimplemented'), 0),
+                COALESCE(SUM(status = 'todo'), 0)
+         FROM symbols",
+        |row| {
+            Ok(StatusCounts {
+                total: row.get(0)?,
+                implemented: row.get(1)?,
+                unimplemented: row.get(2)?,
+                todo: row.get(3)?,
+            })
+        },
     )?;
     
-    let implemented: usize = conn.query_row(
-        &format!("SELECT COUNT(*) FROM symbols {} AND status = 'implemented'", where_clause),
-        [],
-        |row| row.get(0),
+    Ok(counts.into_iter().next().unwrap_or(StatusCounts {
+        total: 0,
+        implemented: 0,
+        unimplemented: 0,
+        todo: 0,
+    }))
+}
+
+/// The innermost module declaring `s`: the longest proper prefix of its path
+/// naming a module symbol, or its first segment (the crate) when none does.
+/// Ancestors sort before `s.path` and the longest of them last, so the
+/// descending index scan stops at it.
+const ENCLOSING_MODULE: &str = "COALESCE(
+    (SELECT m.path FROM symbols m
+     WHERE m.kind = 'module' AND m.path < s.path
+       AND s.path >= m.path || '::' AND s.path < m.path || ':;'
+     ORDER BY m.path DESC LIMIT 1),
+    CASE WHEN instr(s.path, '::') > 0 THEN substr(s.path, 1, instr(s.path, '::') - 1) ELSE s.path END)";
+
+/// Status counts per crate, module or kind, ordered by group name.
+pub fn get_status_groups(conn: &Connection, vis: Option<&str>, group_by: StatusGroup) -> Result<Vec<StatusGroupCounts>> {
+    let mut filter = QueryFilter::new();
+    filter.visibility("s.visibility", vis);
+    
+    let key = match group_by {
+        StatusGroup::Crate => "c.name",
+        StatusGroup::Kind => "s.kind",
+        StatusGroup::Module => ENCLOSING_MODULE,
+    };
+    filter.group_by("grp");
+    
+    filter.query(
+        conn,
+        &format!(
+            "SELECT {} AS grp,
+                    COUNT(*),
+                    COALESCE(SUM(s.status = 'implemented'), 0),
+                    COALESCE(SUM(s.status = 'unimplemented'), 0),
+                    COALESCE(SUM(s.status = 'todo'), 0)
+             FROM symbols s JOIN crates c ON c.id = s.crate_id",
+            key
+        ),
+        |row| {
+            Ok(StatusGroupCounts {
+                name: row.get(0)?,
+                counts: StatusCounts {
+                    total: row.get(1)?,
+                    implemented: row.get(2)?,
+                    unimplemented: row.get(3)?,
+                    todo: row.get(4)?,
+                },
+            })
+        },
+    )
+}
+
+/// Per-crate and workspace-wide counts by kind, visibility and status, plus
+/// doc coverage, from one grouped scan of `symbols`, and the `largest`
+/// largest files and functions.
+pub fn get_stats(conn: &Connection, largest: usize) -> Result<IndexSummary> {
+    let mut stmt = conn.prepare(
+        "SELECT c.name, s.kind, s.visibility, s.status, COUNT(*),
+                SUM(s.docs IS NOT NULL AND s.docs != '')
+         FROM symbols s JOIN crates c ON c.id = s.crate_id
+         GROUP BY c.name, s.kind, s.visibility, s.status
+         ORDER BY c.name"
     )?;
     
-    let unimplemented: usize = conn.query_row(
-        &format!("SELECT COUNT(*) FROM symbols {} AND status = 'unimplemented'", where_clause),
-        [],
-        |row| row.get(0),
+    let mut summary = IndexSummary::default();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(0)?;
+        let kind: String = row.get(1)?;
+        let visibility: String = row.get(2)?;
+        let status: String = row.get(3)?;
+        let count: usize = row.get(4)?;
+        let documented: usize = row.get(5)?;
+        
+        if summary.crates.last().is_none_or(|c| c.name != name) {
+            summary.crates.push(CrateSymbolStats { name, stats: SymbolStats::default() });
+        }
+        let crate_stats = &mut summary.crates.last_mut().expect("pushed above").stats;
+        for stats in [&mut summary.totals, crate_stats] {
+            stats.total += count;
+            stats.documented += documented;
+            *stats.by_kind.entry(kind.clone()).or_default() += count;
+            *stats.by_visibility.entry(visibility.clone()).or_default() += count;
+            *stats.by_status.entry(status.clone()).or_default() += count;
+        }
+    }
+    
+    for stats in std::iter::once(&mut summary.totals).chain(summary.crates.iter_mut().map(|c| &mut c.stats)) {
+        if stats.total > 0 {
+            stats.doc_coverage = stats.documented as f64 / stats.total as f64;
+        }
+    }
+    
+    let mut stmt = conn.prepare(
+        "SELECT f.path, c.name, COUNT(*), MAX(s.span_end) AS lines
+         FROM symbols s JOIN files f ON f.id = s.file_id JOIN crates c ON c.id = f.crate_id
+         GROUP BY f.id
+         ORDER BY lines DESC, f.path
+         LIMIT ?"
     )?;
+    summary.largest_files = stmt
+        .query_map(params![largest as i64], |row| {
+            Ok(FileSize { path: row.get(0)?, crate_name: row.get(1)?, symbols: row.get(2)?, lines: row.get(3)? })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
     
-    let todo: usize = conn.query_row(
-        &format!("SELECT COUNT(*) FROM symbols {} AND status = 'todo'", where_clause),
-        [],
-        |row| row.get(0),
+    let mut stmt = conn.prepare(
+        "SELECT s.path, f.path, s.span_start, s.span_end - s.span_start + 1 AS lines
+         FROM symbols s JOIN files f ON f.id = s.file_id
+         WHERE s.kind IN ('fn', 'method')
+         ORDER BY lines DESC, s.path
+         LIMIT ?"
     )?;
+    summary.largest_fns = stmt
+        .query_map(params![largest as i64], |row| {
+            Ok(FnSize { path: row.get(0)?, file: row.get(1)?, span_start: row.get(2)?, lines: row.get(3)? })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
     
-    Ok(StatusCounts {
-        total,
-        implemented,
-        unimplemented,
-        todo,
-    })
+    Ok(summary)
 }
 
 pub fn get_status_items(
@@ -141,45 +513,979 @@ pub fn get_status_items(
     vis: Option<&str>,
     unimplemented: bool,
     todo: bool,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<StatusItem>> {
+    let mut filter = QueryFilter::new();
+    filter.visibility("visibility", vis);
+    if unimplemented && !todo {
+        filter.eq("status", Some("unimplemented"));
+    } else if todo && !unimplemented {
+        filter.eq("status", Some("todo"));
+    } else if unimplemented && todo {
+        filter.and("status IN (?, ?)", ["unimplemented".to_string(), "todo".to_string()]);
+    }
+    filter.page(after, limit);
+    
+    let rows = filter.query(conn, "SELECT id, path, status, kind FROM symbols", |row| {
+        Ok((row.get(0)?, StatusItem {
+            path: row.get(1)?,
+            status: parse_status(&row.get::<_, String>(2)?),
+            kind: parse_symbol_kind(&row.get::<_, String>(3)?),
+        }))
+    })?;
+    Ok(into_page(rows, limit, |item| &item.path))
+}
+
+pub fn get_hotspots(
+    conn: &Connection,
+    vis: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Hotspot>> {
+    let mut filter = QueryFilter::new();
+    filter
+        .visibility("s.visibility", vis)
+        .order_by("h.churn DESC, h.last_modified DESC, s.path", limit);
+    
+    filter.query(
+        conn,
+        "SELECT s.path, s.kind, h.churn, h.last_commit, h.last_modified
+         FROM symbol_history h JOIN symbols s ON s.symbol_id = h.symbol_id",
+        |row| {
+            Ok(Hotspot {
+                path: row.get(0)?,
+                kind: parse_symbol_kind(&row.get::<_, String>(1)?),
+                churn: row.get(2)?,
+                last_commit: row.get(3)?,
+                last_modified: row.get(4)?,
+            })
+        },
+    )
+}
+
+/// Impls of the trait `trait_path` ("all implementors of X"), by type.
+pub fn find_trait_implementors(conn: &Connection, trait_path: &str, limit: usize) -> Result<Vec<TraitImpl>> {
+    let mut filter = QueryFilter::new();
+    filter.names_path("t.trait_path", trait_path);
+    query_trait_impls(conn, filter, "t.for_path", limit)
+}
+
+/// Traits implemented by the type `for_path` ("all traits of Y"), by trait.
+/// Unless `external`, only traits the index defines: not `Clone` or
+/// `serde::Serialize`, but a workspace trait however its impl spelled it.
+pub fn find_implemented_traits(conn: &Connection, for_path: &str, external: bool, limit: usize) -> Result<Vec<TraitImpl>> {
+    let mut filter = QueryFilter::new();
+    filter.names_path("t.for_path", for_path);
+    if !external {
+        filter.and(
+            "EXISTS (SELECT 1 FROM symbols s WHERE s.kind = ?
+                 AND (s.path = t.trait_path OR substr(s.path, -length(t.trait_path) - 2) = '::' || t.trait_path))",
+            [SymbolKind::Trait.as_str().to_string()],
+        );
+    }
+    query_trait_impls(conn, filter, "t.trait_path", limit)
+}
+
+/// `trait_impls` rows passing `filter`. Paths are recorded as written where
+/// they could not be resolved, so either side may be the shorter one.
+fn query_trait_impls(conn: &Connection, mut filter: QueryFilter, order: &str, limit: usize) -> Result<Vec<TraitImpl>> {
+    filter.order_by(&format!("{}, f.path, i.line_start", order), limit);
+    
+    filter.query(
+        conn,
+        "SELECT t.trait_path, t.for_path, f.path, i.line_start, i.line_end
+         FROM trait_impls t
+         JOIN impls i ON i.id = t.impl_id
+         JOIN files f ON f.id = i.file_id",
+        |row| {
+            Ok(TraitImpl {
+                trait_path: row.get(0)?,
+                for_path: row.get(1)?,
+                file: row.get(2)?,
+                line_start: row.get(3)?,
+                line_end: row.get(4)?,
+            })
+        },
+    )
+}
+
+/// References to the symbol at `path`, ordered by file, line and column. Targets
+/// that could not be resolved are recorded as written, so like trait paths
+/// either side may be the shorter one.
+pub fn find_references(conn: &Connection, path: &str, limit: usize) -> Result<Vec<ReferenceSite>> {
+    let mut filter = QueryFilter::new();
+    filter
+        .names_path("r.target_path", path)
+        .order_by("f.path, r.span_start, r.span_col, s.path", limit);
+    
+    filter.query(
+        conn,
+        "SELECT s.path, r.target_path, c.name, f.path, r.span_start, r.span_end, r.span_col
+         FROM symbol_references r
+         JOIN symbols s ON s.id = r.symbol_id
+         JOIN files f ON f.id = r.file_id
+         JOIN crates c ON c.id = f.crate_id",
+        |row| {
+            Ok(ReferenceSite {
+                from_path: row.get(0)?,
+                target_path: row.get(1)?,
+                crate_name: row.get(2)?,
+                file: row.get(3)?,
+                span_start: row.get(4)?,
+                span_end: row.get(5)?,
+                span_col: row.get(6)?,
+            })
+        },
+    )
+}
+
+/// Name of the crate a file was indexed under or is an input of, matched on
+/// its stored workspace-relative path.
+pub fn find_crate_for_file(conn: &Connection, path: &str) -> Result<Option<String>> {
+    let name = conn.query_row(
+        "SELECT c.name FROM files f JOIN crates c ON f.crate_id = c.id WHERE f.path = ?1
+         UNION ALL
+         SELECT c.name FROM crate_inputs i JOIN crates c ON i.crate_id = c.id WHERE i.path = ?1
+         LIMIT 1",
+        params![path],
+        |row| row.get(0),
+    )
+    .optional()?;
+    
+    Ok(name)
+}
+
+/// Symbols whose name, signature or docs contain every word of `query`,
+/// best match first, after skipping the first `offset`. Words are matched
+/// as FTS5 tokens, so `backpressure strategy` finds docs mentioning both
+/// regardless of order; operators in the input are taken literally.
+pub fn search_fulltext(
+    conn: &Connection,
+    query: &str,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    let Some(terms) = fts_terms(query) else {
+        return Ok(vec![]);
+    };
+    
+    let mut filter = QueryFilter::new();
+    filter
+        .and("symbols_fts MATCH ?", [terms])
+        .eq("s.kind", kind)
+        .visibility("s.visibility", vis)
+        .order_by("score, s.path", limit)
+        .offset(offset);
+    
+    // Name matches outweigh signature matches, which outweigh prose
+    filter.query(
+        conn,
+        "SELECT s.id, s.symbol_id, s.crate_id, s.file_id, s.path, s.name, s.kind, s.visibility,
+                s.signature, s.docs, s.status, s.span_start, s.span_end, s.def_hash,
+                bm25(symbols_fts, 10.0, 3.0, 1.0) AS score
+         FROM symbols_fts JOIN symbols s ON s.id = symbols_fts.rowid",
+        |row| {
+            Ok(SearchHit {
+                symbol: symbol_from_row(row)?,
+                score: row.get(14)?,
+            })
+        },
+    )
+}
+
+/// Symbols whose path holds the characters of `query` in order, best
+/// match first by skim's scoring, which favours runs and word starts such
+/// as segment and camel-case boundaries; shorter paths break ties.
+pub fn fuzzy_find_symbols(
+    conn: &Connection,
+    query: &str,
+    kind: Option<&str>,
+    vis: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<FuzzyHit>> {
+    let mut filter = QueryFilter::new();
+    // The score is taken once per symbol, with the query as ?1, so it binds
+    // first; materialized, as a flattened subquery would score again for
+    // each use of `score`
+    filter
+        .and("score IS NOT NULL", [query.to_string()])
+        .eq("kind", kind)
+        .visibility("visibility", vis)
+        .order_by("score DESC, length(path), path", limit)
+        .offset(offset);
+    
+    let score_column = SYMBOL_COLUMNS.split(',').count();
+    filter.query(
+        conn,
+        &format!(
+            "WITH scored AS MATERIALIZED (SELECT {}, fuzzy_score(?1, path) AS score FROM symbols)
+             SELECT * FROM scored",
+            SYMBOL_COLUMNS
+        ),
+        |row| {
+            Ok(FuzzyHit {
+                symbol: symbol_from_row(row)?,
+                score: row.get(score_column)?,
+            })
+        },
+    )
+}
+
+/// Marks around the matched words in `DocHit::snippet`.
+pub const HIGHLIGHT_START: &str = "**";
+pub const HIGHLIGHT_END: &str = "**";
+
+/// Symbols whose docs contain every word of `query`, best match first, with
+/// an excerpt of the docs around the matches.
+pub fn search_docs(conn: &Connection, query: &str, limit: usize) -> Result<Vec<DocHit>> {
+    let Some(terms) = fts_terms(query) else {
+        return Ok(vec![]);
+    };
+    
+    let mut filter = QueryFilter::new();
+    filter
+        .and("symbols_fts MATCH ?", [format!("docs : ({})", terms)])
+        .order_by("score, s.path", limit);
+    
+    filter.query(
+        conn,
+        &format!(
+            "SELECT s.path, s.kind, snippet(symbols_fts, 2, '{}', '{}', '...', 24), bm25(symbols_fts) AS score
+             FROM symbols_fts JOIN symbols s ON s.id = symbols_fts.rowid",
+            HIGHLIGHT_START, HIGHLIGHT_END
+        ),
+        |row| {
+            Ok(DocHit {
+                path: row.get(0)?,
+                kind: parse_symbol_kind(&row.get::<_, String>(1)?),
+                snippet: row.get(2)?,
+                score: row.get(3)?,
+            })
+        },
+    )
+}
+
+/// What of a symbol `grep_symbols` looks in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolField {
+    Name,
+    Signature,
+    Docs,
+}
+
+impl SymbolField {
+    pub const ALL: [SymbolField; 3] = [SymbolField::Name, SymbolField::Signature, SymbolField::Docs];
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Signature => "sig",
+            Self::Docs => "docs",
+        }
+    }
+    
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Signature => "signature",
+            Self::Docs => "docs",
+        }
+    }
+    
+    fn of<'a>(&self, symbol: &'a Symbol) -> &'a str {
+        match self {
+            Self::Name => &symbol.name,
+            Self::Signature => &symbol.signature,
+            Self::Docs => symbol.docs.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+/// Characters of context `grep_symbols` keeps on each side of a match.
+const GREP_CONTEXT: usize = 40;
+
+/// Symbols whose `fields` contain `pattern`, case-sensitively as written
+/// or as a regex, by path. Each hit is the first of `fields` that matched,
+/// with the line it matched on.
+pub fn grep_symbols(
+    conn: &Connection,
+    pattern: &str,
+    regex: bool,
+    fields: &[SymbolField],
     limit: usize,
-) -> Result<Vec<StatusItem>> {
-    let mut query = String::from(
-        "SELECT path, status, kind FROM symbols WHERE 1=1"
+) -> Result<Vec<GrepHit>> {
+    if pattern.is_empty() {
+        return Err(DbError::InvalidQuery("grep pattern is empty".to_string()));
+    }
+    let fields = if fields.is_empty() { &SymbolField::ALL[..] } else { fields };
+    // Fail up front rather than as an error from inside the query; the same
+    // matcher then finds what to highlight
+    let source = if regex { pattern.to_string() } else { regex::escape(pattern) };
+    let matcher = regex::Regex::new(&source).map_err(|e| DbError::InvalidQuery(e.to_string()))?;
+    
+    let conditions: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if regex {
+                format!("coalesce({}, '') REGEXP ?", field.column())
+            } else {
+                format!("instr({}, ?) > 0", field.column())
+            }
+        })
+        .collect();
+    let mut filter = QueryFilter::new();
+    filter
+        .and(&format!("({})", conditions.join(" OR ")), fields.iter().map(|_| pattern.to_string()))
+        .order_by("path, id", limit);
+    let symbols = filter.query(conn, &format!("SELECT {} FROM symbols", SYMBOL_COLUMNS), symbol_from_row)?;
+    
+    Ok(symbols
+        .into_iter()
+        .filter_map(|symbol| {
+            let (field, found) = fields
+                .iter()
+                .find_map(|field| matcher.find(field.of(&symbol)).map(|found| (field, found.range())))?;
+            Some(GrepHit {
+                snippet: excerpt(field.of(&symbol), found),
+                field: field.as_str().to_string(),
+                path: symbol.path,
+                kind: symbol.kind,
+                span_start: symbol.span_start,
+                span_end: symbol.span_end,
+            })
+        })
+        .collect())
+}
+
+/// The line of `text` that `found` starts on, with the match highlighted
+/// and at most `GREP_CONTEXT` characters kept on either side of it.
+fn excerpt(text: &str, found: std::ops::Range<usize>) -> String {
+    let line_start = text[..found.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[found.start..].find('\n').map_or(text.len(), |i| found.start + i);
+    let end = found.end.min(line_end);
+    
+    let before = text[line_start..found.start].trim_start();
+    let after = text[end..line_end].trim_end();
+    let before = match before.char_indices().rev().nth(GREP_CONTEXT) {
+        Some((cut, c)) => format!("...{}", before[cut + c.len_utf8()..].trim_start()),
+        None => before.to_string(),
+    };
+    let after = match after.char_indices().nth(GREP_CONTEXT) {
+        Some((cut, _)) => format!("{}...", after[..cut].trim_end()),
+        None => after.to_string(),
+    };
+    format!("{}{}{}{}{}", before, HIGHLIGHT_START, &text[found.start..end], HIGHLIGHT_END, after)
+}
+
+/// Each word of `query` as a quoted FTS5 string, so operators in it are
+/// taken literally; `None` if there are no words.
+fn fts_terms(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Call sites whose callee is `callee_path`, ordered by caller. Callees
+/// are recorded as written where they could not be resolved, and match
+/// like trait paths.
+pub fn find_callers(conn: &Connection, callee_path: &str, limit: usize) -> Result<Vec<CallSite>> {
+    let mut filter = QueryFilter::new();
+    filter.names_path("c.callee_path", callee_path);
+    query_calls(conn, filter, limit)
+}
+
+/// Call sites in the body of the symbol at `caller_path`, in source order.
+pub fn find_callees(conn: &Connection, caller_path: &str, limit: usize) -> Result<Vec<CallSite>> {
+    let mut filter = QueryFilter::new();
+    filter.and("s.path = ?", [caller_path.to_string()]);
+    query_calls(conn, filter, limit)
+}
+
+fn query_calls(conn: &Connection, mut filter: QueryFilter, limit: usize) -> Result<Vec<CallSite>> {
+    filter.order_by("s.path, f.path, c.span_start", limit);
+    filter.query(
+        conn,
+        "SELECT s.path, c.callee_path, f.path, c.span_start, c.span_end
+         FROM calls c
+         JOIN symbols s ON s.symbol_id = c.caller_symbol_id
+         JOIN files f ON f.id = c.file_id",
+        |row| {
+            Ok(CallSite {
+                caller_path: row.get(0)?,
+                callee_path: row.get(1)?,
+                file: row.get(2)?,
+                span_start: row.get(3)?,
+                span_end: row.get(4)?,
+            })
+        },
+    )
+}
+
+/// Which way `call_graph` walks from its root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallDirection {
+    Callers,
+    Callees,
+}
+
+/// Call sites reachable from `path` within `depth` hops, breadth first,
+/// each with the hop it was found at (1 for direct callers or callees).
+/// Every symbol is expanded once, and the walk stops after `limit` sites.
+pub fn call_graph(
+    conn: &Connection,
+    path: &str,
+    direction: CallDirection,
+    depth: usize,
+    limit: usize,
+) -> Result<Vec<(usize, CallSite)>> {
+    let mut sites = Vec::new();
+    let mut seen = std::collections::HashSet::from([path.to_string()]);
+    let mut frontier = vec![path.to_string()];
+    for hop in 1..=depth {
+        let mut next = Vec::new();
+        for node in &frontier {
+            let remaining = limit - sites.len();
+            let found = match direction {
+                CallDirection::Callers => find_callers(conn, node, remaining)?,
+                CallDirection::Callees => find_callees(conn, node, remaining)?,
+            };
+            for site in found {
+                let neighbour = match direction {
+                    CallDirection::Callers => &site.caller_path,
+                    CallDirection::Callees => &site.callee_path,
+                };
+                if seen.insert(neighbour.clone()) {
+                    next.push(neighbour.clone());
+                }
+                sites.push((hop, site));
+            }
+            if sites.len() >= limit {
+                return Ok(sites);
+            }
+        }
+        frontier = next;
+    }
+    
+    Ok(sites)
+}
+
+/// Which way `dependency_graph` walks from its crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepDirection {
+    Dependencies,
+    Dependents,
+}
+
+/// Dependency edges reachable from `crate_name` within `depth` steps,
+/// breadth first, or every recorded edge when no crate is given. Only
+/// indexed crates have edges of their own, so walks stop at external
+/// crates. Names match with `-` and `_` taken as the same.
+pub fn dependency_graph(
+    conn: &Connection,
+    crate_name: Option<&str>,
+    direction: DepDirection,
+    depth: usize,
+    limit: usize,
+) -> Result<Vec<DepEdge>> {
+    let Some(crate_name) = crate_name else {
+        return query_deps(conn, QueryFilter::new(), 1, limit);
+    };
+    
+    let column = match direction {
+        DepDirection::Dependencies => "c.name",
+        DepDirection::Dependents => "d.dep_name",
+    };
+    let mut edges = Vec::new();
+    let mut seen = std::collections::HashSet::from([crate_name.replace('-', "_")]);
+    let mut frontier = vec![crate_name.to_string()];
+    for hop in 1..=depth {
+        let mut next = Vec::new();
+        for node in &frontier {
+            let mut filter = QueryFilter::new();
+            filter.and(&format!("REPLACE({}, '-', '_') = ?", column), [node.replace('-', "_")]);
+            for edge in query_deps(conn, filter, hop, limit - edges.len())? {
+                let neighbour = match direction {
+                    DepDirection::Dependencies => &edge.to,
+                    DepDirection::Dependents => &edge.from,
+                };
+                if seen.insert(neighbour.replace('-', "_")) {
+                    next.push(neighbour.clone());
+                }
+                edges.push(edge);
+            }
+            if edges.len() >= limit {
+                return Ok(edges);
+            }
+        }
+        frontier = next;
+    }
+    
+    Ok(edges)
+}
+
+fn query_deps(conn: &Connection, mut filter: QueryFilter, depth: usize, limit: usize) -> Result<Vec<DepEdge>> {
+    filter.order_by("c.name, d.dep_name, d.kind", limit);
+    filter.query(
+        conn,
+        "SELECT c.name, d.dep_name, d.kind, d.req, d.optional
+         FROM crate_deps d JOIN crates c ON c.id = d.crate_id",
+        |row| {
+            Ok(DepEdge {
+                from: row.get(0)?,
+                to: row.get(1)?,
+                kind: row.get(2)?,
+                req: row.get(3)?,
+                optional: row.get(4)?,
+                depth,
+            })
+        },
+    )
+}
+
+/// Workspace-relative paths of every recorded crate input.
+pub fn get_input_paths(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT path FROM crate_inputs ORDER BY path")?;
+    
+    let paths = stmt.query_map([], |row| row.get(0))?
+        .collect::<SqliteResult<Vec<String>>>()?;
+    
+    Ok(paths)
+}
+
+/// Symbols added, removed or changed since a baseline, ordered by path.
+/// Without a snapshot the baseline is the previous epoch of the crates and
+/// files the last index run replaced, saved in `epoch_baseline`; otherwise
+/// `snapshot` is another index file, attached for the duration of the query. Changes are detected by
+/// `def_hash` and visibility. With `breaking_only`, only changes to the baseline's public
+/// API that can break its users are kept: public symbols removed, or whose
+/// signature changed or that are no longer public.
+pub fn diff_symbols(
+    conn: &Connection,
+    snapshot: Option<&Path>,
+    breaking_only: bool,
+    limit: usize,
+) -> Result<Vec<SymbolChange>> {
+    let Some(snapshot) = snapshot else {
+        return query_diff(conn, EPOCH_SCOPE_SYMBOLS, "main.epoch_baseline", breaking_only, limit);
+    };
+    if !snapshot.is_file() {
+        return Err(DbError::InvalidQuery(format!("no snapshot at {}", snapshot.display())));
+    }
+    
+    conn.execute("ATTACH DATABASE ? AS baseline", params![snapshot.to_string_lossy()])?;
+    let has_symbols = conn
+        .query_row(
+            "SELECT 1 FROM baseline.sqlite_master WHERE type = 'table' AND name = 'symbols'",
+            [],
+            |_| Ok(()),
+        )
+        .optional();
+    let diff = match has_symbols {
+        Ok(Some(())) => query_diff(conn, "main.symbols", "baseline.symbols", breaking_only, limit),
+        Ok(None) => Err(DbError::InvalidQuery(format!(
+            "{} is not a ct index",
+            snapshot.display()
+        ))),
+        Err(e) => Err(e.into()),
+    };
+    conn.execute("DETACH DATABASE baseline", [])?;
+    diff
+}
+
+/// The current symbols of the crates and files in `epoch_scope`.
+const EPOCH_SCOPE_SYMBOLS: &str = "(SELECT * FROM main.symbols
+    WHERE crate_id IN (SELECT c.id FROM crates c JOIN epoch_scope e ON e.crate_name = c.name AND e.file IS NULL)
+       OR file_id IN (SELECT f.id FROM files f JOIN epoch_scope e ON e.file = f.path))";
+
+/// Changes from the symbols in `baseline` to those in `current`, each a
+/// table or subquery.
+fn query_diff(conn: &Connection, current: &str, baseline: &str, breaking_only: bool, limit: usize) -> Result<Vec<SymbolChange>> {
+    // Additions never break anything; the rest must have been public
+    let (added, removed, changed) = if breaking_only {
+        (
+            "0",
+            "b.visibility = 'public'",
+            "EXISTS (SELECT 1 FROM {b} b WHERE b.path = s.path AND b.kind = s.kind AND b.visibility = 'public'
+                            AND (b.signature <> s.signature OR s.visibility <> 'public'))",
+        )
+    } else {
+        ("1", "1", "1")
+    };
+    let sql = format!(
+        "SELECT 'added', s.path, s.kind, s.visibility, s.signature, NULL FROM {s} s
+         WHERE NOT EXISTS (SELECT 1 FROM {b} b WHERE b.path = s.path AND b.kind = s.kind) AND {added}
+         UNION ALL
+         SELECT 'removed', b.path, b.kind, b.visibility, b.signature, NULL FROM {b} b
+         WHERE NOT EXISTS (SELECT 1 FROM {s} s WHERE s.path = b.path AND s.kind = b.kind) AND {removed}
+         UNION ALL
+         SELECT 'changed', s.path, s.kind, s.visibility, s.signature,
+                (SELECT b.signature FROM {b} b WHERE b.path = s.path AND b.kind = s.kind LIMIT 1)
+         FROM {s} s
+         WHERE EXISTS (SELECT 1 FROM {b} b WHERE b.path = s.path AND b.kind = s.kind)
+           AND NOT EXISTS (SELECT 1 FROM {b} b
+                           WHERE b.path = s.path AND b.kind = s.kind AND b.def_hash = s.def_hash
+                             AND b.visibility = s.visibility)
+           AND {changed}
+         ORDER BY 2, 3, 1
+         LIMIT ?",
+        changed = changed.replace("{b}", baseline),
+        b = baseline,
+        s = current,
     );
+    let mut stmt = conn.prepare(&sql)?;
+    let changes = stmt
+        .query_map(params![limit as i64], |row| {
+            let change = match row.get_ref(0)?.as_str()? {
+                "added" => ChangeKind::Added,
+                "removed" => ChangeKind::Removed,
+                _ => ChangeKind::Changed,
+            };
+            Ok(SymbolChange {
+                change,
+                path: row.get(1)?,
+                kind: row.get(2)?,
+                visibility: row.get(3)?,
+                signature: row.get(4)?,
+                previous_signature: row.get(5)?,
+            })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
     
-    if let Some(v) = vis {
-        if v != "all" {
-            query.push_str(&format!(" AND visibility = '{}'", v));
+    Ok(changes)
+}
+
+/// Paths a mistyped `path` may have meant: symbols with the same final name,
+/// or failing that a similar one, sharing the longest prefix with `path` first.
+pub fn suggest_paths(conn: &Connection, path: &str, limit: usize) -> Result<Vec<String>> {
+    let name = path.rsplit("::").next().unwrap_or(path);
+    if name.is_empty() || limit == 0 {
+        return Ok(Vec::new());
+    }
+    
+    for mode in [MatchMode::Exact, MatchMode::Fuzzy] {
+        let page = find_symbols_by_name(conn, name, mode, None, None, None, None, None, SUGGESTION_POOL)?;
+        if page.items.is_empty() {
+            continue;
         }
+        let shared = |candidate: &str| candidate.chars().zip(path.chars()).take_while(|(a, b)| a == b).count();
+        let mut paths: Vec<String> = page.items.into_iter().map(|s| s.path).collect();
+        // The path itself is part of the key, so equal paths end up adjacent
+        paths.sort_by_cached_key(|candidate| (std::cmp::Reverse(shared(candidate)), candidate.clone()));
+        paths.dedup();
+        paths.truncate(limit);
+        return Ok(paths);
     }
+    Ok(Vec::new())
+}
+
+/// The epoch `epoch_baseline` was saved from, if any index run saved one.
+pub fn get_baseline_epoch(conn: &Connection) -> Result<Option<u64>> {
+    let epoch: Option<String> = conn
+        .query_row("SELECT val FROM meta WHERE key = 'baseline_epoch'", [], |row| row.get(0))
+        .optional()?;
     
-    if unimplemented && !todo {
-        query.push_str(" AND status = 'unimplemented'");
-    } else if todo && !unimplemented {
-        query.push_str(" AND status = 'todo'");
-    } else if unimplemented && todo {
-        query.push_str(" AND (status = 'unimplemented' OR status = 'todo')");
+    Ok(epoch.and_then(|e| e.parse().ok()))
+}
+
+/// How many times the index was written, 0 before the first index run.
+pub fn get_index_epoch(conn: &Connection) -> Result<u64> {
+    let epoch: Option<String> = conn
+        .query_row("SELECT val FROM meta WHERE key = 'index_epoch'", [], |row| row.get(0))
+        .optional()?;
+    
+    Ok(epoch.and_then(|e| e.parse().ok()).unwrap_or(0))
+}
+
+pub fn get_file(conn: &Connection, file_id: i64) -> Result<Option<File>> {
+    let file = conn
+        .query_row(
+            "SELECT id, crate_id, path, digest FROM files WHERE id = ?",
+            params![file_id],
+            |row| {
+                Ok(File {
+                    id: row.get(0)?,
+                    crate_id: row.get(1)?,
+                    path: row.get(2)?,
+                    digest: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+    
+    Ok(file)
+}
+
+/// Re-hash every indexed file under `workspace_root` and report the crates
+/// whose files changed or disappeared since they were indexed.
+pub fn verify_files(conn: &Connection, workspace_root: &Path) -> Result<VerifyReport> {
+    let mut stmt = conn.prepare(
+        "SELECT c.name, f.path, f.digest FROM files f JOIN crates c ON c.id = f.crate_id
+         ORDER BY c.name, f.path"
+    )?;
+    let files = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    
+    let mut report = VerifyReport { files_checked: files.len(), stale_crates: Vec::new() };
+    for (crate_name, path, digest) in files {
+        let (changed, missing) = match std::fs::read(workspace_root.join(&path)) {
+            Ok(content) => (compute_file_digest(&content) != digest, false),
+            // The indexer records files it could not find as "missing"
+            Err(_) => (false, digest != "missing"),
+        };
+        if !changed && !missing {
+            continue;
+        }
+        
+        if report.stale_crates.last().is_none_or(|c| c.name != crate_name) {
+            report.stale_crates.push(StaleCrate {
+                name: crate_name,
+                changed: Vec::new(),
+                missing: Vec::new(),
+            });
+        }
+        let stale = report.stale_crates.last_mut().expect("pushed above");
+        if changed {
+            stale.changed.push(path);
+        } else {
+            stale.missing.push(path);
+        }
     }
     
-    query.push_str(&format!(" ORDER BY path LIMIT {}", limit));
+    Ok(report)
+}
+
+/// Cached text of a span, if it was sliced from content with `file_digest`.
+pub fn get_snippet(conn: &Connection, file_digest: &str, span_start: u32, span_end: u32) -> Result<Option<String>> {
+    let text = conn
+        .prepare_cached(
+            "SELECT text FROM source_snippets
+             WHERE file_digest = ? AND span_start = ? AND span_end = ?",
+        )?
+        .query_row(params![file_digest, span_start, span_end], |row| row.get(0))
+        .optional()?;
     
-    let mut stmt = conn.prepare(&query)?;
-    let items = stmt.query_map([], |row| {
-        Ok(StatusItem {
-            path: row.get(0)?,
-            status: parse_status(&row.get::<_, String>(1)?),
-            kind: parse_symbol_kind(&row.get::<_, String>(2)?),
+    Ok(text)
+}
+
+/// The doc examples of a symbol, by its `symbol_id` as symbols read from
+/// the index carry it: hex-encoded.
+pub fn get_examples(conn: &Connection, symbol_id: &str) -> Result<Vec<DocExample>> {
+    let key = hex::decode(symbol_id)
+        .map_err(|e| DbError::InvalidQuery(format!("Invalid symbol id {}: {}", symbol_id, e)))?;
+    let mut stmt = conn.prepare(
+        "SELECT lang, code FROM examples WHERE symbol_id = ? ORDER BY idx"
+    )?;
+    
+    let examples = stmt.query_map(params![key], |row| {
+        Ok(DocExample {
+            lang: row.get(0)?,
+            code: row.get(1)?,
         })
     })?
     .collect::<SqliteResult<Vec<_>>>()?;
     
-    Ok(items)
+    Ok(examples)
+}
+
+/// Markers with the innermost symbol whose span holds them, or the crate
+/// when none does, by file and line; only those whose marker or message
+/// contains `matching`, ignoring ASCII case, when it is given.
+pub fn get_markers(conn: &Connection, matching: Option<&str>, limit: usize) -> Result<Vec<MarkerSite>> {
+    let mut filter = QueryFilter::new();
+    if let Some(matching) = matching {
+        filter.and("instr(lower(m.marker || ' ' || m.message), lower(?)) > 0", [matching.to_string()]);
+    }
+    filter.order_by("f.path, m.line", limit);
+    filter.query(
+        conn,
+        "SELECT COALESCE(
+                    (SELECT s.path FROM symbols s
+                     WHERE s.file_id = m.file_id AND s.span_start <= m.line AND s.span_end >= m.line
+                     ORDER BY s.span_end - s.span_start, s.span_start DESC
+                     LIMIT 1),
+                    c.name),
+                f.path, m.marker, m.message, m.line, m.introduced
+         FROM markers m
+         JOIN files f ON f.id = m.file_id
+         JOIN crates c ON c.id = f.crate_id",
+        |row| {
+            Ok(MarkerSite {
+                path: row.get(0)?,
+                file: row.get(1)?,
+                marker: Marker {
+                    marker: row.get(2)?,
+                    message: row.get(3)?,
+                    line: row.get(4)?,
+                    introduced: row.get(5)?,
+                },
+            })
+        },
+    )
+}
+
+/// WHERE conditions, ordering and limit for one query, with every value
+/// bound as a parameter so no input is ever spliced into SQL text.
+#[derive(Default)]
+struct QueryFilter {
+    conditions: Vec<String>,
+    params: Vec<Value>,
+    tail: String,
+}
+
+impl QueryFilter {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require `condition`, binding its `?` placeholders to `values` in order.
+    fn and<V: Into<Value>>(&mut self, condition: &str, values: impl IntoIterator<Item = V>) -> &mut Self {
+        self.conditions.push(condition.to_string());
+        self.params.extend(values.into_iter().map(Into::into));
+        self
+    }
+
+    /// Require `column = value` when a value is given.
+    fn eq(&mut self, column: &str, value: Option<&str>) -> &mut Self {
+        if let Some(value) = value {
+            self.and(&format!("{} = ?", column), [value.to_string()]);
+        }
+        self
+    }
+
+    /// Filter on a `--vis` value, where `all` (like none) matches everything.
+    fn visibility(&mut self, column: &str, vis: Option<&str>) -> &mut Self {
+        self.eq(column, vis.filter(|v| *v != "all"))
+    }
+
+    /// Require a symbol changed at or after the unix time `since`, per git
+    /// history, when one is given.
+    fn changed_since(&mut self, since: Option<i64>) -> &mut Self {
+        match since {
+            Some(since) => self.and(
+                "symbol_id IN (SELECT symbol_id FROM symbol_history WHERE last_modified >= ?)",
+                [since],
+            ),
+            None => self,
+        }
+    }
+
+    /// Require `column` to name `path` where either may be the shorter,
+    /// qualified spelling: `Display`, `fmt::Display` and `std::fmt::Display`
+    /// all match each other.
+    fn names_path(&mut self, column: &str, path: &str) -> &mut Self {
+        let path = path.trim_end_matches("::");
+        self.and(
+            &format!(
+                "({col} = ? OR substr({col}, -length(?)) = ? OR substr(?, -length({col}) - 2) = '::' || {col})",
+                col = column
+            ),
+            [path.to_string(), format!("::{}", path), format!("::{}", path), path.to_string()],
+        )
+    }
+
+    /// One row per value of `key`, in `key` order.
+    fn group_by(&mut self, key: &str) -> &mut Self {
+        self.tail = format!(" GROUP BY {0} ORDER BY {0}", key);
+        self
+    }
+
+    fn order_by(&mut self, order: &str, limit: usize) -> &mut Self {
+        self.tail = format!(" ORDER BY {} LIMIT ?", order);
+        self.params.push(Value::Integer(limit as i64));
+        self
+    }
+
+    /// Skip the first `offset` rows; call after `order_by`.
+    fn offset(&mut self, offset: usize) -> &mut Self {
+        self.tail.push_str(" OFFSET ?");
+        self.params.push(Value::Integer(offset as i64));
+        self
+    }
+
+    /// Keyset pagination over `symbols`: rows strictly after `after` in
+    /// (path, id) order, one more than `limit` so `into_page` can tell
+    /// whether another page follows.
+    fn page(&mut self, after: Option<&Cursor>, limit: usize) -> &mut Self {
+        if let Some(cursor) = after {
+            self.and(
+                "(path, id) > (?, ?)",
+                [Value::from(cursor.after_path.clone()), Value::from(cursor.after_rowid)],
+            );
+        }
+        self.order_by("path, id", limit + 1)
+    }
+
+    /// Run `select` (a query without WHERE) with these conditions appended.
+    fn query<T>(
+        &self,
+        conn: &Connection,
+        select: &str,
+        map: impl FnMut(&Row<'_>) -> SqliteResult<T>,
+    ) -> Result<Vec<T>> {
+        let mut sql = select.to_string();
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        sql.push_str(&self.tail);
+        
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(&self.params), map)?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(rows)
+    }
+}
+
+/// A `Symbol` from a row selecting `SYMBOL_COLUMNS`.
+fn symbol_from_row(row: &Row<'_>) -> SqliteResult<Symbol> {
+    Ok(Symbol {
+        symbol_id: hex::encode(row.get::<_, Vec<u8>>(1)?),
+        crate_id: row.get(2)?,
+        file_id: row.get(3)?,
+        path: row.get(4)?,
+        name: row.get(5)?,
+        kind: parse_symbol_kind(&row.get::<_, String>(6)?),
+        visibility: parse_visibility(&row.get::<_, String>(7)?),
+        signature: row.get(8)?,
+        docs: row.get(9)?,
+        status: parse_status(&row.get::<_, String>(10)?),
+        span_start: row.get(11)?,
+        span_end: row.get(12)?,
+        def_hash: row.get(13)?,
+    })
+}
+
+/// Split rows fetched by a `QueryFilter::page` query into a page of at most `limit`
+/// items and the cursor for the rest, if any.
+fn into_page<T>(mut rows: Vec<(i64, T)>, limit: usize, path: impl Fn(&T) -> &String) -> Page<T> {
+    let next_cursor = if rows.len() > limit {
+        rows.truncate(limit);
+        rows.last().map(|(rowid, item)| Cursor {
+            after_path: path(item).clone(),
+            after_rowid: *rowid,
+        })
+    } else {
+        None
+    };
+    
+    Page {
+        items: rows.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+    }
 }
 
 fn parse_symbol_kind(s: &str) -> SymbolKind {
     match s {
         "module" => SymbolKind::Module,
         "struct" => SymbolKind::Struct,
+        "union" => SymbolKind::Union,
         "enum" => SymbolKind::Enum,
         "trait" => SymbolKind::Trait,
         "fn" => SymbolKind::Fn,
@@ -190,6 +1496,9 @@ fn parse_symbol_kind(s: &str) -> SymbolKind {
         "const" => SymbolKind::Const,
         "static" => SymbolKind::Static,
         "impl" => SymbolKind::Impl,
+        "macro" => SymbolKind::Macro,
+        "extern_type" => SymbolKind::ExternType,
+        "foreign_fn" => SymbolKind::ForeignFn,
         _ => SymbolKind::Module, // Default fallback
     }
 }
@@ -0,0 +1,292 @@
+//! Normalized function signatures ("shapes") for searching by argument and
+//! return types.
+//!
+//! A shape keeps only what a caller sees: `pub async fn parse<'a>(s: &'a str)
+//! -> std::io::Result<Config>` becomes `fn(&str) -> Result<Config>`. Names,
+//! generic parameter lists, lifetimes, path qualifiers and `where` clauses are
+//! dropped, and receivers are written as `self`, `&self` or `&mut self`.
+
+/// The shape of a function signature, or `None` if `signature` is not one.
+pub fn signature_shape(signature: &str) -> Option<String> {
+    Shape::parse(signature).map(|shape| shape.render(false))
+}
+
+/// A GLOB pattern over shapes for a signature pattern such as
+/// `fn(&str) -> Result<_, _>`, where `_` stands for any type and `..` for
+/// any remaining arguments. Without `->` only functions returning `()` match.
+pub fn shape_glob(pattern: &str) -> Option<String> {
+    let pattern = pattern.trim();
+    // Allow `(&str) -> T` as shorthand for `fn(&str) -> T`
+    if pattern.starts_with('(') {
+        return Shape::parse(&format!("fn{}", pattern)).map(|shape| shape.render(true));
+    }
+    Shape::parse(pattern).map(|shape| shape.render(true))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Lifetime,
+    Punct(&'static str),
+    Other(char),
+}
+
+impl Token {
+    fn is(&self, punct: &str) -> bool {
+        matches!(self, Token::Punct(p) if *p == punct)
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Word(w) if w == word)
+    }
+}
+
+const PUNCT: &[&str] = &[
+    "->", "::", "..", "(", ")", "[", "]", "<", ">", "{", "}", ",", ":", ";", "&", "*", "+", "=", "?", "!",
+];
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '"' {
+            // ABI strings carry nothing a shape keeps
+            let end = rest.char_indices().skip(1).find(|&(_, c)| c == '"').map_or(rest.len(), |(i, _)| i + 1);
+            rest = &rest[end..];
+        } else if c == '\'' || c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |(i, _)| i);
+            tokens.push(if c == '\'' { Token::Lifetime } else { Token::Word(rest[..end].to_string()) });
+            rest = &rest[end..];
+        } else if let Some(punct) = PUNCT.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            tokens.push(Token::Other(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    tokens
+}
+
+struct Shape {
+    params: Vec<Vec<Token>>,
+    ret: Option<Vec<Token>>,
+}
+
+impl Shape {
+    fn parse(signature: &str) -> Option<Self> {
+        let tokens = tokenize(signature);
+        let start = tokens.iter().position(|t| t.is_word("fn"))? + 1;
+        let mut i = start;
+        if matches!(tokens.get(i), Some(Token::Word(_))) {
+            i += 1;
+        }
+        if tokens.get(i).is_some_and(|t| t.is("<")) {
+            i = skip_group(&tokens, i)?;
+        }
+        if !tokens.get(i)?.is("(") {
+            return None;
+        }
+        let close = skip_group(&tokens, i)? - 1;
+        let params = split_top_level(&tokens[i + 1..close], ",")
+            .into_iter()
+            .filter(|param| !param.is_empty())
+            .map(param_type)
+            .collect();
+
+        let mut ret = None;
+        i = close + 1;
+        if tokens.get(i).is_some_and(|t| t.is("->")) {
+            let end = (i + 1..tokens.len())
+                .find(|&j| depth_at(&tokens[i + 1..j]) == 0 && (tokens[j].is_word("where") || tokens[j].is("{") || tokens[j].is(";")))
+                .unwrap_or(tokens.len());
+            let ty = normalize_type(&tokens[i + 1..end]);
+            if ty != [Token::Punct("("), Token::Punct(")")] {
+                ret = Some(ty);
+            }
+        }
+
+        Some(Self { params, ret })
+    }
+
+    /// The shape as text, or with `glob` as a GLOB pattern where `_` and
+    /// `..` are wildcards and everything else is literal.
+    fn render(&self, glob: bool) -> String {
+        let mut out = String::from("fn(");
+        for (i, param) in self.params.iter().enumerate() {
+            let rest = glob && param.len() == 1 && param[0].is("..");
+            if i > 0 && !rest {
+                out.push_str(", ");
+            }
+            out.push_str(&render_type(param, glob));
+        }
+        out.push(')');
+        if let Some(ret) = &self.ret {
+            out.push_str(" -> ");
+            out.push_str(&render_type(ret, glob));
+        }
+        out
+    }
+}
+
+/// Index just past the group opening at `open`.
+fn skip_group(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        depth += delta(token);
+        if depth == 0 {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+fn delta(token: &Token) -> i32 {
+    match token {
+        Token::Punct("(" | "[" | "<" | "{") => 1,
+        Token::Punct(")" | "]" | ">" | "}") => -1,
+        _ => 0,
+    }
+}
+
+fn depth_at(tokens: &[Token]) -> i32 {
+    tokens.iter().map(delta).sum()
+}
+
+fn split_top_level<'a>(tokens: &'a [Token], separator: &str) -> Vec<&'a [Token]> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, token) in tokens.iter().enumerate() {
+        depth += delta(token);
+        if depth == 0 && token.is(separator) {
+            parts.push(&tokens[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+/// The type of a `pattern: Type` parameter; a bare type (as in a search
+/// pattern) or a receiver is taken as is.
+fn param_type(param: &[Token]) -> Vec<Token> {
+    let parts = split_top_level(param, ":");
+    if parts.len() < 2 {
+        let mut ty = normalize_type(param);
+        // `mut self` takes self by value
+        if ty.len() == 2 && ty[0].is_word("mut") && ty[1].is_word("self") {
+            ty.remove(0);
+        }
+        return ty;
+    }
+    let ty = normalize_type(&param[parts[0].len() + 1..]);
+    if parts[0].iter().any(|t| t.is_word("self")) {
+        // `self: &Self` is how rustdoc spells `&self`
+        return ty
+            .into_iter()
+            .map(|t| if t.is_word("Self") { Token::Word("self".to_string()) } else { t })
+            .collect();
+    }
+    ty
+}
+
+/// Drop lifetimes and path qualifiers, and any `<>` left empty by that.
+fn normalize_type(tokens: &[Token]) -> Vec<Token> {
+    let mut out: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match token {
+            Token::Lifetime => {
+                // `'a, T` in generic args, or `+ 'a` in bounds
+                if tokens.get(i + 1).is_some_and(|t| t.is(",")) {
+                    i += 1;
+                } else if out.last().is_some_and(|t| t.is(",") || t.is("+")) {
+                    out.pop();
+                }
+            }
+            Token::Word(_) if tokens.get(i + 1).is_some_and(|t| t.is("::")) => {
+                i += 1;
+            }
+            Token::Punct("::") => {}
+            Token::Punct(">") if out.last().is_some_and(|t| t.is("<")) => {
+                out.pop();
+            }
+            _ => out.push(token.clone()),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn render_type(tokens: &[Token], glob: bool) -> String {
+    let mut out = String::new();
+    let mut prev: Option<&Token> = None;
+    for token in tokens {
+        let text = match token {
+            Token::Word(w) if glob && w == "_" => "*".to_string(),
+            Token::Punct("..") if glob => "*".to_string(),
+            Token::Word(w) => w.clone(),
+            Token::Punct(p) if glob && matches!(*p, "*" | "?" | "[") => format!("[{}]", p),
+            Token::Punct(p) => p.to_string(),
+            Token::Other(c) => c.to_string(),
+            Token::Lifetime => continue,
+        };
+        let spaced = match (prev, token) {
+            (Some(Token::Word(_)), Token::Word(_)) => " ",
+            (Some(Token::Word(w)), _) if matches!(w.as_str(), "mut" | "const" | "dyn" | "impl") => " ",
+            (Some(Token::Punct("," | ";")), _) => " ",
+            (Some(_), Token::Punct("->" | "+" | "=")) => " ",
+            (Some(Token::Punct("->" | "+" | "=")), _) => " ",
+            _ => "",
+        };
+        out.push_str(spaced);
+        out.push_str(&text);
+        prev = Some(token);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_shape() {
+        let shape = |sig: &str| signature_shape(sig).unwrap();
+        assert_eq!(shape("pub fn parse(s: &str) -> Result<Config, Error>"), "fn(&str) -> Result<Config, Error>");
+        assert_eq!(
+            shape("pub async fn load<'a, P: AsRef<Path>>(path: P, buf: &'a mut [u8]) -> std::io::Result<usize> where P: Send"),
+            "fn(P, &mut [u8]) -> Result<usize>"
+        );
+        assert_eq!(shape("fn get(&'a self, key: &K) -> Option<&V>"), "fn(&self, &K) -> Option<&V>");
+        assert_eq!(shape("fn into_inner(mut self) -> ()"), "fn(self)");
+        assert_eq!(shape("fn len(self: &Self) -> usize"), "fn(&self) -> usize");
+        assert_eq!(shape("extern \"C\" fn cb(data: *const c_void, n: [u8; 4])"), "fn(*const c_void, [u8; 4])");
+        assert_eq!(
+            shape("fn spawn<F>(f: F) -> JoinHandle<'static, T> where F: FnOnce() -> T + 'static"),
+            "fn(F) -> JoinHandle<T>"
+        );
+        assert_eq!(shape("fn map(f: impl Fn(u8) -> u8 + Send, v: Vec<Box<dyn Error>>)"), "fn(impl Fn(u8) -> u8 + Send, Vec<Box<dyn Error>>)");
+        // Identifiers and literals may start with multi-byte characters
+        assert_eq!(shape("pub fn größe(ä: Öl, c: [u8; 'é' as usize]) -> Ärger"), "fn(Öl, [u8; as usize]) -> Ärger");
+        assert_eq!(shape("extern \"ü\" fn é(x: §)"), "fn(§)");
+        assert_eq!(signature_shape("struct Config<T>"), None);
+        assert_eq!(signature_shape("macro_rules! vec"), None);
+    }
+
+    #[test]
+    fn test_shape_glob() {
+        assert_eq!(shape_glob("fn(&str) -> Result<_, _>").unwrap(), "fn(&str) -> Result<*, *>");
+        assert_eq!(shape_glob("(&self, ..) -> bool").unwrap(), "fn(&self*) -> bool");
+        assert_eq!(shape_glob("fn(&[u8], *const T)").unwrap(), "fn(&[[]u8], [*]const T)");
+        assert_eq!(shape_glob("fn(std::path::Path) -> io::Result<()>").unwrap(), "fn(Path) -> Result<()>");
+        assert_eq!(shape_glob("State"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{IndexError, Result};
+use std::io::Read;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shared flag used to abort an in-flight indexing run.
+///
+/// Clones observe the same flag. The indexer checks it between crates and
+/// between items, and kills a running `cargo rustdoc` when it is set.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled along with this one, whose own cancellation leaves
+    /// this one running.
+    pub fn child(&self) -> Self {
+        Self { flag: Arc::default(), parent: Some(Box::new(self.clone())) }
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst) || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// Whether `other` is a clone of this token rather than a separate one.
+    pub fn same_as(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.flag, &other.flag)
+    }
+
+    /// Returns `Err(IndexError::Cancelled)` once the token has been cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(IndexError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Wait for `child`, killing it if `cancel` fires first. Returns the exit
+/// status and whatever the child wrote to its piped stderr.
+pub(crate) async fn wait_for_child(mut child: Child, cancel: &CancelToken) -> Result<(ExitStatus, String)> {
+    // Drain stderr on a blocking task so a chatty build cannot fill the pipe
+    // and stall.
+    let stderr = child.stderr.take().map(|mut pipe| {
+        tokio::task::spawn_blocking(move || {
+            let mut buf = String::new();
+            let _ = pipe.read_to_string(&mut buf);
+            buf
+        })
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(IndexError::Cancelled);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    let stderr = match stderr {
+        Some(handle) => handle.await.unwrap_or_default(),
+        None => String::new(),
+    };
+    Ok((status, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(IndexError::Cancelled)));
+    }
+
+    #[test]
+    fn test_child_token() {
+        let parent = CancelToken::new();
+        let (first, second) = (parent.child(), parent.child());
+        assert!(first.same_as(&first.clone()));
+        assert!(!first.same_as(&parent));
+
+        first.cancel();
+        assert!(first.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(!second.is_cancelled());
+
+        parent.cancel();
+        assert!(second.is_cancelled());
+    }
+}
@@ -0,0 +1,140 @@
+//! Compatibility shim for rustdoc JSON produced by nightlies other than the
+//! one `rustdoc-types` was built against.
+//!
+//! Older supported documents are upgraded in place on `serde_json::Value`
+//! fragments before deserializing into the current `rustdoc_types` shapes.
+
+use crate::{IndexError, Result};
+use rustdoc_types::FORMAT_VERSION;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+/// Oldest format version the upgrade shim understands (numeric `Id`s).
+pub const MIN_FORMAT_VERSION: u32 = 35;
+
+/// Reject documents the shim cannot read; warn about newer ones.
+pub fn check_version(version: u32) -> Result<()> {
+    if version < MIN_FORMAT_VERSION {
+        return Err(IndexError::UnsupportedFormat(format!(
+            "rustdoc JSON format_version {} is older than the oldest supported ({}); \
+             update your nightly toolchain with `rustup update nightly`",
+            version, MIN_FORMAT_VERSION
+        )));
+    }
+
+    if version > FORMAT_VERSION {
+        // Newer formats are often additive; try as-is before giving up.
+        warn!(
+            "rustdoc JSON format_version {} is newer than supported {}, attempting to parse anyway",
+            version, FORMAT_VERSION
+        );
+    } else if version < FORMAT_VERSION {
+        info!(
+            "Upgrading rustdoc JSON from format_version {} to {}",
+            version, FORMAT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Deserialize a (possibly upgraded) fragment of a document in `version` format.
+pub fn from_value<T: DeserializeOwned>(value: Value, version: u32) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| {
+        if version > FORMAT_VERSION {
+            IndexError::UnsupportedFormat(format!(
+                "rustdoc JSON format_version {} is newer than supported ({}) and failed to parse: {}; \
+                 install a matching nightly (e.g. `rustup toolchain install nightly-2025-01-24`) \
+                 or upgrade ct",
+                version, FORMAT_VERSION, e
+            ))
+        } else if version < FORMAT_VERSION {
+            IndexError::UnsupportedFormat(format!(
+                "failed to upgrade rustdoc JSON format_version {} to {}: {}",
+                version, FORMAT_VERSION, e
+            ))
+        } else {
+            IndexError::Json(e)
+        }
+    })
+}
+
+/// Apply every schema change between `from` and `FORMAT_VERSION`.
+pub fn upgrade(value: &mut Value, from: u32) {
+    if from >= FORMAT_VERSION {
+        return;
+    }
+    upgrade_value(value, from);
+}
+
+fn upgrade_value(value: &mut Value, from: u32) {
+    match value {
+        Value::Object(map) => {
+            upgrade_object(map, from);
+            for child in map.values_mut() {
+                upgrade_value(child, from);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                upgrade_value(item, from);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn upgrade_object(map: &mut Map<String, Value>, from: u32) {
+    // v36: Trait::is_object_safe renamed to is_dyn_compatible
+    if from < 36 {
+        if let Some(flag) = map.remove("is_object_safe") {
+            map.insert("is_dyn_compatible".to_string(), flag);
+        }
+    }
+
+    // v37: Static gained is_unsafe
+    if from < 37 {
+        if let Some(Value::Object(stat)) = map.get_mut("static") {
+            if stat.contains_key("is_mutable") && !stat.contains_key("is_unsafe") {
+                stat.insert("is_unsafe".to_string(), Value::Bool(false));
+            }
+        }
+    }
+
+    // v39: Path::name renamed to Path::path (v38 held only the last segment)
+    if from < 39 && is_path_object(map) {
+        if let Some(name) = map.remove("name") {
+            map.insert("path".to_string(), name);
+        }
+    }
+}
+
+fn is_path_object(map: &Map<String, Value>) -> bool {
+    map.len() == 3 && map.contains_key("name") && map.contains_key("id") && map.contains_key("args")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_upgrade_v35() {
+        let mut value = json!({
+            "inner": {"trait": {"is_object_safe": true, "items": []}},
+            "other": {"static": {"type": {"primitive": "u8"}, "is_mutable": false, "expr": "0"}},
+            "for": {"resolved_path": {"name": "Vec", "id": 3, "args": null}},
+        });
+        upgrade(&mut value, 35);
+
+        assert_eq!(value["inner"]["trait"]["is_dyn_compatible"], json!(true));
+        assert_eq!(value["other"]["static"]["is_unsafe"], json!(false));
+        assert_eq!(value["for"]["resolved_path"]["path"], json!("Vec"));
+    }
+
+    #[test]
+    fn test_reject_old_format() {
+        let err = check_version(30).unwrap_err();
+        assert!(err.to_string().contains("older than the oldest supported"));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{WorkspaceMember, Result, IndexError};
+use ct_core::models::CrateDependency;
 use std::path::Path;
 use std::process::Command;
 use serde::Deserialize;
@@ -18,6 +19,18 @@ struct Package {
     name: String,
     version: String,
     manifest_path: String,
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Dependency {
+    name: String,
+    /// `null` for normal dependencies
+    kind: Option<String>,
+    req: String,
+    #[serde(default)]
+    optional: bool,
 }
 
 pub async fn discover_workspace_members(workspace_root: &Path) -> Result<Vec<WorkspaceMember>> {
@@ -30,7 +43,7 @@ pub async fn discover_workspace_members(workspace_root: &Path) -> Result<Vec<Wor
         .arg("1")
         .current_dir(workspace_root)
         .output()
-        .map_err(|e| IndexError::Io(e))?;
+        .map_err(IndexError::Io)?;
     
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -54,11 +67,23 @@ pub async fn discover_workspace_members(workspace_root: &Path) -> Result<Vec<Wor
             
             debug!("Found workspace member: {} at {:?}", package.name, path);
             
+            let dependencies = package
+                .dependencies
+                .into_iter()
+                .map(|dep| CrateDependency {
+                    name: dep.name,
+                    kind: dep.kind.unwrap_or_else(|| "normal".to_string()),
+                    req: dep.req,
+                    optional: dep.optional,
+                })
+                .collect();
+            
             members.push(WorkspaceMember {
                 name: package.name,
                 version: package.version,
                 path,
                 package_id: package.id,
+                dependencies,
             });
         }
     }
@@ -71,7 +96,7 @@ pub fn get_rustc_version() -> Result<String> {
         .arg("--version")
         .arg("--verbose")
         .output()
-        .map_err(|e| IndexError::Io(e))?;
+        .map_err(IndexError::Io)?;
     
     if !output.status.success() {
         return Err(IndexError::IndexingFailed(
@@ -94,7 +119,7 @@ pub fn get_cfg_snapshot() -> Result<String> {
         .arg("--print")
         .arg("cfg")
         .output()
-        .map_err(|e| IndexError::Io(e))?;
+        .map_err(IndexError::Io)?;
     
     if !output.status.success() {
         return Err(IndexError::IndexingFailed(
@@ -0,0 +1,79 @@
+use crate::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Include/exclude globs applied to source files before their symbols are stored.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    /// The patterns as given, `+` or `-` prefixed, for fingerprinting.
+    patterns: Vec<String>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: build_set(include)?,
+            exclude: build_set(exclude)?,
+            patterns: include
+                .iter()
+                .map(|p| format!("+{}", p))
+                .chain(exclude.iter().map(|p| format!("-{}", p)))
+                .collect(),
+        })
+    }
+
+    /// The include (`+`) and exclude (`-`) patterns this filter was built from.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// `path` is relative to the workspace root, as reported in rustdoc spans.
+    pub fn is_included(&self, path: &Path) -> bool {
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(path) {
+                return false;
+            }
+        }
+
+        match &self.include {
+            Some(include) => include.is_match(path),
+            None => true,
+        }
+    }
+}
+
+fn build_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(
+            &["src/**".to_string(), "crates/*/src/**".to_string()],
+            &["src/generated/**".to_string()],
+        )
+        .unwrap();
+
+        assert!(filter.is_included(Path::new("src/lib.rs")));
+        assert!(filter.is_included(Path::new("crates/a/src/main.rs")));
+        assert!(!filter.is_included(Path::new("src/generated/bindings.rs")));
+        assert!(!filter.is_included(Path::new("benches/speed.rs")));
+
+        let all = PathFilter::default();
+        assert!(all.is_included(Path::new("benches/speed.rs")));
+    }
+}
@@ -0,0 +1,186 @@
+use ct_core::models::SymbolHistory;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use tracing::{debug, warn};
+
+/// Returns true if `workspace_root` is inside a git work tree and git is on PATH.
+pub fn is_git_repo(workspace_root: &Path) -> bool {
+    Command::new("git")
+        .arg("rev-parse")
+        .arg("--is-inside-work-tree")
+        .current_dir(workspace_root)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Collect the last-modifying commit and change count for a 1-based inclusive
+/// line range of `file` using `git log -L`.
+///
+/// Returns `None` for untracked files or when git fails for the range.
+pub fn span_history(
+    workspace_root: &Path,
+    file: &Path,
+    line_start: u32,
+    line_end: u32,
+) -> Option<SymbolHistory> {
+    if line_start == 0 || line_end < line_start {
+        return None;
+    }
+
+    let output = Command::new("git")
+        .arg("log")
+        .arg("--no-color")
+        .arg("--format=commit:%H:%ct")
+        .arg(format!("-L{},{}:{}", line_start, line_end, file.to_string_lossy()))
+        .current_dir(workspace_root)
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to run git log for {:?}: {}", file, e);
+            return None;
+        }
+    };
+
+    if !output.status.success() {
+        debug!(
+            "git log -L failed for {:?}:{}-{}: {}",
+            file,
+            line_start,
+            line_end,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+
+    parse_log_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Author time, as unix seconds, of each line of a 1-based inclusive range
+/// of `file` using `git blame`; lines not committed yet have the current time.
+///
+/// Returns `None` for untracked files or when git fails for the range.
+pub fn line_times(
+    workspace_root: &Path,
+    file: &Path,
+    line_start: u32,
+    line_end: u32,
+) -> Option<HashMap<u32, i64>> {
+    if line_start == 0 || line_end < line_start {
+        return None;
+    }
+
+    let output = Command::new("git")
+        .arg("blame")
+        .arg("--porcelain")
+        .arg(format!("-L{},{}", line_start, line_end))
+        .arg("--")
+        .arg(file)
+        .current_dir(workspace_root)
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to run git blame for {:?}: {}", file, e);
+            return None;
+        }
+    };
+
+    if !output.status.success() {
+        debug!(
+            "git blame failed for {:?}:{}-{}: {}",
+            file,
+            line_start,
+            line_end,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+
+    Some(parse_blame_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse `git blame --porcelain`: a `<hash> <orig line> <final line>` header
+/// per line, `author-time` only the first time a commit appears, and the
+/// line itself after a tab.
+fn parse_blame_output(stdout: &str) -> HashMap<u32, i64> {
+    let mut commit_times: HashMap<&str, i64> = HashMap::new();
+    let mut line_times = HashMap::new();
+    let mut current: Option<(&str, u32)> = None;
+    for line in stdout.lines() {
+        if line.starts_with('\t') {
+            if let Some((hash, number)) = current.take() {
+                if let Some(&time) = commit_times.get(hash) {
+                    line_times.insert(number, time);
+                }
+            }
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            if let (Some((hash, _)), Ok(time)) = (current, time.trim().parse()) {
+                commit_times.insert(hash, time);
+            }
+        } else {
+            let mut fields = line.split(' ');
+            let (Some(hash), Some(_), Some(number)) = (fields.next(), fields.next(), fields.next()) else { continue };
+            if hash.len() >= 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                current = number.parse().ok().map(|number| (hash, number));
+            }
+        }
+    }
+    line_times
+}
+
+/// Parse `commit:<hash>:<timestamp>` marker lines; git lists the newest commit first.
+fn parse_log_output(stdout: &str) -> Option<SymbolHistory> {
+    let mut commits = stdout.lines().filter_map(|line| {
+        let rest = line.strip_prefix("commit:")?;
+        let (hash, ts) = rest.split_once(':')?;
+        Some((hash.to_string(), ts.trim().parse::<i64>().ok()?))
+    });
+
+    let (last_commit, last_modified) = commits.next()?;
+    let churn = 1 + commits.count() as u32;
+
+    Some(SymbolHistory {
+        last_commit,
+        last_modified,
+        churn,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_output() {
+        let stdout = "commit:aaa:1700000200\n\ndiff --git a/src/lib.rs b/src/lib.rs\n+fn x() {}\ncommit:bbb:1700000100\n";
+        let history = parse_log_output(stdout).unwrap();
+        assert_eq!(history.last_commit, "aaa");
+        assert_eq!(history.last_modified, 1700000200);
+        assert_eq!(history.churn, 2);
+
+        assert!(parse_log_output("").is_none());
+    }
+
+    #[test]
+    fn test_parse_blame_output() {
+        let aaa = "a".repeat(40);
+        let bbb = "b".repeat(40);
+        let stdout = format!(
+            "{aaa} 1 4 1\nauthor Ann\nauthor-time 1700000100\nsummary start\nfilename src/lib.rs\n\tfn run() {{\n\
+             {bbb} 7 5 2\nauthor Ben\nauthor-time 1700000200\nfilename src/lib.rs\n\t    todo!()\n\
+             {bbb} 8 6\n\t}}\n\
+             {aaa} 2 7 1\n\t// author-time 5\n"
+        );
+        let times = parse_blame_output(&stdout);
+        assert_eq!(times.len(), 4);
+        assert_eq!(times[&4], 1700000100);
+        assert_eq!(times[&5], 1700000200);
+        assert_eq!(times[&6], 1700000200);
+        assert_eq!(times[&7], 1700000100);
+    }
+}
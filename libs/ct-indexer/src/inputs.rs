@@ -0,0 +1,104 @@
+//! Files that shape a crate's index without being Rust modules themselves:
+//! `include_str!`/`include_bytes!`/`include!` targets and the inputs a build
+//! script declares with `rerun-if-changed`, which regenerate its `OUT_DIR`
+//! bindings.
+
+use crate::source;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+const INCLUDE_MACROS: [&str; 3] = ["include_str!", "include_bytes!", "include!"];
+
+/// Auxiliary inputs of the package at `package_dir`, as absolute paths.
+/// Includes built from `concat!(env!("OUT_DIR"), ..)` are not listed; the
+/// build script inputs that produce them are.
+pub fn auxiliary_inputs(package_dir: &Path) -> Vec<PathBuf> {
+    let mut inputs = BTreeSet::new();
+
+    let src = package_dir.join("src");
+    for file in source::collect_rust_files(&src).unwrap_or_default() {
+        let Ok(text) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let dir = file.parent().unwrap_or(&src);
+        inputs.extend(include_paths(&text).into_iter().map(|p| dir.join(p)));
+    }
+
+    // Cargo resolves `rerun-if-changed` paths against the package root
+    if let Ok(text) = std::fs::read_to_string(package_dir.join("build.rs")) {
+        inputs.extend(rerun_if_changed(&text).into_iter().map(|p| package_dir.join(p)));
+    }
+
+    inputs.into_iter().collect()
+}
+
+/// Literal paths passed to the include macros.
+fn include_paths(source: &str) -> Vec<&str> {
+    let mut paths = Vec::new();
+    for mac in INCLUDE_MACROS {
+        for (at, _) in source.match_indices(mac) {
+            let args = source[at + mac.len()..].trim_start();
+            if let Some(path) = args.strip_prefix('(').and_then(|a| string_literal(a.trim_start())) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+/// Paths from `cargo:rerun-if-changed=...` (or `cargo::`) lines printed by
+/// a build script, skipping ones assembled with format arguments.
+fn rerun_if_changed(build_script: &str) -> Vec<&str> {
+    const DIRECTIVE: &str = "rerun-if-changed=";
+    build_script
+        .match_indices(DIRECTIVE)
+        .filter_map(|(at, _)| {
+            let rest = &build_script[at + DIRECTIVE.len()..];
+            let path = &rest[..rest.find('"')?];
+            (!path.is_empty() && !path.contains('{')).then_some(path)
+        })
+        .collect()
+}
+
+/// Contents of a plain `"..."` literal at the start of `text`.
+fn string_literal(text: &str) -> Option<&str> {
+    let body = text.strip_prefix('"')?;
+    let literal = &body[..body.find('"')?];
+    (!literal.contains('\\')).then_some(literal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include_paths() {
+        let source = r#"
+            const SCHEMA: &str = include_str!("../schema.sql");
+            static LOGO: &[u8] = include_bytes! ( "assets/logo.png" );
+            include!("generated.rs");
+            include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+        "#;
+        let mut paths = include_paths(source);
+        paths.sort();
+        assert_eq!(paths, vec!["../schema.sql", "assets/logo.png", "generated.rs"]);
+    }
+
+    #[test]
+    fn test_build_script_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("build.rs"),
+            "fn main() {\n    println!(\"cargo:rerun-if-changed=wrapper.h\");\n    \
+             println!(\"cargo::rerun-if-changed={}\", out);\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "pub const Q: &str = include_str!(\"q.sql\");\n").unwrap();
+
+        assert_eq!(auxiliary_inputs(dir.path()), vec![
+            dir.path().join("src/q.sql"),
+            dir.path().join("wrapper.h"),
+        ]);
+    }
+}
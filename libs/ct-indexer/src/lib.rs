@@ -1,14 +1,31 @@
+pub mod cancel;
+pub mod compat;
 pub mod discovery;
+pub mod filter;
+pub mod git;
+pub mod inputs;
+pub mod source;
+pub mod stream;
 pub mod watcher;
 
-use ct_core::models::{ImplBlock, ImplementationStatus, Symbol, SymbolKind, Visibility};
+use cancel::CancelToken;
+use ct_core::models::{
+    CrateDependency, CrateFailure, CrateIndexStats, ImplBlock, ImplementationStatus, Reference, Symbol, SymbolChange, SymbolKind,
+    Visibility,
+};
+use ct_core::memory::MemoryGauge;
+use ct_core::utils::{extract_doc_examples, extract_markers};
 use ct_core::{compute_file_digest, compute_symbol_id, CoreError};
 use ct_db::{Database, DbError};
-use rustdoc_types::{Crate, Id, Item, ItemEnum, Type};
+use filter::PathFilter;
+use source::TestItems;
+use rustdoc_types::{Abi, GenericArg, GenericArgs, GenericParamDefKind, Id, Item, ItemEnum, Type};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tracing::{error, info, warn};
 
@@ -29,8 +46,20 @@ pub enum IndexError {
     #[error("Notify error: {0}")]
     Notify(#[from] notify::Error),
 
+    #[error("Invalid glob pattern: {0}")]
+    Glob(#[from] globset::Error),
+
+    #[error("Parse error: {0}")]
+    Parse(#[from] syn::Error),
+
+    #[error("Unsupported rustdoc JSON: {0}")]
+    UnsupportedFormat(String),
+
     #[error("Indexing failed: {0}")]
     IndexingFailed(String),
+
+    #[error("Indexing cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, IndexError>;
@@ -63,6 +92,28 @@ const DERIVE_METHODS: &[&str] = &[
     "deref_mut",
 ];
 
+const CRATE_SAVEPOINT: &str = "crate_index";
+
+/// Symbols buffered before they are written with one cached statement.
+const SYMBOL_BATCH_SIZE: usize = 512;
+
+/// Most symbol changes one run records with `with_change_log`.
+pub const CHANGE_LOG_LIMIT: usize = 10_000;
+
+fn implementation_status(body_text: &str) -> ImplementationStatus {
+    // Look for unimplemented!() macro
+    if body_text.contains("unimplemented!") {
+        return ImplementationStatus::Unimplemented;
+    }
+
+    // Look for todo!() macro or TODO/FIXME comments
+    if body_text.contains("todo!") || body_text.contains("TODO") || body_text.contains("FIXME") {
+        return ImplementationStatus::Todo;
+    }
+
+    ImplementationStatus::Implemented
+}
+
 fn is_derive_method(method_name: &str) -> bool {
     DERIVE_METHODS.contains(&method_name)
 }
@@ -75,6 +126,22 @@ pub struct Indexer {
     filter_module: Option<String>,
     filter_struct: Option<String>,
     include_derives: bool,
+    git_metadata: bool,
+    path_filter: PathFilter,
+    select_members: Vec<String>,
+    exclude_members: Vec<String>,
+    include_tests: bool,
+    skip_unchanged: bool,
+    cancel: CancelToken,
+    progress: Option<ProgressFn>,
+    /// Counts the rustdoc lookup tables held while a crate is processed.
+    memory: MemoryGauge,
+    /// Symbols each committed transaction changed, when change logging is on.
+    changes: Option<Vec<SymbolChange>>,
+    /// Time spent writing rows since the current crate started.
+    db_time: Duration,
+    /// Extracted symbols not yet written; flushed in `SYMBOL_BATCH_SIZE` chunks.
+    pending_symbols: Vec<Symbol>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +150,9 @@ pub struct WorkspaceMember {
     pub version: String,
     pub path: PathBuf,
     pub package_id: String,
+    /// As declared in the manifest, including dev and build dependencies.
+    #[serde(default)]
+    pub dependencies: Vec<CrateDependency>,
 }
 
 impl Indexer {
@@ -95,6 +165,18 @@ impl Indexer {
             filter_module: None,
             filter_struct: None,
             include_derives: false,
+            git_metadata: false,
+            path_filter: PathFilter::default(),
+            select_members: Vec::new(),
+            exclude_members: Vec::new(),
+            include_tests: false,
+            skip_unchanged: false,
+            cancel: CancelToken::new(),
+            progress: None,
+            memory: MemoryGauge::default(),
+            changes: None,
+            db_time: Duration::ZERO,
+            pending_symbols: Vec::new(),
         }
     }
 
@@ -110,34 +192,507 @@ impl Indexer {
         self
     }
 
+    /// Record last-modified commit and churn for each symbol span via `git log -L`.
+    pub fn with_git_metadata(mut self, enabled: bool) -> Self {
+        self.git_metadata = enabled;
+        self
+    }
+
+    /// Skip symbols whose span file is rejected by `filter`.
+    pub fn with_path_filter(mut self, filter: PathFilter) -> Self {
+        self.path_filter = filter;
+        self
+    }
+
+    /// Restrict `index_workspace` to the named members (empty means all),
+    /// minus any in `exclude`.
+    pub fn with_members(mut self, select: Vec<String>, exclude: Vec<String>) -> Self {
+        self.select_members = select;
+        self.exclude_members = exclude;
+        self
+    }
+
+    /// Also index `#[cfg(test)]` modules and `#[test]` functions, which
+    /// rustdoc leaves out, by parsing each member's sources.
+    pub fn with_tests(mut self, enabled: bool) -> Self {
+        self.include_tests = enabled;
+        self
+    }
+
+    /// Have `index_workspace` leave alone members whose stored fingerprint
+    /// and file digests show nothing changed since they were indexed.
+    pub fn with_skip_unchanged(mut self, enabled: bool) -> Self {
+        self.skip_unchanged = enabled;
+        self
+    }
+
+    /// Abort the run (rolling back its transaction) once `token` is cancelled.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Report each crate of a workspace or multi-crate run as it starts.
+    pub fn with_progress(mut self, progress: ProgressFn) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Count the memory held for rustdoc JSON against `gauge`.
+    pub fn with_memory_gauge(mut self, gauge: MemoryGauge) -> Self {
+        self.memory = gauge;
+        self
+    }
+
+    /// Record the symbols each transaction adds, removes or changes, for
+    /// `take_changes`. Costs a diff against the baseline on every commit.
+    pub fn with_change_log(mut self, enabled: bool) -> Self {
+        self.changes = enabled.then(Vec::new);
+        self
+    }
+
+    /// Symbols changed by the transactions committed so far, at most
+    /// `CHANGE_LOG_LIMIT + 1` of them so callers can tell the log was cut.
+    pub fn take_changes(&mut self) -> Vec<SymbolChange> {
+        self.changes.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn report_progress(&self, crate_name: &str, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            progress(&IndexProgress { crate_name: crate_name.to_string(), done, total });
+        }
+    }
+
+    fn is_member_selected(&self, name: &str) -> bool {
+        (self.select_members.is_empty() || self.select_members.iter().any(|m| m == name))
+            && !self.exclude_members.iter().any(|m| m == name)
+    }
+
+    /// Index every selected workspace member. A root without a `Cargo.toml`
+    /// (a plain directory or a single `.rs` file) is indexed with the syn
+    /// backend instead, see [`Indexer::index_sources`].
     pub async fn index_workspace(&mut self) -> Result<IndexStats> {
+        if !self.workspace_root.join("Cargo.toml").is_file() {
+            return self.index_sources();
+        }
+
         info!("Starting workspace indexing at {:?}", self.workspace_root);
 
         let start = std::time::Instant::now();
-        let members = discovery::discover_workspace_members(&self.workspace_root).await?;
+        let mut members = discovery::discover_workspace_members(&self.workspace_root).await?;
 
         info!("Found {} workspace members", members.len());
 
-        self.db.begin_transaction()?;
+        members.retain(|m| self.is_member_selected(&m.name));
+        if !self.select_members.is_empty() || !self.exclude_members.is_empty() {
+            info!("{} members selected for indexing", members.len());
+        }
 
         let mut stats = IndexStats::default();
+        if self.skip_unchanged {
+            let unchanged = self.unchanged_members(&members)?;
+            if !unchanged.is_empty() {
+                info!("{} of {} members unchanged since last indexed", unchanged.len(), members.len());
+                members.retain(|m| !unchanged.contains(&m.name));
+                stats.crates_unchanged = unchanged.len();
+                if members.is_empty() {
+                    stats.duration_ms = start.elapsed().as_millis() as u64;
+                    return Ok(stats);
+                }
+            }
+        }
+
+        self.check_git_repo();
+        self.crate_cache.clear();
+        self.file_cache.clear();
 
-        for member in &members {
+        self.begin()?;
+
+        for (done, member) in members.iter().enumerate() {
             info!("Indexing crate: {} ({})", member.name, member.version);
-            let crate_stats = self.index_crate(member).await?;
-            stats.merge(crate_stats);
+            self.report_progress(&member.name, done, members.len());
+            // Each crate gets a savepoint so one failure only discards its own rows
+            self.db.savepoint(CRATE_SAVEPOINT)?;
+            match self.index_crate(member).await {
+                Ok(crate_stats) => {
+                    self.db.release_savepoint(CRATE_SAVEPOINT)?;
+                    stats.merge(crate_stats);
+                }
+                Err(IndexError::Cancelled) => {
+                    info!("Indexing cancelled, rolling back");
+                    self.db.rollback_transaction()?;
+                    return Err(IndexError::Cancelled);
+                }
+                Err(e) => {
+                    error!("Failed to index crate {}, continuing: {}", member.name, e);
+                    self.db.rollback_to_savepoint(CRATE_SAVEPOINT)?;
+                    // Ids cached during the failed crate were rolled back with it
+                    self.crate_cache.remove(&member.name);
+                    self.file_cache.clear();
+                    stats.failures.push(CrateFailure {
+                        name: member.name.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
         }
 
-        self.db.commit_transaction()?;
+        self.commit()?;
 
         stats.duration_ms = start.elapsed().as_millis() as u64;
-        info!("Indexing completed in {}ms", stats.duration_ms);
+        if stats.failures.is_empty() {
+            info!("Indexing completed in {}ms", stats.duration_ms);
+        } else {
+            warn!(
+                "Indexing completed in {}ms with {} failed crates",
+                stats.duration_ms,
+                stats.failures.len()
+            );
+        }
 
         Ok(stats)
     }
 
+    /// Reindex a single workspace member, replacing its rows and leaving
+    /// every other crate in the database untouched.
+    pub async fn index_package(&mut self, name: &str) -> Result<IndexStats> {
+        info!("Reindexing package {} at {:?}", name, self.workspace_root);
+
+        let start = std::time::Instant::now();
+        let members = discovery::discover_workspace_members(&self.workspace_root).await?;
+        let member = members
+            .into_iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| {
+                IndexError::IndexingFailed(format!("{} is not a workspace member", name))
+            })?;
+
+        self.check_git_repo();
+        self.crate_cache.clear();
+        self.file_cache.clear();
+
+        self.begin()?;
+
+        let mut stats = match self.index_crate(&member).await {
+            Ok(stats) => stats,
+            Err(e) => {
+                if matches!(e, IndexError::Cancelled) {
+                    info!("Reindex of {} cancelled, rolling back", name);
+                }
+                self.db.rollback_transaction()?;
+                return Err(e);
+            }
+        };
+
+        self.commit()?;
+
+        stats.duration_ms = start.elapsed().as_millis() as u64;
+        info!("Reindexed {} in {}ms", name, stats.duration_ms);
+
+        Ok(stats)
+    }
+
+    /// Index a plain directory of `.rs` files, or a single file, with the syn
+    /// backend. Everything found is stored as one crate named after the root.
+    pub fn index_sources(&mut self) -> Result<IndexStats> {
+        info!("Indexing {:?} from source, no Cargo.toml found", self.workspace_root);
+
+        let start = std::time::Instant::now();
+        let crate_name = self
+            .workspace_root
+            .file_stem()
+            .map(|s| s.to_string_lossy().replace('-', "_"))
+            .unwrap_or_else(|| "sources".to_string());
+        let files = source::collect_rust_files(&self.workspace_root)?;
+        info!("Found {} source files", files.len());
+
+        self.check_git_repo();
+        self.crate_cache.clear();
+        self.file_cache.clear();
+
+        self.begin()?;
+
+        let mut stats = match self.index_source_files(&crate_name, &files) {
+            Ok(stats) => stats,
+            Err(e) => {
+                self.db.rollback_transaction()?;
+                return Err(e);
+            }
+        };
+
+        self.commit()?;
+
+        stats.duration_ms = start.elapsed().as_millis() as u64;
+        info!("Source indexing completed in {}ms", stats.duration_ms);
+
+        Ok(stats)
+    }
+
+    /// Open an index transaction with a fresh baseline for `diff`, which
+    /// the rows of each crate or file are saved into as they are replaced.
+    fn begin(&mut self) -> Result<()> {
+        self.db.begin_transaction()?;
+        self.db.save_epoch_baseline()?;
+        Ok(())
+    }
+
+    /// Commit the open transaction as a new index epoch.
+    fn commit(&mut self) -> Result<()> {
+        if let Some(changes) = &mut self.changes {
+            let room = (CHANGE_LOG_LIMIT + 1).saturating_sub(changes.len());
+            if room > 0 {
+                changes.extend(ct_db::queries::diff_symbols(self.db.conn(), None, false, room)?);
+            }
+        }
+        self.db.bump_index_epoch()?;
+        self.db.commit_transaction()?;
+        Ok(())
+    }
+
+    fn index_source_files(&mut self, crate_name: &str, files: &[PathBuf]) -> Result<IndexStats> {
+        let mut stats = IndexStats::default();
+        let base = self.base_dir().to_path_buf();
+        let single_file = self.workspace_root.is_file();
+        let crate_start = Instant::now();
+        self.db_time = Duration::ZERO;
+        self.pending_symbols.clear();
+
+        let mut hasher = blake3::Hasher::new();
+        for file in files {
+            hasher.update(file.to_string_lossy().as_bytes());
+            hasher.update(&std::fs::read(file)?);
+        }
+        let fingerprint = format!("blake3:{}", hasher.finalize().to_hex());
+
+        let db_start = Instant::now();
+        self.db.save_crate_baseline(crate_name)?;
+        self.db.delete_crate(crate_name)?;
+        let crate_id = self.db.insert_crate(crate_name, None, &fingerprint)?;
+        self.crate_cache.insert(crate_name.to_string(), crate_id);
+        stats.crates_indexed += 1;
+        self.db_time += db_start.elapsed();
+
+        let tests = if self.include_tests { TestItems::Include } else { TestItems::Skip };
+        for file in files {
+            self.cancel.check()?;
+
+            let module_path = if single_file {
+                crate_name.to_string()
+            } else {
+                let relative = file.strip_prefix(&base).unwrap_or(file);
+                source::module_path_for(relative, crate_name)
+            };
+            self.index_source_file(crate_id, file, &module_path, tests, &mut stats)?;
+        }
+        self.flush_symbols()?;
+
+        let duration = crate_start.elapsed();
+        stats.crates.push(CrateIndexStats {
+            name: crate_name.to_string(),
+            symbols: stats.symbols_indexed,
+            files: stats.files_indexed,
+            duration_ms: duration.as_millis() as u64,
+            rustdoc_ms: 0,
+            parse_ms: duration.saturating_sub(self.db_time).as_millis() as u64,
+            db_ms: self.db_time.as_millis() as u64,
+        });
+
+        Ok(stats)
+    }
+
+    /// Add what rustdoc never sees to an already indexed member: references
+    /// from item bodies, and the `#[cfg(test)]` items if tests are indexed.
+    fn index_source_extras(&mut self, member: &WorkspaceMember, crate_id: i64, stats: &mut IndexStats) -> Result<()> {
+        let crate_name = member.name.replace('-', "_");
+        let tests = if self.include_tests { TestItems::Only } else { TestItems::None };
+        for file in source::collect_rust_files(&member.path.join("src"))? {
+            self.cancel.check()?;
+
+            let relative = file.strip_prefix(&member.path).unwrap_or(&file);
+            let module_path = source::module_path_for(relative, &crate_name);
+            self.index_source_file(crate_id, &file, &module_path, tests, stats)?;
+        }
+        Ok(())
+    }
+
+    /// Parse one file with the syn backend and store what it yields under `crate_id`.
+    fn index_source_file(
+        &mut self,
+        crate_id: i64,
+        file: &Path,
+        module_path: &str,
+        tests: TestItems,
+        stats: &mut IndexStats,
+    ) -> Result<()> {
+        let relative = file.strip_prefix(self.base_dir()).unwrap_or(file).to_path_buf();
+        if !self.path_filter.is_included(&relative) {
+            return Ok(());
+        }
+
+        let content = std::fs::read_to_string(file)?;
+        let parsed = match source::parse_source(&content, module_path, tests) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Skipping {:?}, failed to parse: {}", relative, e);
+                return Ok(());
+            }
+        };
+        if parsed.items.is_empty() && parsed.impls.is_empty() && parsed.references.is_empty() {
+            return Ok(());
+        }
+
+        let file_name = relative.to_string_lossy().to_string();
+        let file_id = match self.file_cache.get(&file_name) {
+            Some(&fid) => fid,
+            None => {
+                let fid = self
+                    .db
+                    .insert_file(crate_id, &file_name, &compute_file_digest(content.as_bytes()))?;
+                self.file_cache.insert(file_name.clone(), fid);
+                self.store_markers(fid, &relative, &content)?;
+                stats.files_indexed += 1;
+                fid
+            }
+        };
+
+        let lines: Vec<&str> = content.lines().collect();
+        for item in parsed.items {
+            let status = if matches!(item.kind, SymbolKind::Fn | SymbolKind::Method) {
+                let from = (item.line_start as usize).saturating_sub(1).min(lines.len());
+                let to = (item.line_end as usize).min(lines.len());
+                implementation_status(&lines[from..to].join("\n"))
+            } else {
+                ImplementationStatus::Implemented
+            };
+
+            let symbol = Symbol {
+                symbol_id: compute_symbol_id(
+                    &item.path,
+                    item.kind.as_str(),
+                    &file_name,
+                    item.line_start,
+                    item.line_end,
+                ),
+                crate_id,
+                file_id,
+                def_hash: format!("{}", blake3::hash(item.signature.as_bytes()).to_hex()),
+                path: item.path,
+                name: item.name,
+                kind: item.kind,
+                visibility: item.visibility,
+                signature: item.signature,
+                docs: item.docs,
+                status,
+                span_start: item.line_start,
+                span_end: item.line_end,
+            };
+
+            if !self.should_process_symbol(&symbol) {
+                continue;
+            }
+
+            self.store_symbol(&symbol, &relative)?;
+            stats.symbols_indexed += 1;
+        }
+
+        let db_start = Instant::now();
+        for imp in parsed.impls {
+            self.db.insert_impl(&ImplBlock {
+                id: 0,
+                for_path: imp.for_path,
+                trait_path: imp.trait_path,
+                file_id,
+                line_start: imp.line_start,
+                line_end: imp.line_end,
+            })?;
+        }
+        self.db_time += db_start.elapsed();
+
+        if !parsed.references.is_empty() {
+            self.store_references(file_id, parsed.references)?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert references, and the calls among them, under the rows of the
+    /// symbols making them, skipping those whose symbol was filtered out or
+    /// never indexed.
+    fn store_references(&mut self, file_id: i64, references: Vec<source::SourceReference>) -> Result<()> {
+        // References point at symbol rows, so queued symbols must exist first
+        self.flush_symbols()?;
+
+        let db_start = Instant::now();
+        let mut symbol_ids: HashMap<String, Option<i64>> = HashMap::new();
+        for reference in references {
+            let symbol_id = match symbol_ids.get(&reference.from_path) {
+                Some(&id) => id,
+                None => {
+                    let id = ct_db::queries::find_symbol_rowid(self.db.conn(), &reference.from_path, file_id)?;
+                    symbol_ids.insert(reference.from_path.clone(), id);
+                    id
+                }
+            };
+            let Some(symbol_id) = symbol_id else {
+                continue;
+            };
+            if reference.call {
+                self.db.insert_symbol_call(
+                    symbol_id,
+                    &reference.target_path,
+                    file_id,
+                    reference.line_start,
+                    reference.line_end,
+                )?;
+            }
+            self.db.insert_reference(&Reference {
+                id: 0,
+                symbol_id,
+                target_path: reference.target_path,
+                file_id,
+                span_start: reference.line_start,
+                span_end: reference.line_end,
+                span_col: reference.column,
+            })?;
+        }
+        self.db_time += db_start.elapsed();
+        Ok(())
+    }
+
+    /// Directory that span and file paths are relative to; the parent when
+    /// indexing a single file.
+    fn base_dir(&self) -> &Path {
+        if self.workspace_root.is_file() {
+            self.workspace_root.parent().unwrap_or(Path::new("."))
+        } else {
+            &self.workspace_root
+        }
+    }
+
+    fn check_git_repo(&mut self) {
+        if self.git_metadata && !git::is_git_repo(self.base_dir()) {
+            warn!("git_metadata enabled but {:?} is not a git work tree, skipping", self.workspace_root);
+            self.git_metadata = false;
+        }
+    }
+
+    /// Index one member inside the caller's transaction. Rows from any
+    /// previous run of the same crate are deleted first so reindexing
+    /// replaces rather than duplicates.
     async fn index_crate(&mut self, member: &WorkspaceMember) -> Result<IndexStats> {
+        self.cancel.check()?;
         let mut stats = IndexStats::default();
+        let crate_start = Instant::now();
+        let files_before = self.file_cache.len();
+        self.db_time = Duration::ZERO;
+        self.pending_symbols.clear();
+
+        let db_start = Instant::now();
+        self.db.save_crate_baseline(&member.name)?;
+        let removed = self.db.delete_crate(&member.name)?;
+        if removed > 0 {
+            info!("Replacing previously indexed rows for crate {}", member.name);
+        }
 
         // Create crate entry
         let crate_fingerprint = self.compute_crate_fingerprint(member)?;
@@ -146,21 +701,27 @@ impl Indexer {
                 .insert_crate(&member.name, Some(&member.version), &crate_fingerprint)?;
 
         self.crate_cache.insert(member.name.clone(), crate_id);
+        for dependency in &member.dependencies {
+            self.db.insert_dependency(crate_id, dependency)?;
+        }
         stats.crates_indexed += 1;
+        self.db_time += db_start.elapsed();
 
         // Generate rustdoc JSON
-        let rustdoc_json = self.generate_rustdoc_json(member).await?;
+        let rustdoc_start = Instant::now();
+        let rustdoc_json = Self::generate_rustdoc_json(&self.workspace_root, &self.cancel, member).await?;
+        let rustdoc_time = rustdoc_start.elapsed();
 
-        // Parse the rustdoc JSON
-        match self.parse_rustdoc_json(&rustdoc_json) {
-            Ok(krate) => {
+        // Scan the rustdoc JSON for paths and impl membership
+        match stream::scan(&rustdoc_json, &self.cancel) {
+            Ok(scan) => {
                 info!(
-                    "Parsed rustdoc JSON for {}: {} items in index",
-                    member.name,
-                    krate.index.len()
+                    "Scanned rustdoc JSON for {}: {} items in index",
+                    member.name, scan.item_count
                 );
-                // Process the parsed rustdoc data
-                self.process_rustdoc_data(&krate, crate_id, &member.name, &mut stats)?;
+                let _scan_memory = self.memory.hold(scan.approx_bytes());
+                // Stream the items a second time and store their symbols
+                self.process_rustdoc_data(&rustdoc_json, &scan, crate_id, &member.name, &mut stats)?;
             }
             Err(e) => {
                 error!(
@@ -171,6 +732,31 @@ impl Indexer {
             }
         }
 
+        self.index_source_extras(member, crate_id, &mut stats)?;
+
+        // Edits to these mark the crate dirty even though they hold no symbols
+        let db_start = Instant::now();
+        for input in inputs::auxiliary_inputs(&member.path) {
+            let relative = normalize_span_path(&self.workspace_root, &input);
+            self.db.insert_input(crate_id, &relative.to_string_lossy())?;
+        }
+        self.db_time += db_start.elapsed();
+        self.flush_symbols()?;
+
+        // Files are inserted on first sight while symbols are extracted
+        stats.files_indexed = self.file_cache.len() - files_before;
+
+        let duration = crate_start.elapsed();
+        stats.crates.push(CrateIndexStats {
+            name: member.name.clone(),
+            symbols: stats.symbols_indexed,
+            files: stats.files_indexed,
+            duration_ms: duration.as_millis() as u64,
+            rustdoc_ms: rustdoc_time.as_millis() as u64,
+            parse_ms: duration.saturating_sub(rustdoc_time + self.db_time).as_millis() as u64,
+            db_ms: self.db_time.as_millis() as u64,
+        });
+
         Ok(stats)
     }
 
@@ -180,6 +766,29 @@ impl Indexer {
         hasher.update(member.version.as_bytes());
         hasher.update(member.package_id.as_bytes());
 
+        // How the crate is indexed: a new ct or other settings give other rows
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        let settings = (self.include_tests, self.include_derives, self.git_metadata);
+        hasher.update(format!("{:?}", (settings, &self.filter_module, &self.filter_struct)).as_bytes());
+        for pattern in self.path_filter.patterns() {
+            hasher.update(pattern.as_bytes());
+            hasher.update(b"\0");
+        }
+
+        // What the stored file digests cannot show: manifest and build script
+        // edits, source files added or removed, and auxiliary inputs
+        let mut files = vec![member.path.join("Cargo.toml"), member.path.join("build.rs")];
+        files.extend(inputs::auxiliary_inputs(&member.path));
+        for file in files {
+            hasher.update(file.to_string_lossy().as_bytes());
+            if let Ok(content) = std::fs::read(&file) {
+                hasher.update(&content);
+            }
+        }
+        for file in source::collect_rust_files(&member.path.join("src")).unwrap_or_default() {
+            hasher.update(file.to_string_lossy().as_bytes());
+        }
+
         // In real implementation, would include:
         // - rustc version hash
         // - features
@@ -189,31 +798,132 @@ impl Indexer {
         Ok(format!("blake3:{}", hasher.finalize().to_hex()))
     }
 
+    /// Names of the `members` indexed with the fingerprint they have now and
+    /// none of whose indexed files changed or disappeared since.
+    fn unchanged_members(&self, members: &[WorkspaceMember]) -> Result<HashSet<String>> {
+        let stored = self.db.crate_fingerprints()?;
+        if stored.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let stale: HashSet<String> = self
+            .db
+            .verify(&self.workspace_root)?
+            .stale_crates
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+
+        let mut unchanged = HashSet::new();
+        for member in members {
+            if stale.contains(&member.name) {
+                continue;
+            }
+            if stored.get(&member.name) == Some(&self.compute_crate_fingerprint(member)?) {
+                unchanged.insert(member.name.clone());
+            }
+        }
+        Ok(unchanged)
+    }
+
+    /// Reindex the members owning `changed_files`, one package at a time.
     pub async fn reindex_files(&mut self, changed_files: Vec<PathBuf>) -> Result<IndexStats> {
         info!("Reindexing {} changed files", changed_files.len());
 
-        // Stub: In real implementation, would:
-        // 1. Determine which crates are affected
-        // 2. Re-run rustdoc for those crates only
-        // 3. Update the database incrementally
+        let start = std::time::Instant::now();
+        let members = discovery::discover_workspace_members(&self.workspace_root).await?;
+        let mut affected = BTreeSet::new();
+        let mut deleted = Vec::new();
+        for file in &changed_files {
+            match self.owning_crate(file, &members)? {
+                Some(name) => {
+                    affected.insert(name);
+                }
+                None => info!("No crate owns {:?}, ignoring", file),
+            }
+            let absolute = self.workspace_root.join(file);
+            if !absolute.exists() {
+                deleted.push(normalize_span_path(&self.workspace_root, &absolute));
+            }
+        }
+
+        // Prune deleted files up front: a crate that still declares the
+        // removed module fails to rebuild, and its symbols must go regardless.
+        if !deleted.is_empty() {
+            self.begin()?;
+            let pruned = deleted
+                .iter()
+                .try_fold(0, |n, path| {
+                    let path = path.to_string_lossy();
+                    self.db.save_file_baseline(&path)?;
+                    self.db.delete_file(&path).map(|d| n + d)
+                });
+            match pruned {
+                Ok(n) => {
+                    self.commit()?;
+                    info!("Pruned {} deleted files from the index", n);
+                }
+                Err(e) => {
+                    self.db.rollback_transaction()?;
+                    return Err(e.into());
+                }
+            }
+        }
 
-        Ok(IndexStats::default())
+        let mut stats = IndexStats::default();
+        let total = affected.len();
+        for (done, name) in affected.into_iter().enumerate() {
+            self.report_progress(&name, done, total);
+            match self.index_package(&name).await {
+                Ok(crate_stats) => stats.merge(crate_stats),
+                Err(IndexError::Cancelled) => return Err(IndexError::Cancelled),
+                Err(e) => {
+                    warn!("Failed to reindex crate {}: {}", name, e);
+                    stats.failures.push(CrateFailure { name, error: e.to_string() });
+                }
+            }
+        }
+
+        stats.duration_ms = start.elapsed().as_millis() as u64;
+        Ok(stats)
     }
 
-    async fn generate_rustdoc_json(&self, member: &WorkspaceMember) -> Result<PathBuf> {
+    /// Resolve the member a changed file belongs to. Paths recorded from
+    /// rustdoc spans come first, so `#[path]` modules and `include!`d files
+    /// that live outside a member's directory still map to the crate that
+    /// compiles them; files not indexed yet fall back to the innermost
+    /// member directory containing them.
+    fn owning_crate(&self, file: &Path, members: &[WorkspaceMember]) -> Result<Option<String>> {
+        let relative = normalize_span_path(&self.workspace_root, file);
+        if let Some(name) = ct_db::queries::find_crate_for_file(self.db.conn(), &relative.to_string_lossy())? {
+            return Ok(Some(name));
+        }
+
+        let absolute = self.workspace_root.join(&relative);
+        Ok(members
+            .iter()
+            .filter(|m| absolute.starts_with(&m.path))
+            .max_by_key(|m| m.path.components().count())
+            .map(|m| m.name.clone()))
+    }
+
+    async fn generate_rustdoc_json(
+        workspace_root: &Path,
+        cancel: &CancelToken,
+        member: &WorkspaceMember,
+    ) -> Result<PathBuf> {
         info!("Generating rustdoc JSON for crate: {}", member.name);
 
         // Rustdoc outputs to workspace root's target/doc directory
-        let workspace_target_dir = self.workspace_root.join("target/doc");
+        let workspace_target_dir = workspace_root.join("target/doc");
         std::fs::create_dir_all(&workspace_target_dir)?;
 
         info!(
             "Running rustdoc for crate {} from directory {:?}",
-            member.name, self.workspace_root
+            member.name, workspace_root
         );
-        let output = Command::new("cargo")
-            .current_dir(&self.workspace_root)
-            .args(&[
+        let child = Command::new("cargo")
+            .current_dir(workspace_root)
+            .args([
                 "+nightly",
                 "rustdoc",
                 "-p",
@@ -226,10 +936,12 @@ impl Indexer {
                 "json",
                 "--document-private-items",
             ])
-            .output()?;
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (status, stderr) = cancel::wait_for_child(child, cancel).await?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !status.success() {
             error!("rustdoc failed for crate {}: {}", member.name, stderr);
             return Err(IndexError::IndexingFailed(format!(
                 "rustdoc failed for crate {}: {}",
@@ -270,95 +982,62 @@ impl Indexer {
         )))
     }
 
-    fn parse_rustdoc_json(&self, path: &Path) -> Result<Crate> {
-        let content = std::fs::read_to_string(path)?;
-        let krate: Crate = serde_json::from_str(&content)?;
-        Ok(krate)
-    }
-
     fn process_rustdoc_data(
         &mut self,
-        krate: &Crate,
+        rustdoc_json: &Path,
+        scan: &stream::CrateScan,
         crate_id: i64,
         crate_name: &str,
         stats: &mut IndexStats,
     ) -> Result<()> {
-        // Build path map for all local ids -> path segments
-        let mut path_map: HashMap<Id, Vec<String>> = HashMap::new();
-        for (id, summary) in &krate.paths {
-            if summary.crate_id == 0 {
-                path_map.insert(id.clone(), summary.path.clone());
-            }
-        }
-        let local_ids: HashSet<Id> = path_map.keys().cloned().collect();
-        
-        info!("Built path_map with {} entries for crate {}", path_map.len(), crate_name);
-        
-        // Debug: Check if we have paths for all indexed items
+        info!("Built path_map with {} entries for crate {}", scan.path_map.len(), crate_name);
+
+        info!("Processing {} items from rustdoc index", scan.item_count);
+        let mut items_processed = 0;
         let mut items_without_paths = 0;
-        for (id, item) in &krate.index {
-            if item.crate_id == 0 && !path_map.contains_key(id) {
-                items_without_paths += 1;
-                if let Some(name) = &item.name {
-                    warn!("Item {} (id: {:?}) has no entry in paths", name, id);
-                }
-            }
-        }
-        if items_without_paths > 0 {
-            warn!("{} items have no paths entries", items_without_paths);
-        }
 
-        // Track which items belong to which impl blocks
-        let mut impl_context_map: HashMap<Id, (Id, Option<Id>)> = HashMap::new();
+        stream::for_each_item(rustdoc_json, scan.format_version, |id, mut item| {
+            self.cancel.check()?;
 
-        // First pass: map items to their impl blocks
-        for (id, item) in &krate.index {
-            if item.crate_id != 0 {
-                continue;
+            // Store every file under the same workspace-relative name,
+            // whichever way rustdoc reached it
+            if let Some(span) = &mut item.span {
+                span.filename = normalize_span_path(&self.workspace_root, &span.filename);
             }
-            if let ItemEnum::Impl(imp) = &item.inner {
-                // Map all items in this impl to their parent impl
-                for item_id in &imp.items {
-                    impl_context_map.insert(
-                        item_id.clone(),
-                        (id.clone(), imp.trait_.as_ref().and_then(|path| Some(path.id.clone()))),
-                    );
-                }
-            }
-        }
 
-        info!("Processing {} items from rustdoc index", krate.index.len());
-        let mut items_processed = 0;
-
-        for (id, item) in &krate.index {
             // Filter: only process local crate items
             if item.crate_id != 0 {
-                continue;
+                return Ok(());
+            }
+
+            if !scan.path_map.contains_key(&id) {
+                items_without_paths += 1;
+                if let Some(name) = &item.name {
+                    warn!("Item {} (id: {:?}) has no entry in paths", name, id);
+                }
             }
 
             // Skip derive methods unless explicitly included
             if !self.include_derives {
                 if let Some(name) = &item.name {
                     if is_derive_method(name) && self.is_method_item(&item.inner) {
-                        continue;
+                        return Ok(());
                     }
                 }
             }
 
+            // Apply include/exclude globs before the file is recorded
+            if let Some(span) = &item.span {
+                if !self.path_filter.is_included(&span.filename) {
+                    return Ok(());
+                }
+            }
+
             // Extract symbol information
-            if let Some(symbol) = self.extract_symbol(
-                item,
-                id,
-                crate_id,
-                crate_name,
-                &path_map,
-                &impl_context_map,
-                &local_ids,
-                krate,
-            )? {
+            if let Some(symbol) = self.extract_symbol(&item, &id, crate_id, crate_name, scan)? {
                 // Apply module/struct filtering
                 if !self.should_process_symbol(&symbol) {
-                    continue;
+                    return Ok(());
                 }
                 
                 info!(
@@ -369,18 +1048,29 @@ impl Indexer {
                     symbol.path
                 );
                 
-                self.db.insert_symbol(&symbol)?;
+                if let Some(span) = &item.span {
+                    self.store_symbol(&symbol, &span.filename)?;
+                } else {
+                    self.queue_symbol(&symbol)?;
+                }
                 stats.symbols_indexed += 1;
                 items_processed += 1;
 
                 // Process impl blocks
                 if let ItemEnum::Impl(imp) = &item.inner {
                     if let Some(span) = &item.span {
-                        self.process_impl_block(imp, crate_id, span, crate_name)?;
+                        let db_start = Instant::now();
+                        self.process_impl_block(imp, crate_id, span, &scan.path_map)?;
+                        self.db_time += db_start.elapsed();
                         stats.symbols_indexed += 1;
                     }
                 }
             }
+            Ok(())
+        })?;
+
+        if items_without_paths > 0 {
+            warn!("{} items have no paths entries", items_without_paths);
         }
 
         info!(
@@ -391,17 +1081,84 @@ impl Indexer {
         Ok(())
     }
 
+    /// Queue a symbol for the next batched insert.
+    fn queue_symbol(&mut self, symbol: &Symbol) -> Result<()> {
+        self.pending_symbols.push(symbol.clone());
+        if self.pending_symbols.len() >= SYMBOL_BATCH_SIZE {
+            self.flush_symbols()?;
+        }
+        Ok(())
+    }
+
+    fn flush_symbols(&mut self) -> Result<()> {
+        if self.pending_symbols.is_empty() {
+            return Ok(());
+        }
+        let db_start = Instant::now();
+        self.db.insert_symbols_batch(&self.pending_symbols)?;
+        self.pending_symbols.clear();
+        self.db_time += db_start.elapsed();
+        Ok(())
+    }
+
+    /// Queue a symbol and insert its doc examples and, if enabled, git history.
+    fn store_symbol(&mut self, symbol: &Symbol, file: &Path) -> Result<()> {
+        self.queue_symbol(symbol)?;
+
+        let db_start = Instant::now();
+        if let Some(docs) = &symbol.docs {
+            for (idx, example) in extract_doc_examples(docs).iter().enumerate() {
+                self.db.insert_example(&symbol.symbol_id, idx, example)?;
+            }
+        }
+        self.db_time += db_start.elapsed();
+
+        // Fields and variants share their parent's history, skip the extra git calls
+        if self.git_metadata && !matches!(symbol.kind, SymbolKind::Field | SymbolKind::Variant) {
+            if let Some(history) =
+                git::span_history(self.base_dir(), file, symbol.span_start, symbol.span_end)
+            {
+                self.db.insert_symbol_history(&symbol.symbol_id, &history)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert the markers anywhere in `file`, whose row is `file_id`, dated by
+    /// `git blame` if git metadata is enabled.
+    fn store_markers(&mut self, file_id: i64, file: &Path, content: &str) -> Result<()> {
+        let lines: Vec<&str> = content.lines().collect();
+        let mut markers = extract_markers(&lines, 1);
+        if markers.is_empty() {
+            return Ok(());
+        }
+
+        if self.git_metadata {
+            if let Some(times) = git::line_times(self.base_dir(), file, 1, lines.len() as u32) {
+                for marker in &mut markers {
+                    marker.introduced = times.get(&marker.line).copied();
+                }
+            }
+        }
+
+        let db_start = Instant::now();
+        for marker in &markers {
+            self.db.insert_marker(file_id, marker)?;
+        }
+        self.db_time += db_start.elapsed();
+        Ok(())
+    }
+
     fn extract_symbol(
         &mut self,
         item: &Item,
         id: &Id,
         crate_id: i64,
         crate_name: &str,
-        path_map: &HashMap<Id, Vec<String>>,
-        impl_context_map: &HashMap<Id, (Id, Option<Id>)>,
-        local_ids: &HashSet<Id>,
-        krate: &Crate,
+        scan: &stream::CrateScan,
     ) -> Result<Option<Symbol>> {
+        let path_map = &scan.path_map;
         let name = match &item.name {
             Some(n) => n.clone(),
             None => return Ok(None),
@@ -414,6 +1171,10 @@ impl Indexer {
                 let generics_str = self.format_generics(&s.generics);
                 (SymbolKind::Struct, format!("struct {}{}", name, generics_str))
             }
+            ItemEnum::Union(u) => {
+                let generics_str = self.format_generics(&u.generics);
+                (SymbolKind::Union, format!("union {}{}", name, generics_str))
+            }
             ItemEnum::Enum(e) => {
                 let generics_str = self.format_generics(&e.generics);
                 (SymbolKind::Enum, format!("enum {}{}", name, generics_str))
@@ -430,10 +1191,15 @@ impl Indexer {
                     ),
                 )
             }
+            // Declared in an extern block: no body and a non-Rust ABI
+            ItemEnum::Function(f) if !f.has_body && !matches!(f.header.abi, Abi::Rust) => {
+                let sig = self.format_function_signature(&name, &f.sig, &f.generics, &f.header);
+                (SymbolKind::ForeignFn, format!("extern \"{}\" {}", abi_name(&f.header.abi), sig))
+            }
             ItemEnum::Function(f) => {
                 let sig = self.format_function_signature(&name, &f.sig, &f.generics, &f.header);
                 // Check if this function is inside an impl block (making it a method)
-                let kind = if impl_context_map.contains_key(id) {
+                let kind = if scan.impl_context_map.contains_key(id) {
                     SymbolKind::Method
                 } else {
                     SymbolKind::Fn
@@ -457,8 +1223,15 @@ impl Indexer {
                     ),
                 )
             }
+            ItemEnum::ExternType => (SymbolKind::ExternType, format!("extern type {}", name)),
             ItemEnum::Impl(_) => (SymbolKind::Impl, "impl".to_string()),
-            ItemEnum::Variant(_) => (SymbolKind::Variant, format!("{}", name)),
+            ItemEnum::Macro(definition) => {
+                (SymbolKind::Macro, source::macro_rules_signature(&name, definition))
+            }
+            ItemEnum::ProcMacro(m) => {
+                (SymbolKind::Macro, source::proc_macro_signature(&name, &m.kind, &m.helpers))
+            }
+            ItemEnum::Variant(_) => (SymbolKind::Variant, name.clone()),
             ItemEnum::StructField(_) => (SymbolKind::Field, name.clone()),
             _ => return Ok(None),
         };
@@ -469,25 +1242,21 @@ impl Indexer {
         };
 
         // Build the canonical path with module hierarchy
-        let path = if let Some((impl_id, trait_id)) = impl_context_map.get(id) {
+        let path = if let Some((impl_id, trait_id)) = scan.impl_context_map.get(id) {
             // This item is inside an impl block
-            if let Some(impl_item) = krate.index.get(impl_id) {
-                if let ItemEnum::Impl(imp) = &impl_item.inner {
-                    let for_type = self.extract_type_path(&imp.for_, path_map, local_ids);
-                    if let Some(trait_id) = trait_id {
-                        if let Some(trait_path) = path_map.get(trait_id) {
-                            // Trait impl method: crate::Type::trait::method
-                            format!("{}::{}::{}::{}", crate_name, for_type, trait_path.join("::"), name)
-                        } else {
-                            // Fallback
-                            format!("{}::{}::{}", crate_name, for_type, name)
-                        }
+            if let Some(for_) = scan.impl_for.get(impl_id) {
+                let for_type = self.extract_type_path(for_, path_map);
+                if let Some(trait_id) = trait_id {
+                    if let Some(trait_path) = path_map.get(trait_id) {
+                        // Trait impl method: crate::Type::trait::method
+                        format!("{}::{}::{}::{}", crate_name, for_type, trait_path.join("::"), name)
                     } else {
-                        // Inherent impl method: crate::Type::method
+                        // Fallback
                         format!("{}::{}::{}", crate_name, for_type, name)
                     }
                 } else {
-                    format!("{}::{}", crate_name, name)
+                    // Inherent impl method: crate::Type::method
+                    format!("{}::{}::{}", crate_name, for_type, name)
                 }
             } else {
                 format!("{}::{}", crate_name, name)
@@ -525,22 +1294,7 @@ impl Indexer {
             IndexError::IndexingFailed(format!("Item {} has no span information", name))
         })?;
         let file_path = self.workspace_root.join(&span.filename);
-        
-        // Ensure file is in database
-        let file_id = if let Some(&fid) = self.file_cache.get(&span.filename.to_string_lossy().to_string()) {
-            fid
-        } else {
-            let digest = if file_path.exists() {
-                let content = std::fs::read(&file_path)?;
-                compute_file_digest(&content)
-            } else {
-                "missing".to_string()
-            };
-
-            let fid = self.db.insert_file(crate_id, &span.filename.to_string_lossy(), &digest)?;
-            self.file_cache.insert(span.filename.to_string_lossy().to_string(), fid);
-            fid
-        };
+        let file_id = self.file_id_for(crate_id, &span.filename)?;
 
         let symbol_id = compute_symbol_id(
             &path,
@@ -583,35 +1337,28 @@ impl Indexer {
         imp: &rustdoc_types::Impl,
         crate_id: i64,
         span: &rustdoc_types::Span,
-        crate_name: &str,
+        path_map: &HashMap<Id, Vec<String>>,
     ) -> Result<()> {
-        // Extract the type being implemented for
+        // Blanket impls (`impl<T> From<T> for T`) are listed under every
+        // type but live in another crate's source
+        if imp.blanket_impl.is_some() {
+            return Ok(());
+        }
+
+        // Full paths where rustdoc knows them, otherwise as written
+        let resolve = |path: &rustdoc_types::Path| {
+            path_map
+                .get(&path.id)
+                .map(|segments| segments.join("::"))
+                .unwrap_or_else(|| path.path.clone())
+        };
         let for_path = match &imp.for_ {
-            Type::ResolvedPath(path) => {
-                format!("{}::{}", crate_name, path.id.0)
-            }
-            _ => "unknown".to_string(),
+            Type::ResolvedPath(path) => resolve(path),
+            other => self.format_type(other),
         };
+        let trait_path = imp.trait_.as_ref().map(resolve);
 
-        // Extract trait path if this is a trait impl
-        let trait_path = imp.trait_.as_ref().map(|path| path.id.0.to_string());
-
-        // Get or create file ID
-        let file_id = if let Some(&fid) = self.file_cache.get(&span.filename.to_string_lossy().to_string()) {
-            fid
-        } else {
-            let file_path = self.workspace_root.join(&span.filename);
-            let digest = if file_path.exists() {
-                let content = std::fs::read(&file_path)?;
-                compute_file_digest(&content)
-            } else {
-                "missing".to_string()
-            };
-
-            let fid = self.db.insert_file(crate_id, &span.filename.to_string_lossy(), &digest)?;
-            self.file_cache.insert(span.filename.to_string_lossy().to_string(), fid);
-            fid
-        };
+        let file_id = self.file_id_for(crate_id, &span.filename)?;
 
         let impl_block = ImplBlock {
             id: 0, // Will be set by database
@@ -627,13 +1374,33 @@ impl Indexer {
         Ok(())
     }
 
-    fn extract_type_path(&self, ty: &Type, path_map: &HashMap<Id, Vec<String>>, local_ids: &HashSet<Id>) -> String {
+    /// Look up or insert the `files` row for a workspace-relative span filename.
+    fn file_id_for(&mut self, crate_id: i64, filename: &Path) -> Result<i64> {
+        let name = filename.to_string_lossy().to_string();
+        if let Some(&fid) = self.file_cache.get(&name) {
+            return Ok(fid);
+        }
+
+        let file_path = self.workspace_root.join(filename);
+        let content = if file_path.exists() { Some(std::fs::read(&file_path)?) } else { None };
+        let digest = match &content {
+            Some(content) => compute_file_digest(content),
+            None => "missing".to_string(),
+        };
+
+        let fid = self.db.insert_file(crate_id, &name, &digest)?;
+        self.file_cache.insert(name, fid);
+        if let Some(content) = content {
+            self.store_markers(fid, filename, &String::from_utf8_lossy(&content))?;
+        }
+        Ok(fid)
+    }
+
+    fn extract_type_path(&self, ty: &Type, path_map: &HashMap<Id, Vec<String>>) -> String {
         match ty {
             Type::ResolvedPath(path) => {
                 if let Some(path_segments) = path_map.get(&path.id) {
                     path_segments.join("::")
-                } else if local_ids.contains(&path.id) {
-                    format!("type_{}", path.id.0)
                 } else {
                     "external".to_string()
                 }
@@ -653,12 +1420,93 @@ impl Indexer {
         let params: Vec<String> = generics
             .params
             .iter()
-            .map(|p| p.name.clone())
+            .map(|p| match &p.kind {
+                GenericParamDefKind::Const { type_, default } => {
+                    let mut param = format!("const {}: {}", p.name, self.format_type(type_));
+                    if let Some(default) = default {
+                        param.push_str(&format!(" = {}", default));
+                    }
+                    param
+                }
+                _ => p.name.clone(),
+            })
             .collect();
 
         format!("<{}>", params.join(", "))
     }
 
+    /// Render a rustdoc type as Rust source, falling back to `_` for shapes
+    /// we don't print yet.
+    fn format_type(&self, ty: &Type) -> String {
+        match ty {
+            Type::ResolvedPath(path) => {
+                let mut result = path.path.clone();
+                if let Some(args) = &path.args {
+                    result.push_str(&self.format_generic_args(args));
+                }
+                result
+            }
+            Type::Primitive(p) => p.clone(),
+            Type::Generic(g) => g.clone(),
+            Type::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(|t| self.format_type(t)).collect();
+                if types.len() == 1 {
+                    format!("({},)", types[0])
+                } else {
+                    format!("({})", types.join(", "))
+                }
+            }
+            Type::Slice(inner) => format!("[{}]", self.format_type(inner)),
+            Type::Array { type_, len } => format!("[{}; {}]", self.format_type(type_), len),
+            Type::BorrowedRef { lifetime, is_mutable, type_ } => {
+                let mut result = String::from("&");
+                if let Some(lifetime) = lifetime {
+                    result.push_str(lifetime);
+                    result.push(' ');
+                }
+                if *is_mutable {
+                    result.push_str("mut ");
+                }
+                result.push_str(&self.format_type(type_));
+                result
+            }
+            Type::RawPointer { is_mutable, type_ } => format!(
+                "*{} {}",
+                if *is_mutable { "mut" } else { "const" },
+                self.format_type(type_)
+            ),
+            _ => "_".to_string(),
+        }
+    }
+
+    fn format_generic_args(&self, args: &GenericArgs) -> String {
+        match args {
+            GenericArgs::AngleBracketed { args, .. } => {
+                if args.is_empty() {
+                    return String::new();
+                }
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        GenericArg::Lifetime(l) => l.clone(),
+                        GenericArg::Type(t) => self.format_type(t),
+                        GenericArg::Const(c) => c.expr.clone(),
+                        GenericArg::Infer => "_".to_string(),
+                    })
+                    .collect();
+                format!("<{}>", args.join(", "))
+            }
+            GenericArgs::Parenthesized { inputs, output } => {
+                let inputs: Vec<String> = inputs.iter().map(|t| self.format_type(t)).collect();
+                let mut result = format!("({})", inputs.join(", "));
+                if let Some(output) = output {
+                    result.push_str(&format!(" -> {}", self.format_type(output)));
+                }
+                result
+            }
+        }
+    }
+
     fn format_function_signature(
         &self,
         name: &str,
@@ -685,23 +1533,40 @@ impl Indexer {
         result.push('(');
 
         // Add parameters
-        for (i, (param_name, _param_type)) in sig.inputs.iter().enumerate() {
+        for (i, (param_name, param_type)) in sig.inputs.iter().enumerate() {
             if i > 0 {
                 result.push_str(", ");
             }
-            result.push_str(param_name);
+            result.push_str(&self.format_param(param_name, param_type));
         }
 
         result.push(')');
 
         // Add return type
-        if let Some(_output) = &sig.output {
-            result.push_str(" -> _");
+        if let Some(output) = &sig.output {
+            result.push_str(" -> ");
+            result.push_str(&self.format_type(output));
         }
 
         result
     }
 
+    /// `name: Type`, with the usual receiver shorthands for `self`.
+    fn format_param(&self, name: &str, ty: &Type) -> String {
+        if name == "self" {
+            match ty {
+                Type::Generic(g) if g == "Self" => return "self".to_string(),
+                Type::BorrowedRef { lifetime, is_mutable, type_ } if matches!(&**type_, Type::Generic(g) if g == "Self") => {
+                    let lifetime = lifetime.as_ref().map(|l| format!("{} ", l)).unwrap_or_default();
+                    let mutability = if *is_mutable { "mut " } else { "" };
+                    return format!("&{}{}self", lifetime, mutability);
+                }
+                _ => {}
+            }
+        }
+        format!("{}: {}", name, self.format_type(ty))
+    }
+
     fn detect_implementation_status(
         &self,
         file_path: &Path,
@@ -720,19 +1585,7 @@ impl Indexer {
 
             // Check the function body for unimplemented! or todo!
             let body_text = lines[start_line..end_line].join("\n");
-
-            // Look for unimplemented!() macro
-            if body_text.contains("unimplemented!") {
-                return Ok(ImplementationStatus::Unimplemented);
-            }
-
-            // Look for todo!() macro or TODO/FIXME comments
-            if body_text.contains("todo!")
-                || body_text.contains("TODO")
-                || body_text.contains("FIXME")
-            {
-                return Ok(ImplementationStatus::Todo);
-            }
+            return Ok(implementation_status(&body_text));
         }
 
         Ok(ImplementationStatus::Implemented)
@@ -767,19 +1620,104 @@ impl Indexer {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct IndexStats {
     pub crates_indexed: usize,
+    /// Members `with_skip_unchanged` left as they were.
+    pub crates_unchanged: usize,
     pub files_indexed: usize,
     pub symbols_indexed: usize,
     pub duration_ms: u64,
+    /// One entry per crate indexed in this run.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub crates: Vec<CrateIndexStats>,
+    /// Members that failed during `index_workspace`; their rows were rolled back.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<CrateFailure>,
+}
+
+/// Where a run is: `crate_name` is starting with `done` of `total` crates
+/// already behind it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndexProgress {
+    pub crate_name: String,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Receives `IndexProgress` during a run; it is called on the indexing
+/// task, so it should return quickly.
+pub type ProgressFn = Arc<dyn Fn(&IndexProgress) + Send + Sync>;
+
+/// The ABI string as written in `extern "..."`.
+fn abi_name(abi: &Abi) -> String {
+    let (name, unwind) = match abi {
+        Abi::Rust => ("Rust", false),
+        Abi::C { unwind } => ("C", *unwind),
+        Abi::Cdecl { unwind } => ("cdecl", *unwind),
+        Abi::Stdcall { unwind } => ("stdcall", *unwind),
+        Abi::Fastcall { unwind } => ("fastcall", *unwind),
+        Abi::Aapcs { unwind } => ("aapcs", *unwind),
+        Abi::Win64 { unwind } => ("win64", *unwind),
+        Abi::SysV64 { unwind } => ("sysv64", *unwind),
+        Abi::System { unwind } => ("system", *unwind),
+        Abi::Other(other) => return other.clone(),
+    };
+    if unwind {
+        format!("{}-unwind", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Express a file path relative to `root` with `.` and `..` resolved.
+///
+/// rustdoc reports spans using the path the compiler opened, so a
+/// `#[path = "../gen/api.rs"]` module or an `include!` of an absolute path
+/// shows up as `crate_a/src/../gen/api.rs` or `/abs/ws/gen/api.rs`. These
+/// become `crate_a/gen/api.rs` and `gen/api.rs`, the same names a watcher
+/// event for the file normalizes to.
+pub fn normalize_span_path(root: &Path, path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let can_pop = matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                );
+                if can_pop {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    if let Ok(relative) = normalized.strip_prefix(root) {
+        return relative.to_path_buf();
+    }
+    // The root may be spelled differently from the canonical path rustdoc saw
+    match root.canonicalize() {
+        Ok(canonical) => normalized
+            .strip_prefix(&canonical)
+            .map(Path::to_path_buf)
+            .unwrap_or(normalized),
+        Err(_) => normalized,
+    }
 }
 
 impl IndexStats {
     fn merge(&mut self, other: IndexStats) {
         self.crates_indexed += other.crates_indexed;
+        self.crates_unchanged += other.crates_unchanged;
         self.files_indexed += other.files_indexed;
         self.symbols_indexed += other.symbols_indexed;
+        self.crates.extend(other.crates);
+        self.failures.extend(other.failures);
     }
 }
 
@@ -799,4 +1737,235 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_member_selection() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open_temp(temp_dir.path().join("test.db").as_path())
+            .map_err(IndexError::Database)?;
+
+        let indexer = Indexer::new(temp_dir.path().to_path_buf(), db)
+            .with_members(vec![], vec!["huge".to_string()]);
+        assert!(indexer.is_member_selected("core"));
+        assert!(!indexer.is_member_selected("huge"));
+
+        let indexer = indexer.with_members(vec!["core".to_string()], vec![]);
+        assert!(indexer.is_member_selected("core"));
+        assert!(!indexer.is_member_selected("cli"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_crate_is_reported() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("ws");
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"broken\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )?;
+        std::fs::write(root.join("src/lib.rs"), "pub fn broken( {\n")?;
+
+        let db = Database::open_temp(temp_dir.path().join("test.db").as_path())
+            .map_err(IndexError::Database)?;
+        let mut indexer = Indexer::new(root, db);
+
+        let stats = indexer.index_workspace().await?;
+        assert_eq!(stats.crates_indexed, 0);
+        assert_eq!(stats.failures.len(), 1);
+        assert_eq!(stats.failures[0].name, "broken");
+        assert_eq!(indexer.db.get_crate_count()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unchanged_members() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("ws");
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(root.join("Cargo.toml"), "[package]\nname = \"lib\"\nversion = \"0.1.0\"\n")?;
+        std::fs::write(root.join("src/lib.rs"), "pub fn run() {}\n")?;
+        let member = WorkspaceMember {
+            name: "lib".to_string(),
+            version: "0.1.0".to_string(),
+            path: root.clone(),
+            package_id: "lib 0.1.0".to_string(),
+            dependencies: vec![],
+        };
+
+        let db = Database::open_temp(temp_dir.path().join("test.db").as_path())
+            .map_err(IndexError::Database)?;
+        let indexer = Indexer::new(root.clone(), db);
+        let members = std::slice::from_ref(&member);
+        assert!(indexer.unchanged_members(members)?.is_empty());
+
+        let crate_id = indexer.db.insert_crate("lib", Some("0.1.0"), &indexer.compute_crate_fingerprint(&member)?)?;
+        indexer.db.insert_file(crate_id, "src/lib.rs", &compute_file_digest(b"pub fn run() {}\n"))?;
+        assert!(indexer.unchanged_members(members)?.contains("lib"));
+
+        // An edited file
+        std::fs::write(root.join("src/lib.rs"), "pub fn run() { todo!() }\n")?;
+        assert!(indexer.unchanged_members(members)?.is_empty());
+        std::fs::write(root.join("src/lib.rs"), "pub fn run() {}\n")?;
+
+        // A new source file, or other settings, change the fingerprint
+        std::fs::write(root.join("src/extra.rs"), "")?;
+        assert!(indexer.unchanged_members(members)?.is_empty());
+        std::fs::remove_file(root.join("src/extra.rs"))?;
+        assert!(indexer.unchanged_members(members)?.contains("lib"));
+        let indexer = indexer.with_tests(true);
+        assert!(indexer.unchanged_members(members)?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_index_plain_directory() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("vendored");
+        std::fs::create_dir_all(root.join("net"))?;
+        std::fs::write(
+            root.join("lib.rs"),
+            "pub mod net;\npub struct Client;\n\npub fn open() -> Client {\n    Client::connect(&Client);\n    Client\n}\n",
+        )?;
+        std::fs::write(
+            root.join("net/mod.rs"),
+            "impl crate::Client {\n    pub fn connect(&self) {\n        todo!(\"handshake\")\n    }\n}\n\n// TODO: tls\n",
+        )?;
+        std::fs::write(root.join("net/broken.rs"), "fn broken( {\n")?;
+
+        let db = Database::open_temp(temp_dir.path().join("test.db").as_path())
+            .map_err(IndexError::Database)?;
+        let mut indexer = Indexer::new(root, db);
+
+        let stats = indexer.index_workspace().await?;
+        assert_eq!(stats.crates_indexed, 1);
+        assert_eq!(stats.files_indexed, 2);
+        assert_eq!(stats.crates.len(), 1);
+        assert_eq!(stats.crates[0].name, "vendored");
+        assert_eq!(stats.crates[0].files, 2);
+        assert_eq!(stats.crates[0].symbols, stats.symbols_indexed);
+        assert_eq!(stats.crates[0].rustdoc_ms, 0);
+
+        let connect = ct_db::queries::find_symbol_by_path(indexer.db.conn(), "vendored::Client::connect")?
+            .expect("method indexed");
+        assert_eq!(connect.kind, SymbolKind::Method);
+        assert_eq!(connect.status, ImplementationStatus::Todo);
+        assert!(ct_db::queries::find_symbol_by_path(indexer.db.conn(), "vendored::Client")?.is_some());
+
+        let markers = ct_db::queries::get_markers(indexer.db.conn(), None, 10)?;
+        assert_eq!(markers.len(), 2);
+        assert_eq!((markers[0].path.as_str(), markers[0].file.as_str()), ("vendored::Client::connect", "net/mod.rs"));
+        assert_eq!((markers[0].marker.line, markers[0].marker.message.as_str()), (3, "handshake"));
+        // Outside any function, so it was missed when only their bodies were scanned
+        assert_eq!(markers[1].marker.line, 7);
+
+        let refs = ct_db::queries::find_references(indexer.db.conn(), "vendored::Client", 10)?;
+        let sites: Vec<_> = refs
+            .iter()
+            .map(|r| (r.from_path.as_str(), r.crate_name.as_str(), r.file.as_str(), r.span_start, r.span_col))
+            .collect();
+        assert_eq!(sites, vec![
+            ("vendored::open", "vendored", "lib.rs", 4, Some(18)),
+            ("vendored::open", "vendored", "lib.rs", 5, Some(22)),
+            ("vendored::open", "vendored", "lib.rs", 6, Some(5)),
+        ]);
+        let callers = ct_db::queries::find_callers(indexer.db.conn(), "vendored::Client::connect", 10)?;
+        assert_eq!(callers.len(), 1);
+        assert_eq!(callers[0].caller_path, "vendored::open");
+
+        // Every committed run is a new epoch
+        assert_eq!(ct_db::queries::get_index_epoch(indexer.db.conn())?, 1);
+        indexer.index_workspace().await?;
+        assert_eq!(ct_db::queries::get_index_epoch(indexer.db.conn())?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_span_path() {
+        let root = Path::new("/ws");
+        assert_eq!(
+            normalize_span_path(root, Path::new("crate_a/src/../gen/api.rs")),
+            PathBuf::from("crate_a/gen/api.rs")
+        );
+        assert_eq!(
+            normalize_span_path(root, Path::new("/ws/./shared/out.rs")),
+            PathBuf::from("shared/out.rs")
+        );
+        assert_eq!(
+            normalize_span_path(root, Path::new("../vendor/lib.rs")),
+            PathBuf::from("../vendor/lib.rs")
+        );
+        assert_eq!(
+            normalize_span_path(root, Path::new("/elsewhere/lib.rs")),
+            PathBuf::from("/elsewhere/lib.rs")
+        );
+    }
+
+    #[test]
+    fn test_format_const_generics() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open_temp(temp_dir.path().join("test.db").as_path())
+            .map_err(IndexError::Database)?;
+        let indexer = Indexer::new(temp_dir.path().to_path_buf(), db);
+
+        let generics = rustdoc_types::Generics {
+            params: vec![
+                rustdoc_types::GenericParamDef {
+                    name: "T".to_string(),
+                    kind: GenericParamDefKind::Type {
+                        bounds: vec![],
+                        default: None,
+                        is_synthetic: false,
+                    },
+                },
+                rustdoc_types::GenericParamDef {
+                    name: "N".to_string(),
+                    kind: GenericParamDefKind::Const {
+                        type_: Type::Primitive("usize".to_string()),
+                        default: Some("4".to_string()),
+                    },
+                },
+            ],
+            where_predicates: vec![],
+        };
+        assert_eq!(indexer.format_generics(&generics), "<T, const N: usize = 4>");
+
+        let array = Type::Array {
+            type_: Box::new(Type::Generic("T".to_string())),
+            len: "N".to_string(),
+        };
+        assert_eq!(indexer.format_type(&array), "[T; N]");
+
+        let sig = rustdoc_types::FunctionSignature {
+            inputs: vec![
+                (
+                    "self".to_string(),
+                    Type::BorrowedRef {
+                        lifetime: None,
+                        is_mutable: true,
+                        type_: Box::new(Type::Generic("Self".to_string())),
+                    },
+                ),
+                ("len".to_string(), Type::Primitive("usize".to_string())),
+            ],
+            output: Some(Type::Slice(Box::new(Type::Generic("T".to_string())))),
+            is_c_variadic: false,
+        };
+        let header = rustdoc_types::FunctionHeader {
+            is_const: false,
+            is_unsafe: false,
+            is_async: false,
+            abi: Abi::Rust,
+        };
+        assert_eq!(
+            indexer.format_function_signature("take", &sig, &generics, &header),
+            "fn take<T, const N: usize = 4>(&mut self, len: usize) -> [T]"
+        );
+
+        Ok(())
+    }
 }
\ No newline at end of file
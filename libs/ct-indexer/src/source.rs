@@ -0,0 +1,1045 @@
+//! syn backend: extracts symbols by parsing `.rs` files directly, for plain
+//! directories or single files that cargo and rustdoc cannot describe.
+
+use crate::Result;
+use ct_core::models::{SymbolKind, Visibility};
+use proc_macro2::{Span, TokenStream, TokenTree};
+use rustdoc_types::MacroKind;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Expr, ForeignItem, ImplItem, Item, Lit, Meta, TraitItem, Token};
+
+/// A symbol found in source, before it is given file and crate ids.
+#[derive(Debug, Clone)]
+pub struct SourceItem {
+    pub path: String,
+    pub name: String,
+    pub kind: SymbolKind,
+    pub visibility: Visibility,
+    pub signature: String,
+    pub docs: Option<String>,
+    pub line_start: u32,
+    pub line_end: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceImpl {
+    pub for_path: String,
+    pub trait_path: Option<String>,
+    pub line_start: u32,
+    pub line_end: u32,
+}
+
+/// A path named in the signature or body of the item at `from_path`,
+/// resolved against its module's `use` declarations where possible and
+/// otherwise as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceReference {
+    pub from_path: String,
+    pub target_path: String,
+    pub line_start: u32,
+    pub line_end: u32,
+    /// 1-based column on `line_start` the path starts at
+    pub column: u32,
+    /// The path is called: `path(..)`, or `self.method(..)` in an impl.
+    pub call: bool,
+}
+
+/// How items compiled only under `cfg(test)` are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestItems {
+    Skip,
+    Include,
+    /// Only test items; used to add them on top of rustdoc output.
+    Only,
+    /// No items, only the references of non-test ones; used on top of
+    /// rustdoc output when tests are not indexed.
+    None,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedSource {
+    pub items: Vec<SourceItem>,
+    pub impls: Vec<SourceImpl>,
+    pub references: Vec<SourceReference>,
+}
+
+/// Parse one file whose items live in `module_path`.
+pub fn parse_source(content: &str, module_path: &str, tests: TestItems) -> Result<ParsedSource> {
+    let file = syn::parse_file(content)?;
+    let mut parser = SourceParser {
+        lines: content.lines().collect(),
+        out: ParsedSource::default(),
+        tests,
+        in_test: false,
+        scope: Scope::default(),
+        self_type: None,
+        impl_generics: Vec::new(),
+    };
+    parser.visit_items(&file.items, module_path);
+    Ok(parser.out)
+}
+
+/// `macro_rules! name { (matcher); ... }` with the arm bodies dropped, from a
+/// definition as written or as rustdoc renders it. Falls back to the bare
+/// header if `source` does not tokenize.
+pub fn macro_rules_signature(name: &str, source: &str) -> String {
+    let Ok(tokens) = source.parse::<TokenStream>() else {
+        return format!("macro_rules! {}", name);
+    };
+    // The rules are the last group: `macro_rules! name { ... }`
+    let rules = tokens.into_iter().filter_map(|tt| match tt {
+        TokenTree::Group(group) => Some(group.stream()),
+        _ => None,
+    });
+    let lines: Vec<&str> = source.lines().collect();
+    let matchers = match rules.last() {
+        Some(rules) => macro_matchers(rules)
+            .into_iter()
+            .map(|span| text_of(&lines, span))
+            .collect(),
+        None => Vec::new(),
+    };
+    format_macro_rules(name, &matchers)
+}
+
+/// How a procedural macro is invoked, e.g. `#[derive(Serialize)]`.
+pub fn proc_macro_signature(name: &str, kind: &MacroKind, helpers: &[String]) -> String {
+    match kind {
+        MacroKind::Bang => format!("{}!(..)", name),
+        MacroKind::Attr => format!("#[{}]", name),
+        MacroKind::Derive if helpers.is_empty() => format!("#[derive({})]", name),
+        MacroKind::Derive => format!("#[derive({})] attributes({})", name, helpers.join(", ")),
+    }
+}
+
+fn format_macro_rules(name: &str, matchers: &[String]) -> String {
+    if matchers.is_empty() {
+        format!("macro_rules! {}", name)
+    } else {
+        format!("macro_rules! {} {{ {} }}", name, matchers.join("; "))
+    }
+}
+
+/// Spans of the matcher groups in a `macro_rules!` body: each group that is
+/// followed by `=>`.
+fn macro_matchers(rules: TokenStream) -> Vec<Span> {
+    let tokens: Vec<TokenTree> = rules.into_iter().collect();
+    tokens
+        .windows(3)
+        .filter_map(|w| match w {
+            [TokenTree::Group(matcher), TokenTree::Punct(eq), TokenTree::Punct(gt)]
+                if eq.as_char() == '=' && gt.as_char() == '>' =>
+            {
+                Some(matcher.span())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The proc-macro kind, name and helper attributes declared by a function's
+/// `#[proc_macro*]` attribute, if it has one.
+fn proc_macro_decl(f: &syn::ItemFn) -> Option<(MacroKind, String, Vec<String>)> {
+    for attr in &f.attrs {
+        if attr.path().is_ident("proc_macro") {
+            return Some((MacroKind::Bang, f.sig.ident.to_string(), Vec::new()));
+        }
+        if attr.path().is_ident("proc_macro_attribute") {
+            return Some((MacroKind::Attr, f.sig.ident.to_string(), Vec::new()));
+        }
+        if attr.path().is_ident("proc_macro_derive") {
+            let args = attr
+                .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                .ok()?;
+            let name = args.first()?.path().get_ident()?.to_string();
+            let helpers = args
+                .iter()
+                .filter_map(|meta| match meta {
+                    Meta::List(list) if list.path.is_ident("attributes") => list
+                        .parse_args_with(Punctuated::<syn::Ident, Token![,]>::parse_terminated)
+                        .ok(),
+                    _ => None,
+                })
+                .flatten()
+                .map(|ident| ident.to_string())
+                .collect();
+            return Some((MacroKind::Derive, name, helpers));
+        }
+    }
+    None
+}
+
+/// All `.rs` files under `root` (or `root` itself), skipping `target` and
+/// hidden directories, in a stable order.
+pub fn collect_rust_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if root.is_file() {
+        files.push(root.to_path_buf());
+        return Ok(files);
+    }
+
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .map(|n| n.to_string_lossy().starts_with('.') || n == "target")
+                .unwrap_or(false);
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Module path of a file relative to the indexed directory: a leading `src`
+/// and trailing `lib`/`main`/`mod` segments are dropped.
+pub fn module_path_for(relative: &Path, crate_name: &str) -> String {
+    let mut segments: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+
+    if segments.first().is_some_and(|s| s == "src") {
+        segments.remove(0);
+    }
+    if segments
+        .last()
+        .is_some_and(|s| s == "lib" || s == "main" || s == "mod")
+    {
+        segments.pop();
+    }
+
+    std::iter::once(crate_name.to_string())
+        .chain(segments)
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+struct SourceParser<'a> {
+    lines: Vec<&'a str>,
+    out: ParsedSource,
+    tests: TestItems,
+    /// Inside a `#[cfg(test)]` item or a `#[test]` function.
+    in_test: bool,
+    /// Names visible in the module being visited.
+    scope: Scope,
+    /// What `Self` names inside the current impl or trait.
+    self_type: Option<String>,
+    /// Type parameters of the current impl.
+    impl_generics: Vec<String>,
+}
+
+impl SourceParser<'_> {
+    fn visit_items(&mut self, items: &[Item], module: &str) {
+        let outer_scope = std::mem::replace(&mut self.scope, Scope::new(items, module));
+        for item in items {
+            let is_test = self.in_test || is_test_item(item_attrs(item));
+            if is_test && matches!(self.tests, TestItems::Skip | TestItems::None) {
+                continue;
+            }
+            let outer = std::mem::replace(&mut self.in_test, is_test);
+            match item {
+                Item::Mod(m) => {
+                    let path = format!("{}::{}", module, m.ident);
+                    let signature = format!("mod {}", m.ident);
+                    self.push(&path, &m.ident, SymbolKind::Module, &m.vis, signature, &m.attrs, m.span());
+                    if let Some((_, items)) = &m.content {
+                        self.visit_items(items, &path);
+                    }
+                }
+                Item::Struct(s) => {
+                    let path = format!("{}::{}", module, s.ident);
+                    let signature = format!("struct {}{}", s.ident, self.generics_text(&s.generics));
+                    self.push(&path, &s.ident, SymbolKind::Struct, &s.vis, signature, &s.attrs, s.span());
+                    self.record_refs(&path, |refs| refs.visit_item_struct(s));
+                    for field in &s.fields {
+                        if let Some(ident) = &field.ident {
+                            let field_path = format!("{}::{}", path, ident);
+                            self.push(&field_path, ident, SymbolKind::Field, &field.vis, ident.to_string(), &field.attrs, field.span());
+                        }
+                    }
+                }
+                Item::Union(u) => {
+                    let path = format!("{}::{}", module, u.ident);
+                    let signature = format!("union {}{}", u.ident, self.generics_text(&u.generics));
+                    self.push(&path, &u.ident, SymbolKind::Union, &u.vis, signature, &u.attrs, u.span());
+                    self.record_refs(&path, |refs| refs.visit_item_union(u));
+                    for field in &u.fields.named {
+                        if let Some(ident) = &field.ident {
+                            let field_path = format!("{}::{}", path, ident);
+                            self.push(&field_path, ident, SymbolKind::Field, &field.vis, ident.to_string(), &field.attrs, field.span());
+                        }
+                    }
+                }
+                Item::Enum(e) => {
+                    let path = format!("{}::{}", module, e.ident);
+                    let signature = format!("enum {}{}", e.ident, self.generics_text(&e.generics));
+                    self.push(&path, &e.ident, SymbolKind::Enum, &e.vis, signature, &e.attrs, e.span());
+                    self.record_refs(&path, |refs| refs.visit_item_enum(e));
+                    for variant in &e.variants {
+                        let variant_path = format!("{}::{}", path, variant.ident);
+                        // Variants carry no visibility of their own, as in rustdoc
+                        self.push(&variant_path, &variant.ident, SymbolKind::Variant, &syn::Visibility::Inherited, variant.ident.to_string(), &variant.attrs, variant.span());
+                    }
+                }
+                Item::Trait(t) => {
+                    let path = format!("{}::{}", module, t.ident);
+                    let signature = format!(
+                        "{}trait {}{}",
+                        if t.unsafety.is_some() { "unsafe " } else { "" },
+                        t.ident,
+                        self.generics_text(&t.generics)
+                    );
+                    self.push(&path, &t.ident, SymbolKind::Trait, &t.vis, signature, &t.attrs, t.span());
+                    let outer_self = self.self_type.replace(path.clone());
+                    for trait_item in &t.items {
+                        if let TraitItem::Fn(f) = trait_item {
+                            let method_path = format!("{}::{}", path, f.sig.ident);
+                            let signature = self.span_text(f.sig.span());
+                            self.push(&method_path, &f.sig.ident, SymbolKind::Method, &syn::Visibility::Inherited, signature, &f.attrs, f.span());
+                            self.record_refs(&method_path, |refs| refs.visit_trait_item_fn(f));
+                        }
+                    }
+                    self.self_type = outer_self;
+                }
+                Item::Fn(f) => {
+                    if let Some((kind, name, helpers)) = proc_macro_decl(f) {
+                        // Proc macros are known by their macro name, which for derives
+                        // is not the function's
+                        let ident = syn::Ident::new(&name, f.sig.ident.span());
+                        let path = format!("{}::{}", module, name);
+                        let signature = proc_macro_signature(&name, &kind, &helpers);
+                        self.push(&path, &ident, SymbolKind::Macro, &f.vis, signature, &f.attrs, f.span());
+                    } else {
+                        let path = format!("{}::{}", module, f.sig.ident);
+                        let signature = self.span_text(f.sig.span());
+                        self.push(&path, &f.sig.ident, SymbolKind::Fn, &f.vis, signature, &f.attrs, f.span());
+                        self.record_refs(&path, |refs| refs.visit_item_fn(f));
+                    }
+                }
+                Item::Type(t) => {
+                    let path = format!("{}::{}", module, t.ident);
+                    let signature = format!("type {}{}", t.ident, self.generics_text(&t.generics));
+                    self.push(&path, &t.ident, SymbolKind::TypeAlias, &t.vis, signature, &t.attrs, t.span());
+                    self.record_refs(&path, |refs| refs.visit_item_type(t));
+                }
+                Item::Const(c) => {
+                    let path = format!("{}::{}", module, c.ident);
+                    let signature = format!("const {}: _", c.ident);
+                    self.push(&path, &c.ident, SymbolKind::Const, &c.vis, signature, &c.attrs, c.span());
+                    self.record_refs(&path, |refs| refs.visit_item_const(c));
+                }
+                Item::Static(s) => {
+                    let path = format!("{}::{}", module, s.ident);
+                    let is_mut = matches!(s.mutability, syn::StaticMutability::Mut(_));
+                    let signature = format!("{}static {}: _", if is_mut { "mut " } else { "" }, s.ident);
+                    self.push(&path, &s.ident, SymbolKind::Static, &s.vis, signature, &s.attrs, s.span());
+                    self.record_refs(&path, |refs| refs.visit_item_static(s));
+                }
+                Item::Impl(imp) => self.visit_impl(imp, module),
+                Item::ForeignMod(foreign) => self.visit_foreign_mod(foreign, module),
+                Item::Macro(m) if m.mac.path.is_ident("macro_rules") => {
+                    if let Some(ident) = &m.ident {
+                        let path = format!("{}::{}", module, ident);
+                        let matchers: Vec<String> = macro_matchers(m.mac.tokens.clone())
+                            .into_iter()
+                            .map(|span| self.span_text(span))
+                            .collect();
+                        let signature = format_macro_rules(&ident.to_string(), &matchers);
+                        // Only #[macro_export] makes a macro_rules! visible outside the crate
+                        let vis = if m.attrs.iter().any(|a| a.path().is_ident("macro_export")) {
+                            syn::Visibility::Public(Default::default())
+                        } else {
+                            syn::Visibility::Inherited
+                        };
+                        self.push(&path, ident, SymbolKind::Macro, &vis, signature, &m.attrs, m.span());
+                    }
+                }
+                _ => {}
+            }
+            self.in_test = outer;
+        }
+        self.scope = outer_scope;
+    }
+
+    fn visit_impl(&mut self, imp: &syn::ItemImpl, module: &str) {
+        // `impl crate::net::Client` names a type elsewhere in the crate;
+        // anything else is taken to be declared in this module
+        let self_path = match imp.self_ty.as_ref() {
+            syn::Type::Path(p) => {
+                let segments: Vec<String> = p.path.segments.iter().map(|s| s.ident.to_string()).collect();
+                let anchored = match segments.first().map(String::as_str) {
+                    Some("crate" | "self" | "super") => self.scope.anchor(&segments),
+                    _ => None,
+                };
+                anchored.unwrap_or_else(|| {
+                    format!("{}::{}", module, segments.last().map_or("unknown", String::as_str))
+                })
+            }
+            other => format!("{}::{}", module, self.span_text(other.span())),
+        };
+        let trait_path = imp.trait_.as_ref().map(|(_, path, _)| {
+            path.segments
+                .iter()
+                .map(|s| s.ident.to_string())
+                .collect::<Vec<_>>()
+                .join("::")
+        });
+
+        if self.wants_current() {
+            let (line_start, line_end) = self.lines_of(&imp.attrs, imp.span());
+            self.out.impls.push(SourceImpl {
+                for_path: self_path.clone(),
+                trait_path: trait_path.clone(),
+                line_start,
+                line_end,
+            });
+        }
+
+        // Same layout as the rustdoc backend: Type::method or Type::Trait::method
+        let parent = match &trait_path {
+            Some(trait_path) => format!("{}::{}", self_path, trait_path),
+            None => self_path.clone(),
+        };
+        let outer_self = self.self_type.replace(self_path);
+        let outer_generics = std::mem::replace(
+            &mut self.impl_generics,
+            imp.generics.type_params().map(|p| p.ident.to_string()).collect(),
+        );
+        for impl_item in &imp.items {
+            match impl_item {
+                ImplItem::Fn(f) => {
+                    let path = format!("{}::{}", parent, f.sig.ident);
+                    let signature = self.span_text(f.sig.span());
+                    self.push(&path, &f.sig.ident, SymbolKind::Method, &f.vis, signature, &f.attrs, f.span());
+                    self.record_refs(&path, |refs| refs.visit_impl_item_fn(f));
+                }
+                ImplItem::Const(c) => {
+                    let path = format!("{}::{}", parent, c.ident);
+                    let signature = format!("const {}: _", c.ident);
+                    self.push(&path, &c.ident, SymbolKind::Const, &c.vis, signature, &c.attrs, c.span());
+                    self.record_refs(&path, |refs| refs.visit_impl_item_const(c));
+                }
+                _ => {}
+            }
+        }
+        self.self_type = outer_self;
+        self.impl_generics = outer_generics;
+    }
+
+    /// Items of an `extern "ABI" { ... }` block live in the enclosing module.
+    fn visit_foreign_mod(&mut self, foreign: &syn::ItemForeignMod, module: &str) {
+        let abi = foreign
+            .abi
+            .name
+            .as_ref()
+            .map(|name| name.value())
+            .unwrap_or_else(|| "C".to_string());
+        for item in &foreign.items {
+            match item {
+                ForeignItem::Fn(f) => {
+                    let path = format!("{}::{}", module, f.sig.ident);
+                    let signature = format!("extern \"{}\" {}", abi, self.span_text(f.sig.span()));
+                    self.push(&path, &f.sig.ident, SymbolKind::ForeignFn, &f.vis, signature, &f.attrs, f.span());
+                }
+                ForeignItem::Static(s) => {
+                    let path = format!("{}::{}", module, s.ident);
+                    let is_mut = matches!(s.mutability, syn::StaticMutability::Mut(_));
+                    let signature = format!("{}static {}: _", if is_mut { "mut " } else { "" }, s.ident);
+                    self.push(&path, &s.ident, SymbolKind::Static, &s.vis, signature, &s.attrs, s.span());
+                }
+                ForeignItem::Type(t) => {
+                    let path = format!("{}::{}", module, t.ident);
+                    let signature = format!("extern type {}", t.ident);
+                    self.push(&path, &t.ident, SymbolKind::ExternType, &t.vis, signature, &t.attrs, t.span());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        path: &str,
+        name: &syn::Ident,
+        kind: SymbolKind,
+        vis: &syn::Visibility,
+        signature: String,
+        attrs: &[Attribute],
+        span: Span,
+    ) {
+        if !self.wants_current() {
+            return;
+        }
+        let (line_start, line_end) = self.lines_of(attrs, span);
+        self.out.items.push(SourceItem {
+            path: path.to_string(),
+            name: name.to_string(),
+            kind,
+            visibility: match vis {
+                syn::Visibility::Public(_) => Visibility::Public,
+                _ => Visibility::Private,
+            },
+            signature,
+            docs: doc_string(attrs),
+            line_start,
+            line_end,
+        });
+    }
+
+    /// Record the paths the item at `from` refers to, as `visit` walks it.
+    fn record_refs(&mut self, from: &str, visit: impl FnOnce(&mut RefCollector<'_>)) {
+        let mut collector = RefCollector {
+            scope: &self.scope,
+            self_type: self.self_type.as_deref(),
+            generics: self.impl_generics.iter().cloned().collect(),
+            seen: HashSet::new(),
+            refs: Vec::new(),
+        };
+        visit(&mut collector);
+        let refs = collector.refs;
+        self.out.references.extend(refs.into_iter().map(|(target_path, (line_start, line_end), column, call)| SourceReference {
+            from_path: from.to_string(),
+            target_path,
+            line_start,
+            line_end,
+            column,
+            call,
+        }));
+    }
+
+    fn wants_current(&self) -> bool {
+        match self.tests {
+            TestItems::Skip | TestItems::Include => true,
+            TestItems::Only => self.in_test,
+            TestItems::None => false,
+        }
+    }
+
+    /// 1-based inclusive line range of an item, excluding its outer attributes.
+    fn lines_of(&self, attrs: &[Attribute], span: Span) -> (u32, u32) {
+        let end = span.end().line as u32;
+        let start = attrs
+            .iter()
+            .filter(|a| matches!(a.style, syn::AttrStyle::Outer))
+            .map(|a| a.span().end().line as u32 + 1)
+            .max()
+            .unwrap_or(span.start().line as u32)
+            .min(end);
+        (start, end)
+    }
+
+    fn generics_text(&self, generics: &syn::Generics) -> String {
+        if generics.params.is_empty() {
+            String::new()
+        } else {
+            self.span_text(generics.span())
+        }
+    }
+
+    fn span_text(&self, span: Span) -> String {
+        text_of(&self.lines, span)
+    }
+}
+
+/// Names from the std prelude and primitive types, which are left out of
+/// references rather than recorded as written.
+const PRELUDE: &[&str] = &[
+    "Option", "Some", "None", "Result", "Ok", "Err", "Vec", "String", "Box", "ToString", "ToOwned",
+    "Clone", "Copy", "Default", "Drop", "Send", "Sync", "Sized", "Fn", "FnMut", "FnOnce", "Iterator",
+    "IntoIterator", "Extend", "From", "Into", "AsRef", "AsMut", "PartialEq", "Eq", "PartialOrd", "Ord",
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+
+/// Names visible in one module: what its `use` declarations import, and
+/// the items it defines.
+#[derive(Debug, Default)]
+struct Scope {
+    module: String,
+    aliases: HashMap<String, String>,
+    items: HashSet<String>,
+}
+
+impl Scope {
+    fn new(items: &[Item], module: &str) -> Self {
+        let mut scope = Scope {
+            module: module.to_string(),
+            ..Default::default()
+        };
+        let mut uses = Vec::new();
+        for item in items {
+            match item {
+                Item::Use(u) => collect_uses(&u.tree, &mut Vec::new(), &mut uses),
+                other => scope.items.extend(item_ident(other)),
+            }
+        }
+        for (alias, path) in uses {
+            let target = scope.anchor(&path).unwrap_or_else(|| path.join("::"));
+            scope.aliases.insert(alias, target);
+        }
+        scope
+    }
+
+    /// `segments` made absolute when they start with `crate`, `self`,
+    /// `super` or an item of this module.
+    fn anchor(&self, segments: &[String]) -> Option<String> {
+        let first = segments.first()?;
+        let (base, rest) = match first.as_str() {
+            "crate" => (self.module.split("::").next().unwrap_or_default().to_string(), &segments[1..]),
+            "self" => (self.module.clone(), &segments[1..]),
+            "super" => {
+                let supers = segments.iter().take_while(|s| *s == "super").count();
+                let mut module: Vec<&str> = self.module.split("::").collect();
+                module.truncate(module.len().saturating_sub(supers).max(1));
+                (module.join("::"), &segments[supers..])
+            }
+            name if self.items.contains(name) => (self.module.clone(), segments),
+            _ => return None,
+        };
+        Some(std::iter::once(base).chain(rest.iter().cloned()).collect::<Vec<_>>().join("::"))
+    }
+}
+
+/// The name each leaf of a `use` tree brings into scope and the path it
+/// stands for. Globs import nothing nameable and are skipped.
+fn collect_uses(tree: &syn::UseTree, prefix: &mut Vec<String>, out: &mut Vec<(String, Vec<String>)>) {
+    // `use a::b::{self}` names `b` itself
+    let path_of = |ident: &syn::Ident, prefix: &[String]| {
+        let mut path = prefix.to_vec();
+        if ident != "self" {
+            path.push(ident.to_string());
+        }
+        path
+    };
+    match tree {
+        syn::UseTree::Path(p) => {
+            prefix.push(p.ident.to_string());
+            collect_uses(&p.tree, prefix, out);
+            prefix.pop();
+        }
+        syn::UseTree::Name(n) => {
+            let path = path_of(&n.ident, prefix);
+            if let Some(name) = path.last() {
+                out.push((name.clone(), path));
+            }
+        }
+        syn::UseTree::Rename(r) if r.rename != "_" => {
+            out.push((r.rename.to_string(), path_of(&r.ident, prefix)));
+        }
+        syn::UseTree::Rename(_) | syn::UseTree::Glob(_) => {}
+        syn::UseTree::Group(g) => {
+            for tree in &g.items {
+                collect_uses(tree, prefix, out);
+            }
+        }
+    }
+}
+
+fn item_ident(item: &Item) -> Option<String> {
+    let ident = match item {
+        Item::Mod(i) => &i.ident,
+        Item::Struct(i) => &i.ident,
+        Item::Union(i) => &i.ident,
+        Item::Enum(i) => &i.ident,
+        Item::Trait(i) => &i.ident,
+        Item::Fn(i) => &i.sig.ident,
+        Item::Type(i) => &i.ident,
+        Item::Const(i) => &i.ident,
+        Item::Static(i) => &i.ident,
+        Item::Macro(i) => i.ident.as_ref()?,
+        _ => return None,
+    };
+    Some(ident.to_string())
+}
+
+/// Collects the paths one item names, with the line span and starting
+/// column of each. Locals, generic parameters, prelude names and method
+/// calls are not recorded.
+struct RefCollector<'a> {
+    scope: &'a Scope,
+    self_type: Option<&'a str>,
+    generics: HashSet<String>,
+    seen: HashSet<(String, u32)>,
+    refs: Vec<(String, (u32, u32), u32, bool)>,
+}
+
+impl RefCollector<'_> {
+    fn record(&mut self, path: &syn::Path, called: bool) {
+        if let Some(target) = self.resolve(path, called) {
+            self.push(target, path.span(), called);
+        }
+    }
+
+    /// Keep the first reference to `target` on a line; calls are seen
+    /// before the path inside them, so a call is never recorded as a plain
+    /// reference.
+    fn push(&mut self, target: String, span: Span, called: bool) {
+        let lines = (span.start().line as u32, span.end().line as u32);
+        if self.seen.insert((target.clone(), lines.0)) {
+            self.refs.push((target, lines, span.start().column as u32 + 1, called));
+        }
+    }
+
+    fn resolve(&self, path: &syn::Path, called: bool) -> Option<String> {
+        let segments: Vec<String> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+        let (first, rest) = segments.split_first()?;
+        if first == "Self" {
+            let self_type = self.self_type?;
+            return Some(std::iter::once(self_type.to_string()).chain(rest.iter().cloned()).collect::<Vec<_>>().join("::"));
+        }
+        if self.generics.contains(first) || PRELUDE.contains(&first.as_str()) {
+            return None;
+        }
+        // A lone lowercase name is a local unless it is being called
+        if rest.is_empty() && !called && first.starts_with(|c: char| c.is_lowercase() || c == '_') {
+            return None;
+        }
+        if let Some(target) = self.scope.aliases.get(first) {
+            return Some(std::iter::once(target.clone()).chain(rest.iter().cloned()).collect::<Vec<_>>().join("::"));
+        }
+        if rest.is_empty() && matches!(first.as_str(), "self" | "crate" | "super") {
+            return None;
+        }
+        Some(self.scope.anchor(&segments).unwrap_or_else(|| segments.join("::")))
+    }
+}
+
+impl<'ast> Visit<'ast> for RefCollector<'_> {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        self.record(path, false);
+        visit::visit_path(self, path);
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+        if let Expr::Path(callee) = call.func.as_ref() {
+            self.record(&callee.path, true);
+        }
+        visit::visit_expr_call(self, call);
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+        // Only a call on `self` says which type the method belongs to
+        let on_self = matches!(call.receiver.as_ref(), Expr::Path(p) if p.path.is_ident("self"));
+        if let (true, Some(self_type)) = (on_self, self.self_type) {
+            self.push(format!("{}::{}", self_type, call.method), call.method.span(), true);
+        }
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_type_param(&mut self, param: &'ast syn::TypeParam) {
+        self.generics.insert(param.ident.to_string());
+        visit::visit_type_param(self, param);
+    }
+
+    // Attribute and visibility paths (`derive`, `pub(crate)`) are not
+    // references, nor is the `Self` type syn gives every receiver
+    fn visit_attribute(&mut self, _: &'ast Attribute) {}
+
+    fn visit_receiver(&mut self, _: &'ast syn::Receiver) {}
+
+    fn visit_visibility(&mut self, _: &'ast syn::Visibility) {}
+}
+
+/// Source text covered by `span`, with runs of whitespace collapsed.
+fn text_of(lines: &[&str], span: Span) -> String {
+    let (start, end) = (span.start(), span.end());
+    let mut text = String::new();
+    for line_no in start.line..=end.line {
+        let Some(line) = lines.get(line_no.saturating_sub(1)) else {
+            break;
+        };
+        let from = if line_no == start.line { start.column } else { 0 };
+        let to = if line_no == end.line { end.column } else { usize::MAX };
+        text.extend(line.chars().skip(from).take(to.saturating_sub(from)));
+        text.push(' ');
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Mod(i) => &i.attrs,
+        Item::Struct(i) => &i.attrs,
+        Item::Union(i) => &i.attrs,
+        Item::Enum(i) => &i.attrs,
+        Item::Trait(i) => &i.attrs,
+        Item::Fn(i) => &i.attrs,
+        Item::Type(i) => &i.attrs,
+        Item::Const(i) => &i.attrs,
+        Item::Static(i) => &i.attrs,
+        Item::Impl(i) => &i.attrs,
+        Item::Macro(i) => &i.attrs,
+        Item::ForeignMod(i) => &i.attrs,
+        _ => &[],
+    }
+}
+
+/// `#[cfg(test)]` items and `#[test]` functions.
+fn is_test_item(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|a| {
+        a.path().is_ident("test")
+            || (a.path().is_ident("cfg")
+                && matches!(&a.meta, Meta::List(list) if list.tokens.to_string() == "test"))
+    })
+}
+
+/// Join `#[doc = "..."]` attributes (including `///` comments) like rustdoc.
+fn doc_string(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').unwrap_or(&line).to_string())
+        .collect();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        let content = r#"
+/// Shared state.
+pub struct State<T: Clone> {
+    /// Current value.
+    pub value: T,
+}
+
+impl<T: Clone> State<T> {
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> Default for State<T> {
+    fn default() -> Self {
+        todo!()
+    }
+}
+
+mod inner {
+    fn helper(x: u32,
+              y: u32) -> u32 { x + y }
+
+    impl super::State<u32> {
+        pub fn reset(&mut self) {}
+    }
+}
+"#;
+        let parsed = parse_source(content, "demo", TestItems::Include).unwrap();
+        let paths: Vec<&str> = parsed.items.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "demo::State",
+                "demo::State::value",
+                "demo::State::get",
+                "demo::State::Default::default",
+                "demo::inner",
+                "demo::inner::helper",
+                "demo::State::reset",
+            ]
+        );
+
+        let state = &parsed.items[0];
+        assert_eq!(state.signature, "struct State<T: Clone>");
+        assert_eq!(state.docs.as_deref(), Some("Shared state."));
+        assert_eq!((state.line_start, state.line_end), (3, 6));
+        assert_eq!(state.visibility, Visibility::Public);
+
+        assert_eq!(parsed.items[2].signature, "fn get(&self) -> &T");
+        assert_eq!(parsed.items[5].signature, "fn helper(x: u32, y: u32) -> u32");
+        assert_eq!(parsed.impls.len(), 3);
+        assert_eq!(parsed.impls[1].trait_path.as_deref(), Some("Default"));
+        assert_eq!(parsed.impls[2].for_path, "demo::State");
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r#"
+/// Build a map.
+#[macro_export]
+macro_rules! map {
+    () => { HashMap::new() };
+    ($($k:expr => $v:expr),* $(,)?) => {{ let mut m = HashMap::new(); $(m.insert($k, $v);)* m }};
+}
+
+macro_rules! local { ($x:ident) => {} }
+
+#[proc_macro_derive(Builder, attributes(builder))]
+pub fn derive_builder(input: TokenStream) -> TokenStream { input }
+
+#[proc_macro_attribute]
+pub fn traced(attr: TokenStream, item: TokenStream) -> TokenStream { item }
+"#;
+        let parsed = parse_source(src, "demo", TestItems::Skip).unwrap();
+        let macros: Vec<_> = parsed.items.iter().map(|i| (i.path.as_str(), i.kind, i.visibility)).collect();
+        assert_eq!(macros, vec![
+            ("demo::map", SymbolKind::Macro, Visibility::Public),
+            ("demo::local", SymbolKind::Macro, Visibility::Private),
+            ("demo::Builder", SymbolKind::Macro, Visibility::Public),
+            ("demo::traced", SymbolKind::Macro, Visibility::Public),
+        ]);
+        assert_eq!(parsed.items[0].signature, "macro_rules! map { (); ($($k:expr => $v:expr),* $(,)?) }");
+        assert_eq!(parsed.items[0].docs.as_deref(), Some("Build a map."));
+        assert_eq!(parsed.items[2].signature, "#[derive(Builder)] attributes(builder)");
+        assert_eq!(parsed.items[3].signature, "#[traced]");
+
+        // rustdoc renders the definition with arm bodies elided
+        let rendered = "macro_rules! square {\n    ($x:expr) => { ... };\n}";
+        assert_eq!(macro_rules_signature("square", rendered), "macro_rules! square { ($x:expr) }");
+    }
+
+    #[test]
+    fn test_ffi_items() {
+        let src = r#"
+#[repr(C)]
+pub union Value {
+    pub int: i64,
+    pub float: f64,
+}
+
+extern "C" {
+    pub type Handle;
+    pub fn open(path: *const c_char) -> *mut Handle;
+    pub static mut errno: c_int;
+}
+"#;
+        let parsed = parse_source(src, "ffi", TestItems::Skip).unwrap();
+        let kinds: Vec<_> = parsed.items.iter().map(|i| (i.path.as_str(), i.kind)).collect();
+        assert_eq!(kinds, vec![
+            ("ffi::Value", SymbolKind::Union),
+            ("ffi::Value::int", SymbolKind::Field),
+            ("ffi::Value::float", SymbolKind::Field),
+            ("ffi::Handle", SymbolKind::ExternType),
+            ("ffi::open", SymbolKind::ForeignFn),
+            ("ffi::errno", SymbolKind::Static),
+        ]);
+        assert_eq!(parsed.items[4].signature, "extern \"C\" fn open(path: *const c_char) -> *mut Handle");
+    }
+
+    #[test]
+    fn test_module_path_for() {
+        assert_eq!(module_path_for(Path::new("src/lib.rs"), "demo"), "demo");
+        assert_eq!(module_path_for(Path::new("src/net/mod.rs"), "demo"), "demo::net");
+        assert_eq!(module_path_for(Path::new("gen/bindings.rs"), "demo"), "demo::gen::bindings");
+    }
+
+    #[test]
+    fn test_cfg_test_items() {
+        let content = r#"
+pub fn helper() {}
+
+#[cfg(test)]
+mod tests {
+    fn fixture() -> u32 { 1 }
+
+    #[test]
+    fn it_works() {}
+}
+"#;
+        let paths = |tests| -> Vec<String> {
+            parse_source(content, "demo", tests)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|i| i.path)
+                .collect()
+        };
+
+        assert_eq!(paths(TestItems::Skip), vec!["demo::helper"]);
+        assert_eq!(
+            paths(TestItems::Only),
+            vec!["demo::tests", "demo::tests::fixture", "demo::tests::it_works"]
+        );
+        assert_eq!(paths(TestItems::Include).len(), 4);
+        assert!(paths(TestItems::None).is_empty());
+    }
+
+    #[test]
+    fn test_references() {
+        let content = r#"
+use crate::config::{self, Config as Settings};
+use std::io;
+
+pub struct Store<T> {
+    settings: Settings,
+    items: Vec<T>,
+}
+
+impl<T: Clone> Store<T> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let settings = config::load(path)?;
+        let store = Self::empty(settings);
+        helper(store.items.len());
+        Ok(store)
+    }
+
+    fn reload(&mut self) {
+        self.clear();
+    }
+}
+
+fn helper(n: usize) -> super::Count {
+    Count::from(n)
+}
+
+struct Count;
+"#;
+        let parsed = parse_source(content, "demo::store", TestItems::Skip).unwrap();
+        let refs: Vec<(&str, &str, u32, u32, bool)> = parsed
+            .references
+            .iter()
+            .map(|r| (r.from_path.as_str(), r.target_path.as_str(), r.line_start, r.column, r.call))
+            .collect();
+        assert_eq!(refs, vec![
+            ("demo::store::Store", "demo::config::Config", 6, 15, false),
+            ("demo::store::Store::open", "std::io::Result", 11, 32, false),
+            ("demo::store::Store::open", "demo::store::Store", 11, 43, false),
+            ("demo::store::Store::open", "demo::config::load", 12, 24, true),
+            ("demo::store::Store::open", "demo::store::Store::empty", 13, 21, true),
+            ("demo::store::Store::open", "demo::store::helper", 14, 9, true),
+            ("demo::store::Store::reload", "demo::store::Store::clear", 19, 14, true),
+            ("demo::store::helper", "demo::Count", 23, 24, false),
+            ("demo::store::helper", "demo::store::Count::from", 24, 5, true),
+        ]);
+
+        // With rustdoc supplying the items, references are still collected
+        let parsed = parse_source(content, "demo::store", TestItems::None).unwrap();
+        assert!(parsed.items.is_empty());
+        assert_eq!(parsed.references.len(), refs.len());
+    }
+}
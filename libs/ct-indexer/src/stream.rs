@@ -0,0 +1,262 @@
+//! Incremental reader for rustdoc JSON.
+//!
+//! Large crates produce rustdoc documents in the hundreds of megabytes, so
+//! instead of materializing a `rustdoc_types::Crate` the file is read twice
+//! with a streaming deserializer: a `scan` pass that keeps only the lookup
+//! tables symbol extraction needs, then `for_each_item`, which hands each
+//! `index` entry to a callback and drops it before reading the next.
+
+use crate::cancel::CancelToken;
+use crate::{compat, IndexError, Result};
+use rustdoc_types::{Id, Item, ItemSummary, Type};
+use serde::de::{DeserializeOwned, DeserializeSeed, Error as _, IgnoredAny, MapAccess, Visitor};
+use serde::Deserializer as _;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Lookup tables gathered by the first pass over a rustdoc JSON file.
+#[derive(Debug, Default)]
+pub struct CrateScan {
+    pub format_version: u32,
+    /// Local item ids to their canonical path segments.
+    pub path_map: HashMap<Id, Vec<String>>,
+    /// Items declared inside an impl block: item id -> (impl id, trait id).
+    pub impl_context_map: HashMap<Id, (Id, Option<Id>)>,
+    /// The `for` type of each local impl block.
+    pub impl_for: HashMap<Id, Type>,
+    pub item_count: usize,
+}
+
+impl CrateScan {
+    /// Rough heap size of the tables, for memory accounting.
+    pub fn approx_bytes(&self) -> usize {
+        use std::mem::size_of;
+        let segments: usize = self
+            .path_map
+            .values()
+            .flatten()
+            .map(|segment| size_of::<String>() + segment.len())
+            .sum();
+        segments
+            + self.path_map.len() * size_of::<(Id, Vec<String>)>()
+            + self.impl_context_map.len() * size_of::<(Id, (Id, Option<Id>))>()
+            + self.impl_for.len() * size_of::<(Id, Type)>()
+    }
+}
+
+/// First pass: collect paths and impl membership without keeping items.
+pub fn scan(path: &Path, cancel: &CancelToken) -> Result<CrateScan> {
+    let mut scan = CrateScan::default();
+    let mut impl_for_raw: Vec<(Id, Value)> = Vec::new();
+
+    let format_version = visit_document(
+        path,
+        &mut |id, item: Value| {
+            cancel.check()?;
+            scan.item_count += 1;
+            if item.get("crate_id").and_then(Value::as_u64) != Some(0) {
+                return Ok(());
+            }
+            let Some(imp) = item.get("inner").and_then(|inner| inner.get("impl")) else {
+                return Ok(());
+            };
+
+            let trait_id = match imp.get("trait").and_then(|t| t.get("id")) {
+                Some(id) => Some(serde_json::from_value::<Id>(id.clone())?),
+                None => None,
+            };
+            if let Some(items) = imp.get("items").and_then(Value::as_array) {
+                for item_id in items {
+                    let item_id = serde_json::from_value::<Id>(item_id.clone())?;
+                    scan.impl_context_map.insert(item_id, (id, trait_id));
+                }
+            }
+            if let Some(for_) = imp.get("for") {
+                impl_for_raw.push((id, for_.clone()));
+            }
+            Ok(())
+        },
+        &mut |id, summary: ItemSummary| {
+            if summary.crate_id == 0 {
+                scan.path_map.insert(id, summary.path);
+            }
+            Ok(())
+        },
+    )?;
+
+    scan.format_version = format_version.ok_or_else(|| {
+        IndexError::UnsupportedFormat("rustdoc JSON has no format_version".to_string())
+    })?;
+    compat::check_version(scan.format_version)?;
+
+    for (id, mut for_) in impl_for_raw {
+        compat::upgrade(&mut for_, scan.format_version);
+        let ty: Type = compat::from_value(for_, scan.format_version)?;
+        scan.impl_for.insert(id, ty);
+    }
+
+    Ok(scan)
+}
+
+/// Second pass: deserialize each `index` entry in turn and pass it to `f`.
+pub fn for_each_item<F>(path: &Path, format_version: u32, mut f: F) -> Result<()>
+where
+    F: FnMut(Id, Item) -> Result<()>,
+{
+    visit_document(
+        path,
+        &mut |id, mut item: Value| {
+            compat::upgrade(&mut item, format_version);
+            f(id, compat::from_value(item, format_version)?)
+        },
+        &mut |_, _: IgnoredAny| Ok(()),
+    )?;
+    Ok(())
+}
+
+type EntryFn<'a, T> = dyn FnMut(Id, T) -> Result<()> + 'a;
+
+/// Stream the top-level object, routing `index` and `paths` entries to the
+/// callbacks and skipping everything else. Returns `format_version` if present.
+fn visit_document<I, P>(
+    path: &Path,
+    on_item: &mut EntryFn<'_, I>,
+    on_path: &mut EntryFn<'_, P>,
+) -> Result<Option<u32>>
+where
+    I: DeserializeOwned,
+    P: DeserializeOwned,
+{
+    let file = File::open(path)?;
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(file));
+    let mut error = None;
+
+    let result = (&mut de).deserialize_map(DocumentVisitor {
+        on_item,
+        on_path,
+        error: &mut error,
+    });
+
+    // A callback failure surfaces as a generic serde error; report the original.
+    if let Some(e) = error {
+        return Err(e);
+    }
+    let version = result?;
+    de.end()?;
+    Ok(version)
+}
+
+struct DocumentVisitor<'a, 'f, I, P> {
+    on_item: &'a mut EntryFn<'f, I>,
+    on_path: &'a mut EntryFn<'f, P>,
+    error: &'a mut Option<IndexError>,
+}
+
+impl<'de, I, P> Visitor<'de> for DocumentVisitor<'_, '_, I, P>
+where
+    I: DeserializeOwned,
+    P: DeserializeOwned,
+{
+    type Value = Option<u32>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a rustdoc JSON document")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Self::Value, A::Error> {
+        let mut format_version = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "index" => map.next_value_seed(EntriesSeed {
+                    on_entry: &mut *self.on_item,
+                    error: &mut *self.error,
+                    marker: PhantomData,
+                })?,
+                "paths" => map.next_value_seed(EntriesSeed {
+                    on_entry: &mut *self.on_path,
+                    error: &mut *self.error,
+                    marker: PhantomData,
+                })?,
+                "format_version" => format_version = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(format_version)
+    }
+}
+
+struct EntriesSeed<'a, 'f, T> {
+    on_entry: &'a mut EntryFn<'f, T>,
+    error: &'a mut Option<IndexError>,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for EntriesSeed<'_, '_, T> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> std::result::Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for EntriesSeed<'_, '_, T> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map keyed by item id")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error> {
+        while let Some((id, value)) = map.next_entry::<Id, T>()? {
+            if let Err(e) = (self.on_entry)(id, value) {
+                *self.error = Some(e);
+                return Err(A::Error::custom("aborted by callback"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_scan_collects_paths_and_impls() {
+        let doc = serde_json::json!({
+            "root": 0,
+            "index": {
+                "1": {"crate_id": 0, "inner": {"impl": {
+                    "items": [2, 3],
+                    "trait": null,
+                    "for": {"generic": "T"},
+                }}},
+                "4": {"crate_id": 1, "inner": {"impl": {"items": [5], "trait": null, "for": {"generic": "U"}}}},
+            },
+            "paths": {
+                "7": {"crate_id": 0, "path": ["demo", "State"], "kind": "struct"},
+                "8": {"crate_id": 1, "path": ["std", "vec", "Vec"], "kind": "struct"},
+            },
+            "format_version": rustdoc_types::FORMAT_VERSION,
+        });
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), doc.to_string()).unwrap();
+
+        let scan = scan(file.path(), &CancelToken::new()).unwrap();
+        assert_eq!(scan.item_count, 2);
+        assert_eq!(scan.path_map.len(), 1);
+        assert_eq!(scan.path_map[&Id(7)], vec!["demo", "State"]);
+        assert_eq!(scan.impl_context_map.len(), 2);
+        assert_eq!(scan.impl_context_map[&Id(3)], (Id(1), None));
+        assert_eq!(scan.impl_for[&Id(1)], Type::Generic("T".to_string()));
+        assert!(scan.approx_bytes() > CrateScan::default().approx_bytes());
+    }
+}
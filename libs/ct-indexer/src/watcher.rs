@@ -1,18 +1,100 @@
+use notify::event::{ModifyKind, RenameMode};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::error::TryRecvError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
+use crate::filter::PathFilter;
 use crate::Result;
 
+/// Activity counters the watcher thread keeps for `Diag`.
+#[derive(Debug, Default)]
+pub struct WatcherStats {
+    running: AtomicBool,
+    /// Rust files found under the watched roots when watching started.
+    watched_files: AtomicUsize,
+    events_received: AtomicU64,
+    /// Unix seconds of the last event; 0 before the first.
+    last_event_secs: AtomicI64,
+    pending_batch: AtomicUsize,
+}
+
+impl WatcherStats {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn watched_files(&self) -> usize {
+        self.watched_files.load(Ordering::Relaxed)
+    }
+
+    pub fn events_received(&self) -> u64 {
+        self.events_received.load(Ordering::Relaxed)
+    }
+
+    pub fn last_event_secs(&self) -> Option<i64> {
+        match self.last_event_secs.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(secs),
+        }
+    }
+
+    pub fn pending_batch(&self) -> usize {
+        self.pending_batch.load(Ordering::Relaxed)
+    }
+
+    fn record_event(&self) {
+        self.events_received.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        self.last_event_secs.store(now, Ordering::Relaxed);
+    }
+}
+
+/// Non-Rust files the index depends on (see [`crate::inputs`]); changes to
+/// them are reported alongside `.rs` files. Shared so the daemon can refresh
+/// the set after each indexing run.
+#[derive(Debug, Clone, Default)]
+pub struct TrackedInputs(Arc<RwLock<HashSet<PathBuf>>>);
+
+impl TrackedInputs {
+    pub fn replace(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut tracked = self.0.write().unwrap_or_else(|e| e.into_inner());
+        *tracked = paths.into_iter().collect();
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).contains(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 pub struct FileWatcher {
     watcher: RecommendedWatcher,
     rx: Receiver<notify::Result<Event>>,
+    workspace_root: PathBuf,
+    /// Configured `watcher.ignore` globs, as excludes.
+    ignore: PathFilter,
+    stats: Arc<WatcherStats>,
+    inputs: TrackedInputs,
 }
 
 impl FileWatcher {
-    pub fn new(_workspace_root: &Path, debounce_ms: u64) -> Result<Self> {
+    pub fn new(workspace_root: &Path, debounce_ms: u64, ignore: PathFilter) -> Result<Self> {
         let (tx, rx) = channel();
         
         let config = Config::default()
@@ -27,49 +109,75 @@ impl FileWatcher {
             config,
         )?;
         
-        Ok(Self { watcher, rx })
+        Ok(Self {
+            watcher,
+            rx,
+            workspace_root: workspace_root.to_path_buf(),
+            ignore,
+            stats: Arc::default(),
+            inputs: TrackedInputs::default(),
+        })
+    }
+
+    /// Report activity into `stats`, e.g. counters created before the watcher.
+    pub fn with_stats(mut self, stats: Arc<WatcherStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<WatcherStats> {
+        self.stats.clone()
+    }
+
+    /// Also report changes to these non-Rust files.
+    pub fn with_inputs(mut self, inputs: TrackedInputs) -> Self {
+        self.inputs = inputs;
+        self
     }
 
     pub fn watch(&mut self, path: &Path) -> Result<()> {
         info!("Starting file watcher for {:?}", path);
         self.watcher.watch(path, RecursiveMode::Recursive)?;
+        let files = crate::source::collect_rust_files(path)?
+            .into_iter()
+            .filter(|file| !self.is_configured_ignore(file))
+            .count();
+        self.stats.watched_files.fetch_add(files, Ordering::Relaxed);
+        self.stats.running.store(true, Ordering::Relaxed);
         Ok(())
     }
 
     pub fn collect_changes(&mut self, debounce_ms: u64) -> Vec<PathBuf> {
-        let mut changed_files = Vec::new();
-        let start = std::time::Instant::now();
-        let debounce_duration = Duration::from_millis(debounce_ms);
+        let mut batch = ChangeBatch::default();
+        let deadline = std::time::Instant::now() + Duration::from_millis(debounce_ms);
         
         // Collect all events within debounce window
-        while start.elapsed() < debounce_duration {
-            match self.rx.try_recv() {
+        while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match self.rx.recv_timeout(remaining) {
                 Ok(Ok(event)) => {
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
-                            for path in event.paths {
-                                if is_rust_file(&path) && !is_ignored(&path) {
-                                    debug!("File changed: {:?}", path);
-                                    changed_files.push(path);
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+                    self.stats.record_event();
+                    batch.record(event);
+                    self.stats.pending_batch.store(batch.len(), Ordering::Relaxed);
                 }
                 Ok(Err(e)) => {
                     error!("Watch error: {}", e);
                 }
-                Err(_) => {
-                    // No more events, wait a bit
-                    std::thread::sleep(Duration::from_millis(10));
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The watcher is gone; keep the window so callers still
+                    // pace their loop by it
+                    std::thread::sleep(remaining);
                 }
             }
         }
         
-        // Deduplicate
-        changed_files.sort();
-        changed_files.dedup();
+        self.stats.pending_batch.store(0, Ordering::Relaxed);
+        let changed_files: Vec<PathBuf> = batch
+            .finish()
+            .into_iter()
+            .filter(|path| self.is_tracked(path) && !is_ignored(path) && !self.is_configured_ignore(path))
+            .inspect(|path| debug!("File changed: {:?}", path))
+            .collect();
         
         if !changed_files.is_empty() {
             info!("Collected {} changed files after debounce", changed_files.len());
@@ -79,6 +187,109 @@ impl FileWatcher {
     }
 }
 
+impl FileWatcher {
+    fn is_tracked(&self, path: &Path) -> bool {
+        is_rust_file(path) || self.inputs.contains(path)
+    }
+
+    /// Globs are matched against the path relative to the workspace root.
+    fn is_configured_ignore(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.workspace_root).unwrap_or(path);
+        !self.ignore.is_included(relative)
+    }
+}
+
+/// Changed paths within one debounce window, with editor save dances
+/// collapsed onto the file that was actually saved.
+#[derive(Debug, Default)]
+struct ChangeBatch {
+    changed: BTreeSet<PathBuf>,
+    /// `From` halves of renames waiting for their `To`, by tracker cookie.
+    rename_from: HashMap<usize, PathBuf>,
+}
+
+impl ChangeBatch {
+    fn record(&mut self, event: Event) {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(mode)) => {
+                let tracker = event.tracker();
+                self.record_rename(mode, tracker, event.paths);
+            }
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                self.changed.extend(event.paths);
+            }
+            _ => {}
+        }
+    }
+
+    fn record_rename(&mut self, mode: RenameMode, tracker: Option<usize>, paths: Vec<PathBuf>) {
+        match (mode, tracker, paths.as_slice()) {
+            (RenameMode::Both, _, [from, to]) => self.renamed(from, to),
+            (RenameMode::From, Some(tracker), [from]) => {
+                self.rename_from.insert(tracker, from.clone());
+            }
+            (RenameMode::To, Some(tracker), [to]) => match self.rename_from.remove(&tracker) {
+                Some(from) => self.renamed(&from, to),
+                None => {
+                    self.changed.insert(to.clone());
+                }
+            },
+            // Unpaired or backend-specific renames: treat every path as changed
+            _ => self.changed.extend(paths),
+        }
+    }
+
+    fn renamed(&mut self, from: &Path, to: &Path) {
+        // Atomic saves rename a temp file over the target; the temp name was
+        // never part of the crate. A real rename also removes the old path.
+        if temp_file_target(from).is_none() {
+            self.changed.insert(from.to_path_buf());
+        }
+        self.changed.insert(to.to_path_buf());
+    }
+
+    fn len(&self) -> usize {
+        self.changed.len() + self.rename_from.len()
+    }
+
+    /// Every changed path, temp names mapped back to the file they stand for.
+    /// A rename whose `To` half never arrived moved the file out of the tree.
+    fn finish(self) -> BTreeSet<PathBuf> {
+        self.changed
+            .into_iter()
+            .chain(self.rename_from.into_values())
+            .map(|path| temp_file_target(&path).unwrap_or(path))
+            .collect()
+    }
+}
+
+/// The file an editor temp or backup file stands for: `lib.rs~`,
+/// `.lib.rs.swp`, `.#lib.rs`, `lib.rs.tmp`, `lib.rs.tmp.1234` and JetBrains'
+/// `lib.rs___jb_tmp___` all map to `lib.rs`.
+fn temp_file_target(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let real = if let Some(real) = name.strip_suffix('~') {
+        real
+    } else if let Some(real) = name.strip_prefix(".#") {
+        real
+    } else if let Some(real) = name
+        .strip_prefix('.')
+        .and_then(|n| n.strip_suffix(".swp").or_else(|| n.strip_suffix(".swx")))
+    {
+        real
+    } else if let Some(real) = name
+        .strip_suffix("___jb_tmp___")
+        .or_else(|| name.strip_suffix("___jb_old___"))
+    {
+        real
+    } else if let Some((real, _)) = name.split_once(".rs.tmp") {
+        return Some(path.with_file_name(format!("{}.rs", real)));
+    } else {
+        return None;
+    };
+    (!real.is_empty()).then(|| path.with_file_name(real))
+}
+
 fn is_rust_file(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
@@ -97,19 +308,23 @@ fn is_ignored(path: &Path) -> bool {
 
 pub struct WatcherHandle {
     tx: mpsc::Sender<WatcherCommand>,
+    stats: Arc<WatcherStats>,
+    inputs: TrackedInputs,
 }
 
 pub enum WatcherCommand {
-    GetChanges,
     Stop,
 }
 
 impl WatcherHandle {
-    pub async fn request_changes(&self) -> Result<Vec<PathBuf>> {
-        // Stub for now
-        Ok(vec![])
+    pub fn stats(&self) -> Arc<WatcherStats> {
+        self.stats.clone()
     }
-    
+
+    pub fn inputs(&self) -> TrackedInputs {
+        self.inputs.clone()
+    }
+
     pub async fn stop(&self) -> Result<()> {
         self.tx.send(WatcherCommand::Stop).await
             .map_err(|e| crate::IndexError::IndexingFailed(e.to_string()))?;
@@ -117,15 +332,27 @@ impl WatcherHandle {
     }
 }
 
+/// Watch `workspace_root` and send each non-empty debounced batch of changed
+/// Rust files and tracked inputs on `changes`, skipping paths matching the
+/// `ignore` globs. Mapping
+/// files to crates is left to `Indexer::reindex_files`.
 pub async fn spawn_watcher(
     workspace_root: PathBuf,
     debounce_ms: u64,
+    ignore: &[String],
+    changes: mpsc::Sender<Vec<PathBuf>>,
 ) -> Result<WatcherHandle> {
     let (tx, mut rx) = mpsc::channel(100);
+    let ignore = PathFilter::new(&[], ignore)?;
+    let stats = Arc::new(WatcherStats::default());
+    let thread_stats = stats.clone();
+    let inputs = TrackedInputs::default();
+    let thread_inputs = inputs.clone();
     
-    tokio::spawn(async move {
-        let mut watcher = match FileWatcher::new(&workspace_root, debounce_ms) {
-            Ok(w) => w,
+    // Debouncing blocks, so the watcher gets its own thread
+    tokio::task::spawn_blocking(move || {
+        let mut watcher = match FileWatcher::new(&workspace_root, debounce_ms, ignore) {
+            Ok(w) => w.with_stats(thread_stats).with_inputs(thread_inputs),
             Err(e) => {
                 error!("Failed to create file watcher: {}", e);
                 return;
@@ -138,27 +365,24 @@ pub async fn spawn_watcher(
         }
         
         loop {
-            tokio::select! {
-                Some(cmd) = rx.recv() => {
-                    match cmd {
-                        WatcherCommand::GetChanges => {
-                            let _changes = watcher.collect_changes(debounce_ms);
-                            // In real implementation, would send changes back
-                        }
-                        WatcherCommand::Stop => {
-                            info!("Stopping file watcher");
-                            break;
-                        }
-                    }
-                }
-                else => {
+            match rx.try_recv() {
+                Ok(WatcherCommand::Stop) | Err(TryRecvError::Disconnected) => {
+                    info!("Stopping file watcher");
                     break;
                 }
+                Err(TryRecvError::Empty) => {}
+            }
+            
+            let changed = watcher.collect_changes(debounce_ms);
+            if !changed.is_empty() && changes.blocking_send(changed).is_err() {
+                debug!("Change receiver dropped, stopping file watcher");
+                break;
             }
         }
+        watcher.stats.running.store(false, Ordering::Relaxed);
     });
     
-    Ok(WatcherHandle { tx })
+    Ok(WatcherHandle { tx, stats, inputs })
 }
 
 #[cfg(test)]
@@ -173,6 +397,95 @@ mod tests {
         assert!(!is_rust_file(Path::new("README.md")));
     }
 
+    #[test]
+    fn test_configured_ignore() {
+        let ignore = PathFilter::new(&[], &["**/generated/**".to_string(), "**/*.pb.rs".to_string()]).unwrap();
+        let watcher = FileWatcher::new(Path::new("/ws"), 10, ignore).unwrap();
+
+        assert!(watcher.is_configured_ignore(Path::new("/ws/api/src/generated/types.rs")));
+        assert!(watcher.is_configured_ignore(Path::new("/ws/proto/src/user.pb.rs")));
+        assert!(!watcher.is_configured_ignore(Path::new("/ws/api/src/lib.rs")));
+    }
+
+    fn rename(mode: RenameMode, tracker: usize, path: &str) -> Event {
+        Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+            .add_path(PathBuf::from(path))
+            .set_tracker(tracker)
+    }
+
+    #[test]
+    fn test_atomic_save_coalesces_to_target() {
+        let mut batch = ChangeBatch::default();
+        batch.record(Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(PathBuf::from("/ws/src/lib.rs.tmp.4821")));
+        batch.record(rename(RenameMode::From, 7, "/ws/src/lib.rs.tmp.4821"));
+        batch.record(rename(RenameMode::To, 7, "/ws/src/lib.rs"));
+
+        let changed: Vec<_> = batch.finish().into_iter().collect();
+        assert_eq!(changed, vec![PathBuf::from("/ws/src/lib.rs")]);
+    }
+
+    #[test]
+    fn test_real_rename_reports_both_paths() {
+        let mut batch = ChangeBatch::default();
+        batch.record(rename(RenameMode::From, 1, "/ws/src/old.rs"));
+        batch.record(rename(RenameMode::To, 1, "/ws/src/new.rs"));
+        // Moved out of the watched tree: no `To` half
+        batch.record(rename(RenameMode::From, 2, "/ws/src/gone.rs"));
+
+        let changed: Vec<_> = batch.finish().into_iter().collect();
+        assert_eq!(changed, vec![
+            PathBuf::from("/ws/src/gone.rs"),
+            PathBuf::from("/ws/src/new.rs"),
+            PathBuf::from("/ws/src/old.rs"),
+        ]);
+    }
+
+    #[test]
+    fn test_temp_file_target() {
+        for temp in ["lib.rs~", ".lib.rs.swp", ".#lib.rs", "lib.rs.tmp", "lib.rs.tmp.99", "lib.rs___jb_tmp___"] {
+            assert_eq!(temp_file_target(&Path::new("src").join(temp)), Some(PathBuf::from("src/lib.rs")), "{}", temp);
+        }
+        assert_eq!(temp_file_target(Path::new("src/lib.rs")), None);
+    }
+
+    #[test]
+    fn test_tracked_inputs() {
+        let inputs = TrackedInputs::default();
+        let watcher = FileWatcher::new(Path::new("/ws"), 10, PathFilter::default())
+            .unwrap()
+            .with_inputs(inputs.clone());
+        assert!(watcher.is_tracked(Path::new("/ws/src/lib.rs")));
+        assert!(!watcher.is_tracked(Path::new("/ws/schema.sql")));
+
+        inputs.replace([PathBuf::from("/ws/schema.sql")]);
+        assert!(watcher.is_tracked(Path::new("/ws/schema.sql")));
+        assert!(!watcher.is_tracked(Path::new("/ws/README.md")));
+    }
+
+    #[test]
+    fn test_watcher_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = Arc::new(WatcherStats::default());
+        let mut watcher = FileWatcher::new(dir.path(), 10, PathFilter::default())
+            .unwrap()
+            .with_stats(stats.clone());
+        assert!(!stats.is_running());
+        assert_eq!(stats.last_event_secs(), None);
+
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        for file in ["src/lib.rs", "src/main.rs", "target/debug/build.rs", "README.md"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        watcher.watch(dir.path()).unwrap();
+        assert!(stats.is_running());
+        assert_eq!(stats.watched_files(), 2);
+
+        stats.record_event();
+        assert_eq!(stats.events_received(), 1);
+        assert!(stats.last_event_secs().is_some());
+    }
+
     #[test]
     fn test_is_ignored() {
         assert!(is_ignored(Path::new("target/debug/main")));
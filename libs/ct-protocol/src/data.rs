@@ -0,0 +1,469 @@
+//! Typed `data` of success responses, built by the daemon and read back by
+//! clients with `SuccessEnvelope::data_as`.
+
+use crate::SymbolChange;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A symbol in a listing. `fields` holds whatever else the query returned,
+/// such as every indexed field when `all` was requested.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolItem {
+    pub path: String,
+    pub span_start: u32,
+    pub span_end: u32,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+/// `data` of `Command::Find`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FindData {
+    pub items: Vec<SymbolItem>,
+}
+
+/// A full-text or fuzzy match, best first; `fields` as in `SymbolItem`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchItem {
+    pub path: String,
+    pub kind: String,
+    pub score: f64,
+    pub span_start: u32,
+    pub span_end: u32,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+/// `data` of `Command::Search` and `Command::FuzzyFind`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchData {
+    pub items: Vec<SearchItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LsItem {
+    pub path: String,
+    pub kind: String,
+    pub signature: String,
+    /// Only sent when docs were requested
+    #[serde(default)]
+    pub docs: Option<String>,
+    pub span_start: u32,
+    pub span_end: u32,
+}
+
+/// `data` of `Command::Ls`: ancestors asked for with `<`/`<<` first, then
+/// the listed symbols.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LsData {
+    pub items: Vec<LsItem>,
+}
+
+/// `data` of `Command::Export`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportData {
+    pub bundle: ExportBundle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportBundle {
+    /// The requested roots, in request order; a single `symbol` from
+    /// daemons older than multi-path exports
+    #[serde(alias = "symbol", deserialize_with = "crate::one_or_many")]
+    pub symbols: Vec<ExportSymbol>,
+    /// Symbols under the roots when expanded with `>`/`>>`, each once
+    /// however many roots share it
+    #[serde(default)]
+    pub children: Vec<ExportSymbol>,
+    /// Paths outside the bundle that its symbols refer to
+    #[serde(default)]
+    pub extern_refs: Vec<String>,
+    #[serde(default)]
+    pub impl_ranges: Vec<SymbolItem>,
+    /// How `children` are ordered, e.g. `bfs`
+    pub order: String,
+    /// Some children were left out to stay within `max_list`
+    #[serde(default)]
+    pub truncated: bool,
+    pub invariants: ExportInvariants,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportSymbol {
+    pub path: String,
+    pub kind: String,
+    pub signature: String,
+    pub span_start: u32,
+    pub span_end: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docs: Option<String>,
+    /// The span's text, when requested and the file still exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The file changed since indexing, so `source` may be off
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub source_stale: bool,
+}
+
+/// Guarantees a bundle's consumer can rely on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportInvariants {
+    pub range_1_based_inclusive: bool,
+}
+
+/// `data` of `Command::Refs`: reference sites grouped by file, or with
+/// `RefsGroup::Crate` by crate and then file, leaving `files` empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefsData {
+    pub path: String,
+    pub total: usize,
+    pub truncated: bool,
+    pub files: Vec<RefFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub crates: Vec<RefCrate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefCrate {
+    pub name: String,
+    pub files: Vec<RefFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefFile {
+    pub file: String,
+    pub references: Vec<RefSite>,
+}
+
+/// A reference from the body of `from` to `target`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefSite {
+    pub from: String,
+    pub target: String,
+    pub line_start: u32,
+    pub line_end: u32,
+    /// 1-based column on `line_start`; absent for references indexed
+    /// before columns were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+}
+
+/// `data` of `Command::Locate`: `file` as the index knows it, and
+/// `absolute_file` joined onto the daemon's workspace root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocateData {
+    pub path: String,
+    pub kind: String,
+    pub file: String,
+    pub absolute_file: String,
+    pub span_start: u32,
+    pub span_end: u32,
+}
+
+/// `data` of `Command::Todo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoData {
+    pub total: usize,
+    pub truncated: bool,
+    pub items: Vec<TodoItem>,
+}
+
+/// A marker and the function it is in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoItem {
+    pub path: String,
+    pub file: String,
+    pub line: u32,
+    /// `todo`, `unimplemented`, `TODO` or `FIXME`
+    pub marker: String,
+    /// The macro's message, or the comment from the marker on
+    pub message: String,
+    /// Author time of the line as unix seconds; only when the daemon
+    /// indexes git metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introduced: Option<i64>,
+}
+
+/// `data` of `Command::Owner`: the symbol enclosing `file:line`, with
+/// `file` as the index knows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnerData {
+    pub file: String,
+    pub line: u32,
+    pub path: String,
+    pub kind: String,
+    pub signature: String,
+    pub span_start: u32,
+    pub span_end: u32,
+}
+
+/// `data` of `Command::Bench`: a mix of queries sampled from the index,
+/// replayed one at a time after a warmup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchData {
+    /// Size of the sampled mix, cycled through until the time is up
+    pub queries: usize,
+    pub warmup_ms: u64,
+    pub duration_ms: u64,
+    /// Queries timed, and how many of those failed
+    pub samples: u64,
+    pub errors: u64,
+    pub throughput_qps: f64,
+    pub latency: Latency,
+    /// The same per command, in mix order
+    pub commands: Vec<BenchCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchCommand {
+    pub command: String,
+    pub samples: u64,
+    pub latency: Latency,
+}
+
+/// Latency percentiles in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// `data` of `Command::Progress`, and `reindex` in `Diag`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgressData {
+    #[serde(default)]
+    pub running: Option<ReindexJob>,
+    /// In the order they will run
+    #[serde(default)]
+    pub queued: Vec<ReindexJob>,
+    /// The last jobs to finish, most recent first
+    #[serde(default)]
+    pub finished: Vec<ReindexJob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReindexJob {
+    pub id: u64,
+    /// The member reindexed, or the whole workspace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    pub state: JobState,
+    /// Crates indexed so far, of `total`, while running
+    #[serde(default)]
+    pub done: usize,
+    #[serde(default)]
+    pub total: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The newer job that made this one redundant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    Superseded,
+}
+
+/// `data` of `Command::Callers` and `Command::Callees`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallGraphData {
+    pub path: String,
+    pub depth: usize,
+    pub truncated: bool,
+    pub edges: Vec<CallEdge>,
+}
+
+/// A call site, `depth` calls away from the queried symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallEdge {
+    pub caller: String,
+    pub callee: String,
+    pub file: String,
+    pub line_start: u32,
+    pub line_end: u32,
+    pub depth: usize,
+}
+
+/// `data` of `Command::Diff`. `baseline_epoch` is set when diffing against
+/// the previous epoch rather than a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffData {
+    pub baseline: Option<String>,
+    pub baseline_epoch: Option<u64>,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub truncated: bool,
+    pub changes: Vec<SymbolChange>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeKind, Response};
+    use serde_json::json;
+
+    fn round_trip<T>(data: &T) -> T
+    where
+        T: Serialize + serde::de::DeserializeOwned,
+    {
+        let Response::Success(envelope) = Response::success("r1".to_string(), json!(data)) else {
+            unreachable!()
+        };
+        envelope.data_as().unwrap()
+    }
+
+    #[test]
+    fn test_symbol_item_keeps_extra_fields() {
+        let all = json!({
+            "path": "app::net::connect",
+            "span_start": 3,
+            "span_end": 9,
+            "kind": "fn",
+            "visibility": "public",
+        });
+        let item: SymbolItem = serde_json::from_value(all.clone()).unwrap();
+        assert_eq!(item.path, "app::net::connect");
+        assert_eq!(item.fields["kind"], "fn");
+        assert_eq!(serde_json::to_value(&item).unwrap(), all);
+
+        let find = FindData { items: vec![item] };
+        assert_eq!(round_trip(&find), find);
+    }
+
+    #[test]
+    fn test_payload_round_trips() {
+        let ls = LsData {
+            items: vec![LsItem {
+                path: "app::net".to_string(),
+                kind: "module".to_string(),
+                signature: "mod net".to_string(),
+                docs: None,
+                span_start: 1,
+                span_end: 40,
+            }],
+        };
+        assert_eq!(round_trip(&ls), ls);
+
+        let export = ExportData {
+            bundle: ExportBundle {
+                symbols: vec![ExportSymbol {
+                    path: "app::net::connect".to_string(),
+                    kind: "fn".to_string(),
+                    signature: "pub fn connect()".to_string(),
+                    span_start: 3,
+                    span_end: 9,
+                    docs: Some("Connects.".to_string()),
+                    source: Some("pub fn connect() {}\n".to_string()),
+                    source_stale: false,
+                }],
+                children: vec![],
+                extern_refs: vec![],
+                impl_ranges: vec![],
+                order: "bfs".to_string(),
+                truncated: false,
+                invariants: ExportInvariants { range_1_based_inclusive: true },
+            },
+        };
+        assert_eq!(round_trip(&export), export);
+        assert!(json!(export)["bundle"]["symbols"][0].get("source_stale").is_none());
+        // Bundles from older daemons carry their one root as `symbol`
+        let mut old = json!(export);
+        let root = old["bundle"].as_object_mut().unwrap().remove("symbols").unwrap()[0].take();
+        old["bundle"]["symbol"] = root;
+        assert_eq!(serde_json::from_value::<ExportData>(old).unwrap(), export);
+
+        let refs = RefsData {
+            path: "app::net::connect".to_string(),
+            total: 1,
+            truncated: false,
+            files: vec![RefFile {
+                file: "src/main.rs".to_string(),
+                references: vec![RefSite {
+                    from: "app::main".to_string(),
+                    target: "app::net::connect".to_string(),
+                    line_start: 5,
+                    line_end: 5,
+                    column: Some(9),
+                }],
+            }],
+            crates: vec![],
+        };
+        assert_eq!(round_trip(&refs), refs);
+        assert!(json!(refs).get("crates").is_none());
+
+        let owner = OwnerData {
+            file: "src/net.rs".to_string(),
+            line: 5,
+            path: "app::net::connect".to_string(),
+            kind: "fn".to_string(),
+            signature: "pub fn connect()".to_string(),
+            span_start: 3,
+            span_end: 9,
+        };
+        assert_eq!(round_trip(&owner), owner);
+
+        let progress = ProgressData {
+            running: Some(ReindexJob {
+                id: 2,
+                package: None,
+                state: JobState::Running,
+                done: 1,
+                total: 3,
+                duration_ms: None,
+                error: None,
+                superseded_by: None,
+            }),
+            queued: vec![],
+            finished: vec![ReindexJob {
+                id: 1,
+                package: Some("app".to_string()),
+                state: JobState::Superseded,
+                done: 0,
+                total: 0,
+                duration_ms: Some(12),
+                error: None,
+                superseded_by: Some(2),
+            }],
+        };
+        assert_eq!(round_trip(&progress), progress);
+        assert_eq!(json!(progress)["finished"][0]["state"], "superseded");
+
+        let diff = DiffData {
+            baseline: None,
+            baseline_epoch: Some(4),
+            added: 1,
+            removed: 0,
+            changed: 0,
+            truncated: false,
+            changes: vec![SymbolChange {
+                change: ChangeKind::Added,
+                path: "app::net::close".to_string(),
+                kind: "fn".to_string(),
+                visibility: "public".to_string(),
+                signature: "pub fn close()".to_string(),
+                previous_signature: None,
+            }],
+        };
+        assert_eq!(round_trip(&diff), diff);
+    }
+
+    #[test]
+    fn test_data_as_rejects_other_payloads() {
+        let Response::Success(envelope) = Response::success("r1".to_string(), json!({ "status": "ok" })) else {
+            unreachable!()
+        };
+        assert!(envelope.data_as::<FindData>().is_err());
+    }
+}
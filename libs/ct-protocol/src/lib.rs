@@ -1,14 +1,24 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+pub mod data;
+
 pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub cmd: Command,
+    /// Echoed in the response. Requests pipelined on one connection run
+    /// concurrently and are answered as they finish, so clients sending
+    /// several at once match responses by this id
     pub request_id: String,
     #[serde(default = "default_protocol_version")]
     pub protocol_version: u32,
+    /// Accept a response too large for one line split into `data_chunk`
+    /// frames ending with `done`; smaller responses still arrive whole. The
+    /// daemon builds the response in full before splitting it
+    #[serde(default)]
+    pub chunked: bool,
 }
 
 fn default_protocol_version() -> u32 {
@@ -33,11 +43,79 @@ pub enum Command {
         todo: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         all: Option<bool>,
+        /// Only symbols whose span changed within this many days (needs git metadata)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recently_changed: Option<u32>,
+        /// With `path`, how many segments below it to return (unlimited if absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+        /// How `name` is matched: exact (default), prefix, glob, regex or fuzzy
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        match_mode: Option<String>,
+        /// Symbols carrying this tag; used instead of `name` or `path`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        /// Functions whose signature matches this shape, e.g. `fn(&str) -> Result<_, _>`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sig: Option<String>,
+        /// Resume from a `next_cursor` token an earlier page of this query returned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+    },
+    /// Ranked full-text search over symbol names, signatures and docs
+    Search {
+        query: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        kind: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        vis: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        all: Option<bool>,
+        /// Resume from a `next_cursor` token an earlier page of this query returned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+    },
+    /// Symbols whose path holds the characters of `query` in order, ranked
+    /// like a fuzzy finder (skim's scoring); `data` is a `SearchData` whose
+    /// higher scores rank higher
+    FuzzyFind {
+        query: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        kind: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        vis: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        /// Resume from a `next_cursor` token an earlier page of this query returned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+    },
+    /// Symbols whose docs match `query`, with highlighted excerpts
+    SearchDocs {
+        query: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    /// Symbols whose name, signature or docs contain `pattern` as written
+    /// or, with `regex`, match it; each with the line that matched
+    Grep {
+        pattern: String,
+        /// Where to look; everywhere when empty
+        #[serde(default, rename = "in", skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<GrepField>,
+        #[serde(default)]
+        regex: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
     },
     Doc {
         path: String,
         #[serde(default)]
         include_docs: bool,
+        #[serde(default)]
+        examples: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         vis: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -59,9 +137,15 @@ pub enum Command {
         unimplemented: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         todo: Option<bool>,
+        /// Resume from a `next_cursor` token an earlier page of this query returned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
     },
+    /// Bundle one or more root symbols, merging what they share
     Export {
-        path: String,
+        /// Older clients send a single `path`
+        #[serde(alias = "path", deserialize_with = "one_or_many")]
+        paths: Vec<String>,
         #[serde(default)]
         bundle: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -90,7 +174,16 @@ pub enum Command {
         struct_name: Option<String>,
         #[serde(default)]
         include_derives: bool,
+        /// Reindex only this workspace member, leaving other crates untouched
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package: Option<String>,
+        /// Answer once the reindex has run instead of as soon as it is queued
+        #[serde(default)]
+        wait: bool,
     },
+    /// Where queued and running reindexes are; answered without waiting
+    /// for a reindex to finish
+    Progress,
     Status {
         #[serde(skip_serializing_if = "Option::is_none")]
         vis: Option<String>,
@@ -98,8 +191,139 @@ pub enum Command {
         unimplemented: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         todo: Option<bool>,
+        #[serde(default)]
+        hotspots: bool,
+        /// Also break the counts down per crate, module or kind
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group_by: Option<StatusGroup>,
+        /// Resume from a `next_cursor` token an earlier page of this query returned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
     },
     Diag,
+    /// Per-crate and workspace-wide symbol counts and doc coverage, and the
+    /// largest files and functions
+    Stats,
+    /// Re-hash indexed files and report the crates whose sources changed
+    Verify,
+    /// The symbols under `path` nested by path, at most `depth` levels down
+    /// and, if `kinds` is given, only those kinds and what contains them
+    Tree {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        kinds: Vec<String>,
+    },
+    /// References to the symbol at `path`, grouped by the file making them
+    /// or by its crate
+    Refs {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group_by: Option<RefsGroup>,
+    },
+    /// The innermost symbol whose span contains `line` of `file`, a path
+    /// relative to the workspace root or an absolute one inside it
+    Owner {
+        file: String,
+        line: u32,
+    },
+    /// The file and lines of the symbol at `path`
+    Locate {
+        path: String,
+    },
+    /// `todo!`, `unimplemented!` and `TODO`/`FIXME` markers in indexed
+    /// files by file and line; only those whose text contains `matching`,
+    /// ignoring ASCII case, when it is given
+    Todo {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        matching: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    /// Trait impls on either side of `impl Trait for Type`, see `ImplsDirection`
+    Impls {
+        path: String,
+        direction: ImplsDirection,
+        /// With `Traits`, also list traits defined outside the index
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        external: bool,
+    },
+    /// Edges of the crate dependency graph from `crate_name`, up to `depth`
+    /// steps away, or every recorded edge when no crate is given
+    Deps {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        crate_name: Option<String>,
+        direction: DepsDirection,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+    },
+    /// Call sites reaching the function at `path`, up to `depth` calls away
+    Callers {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+    },
+    /// Call sites reached from the function at `path`, up to `depth` calls away
+    Callees {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+    },
+    /// Symbols added, removed or changed since `baseline`, an index file
+    /// saved earlier, or since the previous epoch when none is given
+    Diff {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        baseline: Option<String>,
+        /// Only changes that can break users of the baseline's public API:
+        /// public symbols removed, re-signed or made private
+        #[serde(skip_serializing_if = "Option::is_none")]
+        breaking_only: Option<bool>,
+    },
+    /// Start or stop pushing `event` frames to this connection
+    Events {
+        enabled: bool,
+    },
+    /// Push a `symbols_changed` event to this connection whenever a reindex
+    /// changes symbols at or under `path_prefix` (everything if empty);
+    /// replaces the connection's previous subscription
+    Subscribe {
+        path_prefix: String,
+    },
+    /// Run several commands in one round trip; the response holds one
+    /// envelope per command, in order
+    Batch {
+        requests: Vec<Command>,
+    },
+    /// The next page of a paged query (Find, Ls, Status, Search), from the
+    /// `next_cursor` of the page before
+    Continue {
+        cursor: String,
+    },
+    /// Stop work on the request `request_id`, sent from any connection;
+    /// it then fails with `Cancelled`
+    Cancel {
+        request_id: String,
+    },
+    /// Add or remove tags on the symbol at `path` and report the tags it has
+    Tag {
+        path: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        add: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        remove: Vec<String>,
+        /// Note stored with the tags in `add`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    },
+    /// Pause or resume reindexing on file changes, or report the watcher state
+    WatcherControl {
+        action: WatcherAction,
+    },
+    /// Stop the daemon, letting in-flight requests finish first
+    Shutdown,
     Bench {
         #[serde(default = "default_queries")]
         queries: u32,
@@ -110,6 +334,122 @@ pub enum Command {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherAction {
+    /// Queue changed files instead of reindexing them
+    Pause,
+    /// Reindex everything queued while paused in one pass, then resume
+    Resume,
+    Status,
+}
+
+/// Which side of `impl Trait for Type` an Impls query starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImplsDirection {
+    /// Types implementing the trait at `path`
+    Implementors,
+    /// Traits implemented by the type at `path`
+    Traits,
+}
+
+/// What a Status query breaks its counts down by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum StatusGroup {
+    Crate,
+    /// The innermost module declaring each symbol
+    Module,
+    Kind,
+}
+
+/// What of a symbol a Grep query looks in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrepField {
+    Name,
+    Sig,
+    Docs,
+}
+
+/// What a Refs query groups its sites by; files when unset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefsGroup {
+    File,
+    Crate,
+}
+
+/// Which way a Deps query walks from its crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepsDirection {
+    /// Crates the crate depends on
+    Dependencies,
+    /// Indexed crates depending on the crate
+    Dependents,
+}
+
+impl Command {
+    /// The `cursor` a paged command resumes from, if any.
+    pub fn cursor(&self) -> Option<&str> {
+        match self {
+            Command::Find { cursor, .. }
+            | Command::Search { cursor, .. }
+            | Command::FuzzyFind { cursor, .. }
+            | Command::Ls { cursor, .. }
+            | Command::Status { cursor, .. } => cursor.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// This command resuming from `cursor`; commands that are not paged are
+    /// returned unchanged.
+    pub fn with_cursor(mut self, token: Option<String>) -> Self {
+        match &mut self {
+            Command::Find { cursor, .. }
+            | Command::Search { cursor, .. }
+            | Command::FuzzyFind { cursor, .. }
+            | Command::Ls { cursor, .. }
+            | Command::Status { cursor, .. } => *cursor = token,
+            _ => {}
+        }
+        self
+    }
+}
+
+/// Where the next page of a paged query starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PagePosition {
+    /// Strictly after the row with this path and rowid, for path-ordered
+    /// listings
+    After { path: String, rowid: i64 },
+    /// After this many results, for ranked searches
+    Offset(usize),
+}
+
+/// What a `next_cursor` token carries: the query, without a cursor of its
+/// own, and where its next page starts. Clients treat the token as opaque.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub cmd: Command,
+    pub position: PagePosition,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("commands serialize to JSON"))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, ProtocolError> {
+        let bytes = hex::decode(token).map_err(|e| ProtocolError::InvalidCursor(e.to_string()))?;
+        serde_json::from_slice(&bytes).map_err(|e| ProtocolError::InvalidCursor(e.to_string()))
+    }
+}
+
 fn default_queries() -> u32 {
     200
 }
@@ -130,7 +470,9 @@ pub enum Response {
     Error(ErrorEnvelope),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Serialized by hand, to write `data` from `serialized_data` when
+/// `measure_data` has filled it.
+#[derive(Debug, Clone, Deserialize)]
 pub struct SuccessEnvelope {
     pub ok: bool,
     pub request_id: String,
@@ -138,8 +480,53 @@ pub struct SuccessEnvelope {
     pub data: serde_json::Value,
     #[serde(default)]
     pub truncated: bool,
+    /// Token for `Command::Continue` when a paged query has more results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metrics: Option<Metrics>,
+    /// Index epoch the data was read at; it advances with every (re)index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_epoch: Option<u64>,
+    /// Fingerprint of the workspace the index was built from, which changes
+    /// with its lockfile and so with the database answering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_fingerprint: Option<String>,
+    #[serde(skip)]
+    serialized_data: Option<Box<serde_json::value::RawValue>>,
+}
+
+impl Serialize for SuccessEnvelope {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        
+        let mut s = serializer.serialize_struct("SuccessEnvelope", 9)?;
+        s.serialize_field("ok", &self.ok)?;
+        s.serialize_field("request_id", &self.request_id)?;
+        s.serialize_field("protocol_version", &self.protocol_version)?;
+        match &self.serialized_data {
+            Some(data) => s.serialize_field("data", data)?,
+            None => s.serialize_field("data", &self.data)?,
+        }
+        s.serialize_field("truncated", &self.truncated)?;
+        match &self.next_cursor {
+            Some(cursor) => s.serialize_field("next_cursor", cursor)?,
+            None => s.skip_field("next_cursor")?,
+        }
+        match &self.metrics {
+            Some(metrics) => s.serialize_field("metrics", metrics)?,
+            None => s.skip_field("metrics")?,
+        }
+        match &self.index_epoch {
+            Some(epoch) => s.serialize_field("index_epoch", epoch)?,
+            None => s.skip_field("index_epoch")?,
+        }
+        match &self.workspace_fingerprint {
+            Some(fingerprint) => s.serialize_field("workspace_fingerprint", fingerprint)?,
+            None => s.skip_field("workspace_fingerprint")?,
+        }
+        s.end()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -164,12 +551,35 @@ pub struct ErrorEnvelope {
     pub protocol_version: u32,
     pub err: String,
     pub err_code: ErrorCode,
+    /// What the client could do about it, e.g. the path it probably meant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Whether the same request may succeed if sent again later
+    #[serde(default)]
+    pub retryable: bool,
+    /// Machine-readable context, such as `candidates` for `NotFound`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metrics {
     pub elapsed_ms: u64,
+    /// Size of the serialized `data`
     pub bytes: usize,
+    /// SQLite virtual machine steps the queries took, a measure of the
+    /// rows they scanned; absent for commands that are not measured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db_steps: Option<u64>,
+    /// Lookups in the snippet cache, when the command made any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheMetrics>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -181,6 +591,19 @@ pub enum ErrorCode {
     IndexMismatch,
     InternalError,
     ProtocolError,
+    /// The index database was locked by another process; retry later
+    Busy,
+    /// Stopped by a `Cancel` for this request
+    Cancelled,
+    /// Ran past the daemon's request timeout
+    Timeout,
+}
+
+impl ErrorCode {
+    /// Failures caused by the daemon's momentary state rather than the request.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorCode::Busy | ErrorCode::IndexMismatch | ErrorCode::DaemonUnavailable)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -196,6 +619,30 @@ pub enum ProtocolError {
     
     #[error("Message too large: {0} bytes")]
     MessageTooLarge(usize),
+    
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+    
+    #[error("Invalid chunked response: {0}")]
+    InvalidChunks(String),
+}
+
+impl SuccessEnvelope {
+    /// `data` as the typed payload of the command it answers, such as
+    /// `data::FindData` for `Command::Find`.
+    pub fn data_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ProtocolError> {
+        Ok(T::deserialize(&self.data)?)
+    }
+    
+    /// Serialize `data` now and return its size in bytes; the envelope then
+    /// writes those bytes rather than serializing `data` again, so changes
+    /// made to `data` afterwards are not sent.
+    pub fn measure_data(&mut self) -> usize {
+        match serde_json::value::to_raw_value(&self.data) {
+            Ok(data) => self.serialized_data.insert(data).get().len(),
+            Err(_) => 0,
+        }
+    }
 }
 
 impl Response {
@@ -206,10 +653,23 @@ impl Response {
             protocol_version: PROTOCOL_VERSION,
             data,
             truncated: false,
+            next_cursor: None,
             metrics: None,
+            index_epoch: None,
+            workspace_fingerprint: None,
+            serialized_data: None,
         })
     }
 
+    /// Mark a success as one page of a longer result, resumed by `cursor`.
+    pub fn with_next_cursor(mut self, cursor: Option<String>) -> Self {
+        if let (Response::Success(env), Some(cursor)) = (&mut self, cursor) {
+            env.truncated = true;
+            env.next_cursor = Some(cursor);
+        }
+        self
+    }
+
     pub fn error(request_id: String, err: String, err_code: ErrorCode) -> Self {
         Response::Error(ErrorEnvelope {
             ok: false,
@@ -217,9 +677,46 @@ impl Response {
             protocol_version: PROTOCOL_VERSION,
             err,
             err_code,
+            hint: None,
+            retryable: err_code.is_retryable(),
+            details: None,
         })
     }
 
+    /// Suggest what to do about an error; successes are left alone.
+    pub fn with_hint(mut self, hint: Option<String>) -> Self {
+        if let Response::Error(env) = &mut self {
+            env.hint = hint;
+        }
+        self
+    }
+
+    /// Attach machine-readable context to an error.
+    pub fn with_details(mut self, details: Option<serde_json::Value>) -> Self {
+        if let Response::Error(env) = &mut self {
+            env.details = details;
+        }
+        self
+    }
+
+    pub fn request_id(&self) -> &str {
+        match self {
+            Response::Success(env) => &env.request_id,
+            Response::Decision(env) => &env.request_id,
+            Response::Error(env) => &env.request_id,
+        }
+    }
+
+    /// Answer `request_id`, whatever the response was built with.
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        match &mut self {
+            Response::Success(env) => env.request_id = request_id,
+            Response::Decision(env) => env.request_id = request_id,
+            Response::Error(env) => env.request_id = request_id,
+        }
+        self
+    }
+
     pub fn decision(request_id: String, reason: String, content_len: usize, options: Vec<String>) -> Self {
         Response::Decision(DecisionEnvelope {
             ok: true,
@@ -234,6 +731,170 @@ impl Response {
     }
 }
 
+/// Most bytes of a serialized response one `data_chunk` frame carries.
+pub const RESPONSE_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Frames of a chunked response. Joined in order, the `data` of the
+/// chunks is the serialized `Response`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+pub enum Frame {
+    DataChunk {
+        request_id: String,
+        seq: usize,
+        data: String,
+    },
+    /// Ends the response after `chunks` frames holding `bytes` bytes in all
+    Done {
+        request_id: String,
+        chunks: usize,
+        bytes: usize,
+    },
+    /// Unsolicited, to connections that enabled `Command::Events`; may
+    /// arrive between the frames of a chunked response
+    Event(Event),
+}
+
+/// Daemon activity pushed as `event` frames.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A reindex began, of one member when `package` is set
+    ReindexStarted {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package: Option<String>,
+    },
+    /// The reindex moved on to `crate_name`, with `done` of `total` crates behind it
+    ReindexProgress {
+        crate_name: String,
+        done: usize,
+        total: usize,
+    },
+    /// The reindex ended; `ok` is false if it failed or was cancelled
+    ReindexFinished {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package: Option<String>,
+        ok: bool,
+        duration_ms: u64,
+    },
+    /// The watcher reindexed `crates` after `files` changed
+    WatcherBatchApplied {
+        files: usize,
+        crates: Vec<String>,
+    },
+    /// Symbols a reindex changed, sent to connections that used
+    /// `Command::Subscribe`, with only the changes under their prefix.
+    /// `truncated` is set when there were more than one event carries.
+    SymbolsChanged {
+        epoch: u64,
+        changes: Vec<SymbolChange>,
+        #[serde(default)]
+        truncated: bool,
+    },
+}
+
+/// How a symbol differs from the baseline it was compared against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One symbol that was added, removed or whose definition changed between
+/// a baseline and the current index. Symbols are matched by path and kind;
+/// `previous_signature` is only set for changed symbols.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolChange {
+    pub change: ChangeKind,
+    pub path: String,
+    pub kind: String,
+    pub visibility: String,
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_signature: Option<String>,
+}
+
+/// One line from the daemon: a whole response or a frame of a chunked one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    Frame(Frame),
+    Response(Response),
+}
+
+/// The lines to send for `response`: the response itself when it fits in
+/// `chunk_bytes`, otherwise its serialized form split into `data_chunk`
+/// frames and a closing `done`.
+pub fn chunk_lines(request_id: &str, response: &Response, chunk_bytes: usize) -> Result<Vec<String>, ProtocolError> {
+    let json = serialize_message(response)?;
+    if json.len() <= chunk_bytes {
+        return Ok(vec![json]);
+    }
+    
+    let mut frames = Vec::with_capacity(json.len() / chunk_bytes + 2);
+    let mut rest = json.as_str();
+    while !rest.is_empty() {
+        let mut end = chunk_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        frames.push(Frame::DataChunk {
+            request_id: request_id.to_string(),
+            seq: frames.len(),
+            data: chunk.to_string(),
+        });
+        rest = tail;
+    }
+    frames.push(Frame::Done {
+        request_id: request_id.to_string(),
+        chunks: frames.len(),
+        bytes: json.len(),
+    });
+    frames.iter().map(serialize_message).collect()
+}
+
+/// Joins the frames of a chunked response back into the response.
+#[derive(Debug, Default)]
+pub struct ChunkAssembler {
+    data: String,
+    chunks: usize,
+}
+
+impl ChunkAssembler {
+    /// Take the next frame, returning the response once `done` arrives.
+    /// Events are not part of the response and are ignored.
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Response>, ProtocolError> {
+        match frame {
+            Frame::Event(_) => Ok(None),
+            Frame::DataChunk { seq, data, .. } => {
+                if seq != self.chunks {
+                    return Err(ProtocolError::InvalidChunks(format!("expected chunk {}, got {}", self.chunks, seq)));
+                }
+                self.data.push_str(&data);
+                self.chunks += 1;
+                Ok(None)
+            }
+            Frame::Done { chunks, bytes, .. } => {
+                if chunks != self.chunks || bytes != self.data.len() {
+                    return Err(ProtocolError::InvalidChunks(format!(
+                        "done after {} chunks of {} bytes, expected {} of {}",
+                        self.chunks,
+                        self.data.len(),
+                        chunks,
+                        bytes
+                    )));
+                }
+                let response = deserialize_message(&self.data)?;
+                *self = Self::default();
+                Ok(Some(response))
+            }
+        }
+    }
+}
+
 pub fn serialize_message<T: Serialize>(msg: &T) -> Result<String, ProtocolError> {
     let json = serde_json::to_string(msg)?;
     if json.contains('\n') {
@@ -246,6 +907,25 @@ pub fn deserialize_message<T: for<'de> Deserialize<'de>>(line: &str) -> Result<T
     Ok(serde_json::from_str(line)?)
 }
 
+/// A list that may also arrive as one value, for fields that held a
+/// single value in earlier versions of the protocol.
+pub(crate) fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(values) => values,
+        OneOrMany::One(value) => vec![value],
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -261,9 +941,16 @@ mod tests {
                 unimplemented: None,
                 todo: None,
                 all: None,
+                recently_changed: None,
+                depth: None,
+                match_mode: None,
+                tag: None,
+                sig: None,
+                cursor: None,
             },
             request_id: "test-id".to_string(),
             protocol_version: 1,
+            chunked: false,
         };
 
         let json = serialize_message(&req).unwrap();
@@ -273,6 +960,133 @@ mod tests {
         assert_eq!(parsed.request_id, "test-id");
     }
 
+    #[test]
+    fn test_watcher_control_wire_format() {
+        let json = r#"{"cmd":{"cmd":"watcher_control","action":"pause"},"request_id":"w1"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        assert!(matches!(parsed.cmd, Command::WatcherControl { action: WatcherAction::Pause }));
+    }
+
+    #[test]
+    fn test_impls_wire_format() {
+        let json = r#"{"cmd":{"cmd":"impls","path":"std::fmt::Display","direction":"implementors"},"request_id":"i1"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        assert!(matches!(
+            parsed.cmd,
+            Command::Impls { ref path, direction: ImplsDirection::Implementors, external: false }
+                if path == "std::fmt::Display"
+        ));
+    }
+
+    #[test]
+    fn test_status_group_wire_format() {
+        let json = r#"{"cmd":{"cmd":"status","group_by":"crate"},"request_id":"s1"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        assert!(matches!(parsed.cmd, Command::Status { group_by: Some(StatusGroup::Crate), hotspots: false, .. }));
+    }
+
+    #[test]
+    fn test_grep_wire_format() {
+        let json = r#"{"cmd":{"cmd":"grep","pattern":"Result<","in":["sig","docs"]},"request_id":"g1"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        let Command::Grep { pattern, fields, regex, limit } = parsed.cmd else {
+            panic!("expected grep")
+        };
+        assert_eq!(pattern, "Result<");
+        assert_eq!(fields, vec![GrepField::Sig, GrepField::Docs]);
+        assert!(!regex && limit.is_none());
+    }
+
+    #[test]
+    fn test_export_single_path_wire_format() {
+        for json in [
+            r#"{"cmd":{"cmd":"export","path":"app::State"},"request_id":"x1"}"#,
+            r#"{"cmd":{"cmd":"export","paths":["app::State"]},"request_id":"x1"}"#,
+        ] {
+            let parsed: Request = deserialize_message(json).unwrap();
+            let Command::Export { paths, .. } = parsed.cmd else {
+                panic!("expected export")
+            };
+            assert_eq!(paths, ["app::State"]);
+        }
+    }
+
+    #[test]
+    fn test_refs_group_wire_format() {
+        let json = r#"{"cmd":{"cmd":"refs","path":"app::Client","group_by":"crate"},"request_id":"r1"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        assert!(matches!(parsed.cmd, Command::Refs { group_by: Some(RefsGroup::Crate), limit: None, .. }));
+
+        // Older clients send no grouping
+        let json = r#"{"cmd":{"cmd":"refs","path":"app::Client"},"request_id":"r2"}"#;
+        let parsed: Request = deserialize_message(json).unwrap();
+        assert!(matches!(parsed.cmd, Command::Refs { group_by: None, .. }));
+    }
+
+    #[test]
+    fn test_page_cursor_round_trip() {
+        let cmd = Command::Ls {
+            path: "app::net".to_string(),
+            expansion: Some(">>".to_string()),
+            impl_parents: false,
+            include_docs: false,
+            vis: None,
+            unimplemented: None,
+            todo: None,
+            cursor: None,
+        };
+        let token = PageCursor {
+            cmd: cmd.clone(),
+            position: PagePosition::After { path: "app::net::Client".to_string(), rowid: 7 },
+        }
+        .encode();
+        
+        let resumed = PageCursor::decode(&token).unwrap();
+        assert_eq!(resumed.position, PagePosition::After { path: "app::net::Client".to_string(), rowid: 7 });
+        assert!(resumed.cmd.cursor().is_none());
+        assert_eq!(cmd.with_cursor(Some(token.clone())).cursor(), Some(token.as_str()));
+        assert!(matches!(PageCursor::decode("not a cursor"), Err(ProtocolError::InvalidCursor(_))));
+        
+        let page = Response::success("p1".to_string(), serde_json::json!({})).with_next_cursor(Some(token));
+        assert!(matches!(page, Response::Success(env) if env.truncated && env.next_cursor.is_some()));
+    }
+
+    #[test]
+    fn test_chunk_frames_round_trip() {
+        let response = Response::success("s1".to_string(), serde_json::json!({"bundle": "é".repeat(100)}));
+        
+        let whole = chunk_lines("s1", &response, RESPONSE_CHUNK_BYTES).unwrap();
+        assert_eq!(whole.len(), 1);
+        assert!(matches!(deserialize_message(&whole[0]).unwrap(), ServerMessage::Response(_)));
+        
+        let lines = chunk_lines("s1", &response, 16).unwrap();
+        assert!(lines.len() > 3);
+        let mut assembler = ChunkAssembler::default();
+        let mut assembled = None;
+        for line in lines {
+            let ServerMessage::Frame(frame) = deserialize_message(&line).unwrap() else {
+                panic!("expected a frame")
+            };
+            assembled = assembler.push(frame).unwrap();
+        }
+        match assembled {
+            Some(Response::Success(env)) => assert_eq!(env.data["bundle"].as_str().unwrap().chars().count(), 100),
+            other => panic!("expected the reassembled success, got {:?}", other),
+        }
+        
+        let mut assembler = ChunkAssembler::default();
+        let skipped = Frame::DataChunk { request_id: "s1".to_string(), seq: 1, data: "{".to_string() };
+        assert!(matches!(assembler.push(skipped), Err(ProtocolError::InvalidChunks(_))));
+    }
+
+    #[test]
+    fn test_event_wire_format() {
+        let frame = Frame::Event(Event::ReindexProgress { crate_name: "app".to_string(), done: 1, total: 3 });
+        let line = serialize_message(&frame).unwrap();
+        assert_eq!(line, r#"{"frame":"event","event":"reindex_progress","crate_name":"app","done":1,"total":3}"#);
+        assert!(matches!(deserialize_message(&line).unwrap(), ServerMessage::Frame(Frame::Event(_))));
+    }
+
     #[test]
     fn test_response_envelopes() {
         let success = Response::success(
@@ -285,10 +1099,42 @@ mod tests {
         
         match parsed {
             Response::Success(env) => {
-                assert_eq!(env.ok, true);
+                assert!(env.ok);
                 assert_eq!(env.request_id, "req-1");
             }
             _ => panic!("Expected success envelope"),
         }
+        
+        // Measured data is written as serialized then, byte for byte
+        let Response::Success(mut env) = success.with_next_cursor(Some("c".to_string())) else {
+            unreachable!()
+        };
+        let unmeasured = serialize_message(&env).unwrap();
+        assert_eq!(env.measure_data(), r#"{"count":42}"#.len());
+        env.data = serde_json::json!(null);
+        assert_eq!(serialize_message(&env).unwrap(), unmeasured);
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        let busy = Response::error("req-1".to_string(), "locked".to_string(), ErrorCode::Busy);
+        let Response::Error(env) = &busy else { unreachable!() };
+        assert!(env.retryable);
+        // Optional fields stay off the wire until set
+        let json = serialize_message(&busy).unwrap();
+        assert!(!json.contains("hint") && !json.contains("details"));
+
+        let missing = Response::error("req-2".to_string(), "Symbol not found: a::b".to_string(), ErrorCode::NotFound)
+            .with_hint(Some("did you mean `a::c::b`?".to_string()))
+            .with_details(Some(serde_json::json!({ "candidates": ["a::c::b"] })));
+        let parsed: Response = deserialize_message(&serialize_message(&missing).unwrap()).unwrap();
+        let Response::Error(env) = parsed else { panic!("Expected error envelope") };
+        assert!(!env.retryable);
+        assert_eq!(env.hint.as_deref(), Some("did you mean `a::c::b`?"));
+        assert_eq!(env.details.unwrap()["candidates"][0], "a::c::b");
+
+        // Envelopes from before these fields existed still parse
+        let old = r#"{"ok":false,"request_id":"r","protocol_version":1,"err":"x","err_code":"NOT_FOUND"}"#;
+        assert!(matches!(deserialize_message::<Response>(old).unwrap(), Response::Error(e) if !e.retryable));
     }
 }
\ No newline at end of file